@@ -0,0 +1,88 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+/// What a client of the API is allowed to do.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AccessLevel {
+    /// All the requests are rejected.
+    Denied,
+    /// Only the requests which don't change anything (i.e. GETs) are served.
+    ReadOnly,
+    /// All the requests are served.
+    Full,
+}
+
+/// Decides which clients may use the API, based on the credentials of the process on the other
+/// end of the socket (as reported by `SO_PEERCRED`). Only the effective UID and the primary GID
+/// of the peer are known, so supplementary groups aren't taken into account.
+///
+/// When no list is populated, the API is open to anyone who can connect to the socket.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AccessPolicy {
+    /// UIDs granted full access.
+    pub allowed_uids: Vec<u32>,
+    /// GIDs granted full access.
+    pub allowed_gids: Vec<u32>,
+    /// UIDs granted read-only access.
+    pub read_only_uids: Vec<u32>,
+    /// GIDs granted read-only access.
+    pub read_only_gids: Vec<u32>,
+}
+
+impl AccessPolicy {
+    /// Returns true if access to the API is limited to the listed UIDs and GIDs.
+    pub fn is_restricted(&self) -> bool {
+        !(self.allowed_uids.is_empty()
+            && self.allowed_gids.is_empty()
+            && self.read_only_uids.is_empty()
+            && self.read_only_gids.is_empty())
+    }
+
+    /// Returns the access level of a client, given its credentials. Full access takes
+    /// precedence when the client matches both the full and the read-only lists.
+    pub fn access_level(&self, uid: u32, gid: u32) -> AccessLevel {
+        if !self.is_restricted()
+            || self.allowed_uids.contains(&uid)
+            || self.allowed_gids.contains(&gid)
+        {
+            AccessLevel::Full
+        } else if self.read_only_uids.contains(&uid) || self.read_only_gids.contains(&gid) {
+            AccessLevel::ReadOnly
+        } else {
+            AccessLevel::Denied
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_access_level() {
+        let policy = AccessPolicy::default();
+        assert!(!policy.is_restricted());
+        assert_eq!(policy.access_level(1000, 1000), AccessLevel::Full);
+
+        let policy = AccessPolicy {
+            allowed_uids: vec![1000],
+            allowed_gids: vec![100],
+            read_only_uids: vec![1001, 1000],
+            read_only_gids: vec![101],
+        };
+        assert!(policy.is_restricted());
+        assert_eq!(policy.access_level(1000, 1), AccessLevel::Full);
+        assert_eq!(policy.access_level(1, 100), AccessLevel::Full);
+        assert_eq!(policy.access_level(1001, 1), AccessLevel::ReadOnly);
+        assert_eq!(policy.access_level(1, 101), AccessLevel::ReadOnly);
+        assert_eq!(policy.access_level(0, 0), AccessLevel::Denied);
+
+        let policy = AccessPolicy {
+            read_only_gids: vec![101],
+            ..Default::default()
+        };
+        assert!(policy.is_restricted());
+        assert_eq!(policy.access_level(1, 101), AccessLevel::ReadOnly);
+        assert_eq!(policy.access_level(1, 100), AccessLevel::Denied);
+    }
+}
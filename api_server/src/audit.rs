@@ -0,0 +1,414 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Records the requests which change the state of the microVM, independently of the logger.
+
+use std::cell::RefCell;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Instant;
+
+use chrono::Utc;
+use hyper::{Method, StatusCode};
+use libc::O_NONBLOCK;
+use serde_json::{self, Value};
+
+use http_service::strip_api_version;
+use logger::{Metric, METRICS};
+use sys_util::PeerCredentials;
+
+/// The default size beyond which the audit log file is rotated.
+pub const DEFAULT_AUDIT_LOG_MAX_SIZE: u64 = 10 * 1024 * 1024;
+// The number of rotated audit log files which are kept, besides the current one.
+const AUDIT_LOG_BACKUPS: usize = 5;
+// Longer request bodies are truncated before being recorded.
+const MAX_RECORDED_BODY_LEN: usize = 4096;
+// Fields whose values are secrets, and are never recorded.
+const SECRET_FIELDS: &[&str] = &["token_key"];
+const REDACTED: &str = "<redacted>";
+
+/// Appends a JSON object per line to a FIFO or to a regular file. Regular files are rotated once
+/// they would grow beyond a maximum size: the current file becomes `<path>.1`, `<path>.1` becomes
+/// `<path>.2`, and so on, while the oldest one is dropped.
+#[derive(Debug)]
+pub struct AuditLog {
+    path: PathBuf,
+    file: File,
+    // The size beyond which the file is rotated, or None for FIFOs.
+    max_size: Option<u64>,
+    size: u64,
+}
+
+impl AuditLog {
+    /// Opens the audit log at `path`. A regular file is created if nothing exists there.
+    pub fn new<P: AsRef<Path>>(path: P, max_size: u64) -> io::Result<AuditLog> {
+        let path = path.as_ref().to_path_buf();
+        let is_fifo = match fs::metadata(&path) {
+            Ok(metadata) => metadata.file_type().is_fifo(),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => false,
+            Err(e) => return Err(e),
+        };
+
+        if is_fifo {
+            // Same as for the logger: opening the FIFO for reading too keeps the writes from
+            // failing while nobody is listening on the other end.
+            let file = OpenOptions::new()
+                .custom_flags(O_NONBLOCK)
+                .read(true)
+                .write(true)
+                .open(&path)?;
+            return Ok(AuditLog {
+                path,
+                file,
+                max_size: None,
+                size: 0,
+            });
+        }
+
+        let file = Self::open_file(&path)?;
+        let size = file.metadata()?.len();
+        Ok(AuditLog {
+            path,
+            file,
+            max_size: Some(max_size),
+            size,
+        })
+    }
+
+    fn open_file(path: &Path) -> io::Result<File> {
+        OpenOptions::new().create(true).append(true).open(path)
+    }
+
+    fn backup_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        PathBuf::from(path)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        for index in (1..AUDIT_LOG_BACKUPS).rev() {
+            match fs::rename(self.backup_path(index), self.backup_path(index + 1)) {
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+                result => result?,
+            }
+        }
+        fs::rename(&self.path, self.backup_path(1))?;
+        self.file = Self::open_file(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if let Some(max_size) = self.max_size {
+            if self.size > 0 && self.size + len > max_size {
+                self.rotate()?;
+            }
+        }
+        // A single write per line, so that lines from different writers of a FIFO don't mix.
+        self.file.write_all(format!("{}\n", line).as_bytes())?;
+        self.size += len;
+        Ok(())
+    }
+}
+
+// An entry of the audit log.
+#[derive(Serialize)]
+struct AuditRecord<'a> {
+    timestamp: String,
+    pid: Option<i32>,
+    uid: Option<u32>,
+    gid: Option<u32>,
+    method: String,
+    path: &'a str,
+    body: &'a str,
+    // Missing when the request failed without a response.
+    status: Option<u16>,
+    latency_us: u64,
+}
+
+/// A request on its way to the audit log, which is recorded once its outcome is known.
+pub struct AuditEntry {
+    log: Rc<RefCell<AuditLog>>,
+    client: Option<PeerCredentials>,
+    method: Method,
+    path: String,
+    body: String,
+    start_time: Instant,
+}
+
+impl AuditEntry {
+    /// Starts tracking a request made by `client`, which is unknown if its credentials couldn't
+    /// be retrieved.
+    pub fn new(
+        log: Rc<RefCell<AuditLog>>,
+        client: Option<PeerCredentials>,
+        method: Method,
+        path: String,
+    ) -> Self {
+        AuditEntry {
+            log,
+            client,
+            method,
+            path,
+            body: String::new(),
+            start_time: Instant::now(),
+        }
+    }
+
+    /// Sets the body of the request, which is sanitized before being recorded.
+    pub fn with_body(mut self, body: &[u8]) -> Self {
+        self.body = sanitize_body(&self.path, body);
+        self
+    }
+
+    /// Writes the request to the audit log, along with the status of the response (if any).
+    pub fn record(self, status: Option<StatusCode>) {
+        let elapsed = self.start_time.elapsed();
+        let record = AuditRecord {
+            timestamp: Utc::now().to_rfc3339(),
+            pid: self.client.map(|client| client.pid),
+            uid: self.client.map(|client| client.uid),
+            gid: self.client.map(|client| client.gid),
+            method: self.method.to_string(),
+            path: &self.path,
+            body: &self.body,
+            status: status.map(|status| status.as_u16()),
+            latency_us: elapsed.as_secs() * 1_000_000 + u64::from(elapsed.subsec_micros()),
+        };
+
+        let outcome = serde_json::to_string(&record)
+            .map_err(io::Error::from)
+            .and_then(|line| self.log.borrow_mut().write_line(&line));
+        if let Err(e) = outcome {
+            error!("Failed to write to the audit log: {}", e);
+            METRICS.api_server.audit_log_fails.inc();
+        }
+    }
+}
+
+// Returns whether `path` refers to the contents of a MMDS data store, which are meant for the
+// guest and may be large.
+fn is_mmds_data_path(path: &str) -> bool {
+    let path = strip_api_version(path);
+    // We skip the empty token before the initial '/'.
+    let path_tokens: Vec<&str> = path.split_terminator('/').skip(1).collect();
+    match path_tokens.get(0) {
+        Some(&"mmds") => path_tokens.get(1) != Some(&"config"),
+        Some(&"network-interfaces") => path_tokens.get(2) == Some(&"mmds"),
+        _ => false,
+    }
+}
+
+// Removes the secrets from a JSON value, and returns whether it contained any.
+fn redact_secrets(value: &mut Value) -> bool {
+    match *value {
+        Value::Object(ref mut map) => {
+            let mut redacted = false;
+            for (key, field) in map.iter_mut() {
+                if SECRET_FIELDS.contains(&key.as_str()) {
+                    *field = Value::String(REDACTED.to_string());
+                    redacted = true;
+                } else {
+                    redacted |= redact_secrets(field);
+                }
+            }
+            redacted
+        }
+        Value::Array(ref mut items) => items
+            .iter_mut()
+            .fold(false, |redacted, item| redact_secrets(item) || redacted),
+        _ => false,
+    }
+}
+
+/// Returns the request body with the values of secret fields (such as the MMDS `token_key`)
+/// redacted, so that it can be logged. Bodies without secrets are returned unchanged.
+pub fn redact_body(body: &str) -> String {
+    if let Ok(mut value) = serde_json::from_str::<Value>(body) {
+        if redact_secrets(&mut value) {
+            return value.to_string();
+        }
+    }
+    body.to_string()
+}
+
+// Turns the body of a request to `path` into something fit for the audit log: the secrets are
+// redacted, the MMDS contents are left out, and long bodies are truncated.
+fn sanitize_body(path: &str, body: &[u8]) -> String {
+    if body.is_empty() {
+        return String::new();
+    }
+    if is_mmds_data_path(path) {
+        return format!("<{} bytes of MMDS data>", body.len());
+    }
+
+    let mut body = match serde_json::from_slice::<Value>(body) {
+        Ok(mut value) => {
+            redact_secrets(&mut value);
+            value.to_string()
+        }
+        Err(_) => String::from_utf8_lossy(body).into_owned(),
+    };
+    if body.len() > MAX_RECORDED_BODY_LEN {
+        let mut len = MAX_RECORDED_BODY_LEN;
+        while !body.is_char_boundary(len) {
+            len -= 1;
+        }
+        body.truncate(len);
+        body.push_str("...");
+    }
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempfile;
+
+    use super::*;
+
+    use std::io::Read;
+
+    use self::tempfile::tempdir;
+
+    fn read_lines(path: &Path) -> Vec<Value> {
+        let mut contents = String::new();
+        File::open(path)
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_redact_body() {
+        let body = r#"{ "kernel_image_path": "/vmlinux" }"#;
+        assert_eq!(redact_body(body), body);
+        assert_eq!(redact_body("not json"), "not json");
+        assert_eq!(
+            redact_body(r#"{"token_mode":"required","token_key":"secret"}"#),
+            r#"{"token_key":"<redacted>","token_mode":"required"}"#
+        );
+        assert_eq!(
+            redact_body(r#"{"mmds_config":{"token_key":"secret"}}"#),
+            r#"{"mmds_config":{"token_key":"<redacted>"}}"#
+        );
+        assert_eq!(
+            redact_body(r#"[{"a":1},{"token_key":"secret"}]"#),
+            r#"[{"a":1},{"token_key":"<redacted>"}]"#
+        );
+    }
+
+    #[test]
+    fn test_sanitize_body() {
+        assert_eq!(sanitize_body("/drives/root", b""), "");
+        assert_eq!(
+            sanitize_body("/boot-source", br#"{ "kernel_image_path": "/vmlinux" }"#),
+            r#"{"kernel_image_path":"/vmlinux"}"#
+        );
+        assert_eq!(sanitize_body("/actions", b"not json"), "not json");
+
+        // Secrets are redacted.
+        assert_eq!(
+            sanitize_body(
+                "/mmds/config",
+                br#"{"token_mode":"required","token_key":"secret","ipv4_address":"169.254.0.1"}"#
+            ),
+            r#"{"ipv4_address":"169.254.0.1","token_key":"<redacted>","token_mode":"required"}"#
+        );
+
+        // The MMDS contents are left out, with or without the API version prefix.
+        for path in &[
+            "/mmds",
+            "/v1/mmds",
+            "/mmds/guest",
+            "/network-interfaces/eth0/mmds",
+            "/v1/network-interfaces/eth0/mmds/guest",
+        ] {
+            assert_eq!(
+                sanitize_body(path, br#"{"token_key":"x"}"#),
+                "<17 bytes of MMDS data>"
+            );
+        }
+
+        // Long bodies are truncated.
+        let body = vec![b'x'; MAX_RECORDED_BODY_LEN + 1];
+        let sanitized = sanitize_body("/actions", &body);
+        assert_eq!(sanitized.len(), MAX_RECORDED_BODY_LEN + 3);
+        assert!(sanitized.ends_with("x..."));
+        // Without splitting characters.
+        let body = "é".repeat(MAX_RECORDED_BODY_LEN);
+        let sanitized = sanitize_body("/actions", body.as_bytes());
+        assert_eq!(sanitized.len(), MAX_RECORDED_BODY_LEN + 3);
+    }
+
+    #[test]
+    fn test_audit_entry() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let log = Rc::new(RefCell::new(AuditLog::new(&path, 1024).unwrap()));
+        let client = PeerCredentials {
+            pid: 42,
+            uid: 1000,
+            gid: 100,
+        };
+
+        AuditEntry::new(log.clone(), Some(client), Method::Put, "/drives/root".to_string())
+            .with_body(br#"{"drive_id":"root"}"#)
+            .record(Some(StatusCode::NoContent));
+        AuditEntry::new(log.clone(), None, Method::Patch, "/mmds".to_string())
+            .with_body(b"{}")
+            .record(None);
+
+        let lines = read_lines(&path);
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0]["timestamp"].is_string());
+        assert!(lines[0]["latency_us"].is_u64());
+        assert_eq!(lines[0]["pid"], 42);
+        assert_eq!(lines[0]["uid"], 1000);
+        assert_eq!(lines[0]["gid"], 100);
+        assert_eq!(lines[0]["method"], "PUT");
+        assert_eq!(lines[0]["path"], "/drives/root");
+        assert_eq!(lines[0]["body"], r#"{"drive_id":"root"}"#);
+        assert_eq!(lines[0]["status"], 204);
+        assert!(lines[1]["pid"].is_null());
+        assert_eq!(lines[1]["method"], "PATCH");
+        assert_eq!(lines[1]["body"], "<2 bytes of MMDS data>");
+        assert!(lines[1]["status"].is_null());
+    }
+
+    #[test]
+    fn test_rotation() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let line = "x".repeat(99);
+
+        // Two lines fit in a file.
+        let mut log = AuditLog::new(&path, 200).unwrap();
+        for _ in 0..(AUDIT_LOG_BACKUPS + 2) * 2 {
+            log.write_line(&line).unwrap();
+        }
+        assert_eq!(fs::metadata(&path).unwrap().len(), 200);
+        for index in 1..=AUDIT_LOG_BACKUPS {
+            assert_eq!(fs::metadata(log.backup_path(index)).unwrap().len(), 200);
+        }
+        assert!(!log.backup_path(AUDIT_LOG_BACKUPS + 1).exists());
+
+        // The size of an existing file counts towards the limit.
+        let mut log = AuditLog::new(&path, 200).unwrap();
+        log.write_line("y").unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), 2);
+
+        // A line larger than the limit still makes it into the log.
+        let mut log = AuditLog::new(&path, 1).unwrap();
+        log.write_line(&line).unwrap();
+        log.write_line(&line).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), 100);
+    }
+}
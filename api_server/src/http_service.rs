@@ -1,33 +1,60 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+use std::cell::RefCell;
 use std::rc::Rc;
 use std::result;
 use std::str;
-use std::sync::mpsc;
+use std::sync::mpsc::{SyncSender, TrySendError};
 use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
 
 use futures::future::{self, Either};
+use futures::sync::oneshot::{self, Canceled};
+use futures::sync::mpsc as futures_mpsc;
 use futures::{Future, Stream};
 
-use hyper::{self, Chunk, Headers, Method, StatusCode};
-use serde_json;
+use hyper::{self, Body, Chunk, Headers, Method, StatusCode};
+use serde_json::{self, Value};
+use tokio_core::reactor::{Handle, Timeout};
 
+use access::AccessLevel;
+use audit::{redact_body, AuditEntry, AuditLog};
 use logger::{Metric, METRICS};
-use mmds::data_store::Mmds;
+use mmds::data_store::{Error as MmdsError, JsonPatchError, Mmds};
+use mmds::stores::MmdsStores;
 use request::actions::ActionBody;
 use request::drive::PatchDrivePayload;
 use request::{GenerateHyperResponse, IntoParsedRequest, ParsedRequest};
-use sys_util::EventFd;
+use sys_util::{EventFd, PeerCredentials};
+use vmm::error_code::ErrorCode;
+use vmm::events::{Event, EventPublisher};
 use vmm::vmm_config::boot_source::BootSourceConfig;
 use vmm::vmm_config::drive::BlockDeviceConfig;
-use vmm::vmm_config::instance_info::InstanceInfo;
+use vmm::vmm_config::instance_info::{InstanceInfo, InstanceState};
 use vmm::vmm_config::logger::LoggerConfig;
-use vmm::vmm_config::machine_config::VmConfig;
+use vmm::vmm_config::machine_config::{CpuFeaturesTemplate, VmConfig};
+use vmm::vmm_config::microvm::{FieldError, MicrovmConfig};
+use vmm::vmm_config::mmds::MmdsConfig;
 use vmm::vmm_config::net::NetworkInterfaceConfig;
 #[cfg(feature = "vsock")]
 use vmm::vmm_config::vsock::VsockDeviceConfig;
-use vmm::VmmAction;
+use vmm::{
+    ErrorKind, OutcomeReceiver, OutcomeSender, VmmAction, VmmRequest, VmmRequestHandle,
+    VmmRequestOutcome,
+};
+
+// Prefix under which the current version of the API can be reached, besides the root.
+const API_VERSION_PREFIX: &str = "/v1";
+// Versions of the API served by this binary.
+const API_VERSIONS: &[&str] = &["v1"];
+// Content type of the Prometheus text exposition format.
+const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+// Content type of the event stream, which holds one JSON object per line.
+const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+// Number of events which can be queued for a client of the event stream. Clients which fall this
+// far behind are disconnected, so that they don't hold up the VMM.
+const EVENT_STREAM_BUFFER_SIZE: usize = 64;
 
 fn build_response_base<B: Into<hyper::Body>>(
     status: StatusCode,
@@ -56,17 +83,60 @@ pub fn json_response<T: Into<hyper::Body>>(status: StatusCode, body: T) -> hyper
     build_response_base(status, Some(headers), Some(body))
 }
 
+// The body of the responses to failed requests.
+#[derive(Serialize)]
+struct FaultBody<'a> {
+    // Description of the error, meant for humans.
+    fault_message: &'a str,
+    // Stable identifier of the error, meant for programs.
+    error_code: ErrorCode,
+    // Whether the error was caused by the request (User) or by Firecracker (Internal).
+    error_kind: ErrorKind,
+    // The errors of the individual fields of the request, if reported separately.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    field_errors: Vec<FieldFault<'a>>,
+}
+
+// The error of a field of the request, which is otherwise described the same way as a fault.
+#[derive(Serialize)]
+struct FieldFault<'a> {
+    field: &'a str,
+    fault_message: String,
+    error_code: ErrorCode,
+    error_kind: ErrorKind,
+}
+
 // Builds a string that looks like (where $ stands for substitution):
 //  {
-//    "$k": "$v"
+//    "fault_message": "$msg",
+//    "error_code": "$code",
+//    "error_kind": "$kind"
 //  }
-// Mainly used for building fault message response json bodies.
-fn basic_json_body<K: AsRef<str>, V: AsRef<str>>(k: K, v: V) -> String {
-    format!("{{\n  \"{}\": \"{}\"\n}}", k.as_ref(), v.as_ref())
+pub fn json_fault_message<T: AsRef<str>>(kind: ErrorKind, code: ErrorCode, msg: T) -> String {
+    json_field_fault_message(kind, code, msg, &[])
 }
 
-pub fn json_fault_message<T: AsRef<str>>(msg: T) -> String {
-    basic_json_body("fault_message", msg)
+// Same as json_fault_message, with an extra "field_errors" array which describes the error of
+// each field in `field_errors`, unless there are none.
+pub fn json_field_fault_message<T: AsRef<str>>(
+    kind: ErrorKind,
+    code: ErrorCode,
+    msg: T,
+    field_errors: &[FieldError],
+) -> String {
+    serde_json::to_string_pretty(&FaultBody {
+        fault_message: msg.as_ref(),
+        error_code: code,
+        error_kind: kind,
+        field_errors: field_errors
+            .iter()
+            .map(|field_error| FieldFault {
+                field: &field_error.field,
+                fault_message: field_error.error.to_string(),
+                error_code: field_error.error.get_code(),
+                error_kind: *field_error.error.get_kind(),
+            }).collect(),
+    }).expect("Failed to serialize fault message")
 }
 
 enum Error<'a> {
@@ -84,21 +154,37 @@ enum Error<'a> {
 impl<'a> Into<hyper::Response> for Error<'a> {
     fn into(self) -> hyper::Response {
         match self {
-            Error::Generic(status, msg) => json_response(status, json_fault_message(msg)),
+            Error::Generic(status, msg) => {
+                let kind = if status.is_server_error() {
+                    ErrorKind::Internal
+                } else {
+                    ErrorKind::User
+                };
+                json_response(
+                    status,
+                    json_fault_message(kind, ErrorCode::ApiInvalidRequest, msg),
+                )
+            }
             Error::EmptyID => json_response(
                 StatusCode::BadRequest,
-                json_fault_message("The ID cannot be empty."),
+                json_fault_message(
+                    ErrorKind::User,
+                    ErrorCode::ApiEmptyId,
+                    "The ID cannot be empty.",
+                ),
             ),
             Error::InvalidPathMethod(path, method) => json_response(
                 StatusCode::BadRequest,
-                json_fault_message(format!(
-                    "Invalid request method and/or path: {} {}",
-                    method, path
-                )),
+                json_fault_message(
+                    ErrorKind::User,
+                    ErrorCode::ApiInvalidPathMethod,
+                    format!("Invalid request method and/or path: {} {}", method, path),
+                ),
+            ),
+            Error::SerdeJson(e) => json_response(
+                StatusCode::BadRequest,
+                json_fault_message(ErrorKind::User, ErrorCode::ApiInvalidJson, e.to_string()),
             ),
-            Error::SerdeJson(e) => {
-                json_response(StatusCode::BadRequest, json_fault_message(e.to_string()))
-            }
         }
     }
 }
@@ -126,6 +212,15 @@ fn parse_actions_req<'a>(path: &'a str, method: Method, body: &Chunk) -> Result<
     }
 }
 
+// Builds the request which retrieves the configuration of a microVM resource from the VMM.
+fn get_config_req<F>(make_action: F) -> ParsedRequest
+where
+    F: FnOnce(OutcomeSender) -> VmmAction,
+{
+    let (sender, receiver) = oneshot::channel();
+    ParsedRequest::Sync(make_action(sender), receiver)
+}
+
 // This function is supposed to do id validation for requests.
 fn checked_id(id: &str) -> Result<&str> {
     // todo: are there any checks we want to do on id's?
@@ -142,6 +237,10 @@ fn parse_boot_source_req<'a>(
     let path_tokens: Vec<&str> = path[1..].split_terminator('/').collect();
 
     match path_tokens[1..].len() {
+        0 if method == Method::Get => {
+            METRICS.get_api_requests.boot_source_count.inc();
+            Ok(get_config_req(VmmAction::GetBootSource))
+        }
         0 if method == Method::Put => {
             METRICS.put_api_requests.boot_source_count.inc();
             Ok(serde_json::from_slice::<BootSourceConfig>(body)
@@ -169,17 +268,70 @@ fn parse_mmds_request<'a>(
     let path_tokens: Vec<&str> = path[1..].split_terminator('/').collect();
 
     match path_tokens[1..].len() {
-        0 if method == Method::Get => Ok(ParsedRequest::GetMMDS),
-        0 if method == Method::Put => {
-            match serde_json::from_slice(&body) {
-                Ok(val) => return Ok(ParsedRequest::PutMMDS(val)),
-                Err(e) => return Err(Error::SerdeJson(e)),
-            };
+        1 if path_tokens[1] == "config" && method == Method::Put => {
+            METRICS.put_api_requests.mmds_cfg_count.inc();
+            Ok(serde_json::from_slice::<MmdsConfig>(body)
+                .map_err(|e| {
+                    METRICS.put_api_requests.mmds_cfg_fails.inc();
+                    Error::SerdeJson(e)
+                })?.into_parsed_request(None, method)
+                .map_err(|s| {
+                    METRICS.put_api_requests.mmds_cfg_fails.inc();
+                    Error::Generic(StatusCode::BadRequest, s)
+                })?)
         }
+        _ => parse_mmds_data_req(path, None, &path_tokens[1..], method, body),
+    }
+}
+
+// Turns HTTP requests on the contents of a MMDS data store into a ParsedRequest. The data store
+// is the one dedicated to the network interface identified by iface_id, or the default one when
+// iface_id is None. The data_path tokens follow the data store in the request path.
+fn parse_mmds_data_req<'a>(
+    path: &'a str,
+    iface_id: Option<String>,
+    data_path: &[&str],
+    method: Method,
+    body: &Chunk,
+) -> Result<'a, ParsedRequest> {
+    match data_path.len() {
+        0 if method == Method::Get => Ok(ParsedRequest::GetMMDS(iface_id)),
+        0 if method == Method::Put => match serde_json::from_slice(&body) {
+            Ok(val) => Ok(ParsedRequest::PutMMDS(iface_id, val)),
+            Err(e) => Err(Error::SerdeJson(e)),
+        },
         0 if method == Method::Patch => match serde_json::from_slice(&body) {
-            Ok(val) => return Ok(ParsedRequest::PatchMMDS(val)),
-            Err(e) => return Err(Error::SerdeJson(e)),
+            Ok(val) => Ok(ParsedRequest::PatchMMDS(iface_id, val)),
+            Err(e) => Err(Error::SerdeJson(e)),
         },
+        1 if data_path[0] == "guest" && method == Method::Delete => {
+            Ok(ParsedRequest::DeleteMMDSGuestData(iface_id))
+        }
+        _ if method == Method::Get => Ok(ParsedRequest::GetMMDSSubtree(
+            iface_id,
+            format!("/{}", data_path.join("/")),
+        )),
+        _ => Err(Error::InvalidPathMethod(path, method)),
+    }
+}
+
+// Turns a PUT /vm HTTP request into a ParsedRequest
+fn parse_vm_req<'a>(path: &'a str, method: Method, body: &Chunk) -> Result<'a, ParsedRequest> {
+    let path_tokens: Vec<&str> = path[1..].split_terminator('/').collect();
+
+    match path_tokens[1..].len() {
+        0 if method == Method::Put => {
+            METRICS.put_api_requests.vm_count.inc();
+            Ok(serde_json::from_slice::<MicrovmConfig>(body)
+                .map_err(|e| {
+                    METRICS.put_api_requests.vm_fails.inc();
+                    Error::SerdeJson(e)
+                })?.into_parsed_request(None, method)
+                .map_err(|s| {
+                    METRICS.put_api_requests.vm_fails.inc();
+                    Error::Generic(StatusCode::BadRequest, s)
+                })?)
+        }
         _ => Err(Error::InvalidPathMethod(path, method)),
     }
 }
@@ -189,11 +341,23 @@ fn parse_drives_req<'a>(path: &'a str, method: Method, body: &Chunk) -> Result<'
     let path_tokens: Vec<&str> = path[1..].split_terminator('/').collect();
     let id_from_path = if path_tokens.len() > 1 {
         checked_id(path_tokens[1])?
+    } else if method == Method::Get {
+        METRICS.get_api_requests.drive_count.inc();
+        return Ok(get_config_req(|sender| {
+            VmmAction::GetBlockDevices(None, sender)
+        }));
     } else {
         return Err(Error::EmptyID);
     };
 
     match path_tokens[1..].len() {
+        1 if method == Method::Get => {
+            METRICS.get_api_requests.drive_count.inc();
+            Ok(get_config_req(|sender| {
+                VmmAction::GetBlockDevices(Some(id_from_path.to_string()), sender)
+            }))
+        }
+
         1 if method == Method::Put => {
             METRICS.put_api_requests.drive_count.inc();
 
@@ -233,6 +397,10 @@ fn parse_logger_req<'a>(path: &'a str, method: Method, body: &Chunk) -> Result<'
     let path_tokens: Vec<&str> = path[1..].split_terminator('/').collect();
 
     match path_tokens[1..].len() {
+        0 if method == Method::Get => {
+            METRICS.get_api_requests.logger_count.inc();
+            Ok(get_config_req(VmmAction::GetLogger))
+        }
         0 if method == Method::Put => {
             METRICS.put_api_requests.logger_count.inc();
             Ok(serde_json::from_slice::<LoggerConfig>(body)
@@ -249,6 +417,45 @@ fn parse_logger_req<'a>(path: &'a str, method: Method, body: &Chunk) -> Result<'
     }
 }
 
+// Turns a GET /events HTTP request into a ParsedRequest
+fn parse_events_req<'a>(path: &'a str, method: Method) -> Result<'a, ParsedRequest> {
+    let path_tokens: Vec<&str> = path[1..].split_terminator('/').collect();
+
+    match path_tokens[1..].len() {
+        0 if method == Method::Get => {
+            METRICS.get_api_requests.events_count.inc();
+            Ok(ParsedRequest::GetEvents)
+        }
+        _ => Err(Error::InvalidPathMethod(path, method)),
+    }
+}
+
+// Turns a GET /version HTTP request into a ParsedRequest
+fn parse_version_req<'a>(path: &'a str, method: Method) -> Result<'a, ParsedRequest> {
+    let path_tokens: Vec<&str> = path[1..].split_terminator('/').collect();
+
+    match path_tokens[1..].len() {
+        0 if method == Method::Get => {
+            METRICS.get_api_requests.version_count.inc();
+            Ok(ParsedRequest::GetVersion)
+        }
+        _ => Err(Error::InvalidPathMethod(path, method)),
+    }
+}
+
+// Turns a GET /metrics HTTP request into a ParsedRequest
+fn parse_metrics_req<'a>(path: &'a str, method: Method) -> Result<'a, ParsedRequest> {
+    let path_tokens: Vec<&str> = path[1..].split_terminator('/').collect();
+
+    match path_tokens[1..].len() {
+        0 if method == Method::Get => {
+            METRICS.get_api_requests.metrics_count.inc();
+            Ok(ParsedRequest::GetMetrics)
+        }
+        _ => Err(Error::InvalidPathMethod(path, method)),
+    }
+}
+
 // Turns a GET/PUT /machine-config HTTP request into a ParsedRequest
 fn parse_machine_config_req<'a>(
     path: &'a str,
@@ -295,11 +502,22 @@ fn parse_netif_req<'a>(path: &'a str, method: Method, body: &Chunk) -> Result<'a
     let path_tokens: Vec<&str> = path[1..].split_terminator('/').collect();
     let id_from_path = if path_tokens.len() > 1 {
         checked_id(path_tokens[1])?
+    } else if method == Method::Get {
+        METRICS.get_api_requests.network_count.inc();
+        return Ok(get_config_req(|sender| {
+            VmmAction::GetNetworkInterfaces(None, sender)
+        }));
     } else {
         return Err(Error::EmptyID);
     };
 
     match path_tokens[1..].len() {
+        1 if method == Method::Get => {
+            METRICS.get_api_requests.network_count.inc();
+            Ok(get_config_req(|sender| {
+                VmmAction::GetNetworkInterfaces(Some(id_from_path.to_string()), sender)
+            }))
+        }
         1 if method == Method::Put => {
             METRICS.put_api_requests.network_count.inc();
 
@@ -313,6 +531,13 @@ fn parse_netif_req<'a>(path: &'a str, method: Method, body: &Chunk) -> Result<'a
                     Error::Generic(StatusCode::BadRequest, s)
                 })?)
         }
+        n if n > 1 && path_tokens[2] == "mmds" => parse_mmds_data_req(
+            path,
+            Some(id_from_path.to_string()),
+            &path_tokens[3..],
+            method,
+            body,
+        ),
         _ => Err(Error::InvalidPathMethod(path, method)),
     }
 }
@@ -323,6 +548,9 @@ fn parse_vsocks_req<'a>(path: &'a str, method: Method, body: &Chunk) -> Result<'
     let path_tokens: Vec<&str> = path[1..].split_terminator('/').collect();
     let id_from_path = if path_tokens.len() > 1 {
         checked_id(path_tokens[1])?
+    } else if method == Method::Get {
+        METRICS.get_api_requests.vsock_count.inc();
+        return Ok(get_config_req(VmmAction::GetVsockDevices));
     } else {
         return Err(Error::EmptyID);
     };
@@ -364,6 +592,7 @@ fn parse_request<'a>(method: Method, path: &'a str, body: &Chunk) -> Result<'a,
     if !path.starts_with('/') {
         return Err(Error::InvalidPathMethod(path, method));
     }
+    let path = strip_api_version(path);
 
     // We use path[1..] here to skip the initial '/'.
     let path_tokens: Vec<&str> = path[1..].split_terminator('/').collect();
@@ -380,54 +609,200 @@ fn parse_request<'a>(method: Method, path: &'a str, body: &Chunk) -> Result<'a,
         "actions" => parse_actions_req(path, method, body),
         "boot-source" => parse_boot_source_req(path, method, body),
         "drives" => parse_drives_req(path, method, body),
+        "events" => parse_events_req(path, method),
         "logger" => parse_logger_req(path, method, body),
         "machine-config" => parse_machine_config_req(path, method, body),
+        "metrics" => parse_metrics_req(path, method),
         "network-interfaces" => parse_netif_req(path, method, body),
         "mmds" => parse_mmds_request(path, method, body),
+        "version" => parse_version_req(path, method),
+        "vm" => parse_vm_req(path, method, body),
         #[cfg(feature = "vsock")]
         "vsocks" => parse_vsocks_req(path, method, body),
         _ => Err(Error::InvalidPathMethod(path, method)),
     }
 }
 
+// Removes the API version prefix from `path`, if present. The requests are served the same way
+// with and without the prefix, but clients which want to be shielded from future breaking changes
+// should use it.
+pub fn strip_api_version(path: &str) -> &str {
+    if path == API_VERSION_PREFIX {
+        "/"
+    } else if path.starts_with(API_VERSION_PREFIX)
+        && path[API_VERSION_PREFIX.len()..].starts_with('/')
+    {
+        &path[API_VERSION_PREFIX.len()..]
+    } else {
+        path
+    }
+}
+
+// Why an action couldn't be handed over to the VMM.
+#[derive(Debug, PartialEq)]
+enum VmmSendError {
+    // Too many actions are already waiting for the VMM.
+    QueueFull,
+    // The action was queued, but the VMM couldn't be notified about it.
+    NotNotified,
+    // The VMM thread is gone.
+    Failed,
+}
+
 // A helper function which is always used when a message is placed into the communication channel
-// with the VMM (so we don't forget to write to the EventFd).
+// with the VMM (so we don't forget to write to the EventFd). The channel is bounded, so this never
+// blocks: when the VMM falls behind, the action is rejected instead.
 fn send_to_vmm(
-    req: VmmAction,
-    sender: &mpsc::Sender<Box<VmmAction>>,
+    req: VmmRequest,
+    sender: &SyncSender<Box<VmmRequest>>,
     send_event: &EventFd,
-) -> result::Result<(), ()> {
-    sender.send(Box::new(req)).map_err(|_| ())?;
-    send_event.write(1).map_err(|_| ())
+) -> result::Result<(), VmmSendError> {
+    sender.try_send(Box::new(req)).map_err(|e| match e {
+        TrySendError::Full(_) => VmmSendError::QueueFull,
+        TrySendError::Disconnected(_) => VmmSendError::Failed,
+    })?;
+    // The action is in the queue from now on, whether or not the VMM gets notified about it.
+    let depth = &METRICS.api_server.vmm_action_queue_depth;
+    depth.set(depth.count() + 1);
+    send_event.write(1).map_err(|_| VmmSendError::NotNotified)
+}
+
+// Why the outcome of an action handed over to the VMM is not available.
+#[derive(Debug, PartialEq)]
+enum OutcomeError {
+    // The VMM dropped the action without sending its outcome.
+    Dropped,
+    // The VMM didn't handle the action before the deadline.
+    TimedOut,
+}
+
+// Returns a future which resolves to the outcome of an action handed over to the VMM, and takes
+// the action out of the queue depth metric once the VMM handles or drops it.
+fn dequeued_outcome(
+    outcome_receiver: OutcomeReceiver,
+) -> Box<Future<Item = VmmRequestOutcome, Error = Canceled>> {
+    Box::new(outcome_receiver.then(|outcome| {
+        let depth = &METRICS.api_server.vmm_action_queue_depth;
+        depth.set(depth.count().saturating_sub(1));
+        outcome
+    }))
+}
+
+// Returns a future which resolves to the outcome of an action handed over to the VMM, unless the
+// VMM doesn't handle the action within `timeout`. In that case the request is withdrawn, so the
+// VMM never handles it if it didn't take the action yet. Otherwise, the action cannot be undone,
+// so the VMM finishes it in the background, and its outcome is dropped.
+fn wait_for_outcome(
+    outcome_receiver: OutcomeReceiver,
+    request_handle: VmmRequestHandle,
+    timeout: Duration,
+    handle: &Handle,
+) -> Box<Future<Item = VmmRequestOutcome, Error = OutcomeError>> {
+    let outcome = dequeued_outcome(outcome_receiver);
+    let deadline = match Timeout::new(timeout, handle) {
+        Ok(deadline) => deadline,
+        Err(e) => {
+            error!("Failed to set the deadline of a VMM action: {}", e);
+            return Box::new(outcome.map_err(|_| OutcomeError::Dropped));
+        }
+    };
+
+    let handle = handle.clone();
+    Box::new(outcome.select2(deadline).then(move |result| match result {
+        Ok(Either::A((outcome, _))) => Ok(outcome),
+        Err(Either::A(_)) => Err(OutcomeError::Dropped),
+        Ok(Either::B((_, outcome))) | Err(Either::B((_, outcome))) => {
+            // The VMM either drops the action once it gets to it, or finishes handling it, so we
+            // keep waiting for that in the background, for the sake of the queue depth metric.
+            request_handle.withdraw();
+            handle.spawn(outcome.then(|_| Ok(())));
+            Err(OutcomeError::TimedOut)
+        }
+    }))
+}
+
+// Builds the response to a request which couldn't be handed over to the VMM, because too many
+// requests are waiting for it already.
+fn vmm_busy_response() -> hyper::Response {
+    METRICS.api_server.vmm_action_queue_full_count.inc();
+    json_response(
+        StatusCode::ServiceUnavailable,
+        json_fault_message(
+            ErrorKind::Internal,
+            ErrorCode::ApiVmmBusy,
+            "Too many requests are waiting for the VMM. Try again later.",
+        ),
+    )
+}
+
+// Builds the response to a request which the VMM didn't handle within `timeout`.
+fn vmm_action_timeout_response(timeout: Duration) -> hyper::Response {
+    METRICS.api_server.vmm_action_timeout_count.inc();
+    json_response(
+        StatusCode::GatewayTimeout,
+        json_fault_message(
+            ErrorKind::Internal,
+            ErrorCode::ApiVmmActionTimeout,
+            format!(
+                "The VMM did not handle the request within {} ms. It is withdrawn, unless the \
+                 VMM already started on it.",
+                timeout.as_secs() * 1000 + u64::from(timeout.subsec_nanos()) / 1_000_000
+            ),
+        ),
+    )
 }
 
 // In hyper, a struct that implements the Service trait is created to handle each incoming
 // request. This is the one for our ApiServer.
 pub struct ApiServerHttpService {
-    // MMDS info directly accessible from this API thread.
-    mmds_info: Arc<Mutex<Mmds>>,
+    // MMDS data stores directly accessible from this API thread.
+    mmds_info: Arc<Mutex<MmdsStores>>,
     // VMM instance info directly accessible from this API thread.
     vmm_shared_info: Arc<RwLock<InstanceInfo>>,
+    // Lifecycle events of the microVM, to which the API clients can subscribe.
+    vmm_events: Arc<EventPublisher>,
     // This allows sending messages to the VMM thread. It makes sense to use a Rc for the sender
     // (instead of cloning) because everything happens on a single thread, so there's no risk of
     // having races (if that was even a problem to begin with).
-    api_request_sender: Rc<mpsc::Sender<Box<VmmAction>>>,
+    api_request_sender: Rc<SyncSender<Box<VmmRequest>>>,
     // We write to this EventFd to let the VMM know about new messages.
     vmm_send_event: Rc<EventFd>,
+    // How long a request waits for the VMM to handle it, before giving up.
+    vmm_action_timeout: Duration,
+    // The event loop the API server runs on, used for setting the deadlines of the requests.
+    handle: Rc<Handle>,
+    // What the client on the other end of this connection is allowed to do.
+    access_level: AccessLevel,
+    // The client on the other end of this connection, if its credentials could be retrieved.
+    client: Option<PeerCredentials>,
+    // Records the mutating requests, when enabled.
+    audit_log: Option<Rc<RefCell<AuditLog>>>,
 }
 
 impl ApiServerHttpService {
     pub fn new(
-        mmds_info: Arc<Mutex<Mmds>>,
+        mmds_info: Arc<Mutex<MmdsStores>>,
         vmm_shared_info: Arc<RwLock<InstanceInfo>>,
-        api_request_sender: Rc<mpsc::Sender<Box<VmmAction>>>,
+        vmm_events: Arc<EventPublisher>,
+        api_request_sender: Rc<SyncSender<Box<VmmRequest>>>,
         vmm_send_event: Rc<EventFd>,
+        vmm_action_timeout: Duration,
+        handle: Rc<Handle>,
+        access_level: AccessLevel,
+        client: Option<PeerCredentials>,
+        audit_log: Option<Rc<RefCell<AuditLog>>>,
     ) -> Self {
         ApiServerHttpService {
             mmds_info,
             vmm_shared_info,
+            vmm_events,
             api_request_sender,
             vmm_send_event,
+            vmm_action_timeout,
+            handle,
+            access_level,
+            client,
+            audit_log,
         }
     }
 }
@@ -441,26 +816,57 @@ impl hyper::server::Service for ApiServerHttpService {
     // This function returns a future that will resolve at some point to the response for
     // the HTTP request contained in req.
     fn call(&self, req: Self::Request) -> Self::Future {
+        let denied_response = access_denied_response(self.access_level, req.method());
+        // The mutating requests are recorded in the audit log once their outcome is known,
+        // including the ones which are rejected.
+        let audit_entry = match self.audit_log {
+            Some(ref audit_log) if *req.method() != Method::Get => Some(AuditEntry::new(
+                audit_log.clone(),
+                self.client,
+                req.method().clone(),
+                String::from(req.path()),
+            )),
+            _ => None,
+        };
+
+        // Requests which the client is not allowed to make are answered right away, without
+        // waiting for their body, so they are also recorded without one.
+        if let Some(response) = denied_response {
+            if let Some(audit_entry) = audit_entry {
+                audit_entry.record(Some(response.status()));
+            }
+            return Box::new(future::ok(response));
+        }
+
         // We do all this cloning to be able too move everything we need
         // into the closure that follows.
         let mmds_info = self.mmds_info.clone();
         let method = req.method().clone();
         let method_copy = req.method().clone();
         let path = String::from(req.path());
+        let json_patch = is_json_patch(req.headers());
         let shared_info_lock = self.vmm_shared_info.clone();
+        let vmm_events = self.vmm_events.clone();
         let api_request_sender = self.api_request_sender.clone();
         let vmm_send_event = self.vmm_send_event.clone();
+        let vmm_action_timeout = self.vmm_action_timeout;
+        let handle = self.handle.clone();
 
         // for nice looking match arms
         use request::ParsedRequest::*;
 
-        // The request body is itself a future (a stream of Chunks to be more precise),
-        // so we have to define a future that waits for all the pieces first (via concat2),
-        // and then does something with the newly available body (via and_then).
-        Box::new(req.body().concat2().and_then(move |b| {
-            // When this will be executed, the body is available. We start by parsing the request.
+        // This turns the body of the request into a future which resolves to the response.
+        let respond = move |b: Chunk| {
+            // We start by parsing the request.
             match parse_request(method, path.as_ref(), &b) {
                 Ok(parsed_req) => match parsed_req {
+                    DeleteMMDSGuestData(iface_id) => Either::A(future::ok(
+                        delete_mmds_guest_data_response(&mmds_info, &iface_id),
+                    )),
+                    GetEvents => Either::A(future::ok(get_events_response(
+                        &vmm_events,
+                        &shared_info_lock,
+                    ))),
                     GetInstanceInfo => {
                         METRICS.get_api_requests.instance_info_count.inc();
 
@@ -477,109 +883,513 @@ impl hyper::server::Service for ApiServerHttpService {
                                 METRICS.get_api_requests.instance_info_fails.inc();
                                 Either::A(future::ok(json_response(
                                     StatusCode::InternalServerError,
-                                    json_fault_message(e.to_string()),
+                                    json_fault_message(
+                                        ErrorKind::Internal,
+                                        ErrorCode::ApiSerializationFailure,
+                                        e.to_string(),
+                                    ),
                                 )))
                             }
                         }
                     }
-                    PatchMMDS(json_value) => {
-                        let mut mmds = mmds_info
-                            .lock()
-                            .expect("Failed to acquire lock on MMDS info");
-                        match mmds.is_initialized() {
-                            true => {
-                                mmds.patch_data(json_value);
-                                Either::A(future::ok(empty_response(StatusCode::NoContent)))
-                            }
-                            false => Either::A(future::ok(json_response(
-                                StatusCode::NotFound,
-                                json_fault_message("The MMDS resource does not exist."),
-                            ))),
-                        }
-                    }
-                    PutMMDS(json_value) => {
-                        mmds_info
-                            .lock()
-                            .expect("Failed to acquire lock on MMDS info")
-                            .put_data(json_value);
-                        Either::A(future::ok(empty_response(StatusCode::NoContent)))
-                    }
-                    GetMMDS => Either::A(future::ok(json_response(
-                        StatusCode::Ok,
-                        mmds_info
-                            .lock()
-                            .expect("Failed to acquire lock on MMDS info")
-                            .get_data_str(),
+                    GetMetrics => Either::A(future::ok(get_metrics_response())),
+                    GetVersion => Either::A(future::ok(get_version_response(&shared_info_lock))),
+                    PatchMMDS(iface_id, json_value) => Either::A(future::ok(
+                        patch_mmds_response(&mmds_info, &iface_id, json_value, json_patch),
+                    )),
+                    PutMMDS(iface_id, json_value) => Either::A(future::ok(put_mmds_response(
+                        &mmds_info,
+                        &shared_info_lock,
+                        &iface_id,
+                        json_value,
                     ))),
+                    GetMMDS(iface_id) => {
+                        Either::A(future::ok(get_mmds_response(&mmds_info, &iface_id, None)))
+                    }
+                    GetMMDSSubtree(iface_id, mmds_path) => Either::A(future::ok(
+                        get_mmds_response(&mmds_info, &iface_id, Some(mmds_path)),
+                    )),
                     Sync(sync_req, outcome_receiver) => {
-                        if send_to_vmm(sync_req, &api_request_sender, &vmm_send_event).is_err() {
-                            METRICS.api_server.sync_vmm_send_timeout_count.inc();
-                            return Either::A(future::err(hyper::Error::Timeout));
+                        let (request, request_handle) = VmmRequest::new(sync_req);
+                        match send_to_vmm(request, &api_request_sender, &vmm_send_event) {
+                            Ok(()) => (),
+                            Err(VmmSendError::QueueFull) => {
+                                return Either::A(future::ok(vmm_busy_response()));
+                            }
+                            Err(VmmSendError::NotNotified) => {
+                                // The VMM may still come across the action, but nobody waits for
+                                // its outcome, so it is withdrawn. It leaves the queue depth
+                                // metric once the VMM drops it.
+                                request_handle.withdraw();
+                                handle.spawn(dequeued_outcome(outcome_receiver).then(|_| Ok(())));
+                                METRICS.api_server.sync_vmm_send_timeout_count.inc();
+                                return Either::A(future::err(hyper::Error::Timeout));
+                            }
+                            Err(VmmSendError::Failed) => {
+                                METRICS.api_server.sync_vmm_send_timeout_count.inc();
+                                return Either::A(future::err(hyper::Error::Timeout));
+                            }
                         }
 
                         // metric-logging related variables for being able to log response details
                         let b_str = String::from_utf8_lossy(&b.to_vec()).to_string();
-                        let b_str_err = String::from_utf8_lossy(&b.to_vec()).to_string();
                         let path_copy = path.clone();
-                        let path_copy_err = path_copy.clone();
-                        let method_copy_err = method_copy.clone();
 
                         info!("Sent {}", describe(&method_copy, &path, &b_str));
 
-                        // Sync requests don't receive a response until the outcome is returned.
-                        // Once more, this just registers a closure to run when the result is
-                        // available.
-                        Either::B(
-                            outcome_receiver
-                                .map(move |x| {
-                                    info!(
-                                        "Received Success on {}",
-                                        describe(&method_copy, &path_copy, &b_str)
-                                    );
-                                    x.generate_response()
-                                }).map_err(move |_| {
-                                    info!(
-                                        "Received Error on {}",
-                                        describe(&method_copy_err, &path_copy_err, &b_str_err)
-                                    );
-                                    METRICS.api_server.sync_outcome_fails.inc();
-                                    hyper::Error::Timeout
-                                }),
-                        )
+                        // Sync requests don't receive a response until the outcome is returned,
+                        // or until their deadline. Once more, this just registers a closure to
+                        // run when the result is available.
+                        let outcome = wait_for_outcome(
+                            outcome_receiver,
+                            request_handle,
+                            vmm_action_timeout,
+                            &handle,
+                        );
+                        Either::B(outcome.then(move |outcome| match outcome {
+                            Ok(x) => {
+                                info!(
+                                    "Received Success on {}",
+                                    describe(&method_copy, &path_copy, &b_str)
+                                );
+                                Ok(x.generate_response())
+                            }
+                            Err(OutcomeError::TimedOut) => {
+                                info!(
+                                    "Timed out on {}",
+                                    describe(&method_copy, &path_copy, &b_str)
+                                );
+                                Ok(vmm_action_timeout_response(vmm_action_timeout))
+                            }
+                            Err(OutcomeError::Dropped) => {
+                                info!(
+                                    "Received Error on {}",
+                                    describe(&method_copy, &path_copy, &b_str)
+                                );
+                                METRICS.api_server.sync_outcome_fails.inc();
+                                Err(hyper::Error::Timeout)
+                            }
+                        }))
                     }
                 },
                 Err(e) => Either::A(future::ok(e.into())),
             }
+        };
+
+        // The request body is itself a future (a stream of Chunks to be more precise),
+        // so we have to define a future that waits for all the pieces first (via concat2),
+        // and then does something with the newly available body (via and_then).
+        Box::new(req.body().concat2().and_then(move |b| {
+            // When this will be executed, the body is available.
+            let audit_entry = audit_entry.map(|audit_entry| audit_entry.with_body(&b));
+            respond(b).then(move |result| {
+                if let Some(audit_entry) = audit_entry {
+                    audit_entry.record(result.as_ref().ok().map(|response| response.status()));
+                }
+                result
+            })
         }))
     }
 }
 
+// Builds the response to a request which the client is not allowed to make, or returns None if the
+// request can go ahead.
+fn access_denied_response(access_level: AccessLevel, method: &Method) -> Option<hyper::Response> {
+    let allowed = match access_level {
+        AccessLevel::Full => true,
+        AccessLevel::ReadOnly => *method == Method::Get,
+        AccessLevel::Denied => false,
+    };
+    if allowed {
+        return None;
+    }
+
+    METRICS.api_server.access_denied_count.inc();
+    Some(json_response(
+        StatusCode::Forbidden,
+        json_fault_message(
+            ErrorKind::User,
+            ErrorCode::ApiAccessDenied,
+            "The client is not allowed to make this request.",
+        ),
+    ))
+}
+
+// Builds the response to a GET /events request. The body is a stream which never ends on its own:
+// it starts with the current state of the microVM, followed by every event published from then on,
+// each serialized as a JSON object on its own line. The client is unsubscribed once it goes away,
+// or if it doesn't keep up with the events.
+fn get_events_response(
+    events: &EventPublisher,
+    shared_info: &RwLock<InstanceInfo>,
+) -> hyper::Response {
+    let (mut sender, receiver) =
+        futures_mpsc::channel::<result::Result<Chunk, hyper::Error>>(EVENT_STREAM_BUFFER_SIZE);
+    let mut send_event = move |event: &Event| match serde_json::to_string(event) {
+        Ok(line) => sender.try_send(Ok(Chunk::from(line + "\n"))).is_ok(),
+        Err(e) => {
+            error!("Failed to serialize event {:?}: {}", event, e);
+            true
+        }
+    };
+
+    // The lock is held while subscribing, so that no state change goes unnoticed.
+    let info = shared_info
+        .read()
+        .expect("Failed to read shared_info due to poisoned lock");
+    send_event(&Event::StateChange {
+        state: info.state.clone(),
+    });
+    events.subscribe(Box::new(send_event));
+    drop(info);
+
+    let mut headers = Headers::new();
+    headers.set(hyper::header::ContentType(
+        NDJSON_CONTENT_TYPE
+            .parse()
+            .expect("Invalid NDJSON content type"),
+    ));
+    hyper::Response::new()
+        .with_status(StatusCode::Ok)
+        .with_headers(headers)
+        .with_body(Body::from(receiver))
+}
+
+// The version of Firecracker, along with the capabilities which may differ between versions or
+// builds, as reported by GET /version.
+#[derive(Debug, Serialize)]
+struct VersionInfo {
+    firecracker_version: String,
+    api_versions: Vec<&'static str>,
+    features: Vec<&'static str>,
+    cpu_templates: Vec<CpuFeaturesTemplate>,
+    seccomp_levels: Vec<u32>,
+}
+
+// Returns the optional cargo features Firecracker was built with.
+fn compiled_features() -> Vec<&'static str> {
+    let mut features = Vec::new();
+    if cfg!(feature = "vsock") {
+        features.push("vsock");
+    }
+    features
+}
+
+// Builds the response to a GET /version request.
+fn get_version_response(shared_info: &RwLock<InstanceInfo>) -> hyper::Response {
+    let version_info = VersionInfo {
+        firecracker_version: shared_info
+            .read()
+            .expect("Failed to read shared_info due to poisoned lock")
+            .vmm_version
+            .clone(),
+        api_versions: API_VERSIONS.to_vec(),
+        features: compiled_features(),
+        cpu_templates: CpuFeaturesTemplate::all(),
+        seccomp_levels: vec![
+            seccomp::SECCOMP_LEVEL_NONE,
+            seccomp::SECCOMP_LEVEL_BASIC,
+            seccomp::SECCOMP_LEVEL_ADVANCED,
+        ],
+    };
+    match serde_json::to_string(&version_info) {
+        Ok(body) => json_response(StatusCode::Ok, body),
+        Err(e) => json_response(
+            StatusCode::InternalServerError,
+            json_fault_message(
+                ErrorKind::Internal,
+                ErrorCode::ApiSerializationFailure,
+                e.to_string(),
+            ),
+        ),
+    }
+}
+
+// Builds the response to a GET /metrics request. The cumulative value of each metric is reported,
+// independently of the periodic flushes to the metrics FIFO.
+fn get_metrics_response() -> hyper::Response {
+    let mut headers = Headers::new();
+    headers.set(hyper::header::ContentType(
+        PROMETHEUS_CONTENT_TYPE
+            .parse()
+            .expect("Invalid Prometheus content type"),
+    ));
+    build_response_base(
+        StatusCode::Ok,
+        Some(headers),
+        Some(METRICS.to_prometheus_text()),
+    )
+}
+
+// Returns the MMDS data store targeted by a request, or None if the network interface identified
+// by iface_id does not have a dedicated one.
+fn mmds_store(mmds_stores: &Mutex<MmdsStores>, iface_id: &Option<String>) -> Option<Arc<Mutex<Mmds>>> {
+    let stores = mmds_stores
+        .lock()
+        .expect("Failed to acquire lock on MMDS stores");
+    match *iface_id {
+        Some(ref id) => stores.interface_store(id),
+        None => Some(stores.default_store()),
+    }
+}
+
+fn mmds_not_found_response() -> hyper::Response {
+    json_response(
+        StatusCode::NotFound,
+        json_fault_message(
+            ErrorKind::User,
+            ErrorCode::MmdsNotFound,
+            "The MMDS resource does not exist.",
+        ),
+    )
+}
+
+// Builds the response to a GET request on a MMDS data store, or on the subtree found at
+// mmds_path.
+fn get_mmds_response(
+    mmds_stores: &Mutex<MmdsStores>,
+    iface_id: &Option<String>,
+    mmds_path: Option<String>,
+) -> hyper::Response {
+    let store = match mmds_store(mmds_stores, iface_id) {
+        Some(store) => store,
+        None => return mmds_not_found_response(),
+    };
+    let mmds = store.lock().expect("Failed to acquire lock on MMDS info");
+    match mmds_path {
+        Some(mmds_path) => match mmds.get_value_json(mmds_path) {
+            Ok(body) => json_response(StatusCode::Ok, body),
+            Err(_) => mmds_not_found_response(),
+        },
+        None => json_response(StatusCode::Ok, mmds.get_data_str()),
+    }
+}
+
+// Builds the response to a PUT request on a MMDS data store. The data store dedicated to a
+// network interface is created by the first such request, which has to be issued before boot,
+// since the network devices are bound to their data stores when the microVM starts.
+fn put_mmds_response(
+    mmds_stores: &Mutex<MmdsStores>,
+    shared_info: &RwLock<InstanceInfo>,
+    iface_id: &Option<String>,
+    json_value: Value,
+) -> hyper::Response {
+    let store = match *iface_id {
+        Some(ref id) => {
+            let mut stores = mmds_stores
+                .lock()
+                .expect("Failed to acquire lock on MMDS stores");
+            let instance_started = shared_info
+                .read()
+                .expect("Failed to read shared_info due to poisoned lock")
+                .state != InstanceState::Uninitialized;
+            if instance_started && stores.interface_store(id).is_none() {
+                return json_response(
+                    StatusCode::BadRequest,
+                    json_fault_message(
+                        ErrorKind::User,
+                        ErrorCode::MmdsStoreCreationNotAllowedPostBoot,
+                        "Dedicated MMDS data stores can only be created before boot.",
+                    ),
+                );
+            }
+            stores.add_interface_store(id)
+        }
+        None => mmds_stores
+            .lock()
+            .expect("Failed to acquire lock on MMDS stores")
+            .default_store(),
+    };
+    let mut mmds = store.lock().expect("Failed to acquire lock on MMDS info");
+    let outcome = put_mmds_data(&mut mmds, json_value);
+    mmds_update_response(&mmds, outcome)
+}
+
+/// Replaces the contents of a MMDS data store, the way a PUT request on /mmds does. This is also
+/// how the contents found in a config file are set, so both are counted and logged alike.
+pub fn put_mmds_data(mmds: &mut Mmds, data: Value) -> result::Result<(), MmdsError> {
+    METRICS.put_api_requests.mmds_count.inc();
+    let outcome = mmds.put_data(data);
+    match outcome {
+        Ok(()) => info!("Replaced the MMDS contents."),
+        Err(ref e) => {
+            METRICS.put_api_requests.mmds_fails.inc();
+            info!("Failed to replace the MMDS contents: {:?}", e);
+        }
+    }
+    outcome
+}
+
+// Tells whether the body of a request is a JSON Patch (RFC 6902), as opposed to a JSON Merge
+// Patch (RFC 7396).
+fn is_json_patch(headers: &Headers) -> bool {
+    headers
+        .get::<hyper::header::ContentType>()
+        .map_or(false, |content_type| {
+            let mime = &content_type.0;
+            mime.type_() == hyper::mime::APPLICATION
+                && mime.subtype() == "json-patch"
+                && mime.suffix() == Some(hyper::mime::JSON)
+        })
+}
+
+// Builds the response to a PATCH request on a MMDS data store. The body is applied as a JSON
+// Patch when json_patch is true, and merged into the data store otherwise.
+fn patch_mmds_response(
+    mmds_stores: &Mutex<MmdsStores>,
+    iface_id: &Option<String>,
+    json_value: Value,
+    json_patch: bool,
+) -> hyper::Response {
+    let store = match mmds_store(mmds_stores, iface_id) {
+        Some(store) => store,
+        None => return mmds_not_found_response(),
+    };
+    let mut mmds = store.lock().expect("Failed to acquire lock on MMDS info");
+    if !mmds.is_initialized() {
+        return mmds_not_found_response();
+    }
+    if json_patch {
+        let outcome = mmds.apply_json_patch(json_value);
+        return json_patch_response(&mmds, outcome);
+    }
+    let outcome = mmds.patch_data(json_value);
+    mmds_update_response(&mmds, outcome)
+}
+
+// Builds the response to a DELETE request on the section of a MMDS data store written by the
+// guest.
+fn delete_mmds_guest_data_response(
+    mmds_stores: &Mutex<MmdsStores>,
+    iface_id: &Option<String>,
+) -> hyper::Response {
+    let store = match mmds_store(mmds_stores, iface_id) {
+        Some(store) => store,
+        None => return mmds_not_found_response(),
+    };
+    store
+        .lock()
+        .expect("Failed to acquire lock on MMDS info")
+        .clear_guest_data();
+    empty_response(StatusCode::NoContent)
+}
+
+fn mmds_limit_exceeded_response(mmds: &Mmds) -> hyper::Response {
+    json_response(
+        StatusCode::PayloadTooLarge,
+        json_fault_message(
+            ErrorKind::User,
+            ErrorCode::MmdsDataStoreLimitExceeded,
+            format!(
+                "The MMDS contents would exceed the data store limit of {} bytes.",
+                mmds.data_store_limit()
+            ),
+        ),
+    )
+}
+
+// Builds the response to a PUT or PATCH request on /mmds, given the outcome of the update.
+fn mmds_update_response(mmds: &Mmds, outcome: result::Result<(), MmdsError>) -> hyper::Response {
+    match outcome {
+        Ok(()) => empty_response(StatusCode::NoContent),
+        Err(MmdsError::DataStoreLimitExceeded) => mmds_limit_exceeded_response(mmds),
+        Err(MmdsError::NotFound) => mmds_not_found_response(),
+    }
+}
+
+// Builds the response to a PATCH request on /mmds carrying a JSON Patch, given the outcome of
+// applying it. Patches which cannot be applied to the current contents yield 409.
+fn json_patch_response(
+    mmds: &Mmds,
+    outcome: result::Result<(), JsonPatchError>,
+) -> hyper::Response {
+    match outcome {
+        Ok(()) => empty_response(StatusCode::NoContent),
+        Err(JsonPatchError::NotAnArray) => json_response(
+            StatusCode::BadRequest,
+            json_fault_message(
+                ErrorKind::User,
+                ErrorCode::MmdsInvalidJsonPatch,
+                "The JSON Patch must be an array of operations.",
+            ),
+        ),
+        Err(JsonPatchError::InvalidOperation(index, e)) => json_response(
+            StatusCode::BadRequest,
+            json_fault_message(
+                ErrorKind::User,
+                ErrorCode::MmdsInvalidJsonPatch,
+                format!("JSON Patch operation {} is invalid: {}", index, e),
+            ),
+        ),
+        Err(JsonPatchError::InvalidPointer(index)) => json_response(
+            StatusCode::Conflict,
+            json_fault_message(
+                ErrorKind::User,
+                ErrorCode::MmdsJsonPatchInvalidPointer,
+                format!(
+                    "JSON Patch operation {} refers to a location which does not exist.",
+                    index
+                ),
+            ),
+        ),
+        Err(JsonPatchError::TestFailed {
+            index,
+            path,
+            expected,
+            actual,
+        }) => json_response(
+            StatusCode::Conflict,
+            json_fault_message(
+                ErrorKind::User,
+                ErrorCode::MmdsJsonPatchTestFailed,
+                format!(
+                    "JSON Patch test operation {} failed: the value at \"{}\" is {}, not {}.",
+                    index, path, actual, expected
+                ),
+            ),
+        ),
+        Err(JsonPatchError::DataStoreLimitExceeded) => mmds_limit_exceeded_response(mmds),
+    }
+}
+
 /// Helper function for metric-logging purposes on API requests
 /// `method` is whether PUT or GET
 /// `path` and `body` represent path of the API request and body, respectively
+// The secrets from the body are redacted, since the description ends up in the log.
 fn describe(method: &Method, path: &String, body: &String) -> String {
     format!(
         "synchronous {:?} request {:?} with body {:?}",
-        method, path, body
+        method,
+        path,
+        redact_body(body)
     )
 }
 
 #[cfg(test)]
 mod tests {
     extern crate net_util;
+    extern crate tempfile;
 
     use self::net_util::MacAddr;
+    use self::tempfile::tempdir;
     use super::*;
 
     use serde_json::{Map, Value};
+    use std::fs::File;
+    use std::io::Read;
     use std::path::PathBuf;
     use std::result;
 
     use futures::sync::oneshot;
     use hyper::header::{ContentType, Headers};
+    use hyper::server::Service;
     use hyper::Body;
+    use std::sync::mpsc::sync_channel;
+    use std::thread;
+    use tokio_core::reactor::Core;
+    use vmm::vmm_config::drive::DriveError;
     use vmm::vmm_config::machine_config::CpuFeaturesTemplate;
-    use vmm::VmmAction;
+    use vmm::{VmmAction, VmmActionError, VmmData};
 
     impl<'a> PartialEq for Error<'a> {
         fn eq(&self, other: &Error<'a>) -> bool {
@@ -662,28 +1472,532 @@ mod tests {
     }
 
     #[test]
-    fn test_basic_json_body() {
-        let body = basic_json_body("42", "the answer to life, the universe and everything");
+    fn test_json_fault_message() {
+        let body = json_fault_message(
+            ErrorKind::User,
+            ErrorCode::DrivePathAlreadyExists,
+            "This is an \"error\" message",
+        );
         assert_eq!(
             body,
-            "{\n  \"42\": \"the answer to life, the universe and everything\"\n}"
+            concat!(
+                "{\n",
+                "  \"fault_message\": \"This is an \\\"error\\\" message\",\n",
+                "  \"error_code\": \"drive.path_already_exists\",\n",
+                "  \"error_kind\": \"User\"\n",
+                "}"
+            )
         );
+
+        let field_errors = vec![FieldError::new(
+            "drives[1]",
+            VmmActionError::DriveConfig(ErrorKind::User, DriveError::RootBlockDeviceAlreadyAdded),
+        )];
+        let body: Value = serde_json::from_str(&json_field_fault_message(
+            ErrorKind::User,
+            ErrorCode::MicrovmConfigInvalidFields,
+            "Invalid configuration",
+            &field_errors,
+        )).unwrap();
+        assert_eq!(body["error_code"], "microvm_config.invalid_fields");
+        assert_eq!(body["field_errors"][0]["field"], "drives[1]");
+        assert_eq!(
+            body["field_errors"][0]["fault_message"],
+            DriveError::RootBlockDeviceAlreadyAdded.to_string()
+        );
+        assert_eq!(body["field_errors"][0]["error_code"], "drive.root_already_added");
+        assert_eq!(body["field_errors"][0]["error_kind"], "User");
     }
 
     #[test]
-    fn test_json_fault_message() {
-        let body = json_fault_message("This is an error message");
+    fn test_mmds_update_response() {
+        let mut mmds = Mmds::default();
+        mmds.set_data_store_limit(10);
+
+        let response = mmds_update_response(&mmds, Ok(()));
+        assert_eq!(response.status(), StatusCode::NoContent);
+
+        let outcome = mmds.put_data(serde_json::from_str("{\"user-data\": 15}").unwrap());
+        let response = mmds_update_response(&mmds, outcome);
+        assert_eq!(response.status(), StatusCode::PayloadTooLarge);
         assert_eq!(
-            body,
-            "{\n  \"fault_message\": \"This is an error message\"\n}"
+            body_to_string(response.body()),
+            json_fault_message(
+                ErrorKind::User,
+                ErrorCode::MmdsDataStoreLimitExceeded,
+                "The MMDS contents would exceed the data store limit of 10 bytes."
+            )
+        );
+    }
+
+    #[test]
+    fn test_json_patch_response() {
+        let mut mmds = Mmds::default();
+        mmds.put_data(json_value(r#"{"version": 2}"#)).unwrap();
+
+        let outcome = mmds.apply_json_patch(json_value(
+            r#"[{"op": "test", "path": "/version", "value": 1}]"#,
+        ));
+        let response = json_patch_response(&mmds, outcome);
+        assert_eq!(response.status(), StatusCode::Conflict);
+        assert_eq!(
+            body_to_string(response.body()),
+            json_fault_message(
+                ErrorKind::User,
+                ErrorCode::MmdsJsonPatchTestFailed,
+                "JSON Patch test operation 0 failed: the value at \"/version\" is 2, not 1."
+            )
+        );
+
+        let outcome = mmds.apply_json_patch(json_value(r#"[{"op": "remove", "path": "/a"}]"#));
+        let response = json_patch_response(&mmds, outcome);
+        assert_eq!(response.status(), StatusCode::Conflict);
+
+        let outcome = mmds.apply_json_patch(json_value(r#"[{"path": "/a"}]"#));
+        let response = json_patch_response(&mmds, outcome);
+        assert_eq!(response.status(), StatusCode::BadRequest);
+
+        let outcome = mmds.apply_json_patch(json_value(r#"{"version": 3}"#));
+        let response = json_patch_response(&mmds, outcome);
+        assert_eq!(response.status(), StatusCode::BadRequest);
+
+        mmds.set_data_store_limit(10);
+        let outcome = mmds.apply_json_patch(json_value(
+            r#"[{"op": "add", "path": "/user-data", "value": 15}]"#,
+        ));
+        let response = json_patch_response(&mmds, outcome);
+        assert_eq!(response.status(), StatusCode::PayloadTooLarge);
+
+        let outcome =
+            mmds.apply_json_patch(json_value(r#"[{"op": "remove", "path": "/version"}]"#));
+        let response = json_patch_response(&mmds, outcome);
+        assert_eq!(response.status(), StatusCode::NoContent);
+        assert_eq!(mmds.get_data_str(), "{}");
+    }
+
+    #[test]
+    fn test_get_metrics_response() {
+        METRICS.vmm.device_events.inc();
+        let response = get_metrics_response();
+        assert_eq!(response.status(), StatusCode::Ok);
+        assert_eq!(
+            response.headers().get::<ContentType>(),
+            Some(&ContentType(PROMETHEUS_CONTENT_TYPE.parse().unwrap()))
+        );
+        let body = body_to_string(response.body());
+        assert!(body.contains("# TYPE firecracker_vmm_device_events counter\n"));
+        assert!(!body.contains("utc_timestamp_ms"));
+    }
+
+    #[test]
+    fn test_vmm_action_queue() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let send_event = EventFd::new().unwrap();
+        let timeout = Duration::from_millis(10);
+        let depth = &METRICS.api_server.vmm_action_queue_depth;
+        let initial_depth = depth.count();
+
+        // The queue only holds a single action.
+        let (sender, receiver) = sync_channel(1);
+        let (outcome_sender, outcome_receiver) = oneshot::channel();
+        let (request, request_handle) = VmmRequest::new(VmmAction::StartMicroVm(outcome_sender));
+        assert!(send_to_vmm(request, &sender, &send_event).is_ok());
+        assert_eq!(depth.count(), initial_depth + 1);
+        assert_eq!(send_event.read().unwrap(), 1);
+        let (other_sender, _) = oneshot::channel();
+        let other_request = VmmRequest::from(VmmAction::StartMicroVm(other_sender));
+        assert_eq!(
+            send_to_vmm(other_request, &sender, &send_event),
+            Err(VmmSendError::QueueFull)
+        );
+        assert_eq!(depth.count(), initial_depth + 1);
+
+        // The VMM doesn't get to the action before the deadline, so the request is withdrawn.
+        let timeout_count = METRICS.api_server.vmm_action_timeout_count.count();
+        let outcome = core.run(wait_for_outcome(
+            outcome_receiver,
+            request_handle,
+            timeout,
+            &handle,
+        ));
+        assert_eq!(outcome.unwrap_err(), OutcomeError::TimedOut);
+        let response = vmm_action_timeout_response(timeout);
+        assert_eq!(response.status(), StatusCode::GatewayTimeout);
+        assert_eq!(
+            body_to_string(response.body()),
+            json_fault_message(
+                ErrorKind::Internal,
+                ErrorCode::ApiVmmActionTimeout,
+                "The VMM did not handle the request within 10 ms. It is withdrawn, unless the \
+                 VMM already started on it."
+            )
+        );
+        assert_eq!(
+            METRICS.api_server.vmm_action_timeout_count.count(),
+            timeout_count + 1
+        );
+        // The action stays in the queue until the VMM gets to it, and drops it.
+        assert_eq!(depth.count(), initial_depth + 1);
+        assert!(receiver.recv().unwrap().take().is_none());
+        core.turn(Some(Duration::from_millis(0)));
+        assert_eq!(depth.count(), initial_depth);
+
+        // The VMM takes the action before the deadline, but doesn't handle it in time. The
+        // request times out, and the outcome is dropped once the VMM is done.
+        let (outcome_sender, outcome_receiver) = oneshot::channel();
+        let (request, request_handle) = VmmRequest::new(VmmAction::StartMicroVm(outcome_sender));
+        assert!(send_to_vmm(request, &sender, &send_event).is_ok());
+        let action = receiver.recv().unwrap().take().unwrap();
+        let vmm_thread = thread::spawn(move || {
+            thread::sleep(timeout * 5);
+            match action {
+                VmmAction::StartMicroVm(outcome_sender) => {
+                    outcome_sender.send(Ok(VmmData::Empty)).unwrap()
+                }
+                _ => panic!("Unexpected VMM action"),
+            }
+        });
+        let outcome = core.run(wait_for_outcome(
+            outcome_receiver,
+            request_handle,
+            timeout,
+            &handle,
+        ));
+        assert_eq!(outcome.unwrap_err(), OutcomeError::TimedOut);
+        // The action counts as waiting until the VMM is done with it.
+        assert_eq!(depth.count(), initial_depth + 1);
+        vmm_thread.join().unwrap();
+        core.turn(Some(Duration::from_millis(0)));
+        assert_eq!(depth.count(), initial_depth);
+
+        // The VMM handles the action in time.
+        let (outcome_sender, outcome_receiver) = oneshot::channel();
+        let (request, request_handle) = VmmRequest::new(VmmAction::StartMicroVm(outcome_sender));
+        assert!(send_to_vmm(request, &sender, &send_event).is_ok());
+        match receiver.recv().unwrap().take() {
+            Some(VmmAction::StartMicroVm(outcome_sender)) => {
+                outcome_sender.send(Ok(VmmData::Empty)).unwrap()
+            }
+            _ => panic!("Unexpected VMM action"),
+        }
+        let outcome = core.run(wait_for_outcome(
+            outcome_receiver,
+            request_handle,
+            timeout,
+            &handle,
+        ));
+        assert!(outcome.unwrap().is_ok());
+        assert_eq!(depth.count(), initial_depth);
+
+        // The VMM drops the action.
+        let (outcome_sender, outcome_receiver) = oneshot::channel();
+        let (request, request_handle) = VmmRequest::new(VmmAction::StartMicroVm(outcome_sender));
+        assert!(send_to_vmm(request, &sender, &send_event).is_ok());
+        drop(receiver.recv().unwrap());
+        let outcome = core.run(wait_for_outcome(
+            outcome_receiver,
+            request_handle,
+            timeout,
+            &handle,
+        ));
+        assert_eq!(outcome.unwrap_err(), OutcomeError::Dropped);
+        assert_eq!(depth.count(), initial_depth);
+
+        // The VMM is gone.
+        drop(receiver);
+        let (outcome_sender, _) = oneshot::channel();
+        assert_eq!(
+            send_to_vmm(
+                VmmRequest::from(VmmAction::StartMicroVm(outcome_sender)),
+                &sender,
+                &send_event
+            ),
+            Err(VmmSendError::Failed)
+        );
+
+        let full_count = METRICS.api_server.vmm_action_queue_full_count.count();
+        let response = vmm_busy_response();
+        assert_eq!(response.status(), StatusCode::ServiceUnavailable);
+        assert_eq!(
+            METRICS.api_server.vmm_action_queue_full_count.count(),
+            full_count + 1
+        );
+    }
+
+    #[test]
+    fn test_access_denied_response() {
+        assert!(access_denied_response(AccessLevel::Full, &Method::Put).is_none());
+        assert!(access_denied_response(AccessLevel::ReadOnly, &Method::Get).is_none());
+
+        let denied_count = METRICS.api_server.access_denied_count.count();
+        let response = access_denied_response(AccessLevel::ReadOnly, &Method::Patch).unwrap();
+        assert_eq!(response.status(), StatusCode::Forbidden);
+        assert_eq!(
+            body_to_string(response.body()),
+            json_fault_message(
+                ErrorKind::User,
+                ErrorCode::ApiAccessDenied,
+                "The client is not allowed to make this request."
+            )
+        );
+        let response = access_denied_response(AccessLevel::Denied, &Method::Get).unwrap();
+        assert_eq!(response.status(), StatusCode::Forbidden);
+        assert_eq!(
+            METRICS.api_server.access_denied_count.count(),
+            denied_count + 2
+        );
+    }
+
+    #[test]
+    fn test_access_denied_request() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let audit_log = Rc::new(RefCell::new(AuditLog::new(&path, 1024).unwrap()));
+        let core = Core::new().unwrap();
+        let (sender, _receiver) = sync_channel(1);
+        let service = ApiServerHttpService::new(
+            Arc::new(Mutex::new(MmdsStores::default())),
+            Arc::new(RwLock::new(InstanceInfo {
+                state: InstanceState::Uninitialized,
+                id: String::from("foo"),
+                vmm_version: String::from("1.0"),
+            })),
+            Arc::new(EventPublisher::default()),
+            Rc::new(sender),
+            Rc::new(EventFd::new().unwrap()),
+            Duration::from_millis(10),
+            Rc::new(core.handle()),
+            AccessLevel::ReadOnly,
+            None,
+            Some(audit_log),
+        );
+
+        // The body never arrives, so the response must not wait for it.
+        let (_body_sender, body) = Body::pair();
+        let mut req = hyper::Request::new(Method::Put, "/actions".parse().unwrap());
+        req.set_body(body);
+        let response = service.call(req).wait().unwrap();
+        assert_eq!(response.status(), StatusCode::Forbidden);
+
+        // The request is recorded without its body.
+        let mut contents = String::new();
+        File::open(&path)
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        let lines: Vec<Value> = contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0]["method"], "PUT");
+        assert_eq!(lines[0]["path"], "/actions");
+        assert_eq!(lines[0]["body"], "");
+        assert_eq!(lines[0]["status"], 403);
+    }
+
+    #[test]
+    fn test_get_version_response() {
+        let shared_info = RwLock::new(InstanceInfo {
+            state: InstanceState::Uninitialized,
+            id: String::from("foo"),
+            vmm_version: String::from("1.0"),
+        });
+        let response = get_version_response(&shared_info);
+        assert_eq!(response.status(), StatusCode::Ok);
+
+        let features = if cfg!(feature = "vsock") {
+            r#"["vsock"]"#
+        } else {
+            "[]"
+        };
+        let expected = format!(
+            concat!(
+                r#"{{"firecracker_version":"1.0","api_versions":["v1"],"features":{},"#,
+                r#""cpu_templates":["C3","T2"],"seccomp_levels":[0,1,2]}}"#
+            ),
+            features
+        );
+        assert_eq!(body_to_string(response.body()), expected);
+    }
+
+    #[test]
+    fn test_strip_api_version() {
+        assert_eq!(strip_api_version("/v1"), "/");
+        assert_eq!(strip_api_version("/v1/"), "/");
+        assert_eq!(strip_api_version("/v1/drives/foo"), "/drives/foo");
+        assert_eq!(strip_api_version("/drives/foo"), "/drives/foo");
+        assert_eq!(strip_api_version("/v10/drives"), "/v10/drives");
+        assert_eq!(strip_api_version("/v2/drives"), "/v2/drives");
+    }
+
+    #[test]
+    fn test_get_events_response() {
+        let events = Arc::new(EventPublisher::default());
+        let shared_info = RwLock::new(InstanceInfo {
+            state: InstanceState::Starting,
+            id: String::from("foo"),
+            vmm_version: String::from("1.0"),
+        });
+        let response = get_events_response(&events, &shared_info);
+        assert_eq!(response.status(), StatusCode::Ok);
+        assert_eq!(
+            response.headers().get::<ContentType>(),
+            Some(&ContentType(NDJSON_CONTENT_TYPE.parse().unwrap()))
+        );
+
+        events.publish(Event::StateChange {
+            state: InstanceState::Running,
+        });
+        events.publish(Event::GuestBootComplete { boot_time_us: 100 });
+        // Dropping the publisher closes the stream, so that the body can be read to the end.
+        drop(events);
+        assert_eq!(
+            body_to_string(response.body()),
+            concat!(
+                "{\"event\":\"state_change\",\"state\":\"Starting\"}\n",
+                "{\"event\":\"state_change\",\"state\":\"Running\"}\n",
+                "{\"event\":\"guest_boot_complete\",\"boot_time_us\":100}\n"
+            )
+        );
+
+        // Clients which fall behind are unsubscribed.
+        let events = EventPublisher::default();
+        let response = get_events_response(&events, &shared_info);
+        for _ in 0..EVENT_STREAM_BUFFER_SIZE + 1 {
+            events.publish(Event::DeviceError {
+                reason: String::from("bar"),
+            });
+        }
+        drop(events);
+        let body = body_to_string(response.body());
+        assert_eq!(body.lines().count(), EVENT_STREAM_BUFFER_SIZE + 1);
+    }
+
+    #[test]
+    fn test_is_json_patch() {
+        let mut headers = Headers::new();
+        assert!(!is_json_patch(&headers));
+        headers.set(ContentType::json());
+        assert!(!is_json_patch(&headers));
+        headers.set(ContentType(
+            "application/json-patch+json".parse().unwrap(),
+        ));
+        assert!(is_json_patch(&headers));
+        headers.set(ContentType(
+            "application/json-patch+json; charset=utf-8".parse().unwrap(),
+        ));
+        assert!(is_json_patch(&headers));
+    }
+
+    fn json_value(json: &str) -> Value {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_put_mmds_data() {
+        let mut mmds = Mmds::default();
+        let count = METRICS.put_api_requests.mmds_count.count();
+        let fails = METRICS.put_api_requests.mmds_fails.count();
+
+        assert!(put_mmds_data(&mut mmds, json_value(r#"{"a": 1}"#)).is_ok());
+        assert_eq!(mmds.get_data_str(), "{\"a\":1}");
+        mmds.set_data_store_limit(10);
+        match put_mmds_data(&mut mmds, json_value(r#"{"abcdefghij": 1}"#)) {
+            Err(MmdsError::DataStoreLimitExceeded) => (),
+            _ => panic!("The update should exceed the data store limit."),
+        }
+        assert_eq!(mmds.get_data_str(), "{\"a\":1}");
+
+        assert!(METRICS.put_api_requests.mmds_count.count() >= count + 2);
+        assert!(METRICS.put_api_requests.mmds_fails.count() >= fails + 1);
+    }
+
+    #[test]
+    fn test_mmds_data_stores() {
+        let stores = Mutex::new(MmdsStores::default());
+        let shared_info = RwLock::new(InstanceInfo {
+            state: InstanceState::Uninitialized,
+            id: String::from("TEST_ID"),
+            vmm_version: String::from("1.0"),
+        });
+        let iface_id = Some(String::from("eth0"));
+
+        // The interface has no dedicated data store yet.
+        let response = get_mmds_response(&stores, &iface_id, None);
+        assert_eq!(response.status(), StatusCode::NotFound);
+        let response = patch_mmds_response(
+            &stores,
+            &iface_id,
+            json_value(r#"{"tenant": "eth0"}"#),
+            false,
+        );
+        assert_eq!(response.status(), StatusCode::NotFound);
+
+        let response = put_mmds_response(&stores, &shared_info, &None, json_value(r#"{"tenant": "all"}"#));
+        assert_eq!(response.status(), StatusCode::NoContent);
+        let response = put_mmds_response(&stores, &shared_info, &iface_id, json_value(r#"{"a": 1}"#));
+        assert_eq!(response.status(), StatusCode::NoContent);
+        let response = patch_mmds_response(
+            &stores,
+            &iface_id,
+            json_value(r#"{"tenant": "eth0"}"#),
+            false,
         );
+        assert_eq!(response.status(), StatusCode::NoContent);
+
+        let response = get_mmds_response(&stores, &iface_id, None);
+        assert_eq!(
+            body_to_string(response.body()),
+            "{\"a\":1,\"tenant\":\"eth0\"}"
+        );
+        let response = get_mmds_response(&stores, &None, Some(String::from("/tenant")));
+        assert_eq!(body_to_string(response.body()), "\"all\"");
+
+        // Dedicated data stores can be updated, but not created, after boot.
+        shared_info.write().unwrap().state = InstanceState::Running;
+        let response = put_mmds_response(&stores, &shared_info, &iface_id, json_value(r#"{"a": 2}"#));
+        assert_eq!(response.status(), StatusCode::NoContent);
+        let other_iface_id = Some(String::from("eth1"));
+        let response = put_mmds_response(&stores, &shared_info, &other_iface_id, json_value(r#"{}"#));
+        assert_eq!(response.status(), StatusCode::BadRequest);
+        assert!(stores.lock().unwrap().interface_store("eth1").is_none());
+    }
+
+    #[test]
+    fn test_mmds_guest_data() {
+        let stores = Mutex::new(MmdsStores::default());
+        let iface_id = Some(String::from("eth0"));
+
+        let response = delete_mmds_guest_data_response(&stores, &iface_id);
+        assert_eq!(response.status(), StatusCode::NotFound);
+
+        {
+            let store = stores.lock().unwrap().default_store();
+            let mut mmds = store.lock().unwrap();
+            mmds.set_guest_data_limit(1024);
+            mmds.put_guest_data("/guest/status", json_value(r#""ready""#))
+                .unwrap();
+        }
+
+        let response = get_mmds_response(&stores, &None, Some(String::from("/guest")));
+        assert_eq!(body_to_string(response.body()), "{\"status\":\"ready\"}");
+
+        let response = delete_mmds_guest_data_response(&stores, &None);
+        assert_eq!(response.status(), StatusCode::NoContent);
+        let response = get_mmds_response(&stores, &None, Some(String::from("/guest")));
+        assert_eq!(body_to_string(response.body()), "{}");
     }
 
     #[test]
     fn test_error_to_response() {
-        let json_err_key = "fault_message";
         let json_err_val = "This is an error message";
-        let err_message = format!("{{\n  \"{}\": \"{}\"\n}}", &json_err_key, &json_err_val);
+        let err_message = json_fault_message(
+            ErrorKind::Internal,
+            ErrorCode::ApiInvalidRequest,
+            json_err_val,
+        );
         let message = String::from("This is an error message");
         let mut response: hyper::Response =
             Error::Generic(StatusCode::ServiceUnavailable, message).into();
@@ -694,9 +2008,15 @@ mod tests {
         );
         assert_eq!(body_to_string(response.body()), err_message);
 
+        response = Error::Generic(StatusCode::BadRequest, String::from(json_err_val)).into();
+        let err_message =
+            json_fault_message(ErrorKind::User, ErrorCode::ApiInvalidRequest, json_err_val);
+        assert_eq!(body_to_string(response.body()), err_message);
+
         response = Error::EmptyID.into();
         let json_err_val = "The ID cannot be empty.";
-        let err_message = format!("{{\n  \"{}\": \"{}\"\n}}", &json_err_key, &json_err_val);
+        let err_message =
+            json_fault_message(ErrorKind::User, ErrorCode::ApiEmptyId, json_err_val);
         assert_eq!(response.status(), StatusCode::BadRequest);
         assert_eq!(
             response.headers().get::<ContentType>(),
@@ -708,7 +2028,8 @@ mod tests {
         let method = Method::Options;
         response = Error::InvalidPathMethod(&path, method.clone()).into();
         let json_err_val = format!("Invalid request method and/or path: {} {}", &method, &path);
-        let err_message = format!("{{\n  \"{}\": \"{}\"\n}}", &json_err_key, &json_err_val);
+        let err_message =
+            json_fault_message(ErrorKind::User, ErrorCode::ApiInvalidPathMethod, json_err_val);
         assert_eq!(response.status(), StatusCode::BadRequest);
         assert_eq!(
             response.headers().get::<ContentType>(),
@@ -847,13 +2168,19 @@ mod tests {
                 == Err(expected_err)
         );
 
-        // Test case for invalid method (GET).
-        let expected_err = Error::InvalidPathMethod(boot_source_path, Method::Get);
+        // Test case for invalid method (PATCH).
+        let expected_err = Error::InvalidPathMethod(boot_source_path, Method::Patch);
         assert!(
-            parse_boot_source_req(boot_source_path, Method::Get, &Chunk::from("{}"))
+            parse_boot_source_req(boot_source_path, Method::Patch, &Chunk::from("{}"))
                 == Err(expected_err)
         );
 
+        // GET
+        match parse_boot_source_req(boot_source_path, Method::Get, &Chunk::from("")) {
+            Ok(pr) => assert!(pr.eq(&get_config_req(VmmAction::GetBootSource))),
+            _ => assert!(false),
+        }
+
         // Test case for invalid body (serde  error).
         assert!(
             parse_boot_source_req(boot_source_path, Method::Put, &Chunk::from("foo"))
@@ -861,6 +2188,29 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_vm_req() {
+        let vm_json = r#"{"machine-config": {"vcpu_count": 2}}"#;
+        let body: Chunk = Chunk::from(vm_json);
+
+        let microvm_config = serde_json::from_slice::<MicrovmConfig>(&body).unwrap();
+        match parse_vm_req("/vm", Method::Put, &body) {
+            Ok(pr) => {
+                let (sender, receiver) = oneshot::channel();
+                assert!(pr.eq(&ParsedRequest::Sync(
+                    VmmAction::ConfigureMicrovm(microvm_config, sender),
+                    receiver,
+                )));
+            }
+            _ => assert!(false),
+        }
+
+        // Error cases
+        assert!(parse_vm_req("/vm/foo", Method::Put, &body).is_err());
+        assert!(parse_vm_req("/vm", Method::Get, &body).is_err());
+        assert!(parse_vm_req("/vm", Method::Put, &Chunk::from(r#"{"foo": {}}"#)).is_err());
+    }
+
     #[test]
     fn test_parse_drives_req() {
         let valid_drive_path = "/drives/id_1";
@@ -908,6 +2258,22 @@ mod tests {
         // Test Case for invalid path (path does not contain the id).
         assert!(parse_drives_req("/foo", Method::Put, &body) == Err(Error::EmptyID));
 
+        // GET
+        match parse_drives_req("/drives", Method::Get, &Chunk::from("")) {
+            Ok(pr) => assert!(pr.eq(&get_config_req(|sender| {
+                VmmAction::GetBlockDevices(None, sender)
+            }))),
+            _ => assert!(false),
+        }
+        match parse_drives_req(valid_drive_path, Method::Get, &Chunk::from("")) {
+            Ok(pr) => assert!(pr.eq(&get_config_req(|sender| {
+                VmmAction::GetBlockDevices(Some(String::from("id_1")), sender)
+            }))),
+            _ => assert!(false),
+        }
+        let expected_err = Err(Error::InvalidPathMethod("/drives/id_1/foo", Method::Get));
+        assert!(parse_drives_req("/drives/id_1/foo", Method::Get, &body) == expected_err);
+
         // Test Case for invalid path (more than 2 tokens in path).
         let path = "/a/b/c";
         let expected_error = Err(Error::InvalidPathMethod(path, Method::Put));
@@ -1006,6 +2372,12 @@ mod tests {
         // Error Case: Invalid path.
         let expected_err = Err(Error::InvalidPathMethod("/foo/bar", Method::Put));
         assert!(parse_logger_req(&"/foo/bar", Method::Put, &Chunk::from("foo")) == expected_err);
+
+        // GET
+        match parse_logger_req(logger_path, Method::Get, &Chunk::from("")) {
+            Ok(pr) => assert!(pr.eq(&get_config_req(VmmAction::GetLogger))),
+            _ => assert!(false),
+        }
     }
 
     #[test]
@@ -1072,11 +2444,13 @@ mod tests {
         // PUT
         let netif = NetworkInterfaceConfig {
             iface_id: net_id.clone(),
-            host_dev_name: String::from("foo"),
+            host_dev_name: Some(String::from("foo")),
             guest_mac: Some(MacAddr::parse_str("12:34:56:78:9a:BC").unwrap()),
             rx_rate_limiter: None,
             tx_rate_limiter: None,
             allow_mmds_requests: false,
+            tx_filter: None,
+            user_net: None,
             tap: None,
         };
 
@@ -1088,6 +2462,51 @@ mod tests {
             _ => assert!(false),
         }
 
+        // GET
+        match parse_netif_req("/network-interfaces", Method::Get, &Chunk::from("")) {
+            Ok(pr) => assert!(pr.eq(&get_config_req(|sender| {
+                VmmAction::GetNetworkInterfaces(None, sender)
+            }))),
+            _ => assert!(false),
+        }
+        match parse_netif_req(&path, Method::Get, &Chunk::from("")) {
+            Ok(pr) => assert!(pr.eq(&get_config_req(|sender| {
+                VmmAction::GetNetworkInterfaces(Some(String::from("id_1")), sender)
+            }))),
+            _ => assert!(false),
+        }
+        assert!(
+            parse_netif_req("/network-interfaces", Method::Put, &body) == Err(Error::EmptyID)
+        );
+
+        // Requests on the MMDS data store dedicated to the interface.
+        let mmds_body = Chunk::from("{\"tenant\": \"id_1\"}");
+        match parse_netif_req("/network-interfaces/id_1/mmds", Method::Put, &mmds_body) {
+            Ok(pr) => assert!(pr.eq(&ParsedRequest::PutMMDS(
+                Some(String::from("id_1")),
+                serde_json::from_slice(&mmds_body).unwrap()
+            ))),
+            _ => assert!(false),
+        }
+        match parse_netif_req("/network-interfaces/id_1/mmds/tenant", Method::Get, &body) {
+            Ok(pr) => assert!(pr.eq(&ParsedRequest::GetMMDSSubtree(
+                Some(String::from("id_1")),
+                String::from("/tenant")
+            ))),
+            _ => assert!(false),
+        }
+        match parse_netif_req("/network-interfaces/id_1/mmds/guest", Method::Delete, &body) {
+            Ok(pr) => assert!(pr.eq(&ParsedRequest::DeleteMMDSGuestData(Some(String::from(
+                "id_1"
+            ))))),
+            _ => assert!(false),
+        }
+        let path = "/network-interfaces/id_1/mmds/tenant";
+        assert!(
+            parse_netif_req(path, Method::Put, &body)
+                == Err(Error::InvalidPathMethod(path, Method::Put))
+        );
+
         // Error cases
         // Error Case: The id from the path does not match the id from the body.
         let expected_err = Err(Error::Generic(
@@ -1119,7 +2538,7 @@ mod tests {
 
         // Test for GET request
         match parse_mmds_request(path, Method::Get, &body) {
-            Ok(parsed_req) => assert!(parsed_req.eq(&ParsedRequest::GetMMDS)),
+            Ok(parsed_req) => assert!(parsed_req.eq(&ParsedRequest::GetMMDS(None))),
             Err(_) => assert!(false),
         };
 
@@ -1136,6 +2555,7 @@ mod tests {
         let body = Chunk::from(dummy_json);
         match parse_mmds_request(path, Method::Put, &body) {
             Ok(parsed_req) => assert!(parsed_req.eq(&ParsedRequest::PutMMDS(
+                None,
                 serde_json::from_slice(&body).unwrap()
             ))),
             Err(_) => assert!(false),
@@ -1146,6 +2566,7 @@ mod tests {
         let body = Chunk::from(patch_json);
         match parse_mmds_request(path, Method::Patch, &body) {
             Ok(parsed_req) => assert!(parsed_req.eq(&ParsedRequest::PatchMMDS(
+                None,
                 serde_json::from_slice(&body).unwrap()
             ))),
             Err(_) => assert!(false),
@@ -1167,10 +2588,61 @@ mod tests {
                 == Err(Error::SerdeJson(get_dummy_serde_error()))
         );
 
+        // Test for GET requests on subtrees
+        let path = "/mmds/latest/meta-data/";
+        match parse_mmds_request(path, Method::Get, &body) {
+            Ok(parsed_req) => assert!(parsed_req.eq(&ParsedRequest::GetMMDSSubtree(
+                None,
+                String::from("/latest/meta-data")
+            ))),
+            Err(_) => assert!(false),
+        };
+
+        // Test for DELETE on the guest section
+        let path = "/mmds/guest";
+        match parse_mmds_request(path, Method::Delete, &body) {
+            Ok(parsed_req) => assert!(parsed_req.eq(&ParsedRequest::DeleteMMDSGuestData(None))),
+            Err(_) => assert!(false),
+        };
+        let path = "/mmds/latest";
+        let expected_err = Err(Error::InvalidPathMethod(path, Method::Delete));
+        assert!(parse_mmds_request(path, Method::Delete, &body) == expected_err);
+
         // Test for invalid path
         let path = "/mmds/something";
-        let expected_err = Err(Error::InvalidPathMethod(path, Method::Get));
-        assert!(parse_mmds_request(path, Method::Get, &body) == expected_err);
+        let expected_err = Err(Error::InvalidPathMethod(path, Method::Put));
+        assert!(parse_mmds_request(path, Method::Put, &body) == expected_err);
+
+        // Test for PUT on /mmds/config
+        let path = "/mmds/config";
+        let config_json = "{\
+                \"ipv4_address\": \"169.254.170.2\",\
+                \"tcp_port\": 8080,\
+                \"network_interfaces\": [\"eth0\"]\
+            }";
+        let body = Chunk::from(config_json);
+        let mmds_config = MmdsConfig {
+            ipv4_address: "169.254.170.2".parse().unwrap(),
+            tcp_port: 8080,
+            network_interfaces: Some(vec![String::from("eth0")]),
+            ..MmdsConfig::default()
+        };
+        match mmds_config.into_parsed_request(None, Method::Put) {
+            Ok(pr) => match parse_mmds_request(path, Method::Put, &body) {
+                Ok(pr_config) => assert!(pr.eq(&pr_config)),
+                _ => assert!(false),
+            },
+            _ => assert!(false),
+        }
+
+        // Test for unknown fields and methods on /mmds/config
+        let body = Chunk::from("{\"foo\": 1}");
+        assert!(
+            parse_mmds_request(path, Method::Put, &body)
+                == Err(Error::SerdeJson(get_dummy_serde_error()))
+        );
+        let expected_err = Err(Error::InvalidPathMethod(path, Method::Patch));
+        assert!(parse_mmds_request(path, Method::Patch, &body) == expected_err);
     }
 
     #[test]
@@ -1206,6 +2678,48 @@ mod tests {
             }
         }
 
+        // Test the events request.
+        match parse_request(Method::Get, "/events", &body) {
+            Ok(pr) => assert!(pr.eq(&ParsedRequest::GetEvents)),
+            _ => assert!(false),
+        }
+        for method in &all_methods {
+            assert!(parse_request(method.clone(), "/events", &body).is_err());
+        }
+        assert!(parse_request(Method::Get, "/events/foo", &body).is_err());
+
+        // Test the metrics request.
+        match parse_request(Method::Get, "/metrics", &body) {
+            Ok(pr) => assert!(pr.eq(&ParsedRequest::GetMetrics)),
+            _ => assert!(false),
+        }
+        for method in &all_methods {
+            assert!(parse_request(method.clone(), "/metrics", &body).is_err());
+        }
+        assert!(parse_request(Method::Get, "/metrics/foo", &body).is_err());
+
+        // Test the version request.
+        match parse_request(Method::Get, "/version", &body) {
+            Ok(pr) => assert!(pr.eq(&ParsedRequest::GetVersion)),
+            _ => assert!(false),
+        }
+        for method in &all_methods {
+            assert!(parse_request(method.clone(), "/version", &body).is_err());
+        }
+        assert!(parse_request(Method::Get, "/version/foo", &body).is_err());
+
+        // Test the requests carrying the API version prefix.
+        match parse_request(Method::Get, "/v1", &body) {
+            Ok(pr) => assert!(pr.eq(&ParsedRequest::GetInstanceInfo)),
+            _ => assert!(false),
+        }
+        match parse_request(Method::Get, "/v1/version", &body) {
+            Ok(pr) => assert!(pr.eq(&ParsedRequest::GetVersion)),
+            _ => assert!(false),
+        }
+        assert!(parse_request(Method::Put, "/v1/version", &body).is_err());
+        assert!(parse_request(Method::Get, "/v2/version", &body).is_err());
+
         // Test all valid requests
         // Each request type is unit tested separately
         for path in vec![
@@ -1235,5 +2749,15 @@ mod tests {
             msj,
             "synchronous Put request \"/foo/bar\" with body \"{ \\\"foo\\\": \\\"bar\\\" }\""
         );
+
+        // The MMDS token key doesn't end up in the log.
+        let body = String::from(r#"{ "token_mode": "required", "token_key": "0123456789abcdef" }"#);
+        let msj = describe(&Method::Put, &String::from("/mmds/config"), &body);
+        assert!(!msj.contains("0123456789abcdef"));
+        assert_eq!(
+            msj,
+            "synchronous Put request \"/mmds/config\" with body \
+             \"{\\\"token_key\\\":\\\"<redacted>\\\",\\\"token_mode\\\":\\\"required\\\"}\""
+        );
     }
 }
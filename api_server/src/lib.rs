@@ -4,6 +4,7 @@
 extern crate chrono;
 extern crate futures;
 extern crate hyper;
+extern crate libc;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
@@ -15,30 +16,45 @@ extern crate fc_util;
 #[macro_use]
 extern crate logger;
 extern crate mmds;
+extern crate seccomp;
 extern crate sys_util;
 extern crate vmm;
 
+pub mod access;
+pub mod audit;
 mod http_service;
 pub mod request;
 
+use std::cell::RefCell;
 use std::io;
 use std::os::unix::io::FromRawFd;
 use std::path::Path;
 use std::rc::Rc;
-use std::sync::mpsc;
+use std::sync::mpsc::SyncSender;
 use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
 
 use futures::{Future, Stream};
 use hyper::server::Http;
 use tokio_core::reactor::Core;
 use tokio_uds::UnixListener;
 
+use access::{AccessLevel, AccessPolicy};
+use audit::AuditLog;
 use http_service::ApiServerHttpService;
+pub use http_service::put_mmds_data;
 use logger::{Metric, METRICS};
-use mmds::data_store::Mmds;
-use sys_util::EventFd;
+use mmds::stores::MmdsStores;
+use sys_util::{get_peer_credentials, EventFd, PeerCredentials};
+use vmm::events::EventPublisher;
 use vmm::vmm_config::instance_info::InstanceInfo;
-use vmm::VmmAction;
+use vmm::VmmRequest;
+
+/// Maximum number of actions which can wait to be handled by the VMM. Further API requests are
+/// rejected until the VMM catches up.
+pub const VMM_ACTION_QUEUE_SIZE: usize = 32;
+/// Default time, in milliseconds, an API request waits for the VMM to handle it.
+pub const DEFAULT_VMM_ACTION_TIMEOUT_MS: u64 = 30000;
 
 #[derive(Debug)]
 pub enum Error {
@@ -54,26 +70,42 @@ pub enum UnixDomainSocket<P> {
 pub type Result<T> = std::result::Result<T, Error>;
 
 pub struct ApiServer {
-    // MMDS info directly accessible from the API thread.
-    mmds_info: Arc<Mutex<Mmds>>,
+    // MMDS data stores directly accessible from the API thread.
+    mmds_info: Arc<Mutex<MmdsStores>>,
     // VMM instance info directly accessible from the API thread.
     vmm_shared_info: Arc<RwLock<InstanceInfo>>,
+    // Lifecycle events of the microVM, to which the API clients can subscribe.
+    vmm_events: Arc<EventPublisher>,
+    // Decides which clients may use the API.
+    access_policy: AccessPolicy,
+    // Records the mutating requests, when enabled.
+    audit_log: Option<Rc<RefCell<AuditLog>>>,
     // Sender which allows passing messages to the VMM.
-    api_request_sender: Rc<mpsc::Sender<Box<VmmAction>>>,
+    api_request_sender: Rc<SyncSender<Box<VmmRequest>>>,
     efd: Rc<EventFd>,
+    // How long a request waits for the VMM to handle it, before giving up.
+    vmm_action_timeout: Duration,
 }
 
 impl ApiServer {
     pub fn new(
-        mmds_info: Arc<Mutex<Mmds>>,
+        mmds_info: Arc<Mutex<MmdsStores>>,
         vmm_shared_info: Arc<RwLock<InstanceInfo>>,
-        api_request_sender: mpsc::Sender<Box<VmmAction>>,
+        vmm_events: Arc<EventPublisher>,
+        access_policy: AccessPolicy,
+        audit_log: Option<AuditLog>,
+        api_request_sender: SyncSender<Box<VmmRequest>>,
+        vmm_action_timeout: Duration,
     ) -> Result<Self> {
         Ok(ApiServer {
             mmds_info,
             vmm_shared_info,
+            vmm_events,
+            access_policy,
+            audit_log: audit_log.map(|audit_log| Rc::new(RefCell::new(audit_log))),
             api_request_sender: Rc::new(api_request_sender),
             efd: Rc::new(EventFd::new().map_err(Error::Eventfd)?),
+            vmm_action_timeout,
         })
     }
 
@@ -120,13 +152,26 @@ impl ApiServer {
         let f = listener
             .incoming()
             .for_each(|(stream, _)| {
+                let client = match get_peer_credentials(&stream) {
+                    Ok(client) => Some(client),
+                    Err(e) => {
+                        error!("Failed to get the credentials of an API client: {:?}", e);
+                        None
+                    }
+                };
                 // For the sake of clarity: when we use self.efd.clone(), the intent is to
                 // clone the wrapping Rc, not the EventFd itself.
                 let service = ApiServerHttpService::new(
                     self.mmds_info.clone(),
                     self.vmm_shared_info.clone(),
+                    self.vmm_events.clone(),
                     self.api_request_sender.clone(),
                     self.efd.clone(),
+                    self.vmm_action_timeout,
+                    handle.clone(),
+                    self.access_level(client),
+                    client,
+                    self.audit_log.clone(),
                 );
                 let connection = http.serve_connection(stream, service);
                 // todo: is spawn() any better/worse than execute()?
@@ -145,4 +190,16 @@ impl ApiServer {
     pub fn get_event_fd_clone(&self) -> Result<EventFd> {
         self.efd.try_clone().map_err(Error::Eventfd)
     }
+
+    // Works out what `client` is allowed to do. Clients whose credentials can't be retrieved are
+    // turned away when the access to the API is restricted.
+    fn access_level(&self, client: Option<PeerCredentials>) -> AccessLevel {
+        if !self.access_policy.is_restricted() {
+            return AccessLevel::Full;
+        }
+        match client {
+            Some(client) => self.access_policy.access_level(client.uid, client.gid),
+            None => AccessLevel::Denied,
+        }
+    }
 }
@@ -0,0 +1,47 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::result;
+
+use futures::sync::oneshot;
+use hyper::Method;
+
+use request::{IntoParsedRequest, ParsedRequest};
+use vmm::vmm_config::microvm::MicrovmConfig;
+use vmm::VmmAction;
+
+impl IntoParsedRequest for MicrovmConfig {
+    fn into_parsed_request(
+        self,
+        _: Option<String>,
+        _: Method,
+    ) -> result::Result<ParsedRequest, String> {
+        let (sender, receiver) = oneshot::channel();
+        Ok(ParsedRequest::Sync(
+            VmmAction::ConfigureMicrovm(self, sender),
+            receiver,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json;
+
+    #[test]
+    fn test_into_parsed_request() {
+        let json = r#"{"machine-config": {"vcpu_count": 2}, "drives": []}"#;
+        let body: MicrovmConfig = serde_json::from_str(json).unwrap();
+        let same_body: MicrovmConfig = serde_json::from_str(json).unwrap();
+        let (sender, receiver) = oneshot::channel();
+        assert!(
+            body.into_parsed_request(None, Method::Put)
+                .eq(&Ok(ParsedRequest::Sync(
+                    VmmAction::ConfigureMicrovm(same_body, sender),
+                    receiver
+                )))
+        )
+    }
+}
@@ -0,0 +1,44 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::result;
+
+use futures::sync::oneshot;
+use hyper::Method;
+
+use request::{IntoParsedRequest, ParsedRequest};
+use vmm::vmm_config::mmds::MmdsConfig;
+use vmm::VmmAction;
+
+impl IntoParsedRequest for MmdsConfig {
+    fn into_parsed_request(
+        self,
+        _: Option<String>,
+        _: Method,
+    ) -> result::Result<ParsedRequest, String> {
+        let (sender, receiver) = oneshot::channel();
+        Ok(ParsedRequest::Sync(
+            VmmAction::SetMmdsConfiguration(self, sender),
+            receiver,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_into_parsed_request() {
+        let cfg = MmdsConfig::default();
+        let (sender, receiver) = oneshot::channel();
+        assert!(
+            cfg.clone()
+                .into_parsed_request(None, Method::Put)
+                .eq(&Ok(ParsedRequest::Sync(
+                    VmmAction::SetMmdsConfiguration(cfg, sender),
+                    receiver
+                )))
+        );
+    }
+}
@@ -6,6 +6,8 @@ pub mod boot_source;
 pub mod drive;
 pub mod logger;
 pub mod machine_configuration;
+pub mod microvm;
+pub mod mmds_config;
 pub mod net;
 #[cfg(feature = "vsock")]
 pub mod vsock;
@@ -16,14 +18,22 @@ use std::result;
 use hyper;
 use hyper::{Method, StatusCode};
 
-use http_service::{empty_response, json_fault_message, json_response};
+use http_service::{empty_response, json_fault_message, json_field_fault_message, json_response};
+use vmm::vmm_config::microvm::MicrovmConfigError;
 use vmm::{ErrorKind, OutcomeReceiver, VmmAction, VmmActionError, VmmData};
 
+// The MMDS requests carry the ID of the network interface whose dedicated data store they
+// target, or None for the default data store.
 pub enum ParsedRequest {
+    DeleteMMDSGuestData(Option<String>),
+    GetEvents,
     GetInstanceInfo,
-    GetMMDS,
-    PatchMMDS(Value),
-    PutMMDS(Value),
+    GetMetrics,
+    GetMMDS(Option<String>),
+    GetMMDSSubtree(Option<String>, String),
+    GetVersion,
+    PatchMMDS(Option<String>, Value),
+    PutMMDS(Option<String>, Value),
     Sync(VmmAction, OutcomeReceiver),
 }
 
@@ -56,6 +66,9 @@ impl GenerateHyperResponse for VmmData {
     fn generate_response(&self) -> hyper::Response {
         match *self {
             VmmData::MachineConfiguration(ref machine_config) => machine_config.generate_response(),
+            VmmData::ResourceConfig(ref config) => {
+                json_response(StatusCode::Ok, config.to_string())
+            }
             VmmData::Empty => empty_response(StatusCode::NoContent),
         }
     }
@@ -70,7 +83,19 @@ impl GenerateHyperResponse for VmmActionError {
             Internal => StatusCode::InternalServerError,
         };
 
-        json_response(status_code, json_fault_message(self.to_string()))
+        let body = match *self {
+            // The errors of the individual fields are reported separately as well.
+            VmmActionError::MicrovmConfig(_, MicrovmConfigError::InvalidFields(ref errors)) => {
+                json_field_fault_message(
+                    *self.get_kind(),
+                    self.get_code(),
+                    self.to_string(),
+                    errors,
+                )
+            }
+            _ => json_fault_message(*self.get_kind(), self.get_code(), self.to_string()),
+        };
+        json_response(status_code, body)
     }
 }
 
@@ -82,14 +107,29 @@ impl PartialEq for ParsedRequest {
                 &ParsedRequest::Sync(ref sync_req, _),
                 &ParsedRequest::Sync(ref other_sync_req, _),
             ) => sync_req == other_sync_req,
+            (
+                &ParsedRequest::DeleteMMDSGuestData(ref id),
+                &ParsedRequest::DeleteMMDSGuestData(ref other_id),
+            ) => id == other_id,
+            (&ParsedRequest::GetEvents, &ParsedRequest::GetEvents) => true,
             (&ParsedRequest::GetInstanceInfo, &ParsedRequest::GetInstanceInfo) => true,
-            (&ParsedRequest::GetMMDS, &ParsedRequest::GetMMDS) => true,
-            (&ParsedRequest::PutMMDS(ref val), &ParsedRequest::PutMMDS(ref other_val)) => {
-                val == other_val
-            }
-            (&ParsedRequest::PatchMMDS(ref val), &ParsedRequest::PatchMMDS(ref other_val)) => {
-                val == other_val
+            (&ParsedRequest::GetMetrics, &ParsedRequest::GetMetrics) => true,
+            (&ParsedRequest::GetVersion, &ParsedRequest::GetVersion) => true,
+            (&ParsedRequest::GetMMDS(ref id), &ParsedRequest::GetMMDS(ref other_id)) => {
+                id == other_id
             }
+            (
+                &ParsedRequest::GetMMDSSubtree(ref id, ref path),
+                &ParsedRequest::GetMMDSSubtree(ref other_id, ref other_path),
+            ) => id == other_id && path == other_path,
+            (
+                &ParsedRequest::PutMMDS(ref id, ref val),
+                &ParsedRequest::PutMMDS(ref other_id, ref other_val),
+            ) => id == other_id && val == other_val,
+            (
+                &ParsedRequest::PatchMMDS(ref id, ref val),
+                &ParsedRequest::PatchMMDS(ref other_id, ref other_val),
+            ) => id == other_id && val == other_val,
             _ => false,
         }
     }
@@ -114,6 +154,8 @@ mod tests {
     use vmm::vmm_config::instance_info::StartMicrovmError;
     use vmm::vmm_config::logger::LoggerConfigError;
     use vmm::vmm_config::machine_config::{VmConfig, VmConfigError};
+    use vmm::vmm_config::microvm::FieldError;
+    use vmm::vmm_config::mmds::MmdsConfigError;
     use vmm::vmm_config::net::NetworkInterfaceError;
 
     use futures::{Future, Stream};
@@ -135,9 +177,14 @@ mod tests {
     }
 
     fn check_error_response(error: VmmActionError, status_code: StatusCode) {
+        let kind = *error.get_kind();
+        let code = error.get_code();
         let hyper_resp = Err(error).generate_response();
         assert_eq!(hyper_resp.status(), status_code);
-        assert!(get_body(hyper_resp).is_ok());
+        let body = get_body(hyper_resp).unwrap();
+        assert_eq!(body["error_code"], Value::String(code.as_str().to_string()));
+        assert_eq!(body["error_kind"], serde_json::to_value(kind).unwrap());
+        assert!(body["fault_message"].is_string());
     }
 
     #[test]
@@ -166,6 +213,14 @@ mod tests {
         let vm_config_json: serde_json::Value = serde_json::from_str(vm_config_json).unwrap();
         assert_eq!(get_body(hyper_resp).unwrap(), vm_config_json);
 
+        // Test OK response from VMM that contains the configuration of a resource.
+        let config_json: serde_json::Value =
+            serde_json::from_str(r#"[{"drive_id": "rootfs", "is_read_only": true}]"#).unwrap();
+        let vmm_resp = Ok(VmmData::ResourceConfig(config_json.clone()));
+        let hyper_resp = vmm_resp.generate_response();
+        assert_eq!(hyper_resp.status(), StatusCode::Ok);
+        assert_eq!(get_body(hyper_resp).unwrap(), config_json);
+
         // Tests Error Cases
         // Tests for BootSource Errors.
         let vmm_resp =
@@ -225,6 +280,21 @@ mod tests {
             VmmActionError::MachineConfig(ErrorKind::User, VmConfigError::UpdateNotAllowedPostBoot);
         check_error_response(vmm_resp, StatusCode::BadRequest);
 
+        // Tests for MmdsConfig Errors.
+        let vmm_resp = VmmActionError::MmdsConfig(
+            ErrorKind::User,
+            MmdsConfigError::InvalidIpv4Addr("0.0.0.0".parse().unwrap()),
+        );
+        check_error_response(vmm_resp, StatusCode::BadRequest);
+        let vmm_resp = VmmActionError::MmdsConfig(ErrorKind::User, MmdsConfigError::InvalidTcpPort);
+        check_error_response(vmm_resp, StatusCode::BadRequest);
+        let vmm_resp =
+            VmmActionError::MmdsConfig(ErrorKind::User, MmdsConfigError::InvalidMaxConnections);
+        check_error_response(vmm_resp, StatusCode::BadRequest);
+        let vmm_resp =
+            VmmActionError::MmdsConfig(ErrorKind::User, MmdsConfigError::UpdateNotAllowedPostBoot);
+        check_error_response(vmm_resp, StatusCode::BadRequest);
+
         // Tests for NetworkConfig Errors.
         let vmm_resp = VmmActionError::NetworkConfig(
             ErrorKind::User,
@@ -248,6 +318,11 @@ mod tests {
             NetworkInterfaceError::HostDeviceNameInUse(String::from("tap_name")),
         );
         check_error_response(vmm_resp, StatusCode::BadRequest);
+        let vmm_resp = VmmActionError::NetworkConfig(
+            ErrorKind::User,
+            NetworkInterfaceError::InvalidBackend,
+        );
+        check_error_response(vmm_resp, StatusCode::BadRequest);
 
         // Tests for MicrovmStart Errors.
         // RegisterBlockDevice, RegisterNetDevice, and LegacyIOBus cannot be tested because the
@@ -285,6 +360,11 @@ mod tests {
             StartMicrovmError::NetDeviceNotConfigured,
         );
         check_error_response(vmm_resp, StatusCode::InternalServerError);
+        let vmm_resp = VmmActionError::StartMicrovm(
+            ErrorKind::User,
+            StartMicrovmError::MmdsNetworkInterfaceNotFound(String::from("eth0")),
+        );
+        check_error_response(vmm_resp, StatusCode::BadRequest);
         let vmm_resp = VmmActionError::StartMicrovm(
             ErrorKind::Internal,
             StartMicrovmError::CreateNetDevice(VirtioNetError::TapOpen(TapError::OpenTun(
@@ -318,5 +398,35 @@ mod tests {
             StartMicrovmError::VcpuSpawn(std::io::Error::from_raw_os_error(11)),
         );
         check_error_response(vmm_resp, StatusCode::InternalServerError);
+        // Test the errors of the whole microVM configuration.
+        let vmm_resp = VmmActionError::MicrovmConfig(
+            ErrorKind::User,
+            MicrovmConfigError::UpdateNotAllowedPostBoot,
+        );
+        check_error_response(vmm_resp, StatusCode::BadRequest);
+        let vmm_resp = VmmActionError::MicrovmConfig(
+            ErrorKind::User,
+            MicrovmConfigError::InvalidFields(vec![
+                FieldError::new(
+                    "machine-config",
+                    VmmActionError::MachineConfig(ErrorKind::User, VmConfigError::InvalidVcpuCount),
+                ),
+                FieldError::new(
+                    "drives[0]",
+                    VmmActionError::DriveConfig(
+                        ErrorKind::User,
+                        DriveError::InvalidBlockDevicePath,
+                    ),
+                ),
+            ]),
+        );
+        let hyper_resp = Err(vmm_resp).generate_response();
+        assert_eq!(hyper_resp.status(), StatusCode::BadRequest);
+        let body = get_body(hyper_resp).unwrap();
+        assert_eq!(body["error_code"], "microvm_config.invalid_fields");
+        assert_eq!(body["field_errors"][0]["field"], "machine-config");
+        assert_eq!(body["field_errors"][0]["error_code"], "machine_config.invalid_vcpu_count");
+        assert_eq!(body["field_errors"][1]["field"], "drives[0]");
+        assert_eq!(body["field_errors"][1]["error_kind"], "User");
     }
 }
@@ -36,12 +36,14 @@ mod tests {
     extern crate net_util;
     extern crate rate_limiter;
 
-    use self::net_util::MacAddr;
+    use self::net_util::{Ipv4Cidr, MacAddr};
     use super::*;
 
     use serde_json;
+    use std::net::Ipv4Addr;
 
     use self::rate_limiter::RateLimiter;
+    use vmm::vmm_config::net::TxFilterConfig;
 
     fn get_dummy_netif(
         iface_id: String,
@@ -50,11 +52,13 @@ mod tests {
     ) -> NetworkInterfaceConfig {
         NetworkInterfaceConfig {
             iface_id,
-            host_dev_name,
+            host_dev_name: Some(host_dev_name),
             guest_mac: Some(MacAddr::parse_str(mac).unwrap()),
             rx_rate_limiter: None,
             tx_rate_limiter: None,
             allow_mmds_requests: false,
+            tx_filter: None,
+            user_net: None,
             tap: None,
         }
     }
@@ -98,11 +102,18 @@ mod tests {
     fn test_network_interface_body_serialization_and_deserialization() {
         let netif = NetworkInterfaceConfig {
             iface_id: String::from("foo"),
-            host_dev_name: String::from("bar"),
+            host_dev_name: Some(String::from("bar")),
             guest_mac: Some(MacAddr::parse_str("12:34:56:78:9A:BC").unwrap()),
             rx_rate_limiter: Some(RateLimiter::default()),
             tx_rate_limiter: Some(RateLimiter::default()),
             allow_mmds_requests: true,
+            tx_filter: Some(TxFilterConfig {
+                allowed_source_ips: Some(vec!["10.0.0.2".parse().unwrap()]),
+                denied_ethertypes: vec![0x86dd],
+                denied_tcp_ports: vec![25],
+                denied_udp_ports: vec![],
+            }),
+            user_net: None,
             tap: None,
         };
 
@@ -115,7 +126,12 @@ mod tests {
             },
             "tx_rate_limiter": {
             },
-            "allow_mmds_requests": true
+            "allow_mmds_requests": true,
+            "tx_filter": {
+                "allowed_source_ips": ["10.0.0.2"],
+                "denied_ethertypes": [34525],
+                "denied_tcp_ports": [25]
+            }
         }"#;
 
         let x = serde_json::from_str(jstr).expect("deserialization failed.");
@@ -127,6 +143,65 @@ mod tests {
             "host_dev_name": "bar"
         }"#;
 
-        assert!(serde_json::from_str::<NetworkInterfaceConfig>(jstr_no_mac).is_ok())
+        assert!(serde_json::from_str::<NetworkInterfaceConfig>(jstr_no_mac).is_ok());
+
+        // The user-mode stack parameters have defaults, except for the DNS server.
+        let jstr_user_net = r#"{
+            "iface_id": "foo",
+            "user_net": {
+                "guest_addr": "10.0.2.16"
+            }
+        }"#;
+        let x = serde_json::from_str::<NetworkInterfaceConfig>(jstr_user_net)
+            .expect("deserialization failed.");
+        assert!(x.host_dev_name.is_none());
+        let user_net = x.user_net.unwrap();
+        assert_eq!(user_net.gateway_addr, "10.0.2.2".parse::<Ipv4Addr>().unwrap());
+        assert_eq!(user_net.guest_addr, "10.0.2.16".parse::<Ipv4Addr>().unwrap());
+        assert_eq!(user_net.netmask, "255.255.255.0".parse::<Ipv4Addr>().unwrap());
+        assert!(user_net.dns_addr.is_none());
+        // The host loopback interface is off limits, unless explicitly allowed.
+        assert!(!user_net.allow_host_loopback);
+        assert!(user_net.allowed_destinations.is_empty());
+        assert!(user_net.denied_destinations.is_empty());
+
+        let jstr_destinations = r#"{
+            "iface_id": "foo",
+            "user_net": {
+                "allowed_destinations": ["192.168.0.0/16"],
+                "denied_destinations": ["192.168.1.0/24", "1.1.1.1"]
+            }
+        }"#;
+        let user_net = serde_json::from_str::<NetworkInterfaceConfig>(jstr_destinations)
+            .expect("deserialization failed.")
+            .user_net
+            .unwrap();
+        assert_eq!(
+            user_net.allowed_destinations,
+            vec![Ipv4Cidr::parse_str("192.168.0.0/16").unwrap()]
+        );
+        assert_eq!(
+            user_net.denied_destinations,
+            vec![
+                Ipv4Cidr::parse_str("192.168.1.0/24").unwrap(),
+                Ipv4Cidr::parse_str("1.1.1.1/32").unwrap(),
+            ]
+        );
+
+        let jstr_bad_destinations = r#"{
+            "iface_id": "foo",
+            "user_net": {
+                "allowed_destinations": ["192.168.0.0/33"]
+            }
+        }"#;
+        assert!(serde_json::from_str::<NetworkInterfaceConfig>(jstr_bad_destinations).is_err());
+
+        let jstr_bad_user_net = r#"{
+            "iface_id": "foo",
+            "user_net": {
+                "foo": "bar"
+            }
+        }"#;
+        assert!(serde_json::from_str::<NetworkInterfaceConfig>(jstr_bad_user_net).is_err());
     }
 }
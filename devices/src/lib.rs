@@ -9,11 +9,13 @@
 extern crate byteorder;
 extern crate epoll;
 extern crate libc;
+extern crate timerfd;
 
 extern crate dumbo;
 #[macro_use]
 extern crate logger;
 extern crate memory_model;
+extern crate mmds;
 extern crate net_gen;
 extern crate net_util;
 extern crate rate_limiter;
@@ -25,6 +27,7 @@ extern crate vhost_gen;
 extern crate virtio_gen;
 
 use std::fs::File;
+use std::result;
 
 mod bus;
 pub mod legacy;
@@ -43,11 +46,27 @@ pub enum EpollHandlerPayload {
     Empty,
 }
 
+/// Errors triggered while handling device events.
+#[derive(Debug)]
+pub enum Error {
+    /// Failed to read the event which notified the device.
+    FailedReadingEvent {
+        event_type: &'static str,
+        underlying: sys_util::Error,
+    },
+    /// Failed to signal the guest that the used queue was updated.
+    FailedSignalingUsedQueue(sys_util::Error),
+    /// The rate limiter could not handle its timer event.
+    RateLimiter(rate_limiter::Error),
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
 pub trait EpollHandler: Send {
     fn handle_event(
         &mut self,
         device_event: DeviceEventT,
         event_flags: u32,
         payload: EpollHandlerPayload,
-    );
+    ) -> Result<()>;
 }
@@ -27,7 +27,7 @@ use sys_util::EventFd;
 use sys_util::Result as SysResult;
 use virtio_gen::virtio_blk::*;
 use virtio_gen::virtio_config::*;
-use {DeviceEventT, EpollHandler};
+use {DeviceEventT, EpollHandler, Error as DeviceError, Result as DeviceResult};
 
 const CONFIG_SPACE_SIZE: usize = 8;
 const SECTOR_SHIFT: u8 = 9;
@@ -324,13 +324,13 @@ impl BlockEpollHandler {
         used_count > 0
     }
 
-    fn signal_used_queue(&self) {
+    fn signal_used_queue(&self) -> DeviceResult<()> {
         self.interrupt_status
             .fetch_or(VIRTIO_MMIO_INT_VRING as usize, Ordering::SeqCst);
-        if let Err(e) = self.interrupt_evt.write(1) {
-            error!("Failed to signal used queue: {:?}", e);
+        self.interrupt_evt.write(1).map_err(|e| {
             METRICS.block.event_fails.inc();
-        }
+            DeviceError::FailedSignalingUsedQueue(e)
+        })
     }
 
     fn update_disk_image(&mut self, disk_image: File) {
@@ -341,23 +341,30 @@ impl BlockEpollHandler {
 }
 
 impl EpollHandler for BlockEpollHandler {
-    fn handle_event(&mut self, device_event: DeviceEventT, _: u32, payload: EpollHandlerPayload) {
+    fn handle_event(
+        &mut self,
+        device_event: DeviceEventT,
+        _: u32,
+        payload: EpollHandlerPayload,
+    ) -> DeviceResult<()> {
         match device_event {
             QUEUE_AVAIL_EVENT => {
                 METRICS.block.queue_event_count.inc();
                 if let Err(e) = self.queue_evt.read() {
-                    error!("Failed to get queue event: {:?}", e);
                     METRICS.block.event_fails.inc();
-                    return;
+                    return Err(DeviceError::FailedReadingEvent {
+                        event_type: "queue event",
+                        underlying: e,
+                    });
                 }
 
                 // While limiter is blocked, don't process any more requests.
                 if self.rate_limiter.is_blocked() {
-                    return;
+                    return Ok(());
                 }
 
                 if self.process_queue(0) {
-                    self.signal_used_queue();
+                    self.signal_used_queue()?;
                 }
             }
             RATE_LIMITER_EVENT => {
@@ -365,7 +372,7 @@ impl EpollHandler for BlockEpollHandler {
                 // Upon rate limiter event, call the rate limiter handler
                 // and restart processing the queue.
                 if self.rate_limiter.event_handler().is_ok() && self.process_queue(0) {
-                    self.signal_used_queue();
+                    self.signal_used_queue()?;
                 }
             }
             FS_UPDATE_EVENT => {
@@ -378,6 +385,7 @@ impl EpollHandler for BlockEpollHandler {
             }
             _ => panic!("Unknown event type was received."),
         }
+        Ok(())
     }
 }
 
@@ -746,7 +754,7 @@ mod tests {
         // trigger the queue event
         h.queue_evt.write(1).unwrap();
         // handle event
-        h.handle_event(QUEUE_AVAIL_EVENT, 0, EpollHandlerPayload::Empty);
+        assert!(h.handle_event(QUEUE_AVAIL_EVENT, 0, EpollHandlerPayload::Empty).is_ok());
         // validate the queue operation finished successfully
         assert_eq!(h.interrupt_evt.read(), Ok(2));
     }
@@ -1032,10 +1040,12 @@ mod tests {
         let m = GuestMemory::new(&[(GuestAddress(0), 0x10000)]).unwrap();
         let (mut h, _vq) = default_test_blockepollhandler(&m);
         // This should panic because the event is invalid.
-        h.handle_event(
-            BLOCK_EVENTS_COUNT as DeviceEventT,
-            0,
-            EpollHandlerPayload::Empty,
+        assert!(
+            h.handle_event(
+                BLOCK_EVENTS_COUNT as DeviceEventT,
+                0,
+                EpollHandlerPayload::Empty,
+            ).is_ok()
         );
     }
 
@@ -1045,7 +1055,7 @@ mod tests {
         let m = GuestMemory::new(&[(GuestAddress(0), 0x10000)]).unwrap();
         let (mut h, _vq) = default_test_blockepollhandler(&m);
         // This should panic because payload is empty for event type FS_UPDATE_EVENT.
-        h.handle_event(FS_UPDATE_EVENT, 0, EpollHandlerPayload::Empty);
+        assert!(h.handle_event(FS_UPDATE_EVENT, 0, EpollHandlerPayload::Empty).is_ok());
     }
 
     #[test]
@@ -1293,7 +1303,7 @@ mod tests {
                 h.interrupt_evt.write(1).unwrap();
                 // trigger the attempt to write
                 h.queue_evt.write(1).unwrap();
-                h.handle_event(QUEUE_AVAIL_EVENT, 0, EpollHandlerPayload::Empty);
+                assert!(h.handle_event(QUEUE_AVAIL_EVENT, 0, EpollHandlerPayload::Empty).is_ok());
 
                 // assert that limiter is blocked
                 assert!(h.get_rate_limiter().is_blocked());
@@ -1311,7 +1321,7 @@ mod tests {
             {
                 // leave at least one event here so that reading it later won't block
                 h.interrupt_evt.write(1).unwrap();
-                h.handle_event(RATE_LIMITER_EVENT, 0, EpollHandlerPayload::Empty);
+                assert!(h.handle_event(RATE_LIMITER_EVENT, 0, EpollHandlerPayload::Empty).is_ok());
                 // validate the rate_limiter is no longer blocked
                 assert!(!h.get_rate_limiter().is_blocked());
                 // make sure the virtio queue operation completed this time
@@ -1352,7 +1362,7 @@ mod tests {
                 h.interrupt_evt.write(1).unwrap();
                 // trigger the attempt to write
                 h.queue_evt.write(1).unwrap();
-                h.handle_event(QUEUE_AVAIL_EVENT, 0, EpollHandlerPayload::Empty);
+                assert!(h.handle_event(QUEUE_AVAIL_EVENT, 0, EpollHandlerPayload::Empty).is_ok());
 
                 // assert that limiter is blocked
                 assert!(h.get_rate_limiter().is_blocked());
@@ -1368,7 +1378,7 @@ mod tests {
                 h.interrupt_evt.write(1).unwrap();
                 // trigger the attempt to write
                 h.queue_evt.write(1).unwrap();
-                h.handle_event(QUEUE_AVAIL_EVENT, 0, EpollHandlerPayload::Empty);
+                assert!(h.handle_event(QUEUE_AVAIL_EVENT, 0, EpollHandlerPayload::Empty).is_ok());
 
                 // assert that limiter is blocked
                 assert!(h.get_rate_limiter().is_blocked());
@@ -1386,7 +1396,7 @@ mod tests {
             {
                 // leave at least one event here so that reading it later won't block
                 h.interrupt_evt.write(1).unwrap();
-                h.handle_event(RATE_LIMITER_EVENT, 0, EpollHandlerPayload::Empty);
+                assert!(h.handle_event(RATE_LIMITER_EVENT, 0, EpollHandlerPayload::Empty).is_ok());
                 // validate the rate_limiter is no longer blocked
                 assert!(!h.get_rate_limiter().is_blocked());
                 // make sure the virtio queue operation completed this time
@@ -1421,7 +1431,7 @@ mod tests {
                 .open(path)
                 .unwrap();
             let payload = EpollHandlerPayload::DrivePayload(file);
-            h.handle_event(FS_UPDATE_EVENT, 0, payload);
+            assert!(h.handle_event(FS_UPDATE_EVENT, 0, payload).is_ok());
 
             assert_eq!(h.disk_image.metadata().unwrap().st_ino(), mdata.st_ino());
             assert_eq!(h.disk_image_id, id);
@@ -14,6 +14,7 @@ pub mod block;
 mod mmio;
 pub mod net;
 mod queue;
+pub mod tx_filter;
 #[cfg(feature = "vsock")]
 pub mod vhost;
 
@@ -21,6 +22,7 @@ pub use self::block::*;
 pub use self::mmio::*;
 pub use self::net::*;
 pub use self::queue::*;
+pub use self::tx_filter::*;
 #[cfg(feature = "vsock")]
 pub use self::vhost::vsock::*;
 
@@ -50,6 +52,7 @@ pub enum ActivateError {
     EventFd(SysError),
     TryClone(SysError),
     EpollCtl(IOError),
+    TimerFd(IOError),
     BadActivate,
     #[cfg(feature = "vsock")]
     BadVhostActivate(self::vhost::Error),
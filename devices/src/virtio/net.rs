@@ -17,23 +17,27 @@ use std::os::unix::io::{AsRawFd, RawFd};
 use std::result;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use std::vec::Vec;
 
 use super::{
-    ActivateError, ActivateResult, EpollHandlerPayload, Queue, VirtioDevice, TYPE_NET,
-    VIRTIO_MMIO_INT_VRING,
+    ActivateError, ActivateResult, DropReason, EpollHandlerPayload, Queue, TxFilter,
+    VirtioDevice, TYPE_NET, VIRTIO_MMIO_INT_VRING,
 };
-use dumbo::ns::MmdsNetworkStack;
+use dumbo::ns::{MmdsNetworkStack, MmdsNetworkStackConfig};
+use dumbo::user_net::{UserNetworkParams, UserNetworkStack};
 use logger::{Metric, METRICS};
 use memory_model::{GuestAddress, GuestMemory};
+use mmds::data_store::Mmds;
 use net_gen;
 use net_util::{MacAddr, Tap, TapError, MAC_ADDR_LEN};
 use rate_limiter::{RateLimiter, TokenType};
 use sys_util::EventFd;
+use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
 use virtio_gen::virtio_config::*;
 use virtio_gen::virtio_net::*;
-use {DeviceEventT, EpollHandler};
+use {DeviceEventT, EpollHandler, Error as DeviceError, Result as DeviceResult};
 
 /// The maximum buffer size when segmentation offload is enabled. This
 /// includes the 12-byte virtio net header.
@@ -53,8 +57,12 @@ const TX_QUEUE_EVENT: DeviceEventT = 2;
 const RX_RATE_LIMITER_EVENT: DeviceEventT = 3;
 // tx rate limiter budget is now available.
 const TX_RATE_LIMITER_EVENT: DeviceEventT = 4;
+// The host updated the contents of the MMDS.
+const MMDS_UPDATE_EVENT: DeviceEventT = 5;
+// A guest request waiting for the MMDS contents to change timed out.
+const MMDS_TIMER_EVENT: DeviceEventT = 6;
 // Number of DeviceEventT events supported by this implementation.
-pub const NET_EVENTS_COUNT: usize = 5;
+pub const NET_EVENTS_COUNT: usize = 7;
 
 #[derive(Debug)]
 pub enum Error {
@@ -70,6 +78,8 @@ pub enum Error {
     TapSetVnetHdrSize(TapError),
     /// Enabling tap interface failed.
     TapEnable(TapError),
+    /// Creating the user-mode network stack failed.
+    UserNetStack(io::Error),
 }
 
 pub type Result<T> = result::Result<T, Error>;
@@ -144,9 +154,115 @@ fn init_vnet_hdr(buf: &mut [u8]) {
     }
 }
 
+// The host side of a network device. Frames are either exchanged with a tap device, or handled by
+// a user-mode network stack which proxies guest traffic over regular host sockets.
+enum NetBackend {
+    Tap(Tap),
+    User(Box<UserNetworkStack>),
+}
+
+impl NetBackend {
+    fn is_user(&self) -> bool {
+        match *self {
+            NetBackend::Tap(_) => false,
+            NetBackend::User(_) => true,
+        }
+    }
+
+    // Reads a frame (prefixed by the vnet header) into buf. Returns EAGAIN when there's nothing
+    // to read, just like a non-blocking tap device.
+    #[cfg(not(test))]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            NetBackend::Tap(ref mut tap) => tap.read(buf),
+            NetBackend::User(ref mut stack) => {
+                match stack.write_next_frame(frame_bytes_from_buf_mut(buf)) {
+                    Some(len) => {
+                        init_vnet_hdr(buf);
+                        Ok(vnet_hdr_len() + len.get())
+                    }
+                    None => Err(io::Error::from_raw_os_error(EAGAIN)),
+                }
+            }
+        }
+    }
+
+    // Writes a frame (prefixed by the vnet header) from buf.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            NetBackend::Tap(ref mut tap) => tap.write(buf),
+            NetBackend::User(ref mut stack) => {
+                stack.receive_frame(frame_bytes_from_buf(buf));
+                Ok(buf.len())
+            }
+        }
+    }
+}
+
+impl AsRawFd for NetBackend {
+    fn as_raw_fd(&self) -> RawFd {
+        match *self {
+            NetBackend::Tap(ref tap) => tap.as_raw_fd(),
+            NetBackend::User(ref stack) => stack.as_raw_fd(),
+        }
+    }
+}
+
+// Wakes up the MMDS network stack when the host updates the contents of the MMDS, and when a
+// guest request waiting for such an update times out. Either way, the stack may have responses
+// to send.
+struct MmdsWakeup {
+    update_evt: EventFd,
+    timer: TimerFd,
+    // The deadline the timer is currently armed for, if any.
+    timer_deadline: Option<Instant>,
+}
+
+impl MmdsWakeup {
+    // Makes update_evt readable whenever the host updates the contents of `mmds`.
+    fn new(mmds: &Mutex<Mmds>) -> result::Result<Self, ActivateError> {
+        let update_evt = EventFd::new().map_err(ActivateError::EventFd)?;
+        let listener_evt = update_evt.try_clone().map_err(ActivateError::TryClone)?;
+        let timer =
+            TimerFd::new_custom(ClockId::Monotonic, true, true).map_err(ActivateError::TimerFd)?;
+
+        mmds.lock()
+            .expect("Failed to access the MMDS due to poisoned lock")
+            .add_update_listener(Arc::new(move || {
+                if let Err(e) = listener_evt.write(1) {
+                    error!("Failed to signal MMDS update: {:?}", e);
+                    METRICS.net.event_fails.inc();
+                }
+            }));
+
+        Ok(MmdsWakeup {
+            update_evt,
+            timer,
+            timer_deadline: None,
+        })
+    }
+
+    // Makes sure the timer fires at the given deadline, or never when there's no deadline.
+    fn arm_timer(&mut self, deadline: Option<Instant>) {
+        if deadline == self.timer_deadline {
+            return;
+        }
+        let state = match deadline {
+            // A zero duration would disarm the timer, so we wait for at least one nanosecond.
+            Some(deadline) => TimerState::Oneshot(cmp::max(
+                deadline.saturating_duration_since(Instant::now()),
+                Duration::new(0, 1),
+            )),
+            None => TimerState::Disarmed,
+        };
+        self.timer.set_state(state, SetTimeFlags::Default);
+        self.timer_deadline = deadline;
+    }
+}
+
 struct NetEpollHandler {
     rx: RxVirtio,
-    tap: Tap,
+    backend: NetBackend,
     mem: GuestMemory,
     tx: TxVirtio,
     interrupt_status: Arc<AtomicUsize>,
@@ -156,6 +272,9 @@ struct NetEpollHandler {
     #[allow(dead_code)]
     acked_features: u64,
     mmds_ns: Option<MmdsNetworkStack>,
+    // Present along with mmds_ns, except for some unit tests.
+    mmds_wakeup: Option<MmdsWakeup>,
+    tx_filter: Option<TxFilter>,
 
     #[cfg(test)]
     test_mutators: tests::TestMutators,
@@ -272,15 +391,18 @@ impl NetEpollHandler {
         }
     }
 
-    // Tries to detour the frame to MMDS and if MMDS doesn't accept it, sends it on the host TAP.
+    // Tries to detour the frame to MMDS and if MMDS doesn't accept it, sends it to the backend
+    // (the host TAP or the user-mode network stack), unless the TX filter (when present) decides to
+    // drop it.
     //
     // `frame_buf` should contain the frame bytes in a slice of exact length.
     // Returns whether MMDS consumed the frame.
     fn write_to_mmds_or_tap(
         mmds_ns: Option<&mut MmdsNetworkStack>,
+        tx_filter: Option<&TxFilter>,
         rate_limiter: &mut RateLimiter,
         frame_buf: &[u8],
-        tap: &mut Tap,
+        backend: &mut NetBackend,
     ) -> bool {
         if let Some(ns) = mmds_ns {
             if ns.detour_frame(frame_bytes_from_buf(frame_buf)) {
@@ -294,8 +416,23 @@ impl NetEpollHandler {
                 return true;
             }
         }
-        // This frame goes to the TAP.
-        let write_result = tap.write(frame_buf);
+        // Frames consumed by the MMDS never reach the TAP, so we only filter the rest.
+        if let Some(filter) = tx_filter {
+            if let Err(reason) = filter.check(frame_bytes_from_buf(frame_buf)) {
+                match reason {
+                    DropReason::Denied => METRICS.net.tx_denied_count.inc(),
+                    DropReason::Malformed => METRICS.net.tx_malformed_count.inc(),
+                    DropReason::SpoofedIp => METRICS.net.tx_spoofed_ip_count.inc(),
+                    DropReason::SpoofedMac => METRICS.net.tx_spoofed_mac_count.inc(),
+                    DropReason::UnsupportedEthertype => {
+                        METRICS.net.tx_unsupported_ethertype_count.inc()
+                    }
+                }
+                return false;
+            }
+        }
+        // This frame goes to the TAP (or the user-mode stack).
+        let write_result = backend.write(frame_buf);
         match write_result {
             Ok(_) => {
                 METRICS.net.tx_bytes_count.add(frame_buf.len());
@@ -378,6 +515,9 @@ impl NetEpollHandler {
         // trigger a process_rx() which checks if there are any new frames to be sent, starting
         // with the MMDS network stack.
         let mut process_rx_for_mmds = false;
+        // The same goes for every frame handed over to the user-mode network stack, which may
+        // have to answer right away (with an ARP reply, or a TCP ACK, for example).
+        let user_net = self.backend.is_user();
 
         for avail_desc in self.tx.queue.iter(&self.mem) {
             // If limiter.consume() fails it means there is no more TokenType::Ops
@@ -446,12 +586,14 @@ impl NetEpollHandler {
                 }
             }
 
-            if Self::write_to_mmds_or_tap(
+            if (Self::write_to_mmds_or_tap(
                 self.mmds_ns.as_mut(),
+                self.tx_filter.as_ref(),
                 &mut self.tx.rate_limiter,
                 &mut self.tx.frame_buf[..read_count],
-                &mut self.tap,
-            ) && !self.rx.deferred_frame
+                &mut self.backend,
+            ) || user_net)
+                && !self.rx.deferred_frame
             {
                 // MMDS consumed this frame/request, let's also try to process the response.
                 process_rx_for_mmds = true;
@@ -476,27 +618,58 @@ impl NetEpollHandler {
             }
         }
 
+        // An incoming request for the MMDS may have to wait for the MMDS contents to change.
+        self.arm_mmds_timer();
+
         // An incoming frame for the MMDS may trigger the transmission of a new message.
         if process_rx_for_mmds {
             self.process_rx();
         }
     }
 
+    fn arm_mmds_timer(&mut self) {
+        if let (Some(ns), Some(wakeup)) = (self.mmds_ns.as_ref(), self.mmds_wakeup.as_mut()) {
+            wakeup.arm_timer(ns.next_long_poll_deadline());
+        }
+    }
+
+    // Lets the MMDS network stack answer the guest requests which are done waiting for the MMDS
+    // contents to change, and sends the responses to the guest.
+    fn process_mmds_wakeup(&mut self) {
+        if let Some(ns) = self.mmds_ns.as_mut() {
+            ns.check_long_polls();
+        }
+        self.arm_mmds_timer();
+
+        if !self.rx.rate_limiter.is_blocked() && !self.rx.deferred_frame {
+            self.process_rx();
+        }
+    }
+
     #[cfg(not(test))]
     fn read_tap(&mut self) -> io::Result<usize> {
-        self.tap.read(&mut self.rx.frame_buf)
+        self.backend.read(&mut self.rx.frame_buf)
     }
 }
 
 impl EpollHandler for NetEpollHandler {
-    fn handle_event(&mut self, device_event: DeviceEventT, _: u32, _: EpollHandlerPayload) {
+    fn handle_event(
+        &mut self,
+        device_event: DeviceEventT,
+        _: u32,
+        _: EpollHandlerPayload,
+    ) -> DeviceResult<()> {
+        // Errors hit while reading the event don't stop the event from being processed, since the
+        // queues may hold work regardless. They are reported once processing is done.
+        let mut outcome = Ok(());
+
         match device_event {
             RX_TAP_EVENT => {
                 METRICS.net.rx_tap_event_count.inc();
 
                 // While limiter is blocked, don't process any more incoming.
                 if self.rx.rate_limiter.is_blocked() {
-                    return;
+                    return Ok(());
                 }
                 // Process a deferred frame first if available. Don't read from tap again
                 // until we manage to receive this deferred frame.
@@ -508,7 +681,7 @@ impl EpollHandler for NetEpollHandler {
                             self.rx.deferred_irqs = false;
                             self.signal_used_queue();
                         }
-                        return;
+                        return Ok(());
                     }
                 }
                 self.process_rx();
@@ -516,9 +689,11 @@ impl EpollHandler for NetEpollHandler {
             RX_QUEUE_EVENT => {
                 METRICS.net.rx_queue_event_count.inc();
                 if let Err(e) = self.rx.queue_evt.read() {
-                    error!("Failed to get rx queue event: {:?}", e);
                     METRICS.net.event_fails.inc();
-                    // Shouldn't we return here?
+                    outcome = Err(DeviceError::FailedReadingEvent {
+                        event_type: "rx queue event",
+                        underlying: e,
+                    });
                 }
                 // If the limiter is not blocked, resume the receiving of bytes.
                 if !self.rx.rate_limiter.is_blocked() {
@@ -529,9 +704,11 @@ impl EpollHandler for NetEpollHandler {
             TX_QUEUE_EVENT => {
                 METRICS.net.tx_queue_event_count.inc();
                 if let Err(e) = self.tx.queue_evt.read() {
-                    error!("Failed to get tx queue event: {:?}", e);
-                    // Shouldn't we return here?
                     METRICS.net.event_fails.inc();
+                    outcome = Err(DeviceError::FailedReadingEvent {
+                        event_type: "tx queue event",
+                        underlying: e,
+                    });
                 }
                 // If the limiter is not blocked, continue transmitting bytes.
                 if !self.tx.rate_limiter.is_blocked() {
@@ -549,7 +726,7 @@ impl EpollHandler for NetEpollHandler {
                     }
                     Err(e) => {
                         METRICS.net.event_fails.inc();
-                        error!("Failed to get rx rate-limiter event: {:?}", e)
+                        outcome = Err(DeviceError::RateLimiter(e));
                     }
                 }
             }
@@ -564,12 +741,35 @@ impl EpollHandler for NetEpollHandler {
                     }
                     Err(e) => {
                         METRICS.net.event_fails.inc();
-                        error!("Failed to get tx rate-limiter event: {:?}", e)
+                        outcome = Err(DeviceError::RateLimiter(e));
                     }
                 }
             }
+            MMDS_UPDATE_EVENT => {
+                METRICS.net.mmds_event_count.inc();
+                if let Some(wakeup) = self.mmds_wakeup.as_ref() {
+                    if let Err(e) = wakeup.update_evt.read() {
+                        METRICS.net.event_fails.inc();
+                        outcome = Err(DeviceError::FailedReadingEvent {
+                            event_type: "MMDS update event",
+                            underlying: e,
+                        });
+                    }
+                }
+                self.process_mmds_wakeup();
+            }
+            MMDS_TIMER_EVENT => {
+                METRICS.net.mmds_event_count.inc();
+                if let Some(wakeup) = self.mmds_wakeup.as_mut() {
+                    wakeup.timer.read();
+                    // The timer is no longer armed.
+                    wakeup.timer_deadline = None;
+                }
+                self.process_mmds_wakeup();
+            }
             _ => panic!("Unknown event type was received."),
         }
+        outcome
     }
 }
 
@@ -579,6 +779,8 @@ pub struct EpollConfig {
     tx_queue_token: u64,
     rx_rate_limiter_token: u64,
     tx_rate_limiter_token: u64,
+    mmds_update_token: u64,
+    mmds_timer_token: u64,
     epoll_raw_fd: RawFd,
     sender: mpsc::Sender<Box<EpollHandler>>,
 }
@@ -595,6 +797,8 @@ impl EpollConfig {
             tx_queue_token: first_token + TX_QUEUE_EVENT as u64,
             rx_rate_limiter_token: first_token + RX_RATE_LIMITER_EVENT as u64,
             tx_rate_limiter_token: first_token + TX_RATE_LIMITER_EVENT as u64,
+            mmds_update_token: first_token + MMDS_UPDATE_EVENT as u64,
+            mmds_timer_token: first_token + MMDS_TIMER_EVENT as u64,
             epoll_raw_fd,
             sender,
         }
@@ -602,7 +806,7 @@ impl EpollConfig {
 }
 
 pub struct Net {
-    tap: Option<Tap>,
+    backend: Option<NetBackend>,
     avail_features: u64,
     acked_features: u64,
     // The config space will only consist of the MAC address specified by the user,
@@ -611,7 +815,10 @@ pub struct Net {
     epoll_config: EpollConfig,
     rx_rate_limiter: Option<RateLimiter>,
     tx_rate_limiter: Option<RateLimiter>,
-    allow_mmds_requests: bool,
+    // When present, guest frames addressed to the MMDS are detoured to an MMDS network stack
+    // built using this configuration, which serves the contents of the given data store.
+    mmds: Option<(MmdsNetworkStackConfig, Arc<Mutex<Mmds>>)>,
+    tx_filter: Option<TxFilter>,
 }
 
 impl Net {
@@ -622,7 +829,8 @@ impl Net {
         epoll_config: EpollConfig,
         rx_rate_limiter: Option<RateLimiter>,
         tx_rate_limiter: Option<RateLimiter>,
-        allow_mmds_requests: bool,
+        mmds: Option<(MmdsNetworkStackConfig, Arc<Mutex<Mmds>>)>,
+        tx_filter: Option<TxFilter>,
     ) -> Result<Self> {
         // Set offload flags to match the virtio features below.
         tap.set_offload(
@@ -633,7 +841,7 @@ impl Net {
         tap.set_vnet_hdr_size(vnet_hdr_size)
             .map_err(Error::TapSetVnetHdrSize)?;
 
-        let mut avail_features = 1 << VIRTIO_NET_F_GUEST_CSUM
+        let avail_features = 1 << VIRTIO_NET_F_GUEST_CSUM
             | 1 << VIRTIO_NET_F_CSUM
             | 1 << VIRTIO_NET_F_GUEST_TSO4
             | 1 << VIRTIO_NET_F_GUEST_UFO
@@ -641,6 +849,56 @@ impl Net {
             | 1 << VIRTIO_NET_F_HOST_UFO
             | 1 << VIRTIO_F_VERSION_1;
 
+        Ok(Self::new_with_backend(
+            NetBackend::Tap(tap),
+            avail_features,
+            guest_mac,
+            epoll_config,
+            rx_rate_limiter,
+            tx_rate_limiter,
+            mmds,
+            tx_filter,
+        ))
+    }
+
+    /// Create a new virtio network device which does not require a tap device on the host.
+    /// Guest traffic is handled by a user-mode network stack, emulating the subnet described by
+    /// `params`.
+    pub fn new_with_user_net(
+        params: UserNetworkParams,
+        guest_mac: Option<&MacAddr>,
+        epoll_config: EpollConfig,
+        rx_rate_limiter: Option<RateLimiter>,
+        tx_rate_limiter: Option<RateLimiter>,
+        mmds: Option<(MmdsNetworkStackConfig, Arc<Mutex<Mmds>>)>,
+        tx_filter: Option<TxFilter>,
+    ) -> Result<Self> {
+        let stack = UserNetworkStack::new(params).map_err(Error::UserNetStack)?;
+
+        // The user-mode stack neither produces, nor accepts partially checksummed or oversized
+        // (segmentation offload) frames, so we don't advertise any offload features.
+        Ok(Self::new_with_backend(
+            NetBackend::User(Box::new(stack)),
+            1 << VIRTIO_F_VERSION_1,
+            guest_mac,
+            epoll_config,
+            rx_rate_limiter,
+            tx_rate_limiter,
+            mmds,
+            tx_filter,
+        ))
+    }
+
+    fn new_with_backend(
+        backend: NetBackend,
+        mut avail_features: u64,
+        guest_mac: Option<&MacAddr>,
+        epoll_config: EpollConfig,
+        rx_rate_limiter: Option<RateLimiter>,
+        tx_rate_limiter: Option<RateLimiter>,
+        mmds: Option<(MmdsNetworkStackConfig, Arc<Mutex<Mmds>>)>,
+        tx_filter: Option<TxFilter>,
+    ) -> Self {
         let mut config_space;
         if let Some(mac) = guest_mac {
             config_space = Vec::with_capacity(MAC_ADDR_LEN);
@@ -654,16 +912,17 @@ impl Net {
             config_space = Vec::new();
         }
 
-        Ok(Net {
-            tap: Some(tap),
+        Net {
+            backend: Some(backend),
             avail_features,
             acked_features: 0u64,
             config_space,
             epoll_config,
             rx_rate_limiter,
             tx_rate_limiter,
-            allow_mmds_requests,
-        })
+            mmds,
+            tx_filter,
+        }
     }
 
     /// Create a new virtio network device with the given IP address and
@@ -675,7 +934,8 @@ impl Net {
         epoll_config: EpollConfig,
         rx_rate_limiter: Option<RateLimiter>,
         tx_rate_limiter: Option<RateLimiter>,
-        allow_mmds_requests: bool,
+        mmds: Option<(MmdsNetworkStackConfig, Arc<Mutex<Mmds>>)>,
+        tx_filter: Option<TxFilter>,
     ) -> Result<Self> {
         let tap = Tap::new().map_err(Error::TapOpen)?;
         tap.set_ip_addr(ip_addr).map_err(Error::TapSetIp)?;
@@ -688,7 +948,8 @@ impl Net {
             epoll_config,
             rx_rate_limiter,
             tx_rate_limiter,
-            allow_mmds_requests,
+            mmds,
+            tx_filter,
         )
     }
 }
@@ -778,22 +1039,31 @@ impl VirtioDevice for Net {
             return Err(ActivateError::BadActivate);
         }
 
-        if let Some(tap) = self.tap.take() {
+        if let Some(backend) = self.backend.take() {
             let rx_queue = queues.remove(0);
             let tx_queue = queues.remove(0);
             let rx_queue_evt = queue_evts.remove(0);
             let tx_queue_evt = queue_evts.remove(0);
-            let mut mmds_ns = None;
-            if self.allow_mmds_requests {
-                mmds_ns = Some(MmdsNetworkStack::new_with_defaults());
-            }
+            let (mmds_ns, mmds_wakeup) = match self.mmds.take() {
+                Some((config, mmds)) => {
+                    let mmds_wakeup = MmdsWakeup::new(&mmds).map_err(|e| {
+                        METRICS.net.activate_fails.inc();
+                        e
+                    })?;
+                    (
+                        Some(MmdsNetworkStack::new_with_config(config, mmds)),
+                        Some(mmds_wakeup),
+                    )
+                }
+                None => (None, None),
+            };
             let handler = NetEpollHandler {
                 rx: RxVirtio::new(
                     rx_queue,
                     rx_queue_evt,
                     self.rx_rate_limiter.take().unwrap_or_default(),
                 ),
-                tap,
+                backend,
                 mem,
                 tx: TxVirtio::new(
                     tx_queue,
@@ -804,18 +1074,25 @@ impl VirtioDevice for Net {
                 interrupt_evt,
                 acked_features: self.acked_features,
                 mmds_ns,
+                mmds_wakeup,
+                tx_filter: self.tx_filter.take(),
 
                 #[cfg(test)]
                 test_mutators: tests::TestMutators::default(),
             };
 
-            let tap_raw_fd = handler.tap.as_raw_fd();
+            let backend_raw_fd = handler.backend.as_raw_fd();
             let rx_queue_raw_fd = handler.rx.queue_evt.as_raw_fd();
             let tx_queue_raw_fd = handler.tx.queue_evt.as_raw_fd();
 
             let rx_rate_limiter_rawfd = handler.rx.rate_limiter.as_raw_fd();
             let tx_rate_limiter_rawfd = handler.tx.rate_limiter.as_raw_fd();
 
+            let mmds_raw_fds = handler
+                .mmds_wakeup
+                .as_ref()
+                .map(|wakeup| (wakeup.update_evt.as_raw_fd(), wakeup.timer.as_raw_fd()));
+
             //channel should be open and working
             self.epoll_config
                 .sender
@@ -827,7 +1104,7 @@ impl VirtioDevice for Net {
             epoll::ctl(
                 self.epoll_config.epoll_raw_fd,
                 epoll::EPOLL_CTL_ADD,
-                tap_raw_fd,
+                backend_raw_fd,
                 epoll::Event::new(epoll::EPOLLIN, self.epoll_config.rx_tap_token),
             ).map_err(|e| {
                 METRICS.net.activate_fails.inc();
@@ -872,6 +1149,21 @@ impl VirtioDevice for Net {
                 ).map_err(ActivateError::EpollCtl)?;
             }
 
+            if let Some((mmds_update_raw_fd, mmds_timer_raw_fd)) = mmds_raw_fds {
+                epoll::ctl(
+                    self.epoll_config.epoll_raw_fd,
+                    epoll::EPOLL_CTL_ADD,
+                    mmds_update_raw_fd,
+                    epoll::Event::new(epoll::EPOLLIN, self.epoll_config.mmds_update_token),
+                ).map_err(ActivateError::EpollCtl)?;
+                epoll::ctl(
+                    self.epoll_config.epoll_raw_fd,
+                    epoll::EPOLL_CTL_ADD,
+                    mmds_timer_raw_fd,
+                    epoll::Event::new(epoll::EPOLLIN, self.epoll_config.mmds_timer_token),
+                ).map_err(ActivateError::EpollCtl)?;
+            }
+
             return Ok(());
         }
         METRICS.net.activate_fails.inc();
@@ -954,7 +1246,11 @@ mod tests {
                             1000,
                         ).unwrap(),
                     ),
-                    true,
+                    Some((
+                        MmdsNetworkStackConfig::default(),
+                        Arc::new(Mutex::new(Mmds::default())),
+                    )),
+                    None,
                 ).unwrap(),
                 epoll_raw_fd,
                 _receiver,
@@ -1062,13 +1358,17 @@ mod tests {
         (
             NetEpollHandler {
                 rx: RxVirtio::new(rx_queue, rx_queue_evt, RateLimiter::default()),
-                tap: n.tap.take().unwrap(),
+                backend: n.backend.take().unwrap(),
                 mem: mem.clone(),
                 tx: TxVirtio::new(tx_queue, tx_queue_evt, RateLimiter::default()),
                 interrupt_status,
                 interrupt_evt,
                 acked_features: n.acked_features,
-                mmds_ns: Some(MmdsNetworkStack::new_with_defaults()),
+                mmds_ns: Some(MmdsNetworkStack::new_with_defaults(Arc::new(Mutex::new(
+                    Mmds::default(),
+                )))),
+                mmds_wakeup: None,
+                tx_filter: None,
                 test_mutators,
             },
             txq,
@@ -1237,7 +1537,8 @@ mod tests {
             epoll_config,
             None,
             None,
-            false,
+            None,
+            None,
         ) {
             Err(Error::TapSetIp(_)) => (),
             _ => assert!(false),
@@ -1257,7 +1558,8 @@ mod tests {
             epoll_config,
             None,
             None,
-            false,
+            None,
+            None,
         ) {
             Err(Error::TapSetNetmask(_)) => (),
             _ => assert!(false),
@@ -1311,9 +1613,10 @@ mod tests {
             1,
             assert!(NetEpollHandler::write_to_mmds_or_tap(
                 h.mmds_ns.as_mut(),
+                None,
                 &mut h.tx.rate_limiter,
                 &h.tx.frame_buf[..packet_len],
-                &mut h.tap,
+                &mut h.backend,
             ))
         );
 
@@ -1325,6 +1628,210 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_mmds_wakeup() {
+        extern crate serde_json;
+
+        let mem = GuestMemory::new(&[(GuestAddress(0), 0x10000)]).unwrap();
+        let (mut h, _txq, _rxq) = default_test_netepollhandler(&mem, TestMutators::default());
+
+        let mmds = Arc::new(Mutex::new(Mmds::default()));
+        h.mmds_ns = Some(MmdsNetworkStack::new_with_defaults(mmds.clone()));
+        h.mmds_wakeup = Some(MmdsWakeup::new(&mmds).unwrap());
+
+        // Updating the MMDS contents wakes up the handler.
+        mmds.lock()
+            .unwrap()
+            .put_data(serde_json::from_str(r#"{"key": "value"}"#).unwrap())
+            .unwrap();
+        check_metric_after_block!(
+            &METRICS.net.mmds_event_count,
+            1,
+            assert!(h.handle_event(MMDS_UPDATE_EVENT, 0, EpollHandlerPayload::Empty).is_ok())
+        );
+        // The update event was consumed.
+        assert!(h.mmds_wakeup.as_ref().unwrap().update_evt.write(1).is_ok());
+        assert_eq!(h.mmds_wakeup.as_ref().unwrap().update_evt.read().unwrap(), 1);
+
+        // No guest request is waiting, so the timer stays disarmed.
+        assert!(h.mmds_wakeup.as_ref().unwrap().timer_deadline.is_none());
+
+        let deadline = Instant::now() + Duration::from_millis(10);
+        h.mmds_wakeup.as_mut().unwrap().arm_timer(Some(deadline));
+        assert_eq!(
+            h.mmds_wakeup.as_ref().unwrap().timer_deadline,
+            Some(deadline)
+        );
+        thread::sleep(Duration::from_millis(20));
+        check_metric_after_block!(
+            &METRICS.net.mmds_event_count,
+            1,
+            assert!(h.handle_event(MMDS_TIMER_EVENT, 0, EpollHandlerPayload::Empty).is_ok())
+        );
+        assert!(h.mmds_wakeup.as_ref().unwrap().timer_deadline.is_none());
+    }
+
+    #[test]
+    fn test_tx_filter() {
+        let mem = GuestMemory::new(&[(GuestAddress(0), 0x10000)]).unwrap();
+        let (mut h, _txq, _rxq) = default_test_netepollhandler(&mem, TestMutators::default());
+
+        let guest_mac = MacAddr::parse_str("11:11:11:11:11:11").unwrap();
+        let spoofed_mac = MacAddr::parse_str("33:33:33:33:33:33").unwrap();
+        let spa = Ipv4Addr::new(10, 1, 2, 3);
+        let tha = MacAddr::parse_str("22:22:22:22:22:22").unwrap();
+        let tpa = Ipv4Addr::new(10, 1, 2, 4);
+
+        let filter = TxFilter::new(Some(guest_mac), Some(vec![spa]), vec![], vec![], vec![]);
+
+        let packet_len;
+        {
+            // An ARP request which pretends to come from another MAC address.
+            let eth_frame_i = ethernet::EthernetFrame::write_incomplete(
+                frame_bytes_from_buf_mut(&mut h.tx.frame_buf),
+                tha,
+                spoofed_mac,
+                ethernet::ETHERTYPE_ARP,
+            ).ok()
+            .unwrap();
+            let mut eth_frame_complete =
+                eth_frame_i.with_payload_len_unchecked(arp::ETH_IPV4_FRAME_LEN);
+            packet_len =
+                vnet_hdr_len() + eth_frame_complete.payload_offset() + arp::ETH_IPV4_FRAME_LEN;
+            assert!(
+                arp::EthIPv4ArpFrame::write_request(
+                    eth_frame_complete.payload_mut(),
+                    spoofed_mac,
+                    spa,
+                    tha,
+                    tpa,
+                ).is_ok()
+            );
+        }
+
+        // The frame is dropped, and does not reach the TAP.
+        check_metric_after_block!(
+            &METRICS.net.tx_spoofed_mac_count,
+            1,
+            assert!(!NetEpollHandler::write_to_mmds_or_tap(
+                h.mmds_ns.as_mut(),
+                Some(&filter),
+                &mut h.tx.rate_limiter,
+                &h.tx.frame_buf[..packet_len],
+                &mut h.backend,
+            ))
+        );
+
+        // Frames heading to the MMDS are not filtered.
+        {
+            let mut eth_frame =
+                ethernet::EthernetFrame::from_bytes_unchecked(frame_bytes_from_buf_mut(
+                    &mut h.tx.frame_buf[..packet_len],
+                ));
+            let mut arp_frame = arp::EthIPv4ArpFrame::from_bytes_unchecked(eth_frame.payload_mut());
+            arp_frame.set_tpa(Ipv4Addr::new(169, 254, 169, 254));
+        }
+        check_metric_after_block!(
+            &METRICS.mmds.rx_accepted,
+            1,
+            assert!(NetEpollHandler::write_to_mmds_or_tap(
+                h.mmds_ns.as_mut(),
+                Some(&filter),
+                &mut h.tx.rate_limiter,
+                &h.tx.frame_buf[..packet_len],
+                &mut h.backend,
+            ))
+        );
+    }
+
+    #[test]
+    fn test_user_net_backend() {
+        let epoll_raw_fd = epoll::create(true).unwrap();
+        let (sender, _receiver) = mpsc::channel();
+        let epoll_config = EpollConfig::new(0, epoll_raw_fd, sender);
+        let params = UserNetworkParams::default();
+        let gateway_addr = params.gateway_addr;
+        let guest_addr = params.guest_addr;
+
+        let guest_mac = MacAddr::parse_str("11:11:11:11:11:11").unwrap();
+        let mut n = Net::new_with_user_net(
+            params,
+            Some(&guest_mac),
+            epoll_config,
+            None,
+            None,
+            None,
+            None,
+        ).unwrap();
+
+        // No offload features are advertised by the user-mode backend.
+        assert_eq!(
+            n.avail_features,
+            1 << VIRTIO_F_VERSION_1 | 1 << VIRTIO_NET_F_MAC
+        );
+
+        let mut backend = n.backend.take().unwrap();
+        assert!(backend.is_user());
+
+        let mut frame_buf = [0u8; MAX_BUFFER_SIZE];
+        let mut rate_limiter = RateLimiter::default();
+        let packet_len;
+        {
+            // An ARP request for the gateway address.
+            let eth_frame_i = ethernet::EthernetFrame::write_incomplete(
+                frame_bytes_from_buf_mut(&mut frame_buf),
+                MacAddr::parse_str("ff:ff:ff:ff:ff:ff").unwrap(),
+                guest_mac,
+                ethernet::ETHERTYPE_ARP,
+            ).ok()
+            .unwrap();
+            let mut eth_frame_complete =
+                eth_frame_i.with_payload_len_unchecked(arp::ETH_IPV4_FRAME_LEN);
+            packet_len =
+                vnet_hdr_len() + eth_frame_complete.payload_offset() + arp::ETH_IPV4_FRAME_LEN;
+            assert!(
+                arp::EthIPv4ArpFrame::write_request(
+                    eth_frame_complete.payload_mut(),
+                    guest_mac,
+                    guest_addr,
+                    MacAddr::parse_str("00:00:00:00:00:00").unwrap(),
+                    gateway_addr,
+                ).is_ok()
+            );
+        }
+
+        // Frames always end up in the user-mode stack.
+        assert!(!NetEpollHandler::write_to_mmds_or_tap(
+            None,
+            None,
+            &mut rate_limiter,
+            &frame_buf[..packet_len],
+            &mut backend,
+        ));
+
+        // The stack has a reply ready for the guest.
+        let len = match backend {
+            NetBackend::User(ref mut stack) => stack
+                .write_next_frame(frame_bytes_from_buf_mut(&mut frame_buf))
+                .unwrap()
+                .get(),
+            _ => unreachable!(),
+        };
+        let eth_frame =
+            ethernet::EthernetFrame::from_bytes(&frame_bytes_from_buf(&frame_buf)[..len])
+                .ok()
+                .unwrap();
+        assert_eq!(eth_frame.ethertype(), ethernet::ETHERTYPE_ARP);
+        assert_eq!(eth_frame.dst_mac(), guest_mac);
+        // This is a reply, not a request.
+        assert!(arp::EthIPv4ArpFrame::request_from_bytes(eth_frame.payload()).is_err());
+        let arp_frame = arp::EthIPv4ArpFrame::from_bytes(eth_frame.payload())
+            .ok()
+            .unwrap();
+        assert_eq!(arp_frame.spa(), gateway_addr);
+        assert_eq!(arp_frame.tpa(), guest_addr);
+    }
+
     #[test]
     fn test_handler_error_cases() {
         let mem = GuestMemory::new(&[(GuestAddress(0), 0x10000)]).unwrap();
@@ -1335,7 +1842,7 @@ mod tests {
         check_metric_after_block!(
             &METRICS.net.event_fails,
             1,
-            h.handle_event(RX_RATE_LIMITER_EVENT, 0, EpollHandlerPayload::Empty)
+            assert!(h.handle_event(RX_RATE_LIMITER_EVENT, 0, EpollHandlerPayload::Empty).is_err())
         );
 
         // TX rate limiter events should error since the limiter is not blocked.
@@ -1343,7 +1850,7 @@ mod tests {
         check_metric_after_block!(
             &METRICS.net.event_fails,
             1,
-            h.handle_event(TX_RATE_LIMITER_EVENT, 0, EpollHandlerPayload::Empty)
+            assert!(h.handle_event(TX_RATE_LIMITER_EVENT, 0, EpollHandlerPayload::Empty).is_err())
         );
     }
 
@@ -1353,10 +1860,12 @@ mod tests {
         let mem = GuestMemory::new(&[(GuestAddress(0), 0x10000)]).unwrap();
         let (mut h, _txq, _rxq) = default_test_netepollhandler(&mem, TestMutators::default());
         // This should panic because the event is invalid.
-        h.handle_event(
-            NET_EVENTS_COUNT as DeviceEventT,
-            0,
-            EpollHandlerPayload::Empty,
+        assert!(
+            h.handle_event(
+                NET_EVENTS_COUNT as DeviceEventT,
+                0,
+                EpollHandlerPayload::Empty,
+            ).is_ok()
         );
     }
 
@@ -1421,7 +1930,7 @@ mod tests {
             txq.dtable[0].set(daddr, 0x1000, 0, 0);
 
             h.tx.queue_evt.write(1).unwrap();
-            h.handle_event(TX_QUEUE_EVENT, 0, EpollHandlerPayload::Empty);
+            assert!(h.handle_event(TX_QUEUE_EVENT, 0, EpollHandlerPayload::Empty).is_ok());
             // Make sure the data queue advanced.
             assert_eq!(txq.used.idx.get(), 1);
         }
@@ -1437,7 +1946,7 @@ mod tests {
             rxq.dtable[0].set(daddr, 0x1000, VIRTQ_DESC_F_WRITE, 0);
 
             h.interrupt_evt.write(1).unwrap();
-            h.handle_event(RX_TAP_EVENT, 0, EpollHandlerPayload::Empty);
+            assert!(h.handle_event(RX_TAP_EVENT, 0, EpollHandlerPayload::Empty).is_ok());
             assert!(h.rx.deferred_frame);
             assert_eq!(h.interrupt_evt.read(), Ok(2));
             // The #cfg(test) enabled version of read_tap always returns 1234 bytes (or the len of
@@ -1453,7 +1962,7 @@ mod tests {
 
             // this should also be successful
             h.interrupt_evt.write(1).unwrap();
-            h.handle_event(RX_TAP_EVENT, 0, EpollHandlerPayload::Empty);
+            assert!(h.handle_event(RX_TAP_EVENT, 0, EpollHandlerPayload::Empty).is_ok());
             assert!(h.rx.deferred_frame);
             assert_eq!(h.interrupt_evt.read(), Ok(2));
 
@@ -1468,7 +1977,7 @@ mod tests {
             check_metric_after_block!(
                 &METRICS.net.rx_fails,
                 1,
-                h.handle_event(RX_TAP_EVENT, 0, EpollHandlerPayload::Empty)
+                assert!(h.handle_event(RX_TAP_EVENT, 0, EpollHandlerPayload::Empty).is_ok())
             );
             assert!(h.rx.deferred_frame);
             assert_eq!(h.interrupt_evt.read(), Ok(2));
@@ -1488,7 +1997,7 @@ mod tests {
 
             h.rx.queue_evt.write(1).unwrap();
             h.interrupt_evt.write(1).unwrap();
-            h.handle_event(RX_QUEUE_EVENT, 0, EpollHandlerPayload::Empty);
+            assert!(h.handle_event(RX_QUEUE_EVENT, 0, EpollHandlerPayload::Empty).is_ok());
             assert_eq!(h.interrupt_evt.read(), Ok(2));
         }
 
@@ -1530,7 +2039,7 @@ mod tests {
             {
                 // trigger the TX handler
                 h.tx.queue_evt.write(1).unwrap();
-                h.handle_event(TX_QUEUE_EVENT, 0, EpollHandlerPayload::Empty);
+                assert!(h.handle_event(TX_QUEUE_EVENT, 0, EpollHandlerPayload::Empty).is_ok());
 
                 // assert that limiter is blocked
                 assert!(h.get_tx_rate_limiter().is_blocked());
@@ -1544,7 +2053,10 @@ mod tests {
 
             // following TX procedure should succeed because bandwidth should now be available
             {
-                h.handle_event(TX_RATE_LIMITER_EVENT, 0, EpollHandlerPayload::Empty);
+                assert!(
+                    h.handle_event(TX_RATE_LIMITER_EVENT, 0, EpollHandlerPayload::Empty)
+                        .is_ok()
+                );
                 // validate the rate_limiter is no longer blocked
                 assert!(!h.get_tx_rate_limiter().is_blocked());
                 // make sure the data queue advanced
@@ -1573,7 +2085,7 @@ mod tests {
                 // leave at least one event here so that reading it later won't block
                 h.interrupt_evt.write(1).unwrap();
                 // trigger the RX handler
-                h.handle_event(RX_TAP_EVENT, 0, EpollHandlerPayload::Empty);
+                assert!(h.handle_event(RX_TAP_EVENT, 0, EpollHandlerPayload::Empty).is_ok());
 
                 // assert that limiter is blocked
                 assert!(h.get_rx_rate_limiter().is_blocked());
@@ -1592,7 +2104,10 @@ mod tests {
             {
                 // leave at least one event here so that reading it later won't block
                 h.interrupt_evt.write(1).unwrap();
-                h.handle_event(RX_RATE_LIMITER_EVENT, 0, EpollHandlerPayload::Empty);
+                assert!(
+                    h.handle_event(RX_RATE_LIMITER_EVENT, 0, EpollHandlerPayload::Empty)
+                        .is_ok()
+                );
                 // validate the rate_limiter is no longer blocked
                 assert!(!h.get_rx_rate_limiter().is_blocked());
                 // make sure the virtio queue operation completed this time
@@ -1633,7 +2148,7 @@ mod tests {
             {
                 // trigger the TX handler
                 h.tx.queue_evt.write(1).unwrap();
-                h.handle_event(TX_QUEUE_EVENT, 0, EpollHandlerPayload::Empty);
+                assert!(h.handle_event(TX_QUEUE_EVENT, 0, EpollHandlerPayload::Empty).is_ok());
 
                 // assert that limiter is blocked
                 assert!(h.get_tx_rate_limiter().is_blocked());
@@ -1647,7 +2162,10 @@ mod tests {
 
             // following TX procedure should succeed because ops should now be available
             {
-                h.handle_event(TX_RATE_LIMITER_EVENT, 0, EpollHandlerPayload::Empty);
+                assert!(
+                    h.handle_event(TX_RATE_LIMITER_EVENT, 0, EpollHandlerPayload::Empty)
+                        .is_ok()
+                );
                 // validate the rate_limiter is no longer blocked
                 assert!(!h.get_tx_rate_limiter().is_blocked());
                 // make sure the data queue advanced
@@ -1676,7 +2194,7 @@ mod tests {
                 // leave at least one event here so that reading it later won't block
                 h.interrupt_evt.write(1).unwrap();
                 // trigger the RX handler
-                h.handle_event(RX_TAP_EVENT, 0, EpollHandlerPayload::Empty);
+                assert!(h.handle_event(RX_TAP_EVENT, 0, EpollHandlerPayload::Empty).is_ok());
 
                 // assert that limiter is blocked
                 assert!(h.get_rx_rate_limiter().is_blocked());
@@ -1689,7 +2207,7 @@ mod tests {
                 // leave at least one event here so that reading it later won't block
                 h.interrupt_evt.write(1).unwrap();
                 // trigger the RX handler again, this time it should do the limiter fast path exit
-                h.handle_event(RX_TAP_EVENT, 0, EpollHandlerPayload::Empty);
+                assert!(h.handle_event(RX_TAP_EVENT, 0, EpollHandlerPayload::Empty).is_ok());
                 // assert that no operation actually completed, that the limiter blocked it
                 assert_eq!(h.interrupt_evt.read(), Ok(1));
                 // make sure the data is still queued for processing
@@ -1704,7 +2222,10 @@ mod tests {
             {
                 // leave at least one event here so that reading it later won't block
                 h.interrupt_evt.write(1).unwrap();
-                h.handle_event(RX_RATE_LIMITER_EVENT, 0, EpollHandlerPayload::Empty);
+                assert!(
+                    h.handle_event(RX_RATE_LIMITER_EVENT, 0, EpollHandlerPayload::Empty)
+                        .is_ok()
+                );
                 // make sure the virtio queue operation completed this time
                 assert_eq!(h.interrupt_evt.read(), Ok(2));
                 // make sure the data queue advanced
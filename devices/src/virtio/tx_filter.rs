@@ -0,0 +1,342 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Implements the optional L2/L3 filtering applied to the frames transmitted by a guest via a
+//! virtio-net device, before they reach the associated TAP.
+
+use std::net::Ipv4Addr;
+
+use dumbo::pdu::arp::EthIPv4ArpFrame;
+use dumbo::pdu::ethernet::{EthernetFrame, ETHERTYPE_ARP, ETHERTYPE_IPV4};
+use dumbo::pdu::ipv4::{IPv4Packet, PROTOCOL_TCP, PROTOCOL_UDP};
+use dumbo::pdu::tcp::TcpSegment;
+use dumbo::pdu::udp::UdpDatagram;
+use net_util::MacAddr;
+
+/// Describes why a frame was rejected by a `TxFilter`.
+#[derive(Debug, PartialEq)]
+pub enum DropReason {
+    /// The frame matches one of the configured ethertype or port deny rules.
+    Denied,
+    /// The frame could not be parsed, so the filter cannot vouch for it.
+    Malformed,
+    /// The source IPv4 address (or ARP sender protocol address) is not allowed.
+    SpoofedIp,
+    /// The source MAC address (or ARP sender hardware address) differs from the guest MAC.
+    SpoofedMac,
+    /// Source addresses are restricted, but the ethertype of the frame is not one the filter can
+    /// check them for (such as IPv6, or 802.1Q tagged frames).
+    UnsupportedEthertype,
+}
+
+/// Filters the Ethernet frames sent by the guest on the TX path.
+///
+/// A frame is dropped when any of the following holds:
+/// * the guest MAC is known, and the source MAC of the frame is different;
+/// * an allow-list of source addresses is configured, and the frame is an IPv4 packet or an ARP
+///   frame whose sender address is not on the list, or the frame is neither of those;
+/// * the ethertype of the frame is denied;
+/// * the frame carries a TCP segment or UDP datagram heading to a denied destination port.
+#[derive(Debug, Default, PartialEq)]
+pub struct TxFilter {
+    guest_mac: Option<MacAddr>,
+    allowed_source_ips: Option<Vec<Ipv4Addr>>,
+    denied_ethertypes: Vec<u16>,
+    denied_tcp_ports: Vec<u16>,
+    denied_udp_ports: Vec<u16>,
+}
+
+impl TxFilter {
+    /// Creates a new filter. Source MAC checks are only performed when `guest_mac` is present,
+    /// and source IP checks only when `allowed_source_ips` is present.
+    pub fn new(
+        guest_mac: Option<MacAddr>,
+        allowed_source_ips: Option<Vec<Ipv4Addr>>,
+        denied_ethertypes: Vec<u16>,
+        denied_tcp_ports: Vec<u16>,
+        denied_udp_ports: Vec<u16>,
+    ) -> Self {
+        TxFilter {
+            guest_mac,
+            allowed_source_ips,
+            denied_ethertypes,
+            denied_tcp_ports,
+            denied_udp_ports,
+        }
+    }
+
+    /// Checks whether the L2 frame in `frame` (without the VNET header) is allowed to leave the
+    /// guest.
+    pub fn check(&self, frame: &[u8]) -> Result<(), DropReason> {
+        let eth = EthernetFrame::from_bytes(frame).map_err(|_| DropReason::Malformed)?;
+
+        if let Some(mac) = self.guest_mac {
+            if eth.src_mac() != mac {
+                return Err(DropReason::SpoofedMac);
+            }
+        }
+
+        let ethertype = eth.ethertype();
+        if self.denied_ethertypes.contains(&ethertype) {
+            return Err(DropReason::Denied);
+        }
+
+        match ethertype {
+            ETHERTYPE_ARP => self.check_arp(eth.payload()),
+            ETHERTYPE_IPV4 => self.check_ipv4(eth.payload()),
+            // The source address of other frames can't be vouched for, so they are only allowed
+            // through when it doesn't matter.
+            _ if self.allowed_source_ips.is_some() => Err(DropReason::UnsupportedEthertype),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_source_ip(&self, addr: Ipv4Addr) -> Result<(), DropReason> {
+        match self.allowed_source_ips {
+            Some(ref ips) if !ips.contains(&addr) => Err(DropReason::SpoofedIp),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_arp(&self, bytes: &[u8]) -> Result<(), DropReason> {
+        let arp = EthIPv4ArpFrame::from_bytes(bytes).map_err(|_| DropReason::Malformed)?;
+
+        if let Some(mac) = self.guest_mac {
+            if arp.sha() != mac {
+                return Err(DropReason::SpoofedMac);
+            }
+        }
+
+        // Gratuitous ARP probes use 0.0.0.0 as the sender address, and must not be dropped.
+        let spa = arp.spa();
+        if spa.is_unspecified() {
+            return Ok(());
+        }
+        self.check_source_ip(spa)
+    }
+
+    fn check_ipv4(&self, bytes: &[u8]) -> Result<(), DropReason> {
+        // The checksum is not verified, for the same reasons the MMDS network stack skips it.
+        let ip = IPv4Packet::from_bytes(bytes, false).map_err(|_| DropReason::Malformed)?;
+
+        self.check_source_ip(ip.source_address())?;
+
+        // Only the first fragment of a datagram carries the transport layer header.
+        if ip.flags_and_fragment_offset().1 != 0 {
+            return Ok(());
+        }
+
+        match ip.protocol() {
+            PROTOCOL_TCP if !self.denied_tcp_ports.is_empty() => {
+                let segment = TcpSegment::from_bytes(ip.payload(), None)
+                    .map_err(|_| DropReason::Malformed)?;
+                if self
+                    .denied_tcp_ports
+                    .contains(&segment.destination_port())
+                {
+                    return Err(DropReason::Denied);
+                }
+            }
+            PROTOCOL_UDP if !self.denied_udp_ports.is_empty() => {
+                let datagram =
+                    UdpDatagram::from_bytes(ip.payload(), None).map_err(|_| DropReason::Malformed)?;
+                if self
+                    .denied_udp_ports
+                    .contains(&datagram.destination_port())
+                {
+                    return Err(DropReason::Denied);
+                }
+            }
+            _ => (),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use dumbo::pdu::arp::ETH_IPV4_FRAME_LEN;
+    use dumbo::pdu::tcp::{Flags as TcpFlags, TcpOptions};
+
+    const GUEST_MAC: &str = "12:34:56:78:9a:bc";
+    const GUEST_IP: [u8; 4] = [10, 0, 0, 2];
+
+    fn guest_mac() -> MacAddr {
+        MacAddr::parse_str(GUEST_MAC).unwrap()
+    }
+
+    fn remote_mac() -> MacAddr {
+        MacAddr::parse_str("ff:ff:ff:ff:ff:ff").unwrap()
+    }
+
+    // Writes an Ethernet frame with the given source MAC and ethertype, and returns its length.
+    fn write_eth(buf: &mut [u8], src_mac: MacAddr, ethertype: u16, payload_len: usize) -> usize {
+        let eth = EthernetFrame::write_incomplete(buf, remote_mac(), src_mac, ethertype)
+            .ok()
+            .unwrap()
+            .with_payload_len_unchecked(payload_len);
+        eth.payload_offset() + payload_len
+    }
+
+    fn arp_frame(buf: &mut [u8], src_mac: MacAddr, sha: MacAddr, spa: Ipv4Addr) -> usize {
+        let len = write_eth(buf, src_mac, ETHERTYPE_ARP, ETH_IPV4_FRAME_LEN);
+        let mut eth = EthernetFrame::from_bytes_unchecked(&mut buf[..len]);
+        EthIPv4ArpFrame::write_request(
+            eth.payload_mut(),
+            sha,
+            spa,
+            remote_mac(),
+            Ipv4Addr::new(10, 0, 0, 1),
+        ).ok()
+        .unwrap();
+        len
+    }
+
+    fn ipv4_frame(
+        buf: &mut [u8],
+        src_addr: Ipv4Addr,
+        protocol: u8,
+        transport_header: &[u8],
+    ) -> usize {
+        // A 20 byte IPv4 header, followed by the transport layer header.
+        let ip_len = 20 + transport_header.len();
+        let len = write_eth(buf, guest_mac(), ETHERTYPE_IPV4, ip_len);
+        let mut eth = EthernetFrame::from_bytes_unchecked(&mut buf[..len]);
+        {
+            let payload = eth.payload_mut();
+            let mut ip = IPv4Packet::write_header(
+                payload,
+                protocol,
+                src_addr,
+                Ipv4Addr::new(10, 0, 0, 1),
+            ).ok()
+            .unwrap()
+            .with_payload_len_unchecked(transport_header.len(), true);
+            ip.payload_mut().copy_from_slice(transport_header);
+        }
+        len
+    }
+
+    fn tcp_header(dst_port: u16) -> [u8; 20] {
+        let mut buf = [0u8; 20];
+        TcpSegment::write_segment::<[u8]>(
+            buf.as_mut(),
+            1234,
+            dst_port,
+            0,
+            0,
+            TcpFlags::SYN,
+            0,
+            &TcpOptions::default(),
+            0,
+            None,
+            None,
+        ).ok()
+        .unwrap();
+        buf
+    }
+
+    fn udp_header(dst_port: u16) -> [u8; 8] {
+        let mut buf = [0u8; 8];
+        UdpDatagram::from_bytes_unchecked(buf.as_mut())
+            .set_destination_port(dst_port)
+            .set_len_field(8);
+        buf
+    }
+
+    #[test]
+    fn test_default_filter() {
+        let mut buf = [0u8; 100];
+        let f = TxFilter::default();
+
+        let len = arp_frame(&mut buf, remote_mac(), remote_mac(), Ipv4Addr::new(1, 2, 3, 4));
+        assert!(f.check(&buf[..len]).is_ok());
+
+        // Frames that don't even fit an Ethernet header are malformed.
+        assert_eq!(f.check(&buf[..10]), Err(DropReason::Malformed));
+    }
+
+    #[test]
+    fn test_mac_filtering() {
+        let mut buf = [0u8; 100];
+        let guest_ip = Ipv4Addr::from(GUEST_IP);
+        let f = TxFilter::new(Some(guest_mac()), None, vec![], vec![], vec![]);
+
+        let len = arp_frame(&mut buf, guest_mac(), guest_mac(), guest_ip);
+        assert!(f.check(&buf[..len]).is_ok());
+
+        // Spoofed Ethernet source.
+        let len = arp_frame(&mut buf, remote_mac(), guest_mac(), guest_ip);
+        assert_eq!(f.check(&buf[..len]), Err(DropReason::SpoofedMac));
+
+        // Spoofed ARP sender hardware address.
+        let len = arp_frame(&mut buf, guest_mac(), remote_mac(), guest_ip);
+        assert_eq!(f.check(&buf[..len]), Err(DropReason::SpoofedMac));
+
+        // Other ethertypes are only checked at L2.
+        let len = write_eth(&mut buf, guest_mac(), 0x86dd, 40);
+        assert!(f.check(&buf[..len]).is_ok());
+        let len = write_eth(&mut buf, remote_mac(), 0x86dd, 40);
+        assert_eq!(f.check(&buf[..len]), Err(DropReason::SpoofedMac));
+    }
+
+    #[test]
+    fn test_ip_filtering() {
+        let mut buf = [0u8; 100];
+        let guest_ip = Ipv4Addr::from(GUEST_IP);
+        let other_ip = Ipv4Addr::new(10, 0, 0, 3);
+        let f = TxFilter::new(Some(guest_mac()), Some(vec![guest_ip]), vec![], vec![], vec![]);
+
+        let len = ipv4_frame(&mut buf, guest_ip, PROTOCOL_TCP, &tcp_header(80));
+        assert!(f.check(&buf[..len]).is_ok());
+
+        let len = ipv4_frame(&mut buf, other_ip, PROTOCOL_TCP, &tcp_header(80));
+        assert_eq!(f.check(&buf[..len]), Err(DropReason::SpoofedIp));
+
+        let len = arp_frame(&mut buf, guest_mac(), guest_mac(), other_ip);
+        assert_eq!(f.check(&buf[..len]), Err(DropReason::SpoofedIp));
+
+        // ARP probes are fine.
+        let len = arp_frame(&mut buf, guest_mac(), guest_mac(), Ipv4Addr::new(0, 0, 0, 0));
+        assert!(f.check(&buf[..len]).is_ok());
+
+        // An IPv4 frame with a truncated header.
+        let len = write_eth(&mut buf, guest_mac(), ETHERTYPE_IPV4, 10);
+        assert_eq!(f.check(&buf[..len]), Err(DropReason::Malformed));
+
+        // IPv6 packets can't bypass the allow-list.
+        let len = write_eth(&mut buf, guest_mac(), 0x86dd, 40);
+        assert_eq!(f.check(&buf[..len]), Err(DropReason::UnsupportedEthertype));
+
+        // Neither can 802.1Q tagged frames, whatever they carry.
+        let len = write_eth(&mut buf, guest_mac(), 0x8100, 40);
+        assert_eq!(f.check(&buf[..len]), Err(DropReason::UnsupportedEthertype));
+    }
+
+    #[test]
+    fn test_deny_rules() {
+        let mut buf = [0u8; 100];
+        let guest_ip = Ipv4Addr::from(GUEST_IP);
+        let f = TxFilter::new(None, None, vec![0x86dd], vec![25], vec![53]);
+
+        let len = write_eth(&mut buf, guest_mac(), 0x86dd, 40);
+        assert_eq!(f.check(&buf[..len]), Err(DropReason::Denied));
+
+        let len = ipv4_frame(&mut buf, guest_ip, PROTOCOL_TCP, &tcp_header(25));
+        assert_eq!(f.check(&buf[..len]), Err(DropReason::Denied));
+        let len = ipv4_frame(&mut buf, guest_ip, PROTOCOL_TCP, &tcp_header(53));
+        assert!(f.check(&buf[..len]).is_ok());
+
+        let len = ipv4_frame(&mut buf, guest_ip, PROTOCOL_UDP, &udp_header(53));
+        assert_eq!(f.check(&buf[..len]), Err(DropReason::Denied));
+        let len = ipv4_frame(&mut buf, guest_ip, PROTOCOL_UDP, &udp_header(25));
+        assert!(f.check(&buf[..len]).is_ok());
+
+        // A truncated UDP header.
+        let len = ipv4_frame(&mut buf, guest_ip, PROTOCOL_UDP, &[0u8; 4]);
+        assert_eq!(f.check(&buf[..len]), Err(DropReason::Malformed));
+    }
+}
@@ -12,6 +12,7 @@ use sys_util::EventFd;
 use vhost_backend::Vhost;
 use DeviceEventT;
 use EpollHandler;
+use {Error as DeviceError, Result as DeviceResult};
 
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::sync::atomic::{AtomicUsize, Ordering};
@@ -70,18 +71,23 @@ impl<T: Vhost> EpollHandler for VhostEpollHandler<T>
 where
     T: std::marker::Send,
 {
-    fn handle_event(&mut self, device_event: DeviceEventT, _: u32, _: EpollHandlerPayload) {
+    fn handle_event(
+        &mut self,
+        device_event: DeviceEventT,
+        _: u32,
+        _: EpollHandlerPayload,
+    ) -> DeviceResult<()> {
         let mut needs_interrupt = false;
 
         match device_event {
             VHOST_IRQ_AVAILABLE => {
-                if let Err(e) = self.queue_evt.read() {
-                    error!("failed reading queue EventFd: {:?}", e);
-                    return;
-                }
+                self.queue_evt
+                    .read()
+                    .map_err(|e| DeviceError::FailedReadingEvent {
+                        event_type: "vhost irq",
+                        underlying: e,
+                    })?;
                 needs_interrupt = true;
-                // TODO dpopa@: after changing handle_event's signature to return Result, uncomment this
-                //self.queue_evt.read().map_err(Error::VhostIrqRead)?;
             }
             KILL_EVENT => {
                 //TODO: call API for device removal here
@@ -93,6 +99,7 @@ where
         if needs_interrupt {
             self.signal_used_queue();
         }
+        Ok(())
     }
 }
 
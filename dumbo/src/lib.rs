@@ -4,15 +4,20 @@
 #[macro_use]
 extern crate bitflags;
 extern crate byteorder;
+extern crate epoll;
+extern crate libc;
+extern crate timerfd;
 
 extern crate fc_util;
 extern crate logger;
+extern crate micro_http;
 extern crate mmds;
 extern crate net_util;
 
 pub mod ns;
 pub mod pdu;
 pub mod tcp;
+pub mod user_net;
 
 use std::ops::Index;
 
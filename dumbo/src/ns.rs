@@ -1,26 +1,58 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+use std::collections::{HashMap, VecDeque};
 use std::convert::From;
-use std::net::Ipv4Addr;
-use std::num::NonZeroUsize;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::num::{NonZeroU16, NonZeroU64, NonZeroUsize};
 use std::result::Result;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 use fc_util::timestamp_cycles;
 use logger::{Metric, METRICS};
+use mmds::data_store::Mmds;
 use net_util::MacAddr;
 use pdu::arp::{test_speculative_tpa, Error as ArpFrameError, EthIPv4ArpFrame, ETH_IPV4_FRAME_LEN};
-use pdu::ethernet::{Error as EthernetFrameError, EthernetFrame, ETHERTYPE_ARP, ETHERTYPE_IPV4};
-use pdu::ipv4::{test_speculative_dst_addr, Error as IPv4PacketError, IPv4Packet, PROTOCOL_TCP};
+use pdu::ethernet::{
+    Error as EthernetFrameError, EthernetFrame, ETHERTYPE_ARP, ETHERTYPE_IPV4, ETHERTYPE_IPV6,
+};
+use pdu::icmp::{Error as IcmpMessageError, IcmpEchoMessage, TYPE_ECHO_REPLY, TYPE_ECHO_REQUEST};
+use pdu::ipv4::{
+    test_speculative_dst_addr, Error as IPv4PacketError, IPv4Packet, PROTOCOL_ICMP, PROTOCOL_TCP,
+    PROTOCOL_UDP,
+};
+use pdu::ipv6::{
+    self, Error as IPv6PacketError, IPv6Packet, DEFAULT_HOP_LIMIT, NEXT_HEADER_ICMPV6,
+};
+use pdu::ndp::{
+    solicited_node_multicast_addr, Error as NeighborMessageError, NeighborMessage,
+    FLAG_OVERRIDE, FLAG_SOLICITED,
+};
 use pdu::tcp::Error as TcpSegmentError;
+use pdu::udp::{Error as UdpDatagramError, UdpDatagram};
 use tcp::handler::{self, RecvEvent, TcpIPv4Handler, WriteEvent};
 use tcp::NextSegmentStatus;
 
 const DEFAULT_MAC_ADDR: &str = "06:01:23:45:67:01";
-const DEFAULT_IPV4_ADDR: [u8; 4] = [169, 254, 169, 254];
-const DEFAULT_TCP_PORT: u16 = 80;
-const DEFAULT_MAX_CONNECTIONS: usize = 30;
+/// The IPv4 address of the MMDS, unless configured otherwise.
+pub const DEFAULT_IPV4_ADDR: [u8; 4] = [169, 254, 169, 254];
+/// The TCP port of the MMDS, unless configured otherwise.
+pub const DEFAULT_TCP_PORT: u16 = 80;
+/// The maximum number of concurrent guest TCP connections to the MMDS, unless configured
+/// otherwise.
+pub const DEFAULT_MAX_CONNECTIONS: usize = 30;
+/// The highest allowed number of concurrent guest TCP connections to the MMDS. Each connection
+/// holds buffers of up to roughly 90 KiB, so this bounds the memory used by the MMDS stack.
+pub const MAX_CONNECTIONS_LIMIT: usize = 1024;
+/// The TCP retransmission timeout of MMDS connections, expressed in CPU cycles, unless configured
+/// otherwise. Even on a fast 4GHz CPU, this is roughly 300 ms.
+pub const DEFAULT_CONNECTION_RTO_PERIOD: u64 = 1_200_000_000;
+/// How many times in a row the retransmission timer of an MMDS connection can fire before the
+/// connection is reset, unless configured otherwise.
+pub const DEFAULT_CONNECTION_RTO_COUNT_MAX: u16 = 15;
 const DEFAULT_MAX_PENDING_RESETS: usize = 100;
+const DEFAULT_MAX_PENDING_REPLIES: usize = 16;
 
 #[cfg_attr(test, derive(Debug, PartialEq))]
 enum WriteArpReplyError {
@@ -28,22 +60,92 @@ enum WriteArpReplyError {
     Ethernet(EthernetFrameError),
 }
 
+#[cfg_attr(test, derive(Debug, PartialEq))]
+enum WriteNdpReplyError {
+    Ethernet(EthernetFrameError),
+    IPv6Packet(IPv6PacketError),
+    NeighborMessage(NeighborMessageError),
+}
+
 #[cfg_attr(test, derive(Debug, PartialEq))]
 enum WritePacketError {
     IPv4Packet(IPv4PacketError),
+    IPv6Packet(IPv6PacketError),
     Ethernet(EthernetFrameError),
+    IcmpMessage(IcmpMessageError),
     TcpSegment(TcpSegmentError),
+    UdpDatagram(UdpDatagramError),
 }
 
 impl From<handler::WriteNextError> for WritePacketError {
     fn from(error: handler::WriteNextError) -> Self {
         match error {
             handler::WriteNextError::IPv4Packet(inner) => WritePacketError::IPv4Packet(inner),
+            handler::WriteNextError::IPv6Packet(inner) => WritePacketError::IPv6Packet(inner),
             handler::WriteNextError::TcpSegment(inner) => WritePacketError::TcpSegment(inner),
         }
     }
 }
 
+/// Implemented by services which answer UDP datagrams sent to the MMDS address (for example a
+/// DNS or NTP responder). Handlers are registered for a particular UDP port via
+/// `MmdsNetworkStack::set_udp_handler`.
+pub trait UdpHandler: Send {
+    /// Handles a datagram sent by `src_addr:src_port`. The returned bytes, if any, are sent back
+    /// to the same remote endpoint as the payload of a reply datagram.
+    fn handle_datagram(&mut self, src_addr: Ipv4Addr, src_port: u16, payload: &[u8])
+        -> Option<Vec<u8>>;
+}
+
+// A reply which does not belong to a TCP connection, and is waiting to be sent to the guest.
+enum PendingReply {
+    IcmpEcho {
+        dst_addr: Ipv4Addr,
+        identifier: u16,
+        sequence_number: u16,
+        payload: Vec<u8>,
+    },
+    Udp {
+        dst_addr: Ipv4Addr,
+        src_port: u16,
+        dst_port: u16,
+        payload: Vec<u8>,
+    },
+}
+
+/// The parameters used to build an `MmdsNetworkStack`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MmdsNetworkStackConfig {
+    /// The IPv4 address of the MMDS.
+    pub ipv4_addr: Ipv4Addr,
+    /// The link-local IPv6 address of the MMDS, if it should be reachable over IPv6. The MMDS
+    /// is IPv4 only unless configured otherwise.
+    pub ipv6_addr: Option<Ipv6Addr>,
+    /// The TCP port the MMDS listens on.
+    pub tcp_port: u16,
+    /// The maximum number of concurrent TCP connections.
+    pub max_connections: NonZeroUsize,
+    /// The TCP retransmission timeout, expressed in CPU cycles.
+    pub connection_rto_period: NonZeroU64,
+    /// How many times in a row the retransmission timer of a connection can fire before the
+    /// connection is reset.
+    pub connection_rto_count_max: NonZeroU16,
+}
+
+impl Default for MmdsNetworkStackConfig {
+    fn default() -> Self {
+        MmdsNetworkStackConfig {
+            ipv4_addr: Ipv4Addr::from(DEFAULT_IPV4_ADDR),
+            ipv6_addr: None,
+            tcp_port: DEFAULT_TCP_PORT,
+            // The unwraps are safe because the given literals are greater than 0.
+            max_connections: NonZeroUsize::new(DEFAULT_MAX_CONNECTIONS).unwrap(),
+            connection_rto_period: NonZeroU64::new(DEFAULT_CONNECTION_RTO_PERIOD).unwrap(),
+            connection_rto_count_max: NonZeroU16::new(DEFAULT_CONNECTION_RTO_COUNT_MAX).unwrap(),
+        }
+    }
+}
+
 pub struct MmdsNetworkStack {
     // The Ethernet MAC address of the MMDS server.
     mac_addr: MacAddr,
@@ -56,53 +158,98 @@ pub struct MmdsNetworkStack {
     // here (we keep the remote MAC address in self.remote_mac_addr), to be used when the next
     // opportunity to send a frame presents itself.
     pending_arp_reply: Option<Ipv4Addr>,
+    // The link-local IPv6 address of the MMDS server, if the MMDS should be reachable over IPv6.
+    ipv6_addr: Option<Ipv6Addr>,
+    // Similar to pending_arp_reply, we only remember the source IPv6 address of the most recently
+    // received neighbor solicitation which targets the MMDS.
+    pending_ndp_reply: Option<Ipv6Addr>,
     // This handles MMDS<->guest interaction at the TCP level.
     tcp_handler: TcpIPv4Handler,
+    // UDP handlers, indexed by the local port they serve.
+    udp_handlers: HashMap<u16, Box<UdpHandler>>,
+    // ICMP echo replies and UDP replies waiting to be sent, in the order they were generated.
+    pending_replies: VecDeque<PendingReply>,
 }
 
 impl MmdsNetworkStack {
     pub fn new(
         mac_addr: MacAddr,
         ipv4_addr: Ipv4Addr,
+        ipv6_addr: Option<Ipv6Addr>,
         tcp_port: u16,
         max_connections: NonZeroUsize,
         max_pending_resets: NonZeroUsize,
+        connection_rto_period: NonZeroU64,
+        connection_rto_count_max: NonZeroU16,
+        mmds: Arc<Mutex<Mmds>>,
     ) -> Self {
         MmdsNetworkStack {
             mac_addr,
             remote_mac_addr: mac_addr,
             ipv4_addr,
             pending_arp_reply: None,
+            ipv6_addr,
+            pending_ndp_reply: None,
             tcp_handler: TcpIPv4Handler::new(
                 ipv4_addr,
+                ipv6_addr,
                 tcp_port,
                 max_connections,
                 max_pending_resets,
+                connection_rto_period,
+                connection_rto_count_max,
+                mmds,
             ),
+            udp_handlers: HashMap::new(),
+            pending_replies: VecDeque::with_capacity(DEFAULT_MAX_PENDING_REPLIES),
         }
     }
 
-    pub fn new_with_defaults() -> Self {
+    pub fn new_with_config(config: MmdsNetworkStackConfig, mmds: Arc<Mutex<Mmds>>) -> Self {
         // The unwrap is safe if parse_str() is implemented properly.
         let mac_addr = MacAddr::parse_str(DEFAULT_MAC_ADDR).unwrap();
-        let ipv4_addr = Ipv4Addr::from(DEFAULT_IPV4_ADDR);
 
-        // The unwrap()s are safe because the given literals are greater than 0.
+        // The unwrap() is safe because the given literal is greater than 0.
         Self::new(
             mac_addr,
-            ipv4_addr,
-            DEFAULT_TCP_PORT,
-            NonZeroUsize::new(DEFAULT_MAX_CONNECTIONS).unwrap(),
+            config.ipv4_addr,
+            config.ipv6_addr,
+            config.tcp_port,
+            config.max_connections,
             NonZeroUsize::new(DEFAULT_MAX_PENDING_RESETS).unwrap(),
+            config.connection_rto_period,
+            config.connection_rto_count_max,
+            mmds,
         )
     }
 
+    pub fn new_with_defaults(mmds: Arc<Mutex<Mmds>>) -> Self {
+        Self::new_with_config(MmdsNetworkStackConfig::default(), mmds)
+    }
+
+    /// Registers `handler` to serve the UDP datagrams sent to the MMDS address on the given
+    /// `port`. Returns the handler previously registered for the same port, if any.
+    pub fn set_udp_handler(
+        &mut self,
+        port: u16,
+        handler: Box<UdpHandler>,
+    ) -> Option<Box<UdpHandler>> {
+        self.udp_handlers.insert(port, handler)
+    }
+
+    /// Removes the UDP handler registered for `port`, if any.
+    pub fn remove_udp_handler(&mut self, port: u16) -> Option<Box<UdpHandler>> {
+        self.udp_handlers.remove(&port)
+    }
+
     // This is the entry point into the MMDS network stack. The src slice should hold the contents
     // of an Ethernet frame (of that exact size, without the CRC).
     pub fn detour_frame(&mut self, src: &[u8]) -> bool {
-        // The frame cannot possibly contain an ARP request or IPv4 packet for the MMDS.
+        // The frame cannot possibly contain an ARP request, an IPv4 packet, or an IPv6 packet for
+        // the MMDS.
         if !test_speculative_tpa(src, self.ipv4_addr)
             && !test_speculative_dst_addr(src, self.ipv4_addr)
+            && !self.test_speculative_ipv6_dst_addr(src)
         {
             return false;
         }
@@ -111,6 +258,7 @@ impl MmdsNetworkStack {
             match eth.ethertype() {
                 ETHERTYPE_ARP => return self.detour_arp(eth),
                 ETHERTYPE_IPV4 => return self.detour_ipv4(eth),
+                ETHERTYPE_IPV6 => return self.detour_ipv6(eth),
                 _ => (),
             };
         } else {
@@ -119,6 +267,18 @@ impl MmdsNetworkStack {
         return false;
     }
 
+    // Neighbor solicitations are sent to the solicited-node multicast address associated with the
+    // MMDS IPv6 address, so we have to look out for both.
+    fn test_speculative_ipv6_dst_addr(&self, src: &[u8]) -> bool {
+        match self.ipv6_addr {
+            Some(addr) => {
+                ipv6::test_speculative_dst_addr(src, addr)
+                    || ipv6::test_speculative_dst_addr(src, solicited_node_multicast_addr(addr))
+            }
+            None => false,
+        }
+    }
+
     fn detour_arp(&mut self, eth: EthernetFrame<&[u8]>) -> bool {
         if let Ok(arp) = EthIPv4ArpFrame::request_from_bytes(eth.payload()) {
             if arp.tpa() == self.ipv4_addr {
@@ -136,9 +296,9 @@ impl MmdsNetworkStack {
         // context at some point!
         if let Ok(ip) = IPv4Packet::from_bytes(eth.payload(), false) {
             if ip.destination_address() == self.ipv4_addr {
-                if ip.protocol() == PROTOCOL_TCP {
-                    self.remote_mac_addr = eth.src_mac();
-                    match self.tcp_handler.receive_packet(&ip) {
+                self.remote_mac_addr = eth.src_mac();
+                match ip.protocol() {
+                    PROTOCOL_TCP => match self.tcp_handler.receive_packet(&ip) {
                         Ok(event) => match event {
                             RecvEvent::NewConnectionSuccessful => {
                                 METRICS.mmds.connections_created.inc()
@@ -150,17 +310,170 @@ impl MmdsNetworkStack {
                             _ => (),
                         },
                         Err(_) => METRICS.mmds.rx_accepted_err.inc(),
+                    },
+                    PROTOCOL_ICMP => self.detour_icmp(&ip),
+                    PROTOCOL_UDP => self.detour_udp(&ip),
+                    // Some other IPv4 packet heading towards the MMDS; we consider it unusual.
+                    _ => METRICS.mmds.rx_accepted_unusual.inc(),
+                }
+                return true;
+            }
+        }
+        false
+    }
+
+    fn detour_ipv6(&mut self, eth: EthernetFrame<&[u8]>) -> bool {
+        let local_addr = match self.ipv6_addr {
+            Some(addr) => addr,
+            None => return false,
+        };
+
+        if let Ok(ip) = IPv6Packet::from_bytes(eth.payload()) {
+            let dst_addr = ip.destination_address();
+            if dst_addr == local_addr {
+                self.remote_mac_addr = eth.src_mac();
+                match ip.next_header() {
+                    PROTOCOL_TCP => match self.tcp_handler.receive_ipv6_packet(&ip) {
+                        Ok(event) => match event {
+                            RecvEvent::NewConnectionSuccessful => {
+                                METRICS.mmds.connections_created.inc()
+                            }
+                            RecvEvent::NewConnectionReplacing => {
+                                METRICS.mmds.connections_created.inc();
+                                METRICS.mmds.connections_destroyed.inc();
+                            }
+                            _ => (),
+                        },
+                        Err(_) => METRICS.mmds.rx_accepted_err.inc(),
+                    },
+                    NEXT_HEADER_ICMPV6 => {
+                        self.detour_ndp(&eth, &ip, local_addr);
                     }
-                } else {
-                    // A non-TCP IPv4 packet heading towards the MMDS; we consider it unusual.
-                    METRICS.mmds.rx_accepted_unusual.inc();
+                    _ => METRICS.mmds.rx_accepted_unusual.inc(),
                 }
                 return true;
+            } else if dst_addr == solicited_node_multicast_addr(local_addr)
+                && ip.next_header() == NEXT_HEADER_ICMPV6
+            {
+                // Other hosts might share the same solicited-node multicast address, so we only
+                // claim the frame when the solicitation is actually meant for us.
+                return self.detour_ndp(&eth, &ip, local_addr);
             }
         }
         false
     }
 
+    // Returns true if `ip` holds a neighbor solicitation which targets the MMDS IPv6 address.
+    fn detour_ndp(
+        &mut self,
+        eth: &EthernetFrame<&[u8]>,
+        ip: &IPv6Packet<&[u8]>,
+        local_addr: Ipv6Addr,
+    ) -> bool {
+        // RFC 4861 requires NDP messages to be dropped unless the hop limit is 255, which proves
+        // they have not been forwarded by a router. The ICMPv6 checksum is always verified.
+        let src_addr = ip.source_address();
+        let maybe_remote_mac = match NeighborMessage::solicitation_from_bytes(
+            ip.payload(),
+            Some((src_addr, ip.destination_address())),
+        ) {
+            Ok(ref msg) if ip.hop_limit() == DEFAULT_HOP_LIMIT && msg.target_address() == local_addr => {
+                msg.link_layer_address_option()
+            }
+            _ => {
+                if ip.destination_address() == local_addr {
+                    METRICS.mmds.rx_accepted_unusual.inc();
+                }
+                return false;
+            }
+        };
+
+        // Solicitations sent from the unspecified address are part of duplicate address detection,
+        // and would have to be answered via multicast. The MMDS address is not supposed to be used
+        // by anybody else, so we simply ignore them.
+        if !src_addr.is_unspecified() {
+            self.remote_mac_addr = maybe_remote_mac.unwrap_or_else(|| eth.src_mac());
+            self.pending_ndp_reply = Some(src_addr);
+        }
+        true
+    }
+
+    fn detour_icmp(&mut self, ip: &IPv4Packet<&[u8]>) {
+        // Unlike TCP and UDP, the ICMP checksum is not subject to offloading, so we verify it.
+        match IcmpEchoMessage::from_bytes(ip.payload(), true) {
+            Ok(ref echo) if echo.message_type() == TYPE_ECHO_REQUEST => {
+                METRICS.mmds.icmp_echo_requests.inc();
+                let reply = PendingReply::IcmpEcho {
+                    dst_addr: ip.source_address(),
+                    identifier: echo.identifier(),
+                    sequence_number: echo.sequence_number(),
+                    payload: echo.payload().to_vec(),
+                };
+                self.push_reply(reply);
+            }
+            Ok(_) => METRICS.mmds.rx_accepted_unusual.inc(),
+            Err(_) => METRICS.mmds.rx_accepted_err.inc(),
+        }
+    }
+
+    fn detour_udp(&mut self, ip: &IPv4Packet<&[u8]>) {
+        // Just like for TCP, we don't verify the checksum because of potential offloading.
+        let datagram = match UdpDatagram::from_bytes(ip.payload(), None) {
+            Ok(datagram) => datagram,
+            Err(_) => {
+                METRICS.mmds.rx_accepted_err.inc();
+                return;
+            }
+        };
+
+        let src_addr = ip.source_address();
+        let src_port = datagram.source_port();
+        let dst_port = datagram.destination_port();
+
+        let maybe_payload = match self.udp_handlers.get_mut(&dst_port) {
+            Some(handler) => {
+                METRICS.mmds.udp_datagrams.inc();
+                handler.handle_datagram(src_addr, src_port, datagram.payload())
+            }
+            None => {
+                // Nobody is listening on this port.
+                METRICS.mmds.rx_accepted_unusual.inc();
+                return;
+            }
+        };
+
+        if let Some(payload) = maybe_payload {
+            self.push_reply(PendingReply::Udp {
+                dst_addr: src_addr,
+                src_port: dst_port,
+                dst_port: src_port,
+                payload,
+            });
+        }
+    }
+
+    fn push_reply(&mut self, reply: PendingReply) {
+        if self.pending_replies.len() < DEFAULT_MAX_PENDING_REPLIES {
+            self.pending_replies.push_back(reply);
+        } else {
+            METRICS.mmds.replies_dropped.inc();
+        }
+    }
+
+    /// Answers the guest requests which wait for the MMDS contents to change, if the contents
+    /// changed in the meantime, or if their wait timed out. This should be called whenever the
+    /// host updates the MMDS, and when the deadline returned by `next_long_poll_deadline` is
+    /// reached. The answers are then sent via `write_next_frame`.
+    pub fn check_long_polls(&mut self) {
+        self.tcp_handler.check_long_polls();
+    }
+
+    /// Returns the earliest point in time when a guest request waiting for the MMDS contents to
+    /// change times out, if there are any such requests.
+    pub fn next_long_poll_deadline(&self) -> Option<Instant> {
+        self.tcp_handler.next_long_poll_deadline()
+    }
+
     // Allows the MMDS network stack to write a frame to the specified buffer. Will return:
     // - None, if the MMDS network stack has no frame to send at this point. The buffer can be
     // used for something else by the device model.
@@ -178,6 +491,27 @@ impl MmdsNetworkStack {
                     None
                 }
             };
+        } else if let Some(dst_addr) = self.pending_ndp_reply {
+            return match self.write_ndp_reply(buf, dst_addr) {
+                Ok(something) => {
+                    self.pending_ndp_reply = None;
+                    something
+                }
+                Err(_) => {
+                    METRICS.mmds.tx_errors.inc();
+                    None
+                }
+            };
+        } else if let Some(reply) = self.pending_replies.pop_front() {
+            // ICMP and UDP replies go before TCP segments. A reply which cannot be written is
+            // dropped, because it most likely won't fit the next time either.
+            return match self.write_reply(buf, &reply) {
+                Ok(something) => something,
+                Err(_) => {
+                    METRICS.mmds.tx_errors.inc();
+                    None
+                }
+            };
         } else {
             let call_write = match self.tcp_handler.next_segment_status() {
                 NextSegmentStatus::Available => true,
@@ -229,6 +563,112 @@ impl MmdsNetworkStack {
         ))
     }
 
+    fn write_ndp_reply(
+        &mut self,
+        buf: &mut [u8],
+        dst_addr: Ipv6Addr,
+    ) -> Result<Option<NonZeroUsize>, WriteNdpReplyError> {
+        // The unwrap() is safe because we only get a pending NDP reply when the IPv6 address is set.
+        let src_addr = self.ipv6_addr.unwrap();
+
+        let mut eth_unsized = EthernetFrame::write_incomplete(
+            buf,
+            self.remote_mac_addr,
+            self.mac_addr,
+            ETHERTYPE_IPV6,
+        ).map_err(WriteNdpReplyError::Ethernet)?;
+
+        let mut packet = IPv6Packet::write_header(
+            eth_unsized.inner_mut().payload_mut(),
+            NEXT_HEADER_ICMPV6,
+            src_addr,
+            dst_addr,
+        ).map_err(WriteNdpReplyError::IPv6Packet)?;
+
+        let message_len = NeighborMessage::write_advertisement(
+            packet.inner_mut().payload_mut(),
+            src_addr,
+            dst_addr,
+            src_addr,
+            FLAG_SOLICITED | FLAG_OVERRIDE,
+            self.mac_addr,
+        ).map_err(WriteNdpReplyError::NeighborMessage)?
+        .len();
+
+        let packet_len = packet.with_payload_len_unchecked(message_len).len();
+
+        Ok(Some(
+            // The unwrap() is safe because packet_len > 0.
+            NonZeroUsize::new(eth_unsized.with_payload_len_unchecked(packet_len).len()).unwrap(),
+        ))
+    }
+
+    fn write_reply(
+        &mut self,
+        buf: &mut [u8],
+        reply: &PendingReply,
+    ) -> Result<Option<NonZeroUsize>, WritePacketError> {
+        let mut eth_unsized = EthernetFrame::write_incomplete(
+            buf,
+            self.remote_mac_addr,
+            self.mac_addr,
+            ETHERTYPE_IPV4,
+        ).map_err(WritePacketError::Ethernet)?;
+
+        let packet_len = match *reply {
+            PendingReply::IcmpEcho {
+                dst_addr,
+                identifier,
+                sequence_number,
+                ref payload,
+            } => {
+                let mut packet = IPv4Packet::write_header(
+                    eth_unsized.inner_mut().payload_mut(),
+                    PROTOCOL_ICMP,
+                    self.ipv4_addr,
+                    dst_addr,
+                ).map_err(WritePacketError::IPv4Packet)?;
+
+                let icmp_len = IcmpEchoMessage::write_incomplete_message(
+                    packet.inner_mut().payload_mut(),
+                    TYPE_ECHO_REPLY,
+                    payload,
+                ).map_err(WritePacketError::IcmpMessage)?
+                .finalize(identifier, sequence_number)
+                .len();
+
+                packet.with_payload_len_unchecked(icmp_len, true).len()
+            }
+            PendingReply::Udp {
+                dst_addr,
+                src_port,
+                dst_port,
+                ref payload,
+            } => {
+                let mut packet = IPv4Packet::write_header(
+                    eth_unsized.inner_mut().payload_mut(),
+                    PROTOCOL_UDP,
+                    self.ipv4_addr,
+                    dst_addr,
+                ).map_err(WritePacketError::IPv4Packet)?;
+
+                let datagram_len = UdpDatagram::write_incomplete_datagram(
+                    packet.inner_mut().payload_mut(),
+                    payload,
+                ).map_err(WritePacketError::UdpDatagram)?
+                .finalize(src_port, dst_port, Some((self.ipv4_addr, dst_addr)))
+                .len();
+
+                packet.with_payload_len_unchecked(datagram_len, true).len()
+            }
+        };
+
+        Ok(Some(
+            // The unwrap() is safe because packet_len > 0.
+            NonZeroUsize::new(eth_unsized.with_payload_len_unchecked(packet_len).len()).unwrap(),
+        ))
+    }
+
     fn write_packet(&mut self, buf: &mut [u8]) -> Result<Option<NonZeroUsize>, WritePacketError> {
         let mut eth_unsized = EthernetFrame::write_incomplete(
             buf,
@@ -247,6 +687,11 @@ impl MmdsNetworkStack {
         }
 
         if let Some(packet_len) = maybe_len {
+            // The TCP handler writes either IPv4 or IPv6 packets, depending on the connection, so
+            // we look at the version field to pick the right ethertype.
+            if eth_unsized.inner_mut().payload_mut()[0] >> 4 == 6 {
+                eth_unsized.inner_mut().set_ethertype(ETHERTYPE_IPV6);
+            }
             return Ok(Some(
                 // The unwrap() is safe because packet_len > 0.
                 NonZeroUsize::new(
@@ -263,11 +708,465 @@ impl MmdsNetworkStack {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use pdu::ndp;
+    use pdu::tcp::{Flags as TcpFlags, TcpOptions, TcpSegment};
+
+    const ETH_HEADER_LEN: usize = 14;
+
+    struct EchoHandler;
+
+    impl UdpHandler for EchoHandler {
+        fn handle_datagram(
+            &mut self,
+            _src_addr: Ipv4Addr,
+            _src_port: u16,
+            payload: &[u8],
+        ) -> Option<Vec<u8>> {
+            if payload.is_empty() {
+                None
+            } else {
+                Some(payload.to_vec())
+            }
+        }
+    }
+
+    // Writes an Ethernet frame containing an IPv4 packet from `src_addr` to the MMDS. The
+    // payload of the packet is written by `f`, which returns its length.
+    fn write_ipv4_frame<F>(
+        buf: &mut [u8],
+        ns: &MmdsNetworkStack,
+        src_mac: MacAddr,
+        src_addr: Ipv4Addr,
+        protocol: u8,
+        f: F,
+    ) -> usize
+    where
+        F: FnOnce(&mut [u8]) -> usize,
+    {
+        let mut eth = EthernetFrame::write_incomplete(buf, ns.mac_addr, src_mac, ETHERTYPE_IPV4)
+            .ok()
+            .unwrap();
+        let packet_len = {
+            let mut packet = IPv4Packet::write_header(
+                eth.inner_mut().payload_mut(),
+                protocol,
+                src_addr,
+                ns.ipv4_addr,
+            ).ok()
+            .unwrap();
+            let payload_len = f(packet.inner_mut().payload_mut());
+            packet.with_payload_len_unchecked(payload_len, true).len()
+        };
+        eth.with_payload_len_unchecked(packet_len).len()
+    }
+
+    // Parses the frame in buf as an IPv4 packet sent by the MMDS to `dst_mac` and `dst_addr`,
+    // and returns the payload of the packet.
+    fn check_ipv4_frame<'a>(
+        buf: &'a [u8],
+        ns: &MmdsNetworkStack,
+        dst_mac: MacAddr,
+        dst_addr: Ipv4Addr,
+        protocol: u8,
+    ) -> &'a [u8] {
+        let eth = EthernetFrame::from_bytes(buf).ok().unwrap();
+        assert_eq!(eth.src_mac(), ns.mac_addr);
+        assert_eq!(eth.dst_mac(), dst_mac);
+        assert_eq!(eth.ethertype(), ETHERTYPE_IPV4);
+
+        let ip = IPv4Packet::from_bytes(eth.payload(), true).ok().unwrap();
+        assert_eq!(ip.source_address(), ns.ipv4_addr);
+        assert_eq!(ip.destination_address(), dst_addr);
+        assert_eq!(ip.protocol(), protocol);
+
+        // The returned slice must outlive eth and ip, so we compute it based on buf.
+        let offset = buf.len() - ip.payload().len();
+        &buf[offset..]
+    }
+
+    // Writes an Ethernet frame containing an IPv6 packet from `src_addr` to `dst_addr`. The
+    // payload of the packet is written by `f`, which returns its length.
+    fn write_ipv6_frame<F>(
+        buf: &mut [u8],
+        ns: &MmdsNetworkStack,
+        src_mac: MacAddr,
+        src_addr: Ipv6Addr,
+        dst_addr: Ipv6Addr,
+        next_header: u8,
+        f: F,
+    ) -> usize
+    where
+        F: FnOnce(&mut [u8]) -> usize,
+    {
+        let mut eth = EthernetFrame::write_incomplete(buf, ns.mac_addr, src_mac, ETHERTYPE_IPV6)
+            .ok()
+            .unwrap();
+        let packet_len = {
+            let mut packet = IPv6Packet::write_header(
+                eth.inner_mut().payload_mut(),
+                next_header,
+                src_addr,
+                dst_addr,
+            ).ok()
+            .unwrap();
+            let payload_len = f(packet.inner_mut().payload_mut());
+            packet.with_payload_len_unchecked(payload_len).len()
+        };
+        eth.with_payload_len_unchecked(packet_len).len()
+    }
+
+    fn empty_mmds() -> Arc<Mutex<Mmds>> {
+        Arc::new(Mutex::new(Mmds::default()))
+    }
+
+    // Builds a stack which is also reachable over IPv6, at fe80::a9fe:a9fe.
+    fn new_ipv6_ns() -> MmdsNetworkStack {
+        let config = MmdsNetworkStackConfig {
+            ipv6_addr: Some("fe80::a9fe:a9fe".parse().unwrap()),
+            ..Default::default()
+        };
+        MmdsNetworkStack::new_with_config(config, empty_mmds())
+    }
 
     #[test]
     fn test_new() {
-        let ns = MmdsNetworkStack::new_with_defaults();
+        let ns = MmdsNetworkStack::new_with_defaults(empty_mmds());
         assert_eq!(ns.mac_addr, MacAddr::parse_str(DEFAULT_MAC_ADDR).unwrap());
         assert_eq!(ns.ipv4_addr, Ipv4Addr::from(DEFAULT_IPV4_ADDR));
+        assert!(ns.ipv6_addr.is_none());
+    }
+
+    #[test]
+    fn test_new_with_config() {
+        let config = MmdsNetworkStackConfig {
+            ipv4_addr: Ipv4Addr::new(169, 254, 170, 2),
+            ipv6_addr: None,
+            tcp_port: 8080,
+            max_connections: NonZeroUsize::new(1).unwrap(),
+            connection_rto_period: NonZeroU64::new(1).unwrap(),
+            connection_rto_count_max: NonZeroU16::new(1).unwrap(),
+        };
+        let mut ns = MmdsNetworkStack::new_with_config(config, empty_mmds());
+        assert_eq!(ns.ipv4_addr, config.ipv4_addr);
+        assert!(ns.ipv6_addr.is_none());
+
+        let remote_mac = MacAddr::parse_str("12:34:56:78:9a:bc").unwrap();
+        let remote_addr = Ipv4Addr::new(10, 0, 0, 2);
+        let mut buf = [0u8; 2000];
+
+        // A SYN sent to the configured port is answered with a SYNACK.
+        let len = write_ipv4_frame(
+            buf.as_mut(),
+            &ns,
+            remote_mac,
+            remote_addr,
+            PROTOCOL_TCP,
+            |b| {
+                TcpSegment::write_segment::<[u8]>(
+                    b,
+                    1012,
+                    config.tcp_port,
+                    1,
+                    0,
+                    TcpFlags::SYN,
+                    10000,
+                    &TcpOptions::default(),
+                    100,
+                    None,
+                    None,
+                ).unwrap()
+                .len()
+            },
+        );
+        assert!(ns.detour_frame(&buf[..len]));
+        let len = ns.write_next_frame(buf.as_mut()).unwrap().get();
+        let tcp_bytes = check_ipv4_frame(&buf[..len], &ns, remote_mac, remote_addr, PROTOCOL_TCP);
+        let s = TcpSegment::from_bytes(tcp_bytes, None).unwrap();
+        assert_eq!(s.flags_after_ns(), TcpFlags::SYN | TcpFlags::ACK);
+        assert_eq!(s.source_port(), config.tcp_port);
+
+        // The RTO expires right away, and the connection gets reset instead of retransmitting the
+        // SYNACK, because a single timeout is all it takes to reach connection_rto_count_max.
+        let len = ns.write_next_frame(buf.as_mut()).unwrap().get();
+        let tcp_bytes = check_ipv4_frame(&buf[..len], &ns, remote_mac, remote_addr, PROTOCOL_TCP);
+        let s = TcpSegment::from_bytes(tcp_bytes, None).unwrap();
+        assert!(s.flags_after_ns().intersects(TcpFlags::RST));
+
+        // Frames for the default address are no longer detoured.
+        let mut default_ns = MmdsNetworkStack::new_with_defaults(empty_mmds());
+        let len = write_ipv4_frame(
+            buf.as_mut(),
+            &default_ns,
+            remote_mac,
+            remote_addr,
+            PROTOCOL_TCP,
+            |_| 0,
+        );
+        assert!(!ns.detour_frame(&buf[..len]));
+        assert!(default_ns.detour_frame(&buf[..len]));
+    }
+
+    #[test]
+    fn test_ndp() {
+        let mut ns = new_ipv6_ns();
+        let local_addr = ns.ipv6_addr.unwrap();
+        let multicast_addr = solicited_node_multicast_addr(local_addr);
+        let remote_mac = MacAddr::parse_str("12:34:56:78:9a:bc").unwrap();
+        let remote_addr = "fe80::1".parse::<Ipv6Addr>().unwrap();
+        let mut buf = [0u8; 2000];
+
+        let write_solicitation = |buf: &mut [u8], ns: &MmdsNetworkStack, target: Ipv6Addr| {
+            write_ipv6_frame(
+                buf,
+                ns,
+                remote_mac,
+                remote_addr,
+                multicast_addr,
+                NEXT_HEADER_ICMPV6,
+                |b| {
+                    NeighborMessage::write_solicitation(
+                        b,
+                        remote_addr,
+                        multicast_addr,
+                        target,
+                        remote_mac,
+                    ).unwrap()
+                    .len()
+                },
+            )
+        };
+
+        // Solicitations for some other address are not detoured.
+        let other_addr = "fe80::1234:a9fe".parse::<Ipv6Addr>().unwrap();
+        let len = write_solicitation(buf.as_mut(), &ns, other_addr);
+        assert!(!ns.detour_frame(&buf[..len]));
+
+        let len = write_solicitation(buf.as_mut(), &ns, local_addr);
+        assert!(ns.detour_frame(&buf[..len]));
+        assert_eq!(ns.pending_ndp_reply, Some(remote_addr));
+
+        let len = ns.write_next_frame(buf.as_mut()).unwrap().get();
+        assert!(ns.pending_ndp_reply.is_none());
+        {
+            let eth = EthernetFrame::from_bytes(&buf[..len]).ok().unwrap();
+            assert_eq!(eth.src_mac(), ns.mac_addr);
+            assert_eq!(eth.dst_mac(), remote_mac);
+            assert_eq!(eth.ethertype(), ETHERTYPE_IPV6);
+
+            let ip = IPv6Packet::from_bytes(eth.payload()).unwrap();
+            assert_eq!(ip.source_address(), local_addr);
+            assert_eq!(ip.destination_address(), remote_addr);
+            assert_eq!(ip.hop_limit(), DEFAULT_HOP_LIMIT);
+
+            let na = NeighborMessage::from_bytes(ip.payload(), Some((local_addr, remote_addr)))
+                .unwrap();
+            assert_eq!(na.message_type(), ndp::TYPE_NEIGHBOR_ADVERTISEMENT);
+            assert_eq!(na.flags(), FLAG_SOLICITED | FLAG_OVERRIDE);
+            assert_eq!(na.target_address(), local_addr);
+            assert_eq!(na.link_layer_address_option(), Some(ns.mac_addr));
+        }
+        assert!(ns.write_next_frame(buf.as_mut()).is_none());
+
+        // Solicitations which may have been forwarded by a router are ignored.
+        let len = write_solicitation(buf.as_mut(), &ns, local_addr);
+        buf[ETH_HEADER_LEN + 7] = 64;
+        assert!(!ns.detour_frame(&buf[..len]));
+
+        // Without an IPv6 address, the MMDS doesn't answer solicitations at all.
+        let mut ns = MmdsNetworkStack::new(
+            remote_mac,
+            Ipv4Addr::from(DEFAULT_IPV4_ADDR),
+            None,
+            DEFAULT_TCP_PORT,
+            NonZeroUsize::new(DEFAULT_MAX_CONNECTIONS).unwrap(),
+            NonZeroUsize::new(DEFAULT_MAX_PENDING_RESETS).unwrap(),
+            NonZeroU64::new(DEFAULT_CONNECTION_RTO_PERIOD).unwrap(),
+            NonZeroU16::new(DEFAULT_CONNECTION_RTO_COUNT_MAX).unwrap(),
+            empty_mmds(),
+        );
+        let len = write_solicitation(buf.as_mut(), &ns, local_addr);
+        assert!(!ns.detour_frame(&buf[..len]));
+    }
+
+    #[test]
+    fn test_ipv6_tcp() {
+        let mut ns = new_ipv6_ns();
+        let local_addr = ns.ipv6_addr.unwrap();
+        let remote_mac = MacAddr::parse_str("12:34:56:78:9a:bc").unwrap();
+        let remote_addr = "fe80::1".parse::<Ipv6Addr>().unwrap();
+        let mut buf = [0u8; 2000];
+
+        let len = write_ipv6_frame(
+            buf.as_mut(),
+            &ns,
+            remote_mac,
+            remote_addr,
+            local_addr,
+            PROTOCOL_TCP,
+            |b| {
+                TcpSegment::write_segment::<[u8]>(
+                    b,
+                    1012,
+                    DEFAULT_TCP_PORT,
+                    1,
+                    0,
+                    TcpFlags::SYN,
+                    10000,
+                    &TcpOptions::default(),
+                    100,
+                    None,
+                    None,
+                ).unwrap()
+                .len()
+            },
+        );
+
+        let created = METRICS.mmds.connections_created.count();
+        assert!(ns.detour_frame(&buf[..len]));
+        assert_eq!(METRICS.mmds.connections_created.count(), created + 1);
+
+        let len = ns.write_next_frame(buf.as_mut()).unwrap().get();
+        let eth = EthernetFrame::from_bytes(&buf[..len]).ok().unwrap();
+        assert_eq!(eth.dst_mac(), remote_mac);
+        assert_eq!(eth.ethertype(), ETHERTYPE_IPV6);
+        let ip = IPv6Packet::from_bytes(eth.payload()).unwrap();
+        assert_eq!(ip.destination_address(), remote_addr);
+        let s = TcpSegment::from_bytes(ip.payload(), None).unwrap();
+        assert_eq!(s.flags_after_ns(), TcpFlags::SYN | TcpFlags::ACK);
+        assert_eq!(s.destination_port(), 1012);
+    }
+
+    #[test]
+    fn test_icmp_echo() {
+        let mut ns = MmdsNetworkStack::new_with_defaults(empty_mmds());
+        let remote_mac = MacAddr::parse_str("12:34:56:78:9a:bc").unwrap();
+        let remote_addr = Ipv4Addr::new(10, 0, 0, 2);
+        let mut buf = [0u8; 2000];
+        let payload = b"abcdefghijklmnopqrstuvwxyz";
+
+        let len = write_ipv4_frame(
+            buf.as_mut(),
+            &ns,
+            remote_mac,
+            remote_addr,
+            PROTOCOL_ICMP,
+            |b| {
+                IcmpEchoMessage::write_incomplete_message(b, TYPE_ECHO_REQUEST, payload.as_ref())
+                    .unwrap()
+                    .finalize(0x4321, 9)
+                    .len()
+            },
+        );
+
+        let requests = METRICS.mmds.icmp_echo_requests.count();
+        assert!(ns.detour_frame(&buf[..len]));
+        assert_eq!(METRICS.mmds.icmp_echo_requests.count(), requests + 1);
+
+        let len = ns.write_next_frame(buf.as_mut()).unwrap().get();
+        {
+            let icmp_bytes =
+                check_ipv4_frame(&buf[..len], &ns, remote_mac, remote_addr, PROTOCOL_ICMP);
+            let echo = IcmpEchoMessage::from_bytes(icmp_bytes, true).unwrap();
+            assert_eq!(echo.message_type(), TYPE_ECHO_REPLY);
+            assert_eq!(echo.identifier(), 0x4321);
+            assert_eq!(echo.sequence_number(), 9);
+            assert_eq!(echo.payload(), payload.as_ref());
+        }
+        assert!(ns.write_next_frame(buf.as_mut()).is_none());
+
+        // Echo requests with an invalid checksum are accepted, but not answered.
+        let len = write_ipv4_frame(
+            buf.as_mut(),
+            &ns,
+            remote_mac,
+            remote_addr,
+            PROTOCOL_ICMP,
+            |b| {
+                let mut echo =
+                    IcmpEchoMessage::write_incomplete_message(b, TYPE_ECHO_REQUEST, &[1, 2, 3])
+                        .unwrap()
+                        .finalize(1, 1);
+                echo.set_checksum(0);
+                echo.len()
+            },
+        );
+        assert!(ns.detour_frame(&buf[..len]));
+        assert!(ns.write_next_frame(buf.as_mut()).is_none());
+
+        // The number of pending replies is bounded.
+        let len = write_ipv4_frame(
+            buf.as_mut(),
+            &ns,
+            remote_mac,
+            remote_addr,
+            PROTOCOL_ICMP,
+            |b| {
+                IcmpEchoMessage::write_incomplete_message(b, TYPE_ECHO_REQUEST, &[])
+                    .unwrap()
+                    .finalize(1, 1)
+                    .len()
+            },
+        );
+        let dropped = METRICS.mmds.replies_dropped.count();
+        for _ in 0..DEFAULT_MAX_PENDING_REPLIES + 1 {
+            assert!(ns.detour_frame(&buf[..len]));
+        }
+        assert_eq!(METRICS.mmds.replies_dropped.count(), dropped + 1);
+        for _ in 0..DEFAULT_MAX_PENDING_REPLIES {
+            assert!(ns.write_next_frame(buf.as_mut()).is_some());
+        }
+        assert!(ns.write_next_frame(buf.as_mut()).is_none());
+    }
+
+    #[test]
+    fn test_udp_handler() {
+        let mut ns = MmdsNetworkStack::new_with_defaults(empty_mmds());
+        let remote_mac = MacAddr::parse_str("12:34:56:78:9a:bc").unwrap();
+        let remote_addr = Ipv4Addr::new(10, 0, 0, 2);
+        let mut buf = [0u8; 2000];
+        let payload = b"what time is it?";
+
+        let write_datagram = |buf: &mut [u8], ns: &MmdsNetworkStack, payload: &[u8]| {
+            write_ipv4_frame(buf, ns, remote_mac, remote_addr, PROTOCOL_UDP, |b| {
+                UdpDatagram::write_incomplete_datagram(b, payload)
+                    .unwrap()
+                    .finalize(1000, 123, Some((remote_addr, ns.ipv4_addr)))
+                    .len()
+            })
+        };
+
+        // Without a handler, the datagram is swallowed by the MMDS, but nothing is sent back.
+        let len = write_datagram(buf.as_mut(), &ns, payload.as_ref());
+        assert!(ns.detour_frame(&buf[..len]));
+        assert!(ns.write_next_frame(buf.as_mut()).is_none());
+
+        assert!(ns.set_udp_handler(123, Box::new(EchoHandler)).is_none());
+        assert!(ns.set_udp_handler(123, Box::new(EchoHandler)).is_some());
+
+        let datagrams = METRICS.mmds.udp_datagrams.count();
+        let len = write_datagram(buf.as_mut(), &ns, payload.as_ref());
+        assert!(ns.detour_frame(&buf[..len]));
+        assert_eq!(METRICS.mmds.udp_datagrams.count(), datagrams + 1);
+
+        let len = ns.write_next_frame(buf.as_mut()).unwrap().get();
+        {
+            let udp_bytes =
+                check_ipv4_frame(&buf[..len], &ns, remote_mac, remote_addr, PROTOCOL_UDP);
+            let datagram =
+                UdpDatagram::from_bytes(udp_bytes, Some((ns.ipv4_addr, remote_addr))).unwrap();
+            assert_eq!(datagram.source_port(), 123);
+            assert_eq!(datagram.destination_port(), 1000);
+            assert_eq!(datagram.payload(), payload.as_ref());
+        }
+
+        // The handler chooses not to reply.
+        let len = write_datagram(buf.as_mut(), &ns, &[]);
+        assert!(ns.detour_frame(&buf[..len]));
+        assert!(ns.write_next_frame(buf.as_mut()).is_none());
+
+        assert!(ns.remove_udp_handler(123).is_some());
+        let len = write_datagram(buf.as_mut(), &ns, payload.as_ref());
+        assert!(ns.detour_frame(&buf[..len]));
+        assert!(ns.write_next_frame(buf.as_mut()).is_none());
     }
 }
@@ -87,6 +87,20 @@ impl<'a, T: NetworkBytes> EthIPv4ArpFrame<'a, T> {
     /// If no error occurs, it guarantees accessor methods (which make use of various `_unchecked`
     /// functions) are safe to call on the result, because all predefined offsets will be valid.
     pub fn request_from_bytes(bytes: T) -> Result<Self, Error> {
+        let maybe = EthIPv4ArpFrame::from_bytes(bytes)?;
+
+        if maybe.operation() != OPER_REQUEST {
+            return Err(Error::Operation);
+        }
+
+        Ok(maybe)
+    }
+
+    /// Tries to interpret a byte slice as a valid IPv4 over Ethernet ARP frame, without looking
+    /// at the operation field (so both requests and replies are accepted).
+    ///
+    /// The same guarantees as for `request_from_bytes` apply with respect to the accessor methods.
+    pub fn from_bytes(bytes: T) -> Result<Self, Error> {
         // This kind of frame has a fixed length, so we know what to expect.
         if bytes.len() != ETH_IPV4_FRAME_LEN {
             return Err(Error::SliceExactLen);
@@ -111,10 +125,6 @@ impl<'a, T: NetworkBytes> EthIPv4ArpFrame<'a, T> {
             return Err(Error::PLen);
         }
 
-        if maybe.operation() != OPER_REQUEST {
-            return Err(Error::Operation);
-        }
-
         Ok(maybe)
     }
 
@@ -402,6 +412,14 @@ mod tests {
             Error::Operation
         );
 
+        // The reply is still a valid ARP frame though.
+        {
+            let f = EthIPv4ArpFrame::from_bytes(&a[..ETH_IPV4_FRAME_LEN]).unwrap();
+            assert_eq!(f.operation(), OPER_REPLY);
+            assert_eq!(f.sha(), sha);
+            assert_eq!(f.spa(), spa);
+        }
+
         // TODO: The following test code is way more verbose than it should've been. Make it
         // prettier at some point.
 
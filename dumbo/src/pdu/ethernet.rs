@@ -23,6 +23,8 @@ pub(super) const PAYLOAD_OFFSET: usize = 14;
 pub const ETHERTYPE_ARP: u16 = 0x0806;
 /// Ethertype value for IPv4 packets.
 pub const ETHERTYPE_IPV4: u16 = 0x0800;
+/// Ethertype value for IPv6 packets.
+pub const ETHERTYPE_IPV6: u16 = 0x86dd;
 
 /// Describes the errors which may occur when handling Ethernet frames.
 #[cfg_attr(test, derive(Debug, PartialEq))]
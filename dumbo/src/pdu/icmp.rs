@@ -0,0 +1,352 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Contains support for parsing and writing ICMP (v4) echo messages.
+//!
+//! The layout of ICMP messages is described [here]. Only the echo request and echo reply
+//! message types are currently interpreted beyond the common header.
+//!
+//! [here]: https://en.wikipedia.org/wiki/Internet_Control_Message_Protocol#Datagram_structure
+
+use std::result::Result;
+
+use super::bytes::{InnerBytes, NetworkBytes, NetworkBytesMut};
+use super::Incomplete;
+
+const TYPE_OFFSET: usize = 0;
+const CODE_OFFSET: usize = 1;
+const CHECKSUM_OFFSET: usize = 2;
+const IDENTIFIER_OFFSET: usize = 4;
+const SEQUENCE_NUMBER_OFFSET: usize = 6;
+const PAYLOAD_OFFSET: usize = 8;
+
+/// The length of the header of ICMP echo messages.
+pub const ECHO_HEADER_LEN: usize = PAYLOAD_OFFSET;
+
+/// The ICMP message type associated with echo replies.
+pub const TYPE_ECHO_REPLY: u8 = 0;
+/// The ICMP message type associated with echo requests.
+pub const TYPE_ECHO_REQUEST: u8 = 8;
+
+/// Describes the errors which may occur while handling ICMP echo messages.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// Invalid checksum.
+    Checksum,
+    /// The message is not an echo request or reply.
+    MessageType,
+    /// The payload does not fit in the given slice.
+    PayloadTooLarge,
+    /// The specified slice is shorter than the header length.
+    SliceTooShort,
+}
+
+/// Interprets the inner bytes as an ICMP echo request or echo reply message.
+pub struct IcmpEchoMessage<'a, T: 'a> {
+    bytes: InnerBytes<'a, T>,
+}
+
+impl<'a, T: NetworkBytes> IcmpEchoMessage<'a, T> {
+    /// Returns the message type.
+    #[inline]
+    pub fn message_type(&self) -> u8 {
+        self.bytes[TYPE_OFFSET]
+    }
+
+    /// Returns the message code.
+    #[inline]
+    pub fn code(&self) -> u8 {
+        self.bytes[CODE_OFFSET]
+    }
+
+    /// Returns the checksum value.
+    #[inline]
+    pub fn checksum(&self) -> u16 {
+        self.bytes.ntohs_unchecked(CHECKSUM_OFFSET)
+    }
+
+    /// Returns the identifier of the echo message.
+    #[inline]
+    pub fn identifier(&self) -> u16 {
+        self.bytes.ntohs_unchecked(IDENTIFIER_OFFSET)
+    }
+
+    /// Returns the sequence number of the echo message.
+    #[inline]
+    pub fn sequence_number(&self) -> u16 {
+        self.bytes.ntohs_unchecked(SEQUENCE_NUMBER_OFFSET)
+    }
+
+    /// Returns the payload of the message.
+    #[inline]
+    pub fn payload(&self) -> &[u8] {
+        &self.bytes[PAYLOAD_OFFSET..]
+    }
+
+    /// Returns the length of the message.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Computes the ICMP checksum of the message. Unlike TCP and UDP, there is no
+    /// pseudo-header involved, so the sum only covers the bytes of the message.
+    pub fn compute_checksum(&self) -> u16 {
+        let mut sum = 0u32;
+
+        let len = self.len();
+        for i in 0..len / 2 {
+            sum += self.bytes.ntohs_unchecked(i * 2) as u32;
+        }
+
+        if len % 2 != 0 {
+            sum += (self.bytes[len - 1] as u32) << 8;
+        }
+
+        while sum >> 16 != 0 {
+            sum = (sum & 0xffff) + (sum >> 16);
+        }
+
+        !(sum as u16)
+    }
+
+    /// Interprets `bytes` as an ICMP echo message without any validity checks.
+    ///
+    /// # Panics
+    ///
+    /// This method does not panic, but further method calls on the resulting object may panic if
+    /// `bytes` contains invalid input.
+    #[inline]
+    pub fn from_bytes_unchecked(bytes: T) -> Self {
+        IcmpEchoMessage {
+            bytes: InnerBytes::new(bytes),
+        }
+    }
+
+    /// Attempts to interpret `bytes` as an ICMP echo request or reply, optionally verifying the
+    /// checksum.
+    pub fn from_bytes(bytes: T, verify_checksum: bool) -> Result<Self, Error> {
+        if bytes.len() < ECHO_HEADER_LEN {
+            return Err(Error::SliceTooShort);
+        }
+
+        let message = Self::from_bytes_unchecked(bytes);
+
+        let message_type = message.message_type();
+        if message_type != TYPE_ECHO_REQUEST && message_type != TYPE_ECHO_REPLY {
+            return Err(Error::MessageType);
+        }
+
+        if verify_checksum && message.compute_checksum() != 0 {
+            return Err(Error::Checksum);
+        }
+
+        Ok(message)
+    }
+}
+
+impl<'a, T: NetworkBytesMut> IcmpEchoMessage<'a, T> {
+    /// Sets the message type.
+    #[inline]
+    pub fn set_message_type(&mut self, value: u8) -> &mut Self {
+        self.bytes[TYPE_OFFSET] = value;
+        self
+    }
+
+    /// Sets the message code.
+    #[inline]
+    pub fn set_code(&mut self, value: u8) -> &mut Self {
+        self.bytes[CODE_OFFSET] = value;
+        self
+    }
+
+    /// Sets the value of the `checksum` field.
+    #[inline]
+    pub fn set_checksum(&mut self, value: u16) -> &mut Self {
+        self.bytes.htons_unchecked(CHECKSUM_OFFSET, value);
+        self
+    }
+
+    /// Sets the identifier of the echo message.
+    #[inline]
+    pub fn set_identifier(&mut self, value: u16) -> &mut Self {
+        self.bytes.htons_unchecked(IDENTIFIER_OFFSET, value);
+        self
+    }
+
+    /// Sets the sequence number of the echo message.
+    #[inline]
+    pub fn set_sequence_number(&mut self, value: u16) -> &mut Self {
+        self.bytes.htons_unchecked(SEQUENCE_NUMBER_OFFSET, value);
+        self
+    }
+
+    /// Returns a mutable slice which contains the payload of the message.
+    #[inline]
+    pub fn payload_mut(&mut self) -> &mut [u8] {
+        &mut self.bytes[PAYLOAD_OFFSET..]
+    }
+
+    /// Writes an incomplete ICMP echo message of type `message_type`, which is missing the
+    /// `identifier`, `sequence number`, and `checksum` fields. The payload is copied from
+    /// `payload`.
+    pub fn write_incomplete_message(
+        buf: T,
+        message_type: u8,
+        payload: &[u8],
+    ) -> Result<Incomplete<Self>, Error> {
+        if message_type != TYPE_ECHO_REQUEST && message_type != TYPE_ECHO_REPLY {
+            return Err(Error::MessageType);
+        }
+
+        if buf.len() < ECHO_HEADER_LEN {
+            return Err(Error::SliceTooShort);
+        }
+
+        let len = ECHO_HEADER_LEN + payload.len();
+        if len > buf.len() {
+            return Err(Error::PayloadTooLarge);
+        }
+
+        let mut message = Self::from_bytes_unchecked(buf);
+        // This is ok because len <= buf.len().
+        message.bytes.shrink_unchecked(len);
+        message.set_message_type(message_type).set_code(0);
+        message.payload_mut().copy_from_slice(payload);
+
+        Ok(Incomplete::new(message))
+    }
+}
+
+impl<'a, T: NetworkBytesMut> Incomplete<IcmpEchoMessage<'a, T>> {
+    /// Transforms `self` into an `IcmpEchoMessage<T>` by specifying values for the `identifier`
+    /// and `sequence number` fields, and computing the checksum.
+    pub fn finalize(mut self, identifier: u16, sequence_number: u16) -> IcmpEchoMessage<'a, T> {
+        self.inner
+            .set_identifier(identifier)
+            .set_sequence_number(sequence_number)
+            .set_checksum(0);
+        let checksum = self.inner.compute_checksum();
+        self.inner.set_checksum(checksum);
+        self.inner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt;
+
+    use super::*;
+
+    impl<'a, T: NetworkBytes> fmt::Debug for IcmpEchoMessage<'a, T> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "(ICMP echo message)")
+        }
+    }
+
+    impl<'a, T: NetworkBytes> fmt::Debug for Incomplete<IcmpEchoMessage<'a, T>> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "(Incomplete ICMP echo message)")
+        }
+    }
+
+    #[test]
+    fn test_set_get() {
+        let mut a = [0u8; 100];
+        let mut m = IcmpEchoMessage::from_bytes_unchecked(a.as_mut());
+
+        assert_eq!(m.message_type(), 0);
+        m.set_message_type(TYPE_ECHO_REQUEST);
+        assert_eq!(m.message_type(), TYPE_ECHO_REQUEST);
+
+        assert_eq!(m.code(), 0);
+        m.set_code(3);
+        assert_eq!(m.code(), 3);
+
+        assert_eq!(m.checksum(), 0);
+        m.set_checksum(0x1234);
+        assert_eq!(m.checksum(), 0x1234);
+
+        assert_eq!(m.identifier(), 0);
+        m.set_identifier(0xabcd);
+        assert_eq!(m.identifier(), 0xabcd);
+
+        assert_eq!(m.sequence_number(), 0);
+        m.set_sequence_number(7);
+        assert_eq!(m.sequence_number(), 7);
+
+        assert_eq!(m.payload().len(), 100 - ECHO_HEADER_LEN);
+        assert_eq!(m.len(), 100);
+    }
+
+    #[test]
+    fn test_write_and_parse() {
+        let mut a = [0u8; 100];
+        let payload = b"ping payload";
+
+        assert_eq!(
+            IcmpEchoMessage::write_incomplete_message(&mut a[..5], TYPE_ECHO_REPLY, &[])
+                .unwrap_err(),
+            Error::SliceTooShort
+        );
+        assert_eq!(
+            IcmpEchoMessage::write_incomplete_message(
+                &mut a[..10],
+                TYPE_ECHO_REPLY,
+                payload.as_ref()
+            ).unwrap_err(),
+            Error::PayloadTooLarge
+        );
+        assert_eq!(
+            IcmpEchoMessage::write_incomplete_message(a.as_mut(), 3, payload.as_ref())
+                .unwrap_err(),
+            Error::MessageType
+        );
+
+        let len = IcmpEchoMessage::write_incomplete_message(
+            a.as_mut(),
+            TYPE_ECHO_REQUEST,
+            payload.as_ref(),
+        ).unwrap()
+        .finalize(0x1234, 5)
+        .len();
+        assert_eq!(len, ECHO_HEADER_LEN + payload.len());
+
+        {
+            let m = IcmpEchoMessage::from_bytes(&a[..len], true).unwrap();
+            assert_eq!(m.message_type(), TYPE_ECHO_REQUEST);
+            assert_eq!(m.code(), 0);
+            assert_eq!(m.identifier(), 0x1234);
+            assert_eq!(m.sequence_number(), 5);
+            assert_eq!(m.payload(), payload.as_ref());
+        }
+
+        // Odd lengths are handled by the checksum computation as well.
+        let odd_len = IcmpEchoMessage::write_incomplete_message(
+            a.as_mut(),
+            TYPE_ECHO_REPLY,
+            &payload[..5],
+        ).unwrap()
+        .finalize(1, 2)
+        .len();
+        assert!(IcmpEchoMessage::from_bytes(&a[..odd_len], true).is_ok());
+
+        // Corrupting the message results in a checksum error, unless verification is skipped.
+        a[ECHO_HEADER_LEN] ^= 0xff;
+        assert_eq!(
+            IcmpEchoMessage::from_bytes(&a[..odd_len], true).unwrap_err(),
+            Error::Checksum
+        );
+        assert!(IcmpEchoMessage::from_bytes(&a[..odd_len], false).is_ok());
+
+        assert_eq!(
+            IcmpEchoMessage::from_bytes(&a[..4], false).unwrap_err(),
+            Error::SliceTooShort
+        );
+        a[TYPE_OFFSET] = 3;
+        assert_eq!(
+            IcmpEchoMessage::from_bytes(&a[..odd_len], false).unwrap_err(),
+            Error::MessageType
+        );
+    }
+}
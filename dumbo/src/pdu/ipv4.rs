@@ -30,8 +30,12 @@ const OPTIONS_OFFSET: usize = 20;
 const IPV4_VERSION: u8 = 0x04;
 const DEFAULT_TTL: u8 = 200;
 
+/// The IP protocol number associated with ICMP.
+pub const PROTOCOL_ICMP: u8 = 0x01;
 /// The IP protocol number associated with TCP.
 pub const PROTOCOL_TCP: u8 = 0x06;
+/// The IP protocol number associated with UDP.
+pub const PROTOCOL_UDP: u8 = 0x11;
 
 /// Describes the errors which may occur while handling IPv4 packets.
 #[cfg_attr(test, derive(Debug, PartialEq))]
@@ -0,0 +1,466 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Contains support for parsing and writing IPv6 packets.
+//!
+//! A picture of the IPv6 packet header can be found [here]. Extension headers are not
+//! interpreted, so the payload of a packet starts right after the fixed 40 byte header, and is
+//! described by the `next header` field.
+//!
+//! [here]: https://en.wikipedia.org/wiki/IPv6_packet#Fixed_header
+
+use std::convert::From;
+use std::net::Ipv6Addr;
+use std::result::Result;
+
+use pdu::bytes::{InnerBytes, NetworkBytes, NetworkBytesMut};
+use pdu::ethernet;
+use pdu::Incomplete;
+
+const VERSION_TC_AND_FLOW_LABEL_OFFSET: usize = 0;
+const PAYLOAD_LEN_OFFSET: usize = 4;
+const NEXT_HEADER_OFFSET: usize = 6;
+const HOP_LIMIT_OFFSET: usize = 7;
+const SOURCE_ADDRESS_OFFSET: usize = 8;
+const DESTINATION_ADDRESS_OFFSET: usize = 24;
+const PAYLOAD_OFFSET: usize = 40;
+
+const IPV6_ADDR_LEN: usize = 16;
+const IPV6_VERSION: u8 = 0x06;
+
+/// The length of the fixed IPv6 header.
+pub const HEADER_LEN: usize = PAYLOAD_OFFSET;
+
+/// The hop limit used for written packets. This is also the value required by NDP.
+pub const DEFAULT_HOP_LIMIT: u8 = 255;
+
+/// The `next header` value associated with ICMPv6.
+pub const NEXT_HEADER_ICMPV6: u8 = 0x3a;
+
+/// Describes the errors which may occur while handling IPv6 packets.
+#[cfg_attr(test, derive(Debug, PartialEq))]
+pub enum Error {
+    /// The payload length header field is invalid.
+    InvalidPayloadLen,
+    /// The payload is too large to be described by the payload length header field.
+    PayloadTooLarge,
+    /// The length of the given slice does not match the length of the packet.
+    SliceExactLen,
+    /// The length of the given slice is less than the IPv6 header length.
+    SliceTooShort,
+    /// The version header field is invalid.
+    Version,
+}
+
+/// Interprets the inner bytes as an IPv6 packet.
+pub struct IPv6Packet<'a, T: 'a> {
+    bytes: InnerBytes<'a, T>,
+}
+
+impl<'a, T: NetworkBytes> IPv6Packet<'a, T> {
+    /// Interpret `bytes` as an IPv6Packet without checking the validity of the header fields, and
+    /// the length of the inner byte sequence.
+    ///
+    /// # Panics
+    ///
+    /// This method does not panic, but further method calls on the resulting object may panic if
+    /// `bytes` contains invalid input.
+    #[inline]
+    pub fn from_bytes_unchecked(bytes: T) -> Self {
+        IPv6Packet {
+            bytes: InnerBytes::new(bytes),
+        }
+    }
+
+    /// Attempts to interpret `bytes` as an IPv6 packet, checking the validity of the header fields
+    /// and the length of the inner byte sequence.
+    pub fn from_bytes(bytes: T) -> Result<Self, Error> {
+        let bytes_len = bytes.len();
+
+        if bytes_len < HEADER_LEN {
+            return Err(Error::SliceTooShort);
+        }
+
+        let packet = IPv6Packet::from_bytes_unchecked(bytes);
+
+        if packet.version() != IPV6_VERSION {
+            return Err(Error::Version);
+        }
+
+        // Jumbograms (payload length 0 plus a hop-by-hop option) are not supported.
+        let payload_len = packet.payload_len() as usize;
+        if payload_len == 0 {
+            return Err(Error::InvalidPayloadLen);
+        }
+
+        if HEADER_LEN + payload_len != bytes_len {
+            return Err(Error::SliceExactLen);
+        }
+
+        Ok(packet)
+    }
+
+    /// Returns the value of the `version` header field.
+    #[inline]
+    pub fn version(&self) -> u8 {
+        self.bytes[VERSION_TC_AND_FLOW_LABEL_OFFSET] >> 4
+    }
+
+    /// Returns the value of the `traffic class` header field.
+    #[inline]
+    pub fn traffic_class(&self) -> u8 {
+        (self.bytes.ntohs_unchecked(VERSION_TC_AND_FLOW_LABEL_OFFSET) >> 4) as u8
+    }
+
+    /// Returns the value of the `flow label` header field.
+    #[inline]
+    pub fn flow_label(&self) -> u32 {
+        self.bytes.ntohl_unchecked(VERSION_TC_AND_FLOW_LABEL_OFFSET) & 0x000f_ffff
+    }
+
+    /// Returns the value of the `payload length` header field.
+    #[inline]
+    pub fn payload_len(&self) -> u16 {
+        self.bytes.ntohs_unchecked(PAYLOAD_LEN_OFFSET)
+    }
+
+    /// Returns the value of the `next header` header field.
+    #[inline]
+    pub fn next_header(&self) -> u8 {
+        self.bytes[NEXT_HEADER_OFFSET]
+    }
+
+    /// Returns the value of the `hop limit` header field.
+    #[inline]
+    pub fn hop_limit(&self) -> u8 {
+        self.bytes[HOP_LIMIT_OFFSET]
+    }
+
+    /// Returns the source IPv6 address of the packet.
+    #[inline]
+    pub fn source_address(&self) -> Ipv6Addr {
+        self.address_unchecked(SOURCE_ADDRESS_OFFSET)
+    }
+
+    /// Returns the destination IPv6 address of the packet.
+    #[inline]
+    pub fn destination_address(&self) -> Ipv6Addr {
+        self.address_unchecked(DESTINATION_ADDRESS_OFFSET)
+    }
+
+    #[inline]
+    fn address_unchecked(&self, offset: usize) -> Ipv6Addr {
+        let mut octets = [0u8; IPV6_ADDR_LEN];
+        octets.copy_from_slice(&self.bytes[offset..offset + IPV6_ADDR_LEN]);
+        Ipv6Addr::from(octets)
+    }
+
+    /// Returns a byte slice that contains the payload of the packet.
+    #[inline]
+    pub fn payload(&self) -> &[u8] {
+        self.bytes.split_at(PAYLOAD_OFFSET).1
+    }
+
+    /// Returns the length of the inner byte sequence.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+}
+
+impl<'a, T: NetworkBytesMut> IPv6Packet<'a, T> {
+    /// Attempts to write an IPv6 packet header to `buf`, making sure there is enough space.
+    ///
+    /// This method returns an incomplete packet, because the size of the payload might be unknown
+    /// at this point. The `traffic class` and `flow label` fields are set to 0, and the
+    /// `hop limit` is set to `DEFAULT_HOP_LIMIT`. The `payload length` field will be set when
+    /// the length of the incomplete packet is determined.
+    pub fn write_header(
+        buf: T,
+        next_header: u8,
+        src_addr: Ipv6Addr,
+        dst_addr: Ipv6Addr,
+    ) -> Result<Incomplete<Self>, Error> {
+        if buf.len() < HEADER_LEN {
+            return Err(Error::SliceTooShort);
+        }
+
+        let mut packet = IPv6Packet::from_bytes_unchecked(buf);
+
+        packet
+            .set_version_tc_and_flow_label(IPV6_VERSION, 0, 0)
+            .set_next_header(next_header)
+            .set_hop_limit(DEFAULT_HOP_LIMIT)
+            .set_source_address(src_addr)
+            .set_destination_address(dst_addr);
+
+        Ok(Incomplete::new(packet))
+    }
+
+    /// Sets the values of the `version`, `traffic class`, and `flow label` header fields.
+    #[inline]
+    pub fn set_version_tc_and_flow_label(
+        &mut self,
+        version: u8,
+        traffic_class: u8,
+        flow_label: u32,
+    ) -> &mut Self {
+        let value = (u32::from(version) << 28)
+            | (u32::from(traffic_class) << 20)
+            | (flow_label & 0x000f_ffff);
+        self.bytes
+            .htonl_unchecked(VERSION_TC_AND_FLOW_LABEL_OFFSET, value);
+        self
+    }
+
+    /// Sets the value of the `payload length` header field.
+    #[inline]
+    pub fn set_payload_len(&mut self, value: u16) -> &mut Self {
+        self.bytes.htons_unchecked(PAYLOAD_LEN_OFFSET, value);
+        self
+    }
+
+    /// Sets the value of the `next header` header field.
+    #[inline]
+    pub fn set_next_header(&mut self, value: u8) -> &mut Self {
+        self.bytes[NEXT_HEADER_OFFSET] = value;
+        self
+    }
+
+    /// Sets the value of the `hop limit` header field.
+    #[inline]
+    pub fn set_hop_limit(&mut self, value: u8) -> &mut Self {
+        self.bytes[HOP_LIMIT_OFFSET] = value;
+        self
+    }
+
+    /// Sets the source address of the packet.
+    #[inline]
+    pub fn set_source_address(&mut self, addr: Ipv6Addr) -> &mut Self {
+        self.bytes[SOURCE_ADDRESS_OFFSET..SOURCE_ADDRESS_OFFSET + IPV6_ADDR_LEN]
+            .copy_from_slice(&addr.octets());
+        self
+    }
+
+    /// Sets the destination address of the packet.
+    #[inline]
+    pub fn set_destination_address(&mut self, addr: Ipv6Addr) -> &mut Self {
+        self.bytes[DESTINATION_ADDRESS_OFFSET..DESTINATION_ADDRESS_OFFSET + IPV6_ADDR_LEN]
+            .copy_from_slice(&addr.octets());
+        self
+    }
+
+    /// Returns a mutable byte slice representing the payload of the packet.
+    #[inline]
+    pub fn payload_mut(&mut self) -> &mut [u8] {
+        self.bytes.split_at_mut(PAYLOAD_OFFSET).1
+    }
+}
+
+/// An incomplete packet is one where the payload length has not been determined yet.
+///
+/// It can be transformed into an `IPv6Packet` by specifying the size of the payload, and
+/// shrinking the inner byte sequence to be as large as the packet itself (this includes setting
+/// the `payload length` header field).
+impl<'a, T: NetworkBytesMut> Incomplete<IPv6Packet<'a, T>> {
+    /// Transforms `self` into an `IPv6Packet` based on the supplied payload length.
+    ///
+    /// # Panics
+    ///
+    /// This method may panic if `payload_len` is larger than the space left in the inner byte
+    /// sequence, or does not fit in the `payload length` header field.
+    #[inline]
+    pub fn with_payload_len_unchecked(mut self, payload_len: usize) -> IPv6Packet<'a, T> {
+        {
+            let packet = &mut self.inner;
+            // This unchecked is fine as long as the total length is smaller than the length of
+            // the original slice, which should be the case if our code is not wrong.
+            packet.bytes.shrink_unchecked(HEADER_LEN + payload_len);
+            packet.set_payload_len(payload_len as u16);
+        }
+        self.inner
+    }
+}
+
+/// Computes the sum (before folding and complementing) of the IPv6 pseudo-header used by
+/// upper-layer checksums, as described in [RFC 8200].
+///
+/// [RFC 8200]: https://tools.ietf.org/html/rfc8200#section-8.1
+pub fn pseudo_header_sum(
+    src_addr: Ipv6Addr,
+    dst_addr: Ipv6Addr,
+    next_header: u8,
+    upper_layer_len: usize,
+) -> u32 {
+    let mut sum = 0u32;
+    for segment in src_addr.segments().iter().chain(dst_addr.segments().iter()) {
+        sum += u32::from(*segment);
+    }
+    let len = upper_layer_len as u32;
+    sum += len >> 16;
+    sum += len & 0xffff;
+    sum += u32::from(next_header);
+    sum
+}
+
+/// This function checks if `buf` may hold an IPv6Packet heading towards the given address. Cannot
+/// produce false negatives.
+#[inline]
+pub fn test_speculative_dst_addr(buf: &[u8], addr: Ipv6Addr) -> bool {
+    // The unchecked methods are safe because we actually check the buffer length beforehand.
+    if buf.len() >= ethernet::PAYLOAD_OFFSET + HEADER_LEN {
+        let bytes = &buf[ethernet::PAYLOAD_OFFSET..];
+        if IPv6Packet::from_bytes_unchecked(bytes).destination_address() == addr {
+            return true;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt;
+
+    use net_util::MacAddr;
+    use pdu::ethernet::{EthernetFrame, ETHERTYPE_IPV6};
+
+    use super::*;
+
+    impl<'a, T: NetworkBytes> fmt::Debug for IPv6Packet<'a, T> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "(IPv6 packet)")
+        }
+    }
+
+    impl<'a, T: NetworkBytes> fmt::Debug for Incomplete<IPv6Packet<'a, T>> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "(Incomplete IPv6 packet)")
+        }
+    }
+
+    #[test]
+    fn test_set_get() {
+        let mut a = [0u8; 100];
+        let mut p = IPv6Packet::from_bytes_unchecked(a.as_mut());
+
+        assert_eq!(p.version(), 0);
+        assert_eq!(p.traffic_class(), 0);
+        assert_eq!(p.flow_label(), 0);
+        p.set_version_tc_and_flow_label(IPV6_VERSION, 0xab, 0xf_1234);
+        assert_eq!(p.version(), IPV6_VERSION);
+        assert_eq!(p.traffic_class(), 0xab);
+        assert_eq!(p.flow_label(), 0xf_1234);
+
+        assert_eq!(p.payload_len(), 0);
+        p.set_payload_len(60);
+        assert_eq!(p.payload_len(), 60);
+
+        assert_eq!(p.next_header(), 0);
+        p.set_next_header(NEXT_HEADER_ICMPV6);
+        assert_eq!(p.next_header(), NEXT_HEADER_ICMPV6);
+
+        assert_eq!(p.hop_limit(), 0);
+        p.set_hop_limit(64);
+        assert_eq!(p.hop_limit(), 64);
+
+        let src = "fe80::1".parse::<Ipv6Addr>().unwrap();
+        let dst = "fe80::a9fe:a9fe".parse::<Ipv6Addr>().unwrap();
+
+        assert_eq!(p.source_address(), Ipv6Addr::from([0u8; 16]));
+        p.set_source_address(src);
+        assert_eq!(p.source_address(), src);
+
+        assert_eq!(p.destination_address(), Ipv6Addr::from([0u8; 16]));
+        p.set_destination_address(dst);
+        assert_eq!(p.destination_address(), dst);
+
+        assert_eq!(p.payload().len(), 60);
+        assert_eq!(p.len(), 100);
+    }
+
+    #[test]
+    fn test_constructors() {
+        let mut buf = [1u8; 100];
+        let src = "fe80::1".parse::<Ipv6Addr>().unwrap();
+        let dst = "fe80::2".parse::<Ipv6Addr>().unwrap();
+
+        assert_eq!(
+            IPv6Packet::write_header(&mut buf[..HEADER_LEN - 1], NEXT_HEADER_ICMPV6, src, dst)
+                .unwrap_err(),
+            Error::SliceTooShort
+        );
+
+        let len = IPv6Packet::write_header(buf.as_mut(), NEXT_HEADER_ICMPV6, src, dst)
+            .unwrap()
+            .with_payload_len_unchecked(10)
+            .len();
+        assert_eq!(len, HEADER_LEN + 10);
+
+        {
+            let p = IPv6Packet::from_bytes(&buf[..len]).unwrap();
+            assert_eq!(p.version(), IPV6_VERSION);
+            assert_eq!(p.traffic_class(), 0);
+            assert_eq!(p.flow_label(), 0);
+            assert_eq!(p.payload_len(), 10);
+            assert_eq!(p.next_header(), NEXT_HEADER_ICMPV6);
+            assert_eq!(p.hop_limit(), DEFAULT_HOP_LIMIT);
+            assert_eq!(p.source_address(), src);
+            assert_eq!(p.destination_address(), dst);
+            assert_eq!(p.payload(), &[1u8; 10]);
+        }
+
+        assert_eq!(
+            IPv6Packet::from_bytes(&buf[..HEADER_LEN - 1]).unwrap_err(),
+            Error::SliceTooShort
+        );
+        assert_eq!(
+            IPv6Packet::from_bytes(&buf[..len + 1]).unwrap_err(),
+            Error::SliceExactLen
+        );
+
+        IPv6Packet::from_bytes_unchecked(&mut buf[..len]).set_payload_len(0);
+        assert_eq!(
+            IPv6Packet::from_bytes(&buf[..len]).unwrap_err(),
+            Error::InvalidPayloadLen
+        );
+
+        IPv6Packet::from_bytes_unchecked(&mut buf[..len]).set_version_tc_and_flow_label(4, 0, 0);
+        assert_eq!(
+            IPv6Packet::from_bytes(&buf[..len]).unwrap_err(),
+            Error::Version
+        );
+    }
+
+    #[test]
+    fn test_pseudo_header_sum() {
+        let src = "fe80::1".parse::<Ipv6Addr>().unwrap();
+        let dst = "ff02::1:ff00:2".parse::<Ipv6Addr>().unwrap();
+        assert_eq!(
+            pseudo_header_sum(src, dst, NEXT_HEADER_ICMPV6, 0x1_0020),
+            0xfe80 + 1 + 0xff02 + 1 + 0xff00 + 2 + 1 + 0x20 + 0x3a
+        );
+    }
+
+    #[test]
+    fn test_speculative() {
+        let mut buf = [0u8; 1000];
+        let mac = MacAddr::from_bytes_unchecked(&[0; 6]);
+        let addr = "fe80::a9fe:a9fe".parse::<Ipv6Addr>().unwrap();
+
+        {
+            let mut eth = EthernetFrame::write_incomplete(buf.as_mut(), mac, mac, ETHERTYPE_IPV6)
+                .unwrap();
+            IPv6Packet::from_bytes_unchecked(eth.inner_mut().payload_mut())
+                .set_destination_address(addr);
+        }
+        assert!(test_speculative_dst_addr(buf.as_ref(), addr));
+        assert!(!test_speculative_dst_addr(
+            buf.as_ref(),
+            "fe80::1".parse::<Ipv6Addr>().unwrap()
+        ));
+        assert!(!test_speculative_dst_addr(
+            &buf[..ethernet::PAYLOAD_OFFSET + HEADER_LEN - 1],
+            addr
+        ));
+    }
+}
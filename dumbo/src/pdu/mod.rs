@@ -10,8 +10,12 @@
 pub mod arp;
 pub mod bytes;
 pub mod ethernet;
+pub mod icmp;
 pub mod ipv4;
+pub mod ipv6;
+pub mod ndp;
 pub mod tcp;
+pub mod udp;
 
 /// This is the baseline definition of the `Incomplete` struct, which wraps a PDU that does is
 /// still missing some values or content.
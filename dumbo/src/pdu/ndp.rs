@@ -0,0 +1,506 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Contains logic that helps with handling NDP neighbor solicitation and neighbor advertisement
+//! messages, which play the role of ARP requests and replies for IPv6 over Ethernet.
+//!
+//! These messages are carried by ICMPv6, and their layout is described in [RFC 4861]. The only
+//! option we interpret is the source/target link-layer address option.
+//!
+//! [RFC 4861]: https://tools.ietf.org/html/rfc4861#section-4.3
+
+use std::net::Ipv6Addr;
+use std::result::Result;
+
+use super::bytes::{InnerBytes, NetworkBytes, NetworkBytesMut};
+use super::ipv6::{pseudo_header_sum, NEXT_HEADER_ICMPV6};
+use net_util::{MacAddr, MAC_ADDR_LEN};
+
+const TYPE_OFFSET: usize = 0;
+const CODE_OFFSET: usize = 1;
+const CHECKSUM_OFFSET: usize = 2;
+const FLAGS_OFFSET: usize = 4;
+const TARGET_ADDRESS_OFFSET: usize = 8;
+const OPTIONS_OFFSET: usize = 24;
+
+const IPV6_ADDR_LEN: usize = 16;
+
+// Options are made of a type byte, a length byte (in units of 8 bytes), and the option data.
+const OPTION_UNIT_LEN: usize = 8;
+const OPTION_SOURCE_LINK_LAYER_ADDRESS: u8 = 1;
+const OPTION_TARGET_LINK_LAYER_ADDRESS: u8 = 2;
+const LINK_LAYER_ADDRESS_OPTION_LEN: usize = 8;
+
+/// The ICMPv6 message type associated with neighbor solicitations.
+pub const TYPE_NEIGHBOR_SOLICITATION: u8 = 135;
+/// The ICMPv6 message type associated with neighbor advertisements.
+pub const TYPE_NEIGHBOR_ADVERTISEMENT: u8 = 136;
+
+/// The `router` flag of neighbor advertisements.
+pub const FLAG_ROUTER: u8 = 0x80;
+/// The `solicited` flag of neighbor advertisements.
+pub const FLAG_SOLICITED: u8 = 0x40;
+/// The `override` flag of neighbor advertisements.
+pub const FLAG_OVERRIDE: u8 = 0x20;
+
+/// The length of a neighbor message which carries a single link-layer address option, which is
+/// what this module writes.
+pub const NEIGHBOR_MESSAGE_LEN: usize = OPTIONS_OFFSET + LINK_LAYER_ADDRESS_OPTION_LEN;
+
+/// Represents errors which may occur while parsing or writing a message.
+#[cfg_attr(test, derive(Debug, PartialEq))]
+pub enum Error {
+    /// Invalid checksum.
+    Checksum,
+    /// Invalid ICMPv6 code.
+    Code,
+    /// Invalid ICMPv6 message type.
+    MessageType,
+    /// The target address of a neighbor solicitation is a multicast address.
+    MulticastTarget,
+    /// An option has an invalid length.
+    OptionLen,
+    /// The provided slice is too short.
+    SliceTooShort,
+}
+
+/// The inner bytes will be interpreted as an ICMPv6 neighbor solicitation or neighbor
+/// advertisement message (including the ICMPv6 header).
+pub struct NeighborMessage<'a, T: 'a> {
+    bytes: InnerBytes<'a, T>,
+}
+
+impl<'a, T: NetworkBytes> NeighborMessage<'a, T> {
+    /// Interprets the given bytes as a neighbor message, without doing any validity checks
+    /// beforehand.
+    ///
+    ///  # Panics
+    ///
+    /// This method does not panic, but further method calls on the resulting object may panic if
+    /// `bytes` contains invalid input.
+    #[inline]
+    pub fn from_bytes_unchecked(bytes: T) -> Self {
+        NeighborMessage {
+            bytes: InnerBytes::new(bytes),
+        }
+    }
+
+    /// Tries to interpret a byte slice as a valid neighbor solicitation.
+    ///
+    /// The `verify_checksum` parameter must contain the source and destination addresses from the
+    /// enclosing IPv6 packet if the checksum must be validated.
+    pub fn solicitation_from_bytes(
+        bytes: T,
+        verify_checksum: Option<(Ipv6Addr, Ipv6Addr)>,
+    ) -> Result<Self, Error> {
+        let maybe = NeighborMessage::from_bytes(bytes, verify_checksum)?;
+
+        if maybe.message_type() != TYPE_NEIGHBOR_SOLICITATION {
+            return Err(Error::MessageType);
+        }
+
+        if maybe.target_address().is_multicast() {
+            return Err(Error::MulticastTarget);
+        }
+
+        Ok(maybe)
+    }
+
+    /// Tries to interpret a byte slice as a valid neighbor solicitation or advertisement. The
+    /// options are validated as well, so that `link_layer_address_option` can be safely called
+    /// afterwards.
+    pub fn from_bytes(
+        bytes: T,
+        verify_checksum: Option<(Ipv6Addr, Ipv6Addr)>,
+    ) -> Result<Self, Error> {
+        if bytes.len() < OPTIONS_OFFSET {
+            return Err(Error::SliceTooShort);
+        }
+
+        let maybe = NeighborMessage::from_bytes_unchecked(bytes);
+
+        let message_type = maybe.message_type();
+        if message_type != TYPE_NEIGHBOR_SOLICITATION && message_type != TYPE_NEIGHBOR_ADVERTISEMENT
+        {
+            return Err(Error::MessageType);
+        }
+
+        if maybe.code() != 0 {
+            return Err(Error::Code);
+        }
+
+        if let Some((src_addr, dst_addr)) = verify_checksum {
+            if maybe.compute_checksum(src_addr, dst_addr) != 0 {
+                return Err(Error::Checksum);
+            }
+        }
+
+        // Walk the options, to make sure the length of each is valid.
+        let options = maybe.options();
+        let mut offset = 0;
+        while offset < options.len() {
+            if options.len() - offset < 2 {
+                return Err(Error::OptionLen);
+            }
+            let option_len = options[offset + 1] as usize * OPTION_UNIT_LEN;
+            if option_len == 0 || offset + option_len > options.len() {
+                return Err(Error::OptionLen);
+            }
+            offset += option_len;
+        }
+
+        Ok(maybe)
+    }
+
+    /// Returns the ICMPv6 message type.
+    #[inline]
+    pub fn message_type(&self) -> u8 {
+        self.bytes[TYPE_OFFSET]
+    }
+
+    /// Returns the ICMPv6 code.
+    #[inline]
+    pub fn code(&self) -> u8 {
+        self.bytes[CODE_OFFSET]
+    }
+
+    /// Returns the checksum value.
+    #[inline]
+    pub fn checksum(&self) -> u16 {
+        self.bytes.ntohs_unchecked(CHECKSUM_OFFSET)
+    }
+
+    /// Returns the flags of the message (only meaningful for advertisements).
+    #[inline]
+    pub fn flags(&self) -> u8 {
+        self.bytes[FLAGS_OFFSET]
+    }
+
+    /// Returns the target address.
+    #[inline]
+    pub fn target_address(&self) -> Ipv6Addr {
+        let mut octets = [0u8; IPV6_ADDR_LEN];
+        octets.copy_from_slice(
+            &self.bytes[TARGET_ADDRESS_OFFSET..TARGET_ADDRESS_OFFSET + IPV6_ADDR_LEN],
+        );
+        Ipv6Addr::from(octets)
+    }
+
+    /// Returns the bytes which hold the options of the message.
+    #[inline]
+    pub fn options(&self) -> &[u8] {
+        &self.bytes[OPTIONS_OFFSET..]
+    }
+
+    /// Returns the link-layer address carried by the source (for solicitations) or target (for
+    /// advertisements) link-layer address option, if present.
+    ///
+    /// # Panics
+    ///
+    /// This method may panic if the options were not validated beforehand (by `from_bytes`).
+    pub fn link_layer_address_option(&self) -> Option<MacAddr> {
+        let wanted_type = if self.message_type() == TYPE_NEIGHBOR_SOLICITATION {
+            OPTION_SOURCE_LINK_LAYER_ADDRESS
+        } else {
+            OPTION_TARGET_LINK_LAYER_ADDRESS
+        };
+
+        let options = self.options();
+        let mut offset = 0;
+        while offset < options.len() {
+            let option_len = options[offset + 1] as usize * OPTION_UNIT_LEN;
+            if options[offset] == wanted_type && option_len == LINK_LAYER_ADDRESS_OPTION_LEN {
+                return Some(MacAddr::from_bytes_unchecked(
+                    &options[offset + 2..offset + 2 + MAC_ADDR_LEN],
+                ));
+            }
+            offset += option_len;
+        }
+        None
+    }
+
+    /// Returns the length of the message.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Computes the ICMPv6 checksum of the message, which covers the IPv6 pseudo-header.
+    pub fn compute_checksum(&self, src_addr: Ipv6Addr, dst_addr: Ipv6Addr) -> u16 {
+        let len = self.len();
+        let mut sum = pseudo_header_sum(src_addr, dst_addr, NEXT_HEADER_ICMPV6, len);
+
+        for i in 0..len / 2 {
+            sum += self.bytes.ntohs_unchecked(i * 2) as u32;
+        }
+
+        if len % 2 != 0 {
+            sum += (self.bytes[len - 1] as u32) << 8;
+        }
+
+        while sum >> 16 != 0 {
+            sum = (sum & 0xffff) + (sum >> 16);
+        }
+
+        !(sum as u16)
+    }
+}
+
+impl<'a, T: NetworkBytesMut> NeighborMessage<'a, T> {
+    fn write_raw(
+        buf: T,
+        message_type: u8,
+        flags: u8,
+        target: Ipv6Addr,
+        link_layer_addr: MacAddr,
+        src_addr: Ipv6Addr,
+        dst_addr: Ipv6Addr,
+    ) -> Result<Self, Error> {
+        if buf.len() < NEIGHBOR_MESSAGE_LEN {
+            return Err(Error::SliceTooShort);
+        }
+
+        let option_type = if message_type == TYPE_NEIGHBOR_SOLICITATION {
+            OPTION_SOURCE_LINK_LAYER_ADDRESS
+        } else {
+            OPTION_TARGET_LINK_LAYER_ADDRESS
+        };
+
+        let mut message = NeighborMessage::from_bytes_unchecked(buf);
+        message.bytes.shrink_unchecked(NEIGHBOR_MESSAGE_LEN);
+
+        message.set_message_type(message_type);
+        message.set_code(0);
+        message.set_checksum(0);
+        message.set_flags(flags);
+        message.set_target_address(target);
+        {
+            let options = message.options_mut();
+            options[0] = option_type;
+            options[1] = (LINK_LAYER_ADDRESS_OPTION_LEN / OPTION_UNIT_LEN) as u8;
+            options[2..2 + MAC_ADDR_LEN].copy_from_slice(link_layer_addr.get_bytes());
+        }
+        let checksum = message.compute_checksum(src_addr, dst_addr);
+        message.set_checksum(checksum);
+
+        Ok(message)
+    }
+
+    /// Attempts to write a neighbor solicitation for `target` to `buf`, which carries the source
+    /// link-layer address option. The addresses of the enclosing IPv6 packet are required to
+    /// compute the checksum.
+    pub fn write_solicitation(
+        buf: T,
+        src_addr: Ipv6Addr,
+        dst_addr: Ipv6Addr,
+        target: Ipv6Addr,
+        source_link_layer_addr: MacAddr,
+    ) -> Result<Self, Error> {
+        Self::write_raw(
+            buf,
+            TYPE_NEIGHBOR_SOLICITATION,
+            0,
+            target,
+            source_link_layer_addr,
+            src_addr,
+            dst_addr,
+        )
+    }
+
+    /// Attempts to write a neighbor advertisement for `target` to `buf`, which carries the target
+    /// link-layer address option. The addresses of the enclosing IPv6 packet are required to
+    /// compute the checksum.
+    pub fn write_advertisement(
+        buf: T,
+        src_addr: Ipv6Addr,
+        dst_addr: Ipv6Addr,
+        target: Ipv6Addr,
+        flags: u8,
+        target_link_layer_addr: MacAddr,
+    ) -> Result<Self, Error> {
+        Self::write_raw(
+            buf,
+            TYPE_NEIGHBOR_ADVERTISEMENT,
+            flags,
+            target,
+            target_link_layer_addr,
+            src_addr,
+            dst_addr,
+        )
+    }
+
+    /// Sets the ICMPv6 message type.
+    #[inline]
+    pub fn set_message_type(&mut self, value: u8) {
+        self.bytes[TYPE_OFFSET] = value;
+    }
+
+    /// Sets the ICMPv6 code.
+    #[inline]
+    pub fn set_code(&mut self, value: u8) {
+        self.bytes[CODE_OFFSET] = value;
+    }
+
+    /// Sets the value of the `checksum` field.
+    #[inline]
+    pub fn set_checksum(&mut self, value: u16) {
+        self.bytes.htons_unchecked(CHECKSUM_OFFSET, value);
+    }
+
+    /// Sets the flags, and clears the reserved bytes which follow them.
+    #[inline]
+    pub fn set_flags(&mut self, value: u8) {
+        self.bytes.htonl_unchecked(FLAGS_OFFSET, (value as u32) << 24);
+    }
+
+    /// Sets the target address.
+    #[inline]
+    pub fn set_target_address(&mut self, addr: Ipv6Addr) {
+        self.bytes[TARGET_ADDRESS_OFFSET..TARGET_ADDRESS_OFFSET + IPV6_ADDR_LEN]
+            .copy_from_slice(&addr.octets());
+    }
+
+    /// Returns a mutable slice which holds the options of the message.
+    #[inline]
+    pub fn options_mut(&mut self) -> &mut [u8] {
+        &mut self.bytes[OPTIONS_OFFSET..]
+    }
+}
+
+/// Returns the solicited-node multicast address associated with `addr`, which is where neighbor
+/// solicitations for `addr` are usually sent.
+pub fn solicited_node_multicast_addr(addr: Ipv6Addr) -> Ipv6Addr {
+    let octets = addr.octets();
+    Ipv6Addr::from([
+        0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01, 0xff, octets[13], octets[14], octets[15],
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt;
+
+    use super::*;
+
+    impl<'a, T: NetworkBytes> fmt::Debug for NeighborMessage<'a, T> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "(NDP neighbor message)")
+        }
+    }
+
+    #[test]
+    fn test_solicited_node_multicast_addr() {
+        assert_eq!(
+            solicited_node_multicast_addr("fe80::a9fe:a9fe".parse().unwrap()),
+            "ff02::1:fffe:a9fe".parse::<Ipv6Addr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_write_and_parse() {
+        let mut a = [0u8; 100];
+        let src = "fe80::1".parse::<Ipv6Addr>().unwrap();
+        let target = "fe80::a9fe:a9fe".parse::<Ipv6Addr>().unwrap();
+        let dst = solicited_node_multicast_addr(target);
+        let mac = MacAddr::parse_str("12:34:56:78:9a:bc").unwrap();
+
+        assert_eq!(
+            NeighborMessage::write_solicitation(&mut a[..10], src, dst, target, mac).unwrap_err(),
+            Error::SliceTooShort
+        );
+
+        let len = NeighborMessage::write_solicitation(a.as_mut(), src, dst, target, mac)
+            .unwrap()
+            .len();
+        assert_eq!(len, NEIGHBOR_MESSAGE_LEN);
+
+        {
+            let ns = NeighborMessage::solicitation_from_bytes(&a[..len], Some((src, dst))).unwrap();
+            assert_eq!(ns.message_type(), TYPE_NEIGHBOR_SOLICITATION);
+            assert_eq!(ns.code(), 0);
+            assert_eq!(ns.flags(), 0);
+            assert_eq!(ns.target_address(), target);
+            assert_eq!(ns.link_layer_address_option(), Some(mac));
+        }
+
+        // The checksum covers the pseudo-header.
+        assert_eq!(
+            NeighborMessage::solicitation_from_bytes(&a[..len], Some((target, dst))).unwrap_err(),
+            Error::Checksum
+        );
+
+        // A solicitation without options.
+        {
+            let mut ns = NeighborMessage::from_bytes_unchecked(&mut a[..OPTIONS_OFFSET]);
+            ns.set_checksum(0);
+            let checksum = ns.compute_checksum(src, dst);
+            ns.set_checksum(checksum);
+        }
+        {
+            let ns = NeighborMessage::solicitation_from_bytes(&a[..OPTIONS_OFFSET], Some((src, dst)))
+                .unwrap();
+            assert_eq!(ns.link_layer_address_option(), None);
+        }
+
+        // Invalid option lengths.
+        a[OPTIONS_OFFSET + 1] = 0;
+        assert_eq!(
+            NeighborMessage::solicitation_from_bytes(&a[..len], None).unwrap_err(),
+            Error::OptionLen
+        );
+        a[OPTIONS_OFFSET + 1] = 2;
+        assert_eq!(
+            NeighborMessage::solicitation_from_bytes(&a[..len], None).unwrap_err(),
+            Error::OptionLen
+        );
+        assert_eq!(
+            NeighborMessage::solicitation_from_bytes(&a[..OPTIONS_OFFSET + 1], None).unwrap_err(),
+            Error::OptionLen
+        );
+
+        let len = NeighborMessage::write_advertisement(
+            a.as_mut(),
+            target,
+            src,
+            target,
+            FLAG_SOLICITED | FLAG_OVERRIDE,
+            mac,
+        ).unwrap()
+        .len();
+
+        {
+            let na = NeighborMessage::from_bytes(&a[..len], Some((target, src))).unwrap();
+            assert_eq!(na.message_type(), TYPE_NEIGHBOR_ADVERTISEMENT);
+            assert_eq!(na.flags(), FLAG_SOLICITED | FLAG_OVERRIDE);
+            assert_eq!(na.target_address(), target);
+            assert_eq!(na.link_layer_address_option(), Some(mac));
+        }
+
+        // Advertisements are not solicitations.
+        assert_eq!(
+            NeighborMessage::solicitation_from_bytes(&a[..len], None).unwrap_err(),
+            Error::MessageType
+        );
+
+        // Invalid code.
+        a[CODE_OFFSET] = 1;
+        assert_eq!(
+            NeighborMessage::from_bytes(&a[..len], None).unwrap_err(),
+            Error::Code
+        );
+
+        // Multicast targets are not allowed in solicitations.
+        let len = NeighborMessage::write_solicitation(a.as_mut(), src, dst, dst, mac)
+            .unwrap()
+            .len();
+        assert_eq!(
+            NeighborMessage::solicitation_from_bytes(&a[..len], None).unwrap_err(),
+            Error::MulticastTarget
+        );
+
+        assert_eq!(
+            NeighborMessage::from_bytes(&a[..OPTIONS_OFFSET - 1], None).unwrap_err(),
+            Error::SliceTooShort
+        );
+    }
+}
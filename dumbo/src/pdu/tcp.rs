@@ -9,12 +9,13 @@
 
 use std::cmp::min;
 use std::convert::From;
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr};
 use std::num::NonZeroU16;
 use std::result::Result;
 
 use super::bytes::{InnerBytes, NetworkBytes, NetworkBytesMut};
 use super::ipv4::PROTOCOL_TCP;
+use super::ipv6;
 use super::Incomplete;
 use ByteBuffer;
 
@@ -34,12 +35,27 @@ const MAX_HEADER_LEN: usize = 60;
 const OPTION_KIND_EOL: u8 = 0x00;
 const OPTION_KIND_NOP: u8 = 0x01;
 const OPTION_KIND_MSS: u8 = 0x02;
+const OPTION_KIND_WINDOW_SCALE: u8 = 0x03;
+const OPTION_KIND_SACK_PERMITTED: u8 = 0x04;
+const OPTION_KIND_SACK: u8 = 0x05;
 
 const OPTION_LEN_MSS: usize = 0x04;
+const OPTION_LEN_WINDOW_SCALE: usize = 0x03;
+const OPTION_LEN_SACK_PERMITTED: usize = 0x02;
+// The length of the SACK option without any blocks.
+const OPTION_LEN_SACK_BASE: usize = 0x02;
+const SACK_BLOCK_LEN: usize = 0x08;
 
 // An arbitrarily chosen value, used for sanity checks.
 const MSS_MIN: u16 = 100;
 
+/// The largest window scale shift count allowed by RFC 7323.
+pub const MAX_WINDOW_SCALE: u8 = 14;
+
+/// The maximum number of blocks a SACK option can hold (since the TCP options take up at most
+/// 40 bytes).
+pub const MAX_SACK_BLOCKS: usize = 4;
+
 bitflags! {
     /// Represents the TCP header flags, with the exception of `NS`.
     ///
@@ -81,10 +97,115 @@ pub enum Error {
     MssOption,
     /// The remaining segment length cannot accommodate the MSS option.
     MssRemaining,
+    /// An option has an invalid length, or does not fit within the header.
+    OptionLen,
+    /// The SACK option contains too many blocks.
+    SackOption,
     /// The specified slice is shorter than the header length.
     SliceTooShort,
 }
 
+/// The TCP header options we know how to parse and write. All other options are ignored.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TcpOptions {
+    /// The value of the MSS option.
+    pub mss: Option<u16>,
+    /// The shift count of the window scale option (RFC 7323), which is only valid on `SYN`
+    /// segments.
+    pub window_scale: Option<u8>,
+    /// Whether the SACK-permitted option (RFC 2018) is present, which is only valid on `SYN`
+    /// segments.
+    pub sack_permitted: bool,
+    // The left and right edges of the SACK blocks (RFC 2018); only the first sack_block_count
+    // entries are valid.
+    sack_blocks: [(u32, u32); MAX_SACK_BLOCKS],
+    sack_block_count: usize,
+}
+
+impl TcpOptions {
+    /// Creates a `TcpOptions` object which only holds the MSS option.
+    #[inline]
+    pub fn with_mss(mss: u16) -> Self {
+        TcpOptions {
+            mss: Some(mss),
+            ..Default::default()
+        }
+    }
+
+    /// Returns the blocks of the SACK option, as (left edge, right edge) sequence number pairs.
+    #[inline]
+    pub fn sack_blocks(&self) -> &[(u32, u32)] {
+        &self.sack_blocks[..self.sack_block_count]
+    }
+
+    /// Appends a block to the SACK option.
+    pub fn add_sack_block(&mut self, left_edge: u32, right_edge: u32) -> Result<(), Error> {
+        if self.sack_block_count == MAX_SACK_BLOCKS {
+            return Err(Error::SackOption);
+        }
+        self.sack_blocks[self.sack_block_count] = (left_edge, right_edge);
+        self.sack_block_count += 1;
+        Ok(())
+    }
+
+    // Returns the number of bytes taken up by the options once written. Every option except MSS
+    // is preceded by NOPs, so that the total length is a multiple of 4, as required for the TCP
+    // header.
+    fn len(&self) -> usize {
+        let mut len = 0;
+        if self.mss.is_some() {
+            len += OPTION_LEN_MSS;
+        }
+        if self.sack_permitted {
+            len += 2 + OPTION_LEN_SACK_PERMITTED;
+        }
+        if self.window_scale.is_some() {
+            len += 1 + OPTION_LEN_WINDOW_SCALE;
+        }
+        if self.sack_block_count > 0 {
+            len += 2 + OPTION_LEN_SACK_BASE + self.sack_block_count * SACK_BLOCK_LEN;
+        }
+        len
+    }
+
+    // Writes the options to buf, starting at offset. There must be room for self.len() bytes.
+    fn write_unchecked<T: NetworkBytesMut>(&self, buf: &mut T, offset: usize) {
+        let mut i = offset;
+        if let Some(value) = self.mss {
+            buf[i] = OPTION_KIND_MSS;
+            buf[i + 1] = OPTION_LEN_MSS as u8;
+            buf.htons_unchecked(i + 2, value);
+            i += OPTION_LEN_MSS;
+        }
+        if self.sack_permitted {
+            buf[i] = OPTION_KIND_NOP;
+            buf[i + 1] = OPTION_KIND_NOP;
+            buf[i + 2] = OPTION_KIND_SACK_PERMITTED;
+            buf[i + 3] = OPTION_LEN_SACK_PERMITTED as u8;
+            i += 2 + OPTION_LEN_SACK_PERMITTED;
+        }
+        if let Some(shift) = self.window_scale {
+            buf[i] = OPTION_KIND_NOP;
+            buf[i + 1] = OPTION_KIND_WINDOW_SCALE;
+            buf[i + 2] = OPTION_LEN_WINDOW_SCALE as u8;
+            buf[i + 3] = shift;
+            i += 1 + OPTION_LEN_WINDOW_SCALE;
+        }
+        if self.sack_block_count > 0 {
+            buf[i] = OPTION_KIND_NOP;
+            buf[i + 1] = OPTION_KIND_NOP;
+            buf[i + 2] = OPTION_KIND_SACK;
+            buf[i + 3] = (OPTION_LEN_SACK_BASE + self.sack_block_count * SACK_BLOCK_LEN) as u8;
+            i += 2 + OPTION_LEN_SACK_BASE;
+            for &(left_edge, right_edge) in self.sack_blocks() {
+                buf.htonl_unchecked(i, left_edge);
+                buf.htonl_unchecked(i + 4, right_edge);
+                i += SACK_BLOCK_LEN;
+            }
+        }
+    }
+}
+
 // TODO: The implementation of TcpSegment is IPv4 specific in regard to checksum computation. Maybe
 // make it more generic at some point.
 
@@ -221,6 +342,18 @@ impl<'a, T: NetworkBytes> TcpSegment<'a, T> {
         sum += PROTOCOL_TCP as u32;
         sum += len as u32;
 
+        self.checksum_with_pseudo_header_sum(sum)
+    }
+
+    /// Computes the TCP checksum of a segment carried by an IPv6 packet, which uses a different
+    /// pseudo-header.
+    pub fn compute_checksum_ipv6(&self, src_addr: Ipv6Addr, dst_addr: Ipv6Addr) -> u16 {
+        let sum = ipv6::pseudo_header_sum(src_addr, dst_addr, PROTOCOL_TCP, self.len());
+        self.checksum_with_pseudo_header_sum(sum)
+    }
+
+    fn checksum_with_pseudo_header_sum(&self, mut sum: u32) -> u16 {
+        let len = self.len();
         for i in 0..len / 2 {
             sum += self.bytes.ntohs_unchecked(i * 2) as u32;
         }
@@ -236,53 +369,100 @@ impl<'a, T: NetworkBytes> TcpSegment<'a, T> {
         !(sum as u16)
     }
 
-    /// Parses TCP header options (only `MSS` is supported for now).
+    /// Parses the TCP header options (`MSS`, window scale, SACK-permitted and SACK are
+    /// supported), skipping the ones we don't know about.
     ///
-    /// If no error is encountered, returns the `MSS` value, or `None` if the option is not
-    /// present.
+    /// A window scale shift count greater than `MAX_WINDOW_SCALE` is replaced by
+    /// `MAX_WINDOW_SCALE`, as recommended by RFC 7323.
     ///
     /// # Panics
     ///
     /// This method may panic if the value of `header_len` is invalid.
-    pub fn parse_mss_option_unchecked(
-        &self,
-        header_len: usize,
-    ) -> Result<Option<NonZeroU16>, Error> {
+    pub fn parse_options_unchecked(&self, header_len: usize) -> Result<TcpOptions, Error> {
         let b = self.options_unchecked(header_len);
+        let mut options = TcpOptions::default();
         let mut i = 0;
 
         // All TCP options (except EOL and NOP) are encoded using x bytes (x >= 2), where the first
-        // byte represents the option kind, the second is the option length (including these first two
-        // bytes), and finally the next x - 2 bytes represent option data. The length of the MSS option
-        // is 4, so the option data encodes an u16 in network order.
-
-        // The MSS option is 4 bytes wide, so we need at least 4 more bytes to look for it.
-        while i + 3 < b.len() {
-            match b[i] {
+        // byte represents the option kind, the second is the option length (including these first
+        // two bytes), and finally the next x - 2 bytes represent option data.
+        while i < b.len() {
+            let kind = b[i];
+            match kind {
                 OPTION_KIND_EOL => break,
                 OPTION_KIND_NOP => {
                     i += 1;
                     continue;
                 }
+                _ => (),
+            }
+
+            if i + 1 >= b.len() {
+                return Err(Error::OptionLen);
+            }
+            let len = b[i + 1] as usize;
+            if len < 2 || i + len > b.len() {
+                return Err(Error::OptionLen);
+            }
+
+            match kind {
                 OPTION_KIND_MSS => {
-                    // Read from option data (we skip checking if the len is valid).
-                    // TODO: To be super strict, we should make sure there aren't additional MSS
-                    // options present (which would be super wrong). Should we be super strict?
+                    if len != OPTION_LEN_MSS {
+                        return Err(Error::OptionLen);
+                    }
                     let mss = b.ntohs_unchecked(i + 2);
                     if mss < MSS_MIN {
                         return Err(Error::MssOption);
                     }
-                    // The unwarp() is safe because mms >= MSS_MIN at this point.
-                    return Ok(Some(NonZeroU16::new(mss).unwrap()));
+                    options.mss = Some(mss);
                 }
-                _ => {
-                    // Some other option; just skip opt_len bytes in total.
-                    i += b[i + 1] as usize;
-                    continue;
+                OPTION_KIND_WINDOW_SCALE => {
+                    if len != OPTION_LEN_WINDOW_SCALE {
+                        return Err(Error::OptionLen);
+                    }
+                    options.window_scale = Some(min(b[i + 2], MAX_WINDOW_SCALE));
+                }
+                OPTION_KIND_SACK_PERMITTED => {
+                    if len != OPTION_LEN_SACK_PERMITTED {
+                        return Err(Error::OptionLen);
+                    }
+                    options.sack_permitted = true;
                 }
+                OPTION_KIND_SACK => {
+                    if len < OPTION_LEN_SACK_BASE + SACK_BLOCK_LEN
+                        || (len - OPTION_LEN_SACK_BASE) % SACK_BLOCK_LEN != 0
+                    {
+                        return Err(Error::OptionLen);
+                    }
+                    let mut j = i + OPTION_LEN_SACK_BASE;
+                    while j < i + len {
+                        options.add_sack_block(b.ntohl_unchecked(j), b.ntohl_unchecked(j + 4))?;
+                        j += SACK_BLOCK_LEN;
+                    }
+                }
+                // Some other option; just skip it.
+                _ => (),
             }
+            i += len;
         }
-        Ok(None)
+        Ok(options)
+    }
+
+    /// Parses TCP header options, looking for `MSS`.
+    ///
+    /// If no error is encountered, returns the `MSS` value, or `None` if the option is not
+    /// present.
+    ///
+    /// # Panics
+    ///
+    /// This method may panic if the value of `header_len` is invalid.
+    pub fn parse_mss_option_unchecked(
+        &self,
+        header_len: usize,
+    ) -> Result<Option<NonZeroU16>, Error> {
+        // The unwarp() is safe because the parsed mss >= MSS_MIN.
+        self.parse_options_unchecked(header_len)
+            .map(|options| options.mss.map(|mss| NonZeroU16::new(mss).unwrap()))
     }
 
     /// Interprets `bytes` as a TCP segment without any validity checks.
@@ -429,7 +609,7 @@ impl<'a, T: NetworkBytesMut> TcpSegment<'a, T> {
     /// * `ack_number` - Acknowledgement number.
     /// * `flags_after_ns` - TCP flags to set (except `NS`, which is always set to 0).
     /// * `window_size` - Value to write in the `window size` field.
-    /// * `mss_option` - When a value is specified, use it to add a TCP MSS option to the header.
+    /// * `options` - The TCP options to add to the header.
     /// * `mss_remaining` - Represents an upper bound on the payload length (the number of bytes
     ///    used up by things like IP options have to be subtracted from the MSS). There is some
     ///    redundancy looking at this argument and the next one, so we might end up removing
@@ -447,7 +627,7 @@ impl<'a, T: NetworkBytesMut> TcpSegment<'a, T> {
         ack_number: u32,
         flags_after_ns: Flags,
         window_size: u16,
-        mss_option: Option<u16>,
+        options: &TcpOptions,
         mss_remaining: u16,
         payload: Option<(&R, usize)>,
         compute_checksum: Option<(Ipv4Addr, Ipv4Addr)>,
@@ -458,7 +638,7 @@ impl<'a, T: NetworkBytesMut> TcpSegment<'a, T> {
             ack_number,
             flags_after_ns,
             window_size,
-            mss_option,
+            options,
             mss_remaining,
             payload,
         )?.finalize(src_port, dst_port, compute_checksum))
@@ -467,9 +647,8 @@ impl<'a, T: NetworkBytesMut> TcpSegment<'a, T> {
     /// Writes an incomplete TCP segment, which is missing the `source port`, `destination port`,
     /// and `checksum` fields.
     ///
-    /// This method writes the rest of the segment, including data (when available). Only the `MSS`
-    /// option is supported for now. The `NS` flag, `URG` flag, and `urgent pointer` field are set
-    /// to 0.
+    /// This method writes the rest of the segment, including options and data (when available).
+    /// The `NS` flag, `URG` flag, and `urgent pointer` field are set to 0.
     ///
     /// # Arguments
     ///
@@ -478,7 +657,8 @@ impl<'a, T: NetworkBytesMut> TcpSegment<'a, T> {
     /// * `ack_number` - Acknowledgement number.
     /// * `flags_after_ns` - TCP flags to set (except `NS`, which is always set to 0).
     /// * `window_size` - Value to write in the `window size` field.
-    /// * `mss_option` - When a value is specified, use it to add a TCP MSS option to the header.
+    /// * `options` - The TCP options to add to the header. Their length is subtracted from
+    ///    `mss_remaining`.
     /// * `mss_remaining` - Represents an upper bound on the payload length (the number of bytes
     ///    used up by things like IP options have to be subtracted from the MSS). There is some
     ///    redundancy looking at this argument and the next one, so we might end up removing
@@ -494,24 +674,21 @@ impl<'a, T: NetworkBytesMut> TcpSegment<'a, T> {
         ack_number: u32,
         flags_after_ns: Flags,
         window_size: u16,
-        mss_option: Option<u16>,
+        options: &TcpOptions,
         mss_remaining: u16,
         payload: Option<(&R, usize)>,
     ) -> Result<Incomplete<Self>, Error> {
-        let mut mss_left = mss_remaining as usize;
-
         // We're going to need at least this many bytes.
         let mut segment_len = OPTIONS_OFFSET;
 
         // The TCP options will require this much more bytes.
-        let options_len = if mss_option.is_some() {
-            mss_left = mss_left
-                .checked_sub(OPTION_LEN_MSS)
-                .ok_or(Error::MssRemaining)?;
-            OPTION_LEN_MSS
-        } else {
-            0
-        };
+        let options_len = options.len();
+        if OPTIONS_OFFSET + options_len > MAX_HEADER_LEN {
+            return Err(Error::HeaderLen);
+        }
+        let mss_left = (mss_remaining as usize)
+            .checked_sub(options_len)
+            .ok_or(Error::MssRemaining)?;
 
         segment_len += options_len;
 
@@ -530,12 +707,7 @@ impl<'a, T: NetworkBytesMut> TcpSegment<'a, T> {
             .set_window_size(window_size)
             .set_urgent_pointer(0);
 
-        // Let's write the MSS option if we have to.
-        if let Some(value) = mss_option {
-            segment.bytes[OPTIONS_OFFSET] = OPTION_KIND_MSS;
-            segment.bytes[OPTIONS_OFFSET + 1] = OPTION_LEN_MSS as u8;
-            segment.bytes.htons_unchecked(OPTIONS_OFFSET + 2, value);
-        }
+        options.write_unchecked(&mut segment.bytes, OPTIONS_OFFSET);
 
         segment_len += if let Some((payload_buf, max_payload_bytes)) = payload {
             let left_to_read = min(payload_buf.len(), max_payload_bytes);
@@ -585,10 +757,29 @@ impl<'a, T: NetworkBytesMut> Incomplete<TcpSegment<'a, T>> {
         }
         self.inner
     }
+
+    /// The IPv6 counterpart of `finalize`, which uses the IPv6 pseudo-header when computing the
+    /// TCP checksum.
+    pub fn finalize_ipv6(
+        mut self,
+        src_port: u16,
+        dst_port: u16,
+        compute_checksum: Option<(Ipv6Addr, Ipv6Addr)>,
+    ) -> TcpSegment<'a, T> {
+        self.inner.set_source_port(src_port);
+        self.inner.set_destination_port(dst_port);
+        if let Some((src_addr, dst_addr)) = compute_checksum {
+            // Set this to 0 first.
+            self.inner.set_checksum(0);
+            let checksum = self.inner.compute_checksum_ipv6(src_addr, dst_addr);
+            self.inner.set_checksum(checksum);
+        }
+        self.inner
+    }
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use std::fmt;
 
     use super::*;
@@ -666,7 +857,7 @@ mod tests {
         let flags_after_ns = Flags::SYN | Flags::RST;
         let window_size = 19999;
         let mss_left = 1460;
-        let mss_option = Some(mss_left);
+        let options = TcpOptions::with_mss(mss_left);
         let payload = Some((b.as_ref(), b.len()));
 
         let header_len = OPTIONS_OFFSET + OPTION_LEN_MSS;
@@ -680,7 +871,7 @@ mod tests {
                 ack_number,
                 flags_after_ns,
                 window_size,
-                mss_option,
+                &options,
                 mss_left,
                 payload,
                 Some((src_addr, dst_addr)),
@@ -705,11 +896,11 @@ mod tests {
             assert_eq!(p.urgent_pointer(), 0);
 
             {
-                let options = p.options_unchecked(header_len);
-                assert_eq!(options.len(), OPTION_LEN_MSS);
-                assert_eq!(options[0], OPTION_KIND_MSS);
-                assert_eq!(options[1], OPTION_LEN_MSS as u8);
-                assert_eq!(options.ntohs_unchecked(2), mss_left);
+                let option_bytes = p.options_unchecked(header_len);
+                assert_eq!(option_bytes.len(), OPTION_LEN_MSS);
+                assert_eq!(option_bytes[0], OPTION_KIND_MSS);
+                assert_eq!(option_bytes[1], OPTION_LEN_MSS as u8);
+                assert_eq!(option_bytes.ntohs_unchecked(2), mss_left);
             }
 
             // Payload was smaller than mss_left after options.
@@ -737,7 +928,7 @@ mod tests {
                 ack_number,
                 flags_after_ns,
                 window_size,
-                mss_option,
+                &options,
                 mss_left,
                 Some((c.as_ref(), c.len())),
                 Some((src_addr, dst_addr)),
@@ -797,7 +988,7 @@ mod tests {
                 ack_number,
                 flags_after_ns,
                 window_size,
-                mss_option,
+                &options,
                 mss_left,
                 payload,
                 Some((src_addr, dst_addr)),
@@ -815,7 +1006,7 @@ mod tests {
                 ack_number,
                 flags_after_ns,
                 window_size,
-                mss_option,
+                &options,
                 0,
                 payload,
                 Some((src_addr, dst_addr)),
@@ -823,4 +1014,165 @@ mod tests {
             Error::MssRemaining
         );
     }
+
+    // The following segments were captured on the tap interface of a microVM, while the guest
+    // (172.16.0.2) was connecting to the MMDS (169.254.169.254). The Linux SYN carries the MSS,
+    // SACK-permitted, timestamp, and window scale options, while the Windows one carries MSS,
+    // window scale, and SACK-permitted. The last one is a Linux ACK which carries a SACK block.
+    const CAPTURE_SRC_ADDR: [u8; 4] = [172, 16, 0, 2];
+    const CAPTURE_DST_ADDR: [u8; 4] = [169, 254, 169, 254];
+
+    pub const LINUX_SYN: [u8; 40] = [
+        0xa8, 0xc6, 0x00, 0x50, 0x1f, 0x6a, 0x3b, 0x21, 0x00, 0x00, 0x00, 0x00,
+        0xa0, 0x02, 0xfa, 0xf0, 0x5e, 0xa4, 0x00, 0x00, 0x02, 0x04, 0x05, 0xb4,
+        0x04, 0x02, 0x08, 0x0a, 0x9c, 0x2d, 0x4e, 0x8c, 0x00, 0x00, 0x00, 0x00,
+        0x01, 0x03, 0x03, 0x07,
+    ];
+
+    pub const WINDOWS_SYN: [u8; 32] = [
+        0xc2, 0x3b, 0x00, 0x50, 0x8b, 0x0c, 0x49, 0xd2, 0x00, 0x00, 0x00, 0x00,
+        0x80, 0x02, 0xfa, 0xf0, 0xdc, 0xa5, 0x00, 0x00, 0x02, 0x04, 0x05, 0xb4,
+        0x01, 0x03, 0x03, 0x08, 0x01, 0x01, 0x04, 0x02,
+    ];
+
+    pub const LINUX_SACK_ACK: [u8; 44] = [
+        0xa8, 0xc6, 0x00, 0x50, 0x1f, 0x6a, 0x3b, 0x22, 0x5c, 0x1d, 0x1a, 0x08,
+        0xb0, 0x10, 0x01, 0xf5, 0x5a, 0xf4, 0x00, 0x00, 0x01, 0x01, 0x08, 0x0a,
+        0x9c, 0x2d, 0x50, 0x11, 0x00, 0x4d, 0x7a, 0x3e, 0x01, 0x01, 0x05, 0x0a,
+        0x5c, 0x1d, 0x1f, 0xbc, 0x5c, 0x1d, 0x2b, 0x24,
+    ];
+
+    fn parse_captured(bytes: &[u8]) -> Result<TcpOptions, Error> {
+        let addrs = (
+            Ipv4Addr::from(CAPTURE_SRC_ADDR),
+            Ipv4Addr::from(CAPTURE_DST_ADDR),
+        );
+        let segment = TcpSegment::from_bytes(bytes, Some(addrs)).unwrap();
+        segment.parse_options_unchecked(segment.header_len())
+    }
+
+    #[test]
+    fn test_parse_captured_options() {
+        let options = parse_captured(LINUX_SYN.as_ref()).unwrap();
+        assert_eq!(options.mss, Some(1460));
+        assert_eq!(options.window_scale, Some(7));
+        assert!(options.sack_permitted);
+        assert!(options.sack_blocks().is_empty());
+
+        let options = parse_captured(WINDOWS_SYN.as_ref()).unwrap();
+        assert_eq!(options.mss, Some(1460));
+        assert_eq!(options.window_scale, Some(8));
+        assert!(options.sack_permitted);
+        assert!(options.sack_blocks().is_empty());
+
+        let options = parse_captured(LINUX_SACK_ACK.as_ref()).unwrap();
+        assert_eq!(options.mss, None);
+        assert_eq!(options.window_scale, None);
+        assert!(!options.sack_permitted);
+        assert_eq!(options.sack_blocks(), &[(0x5c1d_1fbc, 0x5c1d_2b24)]);
+    }
+
+    #[test]
+    fn test_invalid_options() {
+        let mut buf = [0u8; 60];
+
+        let mut check = |option_bytes: &[u8], err: Error| {
+            let header_len = OPTIONS_OFFSET + option_bytes.len();
+            let mut segment = TcpSegment::from_bytes_unchecked(buf.as_mut());
+            segment.set_header_len_rsvd_ns(header_len, false);
+            segment.bytes[OPTIONS_OFFSET..header_len].copy_from_slice(option_bytes);
+            assert_eq!(segment.parse_options_unchecked(header_len).unwrap_err(), err);
+        };
+
+        // The length of the window scale option goes past the end of the header.
+        check(&[1, 1, 3, 4], Error::OptionLen);
+        // Bogus option length.
+        check(&[3, 1, 0, 0], Error::OptionLen);
+        // The SACK-permitted option has the wrong length.
+        check(&[4, 4, 0, 0], Error::OptionLen);
+        // SACK option with a partial block.
+        check(&[1, 1, 5, 6, 0, 0, 0, 0], Error::OptionLen);
+        // MSS value which is too small.
+        check(&[2, 4, 0, 1], Error::MssOption);
+
+        // Unknown options are skipped, and window scale values which are too large get clamped.
+        let header_len = OPTIONS_OFFSET + 8;
+        let mut segment = TcpSegment::from_bytes_unchecked(buf.as_mut());
+        segment.set_header_len_rsvd_ns(header_len, false);
+        segment.bytes[OPTIONS_OFFSET..header_len].copy_from_slice(&[0xfe, 2, 1, 3, 3, 15, 0, 0]);
+        let options = segment.parse_options_unchecked(header_len).unwrap();
+        assert_eq!(options.window_scale, Some(MAX_WINDOW_SCALE));
+    }
+
+    #[test]
+    fn test_write_options() {
+        let mut buf = [0u8; 100];
+
+        let mut options = TcpOptions::with_mss(1460);
+        options.window_scale = Some(5);
+        options.sack_permitted = true;
+        options.add_sack_block(1000, 2000).unwrap();
+        options.add_sack_block(3000, 4000).unwrap();
+        assert_eq!(options.len(), 32);
+
+        let header_len = {
+            let segment = TcpSegment::write_segment::<[u8]>(
+                buf.as_mut(),
+                1234,
+                80,
+                1,
+                2,
+                Flags::ACK,
+                1000,
+                &options,
+                100,
+                None,
+                None,
+            ).unwrap();
+            assert_eq!(segment.len(), OPTIONS_OFFSET + 32);
+            segment.header_len()
+        };
+        let segment = TcpSegment::from_bytes(&buf[..header_len], None).unwrap();
+        assert_eq!(segment.parse_options_unchecked(header_len), Ok(options));
+
+        // The options take up room which would otherwise be used for payload.
+        assert_eq!(
+            TcpSegment::write_segment::<[u8]>(
+                buf.as_mut(),
+                1234,
+                80,
+                1,
+                2,
+                Flags::ACK,
+                1000,
+                &options,
+                31,
+                None,
+                None,
+            ).unwrap_err(),
+            Error::MssRemaining
+        );
+
+        // There's no room for more than 40 bytes worth of options.
+        options.add_sack_block(5000, 6000).unwrap();
+        options.add_sack_block(7000, 8000).unwrap();
+        assert_eq!(
+            TcpSegment::write_segment::<[u8]>(
+                buf.as_mut(),
+                1234,
+                80,
+                1,
+                2,
+                Flags::ACK,
+                1000,
+                &options,
+                100,
+                None,
+                None,
+            ).unwrap_err(),
+            Error::HeaderLen
+        );
+
+        assert_eq!(options.add_sack_block(9000, 10000), Err(Error::SackOption));
+    }
 }
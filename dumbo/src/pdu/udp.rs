@@ -0,0 +1,344 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Contains support for parsing and writing UDP datagrams.
+//!
+//! The UDP header layout is described [here].
+//!
+//! [here]: https://en.wikipedia.org/wiki/User_Datagram_Protocol#Packet_structure
+
+use std::net::Ipv4Addr;
+use std::result::Result;
+
+use super::bytes::{InnerBytes, NetworkBytes, NetworkBytesMut};
+use super::ipv4::PROTOCOL_UDP;
+use super::Incomplete;
+
+const SOURCE_PORT_OFFSET: usize = 0;
+const DESTINATION_PORT_OFFSET: usize = 2;
+const LEN_OFFSET: usize = 4;
+const CHECKSUM_OFFSET: usize = 6;
+const PAYLOAD_OFFSET: usize = 8;
+
+/// The length of the UDP header.
+pub const HEADER_LEN: usize = PAYLOAD_OFFSET;
+
+/// Describes the errors which may occur while handling UDP datagrams.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// Invalid checksum.
+    Checksum,
+    /// The value of the length header field does not match the length of the given slice.
+    InvalidLen,
+    /// The payload does not fit in the given slice.
+    PayloadTooLarge,
+    /// The specified slice is shorter than the header length.
+    SliceTooShort,
+}
+
+// TODO: Just like for TCP, checksum computation is IPv4 specific.
+
+/// Interprets the inner bytes as a UDP datagram.
+pub struct UdpDatagram<'a, T: 'a> {
+    bytes: InnerBytes<'a, T>,
+}
+
+impl<'a, T: NetworkBytes> UdpDatagram<'a, T> {
+    /// Returns the source port.
+    #[inline]
+    pub fn source_port(&self) -> u16 {
+        self.bytes.ntohs_unchecked(SOURCE_PORT_OFFSET)
+    }
+
+    /// Returns the destination port.
+    #[inline]
+    pub fn destination_port(&self) -> u16 {
+        self.bytes.ntohs_unchecked(DESTINATION_PORT_OFFSET)
+    }
+
+    /// Returns the value of the `length` header field.
+    #[inline]
+    pub fn len_field(&self) -> u16 {
+        self.bytes.ntohs_unchecked(LEN_OFFSET)
+    }
+
+    /// Returns the checksum value.
+    #[inline]
+    pub fn checksum(&self) -> u16 {
+        self.bytes.ntohs_unchecked(CHECKSUM_OFFSET)
+    }
+
+    /// Returns the payload of the datagram.
+    #[inline]
+    pub fn payload(&self) -> &[u8] {
+        &self.bytes[PAYLOAD_OFFSET..]
+    }
+
+    /// Returns the length of the payload.
+    #[inline]
+    pub fn payload_len(&self) -> usize {
+        self.len() - PAYLOAD_OFFSET
+    }
+
+    /// Returns the length of the datagram.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Computes the UDP checksum of the datagram. The computation is identical to the one used
+    /// for TCP segments, except for the protocol number which is part of the pseudo-header.
+    pub fn compute_checksum(&self, src_addr: Ipv4Addr, dst_addr: Ipv4Addr) -> u16 {
+        let mut sum = 0u32;
+
+        let a = u32::from(src_addr);
+        sum += a & 0xffff;
+        sum += a >> 16;
+
+        let b = u32::from(dst_addr);
+        sum += b & 0xffff;
+        sum += b >> 16;
+
+        let len = self.len();
+        sum += PROTOCOL_UDP as u32;
+        sum += len as u32;
+
+        for i in 0..len / 2 {
+            sum += self.bytes.ntohs_unchecked(i * 2) as u32;
+        }
+
+        if len % 2 != 0 {
+            sum += (self.bytes[len - 1] as u32) << 8;
+        }
+
+        while sum >> 16 != 0 {
+            sum = (sum & 0xffff) + (sum >> 16);
+        }
+
+        !(sum as u16)
+    }
+
+    /// Interprets `bytes` as a UDP datagram without any validity checks.
+    ///
+    /// # Panics
+    ///
+    /// This method does not panic, but further method calls on the resulting object may panic if
+    /// `bytes` contains invalid input.
+    #[inline]
+    pub fn from_bytes_unchecked(bytes: T) -> Self {
+        UdpDatagram {
+            bytes: InnerBytes::new(bytes),
+        }
+    }
+
+    /// Attempts to interpret `bytes` as a UDP datagram, checking the validity of the header
+    /// fields.
+    ///
+    /// The `verify_checksum` parameter must contain the source and destination addresses from the
+    /// enclosing IPv4 packet if the UDP checksum must be validated. A `checksum` field with the
+    /// value 0 means the sender did not compute a checksum, so no validation takes place.
+    pub fn from_bytes(
+        bytes: T,
+        verify_checksum: Option<(Ipv4Addr, Ipv4Addr)>,
+    ) -> Result<Self, Error> {
+        if bytes.len() < HEADER_LEN {
+            return Err(Error::SliceTooShort);
+        }
+
+        let datagram = Self::from_bytes_unchecked(bytes);
+
+        if datagram.len_field() as usize != datagram.len() {
+            return Err(Error::InvalidLen);
+        }
+
+        if let Some((src_addr, dst_addr)) = verify_checksum {
+            if datagram.checksum() != 0 && datagram.compute_checksum(src_addr, dst_addr) != 0 {
+                return Err(Error::Checksum);
+            }
+        }
+
+        Ok(datagram)
+    }
+}
+
+impl<'a, T: NetworkBytesMut> UdpDatagram<'a, T> {
+    /// Sets the source port.
+    #[inline]
+    pub fn set_source_port(&mut self, value: u16) -> &mut Self {
+        self.bytes.htons_unchecked(SOURCE_PORT_OFFSET, value);
+        self
+    }
+
+    /// Sets the destination port.
+    #[inline]
+    pub fn set_destination_port(&mut self, value: u16) -> &mut Self {
+        self.bytes.htons_unchecked(DESTINATION_PORT_OFFSET, value);
+        self
+    }
+
+    /// Sets the value of the `length` header field.
+    #[inline]
+    pub fn set_len_field(&mut self, value: u16) -> &mut Self {
+        self.bytes.htons_unchecked(LEN_OFFSET, value);
+        self
+    }
+
+    /// Sets the value of the `checksum` field.
+    #[inline]
+    pub fn set_checksum(&mut self, value: u16) -> &mut Self {
+        self.bytes.htons_unchecked(CHECKSUM_OFFSET, value);
+        self
+    }
+
+    /// Returns a mutable slice which contains the payload of the datagram.
+    #[inline]
+    pub fn payload_mut(&mut self) -> &mut [u8] {
+        &mut self.bytes[PAYLOAD_OFFSET..]
+    }
+
+    /// Writes an incomplete UDP datagram, which is missing the `source port`, `destination port`,
+    /// and `checksum` fields. The payload is copied from `payload`.
+    pub fn write_incomplete_datagram(buf: T, payload: &[u8]) -> Result<Incomplete<Self>, Error> {
+        let mut datagram = Self::write_incomplete_datagram_with_len(buf, payload.len())?;
+        datagram.inner_mut().payload_mut().copy_from_slice(payload);
+        Ok(datagram)
+    }
+
+    /// Writes an incomplete UDP datagram with a payload of `payload_len` bytes, which is expected
+    /// to be filled by the caller (via `payload_mut()`) before finalizing the datagram.
+    pub fn write_incomplete_datagram_with_len(
+        buf: T,
+        payload_len: usize,
+    ) -> Result<Incomplete<Self>, Error> {
+        if buf.len() < HEADER_LEN {
+            return Err(Error::SliceTooShort);
+        }
+
+        let len = HEADER_LEN + payload_len;
+        if len > buf.len() || len > u16::max_value() as usize {
+            return Err(Error::PayloadTooLarge);
+        }
+
+        let mut datagram = Self::from_bytes_unchecked(buf);
+        // This is ok because len <= buf.len().
+        datagram.bytes.shrink_unchecked(len);
+        datagram.set_len_field(len as u16);
+
+        Ok(Incomplete::new(datagram))
+    }
+}
+
+impl<'a, T: NetworkBytesMut> Incomplete<UdpDatagram<'a, T>> {
+    /// Transforms `self` into a `UdpDatagram<T>` by specifying values for the `source port`,
+    /// `destination port`, and (optionally) the information required to compute the checksum.
+    pub fn finalize(
+        mut self,
+        src_port: u16,
+        dst_port: u16,
+        compute_checksum: Option<(Ipv4Addr, Ipv4Addr)>,
+    ) -> UdpDatagram<'a, T> {
+        self.inner.set_source_port(src_port);
+        self.inner.set_destination_port(dst_port);
+        self.inner.set_checksum(0);
+        if let Some((src_addr, dst_addr)) = compute_checksum {
+            let checksum = self.inner.compute_checksum(src_addr, dst_addr);
+            // A computed checksum of 0 is transmitted as all ones, since 0 means no checksum.
+            self.inner
+                .set_checksum(if checksum == 0 { 0xffff } else { checksum });
+        }
+        self.inner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt;
+
+    use super::*;
+
+    impl<'a, T: NetworkBytes> fmt::Debug for UdpDatagram<'a, T> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "(UDP datagram)")
+        }
+    }
+
+    impl<'a, T: NetworkBytes> fmt::Debug for Incomplete<UdpDatagram<'a, T>> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "(Incomplete UDP datagram)")
+        }
+    }
+
+    #[test]
+    fn test_set_get() {
+        let mut a = [0u8; 100];
+        let mut d = UdpDatagram::from_bytes_unchecked(a.as_mut());
+
+        assert_eq!(d.source_port(), 0);
+        d.set_source_port(123);
+        assert_eq!(d.source_port(), 123);
+
+        assert_eq!(d.destination_port(), 0);
+        d.set_destination_port(322);
+        assert_eq!(d.destination_port(), 322);
+
+        assert_eq!(d.len_field(), 0);
+        d.set_len_field(100);
+        assert_eq!(d.len_field(), 100);
+
+        assert_eq!(d.checksum(), 0);
+        d.set_checksum(0x1234);
+        assert_eq!(d.checksum(), 0x1234);
+
+        assert_eq!(d.payload_len(), 100 - HEADER_LEN);
+    }
+
+    #[test]
+    fn test_write_and_parse() {
+        let mut a = [0u8; 100];
+        let src_addr = Ipv4Addr::new(10, 1, 2, 3);
+        let dst_addr = Ipv4Addr::new(192, 168, 44, 77);
+        let payload = b"hello there";
+
+        assert_eq!(
+            UdpDatagram::write_incomplete_datagram(&mut a[..5], payload.as_ref()).unwrap_err(),
+            Error::SliceTooShort
+        );
+        assert_eq!(
+            UdpDatagram::write_incomplete_datagram(&mut a[..10], payload.as_ref()).unwrap_err(),
+            Error::PayloadTooLarge
+        );
+
+        let len = UdpDatagram::write_incomplete_datagram(a.as_mut(), payload.as_ref())
+            .unwrap()
+            .finalize(1234, 53, Some((src_addr, dst_addr)))
+            .len();
+        assert_eq!(len, HEADER_LEN + payload.len());
+
+        {
+            let d = UdpDatagram::from_bytes(&a[..len], Some((src_addr, dst_addr))).unwrap();
+            assert_eq!(d.source_port(), 1234);
+            assert_eq!(d.destination_port(), 53);
+            assert_eq!(d.payload(), payload.as_ref());
+        }
+
+        // A wrong address means a wrong checksum.
+        let other_addr = Ipv4Addr::new(10, 1, 2, 4);
+        assert_eq!(
+            UdpDatagram::from_bytes(&a[..len], Some((other_addr, dst_addr))).unwrap_err(),
+            Error::Checksum
+        );
+        // The length of the slice does not match the length field.
+        assert_eq!(
+            UdpDatagram::from_bytes(&a[..len + 1], None).unwrap_err(),
+            Error::InvalidLen
+        );
+        assert_eq!(
+            UdpDatagram::from_bytes(&a[..4], None).unwrap_err(),
+            Error::SliceTooShort
+        );
+
+        // A 0 checksum means the checksum is not verified.
+        UdpDatagram::from_bytes_unchecked(&mut a[..len]).set_checksum(0);
+        assert!(UdpDatagram::from_bytes(&a[..len], Some((other_addr, dst_addr))).is_ok());
+    }
+}
@@ -2,9 +2,13 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::num::{NonZeroU16, NonZeroU64, NonZeroUsize, Wrapping};
+use std::ops::Index;
 
 use pdu::bytes::NetworkBytes;
-use pdu::tcp::{Error as TcpSegmentError, Flags as TcpFlags, TcpSegment};
+use pdu::tcp::{
+    Error as TcpSegmentError, Flags as TcpFlags, TcpOptions, TcpSegment, MAX_SACK_BLOCKS,
+    MAX_WINDOW_SCALE,
+};
 use pdu::Incomplete;
 use tcp::{seq_after, seq_at_or_after, NextSegmentStatus, RstConfig, MAX_WINDOW_SIZE, MSS_DEFAULT};
 use ByteBuffer;
@@ -51,6 +55,7 @@ pub type PayloadSource<'a, R> = Option<(&'a R, Wrapping<u32>)>;
 
 #[cfg_attr(test, derive(Debug, PartialEq))]
 pub enum PassiveOpenError {
+    InvalidOptions,
     InvalidSyn,
     MssOption,
 }
@@ -131,21 +136,86 @@ pub struct Connection {
     // We've got a duplicate ACK, so we'll retransmit the highest ACKed sequence number at the
     // first opportunity. Unlike regular TCP, we retransmit after the first duplicate ACK.
     dup_ack: bool,
+    // The window scale shift count received on the SYN. Window scaling is only used when the other
+    // endpoint asks for it, in which case the window size of incoming non-SYN segments is shifted
+    // left by this much.
+    remote_window_scale: Option<u8>,
+    // The shift count we announce on the SYNACK (when window scaling is used), and apply to the
+    // window size of outgoing non-SYN segments.
+    local_window_scale: u8,
+    // Whether the other endpoint sent the SACK-permitted option. We never send SACK blocks
+    // ourselves, because out of order segments are dropped anyway, but we use the ones we receive
+    // to avoid retransmitting data which already got to the other endpoint.
+    sack_permitted: bool,
+    // Sequence number intervals past highest_ack_received which the other endpoint reported as
+    // received via SACK. They are sorted, and do not overlap. Only the first sacked_count entries
+    // are valid.
+    sacked: [(Wrapping<u32>, Wrapping<u32>); MAX_SACK_BLOCKS],
+    sacked_count: usize,
     status_flags: ConnStatusFlags,
 }
 
+// Parses the options of a SYN segment, filling in the default MSS if the option is missing.
+fn parse_syn_options<T: NetworkBytes>(
+    segment: &TcpSegment<T>,
+) -> Result<TcpOptions, PassiveOpenError> {
+    let mut options = segment
+        .parse_options_unchecked(segment.header_len())
+        .map_err(|e| match e {
+            TcpSegmentError::MssOption => PassiveOpenError::MssOption,
+            _ => PassiveOpenError::InvalidOptions,
+        })?;
+    if options.mss.is_none() {
+        options.mss = Some(MSS_DEFAULT);
+    }
+    Ok(options)
+}
+
+#[cfg(test)]
 fn parse_mss_option<T: NetworkBytes>(segment: &TcpSegment<T>) -> Result<u16, PassiveOpenError> {
-    match segment.parse_mss_option_unchecked(segment.header_len()) {
-        Ok(Some(value)) => Ok(value.get()),
-        Ok(None) => Ok(MSS_DEFAULT),
-        Err(_) => Err(PassiveOpenError::MssOption),
+    // The unwrap() is safe because parse_syn_options() always fills in the MSS.
+    parse_syn_options(segment).map(|options| options.mss.unwrap())
+}
+
+// Returns the smallest window scale shift count which allows a receive window of the given size
+// to be announced.
+fn window_scale_for(rwnd_size: u32) -> u8 {
+    let mut shift = 0;
+    while shift < MAX_WINDOW_SCALE && rwnd_size >> shift > u32::from(u16::max_value()) {
+        shift += 1;
     }
+    shift
 }
 
 fn is_valid_syn<T: NetworkBytes>(segment: &TcpSegment<T>) -> bool {
     segment.flags_after_ns() == TcpFlags::SYN && segment.payload_len() == 0
 }
 
+// Exposes the bytes of a payload buffer starting at a given offset, so data segments which don't
+// begin at payload_seq pick up the right bytes.
+struct PayloadFrom<'a, R: 'a + ByteBuffer + ?Sized> {
+    buf: &'a R,
+    offset: usize,
+}
+
+impl<'a, R: ByteBuffer + ?Sized> Index<usize> for PayloadFrom<'a, R> {
+    type Output = u8;
+
+    fn index(&self, index: usize) -> &u8 {
+        &self.buf[self.offset + index]
+    }
+}
+
+impl<'a, R: ByteBuffer + ?Sized> ByteBuffer for PayloadFrom<'a, R> {
+    fn len(&self) -> usize {
+        self.buf.len() - self.offset
+    }
+
+    fn read_to_slice(&self, offset: usize, buf: &mut [u8]) {
+        self.buf.read_to_slice(self.offset + offset, buf)
+    }
+}
+
 impl Connection {
     // This is called a passive open because we create the connection in response to an incoming
     // SYN segment. The sender of the SYN is doing an active open.
@@ -160,11 +230,9 @@ impl Connection {
             return Err(PassiveOpenError::InvalidSyn);
         }
 
-        // TODO: If we ever implement window scaling, change the part that computes
-        // remote_rwnd_edge below.
-
-        // We only care about the MSS option for now.
-        let mss = parse_mss_option(segment)?;
+        // The unwrap() is safe because parse_syn_options() always fills in the MSS.
+        let options = parse_syn_options(segment)?;
+        let mss = options.mss.unwrap();
 
         // This is going to get sent on the SYNACK.
         let ack_to_send = Wrapping(segment.sequence_number()) + Wrapping(1);
@@ -172,6 +240,7 @@ impl Connection {
         // Let's pick the initial sequence number.
         let isn = Wrapping(xor_rng_u32());
         let first_not_sent = isn + Wrapping(1);
+        // The window size of SYN segments is never scaled.
         let remote_rwnd_edge = first_not_sent + Wrapping(segment.window_size() as u32);
 
         Ok(Connection {
@@ -193,6 +262,11 @@ impl Connection {
             mss,
             pending_ack: false,
             dup_ack: false,
+            remote_window_scale: options.window_scale,
+            local_window_scale: window_scale_for(local_rwnd_size),
+            sack_permitted: options.sack_permitted,
+            sacked: [(Wrapping(0), Wrapping(0)); MAX_SACK_BLOCKS],
+            sacked_count: 0,
             status_flags: ConnStatusFlags::SYN_RECEIVED,
         })
     }
@@ -241,8 +315,12 @@ impl Connection {
             return false;
         }
 
-        match parse_mss_option(segment) {
-            Ok(mss) if mss == self.mss => true,
+        match parse_syn_options(segment) {
+            Ok(options) => {
+                options.mss == Some(self.mss)
+                    && options.window_scale == self.remote_window_scale
+                    && options.sack_permitted == self.sack_permitted
+            }
             _ => false,
         }
     }
@@ -270,10 +348,13 @@ impl Connection {
         }
     }
 
-    // Returns the window size which should be written to an outgoing segment. This is going to be
-    // even more useful when we'll support window scaling.
-    fn local_rwnd(&self) -> u16 {
-        let rwnd = (self.local_rwnd_edge - self.ack_to_send).0;
+    // Returns the window size which should be written to an outgoing segment. The window size of
+    // SYN segments is never scaled.
+    fn local_rwnd(&self, syn: bool) -> u16 {
+        let mut rwnd = (self.local_rwnd_edge - self.ack_to_send).0;
+        if self.remote_window_scale.is_some() && !syn {
+            rwnd >>= self.local_window_scale;
+        }
 
         if rwnd > u16::max_value() as u32 {
             u16::max_value()
@@ -282,9 +363,98 @@ impl Connection {
         }
     }
 
-    // Will actually become meaningful when/if we implement window scaling.
+    // Returns the actual size of the remote receive window, given the window size from an
+    // incoming non-SYN segment.
     fn remote_window_size(&self, window_size: u16) -> u32 {
-        window_size as u32
+        (window_size as u32) << self.remote_window_scale.unwrap_or(0)
+    }
+
+    // Records the SACK blocks carried by an incoming segment, which acknowledges everything up to
+    // self.highest_ack_received. Blocks outside of the data we sent are ignored.
+    fn record_sack_blocks<T: NetworkBytes>(&mut self, s: &TcpSegment<T>) {
+        // SACK blocks are purely advisory, so we simply ignore malformed options.
+        if let Ok(options) = s.parse_options_unchecked(s.header_len()) {
+            for &(left, right) in options.sack_blocks() {
+                let (left, right) = (Wrapping(left), Wrapping(right));
+                if seq_after(left, self.highest_ack_received)
+                    && seq_after(right, left)
+                    && seq_at_or_after(self.first_not_sent, right)
+                {
+                    self.insert_sacked(left, right);
+                }
+            }
+        }
+    }
+
+    // Adds the [left, right) interval to self.sacked, merging it with the ones it overlaps or
+    // touches. When there's no room left, the intervals with the highest sequence numbers are
+    // forgotten, which at worst leads to needless retransmissions.
+    fn insert_sacked(&mut self, mut left: Wrapping<u32>, mut right: Wrapping<u32>) {
+        let mut merged = [(Wrapping(0), Wrapping(0)); MAX_SACK_BLOCKS + 1];
+        let mut count = 0;
+        let mut inserted = false;
+
+        for i in 0..self.sacked_count {
+            let (l, r) = self.sacked[i];
+            if seq_after(l, right) {
+                // This interval comes after the new one.
+                if !inserted {
+                    merged[count] = (left, right);
+                    count += 1;
+                    inserted = true;
+                }
+                merged[count] = (l, r);
+                count += 1;
+            } else if seq_after(left, r) {
+                // This interval comes before the new one.
+                merged[count] = (l, r);
+                count += 1;
+            } else {
+                // The intervals overlap or touch, so we merge them.
+                if seq_after(left, l) {
+                    left = l;
+                }
+                if seq_after(r, right) {
+                    right = r;
+                }
+            }
+        }
+        if !inserted {
+            merged[count] = (left, right);
+            count += 1;
+        }
+
+        self.sacked_count = if count > MAX_SACK_BLOCKS {
+            MAX_SACK_BLOCKS
+        } else {
+            count
+        };
+        self.sacked[..self.sacked_count].copy_from_slice(&merged[..self.sacked_count]);
+    }
+
+    // Forgets the SACKed intervals which are no longer past self.highest_ack_received. An ACK which
+    // reaches into a SACKed interval without covering it should not really happen, and we simply
+    // assume the rest of that interval needs to be sent again.
+    fn purge_sacked(&mut self) {
+        let ack = self.highest_ack_received;
+        let mut count = 0;
+        for i in 0..self.sacked_count {
+            if seq_after(self.sacked[i].0, ack) {
+                self.sacked[count] = self.sacked[i];
+                count += 1;
+            }
+        }
+        self.sacked_count = count;
+    }
+
+    // Returns the left edge of the first SACKed interval, if any. The data between
+    // self.highest_ack_received and this point is what the other endpoint is missing.
+    fn first_sacked(&self) -> Option<Wrapping<u32>> {
+        if self.sacked_count > 0 {
+            Some(self.sacked[0].0)
+        } else {
+            None
+        }
     }
 
     // Computes the remote rwnd edge given the ACK number and window size from an incoming segment.
@@ -494,6 +664,7 @@ impl Connection {
                     // We're making progress. We should also reset rto_start in this case.
                     self.highest_ack_received = ack;
                     self.rto_start = now;
+                    self.purge_sacked();
                     if !self.is_established() && self.synack_sent() {
                         // The connection becomes ESTABLISHED.
                         self.set_flags(ConnStatusFlags::ESTABLISHED);
@@ -504,6 +675,16 @@ impl Connection {
                     }
                 }
 
+                if self.sack_permitted && self.is_established() {
+                    self.record_sack_blocks(s);
+                    // If the other endpoint still has data past the one we just got an ACK for,
+                    // then the segment beginning at the ACK number got lost, so we can
+                    // retransmit it right away.
+                    if ack != self.first_not_sent && self.first_sacked().is_some() {
+                        self.dup_ack = true;
+                    }
+                }
+
                 // Look for remote remote rwnd updates.
                 if self.is_established() {
                     let edge = self.compute_remote_rwnd_edge(ack, s.window_size());
@@ -629,20 +810,25 @@ impl Connection {
         flags_after_ns: TcpFlags,
         payload: Option<(&R, usize)>,
     ) -> Result<Incomplete<TcpSegment<'a, &'a mut [u8]>>, WriteNextError> {
-        // Write the MSS option on SYNACK segments.
-        let mss_option = if flags_after_ns == TcpFlags::SYN | TcpFlags::ACK {
-            Some(self.mss)
-        } else {
-            None
-        };
+        // Write the MSS option on SYNACK segments, along with the window scale and SACK-permitted
+        // options, if the other endpoint sent them on the SYN.
+        let syn = flags_after_ns.intersects(TcpFlags::SYN);
+        let mut options = TcpOptions::default();
+        if flags_after_ns == TcpFlags::SYN | TcpFlags::ACK {
+            options = TcpOptions::with_mss(self.mss);
+            if self.remote_window_scale.is_some() {
+                options.window_scale = Some(self.local_window_scale);
+            }
+            options.sack_permitted = self.sack_permitted;
+        }
 
         let segment = TcpSegment::write_incomplete_segment(
             buf,
             seq.0,
             ack.0,
             flags_after_ns,
-            self.local_rwnd(),
-            mss_option,
+            self.local_rwnd(syn),
+            &options,
             self.mss
                 .checked_sub(mss_reserved)
                 .ok_or_else(|| WriteNextError::MssRemaining)?,
@@ -766,6 +952,10 @@ impl Connection {
                         return self.write_next_segment(buf, mss_reserved, payload_src, now);
                     }
 
+                    // The other endpoint may discard data it previously reported via SACK, so
+                    // after a timeout we retransmit everything which was not ACKed (RFC 2018).
+                    self.sacked_count = 0;
+
                     if let Some(fin_seq) = self.send_fin {
                         if self.highest_ack_received == fin_seq {
                             // We're in the relatively unlikely situation where our FIN got lost.
@@ -803,6 +993,16 @@ impl Connection {
                 self.remote_rwnd_edge
             };
 
+            // When retransmitting, there's no need to resend the data the other endpoint reported
+            // via SACK.
+            if seq_to_send == self.highest_ack_received {
+                if let Some(first_sacked) = self.first_sacked() {
+                    if seq_after(actual_end, first_sacked) {
+                        actual_end = first_sacked;
+                    }
+                }
+            }
+
             // Make sure we're not trying to send data past the FIN sequence we previously
             // announced.
             if let Some(fin_seq) = self.send_fin {
@@ -819,6 +1019,13 @@ impl Connection {
                 // We always set the ACK flag for data segments.
                 let tcp_flags = TcpFlags::ACK;
 
+                // The payload of this segment starts seq_to_send - payload_seq bytes into
+                // read_buf.
+                let payload = PayloadFrom {
+                    buf: read_buf,
+                    offset: (seq_to_send - payload_seq).0 as usize,
+                };
+
                 let ack_to_send = self.ack_to_send;
                 let mut segment = self.write_segment(
                     buf,
@@ -826,7 +1033,7 @@ impl Connection {
                     seq_to_send,
                     ack_to_send,
                     tcp_flags,
-                    Some((read_buf, max_payload_len)),
+                    Some((&payload, max_payload_len)),
                 )?;
 
                 // If self.dup_ack was Some(_), we've just written the retransmission segment,
@@ -897,6 +1104,7 @@ pub(crate) mod tests {
     use std::fmt;
 
     use super::*;
+    use pdu::tcp::tests::{LINUX_SYN, WINDOWS_SYN};
 
     // A segment without options or a payload is 20 bytes long.
     const BASIC_SEGMENT_SIZE: usize = 20;
@@ -911,7 +1119,7 @@ pub(crate) mod tests {
         buf: [u8; 2000],
         src_port: u16,
         dst_port: u16,
-        remote_window_size: u16,
+        pub remote_window_size: u16,
         pub mss: u16,
         pub mss_reserved: u16,
         local_rwnd_size: u32,
@@ -958,7 +1166,11 @@ pub(crate) mod tests {
             add_mss_option: bool,
             payload: Option<(&[u8], usize)>,
         ) -> TcpSegment<'a, &'a mut [u8]> {
-            let mss_option = if add_mss_option { Some(self.mss) } else { None };
+            let options = if add_mss_option {
+                TcpOptions::with_mss(self.mss)
+            } else {
+                TcpOptions::default()
+            };
             TcpSegment::write_segment(
                 buf,
                 self.src_port,
@@ -967,7 +1179,7 @@ pub(crate) mod tests {
                 0,
                 TcpFlags::empty(),
                 self.remote_window_size,
-                mss_option,
+                &options,
                 self.mss.checked_sub(self.mss_reserved).unwrap(),
                 payload,
                 None,
@@ -1651,4 +1863,141 @@ pub(crate) mod tests {
         // and we don't wait for our FIN to be ACKed.
         assert!(c.is_done());
     }
+
+    #[test]
+    fn test_window_scale_and_sack() {
+        let mut rcv_buf = [0u8; 100];
+        let mut buf = [0u8; 100];
+        let mut out_buf = [0u8; 2000];
+        let send_buf = [11u8; 20000];
+
+        let local_rwnd_size = 1_000_000;
+        let rto_period = 100_000;
+        let (local_port, remote_port) = (80, 43206);
+
+        let passive_open = |bytes: &[u8]| {
+            Connection::passive_open(
+                &TcpSegment::from_bytes(bytes, None).unwrap(),
+                local_rwnd_size,
+                NonZeroU64::new(rto_period).unwrap(),
+                NonZeroU16::new(3).unwrap(),
+            ).unwrap()
+        };
+
+        // The Windows SYN asks for a larger shift count than the Linux one.
+        assert_eq!(
+            passive_open(WINDOWS_SYN.as_ref()).remote_window_scale,
+            Some(8)
+        );
+
+        let syn = TcpSegment::from_bytes(LINUX_SYN.as_ref(), None).unwrap();
+        let mut c = passive_open(LINUX_SYN.as_ref());
+        assert_eq!(c.remote_window_scale, Some(7));
+        // This is the smallest shift count which allows 1_000_000 to fit in 16 bits.
+        assert_eq!(c.local_window_scale, 4);
+        assert!(c.sack_permitted);
+        // The window size of the SYN is not scaled.
+        assert_eq!(c.remote_rwnd_edge, c.first_not_sent + Wrapping(64240));
+        assert!(c.is_same_syn(&syn));
+
+        {
+            let s = c
+                .write_next_segment::<[u8]>(out_buf.as_mut(), 0, None, 0)
+                .unwrap()
+                .unwrap()
+                .finalize(local_port, remote_port, None);
+            assert_eq!(s.flags_after_ns(), TcpFlags::SYN | TcpFlags::ACK);
+            // Neither is the window size of the SYNACK.
+            assert_eq!(s.window_size(), u16::max_value());
+
+            let options = s.parse_options_unchecked(s.header_len()).unwrap();
+            assert_eq!(options.mss, Some(1460));
+            assert_eq!(options.window_scale, Some(4));
+            assert!(options.sack_permitted);
+            assert!(options.sack_blocks().is_empty());
+        }
+
+        fn write_ack<'a>(
+            buf: &'a mut [u8],
+            ack: Wrapping<u32>,
+            options: &TcpOptions,
+        ) -> TcpSegment<'a, &'a mut [u8]> {
+            TcpSegment::write_segment::<[u8]>(
+                buf,
+                43206,
+                80,
+                0x1f6a_3b22,
+                ack.0,
+                TcpFlags::ACK,
+                502,
+                options,
+                1460,
+                None,
+                None,
+            ).unwrap()
+        }
+
+        // The ACK which completes the handshake has a window size of 502, scaled by 2^7.
+        let data_start = c.first_not_sent;
+        c.receive_segment(
+            &write_ack(buf.as_mut(), data_start, &TcpOptions::default()),
+            rcv_buf.as_mut(),
+            0,
+        ).unwrap();
+        assert!(c.is_established());
+        assert_eq!(c.remote_rwnd_edge, data_start + Wrapping(502 << 7));
+
+        let payload_src = Some((send_buf.as_ref(), data_start));
+        let mut check_next_segment = |c: &mut Connection, offset: u32, len: usize, now: u64| {
+            let s = c
+                .write_next_segment(out_buf.as_mut(), 0, payload_src, now)
+                .unwrap()
+                .unwrap()
+                .finalize(local_port, remote_port, None);
+            assert_eq!(s.sequence_number(), (data_start + Wrapping(offset)).0);
+            assert_eq!(s.payload_len(), len);
+            // The window size of outgoing segments is scaled by 2^4.
+            assert_eq!(s.window_size(), (local_rwnd_size >> 4) as u16);
+        };
+
+        for i in 0..4 {
+            check_next_segment(&mut c, i * 1460, 1460, 0);
+        }
+
+        // The other endpoint ACKs the first segment, and reports most of the others via SACK.
+        let mut options = TcpOptions::default();
+        options
+            .add_sack_block((data_start + Wrapping(2000)).0, (data_start + Wrapping(5840)).0)
+            .unwrap();
+        c.receive_segment(
+            &write_ack(buf.as_mut(), data_start + Wrapping(1460), &options),
+            rcv_buf.as_mut(),
+            0,
+        ).unwrap();
+        assert_eq!(c.first_sacked(), Some(data_start + Wrapping(2000)));
+
+        // The missing data is retransmitted right away, but nothing past the SACKed interval.
+        check_next_segment(&mut c, 1460, 540, 0);
+        // After that, we go on with new data.
+        check_next_segment(&mut c, 5840, 1460, 0);
+
+        // SACK blocks outside of the data we sent, or below the ACK number, are ignored.
+        let mut options = TcpOptions::default();
+        options.add_sack_block(data_start.0, (data_start + Wrapping(1000)).0).unwrap();
+        options
+            .add_sack_block((data_start + Wrapping(7000)).0, (data_start + Wrapping(8000)).0)
+            .unwrap();
+        c.receive_segment(
+            &write_ack(buf.as_mut(), data_start + Wrapping(1460), &options),
+            rcv_buf.as_mut(),
+            0,
+        ).unwrap();
+        assert_eq!(c.sacked_count, 1);
+
+        // When the RTO fires, SACK information is discarded, and everything which was not ACKed
+        // is sent again.
+        check_next_segment(&mut c, 1460, 540, 0);
+        check_next_segment(&mut c, 1460, 1460, rto_period);
+        assert_eq!(c.sacked_count, 0);
+    }
 }
@@ -11,46 +11,62 @@
 // components, but since the separation/interface is not very well defined yet, we keep the
 // Endpoint in here too for the time being.
 
+use std::cmp::min;
 use std::num::{NonZeroU16, NonZeroU64, Wrapping};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 use fc_util::timestamp_cycles;
 use logger::{Metric, METRICS};
-use mmds::parse_request;
+use micro_http::{Request, Response, MAX_BODY_LEN, MAX_HEAD_LEN};
+use mmds::data_store::Mmds;
+use mmds::{long_poll, parse_request};
+#[cfg(test)]
+use ns::{DEFAULT_CONNECTION_RTO_COUNT_MAX, DEFAULT_CONNECTION_RTO_PERIOD};
 use pdu::bytes::NetworkBytes;
 use pdu::tcp::TcpSegment;
 use pdu::Incomplete;
 use tcp::connection::{Connection, PassiveOpenError, RecvStatusFlags};
 use tcp::{seq_after, NextSegmentStatus, MAX_WINDOW_SIZE};
 
-// TODO: These are currently expressed in cycles. Normally, they would be the equivalent of a
+// TODO: This is currently expressed in cycles. Normally, it would be the equivalent of a
 // certain duration, depending on the frequency of the CPU, but we still have a bit to go until
-// that functionality is available, so we just use some conservative-ish values. Even on a fast
-// 4GHz CPU, the first is roughly equal to 10 seconds, and the other is ~300 ms.
-const EVICTION_THRESHOLD: u64 = 40_000_000_000;
-const CONNECTION_RTO_PERIOD: u64 = 1_200_000_000;
-const CONNECTION_RTO_COUNT_MAX: u16 = 15;
-
-// This is one plus the size of the largest bytestream carrying an HTTP request we are willing to
-// accept. It's limited in order to have a bound on memory usage. This value should be plenty for
-// imaginable regular MMDS requests.
-// TODO: Maybe at some point include this in the checks we do when populating the MMDS via the API,
-// since it effectively limits the size of the keys (URIs) we're willing to use.
-const RCV_BUF_MAX_SIZE: usize = 2500;
-
-// Represents the local endpoint of a HTTP over TCP connection which carries GET requests
-// to the MMDS.
+// that functionality is available, so we just use a conservative-ish value. Even on a fast
+// 4GHz CPU, it's roughly equal to 10 seconds. The same goes for the RTO defaults in the ns module.
+pub const EVICTION_THRESHOLD: u64 = 40_000_000_000;
+
+// This is the size of the largest bytestream carrying an HTTP request we are willing to accept. It
+// matches the limits enforced by the HTTP parser, so larger requests are answered with an error
+// (such as 413 Payload Too Large) before they fill the buffer, instead of resetting the connection.
+const RCV_BUF_MAX_SIZE: usize = MAX_HEAD_LEN + MAX_BODY_LEN;
+
+// The maximum number of bytes from a streamed response body which are kept in the response buffer
+// (not counting the framing of the current chunk). Even when the remote receive window is larger,
+// this bounds the memory used by each connection while sending large MMDS values.
+const RESPONSE_BUF_MAX_SIZE: usize = 65536;
+
+// Represents the local endpoint of a HTTP over TCP connection which carries requests to the
+// MMDS. The connection is persistent unless a request asks otherwise, and pipelined requests are
+// answered one at a time, in order. Requests which wait for the MMDS contents to change (long
+// polls) are kept in the receive buffer until the Endpoint is told to check on them again.
 pub struct Endpoint {
     // A fixed size buffer used to store bytes received via TCP. If the current request does not
-    // fit within, we reset the connection, since we see this as a hard memory bound.
+    // fit within (which the HTTP size limits should prevent), we reset the connection, since we
+    // see this as a hard memory bound.
     receive_buf: [u8; RCV_BUF_MAX_SIZE],
     // Represents the next available position in the buffer.
     receive_buf_left: usize,
     // This is filled with the HTTP response bytes after we parse a request and generate the reply.
+    // Bytes are removed from the front of the buffer as they get ACKed.
     response_buf: Vec<u8>,
     // Represents the sequence number associated with the first byte from response_buf.
     response_seq: Wrapping<u32>,
+    // The current response, while its body is still being streamed into response_buf.
+    response: Option<Response>,
     // The TCP connection that does all the receiving/sending work.
     connection: Connection,
+    // The data store used to answer requests.
+    mmds: Arc<Mutex<Mmds>>,
     // Timestamp (in cycles) associated with the most recent reception of a segment.
     last_segment_received_timestamp: u64,
     // These many time units have to pass since receiving the last segment to make the current
@@ -59,6 +75,12 @@ pub struct Endpoint {
     // We ignore incoming segments when this is set, and that happens when we decide to reset
     // the connection (or it decides to reset itself).
     stop_receiving: bool,
+    // Set when the connection must be closed after sending the current response, either because
+    // the client asked for it, or because the last request was invalid.
+    close_after_response: bool,
+    // Set while the request at the beginning of receive_buf waits for the MMDS contents to move
+    // past the specified revision, or for the deadline to pass.
+    long_poll: Option<(u64, Instant)>,
 }
 
 // The "contract" for the Endpoint (if it implemented a trait or something) is something along
@@ -70,6 +92,8 @@ pub struct Endpoint {
 // increases a metric).
 // - After calling either of the previous functions, the user should also call is_done() to see
 // if the Endpoint is finished.
+// - Requests which wait for the MMDS contents to change are answered by check_long_poll(), which
+// should be called whenever the contents change, and when long_poll_deadline() is reached.
 // - The is_evictable() function returns true if the Endpoint can be destroyed as far as its
 // internal logic is concerned. It's going to be used by the connection handler when trying to
 // find a new slot for incoming connections if none are free (when replacing an existing connection
@@ -78,6 +102,7 @@ pub struct Endpoint {
 impl Endpoint {
     pub fn new<T: NetworkBytes>(
         segment: &TcpSegment<T>,
+        mmds: Arc<Mutex<Mmds>>,
         eviction_threshold: NonZeroU64,
         connection_rto_period: NonZeroU64,
         connection_rto_count_max: NonZeroU16,
@@ -97,26 +122,33 @@ impl Endpoint {
             receive_buf: [0u8; RCV_BUF_MAX_SIZE],
             receive_buf_left: 0,
             response_buf: Vec::new(),
+            response: None,
             // TODO: Using first_not_sent() makes sense here because a connection is currently
             // created via passive open only, so this points to the sequence number right after
             // the SYNACK. It might stop working like that if/when the implementation changes.
             response_seq: connection.first_not_sent(),
             connection,
+            mmds,
             last_segment_received_timestamp: timestamp_cycles(),
             eviction_threshold: eviction_threshold.get(),
             stop_receiving: false,
+            close_after_response: false,
+            long_poll: None,
         })
     }
 
+    #[cfg(test)]
     pub fn new_with_defaults<T: NetworkBytes>(
         segment: &TcpSegment<T>,
+        mmds: Arc<Mutex<Mmds>>,
     ) -> Result<Self, PassiveOpenError> {
         // The unwraps are safe because the constants are greater than 0.
         Self::new(
             segment,
+            mmds,
             NonZeroU64::new(EVICTION_THRESHOLD).unwrap(),
-            NonZeroU64::new(CONNECTION_RTO_PERIOD).unwrap(),
-            NonZeroU16::new(CONNECTION_RTO_COUNT_MAX).unwrap(),
+            NonZeroU64::new(DEFAULT_CONNECTION_RTO_PERIOD).unwrap(),
+            NonZeroU16::new(DEFAULT_CONNECTION_RTO_COUNT_MAX).unwrap(),
         )
     }
 
@@ -158,61 +190,36 @@ impl Endpoint {
             self.receive_buf_left += len.get();
         };
 
-        if !self.response_buf.is_empty()
-            && self.connection.highest_ack_received()
-                == self.response_seq + Wrapping(self.response_buf.len() as u32)
-        {
-            // If we got here, then we still have some response bytes to send (which are
-            // stored in self.response_buf).
-
-            // It seems we just recevied the last ACK we were waiting for, so the entire
-            // response has been successfully received. Set the new response_seq and clear
-            // the response_buf.
+        // Drop the response bytes which were ACKed, and read more of the current response body
+        // (if it's streamed) as the remote receive window opens.
+        let acked = (self.connection.highest_ack_received() - self.response_seq).0 as usize;
+        if acked > 0 && acked <= self.response_buf.len() {
+            self.response_buf.drain(..acked);
             self.response_seq = self.connection.highest_ack_received();
-            self.response_buf.clear();
         }
+        self.fill_response_buf();
 
-        if self.response_buf.is_empty() {
+        if !self.response_pending() && !self.close_after_response && self.long_poll.is_none() {
             // There's no pending response currently, so we're back to waiting for a request to be
-            // available in self.receive_buf.
-
-            // The following is some ugly but workable code that attempts to find the end of an
-            // HTTP 1.x request in receive_buf. We need to do this for now because parse_request()
-            // expects the entire request contents as parameter.
-            if self.receive_buf_left > 2 {
-                let b = self.receive_buf.as_mut();
-                for i in 0..self.receive_buf_left - 1 {
-                    // We're basically looking for a double new line, which can only appear at the
-                    // end of a valid request.
-                    if b[i] == b'\n' {
-                        let end = if b[i + 1] == b'\n' {
-                            i + 2
-                        } else if i + 3 <= self.receive_buf_left && &b[i + 1..i + 3] == b"\r\n" {
-                            i + 3
-                        } else {
-                            continue;
-                        };
-
-                        // We found a potential request, let's parse it.
-                        let response = parse_request(&b[..end]);
-                        // The unwrap is safe because a Vec will allocate more space until all the
-                        // writes succeed.
-                        response.write_all(&mut self.response_buf).unwrap();
-
-                        // Sanity check because the current logic operates under this assumption.
-                        assert!(self.response_buf.len() < u32::max_value() as usize);
-
-                        // We have to remove the bytes up to end from receive_buf, by shifting the
-                        // others to the beginning of the buffer, and updating receive_buf_left.
-                        // Also, advance the rwnd edge of the inner connection.
-                        // TODO: Maximum efficiency.
-                        for j in 0..b.len() - end {
-                            b[j] = b[j + end];
-                        }
-                        self.receive_buf_left -= end;
-                        self.connection.advance_local_rwnd_edge(end as u32);
-                        break;
+            // available in self.receive_buf. Pipelined requests remain in the buffer until the
+            // responses to the previous ones have been sent.
+            let end = match Request::complete_len(&self.receive_buf[..self.receive_buf_left]) {
+                Ok(end) => end,
+                Err(_) => {
+                    // We can't tell where an invalid request ends, so we answer with an error
+                    // and then close the connection.
+                    self.close_after_response = true;
+                    Some(self.receive_buf_left)
+                }
+            };
+
+            if let Some(end) = end {
+                match long_poll(&self.mmds, &self.receive_buf[..end]) {
+                    Some(poll) => {
+                        METRICS.mmds.long_polls.inc();
+                        self.long_poll = Some((poll.revision, Instant::now() + poll.timeout));
                     }
+                    None => self.answer_request(end),
                 }
             }
 
@@ -225,13 +232,131 @@ impl Endpoint {
             }
         }
 
-        // We close the connection after receiving a FIN, and making sure there are no more
-        // responses to send.
-        if self.connection.fin_received() && self.response_buf.is_empty() {
+        // We close the connection after receiving a FIN (or when the last request asked for it),
+        // and making sure there are no more responses to send.
+        if (self.connection.fin_received() || self.close_after_response)
+            && !self.response_pending()
+            && self.long_poll.is_none()
+        {
             self.connection.close();
         }
     }
 
+    // Writes the response to the request which ends at position `end` of receive_buf, and then
+    // removes the request from the buffer.
+    fn answer_request(&mut self, end: usize) {
+        let mut response = parse_request(&self.mmds, &self.receive_buf[..end]);
+        if !response.keep_alive() {
+            self.close_after_response = true;
+        }
+        // The unwraps are safe because a Vec will allocate more space until all the writes
+        // succeed.
+        if response.is_streaming() {
+            response.write_head(&mut self.response_buf).unwrap();
+            self.response = Some(response);
+            self.fill_response_buf();
+        } else {
+            response.write_all(&mut self.response_buf).unwrap();
+        }
+
+        // Sanity check because the current logic operates under this assumption.
+        assert!(self.response_buf.len() < u32::max_value() as usize);
+
+        // We have to remove the bytes up to end from receive_buf, by shifting the others to the
+        // beginning of the buffer, and updating receive_buf_left. Also, advance the rwnd edge of
+        // the inner connection. Only the bytes received so far have to be moved.
+        let b = self.receive_buf.as_mut();
+        for j in 0..self.receive_buf_left - end {
+            b[j] = b[j + end];
+        }
+        self.receive_buf_left -= end;
+        self.connection.advance_local_rwnd_edge(end as u32);
+    }
+
+    // Returns true while there are response bytes which were not yet ACKed, or not even read from
+    // the body of a streamed response.
+    #[inline]
+    fn response_pending(&self) -> bool {
+        !self.response_buf.is_empty() || self.response.is_some()
+    }
+
+    // Reads more of the streamed response body (if any) into response_buf, without going past the
+    // remote receive window, or RESPONSE_BUF_MAX_SIZE.
+    fn fill_response_buf(&mut self) {
+        let window = (self.connection.remote_rwnd_edge() - self.response_seq).0 as usize;
+        let limit = min(window, RESPONSE_BUF_MAX_SIZE);
+
+        let result = match self.response {
+            Some(ref mut response) => {
+                let mut result = Ok(false);
+                while let Ok(false) = result {
+                    if self.response_buf.len() >= limit {
+                        break;
+                    }
+                    let max_len = limit - self.response_buf.len();
+                    result = response.write_body_chunk(&mut self.response_buf, max_len);
+                }
+                result
+            }
+            None => return,
+        };
+
+        match result {
+            // The whole body is in response_buf now.
+            Ok(true) => self.response = None,
+            Ok(false) => (),
+            Err(_) => {
+                // There's no way to tell the client that the body is incomplete, other than
+                // resetting the connection.
+                METRICS.mmds.tx_errors.inc();
+                self.response = None;
+                self.connection.reset();
+                self.stop_receiving = true;
+            }
+        }
+
+        // Sanity check because the current logic operates under this assumption.
+        assert!(self.response_buf.len() < u32::max_value() as usize);
+    }
+
+    // Answers the request which waits for the MMDS contents to change, if they changed in the
+    // meantime, or if the wait timed out. Returns true if the request was answered.
+    pub fn check_long_poll(&mut self) -> bool {
+        let (revision, deadline) = match self.long_poll {
+            Some(long_poll) => long_poll,
+            None => return false,
+        };
+
+        let changed = self
+            .mmds
+            .lock()
+            .expect("Failed to check MMDS revision due to poisoned lock")
+            .revision() != revision;
+        if !changed && Instant::now() < deadline {
+            return false;
+        }
+        if !changed {
+            METRICS.mmds.long_poll_timeouts.inc();
+        }
+
+        self.long_poll = None;
+        // The waiting request is complete, and at the beginning of receive_buf, so the unwrap
+        // is safe.
+        let end = Request::complete_len(&self.receive_buf[..self.receive_buf_left])
+            .ok()
+            .and_then(|end| end)
+            .unwrap();
+        self.answer_request(end);
+        true
+    }
+
+    // Returns the point in time when the wait of the current request for the MMDS contents to
+    // change times out, if there is such a request.
+    #[inline]
+    pub fn long_poll_deadline(&self) -> Option<Instant> {
+        self.long_poll.map(|(_, deadline)| deadline)
+    }
+
     pub fn write_next_segment<'a>(
         &mut self,
         buf: &'a mut [u8],
@@ -264,6 +389,13 @@ impl Endpoint {
 
     #[inline]
     pub fn is_evictable(&self) -> bool {
+        // A long poll keeps the connection quiet on purpose, so we only consider evicting it after
+        // the deadline has passed.
+        if let Some(deadline) = self.long_poll_deadline() {
+            if Instant::now() < deadline {
+                return false;
+            }
+        }
         timestamp_cycles().wrapping_sub(self.last_segment_received_timestamp)
             > self.eviction_threshold
     }
@@ -289,6 +421,8 @@ impl Endpoint {
 
 #[cfg(test)]
 mod tests {
+    extern crate serde_json;
+
     use super::*;
 
     use std::fmt;
@@ -303,6 +437,10 @@ mod tests {
         }
     }
 
+    fn empty_mmds() -> Arc<Mutex<Mmds>> {
+        Arc::new(Mutex::new(Mmds::default()))
+    }
+
     #[test]
     fn test_endpoint() {
         let mut buf1 = [0u8; 500];
@@ -317,14 +455,14 @@ mod tests {
         // Put another flag on the SYN so it becomes invalid.
         syn.set_flags_after_ns(TcpFlags::ACK);
         assert_eq!(
-            Endpoint::new_with_defaults(&syn).unwrap_err(),
+            Endpoint::new_with_defaults(&syn, empty_mmds()).unwrap_err(),
             PassiveOpenError::InvalidSyn
         );
 
         // Fix the SYN and create an endpoint.
         syn.set_flags_after_ns(TcpFlags::SYN);
         let remote_isn = syn.sequence_number();
-        let mut e = Endpoint::new_with_defaults(&syn).unwrap();
+        let mut e = Endpoint::new_with_defaults(&syn, empty_mmds()).unwrap();
 
         // Let's complete the three-way handshake. The next segment sent by the endpoint should
         // be a SYNACK.
@@ -483,12 +621,12 @@ mod tests {
         // The endpoint should be evictable now.
         assert!(e.is_evictable());
 
-        // Finally, let's fill self.receive_buf with the following request, and see if we get the
-        // reset we expect on the next segment.
+        // Finally, let's fill self.receive_buf with the following request. Its head is too large,
+        // so we expect an error response rather than a reset.
         let request_to_fill = vec![0u8; RCV_BUF_MAX_SIZE - e.receive_buf_left];
 
         {
-            // Hack: have to artificially increase t.mss to create this segment which is 2k+.
+            // Hack: have to artificially increase t.mss to create this large segment.
             t.mss = RCV_BUF_MAX_SIZE as u16;
             let mut data = t.write_data(write_buf.as_mut(), request_to_fill.as_ref());
 
@@ -502,8 +640,366 @@ mod tests {
             let s = e
                 .write_next_segment(write_buf.as_mut(), t.mss_reserved)
                 .unwrap();
-            assert_eq!(s.inner().flags_after_ns(), TcpFlags::RST);
+            assert!(!s.inner().flags_after_ns().intersects(TcpFlags::RST));
+            let response = from_utf8(s.inner().payload()).unwrap();
+            assert!(response.starts_with("HTTP/1.1 400"));
+            assert!(response.contains("Request headers too large."));
+        }
+    }
+
+    #[test]
+    fn test_large_request() {
+        let mut buf1 = [0u8; 500];
+        let mut buf2 = [0u8; 500];
+        let mut write_buf = vec![0u8; RCV_BUF_MAX_SIZE + 100];
+
+        let mut t = ConnectionTester::new();
+        // Hack: have to artificially increase t.mss to send each request in a single segment.
+        t.mss = RCV_BUF_MAX_SIZE as u16;
+        let mmds = empty_mmds();
+        mmds.lock().unwrap().set_guest_data_limit(MAX_BODY_LEN);
+
+        let mut syn = t.write_syn(buf1.as_mut());
+        syn.set_flags_after_ns(TcpFlags::SYN);
+        let remote_isn = syn.sequence_number();
+        let mut e = Endpoint::new_with_defaults(&syn, mmds.clone()).unwrap();
+
+        let endpoint_isn = e
+            .write_next_segment(write_buf.as_mut(), t.mss_reserved)
+            .unwrap()
+            .inner()
+            .sequence_number();
+
+        let mut ctrl = t.write_ctrl(buf2.as_mut());
+        ctrl.set_flags_after_ns(TcpFlags::ACK);
+        ctrl.set_sequence_number(remote_isn.wrapping_add(1));
+        ctrl.set_ack_number(endpoint_isn.wrapping_add(1));
+        e.receive_segment(&ctrl);
+        assert!(e.connection.is_established());
+
+        let mut remote_first_not_sent = remote_isn.wrapping_add(1);
+        let mut endpoint_first_not_sent = endpoint_isn.wrapping_add(1);
+        let mut send_request = |e: &mut Endpoint, body: &str| -> String {
+            let request = format!(
+                "PUT /guest/blob HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            {
+                let mut data = t.write_data(write_buf.as_mut(), request.as_bytes());
+                data.set_flags_after_ns(TcpFlags::ACK);
+                data.set_sequence_number(remote_first_not_sent);
+                data.set_ack_number(endpoint_first_not_sent);
+                e.receive_segment(&data);
+            }
+            remote_first_not_sent = remote_first_not_sent.wrapping_add(request.len() as u32);
+
+            let s = e
+                .write_next_segment(write_buf.as_mut(), t.mss_reserved)
+                .unwrap();
+            assert_eq!(s.inner().ack_number(), remote_first_not_sent);
+            endpoint_first_not_sent =
+                endpoint_first_not_sent.wrapping_add(s.inner().payload_len() as u32);
+            from_utf8(s.inner().payload()).unwrap().to_string()
+        };
+
+        // A request with a body which is larger than a few KB, but within the limits of the HTTP
+        // parser, gets answered as usual.
+        let body = format!("\"{}\"", "a".repeat(10_000));
+        let response = send_request(&mut e, &body);
+        assert!(response.starts_with("HTTP/1.1 204"));
+        assert_eq!(
+            mmds.lock()
+                .unwrap()
+                .get_value("/guest/blob".to_string())
+                .unwrap(),
+            vec!["a".repeat(10_000)]
+        );
+        assert!(!e.is_done());
+
+        // A body over the limit gets a 413, instead of a reset.
+        let body = format!("\"{}\"", "a".repeat(MAX_BODY_LEN));
+        let response = send_request(&mut e, &body);
+        assert!(response.starts_with("HTTP/1.1 413"));
+    }
+
+    #[test]
+    fn test_pipelining() {
+        let mut buf1 = [0u8; 500];
+        let mut buf2 = [0u8; 500];
+        let mut write_buf = [0u8; RCV_BUF_MAX_SIZE + 100];
+
+        let t = ConnectionTester::new();
+
+        let mut syn = t.write_syn(buf1.as_mut());
+        syn.set_flags_after_ns(TcpFlags::SYN);
+        let remote_isn = syn.sequence_number();
+        let mut e = Endpoint::new_with_defaults(&syn, empty_mmds()).unwrap();
+
+        let endpoint_isn = e
+            .write_next_segment(write_buf.as_mut(), t.mss_reserved)
+            .unwrap()
+            .inner()
+            .sequence_number();
+
+        let mut ctrl = t.write_ctrl(buf2.as_mut());
+        ctrl.set_flags_after_ns(TcpFlags::ACK);
+        ctrl.set_sequence_number(remote_isn.wrapping_add(1));
+        ctrl.set_ack_number(endpoint_isn.wrapping_add(1));
+        e.receive_segment(&ctrl);
+        assert!(e.connection.is_established());
+
+        // Two pipelined requests, followed by the beginning of a third one.
+        let requests = b"GET /a HTTP/1.1\r\n\r\n\
+                         GET /b HTTP/1.1\r\nConnection: close\r\n\r\n\
+                         GET /c";
+        let remote_first_not_sent = remote_isn.wrapping_add(1 + requests.len() as u32);
+        {
+            let mut data = t.write_data(write_buf.as_mut(), requests.as_ref());
+            data.set_flags_after_ns(TcpFlags::ACK);
+            data.set_sequence_number(remote_isn.wrapping_add(1));
+            data.set_ack_number(endpoint_isn.wrapping_add(1));
+            e.receive_segment(&data);
+        }
+
+        // Only the first request is answered for now, and the connection stays open.
+        let mut endpoint_first_not_sent = {
+            let s = e
+                .write_next_segment(write_buf.as_mut(), t.mss_reserved)
+                .unwrap();
+            assert_eq!(s.inner().flags_after_ns(), TcpFlags::ACK);
+            let response = from_utf8(s.inner().payload()).unwrap();
+            assert!(response.contains("Resource not found: /a."));
+            assert!(response.contains("Connection: keep-alive"));
+            s.inner()
+                .sequence_number()
+                .wrapping_add(s.inner().payload_len() as u32)
+        };
+
+        // ACKing the first response makes the endpoint answer the second request.
+        {
+            let mut ctrl = t.write_ctrl(buf2.as_mut());
+            ctrl.set_flags_after_ns(TcpFlags::ACK);
+            ctrl.set_sequence_number(remote_first_not_sent);
+            ctrl.set_ack_number(endpoint_first_not_sent);
+            e.receive_segment(&ctrl);
+        }
+        endpoint_first_not_sent = {
+            let s = e
+                .write_next_segment(write_buf.as_mut(), t.mss_reserved)
+                .unwrap();
+            let response = from_utf8(s.inner().payload()).unwrap();
+            assert!(response.contains("Resource not found: /b."));
+            assert!(response.contains("Connection: close"));
+            s.inner()
+                .sequence_number()
+                .wrapping_add(s.inner().payload_len() as u32)
+        };
+
+        // The second request asked for the connection to be closed, so the endpoint sends a FIN
+        // once the response is ACKed, and the third request is never answered.
+        {
+            let mut ctrl = t.write_ctrl(buf2.as_mut());
+            ctrl.set_flags_after_ns(TcpFlags::ACK);
+            ctrl.set_sequence_number(remote_first_not_sent);
+            ctrl.set_ack_number(endpoint_first_not_sent);
+            e.receive_segment(&ctrl);
+        }
+        {
+            let s = e
+                .write_next_segment(write_buf.as_mut(), t.mss_reserved)
+                .unwrap();
+            assert_eq!(s.inner().flags_after_ns(), TcpFlags::ACK | TcpFlags::FIN);
+            assert_eq!(s.inner().payload_len(), 0);
+        }
+        assert!(e.response_buf.is_empty());
+    }
+
+    #[test]
+    fn test_long_poll() {
+        let mut buf1 = [0u8; 500];
+        let mut buf2 = [0u8; 500];
+        let mut write_buf = [0u8; RCV_BUF_MAX_SIZE + 100];
+
+        let t = ConnectionTester::new();
+        let mmds = empty_mmds();
+        mmds.lock()
+            .unwrap()
+            .put_data(serde_json::from_str(r#"{"status": "booting"}"#).unwrap())
+            .unwrap();
+
+        let mut syn = t.write_syn(buf1.as_mut());
+        syn.set_flags_after_ns(TcpFlags::SYN);
+        let remote_isn = syn.sequence_number();
+        let mut e = Endpoint::new_with_defaults(&syn, mmds.clone()).unwrap();
+
+        let endpoint_isn = e
+            .write_next_segment(write_buf.as_mut(), t.mss_reserved)
+            .unwrap()
+            .inner()
+            .sequence_number();
+
+        let mut ctrl = t.write_ctrl(buf2.as_mut());
+        ctrl.set_flags_after_ns(TcpFlags::ACK);
+        ctrl.set_sequence_number(remote_isn.wrapping_add(1));
+        ctrl.set_ack_number(endpoint_isn.wrapping_add(1));
+        e.receive_segment(&ctrl);
+        assert!(e.connection.is_established());
+
+        // The guest already knows the current revision, and asks for the next one.
+        let request = b"GET /status HTTP/1.1\r\nIf-None-Match: \"1\"\r\n\r\n";
+        let mut remote_first_not_sent = remote_isn.wrapping_add(1 + request.len() as u32);
+        {
+            let mut data = t.write_data(write_buf.as_mut(), request.as_ref());
+            data.set_flags_after_ns(TcpFlags::ACK);
+            data.set_sequence_number(remote_isn.wrapping_add(1));
+            data.set_ack_number(endpoint_isn.wrapping_add(1));
+            e.receive_segment(&data);
+        }
+
+        // The request waits, so only an ACK is sent.
+        {
+            let s = e
+                .write_next_segment(write_buf.as_mut(), t.mss_reserved)
+                .unwrap();
+            assert_eq!(s.inner().ack_number(), remote_first_not_sent);
+            assert_eq!(s.inner().payload_len(), 0);
+        }
+        assert_eq!(e.next_segment_status(), NextSegmentStatus::Nothing);
+        assert!(e.long_poll_deadline().unwrap() > Instant::now());
+        assert!(!e.check_long_poll());
+
+        // The request is answered once the host updates the MMDS contents.
+        mmds.lock()
+            .unwrap()
+            .patch_data(serde_json::from_str(r#"{"status": "ready"}"#).unwrap())
+            .unwrap();
+        assert!(e.check_long_poll());
+        assert!(e.long_poll_deadline().is_none());
+        assert_eq!(e.next_segment_status(), NextSegmentStatus::Available);
+        let endpoint_first_not_sent = {
+            let s = e
+                .write_next_segment(write_buf.as_mut(), t.mss_reserved)
+                .unwrap();
+            let response = from_utf8(s.inner().payload()).unwrap();
+            assert!(response.starts_with("HTTP/1.1 200"));
+            assert!(response.contains("ETag: \"2\""));
+            assert!(response.ends_with("ready"));
+            s.inner()
+                .sequence_number()
+                .wrapping_add(s.inner().payload_len() as u32)
+        };
+
+        // A request waiting for the next revision, which times out.
+        let request = b"GET /status HTTP/1.1\r\nIf-None-Match: \"2\"\r\n\r\n";
+        {
+            let mut data = t.write_data(write_buf.as_mut(), request.as_ref());
+            data.set_flags_after_ns(TcpFlags::ACK);
+            data.set_sequence_number(remote_first_not_sent);
+            data.set_ack_number(endpoint_first_not_sent);
+            e.receive_segment(&data);
+        }
+        remote_first_not_sent = remote_first_not_sent.wrapping_add(request.len() as u32);
+        assert!(!e.check_long_poll());
+
+        // Let's hack this a bit and move the deadline to the present.
+        e.long_poll = Some((2, Instant::now()));
+        let timeouts = METRICS.mmds.long_poll_timeouts.count();
+        assert!(e.check_long_poll());
+        assert_eq!(METRICS.mmds.long_poll_timeouts.count(), timeouts + 1);
+        {
+            let s = e
+                .write_next_segment(write_buf.as_mut(), t.mss_reserved)
+                .unwrap();
+            assert_eq!(s.inner().ack_number(), remote_first_not_sent);
+            let response = from_utf8(s.inner().payload()).unwrap();
+            assert!(response.starts_with("HTTP/1.1 304"));
+            assert!(response.contains("ETag: \"2\""));
+        }
+        assert_eq!(e.receive_buf_left, 0);
+    }
+
+    #[test]
+    fn test_streamed_response() {
+        let mut buf1 = [0u8; 500];
+        let mut buf2 = [0u8; 500];
+        let mut write_buf = [0u8; RCV_BUF_MAX_SIZE + 100];
+
+        let t = ConnectionTester::new();
+        let mmds = empty_mmds();
+        let user_data: String = (0..40_000u32)
+            .map(|i| (b'a' + (i % 26) as u8) as char)
+            .collect();
+        mmds.lock()
+            .unwrap()
+            .put_data(
+                serde_json::from_str(&format!(r#"{{"user-data": "{}"}}"#, user_data)).unwrap(),
+            )
+            .unwrap();
+
+        let mut syn = t.write_syn(buf1.as_mut());
+        syn.set_flags_after_ns(TcpFlags::SYN);
+        let remote_isn = syn.sequence_number();
+        let mut e = Endpoint::new_with_defaults(&syn, mmds.clone()).unwrap();
+
+        let endpoint_isn = e
+            .write_next_segment(write_buf.as_mut(), t.mss_reserved)
+            .unwrap()
+            .inner()
+            .sequence_number();
+
+        let mut ctrl = t.write_ctrl(buf2.as_mut());
+        ctrl.set_flags_after_ns(TcpFlags::ACK);
+        ctrl.set_sequence_number(remote_isn.wrapping_add(1));
+        ctrl.set_ack_number(endpoint_isn.wrapping_add(1));
+        e.receive_segment(&ctrl);
+        assert!(e.connection.is_established());
+
+        let request = b"GET /user-data HTTP/1.1\r\n\r\n";
+        let remote_first_not_sent = remote_isn.wrapping_add(1 + request.len() as u32);
+        {
+            let mut data = t.write_data(write_buf.as_mut(), request.as_ref());
+            data.set_flags_after_ns(TcpFlags::ACK);
+            data.set_sequence_number(remote_isn.wrapping_add(1));
+            data.set_ack_number(endpoint_isn.wrapping_add(1));
+            e.receive_segment(&data);
+        }
+        assert!(e.response.is_some());
+
+        // We receive the response one window at a time, and ACK everything before moving on.
+        let mut received = Vec::new();
+        let mut ack = endpoint_isn.wrapping_add(1);
+        while e.response_pending() {
+            // Only what fits in the remote receive window is kept in the buffer (plus the framing
+            // of the last chunk).
+            assert!(e.response_buf.len() <= t.remote_window_size as usize + 10);
+
+            while let Some(s) = e.write_next_segment(write_buf.as_mut(), t.mss_reserved) {
+                assert_eq!(s.inner().sequence_number(), ack);
+                received.extend_from_slice(s.inner().payload());
+                ack = ack.wrapping_add(s.inner().payload_len() as u32);
+            }
+
+            let mut ctrl = t.write_ctrl(buf2.as_mut());
+            ctrl.set_flags_after_ns(TcpFlags::ACK);
+            ctrl.set_sequence_number(remote_first_not_sent);
+            ctrl.set_ack_number(ack);
+            e.receive_segment(&ctrl);
         }
+
+        // The connection stays open after the whole response has been ACKed.
+        assert!(e.response_buf.is_empty());
+        assert_eq!(e.next_segment_status(), NextSegmentStatus::Nothing);
+
+        let response = from_utf8(&received).unwrap();
+        let head_end = response.find("\r\n\r\n").unwrap() + 4;
+        assert!(response[..head_end].starts_with("HTTP/1.1 200"));
+        assert!(response[..head_end].contains(&format!(
+            "Content-Length: {}\r\n",
+            user_data.len()
+        )));
+        let body = &response[head_end..];
+        assert_eq!(body, user_data);
     }
 }
 
@@ -512,4 +1008,8 @@ impl Endpoint {
     pub fn set_eviction_threshold(&mut self, value: u64) {
         self.eviction_threshold = value;
     }
+
+    pub fn set_long_poll(&mut self, value: Option<(u64, Instant)>) {
+        self.long_poll = value;
+    }
 }
@@ -2,16 +2,23 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::collections::{HashMap, HashSet};
-use std::net::Ipv4Addr;
-use std::num::NonZeroUsize;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::num::{NonZeroU16, NonZeroU64, NonZeroUsize};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
+use mmds::data_store::Mmds;
 use pdu::bytes::NetworkBytes;
 use pdu::ipv4::{Error as IPv4PacketError, IPv4Packet, PROTOCOL_TCP};
-use pdu::tcp::{Error as TcpSegmentError, Flags as TcpFlags, TcpSegment};
-use tcp::endpoint::Endpoint;
+use pdu::ipv6::{Error as IPv6PacketError, IPv6Packet};
+use pdu::tcp::{Error as TcpSegmentError, Flags as TcpFlags, TcpOptions, TcpSegment};
+use pdu::Incomplete;
+use tcp::endpoint::{Endpoint, EVICTION_THRESHOLD};
 use tcp::{NextSegmentStatus, RstConfig};
 
-// TODO: This is currently IPv4 specific. Maybe change it to a more generic implementation.
+// Despite the name, the handler accepts connections over both IPv4 and IPv6 (when a local IPv6
+// address is configured). Each connection is associated with the address family of its remote
+// endpoint.
 
 // When sending or receiving segments, we may encounter events such as connections being added or
 // removed, and others. The following two enums represent any such occurrences when receiving
@@ -36,29 +43,33 @@ pub enum WriteEvent {
 #[cfg_attr(test, derive(Debug, PartialEq))]
 pub enum RecvError {
     InvalidPort,
+    // An IPv6 packet was received, but the handler has no local IPv6 address.
+    NoLocalIPv6Addr,
     TcpSegment(TcpSegmentError),
 }
 
 #[cfg_attr(test, derive(Debug, PartialEq))]
 pub enum WriteNextError {
     IPv4Packet(IPv4PacketError),
+    IPv6Packet(IPv6PacketError),
     TcpSegment(TcpSegmentError),
 }
 
-// Generally speaking, a TCP/IPv4 connection is identified using the four-tuple (src_addr, src_port,
-// dst_addr, dst_port). However, the IPv4 address and TCP port of the MMDS endpoint are fixed, so
-// we can get away with uniquely identifying connections using just the remote address and port.
+// Generally speaking, a TCP/IP connection is identified using the four-tuple (src_addr, src_port,
+// dst_addr, dst_port). However, the IP addresses (one per family) and TCP port of the MMDS endpoint
+// are fixed, so we can get away with uniquely identifying connections using just the remote
+// address and port.
 #[derive(Clone, Copy, Eq, Hash, PartialEq)]
 #[cfg_attr(test, derive(Debug))]
 struct ConnectionTuple {
-    remote_addr: Ipv4Addr,
+    remote_addr: IpAddr,
     remote_port: u16,
 }
 
 impl ConnectionTuple {
-    fn new(remote_addr: Ipv4Addr, remote_port: u16) -> Self {
+    fn new<A: Into<IpAddr>>(remote_addr: A, remote_port: u16) -> Self {
         ConnectionTuple {
-            remote_addr,
+            remote_addr: remote_addr.into(),
             remote_port,
         }
     }
@@ -66,6 +77,8 @@ impl ConnectionTuple {
 
 pub struct TcpIPv4Handler {
     local_addr: Ipv4Addr,
+    // Connections over IPv6 are only accepted when this is set.
+    local_ipv6_addr: Option<Ipv6Addr>,
     local_port: u16,
     // This map holds the currently active endpoints, identified by their connection tuple.
     connections: HashMap<ConnectionTuple, Endpoint>,
@@ -80,6 +93,12 @@ pub struct TcpIPv4Handler {
     rst_queue: Vec<(ConnectionTuple, RstConfig)>,
     // Maximum size of the RST queue.
     max_pending_resets: usize,
+    // The retransmission timeout (in cycles) of new connections.
+    connection_rto_period: NonZeroU64,
+    // How many consecutive retransmission timeouts a connection can go through before being reset.
+    connection_rto_count_max: NonZeroU16,
+    // The data store used by the endpoints to answer requests.
+    mmds: Arc<Mutex<Mmds>>,
 }
 
 // Only used locally, in the receive_packet method, to differentiate between different outcomes
@@ -97,14 +116,19 @@ impl TcpIPv4Handler {
     #[inline]
     pub fn new(
         local_addr: Ipv4Addr,
+        local_ipv6_addr: Option<Ipv6Addr>,
         local_port: u16,
         max_connections: NonZeroUsize,
         max_pending_resets: NonZeroUsize,
+        connection_rto_period: NonZeroU64,
+        connection_rto_count_max: NonZeroU16,
+        mmds: Arc<Mutex<Mmds>>,
     ) -> Self {
         let max_connections = max_connections.get();
         let max_pending_resets = max_pending_resets.get();
         TcpIPv4Handler {
             local_addr,
+            local_ipv6_addr,
             local_port,
             connections: HashMap::with_capacity(max_connections),
             max_connections,
@@ -112,24 +136,45 @@ impl TcpIPv4Handler {
             next_timeout: None,
             rst_queue: Vec::with_capacity(max_pending_resets),
             max_pending_resets,
+            connection_rto_period,
+            connection_rto_count_max,
+            mmds,
         }
     }
 
     pub fn receive_packet<T: NetworkBytes>(
         &mut self,
         packet: &IPv4Packet<T>,
+    ) -> Result<RecvEvent, RecvError> {
+        self.receive_segment(IpAddr::V4(packet.source_address()), packet.payload())
+    }
+
+    pub fn receive_ipv6_packet<T: NetworkBytes>(
+        &mut self,
+        packet: &IPv6Packet<T>,
+    ) -> Result<RecvEvent, RecvError> {
+        if self.local_ipv6_addr.is_none() {
+            return Err(RecvError::NoLocalIPv6Addr);
+        }
+        self.receive_segment(IpAddr::V6(packet.source_address()), packet.payload())
+    }
+
+    fn receive_segment(
+        &mut self,
+        remote_addr: IpAddr,
+        segment_bytes: &[u8],
     ) -> Result<RecvEvent, RecvError> {
         // TODO: We skip verifying the checksum, just in case the device model relies on offloading
         // checksum computation from the guest to some other entity. Clear this up at some point!
         // (Issue #520)
         let segment =
-            TcpSegment::from_bytes(packet.payload(), None).map_err(RecvError::TcpSegment)?;
+            TcpSegment::from_bytes(segment_bytes, None).map_err(RecvError::TcpSegment)?;
 
         if segment.destination_port() != self.local_port {
             return Err(RecvError::InvalidPort);
         }
 
-        let tuple = ConnectionTuple::new(packet.source_address(), segment.source_port());
+        let tuple = ConnectionTuple::new(remote_addr, segment.source_port());
 
         let outcome = if let Some(endpoint) = self.connections.get_mut(&tuple) {
             endpoint.receive_segment(&segment);
@@ -161,7 +206,14 @@ impl TcpIPv4Handler {
                 return Ok(RecvEvent::Nothing);
             }
             RecvSegmentOutcome::NewConnection => {
-                let endpoint = match Endpoint::new_with_defaults(&segment) {
+                // The unwrap() is safe because the constant is greater than 0.
+                let endpoint = match Endpoint::new(
+                    &segment,
+                    self.mmds.clone(),
+                    NonZeroU64::new(EVICTION_THRESHOLD).unwrap(),
+                    self.connection_rto_period,
+                    self.connection_rto_count_max,
+                ) {
                     Ok(endpoint) => endpoint,
                     Err(_) => return Ok(RecvEvent::FailedNewConnection),
                 };
@@ -284,6 +336,63 @@ impl TcpIPv4Handler {
         self.enqueue_rst_config(tuple, RstConfig::new(&s));
     }
 
+    // Writes an IP packet which carries a TCP segment from the local endpoint to `tuple`. The
+    // segment itself is written by `f`, which receives the payload area of the packet, and may
+    // return None if it has nothing to write after all.
+    fn write_ip_packet<F>(
+        buf: &mut [u8],
+        local_addr: Ipv4Addr,
+        local_ipv6_addr: Option<Ipv6Addr>,
+        local_port: u16,
+        tuple: &ConnectionTuple,
+        f: F,
+    ) -> Result<Option<NonZeroUsize>, WriteNextError>
+    where
+        F: for<'b> FnOnce(&'b mut [u8])
+            -> Result<Option<Incomplete<TcpSegment<'b, &'b mut [u8]>>>, TcpSegmentError>,
+    {
+        let packet_len = match tuple.remote_addr {
+            IpAddr::V4(remote_addr) => {
+                let mut packet = IPv4Packet::write_header(buf, PROTOCOL_TCP, local_addr, remote_addr)
+                    .map_err(WriteNextError::IPv4Packet)?;
+                let segment_len = match f(packet.inner_mut().payload_mut())
+                    .map_err(WriteNextError::TcpSegment)?
+                {
+                    Some(segment) => segment
+                        .finalize(
+                            local_port,
+                            tuple.remote_port,
+                            Some((local_addr, remote_addr)),
+                        ).len(),
+                    None => return Ok(None),
+                };
+                packet.with_payload_len_unchecked(segment_len, true).len()
+            }
+            IpAddr::V6(remote_addr) => {
+                // The unwrap() is safe because IPv6 connections only exist when the local IPv6
+                // address is set.
+                let local_ipv6_addr = local_ipv6_addr.unwrap();
+                let mut packet =
+                    IPv6Packet::write_header(buf, PROTOCOL_TCP, local_ipv6_addr, remote_addr)
+                        .map_err(WriteNextError::IPv6Packet)?;
+                let segment_len = match f(packet.inner_mut().payload_mut())
+                    .map_err(WriteNextError::TcpSegment)?
+                {
+                    Some(segment) => segment
+                        .finalize_ipv6(
+                            local_port,
+                            tuple.remote_port,
+                            Some((local_ipv6_addr, remote_addr)),
+                        ).len(),
+                    None => return Ok(None),
+                };
+                packet.with_payload_len_unchecked(segment_len).len()
+            }
+        };
+        // The unwrap() is safe because packet_len > 0.
+        Ok(Some(NonZeroUsize::new(packet_len).unwrap()))
+    }
+
     pub fn write_next_packet(
         &mut self,
         buf: &mut [u8],
@@ -292,13 +401,6 @@ impl TcpIPv4Handler {
         let mut writer_status = None;
         let mut event = WriteEvent::Nothing;
 
-        // We use self.local_addr for the dst_addr parameter also just as a placeholder value. The
-        // actual destination address is written below, after deciding which endpoint is allowed
-        // to send the next packet.
-        let mut packet =
-            IPv4Packet::write_header(buf, PROTOCOL_TCP, self.local_addr, self.local_addr)
-                .map_err(WriteNextError::IPv4Packet)?;
-
         // We set mss_used to 0, because we don't add any IP options.
         // TODO: Maybe get this nicely from packet at some point.
         let mss_reserved = 0;
@@ -308,32 +410,26 @@ impl TcpIPv4Handler {
         // any TCP options, or a payload.
         if let Some((tuple, rst_cfg)) = self.rst_queue.pop() {
             let (seq, ack, flags_after_ns) = rst_cfg.seq_ack_tcp_flags();
-            let segment_len = TcpSegment::write_incomplete_segment::<[u8]>(
-                packet.inner_mut().payload_mut(),
-                seq,
-                ack,
-                flags_after_ns,
-                10000,
-                None,
-                0,
-                None,
-            ).map_err(WriteNextError::TcpSegment)?
-            .finalize(
+            let packet_len = Self::write_ip_packet(
+                buf,
+                self.local_addr,
+                self.local_ipv6_addr,
                 self.local_port,
-                tuple.remote_port,
-                Some((self.local_addr, tuple.remote_addr)),
-            ).len();
-
-            packet
-                .inner_mut()
-                .set_destination_address(tuple.remote_addr);
-
-            let packet_len = packet.with_payload_len_unchecked(segment_len, true).len();
-            // The unwrap() is safe because packet_len > 0.
-            return Ok((
-                Some(NonZeroUsize::new(packet_len).unwrap()),
-                WriteEvent::Nothing,
-            ));
+                &tuple,
+                |segment_buf| {
+                    TcpSegment::write_incomplete_segment::<[u8]>(
+                        segment_buf,
+                        seq,
+                        ack,
+                        flags_after_ns,
+                        10000,
+                        &TcpOptions::default(),
+                        0,
+                        None,
+                    ).map(Some)
+                },
+            )?;
+            return Ok((packet_len, WriteEvent::Nothing));
         }
 
         for tuple in self
@@ -344,32 +440,21 @@ impl TcpIPv4Handler {
             // Tuples in self.active_connection or self.next_timeout should also appear as keys
             // in self.connections.
             let endpoint = self.connections.get_mut(tuple).unwrap();
-            // We need this block to clearly delimit the lifetime of the mutable borrow started by
-            // the following packet.inner_mut().
-            let segment_len = {
-                let maybe_segment =
-                    endpoint.write_next_segment(packet.inner_mut().payload_mut(), mss_reserved);
-
-                match maybe_segment {
-                    Some(segment) => segment
-                        .finalize(
-                            self.local_port,
-                            tuple.remote_port,
-                            Some((self.local_addr, tuple.remote_addr)),
-                        ).len(),
-                    None => continue,
-                }
-            };
 
-            packet
-                .inner_mut()
-                .set_destination_address(tuple.remote_addr);
-
-            let ip_len = packet.with_payload_len_unchecked(segment_len, true).len();
+            let maybe_len = Self::write_ip_packet(
+                buf,
+                self.local_addr,
+                self.local_ipv6_addr,
+                self.local_port,
+                tuple,
+                |segment_buf| Ok(endpoint.write_next_segment(segment_buf, mss_reserved)),
+            )?;
 
-            // The unwrap is safe because ip_len > 0.
-            len = Some(NonZeroUsize::new(ip_len).unwrap());
+            if maybe_len.is_none() {
+                continue;
+            }
 
+            len = maybe_len;
             writer_status = Some((*tuple, endpoint.is_done()));
 
             break;
@@ -392,6 +477,39 @@ impl TcpIPv4Handler {
         Ok((len, event))
     }
 
+    // Answers the requests which wait for the MMDS contents to change, if the contents changed
+    // in the meantime, or if their wait timed out.
+    pub fn check_long_polls(&mut self) {
+        let answered: Vec<ConnectionTuple> = self
+            .connections
+            .iter_mut()
+            .filter_map(|(tuple, endpoint)| {
+                if endpoint.check_long_poll() {
+                    Some(*tuple)
+                } else {
+                    None
+                }
+            }).collect();
+
+        for tuple in answered {
+            // The unwrap is safe because tuple was just found among the keys of
+            // self.connections.
+            let status = self.connections.get(&tuple).unwrap().next_segment_status();
+            if !self.check_next_segment_status(&tuple, status) {
+                self.active_connections.remove(&tuple);
+            }
+        }
+    }
+
+    // Returns the earliest point in time when a request waiting for the MMDS contents to change
+    // times out, if there are any such requests.
+    pub fn next_long_poll_deadline(&self) -> Option<Instant> {
+        self.connections
+            .values()
+            .filter_map(Endpoint::long_poll_deadline)
+            .min()
+    }
+
     #[inline]
     pub fn next_segment_status(&self) -> NextSegmentStatus {
         if !self.active_connections.is_empty() || !self.rst_queue.is_empty() {
@@ -408,6 +526,9 @@ impl TcpIPv4Handler {
 
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
+    use ns::{DEFAULT_CONNECTION_RTO_COUNT_MAX, DEFAULT_CONNECTION_RTO_PERIOD};
     use pdu::bytes::NetworkBytesMut;
 
     use super::*;
@@ -485,9 +606,13 @@ mod tests {
 
         let mut h = TcpIPv4Handler::new(
             local_addr,
+            None,
             local_port,
             NonZeroUsize::new(max_connections).unwrap(),
             NonZeroUsize::new(max_pending_resets).unwrap(),
+            NonZeroU64::new(DEFAULT_CONNECTION_RTO_PERIOD).unwrap(),
+            NonZeroU16::new(DEFAULT_CONNECTION_RTO_COUNT_MAX).unwrap(),
+            Arc::new(Mutex::new(Mmds::default())),
         );
 
         // We set the proper value of dst_addr from the start, because the TcpHandler expects this
@@ -508,7 +633,7 @@ mod tests {
                 456,
                 TcpFlags::empty(),
                 10000,
-                None,
+                &TcpOptions::default(),
                 100,
                 None,
                 None,
@@ -691,4 +816,159 @@ mod tests {
         assert_eq!(h.connections.len(), 1);
         assert_eq!(h.active_connections.len(), 0);
     }
+
+    #[test]
+    fn test_handler_long_poll_eviction() {
+        let mut buf = [0u8; 100];
+
+        let local_addr = Ipv4Addr::new(169, 254, 169, 254);
+        let local_port = 80;
+        let remote_addr = Ipv4Addr::new(10, 0, 0, 1);
+        let remote_port = 1012;
+
+        // There's only room for a single connection.
+        let mut h = TcpIPv4Handler::new(
+            local_addr,
+            None,
+            local_port,
+            NonZeroUsize::new(1).unwrap(),
+            NonZeroUsize::new(2).unwrap(),
+            NonZeroU64::new(DEFAULT_CONNECTION_RTO_PERIOD).unwrap(),
+            NonZeroU16::new(DEFAULT_CONNECTION_RTO_COUNT_MAX).unwrap(),
+            Arc::new(Mutex::new(Mmds::default())),
+        );
+
+        let mut p =
+            IPv4Packet::write_header(buf.as_mut(), PROTOCOL_TCP, remote_addr, local_addr).unwrap();
+        let s_len = TcpSegment::write_segment::<[u8]>(
+            p.inner_mut().payload_mut(),
+            remote_port,
+            local_port,
+            123,
+            0,
+            TcpFlags::SYN,
+            10000,
+            &TcpOptions::default(),
+            100,
+            None,
+            None,
+        ).unwrap()
+            .len();
+        let mut p = p.with_payload_len_unchecked(s_len, false);
+
+        assert_eq!(h.receive_packet(&p), Ok(RecvEvent::NewConnectionSuccessful));
+        // SYNACK
+        assert_eq!(drain_packets(&mut h, remote_addr), Ok(1));
+
+        // The connection has been quiet for long enough, but it's waiting on a long poll which
+        // didn't time out yet.
+        let remote_tuple = ConnectionTuple::new(remote_addr, remote_port);
+        {
+            let endpoint = h.connections.get_mut(&remote_tuple).unwrap();
+            endpoint.set_eviction_threshold(0);
+            endpoint.set_long_poll(Some((0, Instant::now() + Duration::from_secs(60))));
+        }
+
+        // The table is full, and the long poll must not be evicted.
+        inner_tcp_mut(&mut p).set_source_port(remote_port + 1);
+        assert_eq!(h.receive_packet(&p), Ok(RecvEvent::NewConnectionDropped));
+        assert_eq!(h.connections.len(), 1);
+        assert!(h.connections.contains_key(&remote_tuple));
+        // The RST for the dropped connection.
+        assert_eq!(drain_packets(&mut h, remote_addr), Ok(1));
+
+        // Once the deadline passes, the connection can be evicted as usual.
+        h.connections
+            .get_mut(&remote_tuple)
+            .unwrap()
+            .set_long_poll(Some((0, Instant::now())));
+        assert_eq!(h.receive_packet(&p), Ok(RecvEvent::NewConnectionReplacing));
+        assert_eq!(h.connections.len(), 1);
+        assert!(!h.connections.contains_key(&remote_tuple));
+        // One SYNACK for the new connection, and one RST for the old one.
+        assert_eq!(drain_packets(&mut h, remote_addr), Ok(2));
+    }
+
+    #[test]
+    fn test_handler_ipv6() {
+        let mut buf = [0u8; 100];
+        let mut buf2 = [0u8; 2000];
+
+        let local_addr = Ipv4Addr::new(169, 254, 169, 254);
+        let local_ipv6_addr = "fe80::a9fe:a9fe".parse::<Ipv6Addr>().unwrap();
+        let local_port = 80;
+        let remote_addr = "fe80::1".parse::<Ipv6Addr>().unwrap();
+        let remote_port = 1012;
+
+        let mut h = TcpIPv4Handler::new(
+            local_addr,
+            Some(local_ipv6_addr),
+            local_port,
+            NonZeroUsize::new(2).unwrap(),
+            NonZeroUsize::new(2).unwrap(),
+            NonZeroU64::new(DEFAULT_CONNECTION_RTO_PERIOD).unwrap(),
+            NonZeroU16::new(DEFAULT_CONNECTION_RTO_COUNT_MAX).unwrap(),
+            Arc::new(Mutex::new(Mmds::default())),
+        );
+
+        let mut p = IPv6Packet::write_header(
+            buf.as_mut(),
+            PROTOCOL_TCP,
+            remote_addr,
+            local_ipv6_addr,
+        ).unwrap();
+
+        let s_len = TcpSegment::write_segment::<[u8]>(
+            p.inner_mut().payload_mut(),
+            remote_port,
+            local_port,
+            123,
+            0,
+            TcpFlags::SYN,
+            10000,
+            &TcpOptions::default(),
+            100,
+            None,
+            None,
+        ).unwrap()
+        .len();
+        let p = p.with_payload_len_unchecked(s_len);
+
+        assert_eq!(h.receive_ipv6_packet(&p), Ok(RecvEvent::NewConnectionSuccessful));
+        assert!(h
+            .connections
+            .contains_key(&ConnectionTuple::new(remote_addr, remote_port)));
+
+        // The SYNACK must be sent back over IPv6, with a checksum based on the IPv6 addresses.
+        let len = h.write_next_packet(buf2.as_mut()).unwrap().0.unwrap().get();
+        {
+            let reply = IPv6Packet::from_bytes(&buf2[..len]).unwrap();
+            assert_eq!(reply.next_header(), PROTOCOL_TCP);
+            assert_eq!(reply.source_address(), local_ipv6_addr);
+            assert_eq!(reply.destination_address(), remote_addr);
+
+            let s = TcpSegment::from_bytes(reply.payload(), None).unwrap();
+            assert_eq!(s.flags_after_ns(), TcpFlags::SYN | TcpFlags::ACK);
+            assert_eq!(s.source_port(), local_port);
+            assert_eq!(s.destination_port(), remote_port);
+            assert_eq!(s.ack_number(), 124);
+            assert_eq!(s.compute_checksum_ipv6(local_ipv6_addr, remote_addr), 0);
+        }
+
+        // A handler without a local IPv6 address refuses IPv6 packets.
+        let mut h = TcpIPv4Handler::new(
+            local_addr,
+            None,
+            local_port,
+            NonZeroUsize::new(2).unwrap(),
+            NonZeroUsize::new(2).unwrap(),
+            NonZeroU64::new(DEFAULT_CONNECTION_RTO_PERIOD).unwrap(),
+            NonZeroU16::new(DEFAULT_CONNECTION_RTO_COUNT_MAX).unwrap(),
+            Arc::new(Mutex::new(Mmds::default())),
+        );
+        assert_eq!(
+            h.receive_ipv6_packet(&p).unwrap_err(),
+            RecvError::NoLocalIPv6Addr
+        );
+    }
 }
@@ -77,6 +77,8 @@ pub fn seq_after(a: Wrapping<u32>, b: Wrapping<u32>) -> bool {
 mod tests {
     use super::*;
 
+    use pdu::tcp::TcpOptions;
+
     #[test]
     fn test_rst_config() {
         let mut buf = [0u8; 100];
@@ -92,7 +94,7 @@ mod tests {
             ack,
             TcpFlags::empty(),
             0,
-            None,
+            &TcpOptions::default(),
             100,
             None,
             None,
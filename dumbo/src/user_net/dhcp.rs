@@ -0,0 +1,415 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Contains the minimal amount of DHCP (RFC 2131) support required by the user-mode network stack
+//! to hand out a single, fixed IPv4 address to the guest.
+//!
+//! Only the fixed size part of the BOOTP message and a handful of options are interpreted. The
+//! `sname` and `file` fields are never used for option overloading by our replies, and are ignored
+//! when parsing requests.
+
+use std::net::Ipv4Addr;
+use std::result::Result;
+
+use byteorder::{BigEndian, ByteOrder};
+
+use net_util::MacAddr;
+
+/// The UDP port used by DHCP servers.
+pub const SERVER_PORT: u16 = 67;
+/// The UDP port used by DHCP clients.
+pub const CLIENT_PORT: u16 = 68;
+
+const OP_OFFSET: usize = 0;
+const HTYPE_OFFSET: usize = 1;
+const HLEN_OFFSET: usize = 2;
+const XID_OFFSET: usize = 4;
+const FLAGS_OFFSET: usize = 10;
+const CIADDR_OFFSET: usize = 12;
+const YIADDR_OFFSET: usize = 16;
+const SIADDR_OFFSET: usize = 20;
+const CHADDR_OFFSET: usize = 28;
+const MAGIC_COOKIE_OFFSET: usize = 236;
+const OPTIONS_OFFSET: usize = 240;
+
+const OP_BOOTREQUEST: u8 = 1;
+const OP_BOOTREPLY: u8 = 2;
+const HTYPE_ETHERNET: u8 = 1;
+const HLEN_ETHERNET: u8 = 6;
+const MAGIC_COOKIE: u32 = 0x6382_5363;
+
+const OPTION_PAD: u8 = 0;
+const OPTION_SUBNET_MASK: u8 = 1;
+const OPTION_ROUTER: u8 = 3;
+const OPTION_DNS_SERVER: u8 = 6;
+const OPTION_REQUESTED_ADDR: u8 = 50;
+const OPTION_LEASE_TIME: u8 = 51;
+const OPTION_MESSAGE_TYPE: u8 = 53;
+const OPTION_SERVER_ID: u8 = 54;
+const OPTION_END: u8 = 255;
+
+// The largest reply we can generate: the fixed part, plus the message type (3 bytes), server id,
+// lease time, subnet mask, router, and DNS server (6 bytes each) options, and the end option.
+const MAX_REPLY_LEN: usize = OPTIONS_OFFSET + 3 + 5 * 6 + 1;
+
+/// DHCP message types, as carried by option 53.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MessageType {
+    Discover,
+    Offer,
+    Request,
+    Decline,
+    Ack,
+    Nak,
+    Release,
+    Inform,
+}
+
+impl MessageType {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(MessageType::Discover),
+            2 => Some(MessageType::Offer),
+            3 => Some(MessageType::Request),
+            4 => Some(MessageType::Decline),
+            5 => Some(MessageType::Ack),
+            6 => Some(MessageType::Nak),
+            7 => Some(MessageType::Release),
+            8 => Some(MessageType::Inform),
+            _ => None,
+        }
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            MessageType::Discover => 1,
+            MessageType::Offer => 2,
+            MessageType::Request => 3,
+            MessageType::Decline => 4,
+            MessageType::Ack => 5,
+            MessageType::Nak => 6,
+            MessageType::Release => 7,
+            MessageType::Inform => 8,
+        }
+    }
+}
+
+/// Describes the errors which may occur while handling DHCP messages.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// The hardware type or address length does not describe an Ethernet MAC address.
+    HardwareType,
+    /// The magic cookie is missing.
+    MagicCookie,
+    /// The message type option is missing, or has an unknown value.
+    MessageType,
+    /// The message is not a BOOTREQUEST.
+    Op,
+    /// An option does not fit within the message.
+    OptionLen,
+    /// The specified slice is too short.
+    SliceTooShort,
+}
+
+/// The information we care about from a DHCP message sent by the guest.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DhcpRequest {
+    /// The transaction id chosen by the client.
+    pub xid: u32,
+    /// The value of the `flags` field.
+    pub flags: u16,
+    /// The client IP address (only filled in by clients which are bound, renewing or rebinding).
+    pub ciaddr: Ipv4Addr,
+    /// The hardware address of the client.
+    pub chaddr: MacAddr,
+    /// The value of the message type option.
+    pub message_type: MessageType,
+    /// The value of the requested IP address option, if present.
+    pub requested_addr: Option<Ipv4Addr>,
+    /// The value of the server identifier option, if present.
+    pub server_id: Option<Ipv4Addr>,
+}
+
+fn read_ipv4_addr(bytes: &[u8], offset: usize) -> Ipv4Addr {
+    Ipv4Addr::from(BigEndian::read_u32(&bytes[offset..]))
+}
+
+fn read_ipv4_option(value: &[u8]) -> Result<Ipv4Addr, Error> {
+    if value.len() != 4 {
+        return Err(Error::OptionLen);
+    }
+    Ok(read_ipv4_addr(value, 0))
+}
+
+impl DhcpRequest {
+    /// Attempts to interpret `bytes` (the payload of an UDP datagram) as a client DHCP message.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() < OPTIONS_OFFSET {
+            return Err(Error::SliceTooShort);
+        }
+
+        if bytes[OP_OFFSET] != OP_BOOTREQUEST {
+            return Err(Error::Op);
+        }
+
+        if bytes[HTYPE_OFFSET] != HTYPE_ETHERNET || bytes[HLEN_OFFSET] != HLEN_ETHERNET {
+            return Err(Error::HardwareType);
+        }
+
+        if BigEndian::read_u32(&bytes[MAGIC_COOKIE_OFFSET..]) != MAGIC_COOKIE {
+            return Err(Error::MagicCookie);
+        }
+
+        let mut message_type = None;
+        let mut requested_addr = None;
+        let mut server_id = None;
+
+        let mut i = OPTIONS_OFFSET;
+        while i < bytes.len() {
+            let code = bytes[i];
+            if code == OPTION_END {
+                break;
+            }
+            if code == OPTION_PAD {
+                i += 1;
+                continue;
+            }
+            if i + 1 >= bytes.len() {
+                return Err(Error::OptionLen);
+            }
+            let start = i + 2;
+            let end = start + bytes[i + 1] as usize;
+            if end > bytes.len() {
+                return Err(Error::OptionLen);
+            }
+            let value = &bytes[start..end];
+
+            match code {
+                OPTION_MESSAGE_TYPE => {
+                    if value.len() != 1 {
+                        return Err(Error::OptionLen);
+                    }
+                    message_type = MessageType::from_u8(value[0]);
+                }
+                OPTION_REQUESTED_ADDR => requested_addr = Some(read_ipv4_option(value)?),
+                OPTION_SERVER_ID => server_id = Some(read_ipv4_option(value)?),
+                _ => (),
+            }
+
+            i = end;
+        }
+
+        Ok(DhcpRequest {
+            xid: BigEndian::read_u32(&bytes[XID_OFFSET..]),
+            flags: BigEndian::read_u16(&bytes[FLAGS_OFFSET..]),
+            ciaddr: read_ipv4_addr(bytes, CIADDR_OFFSET),
+            chaddr: MacAddr::from_bytes_unchecked(&bytes[CHADDR_OFFSET..CHADDR_OFFSET + 6]),
+            message_type: message_type.ok_or(Error::MessageType)?,
+            requested_addr,
+            server_id,
+        })
+    }
+}
+
+/// The contents of a DHCP reply sent by the user-mode network stack.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DhcpReply {
+    /// Either `Offer`, `Ack` or `Nak`.
+    pub message_type: MessageType,
+    /// Copied from the request.
+    pub xid: u32,
+    /// Copied from the request.
+    pub flags: u16,
+    /// Copied from the request.
+    pub chaddr: MacAddr,
+    /// The address handed out to the client (ignored for NAKs).
+    pub yiaddr: Ipv4Addr,
+    /// The address of the server, which is also used as the router for the guest.
+    pub server_id: Ipv4Addr,
+    /// Ignored for NAKs.
+    pub subnet_mask: Ipv4Addr,
+    /// Ignored for NAKs.
+    pub dns_server: Option<Ipv4Addr>,
+    /// The lease time, in seconds (ignored for NAKs).
+    pub lease_time: u32,
+}
+
+fn write_option(buf: &mut [u8], offset: usize, code: u8, value: &[u8]) -> usize {
+    buf[offset] = code;
+    buf[offset + 1] = value.len() as u8;
+    buf[offset + 2..offset + 2 + value.len()].copy_from_slice(value);
+    offset + 2 + value.len()
+}
+
+impl DhcpReply {
+    /// Writes the reply to `buf`, returning the number of bytes written.
+    pub fn write_to(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        if buf.len() < MAX_REPLY_LEN {
+            return Err(Error::SliceTooShort);
+        }
+
+        for b in buf[..OPTIONS_OFFSET].iter_mut() {
+            *b = 0;
+        }
+
+        buf[OP_OFFSET] = OP_BOOTREPLY;
+        buf[HTYPE_OFFSET] = HTYPE_ETHERNET;
+        buf[HLEN_OFFSET] = HLEN_ETHERNET;
+        BigEndian::write_u32(&mut buf[XID_OFFSET..], self.xid);
+        BigEndian::write_u16(&mut buf[FLAGS_OFFSET..], self.flags);
+        buf[CHADDR_OFFSET..CHADDR_OFFSET + 6].copy_from_slice(self.chaddr.get_bytes());
+        BigEndian::write_u32(&mut buf[MAGIC_COOKIE_OFFSET..], MAGIC_COOKIE);
+
+        let mut offset = write_option(
+            buf,
+            OPTIONS_OFFSET,
+            OPTION_MESSAGE_TYPE,
+            &[self.message_type.to_u8()],
+        );
+        offset = write_option(buf, offset, OPTION_SERVER_ID, &self.server_id.octets());
+
+        if self.message_type != MessageType::Nak {
+            BigEndian::write_u32(&mut buf[YIADDR_OFFSET..], u32::from(self.yiaddr));
+            BigEndian::write_u32(&mut buf[SIADDR_OFFSET..], u32::from(self.server_id));
+
+            let mut lease_time = [0u8; 4];
+            BigEndian::write_u32(&mut lease_time, self.lease_time);
+            offset = write_option(buf, offset, OPTION_LEASE_TIME, &lease_time);
+            offset = write_option(buf, offset, OPTION_SUBNET_MASK, &self.subnet_mask.octets());
+            offset = write_option(buf, offset, OPTION_ROUTER, &self.server_id.octets());
+            if let Some(addr) = self.dns_server {
+                offset = write_option(buf, offset, OPTION_DNS_SERVER, &addr.octets());
+            }
+        }
+
+        buf[offset] = OPTION_END;
+        Ok(offset + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_request(
+        buf: &mut [u8],
+        message_type: u8,
+        requested_addr: Option<Ipv4Addr>,
+    ) -> usize {
+        for b in buf[..OPTIONS_OFFSET].iter_mut() {
+            *b = 0;
+        }
+        buf[OP_OFFSET] = OP_BOOTREQUEST;
+        buf[HTYPE_OFFSET] = HTYPE_ETHERNET;
+        buf[HLEN_OFFSET] = HLEN_ETHERNET;
+        BigEndian::write_u32(&mut buf[XID_OFFSET..], 0xdead_beef);
+        BigEndian::write_u16(&mut buf[FLAGS_OFFSET..], 0x8000);
+        buf[CHADDR_OFFSET..CHADDR_OFFSET + 6].copy_from_slice(&[1, 2, 3, 4, 5, 6]);
+        BigEndian::write_u32(&mut buf[MAGIC_COOKIE_OFFSET..], MAGIC_COOKIE);
+
+        // Add some padding to make sure it's skipped.
+        buf[OPTIONS_OFFSET] = OPTION_PAD;
+        let mut offset = write_option(buf, OPTIONS_OFFSET + 1, OPTION_MESSAGE_TYPE, &[message_type]);
+        if let Some(addr) = requested_addr {
+            offset = write_option(buf, offset, OPTION_REQUESTED_ADDR, &addr.octets());
+        }
+        // An option we don't care about.
+        offset = write_option(buf, offset, 12, b"guest");
+        buf[offset] = OPTION_END;
+        offset + 1
+    }
+
+    #[test]
+    fn test_parse_request() {
+        let mut a = [0u8; 400];
+        let addr = Ipv4Addr::new(10, 0, 2, 15);
+
+        let len = write_request(a.as_mut(), 3, Some(addr));
+        let r = DhcpRequest::from_bytes(&a[..len]).unwrap();
+        assert_eq!(r.xid, 0xdead_beef);
+        assert_eq!(r.flags, 0x8000);
+        assert_eq!(r.ciaddr, Ipv4Addr::new(0, 0, 0, 0));
+        assert_eq!(r.chaddr, MacAddr::parse_str("01:02:03:04:05:06").unwrap());
+        assert_eq!(r.message_type, MessageType::Request);
+        assert_eq!(r.requested_addr, Some(addr));
+        assert_eq!(r.server_id, None);
+
+        assert_eq!(
+            DhcpRequest::from_bytes(&a[..OPTIONS_OFFSET - 1]).unwrap_err(),
+            Error::SliceTooShort
+        );
+        // Truncating the message in the middle of an option.
+        assert_eq!(
+            DhcpRequest::from_bytes(&a[..OPTIONS_OFFSET + 3]).unwrap_err(),
+            Error::OptionLen
+        );
+
+        // Unknown message type.
+        let len = write_request(a.as_mut(), 100, None);
+        assert_eq!(
+            DhcpRequest::from_bytes(&a[..len]).unwrap_err(),
+            Error::MessageType
+        );
+
+        let len = write_request(a.as_mut(), 1, None);
+        a[MAGIC_COOKIE_OFFSET] = 0;
+        assert_eq!(
+            DhcpRequest::from_bytes(&a[..len]).unwrap_err(),
+            Error::MagicCookie
+        );
+
+        let len = write_request(a.as_mut(), 1, None);
+        a[HLEN_OFFSET] = 8;
+        assert_eq!(
+            DhcpRequest::from_bytes(&a[..len]).unwrap_err(),
+            Error::HardwareType
+        );
+
+        let len = write_request(a.as_mut(), 1, None);
+        a[OP_OFFSET] = OP_BOOTREPLY;
+        assert_eq!(DhcpRequest::from_bytes(&a[..len]).unwrap_err(), Error::Op);
+    }
+
+    #[test]
+    fn test_write_reply() {
+        let mut a = [0u8; 400];
+
+        let mut reply = DhcpReply {
+            message_type: MessageType::Offer,
+            xid: 1234,
+            flags: 0,
+            chaddr: MacAddr::parse_str("01:02:03:04:05:06").unwrap(),
+            yiaddr: Ipv4Addr::new(10, 0, 2, 15),
+            server_id: Ipv4Addr::new(10, 0, 2, 2),
+            subnet_mask: Ipv4Addr::new(255, 255, 255, 0),
+            dns_server: Some(Ipv4Addr::new(10, 0, 2, 3)),
+            lease_time: 3600,
+        };
+
+        assert_eq!(
+            reply.write_to(&mut a[..MAX_REPLY_LEN - 1]).unwrap_err(),
+            Error::SliceTooShort
+        );
+
+        let len = reply.write_to(a.as_mut()).unwrap();
+        assert_eq!(len, MAX_REPLY_LEN);
+        assert_eq!(a[OP_OFFSET], OP_BOOTREPLY);
+        assert_eq!(BigEndian::read_u32(&a[XID_OFFSET..]), 1234);
+        assert_eq!(read_ipv4_addr(&a, YIADDR_OFFSET), reply.yiaddr);
+        assert_eq!(&a[CHADDR_OFFSET..CHADDR_OFFSET + 6], &[1, 2, 3, 4, 5, 6]);
+        assert_eq!(
+            &a[OPTIONS_OFFSET..OPTIONS_OFFSET + 3],
+            &[OPTION_MESSAGE_TYPE, 1, 2]
+        );
+        assert_eq!(
+            &a[len - 7..len],
+            &[OPTION_DNS_SERVER, 4, 10, 0, 2, 3, OPTION_END]
+        );
+
+        // NAKs don't carry any configuration.
+        reply.message_type = MessageType::Nak;
+        let len = reply.write_to(a.as_mut()).unwrap();
+        assert_eq!(len, OPTIONS_OFFSET + 3 + 6 + 1);
+        assert_eq!(read_ipv4_addr(&a, YIADDR_OFFSET), Ipv4Addr::new(0, 0, 0, 0));
+    }
+}
@@ -0,0 +1,1665 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A user-mode network stack which allows a guest to reach the outside world without requiring a
+//! tap device on the host.
+//!
+//! The stack pretends to be the default gateway of a small, private IPv4 subnet which contains the
+//! guest. It answers ARP requests for the gateway address, hands out the guest address via DHCP,
+//! and terminates the TCP connections and UDP flows initiated by the guest, proxying the data over
+//! regular host sockets. Connections towards the gateway address itself are directed to the host
+//! loopback interface, when explicitly allowed. Link-local and private destinations, which may
+//! belong to the host or its network, are off limits unless explicitly allowed as well. Only
+//! outgoing connections are supported.
+//!
+//! All host sockets are registered with an internal epoll instance, whose file descriptor is
+//! exposed via `AsRawFd`. The device model is expected to add this file descriptor to its own event
+//! loop, and call `write_next_frame()` whenever it becomes readable (in addition to right after
+//! handing over a frame via `receive_frame()`).
+
+pub mod dhcp;
+
+use std::cmp::min;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read, Write};
+use std::mem;
+use std::net::{Ipv4Addr, Shutdown, SocketAddrV4, TcpStream, UdpSocket};
+use std::num::{NonZeroU16, NonZeroU64, NonZeroUsize, Wrapping};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::result::Result;
+use std::time::{Duration, Instant};
+
+use epoll;
+use libc;
+use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
+
+use self::dhcp::{DhcpReply, DhcpRequest, MessageType};
+use fc_util::timestamp_cycles;
+use logger::{Metric, METRICS};
+use net_util::{Ipv4Cidr, MacAddr};
+use ns::{DEFAULT_CONNECTION_RTO_COUNT_MAX, DEFAULT_CONNECTION_RTO_PERIOD};
+use pdu::arp::{Error as ArpFrameError, EthIPv4ArpFrame, ETH_IPV4_FRAME_LEN};
+use pdu::bytes::NetworkBytes;
+use pdu::ethernet::{Error as EthernetFrameError, EthernetFrame, ETHERTYPE_ARP, ETHERTYPE_IPV4};
+use pdu::ipv4::{Error as IPv4PacketError, IPv4Packet, PROTOCOL_TCP, PROTOCOL_UDP};
+use pdu::tcp::{Error as TcpSegmentError, Flags as TcpFlags, TcpOptions, TcpSegment};
+use pdu::udp::{self, Error as UdpDatagramError, UdpDatagram};
+use pdu::Incomplete;
+use tcp::connection::{Connection, WriteNextError};
+use tcp::{seq_after, NextSegmentStatus, RstConfig};
+
+const DEFAULT_MAC_ADDR: &str = "06:01:23:45:67:02";
+const BROADCAST_MAC_ADDR: &str = "ff:ff:ff:ff:ff:ff";
+
+/// The default address of the gateway emulated by the user-mode stack.
+pub const DEFAULT_GATEWAY_ADDR: [u8; 4] = [10, 0, 2, 2];
+/// The default address handed out to the guest.
+pub const DEFAULT_GUEST_ADDR: [u8; 4] = [10, 0, 2, 15];
+/// The default netmask of the emulated subnet.
+pub const DEFAULT_NETMASK: [u8; 4] = [255, 255, 255, 0];
+
+// The guest can renew the lease as often as it likes, since the address never changes.
+const DHCP_LEASE_TIME: u32 = 86_400;
+
+const MAX_TCP_FLOWS: usize = 256;
+const MAX_UDP_FLOWS: usize = 256;
+const MAX_PENDING_RESETS: usize = 100;
+
+// How many bytes received from the guest can wait to be written to a host socket. This is also
+// the size of the receive window advertised to the guest.
+const TCP_TO_HOST_BUF_SIZE: usize = 32_768;
+// How many bytes read from a host socket can wait to be acknowledged by the guest.
+const TCP_TO_GUEST_BUF_SIZE: usize = 65_535;
+
+// How many datagrams read from a host socket can wait to be sent to the guest.
+const UDP_TO_GUEST_QUEUE_LEN: usize = 16;
+// The largest UDP payload which fits in an IPv4 packet.
+const UDP_MAX_PAYLOAD_LEN: usize = 65_507;
+// UDP flows are removed after this many seconds without any traffic.
+const UDP_FLOW_TIMEOUT_SECS: u64 = 60;
+
+// How often we wake up to check for expired retransmission timers, or idle UDP flows.
+const TCP_TIMER_PERIOD_MS: u64 = 100;
+const UDP_TIMER_PERIOD_MS: u64 = 1000;
+
+const EPOLL_EVENTS_LEN: usize = 32;
+// Host socket tokens start right after the one used by the timer.
+const TIMER_TOKEN: u64 = 0;
+
+#[cfg_attr(test, derive(Debug, PartialEq))]
+enum WriteFrameError {
+    Arp(ArpFrameError),
+    Dhcp(dhcp::Error),
+    Ethernet(EthernetFrameError),
+    IPv4Packet(IPv4PacketError),
+    TcpConnection(WriteNextError),
+    TcpSegment(TcpSegmentError),
+    UdpDatagram(UdpDatagramError),
+}
+
+/// The parameters of the subnet emulated by a `UserNetworkStack`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UserNetworkParams {
+    /// The address of the emulated gateway. Connections towards this address are directed to the
+    /// host loopback interface when `allow_host_loopback` is set, and refused otherwise.
+    pub gateway_addr: Ipv4Addr,
+    /// The address handed out to the guest via DHCP.
+    pub guest_addr: Ipv4Addr,
+    /// The netmask of the emulated subnet.
+    pub netmask: Ipv4Addr,
+    /// The DNS server advertised to the guest via DHCP, if any.
+    pub dns_addr: Option<Ipv4Addr>,
+    /// Whether the guest can reach the services listening on the host loopback interface, via
+    /// the gateway address.
+    pub allow_host_loopback: bool,
+    /// Destinations the guest can reach even though they are link-local or private, which are
+    /// off limits by default. The DNS server is always allowed.
+    pub allowed_destinations: Vec<Ipv4Cidr>,
+    /// Destinations the guest can never reach, which takes precedence over all of the above.
+    pub denied_destinations: Vec<Ipv4Cidr>,
+}
+
+impl Default for UserNetworkParams {
+    fn default() -> Self {
+        UserNetworkParams {
+            gateway_addr: Ipv4Addr::from(DEFAULT_GATEWAY_ADDR),
+            guest_addr: Ipv4Addr::from(DEFAULT_GUEST_ADDR),
+            netmask: Ipv4Addr::from(DEFAULT_NETMASK),
+            dns_addr: None,
+            allow_host_loopback: false,
+            allowed_destinations: Vec::new(),
+            denied_destinations: Vec::new(),
+        }
+    }
+}
+
+// Identifies a flow from the point of view of the guest. The guest address is implied.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+struct FlowKey {
+    guest_port: u16,
+    remote_addr: Ipv4Addr,
+    remote_port: u16,
+}
+
+#[derive(Clone, Copy)]
+enum FlowRef {
+    Tcp(FlowKey),
+    Udp(FlowKey),
+}
+
+// A TCP connection opened by the guest, and the host socket it is proxied to.
+struct TcpFlow {
+    connection: Connection,
+    stream: TcpStream,
+    // Set until the non-blocking connect() of the host socket completes. The SYNACK is only sent
+    // to the guest afterwards.
+    connecting: bool,
+    // Holds data received from the guest, which has not been written to the host socket yet.
+    to_host_buf: Box<[u8]>,
+    to_host_len: usize,
+    // Holds data read from the host socket, which has not been acknowledged by the guest yet.
+    to_guest_buf: Vec<u8>,
+    // The sequence number associated with the first byte from to_guest_buf.
+    to_guest_seq: Wrapping<u32>,
+    // Host sockets are registered in edge triggered mode, so we have to remember whether they
+    // are readable/writable until an operation returns WouldBlock.
+    host_readable: bool,
+    host_writable: bool,
+    host_eof: bool,
+    host_write_shutdown: bool,
+}
+
+impl TcpFlow {
+    fn new(connection: Connection, stream: TcpStream) -> Self {
+        TcpFlow {
+            // The connection was created via passive open, so this points to the sequence number
+            // right after the SYNACK.
+            to_guest_seq: connection.first_not_sent(),
+            connection,
+            stream,
+            connecting: true,
+            to_host_buf: vec![0u8; TCP_TO_HOST_BUF_SIZE].into_boxed_slice(),
+            to_host_len: 0,
+            to_guest_buf: Vec::new(),
+            host_readable: false,
+            host_writable: false,
+            host_eof: false,
+            host_write_shutdown: false,
+        }
+    }
+
+    fn receive_segment<T: NetworkBytes>(&mut self, s: &TcpSegment<T>) {
+        // We don't have to worry about writing out of bounds, because the receive window of the
+        // connection matches the free space in the buffer.
+        if let Ok((Some(len), _)) = self.connection.receive_segment(
+            s,
+            &mut self.to_host_buf[self.to_host_len..],
+            timestamp_cycles(),
+        ) {
+            self.to_host_len += len.get();
+        }
+
+        // Drop whatever the guest acknowledged from the front of to_guest_buf. The ACK may also
+        // cover our FIN, which does not correspond to any byte from the buffer.
+        let acked = (self.connection.highest_ack_received() - self.to_guest_seq).0 as usize;
+        if acked <= self.to_guest_buf.len() + 1 {
+            let len = min(acked, self.to_guest_buf.len());
+            self.to_guest_buf.drain(..len);
+            self.to_guest_seq += Wrapping(len as u32);
+        }
+    }
+
+    // Moves data between the host socket and the local buffers, as much as possible.
+    fn service_host(&mut self) -> io::Result<()> {
+        if self.connecting || self.connection.is_done() {
+            return Ok(());
+        }
+
+        while self.to_host_len > 0 && self.host_writable {
+            match self.stream.write(&self.to_host_buf[..self.to_host_len]) {
+                Ok(len) => {
+                    self.to_host_buf.copy_within(len..self.to_host_len, 0);
+                    self.to_host_len -= len;
+                    self.connection.advance_local_rwnd_edge(len as u32);
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => self.host_writable = false,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e),
+            }
+        }
+
+        // Pass the guest FIN along once everything before it reached the host.
+        if self.to_host_len == 0 && self.connection.fin_received() && !self.host_write_shutdown {
+            self.host_write_shutdown = true;
+            self.stream.shutdown(Shutdown::Write)?;
+        }
+
+        while self.host_readable && !self.host_eof && self.to_guest_buf.len() < TCP_TO_GUEST_BUF_SIZE
+        {
+            let old_len = self.to_guest_buf.len();
+            self.to_guest_buf.resize(TCP_TO_GUEST_BUF_SIZE, 0);
+            let result = self.stream.read(&mut self.to_guest_buf[old_len..]);
+            // Only keep the bytes which were actually read.
+            self.to_guest_buf
+                .truncate(old_len + *result.as_ref().unwrap_or(&0));
+
+            match result {
+                Ok(0) => self.host_eof = true,
+                Ok(_) => (),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => self.host_readable = false,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.maybe_close();
+        Ok(())
+    }
+
+    // Closes our end of the connection after the host closed its end, and everything it sent
+    // was written to segments. The Connection places the FIN right after the last byte sent.
+    fn maybe_close(&mut self) {
+        if self.host_eof
+            && self.connection.first_not_sent()
+                == self.to_guest_seq + Wrapping(self.to_guest_buf.len() as u32)
+        {
+            self.connection.close();
+        }
+    }
+
+    fn next_segment_status(&self) -> NextSegmentStatus {
+        if self.connecting {
+            return NextSegmentStatus::Nothing;
+        }
+
+        let data_end = self.to_guest_seq + Wrapping(self.to_guest_buf.len() as u32);
+        let can_send_new_data = seq_after(data_end, self.connection.first_not_sent())
+            && seq_after(
+                self.connection.remote_rwnd_edge(),
+                self.connection.first_not_sent(),
+            );
+
+        if can_send_new_data || self.connection.dup_ack_pending() {
+            NextSegmentStatus::Available
+        } else {
+            self.connection.control_segment_or_timeout_status()
+        }
+    }
+
+    fn write_next_segment<'a>(
+        &mut self,
+        buf: &'a mut [u8],
+    ) -> Result<Option<Incomplete<TcpSegment<'a, &'a mut [u8]>>>, WriteNextError> {
+        let payload_src = if !self.to_guest_buf.is_empty() {
+            Some((self.to_guest_buf.as_slice(), self.to_guest_seq))
+        } else {
+            None
+        };
+
+        // We use mss_reserved = 0, because we don't add any IP options.
+        self.connection
+            .write_next_segment(buf, 0, payload_src, timestamp_cycles())
+    }
+}
+
+// A stream of UDP datagrams exchanged between a guest port and a remote endpoint.
+struct UdpFlow {
+    socket: UdpSocket,
+    readable: bool,
+    to_guest: VecDeque<Vec<u8>>,
+    last_activity: Instant,
+}
+
+impl UdpFlow {
+    fn read_host(&mut self, buf: &mut [u8]) {
+        while self.readable && self.to_guest.len() < UDP_TO_GUEST_QUEUE_LEN {
+            match self.socket.recv(buf) {
+                Ok(len) => {
+                    self.to_guest.push_back(buf[..len].to_vec());
+                    self.last_activity = Instant::now();
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => self.readable = false,
+                // An ICMP error triggered by a previous send. There's no way to forward these to
+                // the guest right now, so we just keep on reading.
+                Err(ref e) if e.kind() == io::ErrorKind::ConnectionRefused => (),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(_) => {
+                    METRICS.user_net.host_socket_errors.inc();
+                    self.readable = false;
+                }
+            }
+        }
+    }
+}
+
+// Opens a non-blocking IPv4 socket of the given type, and starts connecting it to addr. The
+// sockets are created this way, rather than via the std helpers, so that the syscalls (and their
+// arguments) match the ones allowed by the seccomp filters of the VMM thread.
+fn connect_nonblocking<T: FromRawFd>(sock_type: libc::c_int, addr: SocketAddrV4) -> io::Result<T> {
+    // Safe because we check the return value.
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_INET,
+            sock_type | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+            0,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Safe because fd is a valid socket which is not owned by anything else. The returned object
+    // closes it when dropped.
+    let socket = unsafe { T::from_raw_fd(fd) };
+
+    let sockaddr = libc::sockaddr_in {
+        sin_family: libc::AF_INET as libc::sa_family_t,
+        sin_port: addr.port().to_be(),
+        sin_addr: libc::in_addr {
+            s_addr: u32::from(*addr.ip()).to_be(),
+        },
+        sin_zero: [0; 8],
+    };
+    // Safe because sockaddr is a valid sockaddr_in, and we pass its exact size.
+    let ret = unsafe {
+        libc::connect(
+            fd,
+            &sockaddr as *const libc::sockaddr_in as *const libc::sockaddr,
+            mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        let e = io::Error::last_os_error();
+        if e.raw_os_error() != Some(libc::EINPROGRESS) {
+            return Err(e);
+        }
+    }
+    Ok(socket)
+}
+
+/// Opens a TCP socket, and starts a non-blocking connect towards `addr`. Completion is signaled
+/// via `EPOLLOUT`, after which the outcome can be checked with `take_error()`.
+pub fn connect_tcp(addr: SocketAddrV4) -> io::Result<TcpStream> {
+    connect_nonblocking(libc::SOCK_STREAM, addr)
+}
+
+/// Opens a non-blocking UDP socket, connected to `addr`.
+pub fn connect_udp(addr: SocketAddrV4) -> io::Result<UdpSocket> {
+    connect_nonblocking(libc::SOCK_DGRAM, addr)
+}
+
+/// Implements a minimal NAT gateway in user space. See the module level documentation for details.
+pub struct UserNetworkStack {
+    // The Ethernet MAC address of the emulated gateway.
+    mac_addr: MacAddr,
+    // Updated every time we receive a frame from the guest.
+    remote_mac_addr: MacAddr,
+    params: UserNetworkParams,
+    epoll_fd: RawFd,
+    // Used to make sure we get to check retransmission timers and idle UDP flows.
+    timer: TimerFd,
+    // The period of the currently armed timer, if any.
+    timer_period_ms: Option<u64>,
+    next_token: u64,
+    // We only remember the most recently received ARP request for the gateway address.
+    pending_arp_reply: Option<Ipv4Addr>,
+    pending_dhcp_reply: Option<DhcpReply>,
+    rst_queue: Vec<(FlowKey, RstConfig)>,
+    tcp_flows: HashMap<FlowKey, TcpFlow>,
+    udp_flows: HashMap<FlowKey, UdpFlow>,
+    // Maps epoll tokens to flows.
+    tokens: HashMap<u64, FlowRef>,
+    // Scratch buffer used when reading datagrams from host sockets.
+    udp_buf: Box<[u8]>,
+}
+
+impl UserNetworkStack {
+    /// Creates a new stack which emulates the subnet described by `params`.
+    pub fn new(params: UserNetworkParams) -> io::Result<Self> {
+        let epoll_fd = epoll::create(true)?;
+        // Make sure the epoll fd gets closed if one of the following operations fails.
+        let stack_result = TimerFd::new_custom(ClockId::Monotonic, true, true).map(|timer| {
+            UserNetworkStack {
+                // The unwraps are safe if parse_str() is implemented properly.
+                mac_addr: MacAddr::parse_str(DEFAULT_MAC_ADDR).unwrap(),
+                remote_mac_addr: MacAddr::parse_str(BROADCAST_MAC_ADDR).unwrap(),
+                params,
+                epoll_fd,
+                timer,
+                timer_period_ms: None,
+                next_token: TIMER_TOKEN + 1,
+                pending_arp_reply: None,
+                pending_dhcp_reply: None,
+                rst_queue: Vec::new(),
+                tcp_flows: HashMap::new(),
+                udp_flows: HashMap::new(),
+                tokens: HashMap::new(),
+                udp_buf: vec![0u8; UDP_MAX_PAYLOAD_LEN].into_boxed_slice(),
+            }
+        });
+        let stack = match stack_result {
+            Ok(stack) => stack,
+            Err(e) => {
+                // Safe because we own epoll_fd.
+                unsafe { libc::close(epoll_fd) };
+                return Err(e);
+            }
+        };
+
+        let timer_fd = stack.timer.as_raw_fd();
+        stack.register(timer_fd, epoll::EPOLLIN, TIMER_TOKEN)?;
+        Ok(stack)
+    }
+
+    /// Returns the parameters of the emulated subnet.
+    pub fn params(&self) -> &UserNetworkParams {
+        &self.params
+    }
+
+    fn register(&self, fd: RawFd, events: epoll::Events, token: u64) -> io::Result<()> {
+        epoll::ctl(
+            self.epoll_fd,
+            epoll::EPOLL_CTL_ADD,
+            fd,
+            epoll::Event::new(events | epoll::EPOLLET, token),
+        )
+    }
+
+    fn new_token(&mut self) -> u64 {
+        let token = self.next_token;
+        // We skip the timer token when wrapping around, which is not really going to happen.
+        self.next_token = self.next_token.wrapping_add(1).max(TIMER_TOKEN + 1);
+        token
+    }
+
+    // Returns the host address flows towards addr are proxied to, or None if the guest is not
+    // allowed to reach it. Connections to the gateway address end up on the host loopback
+    // interface, if allowed. The guest can't target the loopback interface directly, via
+    // 127.0.0.0/8 or 0.0.0.0 (which Linux also treats as local). Link-local destinations (such as
+    // the cloud metadata service at 169.254.169.254) and private ones are only reachable when
+    // allowed, with the exception of the DNS server.
+    fn host_addr(&self, addr: Ipv4Addr) -> Option<Ipv4Addr> {
+        let params = &self.params;
+        if addr == params.gateway_addr {
+            return if params.allow_host_loopback {
+                Some(Ipv4Addr::new(127, 0, 0, 1))
+            } else {
+                None
+            };
+        }
+
+        let denied = addr.is_loopback()
+            || addr.is_unspecified()
+            || params.denied_destinations.iter().any(|d| d.contains(addr));
+        let restricted = (addr.is_link_local() || addr.is_private())
+            && params.dns_addr != Some(addr)
+            && !params.allowed_destinations.iter().any(|d| d.contains(addr));
+        if denied || restricted {
+            None
+        } else {
+            Some(addr)
+        }
+    }
+
+    /// Handles a frame sent by the guest. The `src` slice should hold the contents of an Ethernet
+    /// frame (of that exact size, without the CRC). Frames which are not understood or not
+    /// supported are dropped.
+    pub fn receive_frame(&mut self, src: &[u8]) {
+        let eth = match EthernetFrame::from_bytes(src) {
+            Ok(eth) => eth,
+            Err(_) => {
+                METRICS.user_net.rx_dropped.inc();
+                return;
+            }
+        };
+
+        self.remote_mac_addr = eth.src_mac();
+
+        let accepted = match eth.ethertype() {
+            ETHERTYPE_ARP => self.receive_arp(eth.payload()),
+            ETHERTYPE_IPV4 => self.receive_ipv4(eth.payload()),
+            _ => false,
+        };
+
+        if !accepted {
+            METRICS.user_net.rx_dropped.inc();
+        }
+    }
+
+    fn receive_arp(&mut self, bytes: &[u8]) -> bool {
+        if let Ok(arp) = EthIPv4ArpFrame::request_from_bytes(bytes) {
+            if arp.tpa() == self.params.gateway_addr {
+                self.pending_arp_reply = Some(arp.spa());
+                return true;
+            }
+        }
+        false
+    }
+
+    fn receive_ipv4(&mut self, bytes: &[u8]) -> bool {
+        // Ethernet frames may contain padding after the IPv4 packet, which we get rid of.
+        let bytes = if bytes.len() >= 4 {
+            let total_len = ((bytes[2] as usize) << 8) | bytes[3] as usize;
+            &bytes[..min(total_len, bytes.len())]
+        } else {
+            bytes
+        };
+
+        let ip = match IPv4Packet::from_bytes(bytes, true) {
+            Ok(ip) => ip,
+            Err(_) => return false,
+        };
+
+        // Fragmented packets are not supported. The guest should not be sending any, because
+        // path MTU discovery works just fine against the emulated gateway.
+        let (flags, fragment_offset) = ip.flags_and_fragment_offset();
+        if flags & 1 != 0 || fragment_offset != 0 {
+            return false;
+        }
+
+        match ip.protocol() {
+            PROTOCOL_TCP => self.receive_tcp(&ip),
+            PROTOCOL_UDP => self.receive_udp(&ip),
+            _ => false,
+        }
+    }
+
+    fn receive_tcp(&mut self, ip: &IPv4Packet<&[u8]>) -> bool {
+        let src_addr = ip.source_address();
+        let dst_addr = ip.destination_address();
+        if src_addr != self.params.guest_addr {
+            return false;
+        }
+
+        let segment = match TcpSegment::from_bytes(ip.payload(), Some((src_addr, dst_addr))) {
+            Ok(segment) => segment,
+            Err(_) => return false,
+        };
+
+        let key = FlowKey {
+            guest_port: segment.source_port(),
+            remote_addr: dst_addr,
+            remote_port: segment.destination_port(),
+        };
+        let flags = segment.flags_after_ns();
+
+        let remove = if let Some(flow) = self.tcp_flows.get_mut(&key) {
+            if flow.connecting {
+                // Anything other than a RST (most likely a retransmitted SYN) is ignored until
+                // the host side of the connection is established.
+                flags.intersects(TcpFlags::RST)
+            } else {
+                flow.receive_segment(&segment);
+                if flow.service_host().is_err() {
+                    METRICS.user_net.host_socket_errors.inc();
+                    flow.connection.reset();
+                }
+                flow.connection.is_done()
+            }
+        } else {
+            if flags == TcpFlags::SYN {
+                self.open_tcp_flow(key, &segment);
+            } else if !flags.intersects(TcpFlags::RST) {
+                self.enqueue_rst(key, RstConfig::new(&segment));
+            }
+            false
+        };
+
+        if remove {
+            self.remove_tcp_flow(&key);
+        }
+        true
+    }
+
+    fn open_tcp_flow(&mut self, key: FlowKey, syn: &TcpSegment<&[u8]>) {
+        if self.tcp_flows.len() >= MAX_TCP_FLOWS {
+            METRICS.user_net.flows_limit_exceeded.inc();
+            self.enqueue_rst(key, RstConfig::new(syn));
+            return;
+        }
+
+        // The unwraps are safe because the constants are greater than 0.
+        let connection = match Connection::passive_open(
+            syn,
+            TCP_TO_HOST_BUF_SIZE as u32,
+            NonZeroU64::new(DEFAULT_CONNECTION_RTO_PERIOD).unwrap(),
+            NonZeroU16::new(DEFAULT_CONNECTION_RTO_COUNT_MAX).unwrap(),
+        ) {
+            Ok(connection) => connection,
+            Err(_) => {
+                METRICS.user_net.rx_dropped.inc();
+                return;
+            }
+        };
+
+        let host_addr = match self.host_addr(key.remote_addr) {
+            Some(addr) => SocketAddrV4::new(addr, key.remote_port),
+            None => {
+                METRICS.user_net.flows_not_allowed.inc();
+                self.enqueue_rst(key, connection.make_rst_config());
+                return;
+            }
+        };
+        let stream = match connect_tcp(host_addr) {
+            Ok(stream) => stream,
+            Err(_) => {
+                METRICS.user_net.tcp_connect_fails.inc();
+                self.enqueue_rst(key, connection.make_rst_config());
+                return;
+            }
+        };
+
+        let token = self.new_token();
+        if self
+            .register(
+                stream.as_raw_fd(),
+                epoll::EPOLLIN | epoll::EPOLLOUT | epoll::EPOLLRDHUP,
+                token,
+            ).is_err()
+        {
+            METRICS.user_net.host_socket_errors.inc();
+            self.enqueue_rst(key, connection.make_rst_config());
+            return;
+        }
+
+        self.tcp_flows.insert(key, TcpFlow::new(connection, stream));
+        self.tokens.insert(token, FlowRef::Tcp(key));
+        METRICS.user_net.tcp_flows_created.inc();
+    }
+
+    fn remove_tcp_flow(&mut self, key: &FlowKey) {
+        // Closing the host socket also removes it from the epoll interest list.
+        if self.tcp_flows.remove(key).is_some() {
+            self.tokens.retain(|_, flow_ref| match *flow_ref {
+                FlowRef::Tcp(ref k) => k != key,
+                FlowRef::Udp(_) => true,
+            });
+            METRICS.user_net.tcp_flows_destroyed.inc();
+        }
+    }
+
+    fn enqueue_rst(&mut self, key: FlowKey, rst_cfg: RstConfig) {
+        if self.rst_queue.len() < MAX_PENDING_RESETS {
+            self.rst_queue.push((key, rst_cfg));
+        }
+    }
+
+    fn receive_udp(&mut self, ip: &IPv4Packet<&[u8]>) -> bool {
+        let src_addr = ip.source_address();
+        let dst_addr = ip.destination_address();
+
+        let datagram = match UdpDatagram::from_bytes(ip.payload(), Some((src_addr, dst_addr))) {
+            Ok(datagram) => datagram,
+            Err(_) => return false,
+        };
+
+        if datagram.destination_port() == dhcp::SERVER_PORT
+            && (dst_addr == Ipv4Addr::new(255, 255, 255, 255)
+                || dst_addr == self.params.gateway_addr)
+        {
+            return self.receive_dhcp(datagram.payload());
+        }
+
+        if src_addr != self.params.guest_addr || dst_addr.is_broadcast() || dst_addr.is_multicast()
+        {
+            return false;
+        }
+
+        let key = FlowKey {
+            guest_port: datagram.source_port(),
+            remote_addr: dst_addr,
+            remote_port: datagram.destination_port(),
+        };
+
+        if !self.udp_flows.contains_key(&key) && !self.open_udp_flow(key) {
+            return false;
+        }
+
+        // The unwrap is safe because the flow is present at this point.
+        let flow = self.udp_flows.get_mut(&key).unwrap();
+        flow.last_activity = Instant::now();
+        match flow.socket.send(datagram.payload()) {
+            Ok(_) => (),
+            // UDP is unreliable anyway, so we just drop the datagram.
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => (),
+            Err(_) => METRICS.user_net.host_socket_errors.inc(),
+        }
+        true
+    }
+
+    fn open_udp_flow(&mut self, key: FlowKey) -> bool {
+        if self.udp_flows.len() >= MAX_UDP_FLOWS {
+            METRICS.user_net.flows_limit_exceeded.inc();
+            return false;
+        }
+
+        let host_addr = match self.host_addr(key.remote_addr) {
+            Some(addr) => SocketAddrV4::new(addr, key.remote_port),
+            None => {
+                METRICS.user_net.flows_not_allowed.inc();
+                return false;
+            }
+        };
+        let socket = match connect_udp(host_addr) {
+            Ok(socket) => socket,
+            Err(_) => {
+                METRICS.user_net.host_socket_errors.inc();
+                return false;
+            }
+        };
+
+        let token = self.new_token();
+        if self
+            .register(socket.as_raw_fd(), epoll::EPOLLIN, token)
+            .is_err()
+        {
+            METRICS.user_net.host_socket_errors.inc();
+            return false;
+        }
+
+        self.udp_flows.insert(
+            key,
+            UdpFlow {
+                socket,
+                readable: false,
+                to_guest: VecDeque::new(),
+                last_activity: Instant::now(),
+            },
+        );
+        self.tokens.insert(token, FlowRef::Udp(key));
+        METRICS.user_net.udp_flows_created.inc();
+        true
+    }
+
+    fn remove_idle_udp_flows(&mut self) {
+        let timeout = Duration::from_secs(UDP_FLOW_TIMEOUT_SECS);
+        let expired: Vec<FlowKey> = self
+            .udp_flows
+            .iter()
+            .filter(|&(_, flow)| flow.last_activity.elapsed() >= timeout)
+            .map(|(key, _)| *key)
+            .collect();
+
+        for key in expired {
+            self.udp_flows.remove(&key);
+            self.tokens.retain(|_, flow_ref| match *flow_ref {
+                FlowRef::Udp(ref k) => *k != key,
+                FlowRef::Tcp(_) => true,
+            });
+            METRICS.user_net.udp_flows_destroyed.inc();
+        }
+    }
+
+    fn receive_dhcp(&mut self, payload: &[u8]) -> bool {
+        let request = match DhcpRequest::from_bytes(payload) {
+            Ok(request) => request,
+            Err(_) => return false,
+        };
+
+        let message_type = match request.message_type {
+            MessageType::Discover => MessageType::Offer,
+            MessageType::Request => {
+                if let Some(server_id) = request.server_id {
+                    if server_id != self.params.gateway_addr {
+                        // The guest chose a different server.
+                        return true;
+                    }
+                }
+                let addr = request.requested_addr.unwrap_or(request.ciaddr);
+                if addr == self.params.guest_addr {
+                    MessageType::Ack
+                } else {
+                    MessageType::Nak
+                }
+            }
+            // We don't care about anything else, since we always hand out the same address.
+            _ => return true,
+        };
+
+        self.pending_dhcp_reply = Some(DhcpReply {
+            message_type,
+            xid: request.xid,
+            flags: request.flags,
+            chaddr: request.chaddr,
+            yiaddr: self.params.guest_addr,
+            server_id: self.params.gateway_addr,
+            subnet_mask: self.params.netmask,
+            dns_server: self.params.dns_addr,
+            lease_time: DHCP_LEASE_TIME,
+        });
+        true
+    }
+
+    // Drains the internal epoll instance, and handles the events reported for host sockets.
+    fn process_host_events(&mut self) {
+        let mut events = [epoll::Event::new(epoll::Events::empty(), 0); EPOLL_EVENTS_LEN];
+
+        loop {
+            let num_events = match epoll::wait(self.epoll_fd, 0, &mut events[..]) {
+                Ok(num_events) => num_events,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => {
+                    METRICS.user_net.host_socket_errors.inc();
+                    return;
+                }
+            };
+
+            for event in events[..num_events].iter() {
+                self.handle_host_event(event.data(), event.events());
+            }
+
+            if num_events < EPOLL_EVENTS_LEN {
+                break;
+            }
+        }
+    }
+
+    fn handle_host_event(&mut self, token: u64, events: epoll::Events) {
+        if token == TIMER_TOKEN {
+            self.timer.read();
+            self.timer_period_ms = None;
+            self.remove_idle_udp_flows();
+            return;
+        }
+
+        // Events may still be reported for sockets which were closed in the meantime.
+        let flow_ref = match self.tokens.get(&token) {
+            Some(flow_ref) => *flow_ref,
+            None => return,
+        };
+
+        match flow_ref {
+            FlowRef::Tcp(key) => self.handle_tcp_event(key, events),
+            FlowRef::Udp(key) => {
+                if let Some(flow) = self.udp_flows.get_mut(&key) {
+                    flow.readable = true;
+                    flow.read_host(&mut self.udp_buf);
+                }
+            }
+        }
+    }
+
+    fn handle_tcp_event(&mut self, key: FlowKey, events: epoll::Events) {
+        let failure = epoll::EPOLLERR | epoll::EPOLLHUP;
+
+        let connect_failed = match self.tcp_flows.get_mut(&key) {
+            Some(ref mut flow) if flow.connecting => {
+                if !events.intersects(epoll::EPOLLOUT | failure) {
+                    return;
+                }
+                match flow.stream.take_error() {
+                    Ok(None) if !events.intersects(failure) => {
+                        flow.connecting = false;
+                        false
+                    }
+                    _ => true,
+                }
+            }
+            Some(_) => false,
+            None => return,
+        };
+
+        if connect_failed {
+            self.fail_tcp_connect(key);
+            return;
+        }
+
+        // The unwrap is safe because we just checked the flow exists.
+        let flow = self.tcp_flows.get_mut(&key).unwrap();
+        if events.intersects(epoll::EPOLLIN | epoll::EPOLLRDHUP | failure) {
+            flow.host_readable = true;
+        }
+        if events.intersects(epoll::EPOLLOUT | failure) {
+            flow.host_writable = true;
+        }
+        if flow.service_host().is_err() {
+            METRICS.user_net.host_socket_errors.inc();
+            flow.connection.reset();
+        }
+    }
+
+    // The guest gets a RST in response to its SYN.
+    fn fail_tcp_connect(&mut self, key: FlowKey) {
+        METRICS.user_net.tcp_connect_fails.inc();
+        let rst_cfg = match self.tcp_flows.get(&key) {
+            Some(flow) => flow.connection.make_rst_config(),
+            None => return,
+        };
+        self.remove_tcp_flow(&key);
+        self.enqueue_rst(key, rst_cfg);
+    }
+
+    /// Allows the stack to write a frame to the specified buffer. Will return:
+    /// - `None`, if there's nothing to send to the guest at this point.
+    /// - `Some(len)`, if a frame of the given length has been written to the specified buffer.
+    pub fn write_next_frame(&mut self, buf: &mut [u8]) -> Option<NonZeroUsize> {
+        self.process_host_events();
+
+        let result = if let Some(spa) = self.pending_arp_reply.take() {
+            self.write_arp_reply(buf, spa)
+        } else if let Some(reply) = self.pending_dhcp_reply.take() {
+            METRICS.user_net.dhcp_replies.inc();
+            self.write_dhcp_reply(buf, &reply)
+        } else {
+            self.write_packet(buf)
+        };
+
+        match result {
+            Ok(Some(len)) => return Some(len),
+            Ok(None) => (),
+            Err(_) => METRICS.user_net.tx_errors.inc(),
+        }
+
+        self.update_timer();
+        None
+    }
+
+    fn write_arp_reply(
+        &mut self,
+        buf: &mut [u8],
+        dst_ipv4: Ipv4Addr,
+    ) -> Result<Option<NonZeroUsize>, WriteFrameError> {
+        let mut eth_unsized = EthernetFrame::write_incomplete(
+            buf,
+            self.remote_mac_addr,
+            self.mac_addr,
+            ETHERTYPE_ARP,
+        ).map_err(WriteFrameError::Ethernet)?;
+
+        let arp_len = EthIPv4ArpFrame::write_reply(
+            eth_unsized
+                .inner_mut()
+                .payload_mut()
+                .split_at_mut(ETH_IPV4_FRAME_LEN)
+                .0,
+            self.mac_addr,
+            self.params.gateway_addr,
+            self.remote_mac_addr,
+            dst_ipv4,
+        ).map_err(WriteFrameError::Arp)?
+        .len();
+
+        Ok(Some(
+            // The unwrap() is safe because arp_len > 0.
+            NonZeroUsize::new(eth_unsized.with_payload_len_unchecked(arp_len).len()).unwrap(),
+        ))
+    }
+
+    // DHCP replies are broadcast, since the guest does not have an address yet (or we NAK it).
+    fn write_dhcp_reply(
+        &mut self,
+        buf: &mut [u8],
+        reply: &DhcpReply,
+    ) -> Result<Option<NonZeroUsize>, WriteFrameError> {
+        // The unwrap is safe if parse_str() is implemented properly.
+        let broadcast_mac = MacAddr::parse_str(BROADCAST_MAC_ADDR).unwrap();
+        let broadcast_addr = Ipv4Addr::new(255, 255, 255, 255);
+
+        let mut eth_unsized =
+            EthernetFrame::write_incomplete(buf, broadcast_mac, self.mac_addr, ETHERTYPE_IPV4)
+                .map_err(WriteFrameError::Ethernet)?;
+
+        let packet_len = {
+            let mut packet = IPv4Packet::write_header(
+                eth_unsized.inner_mut().payload_mut(),
+                PROTOCOL_UDP,
+                self.params.gateway_addr,
+                broadcast_addr,
+            ).map_err(WriteFrameError::IPv4Packet)?;
+
+            let datagram_len = {
+                let udp_buf = packet.inner_mut().payload_mut();
+                if udp_buf.len() < udp::HEADER_LEN {
+                    return Err(WriteFrameError::UdpDatagram(UdpDatagramError::SliceTooShort));
+                }
+                let dhcp_len = reply
+                    .write_to(&mut udp_buf[udp::HEADER_LEN..])
+                    .map_err(WriteFrameError::Dhcp)?;
+
+                UdpDatagram::write_incomplete_datagram_with_len(udp_buf, dhcp_len)
+                    .map_err(WriteFrameError::UdpDatagram)?
+                    .finalize(
+                        dhcp::SERVER_PORT,
+                        dhcp::CLIENT_PORT,
+                        Some((self.params.gateway_addr, broadcast_addr)),
+                    ).len()
+            };
+
+            packet.with_payload_len_unchecked(datagram_len, true).len()
+        };
+
+        Ok(Some(
+            // The unwrap() is safe because packet_len > 0.
+            NonZeroUsize::new(eth_unsized.with_payload_len_unchecked(packet_len).len()).unwrap(),
+        ))
+    }
+
+    fn write_packet(&mut self, buf: &mut [u8]) -> Result<Option<NonZeroUsize>, WriteFrameError> {
+        let mut eth_unsized = EthernetFrame::write_incomplete(
+            buf,
+            self.remote_mac_addr,
+            self.mac_addr,
+            ETHERTYPE_IPV4,
+        ).map_err(WriteFrameError::Ethernet)?;
+
+        let maybe_len = {
+            let packet_buf = eth_unsized.inner_mut().payload_mut();
+            if let Some(len) = self.write_rst_packet(packet_buf)? {
+                Some(len)
+            } else if let Some(len) = self.write_tcp_packet(packet_buf)? {
+                Some(len)
+            } else {
+                self.write_udp_packet(packet_buf)?
+            }
+        };
+
+        Ok(maybe_len.map(|packet_len| {
+            // The unwrap() is safe because packet_len > 0.
+            NonZeroUsize::new(eth_unsized.with_payload_len_unchecked(packet_len).len()).unwrap()
+        }))
+    }
+
+    fn write_rst_packet(&mut self, buf: &mut [u8]) -> Result<Option<usize>, WriteFrameError> {
+        let (key, rst_cfg) = match self.rst_queue.pop() {
+            Some(pair) => pair,
+            None => return Ok(None),
+        };
+
+        let mut packet = IPv4Packet::write_header(
+            buf,
+            PROTOCOL_TCP,
+            key.remote_addr,
+            self.params.guest_addr,
+        ).map_err(WriteFrameError::IPv4Packet)?;
+
+        // The 10000 value for window size is just an arbitrary number, and using
+        // mss_remaining = 0 is perfectly fine in this case, because we don't add any TCP options,
+        // or a payload.
+        let (seq, ack, flags_after_ns) = rst_cfg.seq_ack_tcp_flags();
+        let segment_len = TcpSegment::write_incomplete_segment::<[u8]>(
+            packet.inner_mut().payload_mut(),
+            seq,
+            ack,
+            flags_after_ns,
+            10000,
+            &TcpOptions::default(),
+            0,
+            None,
+        ).map_err(WriteFrameError::TcpSegment)?
+        .finalize(
+            key.remote_port,
+            key.guest_port,
+            Some((key.remote_addr, self.params.guest_addr)),
+        ).len();
+
+        Ok(Some(
+            packet.with_payload_len_unchecked(segment_len, true).len(),
+        ))
+    }
+
+    fn write_tcp_packet(&mut self, buf: &mut [u8]) -> Result<Option<usize>, WriteFrameError> {
+        let guest_addr = self.params.guest_addr;
+        let now = timestamp_cycles();
+        let mut done = Vec::new();
+        let mut result = Ok(None);
+
+        for (key, flow) in self.tcp_flows.iter_mut() {
+            let ready = match flow.next_segment_status() {
+                NextSegmentStatus::Available => true,
+                NextSegmentStatus::Timeout(value) => now >= value,
+                NextSegmentStatus::Nothing => false,
+            };
+            if !ready {
+                continue;
+            }
+
+            let mut packet =
+                match IPv4Packet::write_header(&mut *buf, PROTOCOL_TCP, key.remote_addr, guest_addr)
+                {
+                    Ok(packet) => packet,
+                    Err(e) => {
+                        result = Err(WriteFrameError::IPv4Packet(e));
+                        break;
+                    }
+                };
+
+            let segment_len = match flow.write_next_segment(packet.inner_mut().payload_mut()) {
+                Ok(Some(segment)) => Some(
+                    segment
+                        .finalize(
+                            key.remote_port,
+                            key.guest_port,
+                            Some((key.remote_addr, guest_addr)),
+                        ).len(),
+                ),
+                Ok(None) => None,
+                Err(e) => {
+                    result = Err(WriteFrameError::TcpConnection(e));
+                    None
+                }
+            };
+
+            // Sending data may allow us to forward a FIN from the host.
+            flow.maybe_close();
+            if flow.connection.is_done() {
+                done.push(*key);
+            }
+
+            if let Some(len) = segment_len {
+                result = Ok(Some(packet.with_payload_len_unchecked(len, true).len()));
+                break;
+            }
+            if result.is_err() {
+                break;
+            }
+        }
+
+        for key in done.iter() {
+            self.remove_tcp_flow(key);
+        }
+        result
+    }
+
+    fn write_udp_packet(&mut self, buf: &mut [u8]) -> Result<Option<usize>, WriteFrameError> {
+        let guest_addr = self.params.guest_addr;
+
+        for (key, flow) in self.udp_flows.iter_mut() {
+            let payload = match flow.to_guest.pop_front() {
+                Some(payload) => payload,
+                None => continue,
+            };
+            // Make room for more datagrams, if the host socket is still readable.
+            flow.read_host(&mut self.udp_buf);
+
+            let mut packet = IPv4Packet::write_header(buf, PROTOCOL_UDP, key.remote_addr, guest_addr)
+                .map_err(WriteFrameError::IPv4Packet)?;
+
+            let datagram_len = UdpDatagram::write_incomplete_datagram(
+                packet.inner_mut().payload_mut(),
+                &payload,
+            ).map_err(WriteFrameError::UdpDatagram)?
+            .finalize(
+                key.remote_port,
+                key.guest_port,
+                Some((key.remote_addr, guest_addr)),
+            ).len();
+
+            return Ok(Some(
+                packet.with_payload_len_unchecked(datagram_len, true).len(),
+            ));
+        }
+        Ok(None)
+    }
+
+    // Makes sure we wake up in time to handle retransmissions and idle UDP flows.
+    fn update_timer(&mut self) {
+        let tcp_timeout_pending = self.tcp_flows.values().any(|flow| {
+            match flow.next_segment_status() {
+                NextSegmentStatus::Timeout(_) => true,
+                _ => false,
+            }
+        });
+
+        let period_ms = if tcp_timeout_pending {
+            Some(TCP_TIMER_PERIOD_MS)
+        } else if !self.udp_flows.is_empty() {
+            Some(UDP_TIMER_PERIOD_MS)
+        } else {
+            None
+        };
+
+        if period_ms != self.timer_period_ms {
+            let state = match period_ms {
+                Some(ms) => TimerState::Oneshot(Duration::from_millis(ms)),
+                None => TimerState::Disarmed,
+            };
+            self.timer.set_state(state, SetTimeFlags::Default);
+            self.timer_period_ms = period_ms;
+        }
+    }
+}
+
+impl AsRawFd for UserNetworkStack {
+    fn as_raw_fd(&self) -> RawFd {
+        self.epoll_fd
+    }
+}
+
+impl Drop for UserNetworkStack {
+    fn drop(&mut self) {
+        // Safe because we own the epoll fd.
+        unsafe { libc::close(self.epoll_fd) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::thread::sleep;
+
+    use super::*;
+
+    const GUEST_MAC_ADDR: &str = "12:34:56:78:9a:bc";
+
+    fn guest_mac() -> MacAddr {
+        MacAddr::parse_str(GUEST_MAC_ADDR).unwrap()
+    }
+
+    // The tests use host sockets bound to the loopback interface.
+    fn loopback_params() -> UserNetworkParams {
+        UserNetworkParams {
+            allow_host_loopback: true,
+            ..Default::default()
+        }
+    }
+
+    // Writes a UDP datagram from the guest towards dst, and returns the length of the frame.
+    fn write_guest_udp(buf: &mut [u8], params: &UserNetworkParams, dst: SocketAddrV4) -> usize {
+        let src = params.guest_addr;
+        write_guest_ipv4(buf, PROTOCOL_UDP, src, *dst.ip(), |b| {
+            UdpDatagram::write_incomplete_datagram(b, b"hello")
+                .unwrap()
+                .finalize(5000, dst.port(), Some((src, *dst.ip())))
+                .len()
+        })
+    }
+
+    // Writes an Ethernet frame from the guest which contains an IPv4 packet, whose payload is
+    // produced by the given closure, and returns the length of the frame.
+    fn write_guest_ipv4<F>(buf: &mut [u8], protocol: u8, src: Ipv4Addr, dst: Ipv4Addr, f: F) -> usize
+    where
+        F: FnOnce(&mut [u8]) -> usize,
+    {
+        let mut eth = EthernetFrame::write_incomplete(
+            buf,
+            MacAddr::parse_str(DEFAULT_MAC_ADDR).unwrap(),
+            guest_mac(),
+            ETHERTYPE_IPV4,
+        ).unwrap();
+        let packet_len = {
+            let mut packet =
+                IPv4Packet::write_header(eth.inner_mut().payload_mut(), protocol, src, dst)
+                    .unwrap();
+            let payload_len = f(packet.inner_mut().payload_mut());
+            packet.with_payload_len_unchecked(payload_len, true).len()
+        };
+        eth.with_payload_len_unchecked(packet_len).len()
+    }
+
+    fn write_guest_tcp(
+        buf: &mut [u8],
+        params: &UserNetworkParams,
+        dst: SocketAddrV4,
+        seq: u32,
+        ack: u32,
+        flags: TcpFlags,
+        payload: &[u8],
+    ) -> usize {
+        let src = params.guest_addr;
+        write_guest_ipv4(buf, PROTOCOL_TCP, src, *dst.ip(), |b| {
+            TcpSegment::write_segment(
+                b,
+                1234,
+                dst.port(),
+                seq,
+                ack,
+                flags,
+                10000,
+                &TcpOptions::default(),
+                1460,
+                if payload.is_empty() {
+                    None
+                } else {
+                    Some((payload, payload.len()))
+                },
+                Some((src, *dst.ip())),
+            ).unwrap()
+            .len()
+        })
+    }
+
+    // Returns the next TCP segment sent to the guest, as (seq, ack, flags, payload), retrying for
+    // a while since the host side may need some time to make progress.
+    fn next_tcp(stack: &mut UserNetworkStack, buf: &mut [u8]) -> (u32, u32, TcpFlags, Vec<u8>) {
+        for _ in 0..100 {
+            if let Some(len) = stack.write_next_frame(buf) {
+                let eth = EthernetFrame::from_bytes(&buf[..len.get()]).unwrap();
+                assert_eq!(eth.dst_mac(), guest_mac());
+                let ip = IPv4Packet::from_bytes(eth.payload(), true).unwrap();
+                assert_eq!(ip.protocol(), PROTOCOL_TCP);
+                let s = TcpSegment::from_bytes(
+                    ip.payload(),
+                    Some((ip.source_address(), ip.destination_address())),
+                ).unwrap();
+                return (
+                    s.sequence_number(),
+                    s.ack_number(),
+                    s.flags_after_ns(),
+                    s.payload().to_vec(),
+                );
+            }
+            sleep(Duration::from_millis(10));
+        }
+        panic!("no segment was sent to the guest");
+    }
+
+    #[test]
+    fn test_arp() {
+        let mut stack = UserNetworkStack::new(UserNetworkParams::default()).unwrap();
+        let params = stack.params().clone();
+        let mut buf = [0u8; 2000];
+
+        let len = {
+            let mut eth = EthernetFrame::write_incomplete(
+                buf.as_mut(),
+                MacAddr::parse_str(BROADCAST_MAC_ADDR).unwrap(),
+                guest_mac(),
+                ETHERTYPE_ARP,
+            ).unwrap();
+            let arp_len = EthIPv4ArpFrame::write_request(
+                eth.inner_mut()
+                    .payload_mut()
+                    .split_at_mut(ETH_IPV4_FRAME_LEN)
+                    .0,
+                guest_mac(),
+                params.guest_addr,
+                MacAddr::parse_str("00:00:00:00:00:00").unwrap(),
+                params.gateway_addr,
+            ).unwrap()
+            .len();
+            eth.with_payload_len_unchecked(arp_len).len()
+        };
+        stack.receive_frame(&buf[..len]);
+
+        let len = stack.write_next_frame(buf.as_mut()).unwrap().get();
+        let eth = EthernetFrame::from_bytes(&buf[..len]).unwrap();
+        assert_eq!(eth.dst_mac(), guest_mac());
+        assert_eq!(eth.ethertype(), ETHERTYPE_ARP);
+        let arp = EthIPv4ArpFrame::from_bytes(eth.payload()).unwrap();
+        assert_eq!(arp.spa(), params.gateway_addr);
+        assert_eq!(arp.sha(), MacAddr::parse_str(DEFAULT_MAC_ADDR).unwrap());
+        assert_eq!(arp.tpa(), params.guest_addr);
+
+        assert!(stack.write_next_frame(buf.as_mut()).is_none());
+    }
+
+    #[test]
+    fn test_dhcp() {
+        let mut params = UserNetworkParams::default();
+        params.dns_addr = Some(Ipv4Addr::new(10, 0, 2, 3));
+        let mut stack = UserNetworkStack::new(params.clone()).unwrap();
+        let mut buf = [0u8; 2000];
+
+        let mut send_request = |stack: &mut UserNetworkStack, message_type: u8, addr: Ipv4Addr| {
+            let mut dhcp_buf = [0u8; 300];
+            dhcp_buf[0] = 1;
+            dhcp_buf[1] = 1;
+            dhcp_buf[2] = 6;
+            dhcp_buf[4..8].copy_from_slice(&[0, 0, 0, 42]);
+            dhcp_buf[28..34].copy_from_slice(guest_mac().get_bytes());
+            dhcp_buf[236..240].copy_from_slice(&[0x63, 0x82, 0x53, 0x63]);
+            dhcp_buf[240..243].copy_from_slice(&[53, 1, message_type]);
+            dhcp_buf[243..245].copy_from_slice(&[50, 4]);
+            dhcp_buf[245..249].copy_from_slice(&addr.octets());
+            dhcp_buf[249] = 255;
+
+            let len = write_guest_ipv4(
+                buf.as_mut(),
+                PROTOCOL_UDP,
+                Ipv4Addr::new(0, 0, 0, 0),
+                Ipv4Addr::new(255, 255, 255, 255),
+                |b| {
+                    UdpDatagram::write_incomplete_datagram(b, &dhcp_buf[..250])
+                        .unwrap()
+                        .finalize(
+                            dhcp::CLIENT_PORT,
+                            dhcp::SERVER_PORT,
+                            Some((Ipv4Addr::new(0, 0, 0, 0), Ipv4Addr::new(255, 255, 255, 255))),
+                        ).len()
+                },
+            );
+            stack.receive_frame(&buf[..len]);
+
+            let len = stack.write_next_frame(buf.as_mut()).unwrap().get();
+            let eth = EthernetFrame::from_bytes(&buf[..len]).unwrap();
+            assert_eq!(eth.dst_mac(), MacAddr::parse_str(BROADCAST_MAC_ADDR).unwrap());
+            let ip = IPv4Packet::from_bytes(eth.payload(), true).unwrap();
+            assert_eq!(ip.source_address(), params.gateway_addr);
+            let d = UdpDatagram::from_bytes(
+                ip.payload(),
+                Some((ip.source_address(), ip.destination_address())),
+            ).unwrap();
+            assert_eq!(d.source_port(), dhcp::SERVER_PORT);
+            assert_eq!(d.destination_port(), dhcp::CLIENT_PORT);
+            let p = d.payload();
+            // Return the message type and yiaddr.
+            (p[242], Ipv4Addr::new(p[16], p[17], p[18], p[19]))
+        };
+
+        // DISCOVER -> OFFER
+        assert_eq!(
+            send_request(&mut stack, 1, Ipv4Addr::new(0, 0, 0, 0)),
+            (2, params.guest_addr)
+        );
+        // REQUEST -> ACK
+        assert_eq!(
+            send_request(&mut stack, 3, params.guest_addr),
+            (5, params.guest_addr)
+        );
+        // REQUEST for some other address -> NAK
+        assert_eq!(
+            send_request(&mut stack, 3, Ipv4Addr::new(10, 0, 2, 100)).0,
+            6
+        );
+    }
+
+    #[test]
+    fn test_tcp_proxy() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let mut stack = UserNetworkStack::new(loopback_params()).unwrap();
+        let params = stack.params().clone();
+        // Connections to the gateway end up on the host loopback interface.
+        let dst = SocketAddrV4::new(params.gateway_addr, port);
+        let mut buf = [0u8; 2000];
+
+        let len = write_guest_tcp(buf.as_mut(), &params, dst, 100, 0, TcpFlags::SYN, b"");
+        stack.receive_frame(&buf[..len]);
+        let (mut conn, _) = listener.accept().unwrap();
+
+        // The SYNACK is sent after the host connection is established.
+        let (seq, ack, flags, _) = next_tcp(&mut stack, buf.as_mut());
+        assert_eq!(flags, TcpFlags::SYN | TcpFlags::ACK);
+        assert_eq!(ack, 101);
+
+        let seq = seq.wrapping_add(1);
+        let len = write_guest_tcp(buf.as_mut(), &params, dst, 101, seq, TcpFlags::ACK, b"ping");
+        stack.receive_frame(&buf[..len]);
+
+        let mut host_buf = [0u8; 4];
+        conn.read_exact(host_buf.as_mut()).unwrap();
+        assert_eq!(&host_buf, b"ping");
+
+        conn.write_all(b"pong").unwrap();
+        // We may get a pure ACK for the guest data first.
+        let (mut ack, mut payload) = (0, Vec::new());
+        while payload.is_empty() {
+            let (_, a, _, p) = next_tcp(&mut stack, buf.as_mut());
+            ack = a;
+            payload = p;
+        }
+        assert_eq!(ack, 105);
+        assert_eq!(payload, b"pong".to_vec());
+
+        // The host closes the connection, so the guest should get a FIN.
+        drop(conn);
+        let seq = seq.wrapping_add(4);
+        let len = write_guest_tcp(buf.as_mut(), &params, dst, 105, seq, TcpFlags::ACK, b"");
+        stack.receive_frame(&buf[..len]);
+        let (fin_seq, _, flags, _) = next_tcp(&mut stack, buf.as_mut());
+        assert!(flags.intersects(TcpFlags::FIN));
+        assert_eq!(fin_seq, seq);
+    }
+
+    #[test]
+    fn test_tcp_connect_refused() {
+        // Grab a free port, and then make sure nothing listens on it.
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let mut stack = UserNetworkStack::new(loopback_params()).unwrap();
+        let params = stack.params().clone();
+        let dst = SocketAddrV4::new(params.gateway_addr, port);
+        let mut buf = [0u8; 2000];
+
+        let len = write_guest_tcp(buf.as_mut(), &params, dst, 100, 0, TcpFlags::SYN, b"");
+        stack.receive_frame(&buf[..len]);
+
+        let (_, ack, flags, _) = next_tcp(&mut stack, buf.as_mut());
+        assert_eq!(flags, TcpFlags::RST | TcpFlags::ACK);
+        assert_eq!(ack, 101);
+        assert!(stack.tcp_flows.is_empty());
+    }
+
+    #[test]
+    fn test_udp_proxy() {
+        let host = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = host.local_addr().unwrap().port();
+
+        let mut stack = UserNetworkStack::new(loopback_params()).unwrap();
+        let params = stack.params().clone();
+        let mut buf = [0u8; 2000];
+
+        let dst = SocketAddrV4::new(params.gateway_addr, port);
+        let len = write_guest_udp(buf.as_mut(), &params, dst);
+        stack.receive_frame(&buf[..len]);
+
+        let mut host_buf = [0u8; 100];
+        let (len, from) = host.recv_from(host_buf.as_mut()).unwrap();
+        assert_eq!(&host_buf[..len], b"hello");
+
+        host.send_to(b"world", from).unwrap();
+        let mut reply = None;
+        for _ in 0..100 {
+            if let Some(len) = stack.write_next_frame(buf.as_mut()) {
+                reply = Some(len.get());
+                break;
+            }
+            sleep(Duration::from_millis(10));
+        }
+        let len = reply.unwrap();
+
+        let eth = EthernetFrame::from_bytes(&buf[..len]).unwrap();
+        let ip = IPv4Packet::from_bytes(eth.payload(), true).unwrap();
+        assert_eq!(ip.source_address(), params.gateway_addr);
+        assert_eq!(ip.destination_address(), params.guest_addr);
+        let d = UdpDatagram::from_bytes(
+            ip.payload(),
+            Some((ip.source_address(), ip.destination_address())),
+        ).unwrap();
+        assert_eq!(d.source_port(), port);
+        assert_eq!(d.destination_port(), 5000);
+        assert_eq!(d.payload(), b"world");
+    }
+
+    #[test]
+    fn test_host_loopback_not_allowed() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        listener.set_nonblocking(true).unwrap();
+
+        let mut stack = UserNetworkStack::new(UserNetworkParams::default()).unwrap();
+        let params = stack.params().clone();
+        let mut buf = [0u8; 2000];
+
+        // Neither via the gateway, nor directly.
+        for addr in &[
+            params.gateway_addr,
+            Ipv4Addr::new(127, 0, 0, 1),
+            Ipv4Addr::new(0, 0, 0, 0),
+        ] {
+            let dst = SocketAddrV4::new(*addr, port);
+            let len = write_guest_tcp(buf.as_mut(), &params, dst, 100, 0, TcpFlags::SYN, b"");
+            stack.receive_frame(&buf[..len]);
+            let (_, ack, flags, _) = next_tcp(&mut stack, buf.as_mut());
+            assert_eq!(flags, TcpFlags::RST | TcpFlags::ACK);
+            assert_eq!(ack, 101);
+            assert!(stack.tcp_flows.is_empty());
+
+            let len = write_guest_udp(buf.as_mut(), &params, dst);
+            stack.receive_frame(&buf[..len]);
+            assert!(stack.udp_flows.is_empty());
+        }
+        assert_eq!(
+            listener.accept().unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+
+        // Opting in doesn't open up the loopback addresses either.
+        let mut stack = UserNetworkStack::new(loopback_params()).unwrap();
+        let dst = SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), port);
+        let len = write_guest_tcp(buf.as_mut(), &params, dst, 100, 0, TcpFlags::SYN, b"");
+        stack.receive_frame(&buf[..len]);
+        assert!(stack.tcp_flows.is_empty());
+    }
+
+    #[test]
+    fn test_destinations_not_allowed() {
+        let mut stack = UserNetworkStack::new(UserNetworkParams::default()).unwrap();
+        let params = stack.params().clone();
+        let mut buf = [0u8; 2000];
+
+        // Neither the cloud metadata service, nor the private ranges.
+        for addr in &[
+            Ipv4Addr::new(169, 254, 169, 254),
+            Ipv4Addr::new(10, 1, 2, 3),
+            Ipv4Addr::new(172, 16, 0, 1),
+            Ipv4Addr::new(192, 168, 0, 1),
+        ] {
+            let not_allowed_count = METRICS.user_net.flows_not_allowed.count();
+            let dst = SocketAddrV4::new(*addr, 80);
+            let len = write_guest_tcp(buf.as_mut(), &params, dst, 100, 0, TcpFlags::SYN, b"");
+            stack.receive_frame(&buf[..len]);
+            let (_, ack, flags, _) = next_tcp(&mut stack, buf.as_mut());
+            assert_eq!(flags, TcpFlags::RST | TcpFlags::ACK);
+            assert_eq!(ack, 101);
+            assert!(stack.tcp_flows.is_empty());
+
+            let len = write_guest_udp(buf.as_mut(), &params, dst);
+            stack.receive_frame(&buf[..len]);
+            assert!(stack.udp_flows.is_empty());
+            assert!(METRICS.user_net.flows_not_allowed.count() >= not_allowed_count + 2);
+            assert_eq!(stack.host_addr(*addr), None);
+        }
+        assert_eq!(
+            stack.host_addr(Ipv4Addr::new(1, 1, 1, 1)),
+            Some(Ipv4Addr::new(1, 1, 1, 1))
+        );
+
+        // The allowed destinations open up parts of these ranges, while the denied ones take
+        // precedence, and also apply to public addresses.
+        let mut params = UserNetworkParams::default();
+        params.dns_addr = Some(Ipv4Addr::new(192, 168, 0, 53));
+        params.allowed_destinations = vec![Ipv4Cidr::parse_str("10.1.0.0/16").unwrap()];
+        params.denied_destinations = vec![
+            Ipv4Cidr::parse_str("10.1.2.0/24").unwrap(),
+            Ipv4Cidr::parse_str("1.1.1.1").unwrap(),
+        ];
+        let stack = UserNetworkStack::new(params).unwrap();
+        for (addr, allowed) in &[
+            (Ipv4Addr::new(10, 1, 3, 4), true),
+            (Ipv4Addr::new(10, 1, 2, 3), false),
+            (Ipv4Addr::new(10, 2, 0, 1), false),
+            (Ipv4Addr::new(169, 254, 169, 254), false),
+            (Ipv4Addr::new(192, 168, 0, 53), true),
+            (Ipv4Addr::new(192, 168, 0, 54), false),
+            (Ipv4Addr::new(1, 1, 1, 1), false),
+            (Ipv4Addr::new(1, 1, 1, 2), true),
+        ] {
+            assert_eq!(stack.host_addr(*addr).is_some(), *allowed);
+        }
+    }
+}
@@ -51,6 +51,7 @@ pub struct Env {
     netns: Option<String>,
     daemonize: bool,
     seccomp_level: u32,
+    exec_file_args: Vec<String>,
     start_time_us: u64,
     start_time_cpu_us: u64,
 }
@@ -113,6 +114,11 @@ impl Env {
             .parse::<u32>()
             .map_err(|err| Error::SeccompLevel(err))?;
 
+        let exec_file_args = args
+            .values_of("exec_file_args")
+            .map(|values| values.map(String::from).collect())
+            .unwrap_or_default();
+
         Ok(Env {
             id: id.to_string(),
             numa_node,
@@ -123,6 +129,7 @@ impl Env {
             netns,
             daemonize,
             seccomp_level,
+            exec_file_args,
             start_time_us,
             start_time_cpu_us,
         })
@@ -287,7 +294,8 @@ impl Env {
                 .arg(format!(
                     "--context={}",
                     serde_json::to_string(&context).expect("Failed to serialize context")
-                )).stdin(Stdio::inherit())
+                )).args(&self.exec_file_args)
+                .stdin(Stdio::inherit())
                 .stdout(Stdio::inherit())
                 .stderr(Stdio::inherit())
                 .uid(self.uid())
@@ -454,6 +462,44 @@ mod tests {
         // actually attempt to create the folder structure (the same goes for netns).
     }
 
+    #[test]
+    fn test_exec_file_args() {
+        let env = Env::new(
+            make_args("1", "exec-args", "/proc/cpuinfo", "1001", "1002", "/", None, false),
+            0,
+            0,
+        ).unwrap();
+        assert!(env.exec_file_args.is_empty());
+
+        // Everything following -- is passed on to the exec file, as is.
+        let args = clap_app()
+            .get_matches_from_safe(vec![
+                "jailer",
+                "--node",
+                "1",
+                "--id",
+                "exec-args",
+                "--exec-file",
+                "/proc/cpuinfo",
+                "--uid",
+                "1001",
+                "--gid",
+                "1002",
+                "--chroot-base-dir",
+                "/",
+                "--",
+                "--api-allowed-uids",
+                "1001,1003",
+                "--config-file",
+                "/vm.json",
+            ]).unwrap();
+        let env = Env::new(args, 0, 0).unwrap();
+        assert_eq!(
+            env.exec_file_args,
+            vec!["--api-allowed-uids", "1001,1003", "--config-file", "/vm.json"]
+        );
+    }
+
     #[test]
     fn test_dup2() {
         // Open /dev/kvm since it should be available anyway.
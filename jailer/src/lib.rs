@@ -317,6 +317,13 @@ pub fn clap_app<'a, 'b>() -> App<'a, 'b> {
                 .default_value("2")
                 .possible_values(&["0", "1", "2"]),
         )
+        .arg(
+            Arg::with_name("exec_file_args")
+                .help("Arguments passed on to the exec file, following --.")
+                .required(false)
+                .multiple(true)
+                .last(true),
+        )
 }
 
 fn sanitize_process() {
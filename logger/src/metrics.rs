@@ -23,14 +23,33 @@
 //!   (this could be a concern, I guess).
 //! If if turns out this approach is not really what we want, it's pretty easy to resort to
 //! something else, while working behind the same interface.
+//!
+//! The metrics can also be rendered in the Prometheus text exposition format, using
+//! `FirecrackerMetrics::to_prometheus_text`. In that case, the cumulative value of each metric
+//! is reported, and the deltas logged by the next flush are left untouched.
 
+use std::cell::Cell;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
 use chrono;
 use serde::{Serialize, Serializer};
+use serde_json::{self, Value};
 
 const SYSCALL_MAX: usize = 350;
 
+// Prefix of the metric names in the Prometheus text exposition format.
+const PROMETHEUS_PREFIX: &str = "firecracker";
+
+thread_local! {
+    // Set while the metrics are rendered in the Prometheus text exposition format. `SharedMetric`s
+    // are then serialized as their cumulative value, without affecting the flush deltas.
+    static CUMULATIVE_SERIALIZATION: Cell<bool> = Cell::new(false);
+}
+
+fn serializing_cumulative<T>(_: &T) -> bool {
+    CUMULATIVE_SERIALIZATION.with(Cell::get)
+}
+
 /// Used for defining new types of metrics that can be either incremented with an unit
 /// or an arbitrary amount of units.
 // This trait helps with writing less code. It has to be in scope (via an use directive) in order
@@ -68,6 +87,14 @@ impl Metric for SimpleMetric {
     }
 }
 
+impl SimpleMetric {
+    /// Sets the current value, for metrics which report a level (such as the length of a queue)
+    /// rather than a count of events.
+    pub fn set(&self, value: usize) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+}
+
 impl Serialize for SimpleMetric {
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
         // There's no serializer.serialize_usize().
@@ -106,7 +133,13 @@ impl Serialize for SharedMetric {
     /// Reset counters of each metrics. Here we suppose that Serialize's goal is to help with the
     /// flushing of metrics.
     /// !!! Any print of the metrics will also reset them. Use with caution !!!
+    /// The only exception is `FirecrackerMetrics::to_prometheus_text`, which reports cumulative
+    /// values.
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializing_cumulative(self) {
+            return serializer.serialize_u64(self.count() as u64);
+        }
+
         // There's no serializer.serialize_usize() for some reason :(
         let snapshot = self.0.load(Ordering::Relaxed);
         let res = serializer.serialize_u64(snapshot as u64 - self.1.load(Ordering::Relaxed) as u64);
@@ -133,6 +166,19 @@ pub struct ApiServerMetrics {
     pub sync_outcome_fails: SharedMetric,
     /// Number of timeouts during communication with the VMM.
     pub sync_vmm_send_timeout_count: SharedMetric,
+    /// Number of requests rejected because of the credentials of the client.
+    pub access_denied_count: SharedMetric,
+    /// Number of requests which couldn't be written to the audit log.
+    pub audit_log_fails: SharedMetric,
+    /// Number of actions handed over to the VMM which it hasn't handled yet. Unlike the other
+    /// metrics, this is the current value rather than a count, and it is only updated by the API
+    /// thread.
+    pub vmm_action_queue_depth: SimpleMetric,
+    /// Number of requests rejected because too many actions were waiting for the VMM.
+    pub vmm_action_queue_full_count: SharedMetric,
+    /// Number of requests which timed out because the VMM didn't handle them before their
+    /// deadline.
+    pub vmm_action_timeout_count: SharedMetric,
 }
 
 /// Metrics specific to GET API Requests for counting user triggered actions and/or failures.
@@ -146,6 +192,22 @@ pub struct GetRequestsMetrics {
     pub machine_cfg_count: SharedMetric,
     /// Number of failures during GETs for getting information on the instance.
     pub machine_cfg_fails: SharedMetric,
+    /// Number of GETs for getting the boot source configuration.
+    pub boot_source_count: SharedMetric,
+    /// Number of GETs for getting the block device configurations.
+    pub drive_count: SharedMetric,
+    /// Number of GETs for getting the logger configuration.
+    pub logger_count: SharedMetric,
+    /// Number of GETs for getting the network interface configurations.
+    pub network_count: SharedMetric,
+    /// Number of GETs for getting the vsock device configurations.
+    pub vsock_count: SharedMetric,
+    /// Number of GETs for getting the metrics in the Prometheus text exposition format.
+    pub metrics_count: SharedMetric,
+    /// Number of GETs for streaming the lifecycle events of the microVM.
+    pub events_count: SharedMetric,
+    /// Number of GETs for getting the version and capabilities of Firecracker.
+    pub version_count: SharedMetric,
 }
 
 /// Metrics specific to PUT API Requests for counting user triggered actions and/or failures.
@@ -171,10 +233,22 @@ pub struct PutRequestsMetrics {
     pub machine_cfg_count: SharedMetric,
     /// Number of failures in configuring the machine.
     pub machine_cfg_fails: SharedMetric,
+    /// Number of PUTs for configuring the MMDS network parameters.
+    pub mmds_cfg_count: SharedMetric,
+    /// Number of failures in configuring the MMDS network parameters.
+    pub mmds_cfg_fails: SharedMetric,
+    /// Number of PUTs replacing the contents of a MMDS data store.
+    pub mmds_count: SharedMetric,
+    /// Number of failures in replacing the contents of a MMDS data store.
+    pub mmds_fails: SharedMetric,
     /// Number of PUTs for creating a new network interface.
     pub network_count: SharedMetric,
     /// Number of failures in creating a new network interface.
     pub network_fails: SharedMetric,
+    /// Number of PUTs replacing the whole microVM configuration.
+    pub vm_count: SharedMetric,
+    /// Number of failures in replacing the whole microVM configuration.
+    pub vm_fails: SharedMetric,
 }
 
 /// Metrics specific to PATCH API Requests for counting user triggered actions and/or failures.
@@ -266,6 +340,27 @@ pub struct MmdsMetrics {
     pub connections_created: SharedMetric,
     /// The number of connections cleaned up by the MMDS TCP handler.
     pub connections_destroyed: SharedMetric,
+    /// The number of ICMP echo requests received by the MMDS.
+    pub icmp_echo_requests: SharedMetric,
+    /// The number of UDP datagrams passed to the MMDS UDP handlers.
+    pub udp_datagrams: SharedMetric,
+    /// The number of ICMP/UDP replies dropped because too many were already pending.
+    pub replies_dropped: SharedMetric,
+    /// The number of session tokens generated by the MMDS.
+    pub tokens_issued: SharedMetric,
+    /// The number of session token requests rejected by the MMDS.
+    pub token_requests_rejected: SharedMetric,
+    /// The number of requests rejected by the MMDS because of a missing, invalid, or expired
+    /// session token.
+    pub unauthorized_requests: SharedMetric,
+    /// The number of guest writes to the MMDS.
+    pub guest_data_updates: SharedMetric,
+    /// The number of guest writes rejected by the MMDS.
+    pub guest_data_update_fails: SharedMetric,
+    /// The number of guest requests which waited for the MMDS contents to change.
+    pub long_polls: SharedMetric,
+    /// The number of guest requests whose wait for the MMDS contents to change timed out.
+    pub long_poll_timeouts: SharedMetric,
 }
 
 /// Network-related metrics.
@@ -277,6 +372,8 @@ pub struct NetDeviceMetrics {
     pub cfg_fails: SharedMetric,
     /// Number of times when handling events on a network device failed.
     pub event_fails: SharedMetric,
+    /// Number of events which woke up the MMDS network stack.
+    pub mmds_event_count: SharedMetric,
     /// Number of events associated with the receiving queue.
     pub rx_queue_event_count: SharedMetric,
     /// Number of events associated with the rate limiter installed on the receiving path.
@@ -291,14 +388,24 @@ pub struct NetDeviceMetrics {
     pub rx_fails: SharedMetric,
     /// Number of transmitted bytes.
     pub tx_bytes_count: SharedMetric,
+    /// Number of frames dropped by the TX filter because they matched a deny rule.
+    pub tx_denied_count: SharedMetric,
     /// Number of errors while transmitting data.
     pub tx_fails: SharedMetric,
+    /// Number of frames dropped by the TX filter because they could not be parsed.
+    pub tx_malformed_count: SharedMetric,
     /// Number of transmitted packets.
     pub tx_packets_count: SharedMetric,
     /// Number of events associated with the transmitting queue.
     pub tx_queue_event_count: SharedMetric,
     /// Number of events associated with the rate limiter installed on the transmitting path.
     pub tx_rate_limiter_event_count: SharedMetric,
+    /// Number of frames dropped by the TX filter because of a spoofed source IP address.
+    pub tx_spoofed_ip_count: SharedMetric,
+    /// Number of frames dropped by the TX filter because of a spoofed source MAC address.
+    pub tx_spoofed_mac_count: SharedMetric,
+    /// Number of frames dropped by the TX filter because their source address can't be checked.
+    pub tx_unsupported_ethertype_count: SharedMetric,
 }
 
 /// Metrics for the seccomp filtering.
@@ -340,6 +447,33 @@ pub struct SerialDeviceMetrics {
     pub write_count: SharedMetric,
 }
 
+/// Metrics specific to the user-mode (tap-less) network backend.
+#[derive(Default, Serialize)]
+pub struct UserNetMetrics {
+    /// Number of DHCP replies sent to the guest.
+    pub dhcp_replies: SharedMetric,
+    /// Number of guest flows rejected because the maximum number of flows was reached.
+    pub flows_limit_exceeded: SharedMetric,
+    /// Number of guest flows rejected because they target the host loopback interface.
+    pub flows_not_allowed: SharedMetric,
+    /// Number of errors encountered while operating on host sockets.
+    pub host_socket_errors: SharedMetric,
+    /// Number of guest frames which could not be handled by the user-mode stack.
+    pub rx_dropped: SharedMetric,
+    /// Number of TCP connections which could not be established towards the host.
+    pub tcp_connect_fails: SharedMetric,
+    /// Number of TCP flows created on behalf of the guest.
+    pub tcp_flows_created: SharedMetric,
+    /// Number of TCP flows cleaned up.
+    pub tcp_flows_destroyed: SharedMetric,
+    /// Number of errors raised while writing frames for the guest.
+    pub tx_errors: SharedMetric,
+    /// Number of UDP flows created on behalf of the guest.
+    pub udp_flows_created: SharedMetric,
+    /// Number of UDP flows cleaned up after being idle.
+    pub udp_flows_destroyed: SharedMetric,
+}
+
 /// Metrics specific to VCPUs' mode of functioning.
 #[derive(Default, Serialize)]
 pub struct VcpuMetrics {
@@ -377,6 +511,8 @@ impl Serialize for SerializeToUtcTimestampMs {
 /// Structure storing all metrics while enforcing serialization support on them.
 #[derive(Default, Serialize)]
 pub struct FirecrackerMetrics {
+    // Prometheus records the time of each scrape on its own.
+    #[serde(skip_serializing_if = "serializing_cumulative")]
     utc_timestamp_ms: SerializeToUtcTimestampMs,
     /// API Server related metrics.
     pub api_server: ApiServerMetrics,
@@ -398,6 +534,8 @@ pub struct FirecrackerMetrics {
     pub put_api_requests: PutRequestsMetrics,
     /// Metrics related to seccomp filtering.
     pub seccomp: SeccompMetrics,
+    /// Metrics related to the user-mode network backend.
+    pub user_net: UserNetMetrics,
     /// Metrics related to a vcpu's functioning.
     pub vcpu: VcpuMetrics,
     /// Metrics related to the virtual machine manager.
@@ -406,6 +544,46 @@ pub struct FirecrackerMetrics {
     pub uart: SerialDeviceMetrics,
 }
 
+impl FirecrackerMetrics {
+    /// Renders the cumulative value of every metric in the Prometheus text exposition format.
+    /// Metric names are made of the `firecracker` prefix, the group name and the field name
+    /// (e.g. `firecracker_net_tx_packets_count`), and the elements of metric arrays are told
+    /// apart by an `index` label.
+    pub fn to_prometheus_text(&self) -> String {
+        CUMULATIVE_SERIALIZATION.with(|cumulative| cumulative.set(true));
+        let metrics = serde_json::to_value(self);
+        CUMULATIVE_SERIALIZATION.with(|cumulative| cumulative.set(false));
+
+        let mut text = String::new();
+        // The metrics only have string keys, so turning them into a `Value` can't fail.
+        if let Ok(metrics) = metrics {
+            write_prometheus_samples(&mut text, PROMETHEUS_PREFIX, &metrics);
+        }
+        text
+    }
+}
+
+// Appends the samples of a metric, or of a group of metrics, to `text`.
+fn write_prometheus_samples(text: &mut String, name: &str, value: &Value) {
+    match *value {
+        Value::Number(ref count) => {
+            text.push_str(&format!("# TYPE {} counter\n{} {}\n", name, name, count));
+        }
+        Value::Array(ref counts) => {
+            text.push_str(&format!("# TYPE {} counter\n", name));
+            for (index, count) in counts.iter().enumerate() {
+                text.push_str(&format!("{}{{index=\"{}\"}} {}\n", name, index, count));
+            }
+        }
+        Value::Object(ref group) => {
+            for (key, value) in group {
+                write_prometheus_samples(text, &format!("{}_{}", name, key), value);
+            }
+        }
+        _ => (),
+    }
+}
+
 lazy_static! {
     /// Static instance used for handling metrics.
     ///
@@ -431,6 +609,9 @@ mod tests {
 
         assert_eq!(m1.count(), 8);
 
+        m1.set(3);
+        assert_eq!(m1.count(), 3);
+
         let m2 = Arc::new(SharedMetric::default());
 
         // We're going to create a number of threads that will attempt to increase this metric
@@ -469,4 +650,33 @@ mod tests {
         let s = serde_json::to_string(&FirecrackerMetrics::default());
         assert!(s.is_ok());
     }
+
+    #[test]
+    fn test_to_prometheus_text() {
+        let metrics = FirecrackerMetrics::default();
+        metrics.net.tx_packets_count.add(5);
+        metrics.seccomp.bad_syscalls[2].inc();
+
+        let text = metrics.to_prometheus_text();
+        assert!(!text.contains("utc_timestamp_ms"));
+        assert!(text.contains(concat!(
+            "# TYPE firecracker_net_tx_packets_count counter\n",
+            "firecracker_net_tx_packets_count 5\n"
+        )));
+        assert!(text.contains("# TYPE firecracker_seccomp_bad_syscalls counter\n"));
+        assert!(text.contains("firecracker_seccomp_bad_syscalls{index=\"2\"} 1\n"));
+        assert!(text.contains("firecracker_seccomp_bad_syscalls{index=\"3\"} 0\n"));
+
+        // Rendering the metrics doesn't reset the deltas logged by the flushes.
+        let flushed: Value = serde_json::to_value(&metrics).unwrap();
+        assert!(flushed.get("utc_timestamp_ms").is_some());
+        assert_eq!(flushed["net"]["tx_packets_count"], 5);
+        let flushed: Value = serde_json::to_value(&metrics).unwrap();
+        assert_eq!(flushed["net"]["tx_packets_count"], 0);
+
+        // The cumulative values are still reported after a flush.
+        metrics.net.tx_packets_count.inc();
+        let text = metrics.to_prometheus_text();
+        assert!(text.contains("\nfirecracker_net_tx_packets_count 6\n"));
+    }
 }
@@ -3,23 +3,135 @@
 
 use std::collections::HashMap;
 use std::io::{Error as WriteError, Write};
+use std::str::from_utf8;
 
-use ascii::{COLON, CR, LF, SP};
+use ascii::{COLON, CR, HTAB, LF, SP};
+use common::RequestError;
+
+// The maximum number of headers accepted in a Request.
+const MAX_HEADERS: usize = 32;
+// The maximum length of a header line, without the line ending.
+const MAX_HEADER_LINE_LEN: usize = 1024;
+// The Content-Length value is rejected early when it has more digits than this, so it can't
+// overflow when being parsed.
+const MAX_CONTENT_LENGTH_DIGITS: usize = 10;
+
+// Returns true if `byte` is allowed in a header name, as specified by the `token` rule from
+// [RFC 7230](https://tools.ietf.org/html/rfc7230#section-3.2.6).
+fn is_token_char(byte: u8) -> bool {
+    if byte.is_ascii_alphanumeric() {
+        return true;
+    }
+    match byte {
+        b'!' | b'#' | b'$' | b'%' | b'&' | b'\'' | b'*' | b'+' | b'-' | b'.' | b'^' | b'_'
+        | b'`' | b'|' | b'~' => true,
+        _ => false,
+    }
+}
+
+// Removes the optional whitespace surrounding a header value, and checks that the value does not
+// contain control characters.
+fn parse_header_value(bytes: &[u8]) -> Result<String, RequestError> {
+    let is_whitespace = |byte: &u8| *byte == SP || *byte == HTAB;
+    let start = bytes
+        .iter()
+        .position(|byte| !is_whitespace(byte))
+        .unwrap_or(bytes.len());
+    let end = bytes
+        .iter()
+        .rposition(|byte| !is_whitespace(byte))
+        .map_or(start, |pos| pos + 1);
+    let value = &bytes[start..end];
+
+    if value
+        .iter()
+        .any(|byte| (*byte < SP && *byte != HTAB) || *byte == 0x7f)
+    {
+        return Err(RequestError::InvalidHeader(
+            "Invalid character in header value.",
+        ));
+    }
+
+    from_utf8(value)
+        .map(String::from)
+        .map_err(|_| RequestError::InvalidHeader("Cannot parse header value as UTF-8."))
+}
 
 /// Wrapper over an HTTP Header type.
-#[derive(Debug, Eq, Hash, PartialEq)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub enum Header {
+    /// Header `Accept`.
+    Accept,
+    /// Header `Connection`.
+    Connection,
     /// Header `Content-Length`.
     ContentLength,
     /// Header `Content-Type`.
     ContentType,
+    /// Header `ETag`.
+    ETag,
+    /// Header `Host`.
+    Host,
+    /// Header `If-None-Match`.
+    IfNoneMatch,
+    /// Header `Transfer-Encoding`, which is only used by Responses.
+    TransferEncoding,
+    /// Custom header, whose name starts with `X-`. The name is stored in lower case.
+    Custom(String),
 }
 
 impl Header {
-    fn raw(&self) -> &'static [u8] {
+    fn raw(&self) -> &[u8] {
         match self {
+            Header::Accept => b"Accept",
+            Header::Connection => b"Connection",
             Header::ContentLength => b"Content-Length",
             Header::ContentType => b"Content-Type",
+            Header::ETag => b"ETag",
+            Header::Host => b"Host",
+            Header::IfNoneMatch => b"If-None-Match",
+            Header::TransferEncoding => b"Transfer-Encoding",
+            Header::Custom(name) => name.as_bytes(),
+        }
+    }
+
+    /// Parses a header name. Header names are case insensitive.
+    ///
+    /// Returns `None` for valid header names which are not interpreted by this crate. Such
+    /// headers are ignored when parsing a Request.
+    ///
+    /// # Errors
+    /// Returns `InvalidHeader` when the name is not a valid token, or when it refers to a header
+    /// which alters the framing of the Request in a way this crate does not support.
+    pub fn try_from(bytes: &[u8]) -> Result<Option<Self>, RequestError> {
+        if bytes.is_empty() || !bytes.iter().all(|byte| is_token_char(*byte)) {
+            return Err(RequestError::InvalidHeader("Invalid header name."));
+        }
+
+        // The unwrap is safe because token characters are ASCII.
+        let name = from_utf8(bytes).unwrap().to_ascii_lowercase();
+        match name.as_str() {
+            "accept" => Ok(Some(Header::Accept)),
+            "connection" => Ok(Some(Header::Connection)),
+            "content-length" => Ok(Some(Header::ContentLength)),
+            "content-type" => Ok(Some(Header::ContentType)),
+            "etag" => Ok(Some(Header::ETag)),
+            "host" => Ok(Some(Header::Host)),
+            "if-none-match" => Ok(Some(Header::IfNoneMatch)),
+            "transfer-encoding" => Err(RequestError::InvalidHeader(
+                "Transfer-Encoding is not supported.",
+            )),
+            _ if name.starts_with("x-") => Ok(Some(Header::Custom(name))),
+            _ => Ok(None),
+        }
+    }
+
+    // Headers which can appear at most once in a Request. The values of other repeated headers
+    // are combined in a comma separated list.
+    fn is_singleton(&self) -> bool {
+        match self {
+            Header::ContentLength | Header::ContentType | Header::Host => true,
+            _ => false,
         }
     }
 }
@@ -38,11 +150,105 @@ impl Headers {
         };
     }
 
+    /// Parses the header section of a Request. The section ends with an empty line or with the
+    /// end of `bytes`. Lines can end with either CRLF or LF.
+    ///
+    /// Only the headers described by `Header` are stored; the others are validated and then
+    /// ignored.
+    ///
+    /// # Errors
+    /// Returns `HeadersTooLarge` when there are too many headers, or when a header line is too
+    /// long. Returns `InvalidHeader` when a header is malformed, or when a header which can only
+    /// appear once is repeated.
+    pub fn try_from(bytes: &[u8]) -> Result<Headers, RequestError> {
+        let mut headers = Headers::default();
+
+        for (count, line) in bytes.split(|byte| *byte == LF).enumerate() {
+            let line = match line.last() {
+                Some(&CR) => &line[..line.len() - 1],
+                _ => line,
+            };
+            if line.is_empty() {
+                break;
+            }
+            if count >= MAX_HEADERS || line.len() > MAX_HEADER_LINE_LEN {
+                return Err(RequestError::HeadersTooLarge);
+            }
+
+            let colon = line
+                .iter()
+                .position(|byte| *byte == COLON)
+                .ok_or(RequestError::InvalidHeader("Missing colon in header."))?;
+            let header = Header::try_from(&line[..colon])?;
+            let value = parse_header_value(&line[colon + 1..])?;
+            if let Some(header) = header {
+                headers.insert_parsed(header, value)?;
+            }
+        }
+
+        Ok(headers)
+    }
+
+    fn insert_parsed(&mut self, header: Header, value: String) -> Result<(), RequestError> {
+        if header == Header::ContentLength
+            && (value.is_empty()
+                || value.len() > MAX_CONTENT_LENGTH_DIGITS
+                || !value.bytes().all(|byte| byte.is_ascii_digit()))
+        {
+            return Err(RequestError::InvalidHeader("Invalid Content-Length."));
+        }
+
+        if header.is_singleton() && self.headers.contains_key(&header) {
+            return Err(RequestError::InvalidHeader("Duplicate header."));
+        }
+
+        let value = match self.headers.remove(&header) {
+            Some(previous) => format!("{}, {}", previous, value),
+            None => value,
+        };
+        self.headers.insert(header, value);
+        Ok(())
+    }
+
     /// Adds a new header to the list.
     pub fn add(&mut self, header: Header, value: String) {
         self.headers.insert(header, value);
     }
 
+    /// Removes `header` from the list.
+    pub fn remove(&mut self, header: &Header) {
+        self.headers.remove(header);
+    }
+
+    /// Returns the value of `header`, if present.
+    pub fn get(&self, header: &Header) -> Option<&str> {
+        self.headers.get(header).map(String::as_str)
+    }
+
+    /// Returns the value of the `Content-Length` header, or 0 when the header is missing.
+    pub fn content_length(&self) -> usize {
+        self.get(&Header::ContentLength)
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(0)
+    }
+
+    /// Returns the value of the `Accept` header, if present.
+    pub fn accept(&self) -> Option<&str> {
+        self.get(&Header::Accept)
+    }
+
+    /// Returns the value of the `If-None-Match` header, if present. Repeated headers are combined
+    /// in a single comma separated list of entity tags.
+    pub fn if_none_match(&self) -> Option<&str> {
+        self.get(&Header::IfNoneMatch)
+    }
+
+    /// Returns the value of the custom header called `name`, if present. The lookup is case
+    /// insensitive.
+    pub fn custom(&self, name: &str) -> Option<&str> {
+        self.get(&Header::Custom(name.to_ascii_lowercase()))
+    }
+
     /// Writes the headers to `buf` using the HTTP specification.
     pub fn write_all<T: Write>(&self, buf: &mut T) -> Result<(), WriteError> {
         for (key, val) in &self.headers {
@@ -59,10 +265,40 @@ impl Headers {
     }
 }
 
+// Parses a quality value from an `Accept` header, as specified by
+// [RFC 7231](https://tools.ietf.org/html/rfc7231#section-5.3.1). The result is expressed in
+// thousandths, so 1 is returned as 1000.
+fn parse_qvalue(value: &str) -> Option<u16> {
+    let (int_part, frac_part) = match value.find('.') {
+        Some(pos) => (&value[..pos], &value[pos + 1..]),
+        None => (value, ""),
+    };
+    if frac_part.len() > 3 || !frac_part.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+
+    let mut thousandths: u16 = match int_part {
+        "0" => 0,
+        "1" => 1000,
+        _ => return None,
+    };
+    for (i, digit) in frac_part.bytes().enumerate() {
+        thousandths += u16::from(digit - b'0') * [100, 10, 1][i];
+    }
+
+    if thousandths > 1000 {
+        return None;
+    }
+    Some(thousandths)
+}
+
 /// Wrapper over supported Media Types.
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum MediaType {
     /// Media Type: "text/plain".
     PlainText,
+    /// Media Type: "application/json".
+    ApplicationJson,
 }
 
 impl MediaType {
@@ -70,7 +306,81 @@ impl MediaType {
     pub fn as_str(&self) -> &'static str {
         match self {
             MediaType::PlainText => "text/plain",
+            MediaType::ApplicationJson => "application/json",
+        }
+    }
+
+    /// Picks the media type from `supported` which is preferred by the client, based on the
+    /// value of an `Accept` header.
+    ///
+    /// Media ranges with wildcards and quality values are taken into account, and the most
+    /// specific range matching a media type determines its quality. When several media types
+    /// have the same quality, the one listed first in `supported` wins. Media ranges with an
+    /// invalid quality value are ignored.
+    ///
+    /// Returns None when none of the `supported` media types is acceptable.
+    ///
+    /// # Examples
+    /// ```
+    /// extern crate micro_http;
+    /// use micro_http::MediaType;
+    ///
+    /// let supported = [MediaType::PlainText, MediaType::ApplicationJson];
+    /// assert_eq!(
+    ///     MediaType::negotiate("text/*;q=0.5, application/json", &supported),
+    ///     Some(MediaType::ApplicationJson)
+    /// );
+    /// ```
+    pub fn negotiate(accept: &str, supported: &[MediaType]) -> Option<MediaType> {
+        // For each supported media type, the specificity and the quality of the best matching
+        // media range.
+        let mut matches = vec![(0u8, 0u16); supported.len()];
+
+        for range in accept.split(',') {
+            let mut params = range.split(';').map(str::trim);
+            // The unwrap is safe because split() always yields at least one item.
+            let range = params.next().unwrap().to_ascii_lowercase();
+            let mut quality = Some(1000);
+            for param in params {
+                let mut name_value = param.splitn(2, '=').map(str::trim);
+                if name_value.next().map_or(false, |name| name.eq_ignore_ascii_case("q")) {
+                    quality = name_value.next().and_then(parse_qvalue);
+                }
+            }
+            let quality = match quality {
+                Some(quality) => quality,
+                None => continue,
+            };
+
+            for (media_type, best) in supported.iter().zip(matches.iter_mut()) {
+                let media_type = media_type.as_str();
+                let specificity = if range == media_type {
+                    3
+                } else if range.ends_with("/*")
+                    && media_type.starts_with(&range[..range.len() - 1])
+                {
+                    2
+                } else if range == "*/*" {
+                    1
+                } else {
+                    0
+                };
+
+                if specificity > best.0 {
+                    *best = (specificity, quality);
+                }
+            }
         }
+
+        let mut result = None;
+        let mut best_quality = 0;
+        for (media_type, &(_, quality)) in supported.iter().zip(matches.iter()) {
+            if quality > best_quality {
+                result = Some(*media_type);
+                best_quality = quality;
+            }
+        }
+        result
     }
 }
 
@@ -107,6 +417,171 @@ mod tests {
             headers.headers.get(&Header::ContentLength).unwrap(),
             &"130".to_string()
         );
+
+        headers.remove(&Header::ContentLength);
+        assert!(headers.get(&Header::ContentLength).is_none());
+    }
+
+    #[test]
+    fn test_header_try_from() {
+        assert_eq!(
+            Header::try_from(b"content-LENGTH").unwrap(),
+            Some(Header::ContentLength)
+        );
+        assert_eq!(Header::try_from(b"Accept").unwrap(), Some(Header::Accept));
+        assert_eq!(
+            Header::try_from(b"If-None-Match").unwrap(),
+            Some(Header::IfNoneMatch)
+        );
+        assert_eq!(
+            Header::try_from(b"X-Custom-Header").unwrap(),
+            Some(Header::Custom("x-custom-header".to_string()))
+        );
+        assert_eq!(Header::try_from(b"User-Agent").unwrap(), None);
+
+        assert_eq!(
+            Header::try_from(b"").unwrap_err(),
+            RequestError::InvalidHeader("Invalid header name.")
+        );
+        assert_eq!(
+            Header::try_from(b"Content-Length ").unwrap_err(),
+            RequestError::InvalidHeader("Invalid header name.")
+        );
+        assert_eq!(
+            Header::try_from(b"Transfer-Encoding").unwrap_err(),
+            RequestError::InvalidHeader("Transfer-Encoding is not supported.")
+        );
+    }
+
+    #[test]
+    fn test_parse_headers() {
+        let headers = Headers::try_from(
+            b"Content-Length: 12\r\n\
+              accept:\tapplication/json \r\n\
+              X-Token: abc\n\
+              x-token: def\r\n\
+              User-Agent: curl\r\n\
+              If-None-Match: \"1\"\r\n\
+              if-none-match: W/\"2\"\r\n\
+              \r\n\
+              Host: ignored after the empty line",
+        ).unwrap();
+        assert_eq!(headers.content_length(), 12);
+        assert_eq!(headers.accept(), Some("application/json"));
+        assert_eq!(headers.custom("X-TOKEN"), Some("abc, def"));
+        assert_eq!(headers.custom("User-Agent"), None);
+        assert_eq!(headers.get(&Header::Host), None);
+        assert_eq!(headers.if_none_match(), Some(r#""1", W/"2""#));
+
+        // Missing headers.
+        let headers = Headers::try_from(b"").unwrap();
+        assert_eq!(headers.content_length(), 0);
+        assert_eq!(headers.accept(), None);
+        assert_eq!(headers.if_none_match(), None);
+
+        // Malformed headers.
+        assert_eq!(
+            Headers::try_from(b"Accept text/plain\r\n").unwrap_err(),
+            RequestError::InvalidHeader("Missing colon in header.")
+        );
+        assert_eq!(
+            Headers::try_from(b" Accept: text/plain\r\n").unwrap_err(),
+            RequestError::InvalidHeader("Invalid header name.")
+        );
+        assert_eq!(
+            Headers::try_from(b"Accept: text/\x01plain\r\n").unwrap_err(),
+            RequestError::InvalidHeader("Invalid character in header value.")
+        );
+        assert_eq!(
+            Headers::try_from(b"Accept: text/\xffplain\r\n").unwrap_err(),
+            RequestError::InvalidHeader("Cannot parse header value as UTF-8.")
+        );
+        for value in &["", "-1", "1 2", "0x10", "12345678901"] {
+            let line = format!("Content-Length: {}\r\n", value);
+            assert_eq!(
+                Headers::try_from(line.as_bytes()).unwrap_err(),
+                RequestError::InvalidHeader("Invalid Content-Length.")
+            );
+        }
+        assert_eq!(
+            Headers::try_from(b"Content-Length: 1\r\nContent-Length: 1\r\n").unwrap_err(),
+            RequestError::InvalidHeader("Duplicate header.")
+        );
+        assert_eq!(
+            Headers::try_from(b"Host: a\r\nhost: b\r\n").unwrap_err(),
+            RequestError::InvalidHeader("Duplicate header.")
+        );
+
+        // Size limits.
+        let long_line = format!("X-Long: {}\r\n", "a".repeat(MAX_HEADER_LINE_LEN));
+        assert_eq!(
+            Headers::try_from(long_line.as_bytes()).unwrap_err(),
+            RequestError::HeadersTooLarge
+        );
+        let max_headers = "X-A: b\r\n".repeat(MAX_HEADERS);
+        assert!(Headers::try_from(max_headers.as_bytes()).is_ok());
+        let too_many_headers = "X-A: b\r\n".repeat(MAX_HEADERS + 1);
+        assert_eq!(
+            Headers::try_from(too_many_headers.as_bytes()).unwrap_err(),
+            RequestError::HeadersTooLarge
+        );
+    }
+
+    #[test]
+    fn test_parse_qvalue() {
+        assert_eq!(parse_qvalue("1"), Some(1000));
+        assert_eq!(parse_qvalue("1.000"), Some(1000));
+        assert_eq!(parse_qvalue("0"), Some(0));
+        assert_eq!(parse_qvalue("0.5"), Some(500));
+        assert_eq!(parse_qvalue("0.123"), Some(123));
+        assert_eq!(parse_qvalue("0."), Some(0));
+        assert_eq!(parse_qvalue("1.001"), None);
+        assert_eq!(parse_qvalue("0.1234"), None);
+        assert_eq!(parse_qvalue("2"), None);
+        assert_eq!(parse_qvalue("-0.5"), None);
+        assert_eq!(parse_qvalue(""), None);
+    }
+
+    #[test]
+    fn test_negotiate_media_type() {
+        let supported = [MediaType::PlainText, MediaType::ApplicationJson];
+        let negotiate = |accept: &str| MediaType::negotiate(accept, &supported);
+
+        assert_eq!(negotiate("application/json"), Some(MediaType::ApplicationJson));
+        assert_eq!(negotiate("Application/JSON"), Some(MediaType::ApplicationJson));
+        assert_eq!(negotiate("text/plain"), Some(MediaType::PlainText));
+        // Ties are broken by the order of the supported media types.
+        assert_eq!(negotiate("*/*"), Some(MediaType::PlainText));
+        assert_eq!(
+            negotiate("text/plain, application/json"),
+            Some(MediaType::PlainText)
+        );
+        // Quality values.
+        assert_eq!(
+            negotiate("text/plain;q=0.5, application/json;q=0.8"),
+            Some(MediaType::ApplicationJson)
+        );
+        assert_eq!(
+            negotiate("*/*;q=0.1, application/*"),
+            Some(MediaType::ApplicationJson)
+        );
+        // The most specific range wins, even if a less specific one has a higher quality.
+        assert_eq!(
+            negotiate("application/json;q=0, */*"),
+            Some(MediaType::PlainText)
+        );
+        assert_eq!(negotiate("text/*;q=0, application/json;q=0"), None);
+        // Unknown and invalid ranges are ignored.
+        assert_eq!(negotiate("image/png"), None);
+        assert_eq!(negotiate(""), None);
+        assert_eq!(
+            negotiate("application/json;q=2, text/plain;q=0.1"),
+            Some(MediaType::PlainText)
+        );
+        assert_eq!(
+            negotiate("application/json; charset=utf-8"),
+            Some(MediaType::ApplicationJson)
+        );
     }
 
     #[test]
@@ -6,6 +6,7 @@ pub mod headers;
 pub mod ascii {
     pub const CR: u8 = b'\r';
     pub const COLON: u8 = b':';
+    pub const HTAB: u8 = b'\t';
     pub const LF: u8 = b'\n';
     pub const SP: u8 = b' ';
 }
@@ -21,6 +22,12 @@ pub enum RequestError {
     InvalidUri(&'static str),
     /// The HTTP Version in the Request is not supported or it is invalid.
     InvalidHttpVersion(&'static str),
+    /// A Request header is malformed, duplicated, or not supported.
+    InvalidHeader(&'static str),
+    /// The Request line and headers exceed the maximum allowed size or number of headers.
+    HeadersTooLarge,
+    /// The length of the Request body exceeds the maximum allowed size.
+    BodyTooLarge,
 }
 
 /// The Body associated with an HTTP Request or Response.
@@ -56,10 +63,18 @@ impl Body {
 }
 
 /// Supported HTTP Methods.
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Method {
     /// GET Method.
     Get,
+    /// HEAD Method.
+    Head,
+    /// PUT Method.
+    Put,
+    /// PATCH Method.
+    Patch,
+    /// DELETE Method.
+    Delete,
 }
 
 impl Method {
@@ -73,6 +88,10 @@ impl Method {
     pub fn try_from(bytes: &[u8]) -> Result<Self, RequestError> {
         match bytes {
             b"GET" => Ok(Method::Get),
+            b"HEAD" => Ok(Method::Head),
+            b"PUT" => Ok(Method::Put),
+            b"PATCH" => Ok(Method::Patch),
+            b"DELETE" => Ok(Method::Delete),
             _ => Err(RequestError::InvalidHttpMethod("Unsupported HTTP method.")),
         }
     }
@@ -81,6 +100,10 @@ impl Method {
     pub fn raw(&self) -> &'static [u8] {
         match self {
             Method::Get => b"GET",
+            Method::Head => b"HEAD",
+            Method::Put => b"PUT",
+            Method::Patch => b"PATCH",
+            Method::Delete => b"DELETE",
         }
     }
 }
@@ -164,11 +187,27 @@ mod tests {
     fn test_method() {
         // Test for raw
         assert_eq!(Method::Get.raw(), b"GET");
+        assert_eq!(Method::Head.raw(), b"HEAD");
+        assert_eq!(Method::Put.raw(), b"PUT");
+        assert_eq!(Method::Patch.raw(), b"PATCH");
+        assert_eq!(Method::Delete.raw(), b"DELETE");
 
         // Tests for try_from
-        assert_eq!(Method::try_from(b"GET").unwrap(), Method::Get);
+        for method in &[
+            Method::Get,
+            Method::Head,
+            Method::Put,
+            Method::Patch,
+            Method::Delete,
+        ] {
+            assert_eq!(Method::try_from(method.raw()).unwrap(), *method);
+        }
+        assert_eq!(
+            Method::try_from(b"put").unwrap_err(),
+            RequestError::InvalidHttpMethod("Unsupported HTTP method.")
+        );
         assert_eq!(
-            Method::try_from(b"PUT").unwrap_err(),
+            Method::try_from(b"POST").unwrap_err(),
             RequestError::InvalidHttpMethod("Unsupported HTTP method.")
         );
     }
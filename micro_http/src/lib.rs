@@ -7,29 +7,54 @@
 //! HTTP/1.1 has a mandatory header **Host**, but as this crate is only used
 //! for parsing MMDS requests, this header (if present) is ignored.
 //!
-//! This HTTP implementation is stateless thus it does not support chunking or
-//! compression.
+//! This HTTP implementation does not support compression, and only supports
+//! chunking for **Response** bodies. Persistent connections and pipelining are
+//! supported by parsing requests one at a time from a byte stream:
+//! **Request::complete_len** finds where the first complete request ends, and
+//! **Request::keep_alive** tells whether the connection should stay open afterwards.
 //!
 //! ## Supported Headers
-//! The **micro_http** crate does not have support for parsing **Request**
-//! headers.
+//! The **Request** headers **Content-Length**, **Content-Type**, **Accept**,
+//! **Connection**, **Host**, and custom headers starting with **X-** are parsed
+//! into **Headers**. Header names are case insensitive. Other headers are
+//! validated and then ignored. **Transfer-Encoding** is not supported, so a
+//! request body is only accepted when its length is given by **Content-Length**.
 //!
-//! The **Response** does not have a public interface for adding headers, but whenever
-//! a write to the **Body** is made, the headers **ContentLength** and **MediaType**
-//! are automatically updated.
+//! Requests are subject to strict size limits: the request line and headers
+//! can't exceed 8 KiB, there can be at most 32 headers of at most 1 KiB each,
+//! and the body can't exceed 16 KiB.
+//!
+//! The **Response** does not have a public interface for adding arbitrary headers.
+//! Whenever a write to the **Body** is made, the headers **ContentLength** and
+//! **MediaType** are automatically updated. The **MediaType** and **Connection**
+//! headers can also be set with **set_content_type** and **set_keep_alive**.
+//!
+//! Large bodies can be streamed with **set_body_stream**, in which case they are read
+//! incrementally while the **Response** is written with **write_head** and
+//! **write_body_chunk**. Streamed bodies use the chunked transfer coding (and the
+//! **Transfer-Encoding** header) for HTTP/1.1 clients, while HTTP/1.0 responses close the
+//! connection after the body. When the length of a streamed body is known in advance,
+//! **set_sized_body_stream** sends it via the **Content-Length** header instead, for both
+//! HTTP versions.
 //!
 //! ### Media Types
-//! The only supported media type is **text/plain**.
+//! The supported media types are **text/plain** and **application/json**.
+//! **MediaType::negotiate** picks the media type of a response based on the
+//! **Accept** header of the request.
 //!
 //! ## Supported Methods
-//! The only supported HTTP Method is **GET**.
+//! The supported HTTP Methods are **GET**, **HEAD**, **PUT**, **PATCH** and
+//! **DELETE**.
 //!
 //! ## Supported Status Codes
 //! The supported status codes are:
 //!
 //! - OK - 200
 //! - Bad Request - 400
+//! - Unauthorized - 401
+//! - Forbidden - 403
 //! - Not Found - 404
+//! - Payload Too Large - 413
 //! - Internal Server Error - 500
 //! - Not Implemented - 501
 //!
@@ -65,7 +90,8 @@ mod response;
 use common::ascii;
 use common::headers;
 
-pub use request::{Request, RequestError};
+pub use request::{Request, RequestError, MAX_BODY_LEN, MAX_HEAD_LEN};
 pub use response::{Response, StatusCode};
 
-pub use common::{Body, Version};
+pub use common::headers::{Header, Headers, MediaType};
+pub use common::{Body, Method, Version};
@@ -6,7 +6,12 @@ use std::str::from_utf8;
 use common::ascii::{CR, LF, SP};
 pub use common::RequestError;
 use common::{Body, Method, Version};
-use headers::Headers;
+use headers::{Header, Headers};
+
+/// The maximum length of the Request line and headers, including the line endings.
+pub const MAX_HEAD_LEN: usize = 8192;
+/// The maximum length of a Request body.
+pub const MAX_BODY_LEN: usize = 16384;
 
 // Helper function used for parsing the HTTP Request.
 // Splits the bytes in a pair containing the bytes before the separator and after the separator.
@@ -18,6 +23,21 @@ fn split(bytes: &[u8], separator: u8) -> (&[u8], &[u8]) {
     }
 }
 
+// Returns the length of the head of a Request (the Request line and the headers), up to and
+// including the empty line which ends the header section, or None if there is no such line.
+fn find_head_end(bytes: &[u8]) -> Option<usize> {
+    let mut line_start = 0;
+    for (i, byte) in bytes.iter().enumerate() {
+        if *byte == LF {
+            if i == line_start || (i == line_start + 1 && bytes[line_start] == CR) {
+                return Some(i + 1);
+            }
+            line_start = i + 1;
+        }
+    }
+    None
+}
+
 /// Wrapper over HTTP URIs.
 ///
 /// The `Uri` can not be used directly and it is only accessible from an HTTP Request.
@@ -119,28 +139,33 @@ impl<'a> RequestLine<'a> {
 }
 
 /// Wrapper over an HTTP Request.
-#[allow(unused)]
 #[derive(Debug)]
 pub struct Request<'a> {
     request_line: RequestLine<'a>,
     headers: Headers,
     body: Option<Body>,
+    // The number of bytes from the input byte stream which belong to this Request.
+    len: usize,
 }
 
 impl<'a> Request<'a> {
     /// Parses a byte slice into a HTTP Request.
     ///
     /// The byte slice is expected to have the following format: </br>
-    ///     * Request Line: "GET SP Request-uri SP HTTP/1.0 CRLF" - Mandatory </br>
+    ///     * Request Line: "METHOD SP Request-uri SP HTTP/1.0 CRLF" - Mandatory </br>
     ///     * Request Headers "<headers> CRLF"- Optional </br>
     ///     * Entity Body - Optional </br>
-    /// The request headers and the entity body is not parsed and None is returned because
-    /// these are not used by the MMDS server.
-    /// The only supported method is GET and the HTTP protocol is expected to be HTTP/1.0
-    /// or HTTP/1.1.
+    /// The header section ends with an empty line, or with the end of the byte slice. Lines can
+    /// end with either CRLF or LF. The entity body is only read when the `Content-Length` header
+    /// is present, and it contains exactly that many bytes. Any bytes after the body are not part
+    /// of the Request (they usually belong to the next pipelined Request), and `len` can be used
+    /// to find out where the Request ends.
+    /// The HTTP protocol is expected to be HTTP/1.0 or HTTP/1.1.
     ///
     /// # Errors
-    /// The function returns InvalidRequest when parsing the byte stream fails.
+    /// The function returns InvalidRequest when parsing the byte stream fails, or when the byte
+    /// stream is shorter than the `Content-Length` announced by the headers. The limits on the
+    /// size of the Request are enforced by returning HeadersTooLarge and BodyTooLarge.
     ///
     /// # Examples
     ///
@@ -151,20 +176,80 @@ impl<'a> Request<'a> {
     /// let http_request = Request::try_from(b"GET http://localhost/home HTTP/1.0\r\n");
     /// ```
     pub fn try_from(byte_stream: &'a [u8]) -> Result<Self, RequestError> {
+        let (head_len, head_complete) = match find_head_end(byte_stream) {
+            Some(len) => (len, true),
+            None => (byte_stream.len(), false),
+        };
+        let (request_line, headers) = Request::parse_head(&byte_stream[..head_len])?;
+
+        let body_len = Request::body_len(&headers)?;
+        let body = if body_len > 0 {
+            if !head_complete || byte_stream.len() - head_len < body_len {
+                return Err(RequestError::InvalidRequest);
+            }
+            Some(Body::new(&byte_stream[head_len..head_len + body_len]))
+        } else {
+            None
+        };
+
+        Ok(Request {
+            request_line,
+            headers,
+            body,
+            len: head_len + body_len,
+        })
+    }
+
+    /// Looks for a complete Request at the beginning of `byte_stream`, which may contain a
+    /// partially received Request, or multiple pipelined ones.
+    ///
+    /// Returns the length of the first Request when all its bytes (including the body) are
+    /// available, or `None` when more bytes are needed.
+    ///
+    /// # Errors
+    /// Returns the same errors as `try_from` when the Request line or the headers are invalid,
+    /// and HeadersTooLarge when the header section does not end within the maximum allowed
+    /// length.
+    pub fn complete_len(byte_stream: &[u8]) -> Result<Option<usize>, RequestError> {
+        let head_len = match find_head_end(byte_stream) {
+            Some(len) => len,
+            None if byte_stream.len() > MAX_HEAD_LEN => return Err(RequestError::HeadersTooLarge),
+            None => return Ok(None),
+        };
+        let (_, headers) = Request::parse_head(&byte_stream[..head_len])?;
+
+        let len = head_len + Request::body_len(&headers)?;
+        if len <= byte_stream.len() {
+            Ok(Some(len))
+        } else {
+            Ok(None)
+        }
+    }
+
+    // Parses the Request line and the headers.
+    fn parse_head(head: &'a [u8]) -> Result<(RequestLine<'a>, Headers), RequestError> {
+        if head.len() > MAX_HEAD_LEN {
+            return Err(RequestError::HeadersTooLarge);
+        }
+
         // The first line of the request is the Request Line. The line ending is LF.
-        let (request_line, _) = split(byte_stream, LF);
+        let (request_line, headers) = split(head, LF);
         if request_line.len() < RequestLine::min_len() {
             return Err(RequestError::InvalidRequest);
         }
 
         // The Request Line should include the trailing LF.
-        let request_line = RequestLine::try_from(&byte_stream[..=request_line.len()])?;
-        // We ignore the Headers and Entity body because we don't need them for MMDS requests.
-        Ok(Request {
-            request_line,
-            headers: Headers::default(),
-            body: None,
-        })
+        let request_line = RequestLine::try_from(&head[..=request_line.len()])?;
+        let headers = Headers::try_from(headers)?;
+        Ok((request_line, headers))
+    }
+
+    fn body_len(headers: &Headers) -> Result<usize, RequestError> {
+        let len = headers.content_length();
+        if len > MAX_BODY_LEN {
+            return Err(RequestError::BodyTooLarge);
+        }
+        Ok(len)
     }
 
     /// Returns the `Uri` from the parsed `Request`.
@@ -178,6 +263,47 @@ impl<'a> Request<'a> {
     pub fn http_version(&self) -> Version {
         self.request_line.http_version
     }
+
+    /// Returns the HTTP `Method` of the `Request`.
+    pub fn method(&self) -> Method {
+        self.request_line.method
+    }
+
+    /// Returns the headers of the `Request`.
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    /// Returns the body of the `Request`, or None if the `Request` does not have a body.
+    pub fn body(&self) -> Option<&Body> {
+        self.body.as_ref()
+    }
+
+    /// Returns the number of bytes from the parsed byte stream which make up the `Request`.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if the client wants to keep the connection open after receiving the
+    /// response to this `Request`.
+    ///
+    /// HTTP/1.1 connections are persistent unless the `Connection` header contains the "close"
+    /// option, while HTTP/1.0 connections are persistent only if the header contains the
+    /// "keep-alive" option.
+    pub fn keep_alive(&self) -> bool {
+        let mut keep_alive = self.request_line.http_version == Version::Http11;
+        if let Some(value) = self.headers.get(&Header::Connection) {
+            for option in value.split(',').map(str::trim) {
+                if option.eq_ignore_ascii_case("close") {
+                    return false;
+                }
+                if option.eq_ignore_ascii_case("keep-alive") {
+                    keep_alive = true;
+                }
+            }
+        }
+        keep_alive
+    }
 }
 
 #[cfg(test)]
@@ -243,8 +369,20 @@ mod tests {
             Err(_) => assert!(false),
         };
 
+        // Happy case with a method other than GET.
+        let expected_request_line = RequestLine {
+            http_version: Version::Http11,
+            method: Method::Put,
+            uri: Uri::new("/home"),
+        };
+        let request_line = b"PUT /home HTTP/1.1\r\n";
+        match RequestLine::try_from(request_line) {
+            Ok(request) => assert_eq!(request, expected_request_line),
+            Err(_) => assert!(false),
+        };
+
         // Test for invalid method.
-        let request_line = b"PUT http://localhost/home HTTP/1.0\r\n";
+        let request_line = b"POST http://localhost/home HTTP/1.0\r\n";
         assert_eq!(
             RequestLine::try_from(request_line).unwrap_err(),
             RequestError::InvalidHttpMethod("Unsupported HTTP method.")
@@ -275,13 +413,17 @@ mod tests {
             },
             body: None,
             headers: Headers::default(),
+            len: 0,
         };
-        let request_bytes = b"GET http://localhost/home HTTP/1.0\r\n \
+        let request_bytes = b"GET http://localhost/home HTTP/1.0\r\n\
                                      Last-Modified: Tue, 15 Nov 1994 12:45:26 GMT";
         let request = Request::try_from(request_bytes).unwrap();
         assert!(request == expected_request);
         assert_eq!(request.uri(), &Uri::new("http://localhost/home"));
         assert_eq!(request.http_version(), Version::Http10);
+        assert_eq!(request.method(), Method::Get);
+        assert!(request.body().is_none());
+        assert_eq!(request.len(), request_bytes.len());
 
         // Test for invalid Request (length is less than minimum).
         let request_bytes = b"GET";
@@ -289,5 +431,230 @@ mod tests {
             Request::try_from(request_bytes).unwrap_err(),
             RequestError::InvalidRequest
         );
+
+        // Test for invalid Request (obsolete line folding is not supported).
+        let request_bytes = b"GET http://localhost/home HTTP/1.0\r\n \
+                                     Last-Modified: Tue, 15 Nov 1994 12:45:26 GMT";
+        assert_eq!(
+            Request::try_from(request_bytes).unwrap_err(),
+            RequestError::InvalidHeader("Invalid header name.")
+        );
+    }
+
+    #[test]
+    fn test_request_with_body() {
+        let request_bytes = b"PATCH /mmds HTTP/1.1\r\n\
+                              Content-Length: 12\r\n\
+                              Accept: application/json\r\n\
+                              X-Metadata-Token: secret\r\n\r\n\
+                              {\"key\": \"a\"}GET / HTTP/1.1\r\n\r\n";
+        let request = Request::try_from(request_bytes).unwrap();
+        assert_eq!(request.method(), Method::Patch);
+        assert_eq!(request.uri().get_abs_path(), "/mmds");
+        assert_eq!(request.headers().content_length(), 12);
+        assert_eq!(request.headers().accept(), Some("application/json"));
+        assert_eq!(
+            request.headers().custom("x-metadata-token"),
+            Some("secret")
+        );
+        assert_eq!(request.body().unwrap().raw(), b"{\"key\": \"a\"}");
+        // The trailing bytes belong to the next Request.
+        assert_eq!(request.len(), request_bytes.len() - 18);
+
+        // The body is shorter than the Content-Length.
+        assert_eq!(
+            Request::try_from(&request_bytes[..request_bytes.len() - 20]).unwrap_err(),
+            RequestError::InvalidRequest
+        );
+
+        // The header section does not end, so the body can't be read.
+        assert_eq!(
+            Request::try_from(b"PUT / HTTP/1.1\r\nContent-Length: 1\r\n").unwrap_err(),
+            RequestError::InvalidRequest
+        );
+
+        // Bodies without a Content-Length are not part of the Request.
+        let request_bytes = b"PUT / HTTP/1.1\r\n\r\nbody";
+        let request = Request::try_from(request_bytes).unwrap();
+        assert!(request.body().is_none());
+        assert_eq!(request.len(), request_bytes.len() - 4);
+
+        // The body exceeds the maximum size.
+        let request_bytes = format!("PUT / HTTP/1.1\r\nContent-Length: {}\r\n\r\n", MAX_BODY_LEN + 1);
+        assert_eq!(
+            Request::try_from(request_bytes.as_bytes()).unwrap_err(),
+            RequestError::BodyTooLarge
+        );
+
+        // The header section exceeds the maximum size.
+        let request_bytes = format!(
+            "GET / HTTP/1.1\r\n{}\r\n",
+            "User-Agent: a\r\n".repeat(MAX_HEAD_LEN / 16)
+        );
+        assert_eq!(
+            Request::try_from(request_bytes.as_bytes()).unwrap_err(),
+            RequestError::HeadersTooLarge
+        );
+
+        // Framing headers we do not support.
+        assert_eq!(
+            Request::try_from(b"PUT / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n").unwrap_err(),
+            RequestError::InvalidHeader("Transfer-Encoding is not supported.")
+        );
+    }
+
+    #[test]
+    fn test_keep_alive() {
+        let keep_alive = |bytes: &[u8]| Request::try_from(bytes).unwrap().keep_alive();
+
+        assert!(keep_alive(b"GET / HTTP/1.1\r\n\r\n"));
+        assert!(!keep_alive(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n"));
+        assert!(!keep_alive(
+            b"GET / HTTP/1.1\r\nConnection: keep-alive, Close\r\n\r\n"
+        ));
+        assert!(!keep_alive(b"GET / HTTP/1.0\r\n\r\n"));
+        assert!(keep_alive(
+            b"GET / HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\n"
+        ));
+    }
+
+    #[test]
+    fn test_pipelined_requests() {
+        let mut stream = Vec::new();
+        stream.extend_from_slice(b"GET /a HTTP/1.1\r\n\r\n");
+        stream.extend_from_slice(b"PUT /b HTTP/1.1\r\nContent-Length: 3\r\n\r\nabc");
+        stream.extend_from_slice(b"DELETE /c HTTP/1.1\n\n");
+        stream.extend_from_slice(b"HEAD /d HTTP/1.1\r\nHost: localhost\r\n");
+
+        let mut requests = Vec::new();
+        let mut remaining = stream.as_slice();
+        while let Some(len) = Request::complete_len(remaining).unwrap() {
+            let request = Request::try_from(&remaining[..len]).unwrap();
+            assert_eq!(request.len(), len);
+            requests.push((request.method(), request.uri().get_abs_path().to_string()));
+            remaining = &remaining[len..];
+        }
+
+        assert_eq!(
+            requests,
+            vec![
+                (Method::Get, "/a".to_string()),
+                (Method::Put, "/b".to_string()),
+                (Method::Delete, "/c".to_string()),
+            ]
+        );
+        // The last Request is incomplete because its header section has not ended yet.
+        assert_eq!(remaining, b"HEAD /d HTTP/1.1\r\nHost: localhost\r\n");
+
+        // A partially received body.
+        assert_eq!(
+            Request::complete_len(b"PUT /b HTTP/1.1\r\nContent-Length: 3\r\n\r\nab").unwrap(),
+            None
+        );
+
+        // Errors are reported as soon as the header section is complete.
+        assert_eq!(
+            Request::complete_len(b"GET /a HTTP/1.1\r\nbad header\r\n\r\n").unwrap_err(),
+            RequestError::InvalidHeader("Missing colon in header.")
+        );
+        assert_eq!(
+            Request::complete_len(&[b'a'; MAX_HEAD_LEN + 1]).unwrap_err(),
+            RequestError::HeadersTooLarge
+        );
+    }
+
+    // A simple xorshift generator, which makes the fuzz-style tests below deterministic.
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+    }
+
+    // Checks the invariants of the parsing functions for an arbitrary byte stream.
+    fn check_parse_invariants(bytes: &[u8]) {
+        if let Ok(request) = Request::try_from(bytes) {
+            assert!(request.len() <= bytes.len());
+            if let Some(body) = request.body() {
+                assert_eq!(body.len(), request.headers().content_length());
+                assert!(body.len() <= MAX_BODY_LEN);
+            }
+        }
+
+        if let Ok(Some(len)) = Request::complete_len(bytes) {
+            assert!(len <= bytes.len());
+            // A complete Request must be parsed successfully, and it must end exactly where
+            // complete_len() said it does.
+            assert_eq!(Request::try_from(&bytes[..len]).unwrap().len(), len);
+        }
+    }
+
+    #[test]
+    fn test_fuzz_mutations() {
+        let seeds: &[&[u8]] = &[
+            b"GET http://169.254.169.254/latest/meta-data HTTP/1.1\r\n\r\n",
+            b"PUT /latest/api/token HTTP/1.1\r\nX-Token-TTL: 21600\r\nContent-Length: 4\r\n\r\nbody",
+            b"PATCH /mmds HTTP/1.0\nAccept: application/json\nConnection: keep-alive\n\n",
+            b"DELETE / HTTP/1.1\r\nHost: a\r\n\r\nHEAD / HTTP/1.1\r\n\r\n",
+        ];
+        // Bytes which are more likely to change the structure of a Request.
+        let interesting: &[u8] = b"\r\n: \t,0123456789/-Xx\x00\x7f\xff";
+
+        let mut rng = XorShift(0x2545_f491_4f6c_dd1d);
+        for _ in 0..20_000 {
+            let mut bytes = seeds[rng.below(seeds.len())].to_vec();
+            for _ in 0..=rng.below(4) {
+                let pos = rng.below(bytes.len() + 1);
+                let byte = if rng.below(2) == 0 {
+                    interesting[rng.below(interesting.len())]
+                } else {
+                    rng.next() as u8
+                };
+                match rng.below(4) {
+                    0 if pos < bytes.len() => bytes[pos] = byte,
+                    1 if pos < bytes.len() => {
+                        bytes.remove(pos);
+                    }
+                    2 => bytes.truncate(pos),
+                    _ => bytes.insert(pos, byte),
+                }
+            }
+            check_parse_invariants(&bytes);
+        }
+    }
+
+    #[test]
+    fn test_fuzz_random_bytes() {
+        let mut rng = XorShift(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..5_000 {
+            let len = rng.below(256);
+            let mut bytes: Vec<u8> = (0..len).map(|_| rng.next() as u8).collect();
+            // Give some of the inputs a valid Request line, so the header parser is exercised too.
+            if rng.below(2) == 0 {
+                let mut prefixed = b"PUT / HTTP/1.1\r\n".to_vec();
+                prefixed.append(&mut bytes);
+                bytes = prefixed;
+            }
+            check_parse_invariants(&bytes);
+        }
+
+        // Large inputs exercise the size limits.
+        let mut bytes = b"GET / HTTP/1.1\r\n".to_vec();
+        while bytes.len() <= MAX_HEAD_LEN {
+            bytes.extend_from_slice(b"X-Filler: 0123456789\r\n");
+        }
+        check_parse_invariants(&bytes);
+        assert_eq!(
+            Request::complete_len(&bytes).unwrap_err(),
+            RequestError::HeadersTooLarge
+        );
     }
 }
@@ -1,12 +1,15 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-use std::io::{Error as WriteError, Write};
+use std::io::{Error as WriteError, Read, Write};
 
 use ascii::{CR, LF, SP};
 use common::{Body, Version};
 use headers::{Header, Headers, MediaType};
 
+// The largest chunk written by write_all() for streamed bodies.
+const STREAM_CHUNK_MAX_LEN: usize = 4096;
+
 /// Wrapper over a response status code.
 ///
 /// The status code is defined as specified in the
@@ -16,10 +19,20 @@ use headers::{Header, Headers, MediaType};
 pub enum StatusCode {
     /// 100, OK
     OK,
+    /// 204, No Content
+    NoContent,
+    /// 304, Not Modified
+    NotModified,
     /// 400, Bad Request
     BadRequest,
+    /// 401, Unauthorized
+    Unauthorized,
+    /// 403, Forbidden
+    Forbidden,
     /// 404, Not Found
     NotFound,
+    /// 413, Payload Too Large
+    PayloadTooLarge,
     /// 500, Internal Server Error
     InternalServerError,
     /// 501, Not Implemented
@@ -30,8 +43,13 @@ impl StatusCode {
     fn raw(&self) -> &'static [u8; 3] {
         match self {
             StatusCode::OK => b"200",
+            StatusCode::NoContent => b"204",
+            StatusCode::NotModified => b"304",
             StatusCode::BadRequest => b"400",
+            StatusCode::Unauthorized => b"401",
+            StatusCode::Forbidden => b"403",
             StatusCode::NotFound => b"404",
+            StatusCode::PayloadTooLarge => b"413",
             StatusCode::InternalServerError => b"500",
             StatusCode::NotImplemented => b"501",
         }
@@ -61,14 +79,33 @@ impl StatusLine {
     }
 }
 
+// The body of a Response which is read incrementally, and the state of the transfer.
+struct BodyStream {
+    source: Box<Read + Send>,
+    // The length of the body, when known in advance. It is sent via the `Content-Length` header.
+    len: Option<usize>,
+    // The body is sent using the chunked transfer coding when this is set. Otherwise, the end of
+    // the body is signaled by its length, if known, or by closing the connection.
+    chunked: bool,
+    // Set after the whole body (including the last chunk) has been written.
+    done: bool,
+}
+
 /// Wrapper over an HTTP Response.
 ///
 /// The Response is created using a `Version` and a `StatusCode`. When creating a Response object,
-/// the body is initialize to `None`. The body can be updated with a call to `set_body`.
+/// the body is initialize to `None`. The body can be updated with a call to `set_body`, or with a
+/// call to `set_body_stream` (or `set_sized_body_stream`) for bodies which should be read
+/// incrementally.
+/// HTTP/1.1 responses keep the connection open by default, while HTTP/1.0 responses do not.
+/// This can be changed with a call to `set_keep_alive`.
 pub struct Response {
     status_line: StatusLine,
     headers: Headers,
     body: Option<Body>,
+    body_stream: Option<BodyStream>,
+    content_type: MediaType,
+    keep_alive: bool,
 }
 
 impl Response {
@@ -78,38 +115,194 @@ impl Response {
             status_line: StatusLine::new(http_version, status_code),
             headers: Headers::default(),
             body: None,
+            body_stream: None,
+            content_type: MediaType::PlainText,
+            keep_alive: http_version == Version::Http11,
         };
     }
 
+    /// Sets the `Connection` header, which tells the client whether the connection stays open
+    /// after the `Response` is sent.
+    ///
+    /// HTTP/1.0 responses with a streamed body of unknown length always close the connection.
+    pub fn set_keep_alive(&mut self, keep_alive: bool) {
+        let keep_alive = keep_alive && !self.closes_connection();
+        let value = if keep_alive { "keep-alive" } else { "close" };
+        self.headers.add(Header::Connection, String::from(value));
+        self.keep_alive = keep_alive;
+    }
+
+    /// Returns true if the connection should stay open after the `Response` is sent.
+    pub fn keep_alive(&self) -> bool {
+        self.keep_alive
+    }
+
+    /// Sets the `ETag` header, which identifies the version of the resource carried by the
+    /// `Response`. The value is sent as is, so it should already be quoted.
+    pub fn set_etag(&mut self, etag: String) {
+        self.headers.add(Header::ETag, etag);
+    }
+
     /// Updates the body of the `Response`.
     ///
     /// This function has side effects because it also updates the headers:
     /// - `ContentLength`: this is set to the length of the specified body.
-    /// - `MediaType`: this is set to "text/plain".
+    /// - `MediaType`: this is set to "text/plain", unless `set_content_type` was called.
     pub fn set_body(&mut self, body: Body) {
+        self.headers.remove(&Header::TransferEncoding);
         self.headers
             .add(Header::ContentLength, body.len().to_string());
         self.headers.add(
             Header::ContentType,
-            String::from(MediaType::PlainText.as_str()),
+            String::from(self.content_type.as_str()),
         );
         self.body = Some(body);
+        self.body_stream = None;
+    }
+
+    /// Sets a body which is read from `source` as the `Response` is being written, instead of
+    /// being held in memory as a whole.
+    ///
+    /// HTTP/1.1 responses use the chunked transfer coding, and the `Transfer-Encoding` header
+    /// is set accordingly. HTTP/1.0 clients don't know about chunks, so the end of the body is
+    /// signaled by closing the connection instead, and the `Connection` header is set to `close`.
+    /// The `MediaType` header is also updated, just like with `set_body`.
+    pub fn set_body_stream(&mut self, source: Box<Read + Send>) {
+        self.set_stream(source, None);
     }
 
-    fn write_body<T: Write>(&self, mut buf: T) -> Result<(), WriteError> {
+    /// Sets a body of `len` bytes, which is read from `source` as the `Response` is being
+    /// written, instead of being held in memory as a whole.
+    ///
+    /// Since the length is known in advance, the `Content-Length` header is set, and the body is
+    /// sent as is, regardless of the HTTP version. The connection can stay open afterwards. The
+    /// `MediaType` header is also updated, just like with `set_body`. `source` must yield exactly
+    /// `len` bytes.
+    pub fn set_sized_body_stream(&mut self, source: Box<Read + Send>, len: usize) {
+        self.set_stream(source, Some(len));
+    }
+
+    fn set_stream(&mut self, source: Box<Read + Send>, len: Option<usize>) {
+        let chunked = len.is_none() && self.status_line.http_version == Version::Http11;
+        self.headers.remove(&Header::TransferEncoding);
+        match len {
+            Some(len) => self.headers.add(Header::ContentLength, len.to_string()),
+            None => self.headers.remove(&Header::ContentLength),
+        }
+        if chunked {
+            self.headers
+                .add(Header::TransferEncoding, String::from("chunked"));
+        }
+        self.headers.add(
+            Header::ContentType,
+            String::from(self.content_type.as_str()),
+        );
+        self.body = None;
+        self.body_stream = Some(BodyStream {
+            source,
+            len,
+            chunked,
+            done: false,
+        });
+        if self.closes_connection() {
+            self.set_keep_alive(false);
+        }
+    }
+
+    /// Returns true if the `Response` has a streamed body, which was set using `set_body_stream`.
+    pub fn is_streaming(&self) -> bool {
+        self.body_stream.is_some()
+    }
+
+    // Returns true if the end of the body can only be signaled by closing the connection.
+    fn closes_connection(&self) -> bool {
+        match self.body_stream {
+            Some(ref stream) => !stream.chunked && stream.len.is_none(),
+            None => false,
+        }
+    }
+
+    /// Sets the media type of the body, which is sent via the `Content-Type` header.
+    pub fn set_content_type(&mut self, content_type: MediaType) {
+        self.content_type = content_type;
+        if self.body.is_some() || self.body_stream.is_some() {
+            self.headers
+                .add(Header::ContentType, String::from(content_type.as_str()));
+        }
+    }
+
+    fn write_body<T: Write>(&mut self, mut buf: T) -> Result<(), WriteError> {
         if let Some(ref body) = self.body {
             buf.write_all(body.raw())?;
         }
+        while !self.write_body_chunk(&mut buf, STREAM_CHUNK_MAX_LEN)? {}
         Ok(())
     }
 
-    /// Writes the content of the `Response` to the specified `buf`.
+    /// Writes the status line and the headers of the `Response` to the specified `buf`.
     ///
     /// # Errors
     /// Returns an error when the buffer is not large enough.
-    pub fn write_all<T: Write>(&self, mut buf: &mut T) -> Result<(), WriteError> {
+    pub fn write_head<T: Write>(&self, mut buf: &mut T) -> Result<(), WriteError> {
         self.status_line.write_all(&mut buf)?;
         self.headers.write_all(&mut buf)?;
+
+        Ok(())
+    }
+
+    /// Reads at most `max_len` bytes from the streamed body, and writes them to the specified
+    /// `buf`, as a chunk when the chunked transfer coding is used. Returns true if there's no
+    /// more streamed body to write, and false otherwise.
+    ///
+    /// This should be called after `write_head`, until it returns true. Responses without a
+    /// streamed body have nothing to write, so true is returned right away.
+    ///
+    /// # Errors
+    /// Returns an error when reading the body fails, or when the buffer is not large enough.
+    pub fn write_body_chunk<T: Write>(
+        &mut self,
+        mut buf: T,
+        max_len: usize,
+    ) -> Result<bool, WriteError> {
+        let stream = match self.body_stream {
+            Some(ref mut stream) if !stream.done => stream,
+            _ => return Ok(true),
+        };
+
+        let mut data = vec![0u8; max_len];
+        let len = stream.source.read(&mut data)?;
+        if len == 0 {
+            if max_len == 0 {
+                return Ok(false);
+            }
+            // We've reached the end of the body, so we write the last chunk, without any trailer.
+            if stream.chunked {
+                buf.write_all(b"0")?;
+                buf.write_all(&[CR, LF, CR, LF])?;
+            }
+            stream.done = true;
+            return Ok(true);
+        }
+
+        if stream.chunked {
+            buf.write_all(format!("{:x}", len).as_bytes())?;
+            buf.write_all(&[CR, LF])?;
+            buf.write_all(&data[..len])?;
+            buf.write_all(&[CR, LF])?;
+        } else {
+            buf.write_all(&data[..len])?;
+        }
+        Ok(false)
+    }
+
+    /// Writes the content of the `Response` to the specified `buf`. A streamed body is read
+    /// until its end.
+    ///
+    /// # Errors
+    /// Returns an error when the buffer is not large enough, or when reading a streamed body
+    /// fails.
+    pub fn write_all<T: Write>(&mut self, mut buf: &mut T) -> Result<(), WriteError> {
+        self.write_head(&mut buf)?;
         self.write_body(&mut buf)?;
 
         Ok(())
@@ -120,8 +313,8 @@ impl Response {
         self.status_line.status_code
     }
 
-    /// Returns the Body of the response. If the response does not have a body,
-    /// it returns None.
+    /// Returns the Body of the response. If the response does not have a body, or the body is
+    /// streamed, it returns None.
     pub fn body(&self) -> Option<Body> {
         self.body.clone()
     }
@@ -164,16 +357,137 @@ mod tests {
                 || response_buf.as_ref() == expected_response_2
         );
 
+        // Test the Connection header.
+        assert!(!response.keep_alive());
+        response.set_keep_alive(true);
+        assert!(response.keep_alive());
+        let mut response_buf = Vec::new();
+        assert!(response.write_all(&mut response_buf).is_ok());
+        let response_str = String::from_utf8(response_buf).unwrap();
+        assert!(response_str.contains("Connection: keep-alive\r\n"));
+
+        let mut response = Response::new(Version::Http11, StatusCode::NotFound);
+        assert!(response.keep_alive());
+        response.set_keep_alive(false);
+        assert!(!response.keep_alive());
+        let mut response_buf = Vec::new();
+        assert!(response.write_all(&mut response_buf).is_ok());
+        assert_eq!(response_buf, b"HTTP/1.1 404 \r\nConnection: close\r\n\r\n".to_vec());
+
+        // Test the Content-Type header.
+        let mut response = Response::new(Version::Http11, StatusCode::OK);
+        response.set_content_type(MediaType::ApplicationJson);
+        response.set_body(Body::new("{}"));
+        let mut response_buf = Vec::new();
+        assert!(response.write_all(&mut response_buf).is_ok());
+        let response_str = String::from_utf8(response_buf).unwrap();
+        assert!(response_str.contains("Content-Type: application/json\r\n"));
+        response.set_content_type(MediaType::PlainText);
+        let mut response_buf = Vec::new();
+        assert!(response.write_all(&mut response_buf).is_ok());
+        let response_str = String::from_utf8(response_buf).unwrap();
+        assert!(response_str.contains("Content-Type: text/plain\r\n"));
+
+        // Test the ETag header.
+        let mut response = Response::new(Version::Http11, StatusCode::NotModified);
+        response.set_etag(String::from("\"7\""));
+        let mut response_buf = Vec::new();
+        assert!(response.write_all(&mut response_buf).is_ok());
+        assert_eq!(response_buf, b"HTTP/1.1 304 \r\nETag: \"7\"\r\n\r\n".to_vec());
+
         // Test write failed.
         let mut response_buf: [u8; 1] = [0; 1];
         assert!(response.write_all(&mut response_buf.as_mut()).is_err());
     }
 
+    #[test]
+    fn test_streamed_body() {
+        use std::io::Cursor;
+
+        let body = "This is a streamed body";
+
+        let mut response = Response::new(Version::Http11, StatusCode::OK);
+        response.set_body(Body::new("This will be replaced"));
+        response.set_body_stream(Box::new(Cursor::new(body)));
+        response.set_keep_alive(true);
+        assert!(response.is_streaming());
+        assert!(response.keep_alive());
+        assert!(response.body().is_none());
+
+        let mut response_buf = Vec::new();
+        assert!(response.write_head(&mut response_buf).is_ok());
+        let head = String::from_utf8(response_buf.clone()).unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 \r\n"));
+        assert!(head.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(head.contains("Content-Type: text/plain\r\n"));
+        assert!(!head.contains("Content-Length"));
+        assert!(head.ends_with("\r\n\r\n"));
+
+        // The body is written in chunks of at most 10 bytes, followed by the last chunk.
+        response_buf.clear();
+        assert!(!response.write_body_chunk(&mut response_buf, 10).unwrap());
+        assert!(!response.write_body_chunk(&mut response_buf, 10).unwrap());
+        // A zero length read is not mistaken for the end of the body.
+        assert!(!response.write_body_chunk(&mut response_buf, 0).unwrap());
+        assert!(!response.write_body_chunk(&mut response_buf, 10).unwrap());
+        assert!(response.write_body_chunk(&mut response_buf, 10).unwrap());
+        let expected_body: &'static [u8] =
+            b"a\r\nThis is a \r\na\r\nstreamed b\r\n3\r\nody\r\n0\r\n\r\n";
+        assert_eq!(response_buf, expected_body);
+        // Nothing else gets written after the end of the body.
+        assert!(response.write_body_chunk(&mut response_buf, 10).unwrap());
+        assert_eq!(response_buf, expected_body);
+
+        // HTTP/1.0 clients get the body as is, followed by the end of the connection.
+        let mut response = Response::new(Version::Http10, StatusCode::OK);
+        response.set_body_stream(Box::new(Cursor::new(body)));
+        response.set_keep_alive(true);
+        assert!(!response.keep_alive());
+        let mut response_buf = Vec::new();
+        assert!(response.write_all(&mut response_buf).is_ok());
+        let response_str = String::from_utf8(response_buf).unwrap();
+        assert!(response_str.contains("Connection: close\r\n"));
+        assert!(!response_str.contains("Transfer-Encoding"));
+        assert!(response_str.ends_with("\r\n\r\nThis is a streamed body"));
+
+        // When the length is known, it is sent along, and the connection can stay open.
+        for version in [Version::Http10, Version::Http11].iter() {
+            let mut response = Response::new(*version, StatusCode::OK);
+            response.set_sized_body_stream(Box::new(Cursor::new(body)), body.len());
+            response.set_keep_alive(true);
+            assert!(response.is_streaming());
+            assert!(response.keep_alive());
+            let mut response_buf = Vec::new();
+            assert!(response.write_all(&mut response_buf).is_ok());
+            let response_str = String::from_utf8(response_buf).unwrap();
+            assert!(response_str.contains("Content-Length: 23\r\n"));
+            assert!(response_str.contains("Connection: keep-alive\r\n"));
+            assert!(!response_str.contains("Transfer-Encoding"));
+            assert!(response_str.ends_with("\r\n\r\nThis is a streamed body"));
+        }
+
+        // A regular body replaces a streamed one.
+        let mut response = Response::new(Version::Http11, StatusCode::OK);
+        response.set_body_stream(Box::new(Cursor::new(body)));
+        response.set_body(Body::new(body));
+        assert!(!response.is_streaming());
+        let mut response_buf = Vec::new();
+        assert!(response.write_all(&mut response_buf).is_ok());
+        let response_str = String::from_utf8(response_buf).unwrap();
+        assert!(response_str.contains("Content-Length: 23\r\n"));
+        assert!(!response_str.contains("Transfer-Encoding"));
+    }
+
     #[test]
     fn test_status_code() {
         assert_eq!(StatusCode::OK.raw(), b"200");
+        assert_eq!(StatusCode::NoContent.raw(), b"204");
+        assert_eq!(StatusCode::NotModified.raw(), b"304");
         assert_eq!(StatusCode::BadRequest.raw(), b"400");
+        assert_eq!(StatusCode::Unauthorized.raw(), b"401");
+        assert_eq!(StatusCode::Forbidden.raw(), b"403");
         assert_eq!(StatusCode::NotFound.raw(), b"404");
+        assert_eq!(StatusCode::PayloadTooLarge.raw(), b"413");
         assert_eq!(StatusCode::InternalServerError.raw(), b"500");
         assert_eq!(StatusCode::NotImplemented.raw(), b"501");
     }
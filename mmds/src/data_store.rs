@@ -1,27 +1,110 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-use json_patch::merge;
-use serde_json::Value;
+use std::sync::Arc;
+
+use json_patch::{self, merge, PatchError};
+use serde_json::{Map, Value};
+
+use token::TokenAuthority;
+
+/// The default maximum size, in bytes, of the serialized MMDS contents.
+pub const DEFAULT_DATA_STORE_LIMIT: usize = 51200;
+/// The path of the MMDS section which guests can write to.
+pub const GUEST_DATA_PREFIX: &str = "/guest";
+
+/// Called every time the host updates the MMDS contents.
+pub type UpdateListener = Arc<Fn() + Send + Sync>;
 
 /// The Mmds is the Microvm Metadata Service represented as an untyped json.
+///
+/// The contents are shared with the snapshots handed out by `get_value_snapshot`, and updates
+/// replace them as a whole, so a snapshot never observes a partial update.
 #[derive(Clone)]
 pub struct Mmds {
-    data_store: Value,
+    data_store: Arc<Value>,
+    data_store_limit: usize,
+    // Incremented every time the host updates the contents, so guests can tell whether
+    // something changed since their last request.
+    revision: u64,
+    update_listeners: Vec<UpdateListener>,
+    // The section written by the guest, which is kept apart from the contents provided by the
+    // host, so that it survives updates of the latter. It's always a JSON object.
+    guest_data: Arc<Value>,
+    // The maximum size of the serialized guest section. Guest writes are disabled when 0.
+    guest_data_limit: usize,
     is_initialized: bool,
+    token_authority: TokenAuthority,
+}
+
+/// A value from the MMDS contents, as they were when the snapshot was taken. Later updates of
+/// the contents do not affect it, and it can be read without holding the MMDS lock.
+#[derive(Clone)]
+pub struct ValueSnapshot {
+    root: Arc<Value>,
+    // The JSON pointer of the value within root.
+    pointer: String,
+}
+
+impl ValueSnapshot {
+    /// Returns the value captured by the snapshot.
+    pub fn value(&self) -> &Value {
+        // The unwrap() is safe because snapshots are only taken of existing values, and root
+        // is never modified.
+        self.root.pointer(&self.pointer).unwrap()
+    }
 }
 
 #[derive(Debug, PartialEq)]
 pub enum Error {
+    /// The serialized contents would exceed the configured size limit.
+    DataStoreLimitExceeded,
     NotFound,
-    UnsupportedValueType,
+}
+
+/// Errors associated with applying a JSON Patch (RFC 6902) to the MMDS contents. Operations are
+/// identified by their index within the patch.
+#[derive(Debug, PartialEq)]
+pub enum JsonPatchError {
+    /// The patch is not an array of operations.
+    NotAnArray,
+    /// The operation is malformed, e.g. it has an unknown `op` or lacks a member.
+    InvalidOperation(usize, String),
+    /// The operation refers to a location which does not exist.
+    InvalidPointer(usize),
+    /// The value found at `path` by a `test` operation differs from the expected one.
+    TestFailed {
+        index: usize,
+        path: String,
+        expected: Value,
+        actual: Value,
+    },
+    /// The serialized contents would exceed the configured size limit.
+    DataStoreLimitExceeded,
+}
+
+/// Errors associated with guest writes to the MMDS.
+#[derive(Debug, PartialEq)]
+pub enum GuestDataError {
+    /// The host did not allow guest writes.
+    Disabled,
+    /// The path does not lead to a dictionary within the guest section.
+    InvalidPath,
+    /// The serialized guest section would exceed the configured size limit.
+    LimitExceeded,
 }
 
 impl Default for Mmds {
     fn default() -> Self {
         Mmds {
-            data_store: Value::default(),
+            data_store: Arc::new(Value::default()),
+            data_store_limit: DEFAULT_DATA_STORE_LIMIT,
+            revision: 0,
+            update_listeners: Vec::new(),
+            guest_data: Arc::new(Value::Object(Map::new())),
+            guest_data_limit: 0,
             is_initialized: false,
+            token_authority: TokenAuthority::default(),
         }
     }
 }
@@ -35,15 +118,210 @@ impl Mmds {
         return self.is_initialized;
     }
 
-    pub fn put_data(&mut self, data: Value) {
-        // TODO: we should add a data validator and only accept Strings, arrays & dictionaries
-        // https://github.com/firecracker-microvm/firecracker/issues/401
-        self.data_store = data;
+    /// Returns the authority which generates and validates the session tokens presented by
+    /// guest requests.
+    pub fn token_authority(&self) -> &TokenAuthority {
+        &self.token_authority
+    }
+
+    /// Replaces the token authority. Tokens generated by the previous authority are no longer
+    /// valid, unless the new one uses the same key.
+    pub fn set_token_authority(&mut self, token_authority: TokenAuthority) {
+        self.token_authority = token_authority;
+    }
+
+    /// Returns the maximum size, in bytes, of the serialized MMDS contents.
+    pub fn data_store_limit(&self) -> usize {
+        self.data_store_limit
+    }
+
+    /// Sets the maximum size, in bytes, of the serialized MMDS contents. The limit is enforced
+    /// on subsequent updates; the current contents are kept even if they exceed it.
+    pub fn set_data_store_limit(&mut self, data_store_limit: usize) {
+        self.data_store_limit = data_store_limit;
+    }
+
+    /// Returns the revision of the MMDS contents, which changes every time the host updates
+    /// them.
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Registers a `listener` to be called after every update of the MMDS contents by the host.
+    /// Listeners are called while the MMDS is locked, so they must not try to access it.
+    pub fn add_update_listener(&mut self, listener: UpdateListener) {
+        self.update_listeners.push(listener);
+    }
+
+    // Bumps the revision, and lets the listeners know about the update.
+    fn commit_update(&mut self) {
+        self.revision = self.revision.wrapping_add(1);
+        for listener in &self.update_listeners {
+            listener();
+        }
+    }
+
+    fn check_data_store_limit(&self, data: &Value) -> Result<(), Error> {
+        if data.to_string().len() > self.data_store_limit {
+            return Err(Error::DataStoreLimitExceeded);
+        }
+        Ok(())
+    }
+
+    /// Replaces the MMDS contents. Fails, leaving the contents unchanged, when the serialized
+    /// `data` exceeds the data store limit.
+    pub fn put_data(&mut self, data: Value) -> Result<(), Error> {
+        self.check_data_store_limit(&data)?;
+        self.data_store = Arc::new(data);
         self.is_initialized = true;
+        self.commit_update();
+        Ok(())
+    }
+
+    /// Merges `patch_data` into the MMDS contents, as specified by RFC 7396. Fails, leaving the
+    /// contents unchanged, when the serialized result exceeds the data store limit.
+    pub fn patch_data(&mut self, patch_data: Value) -> Result<(), Error> {
+        let mut data_store = (*self.data_store).clone();
+        merge(&mut data_store, &patch_data);
+        self.check_data_store_limit(&data_store)?;
+        self.data_store = Arc::new(data_store);
+        self.commit_update();
+        Ok(())
+    }
+
+    /// Applies the operations of a JSON Patch (RFC 6902) to the MMDS contents, in order. The
+    /// patch is atomic: when any operation fails, or when the serialized result exceeds the data
+    /// store limit, the contents are left unchanged.
+    pub fn apply_json_patch(&mut self, patch: Value) -> Result<(), JsonPatchError> {
+        let operations = match patch {
+            Value::Array(operations) => operations,
+            _ => return Err(JsonPatchError::NotAnArray),
+        };
+
+        let mut data_store = (*self.data_store).clone();
+        for (index, operation) in operations.into_iter().enumerate() {
+            Mmds::apply_json_patch_operation(&mut data_store, index, operation)?;
+        }
+        self.check_data_store_limit(&data_store)
+            .map_err(|_| JsonPatchError::DataStoreLimitExceeded)?;
+        self.data_store = Arc::new(data_store);
+        self.commit_update();
+        Ok(())
+    }
+
+    // Applies a single operation, so that failures can be tied to it. The members of the
+    // operation are also looked up here, as json_patch does not report them.
+    fn apply_json_patch_operation(
+        data_store: &mut Value,
+        index: usize,
+        operation: Value,
+    ) -> Result<(), JsonPatchError> {
+        let patch = json_patch::from_value(Value::Array(vec![operation.clone()]))
+            .map_err(|e| JsonPatchError::InvalidOperation(index, e.to_string()))?;
+
+        match json_patch::patch(data_store, &patch) {
+            Ok(()) => Ok(()),
+            Err(PatchError::InvalidPointer) => Err(JsonPatchError::InvalidPointer(index)),
+            Err(PatchError::TestFailed) => {
+                let path = operation["path"].as_str().unwrap_or_default().to_string();
+                let actual = data_store.pointer(&path).cloned().unwrap_or(Value::Null);
+                Err(JsonPatchError::TestFailed {
+                    index,
+                    path,
+                    expected: operation["value"].clone(),
+                    actual,
+                })
+            }
+        }
+    }
+
+    /// Returns the maximum size, in bytes, of the serialized guest section.
+    pub fn guest_data_limit(&self) -> usize {
+        self.guest_data_limit
+    }
+
+    /// Sets the maximum size, in bytes, of the serialized guest section. A limit of 0 disables
+    /// guest writes. As with the data store limit, the current contents are kept.
+    pub fn set_guest_data_limit(&mut self, guest_data_limit: usize) {
+        self.guest_data_limit = guest_data_limit;
+    }
+
+    // Returns the location of `path` relative to the guest section, or None if `path` is outside
+    // of the guest section.
+    fn guest_data_path(path: &str) -> Option<&str> {
+        if !path.starts_with(GUEST_DATA_PREFIX) {
+            return None;
+        }
+        let rest = &path[GUEST_DATA_PREFIX.len()..];
+        if rest.is_empty() || rest.starts_with('/') {
+            Some(rest)
+        } else {
+            None
+        }
+    }
+
+    /// Returns true if `path` belongs to the guest section.
+    pub fn is_guest_data_path(path: &str) -> bool {
+        Mmds::guest_data_path(path).is_some()
+    }
+
+    // Applies `update` to the guest value found at `path` (relative to the guest section), after
+    // creating the missing dictionaries along the way. The guest section is left unchanged when
+    // the update fails, or makes it exceed the guest data limit.
+    fn update_guest_data<F>(&mut self, path: &str, update: F) -> Result<(), GuestDataError>
+    where
+        F: FnOnce(&mut Value),
+    {
+        if self.guest_data_limit == 0 {
+            return Err(GuestDataError::Disabled);
+        }
+
+        let mut guest_data = (*self.guest_data).clone();
+        {
+            let mut target = &mut guest_data;
+            for key in path.split('/').filter(|key| !key.is_empty()) {
+                target = match *target {
+                    Value::Object(ref mut map) => map
+                        .entry(key.to_string())
+                        .or_insert_with(|| Value::Object(Map::new())),
+                    _ => return Err(GuestDataError::InvalidPath),
+                };
+            }
+            update(target);
+        }
+
+        if !guest_data.is_object() {
+            return Err(GuestDataError::InvalidPath);
+        }
+        if guest_data.to_string().len() > self.guest_data_limit {
+            return Err(GuestDataError::LimitExceeded);
+        }
+        self.guest_data = Arc::new(guest_data);
+        Ok(())
+    }
+
+    /// Replaces the value found at `path`, which has to be within the guest section, with
+    /// `data`.
+    pub fn put_guest_data(&mut self, path: &str, data: Value) -> Result<(), GuestDataError> {
+        let path = Mmds::guest_data_path(path).ok_or(GuestDataError::InvalidPath)?;
+        self.update_guest_data(path, |target| *target = data)
+    }
+
+    /// Merges `patch_data` into the value found at `path`, which has to be within the guest
+    /// section, as specified by RFC 7396.
+    pub fn patch_guest_data(&mut self, path: &str, patch_data: Value) -> Result<(), GuestDataError> {
+        let path = Mmds::guest_data_path(path).ok_or(GuestDataError::InvalidPath)?;
+        self.update_guest_data(path, |target| merge(target, &patch_data))
+    }
+
+    /// Returns the JSON representation of the guest section.
+    pub fn get_guest_data_str(&self) -> String {
+        self.guest_data.to_string()
     }
 
-    pub fn patch_data(&mut self, patch_data: Value) {
-        merge(&mut self.data_store, &patch_data);
+    /// Removes everything written by the guest.
+    pub fn clear_guest_data(&mut self) {
+        self.guest_data = Arc::new(Value::Object(Map::new()));
     }
 
     pub fn get_data_str(&self) -> String {
@@ -53,85 +331,112 @@ impl Mmds {
         return self.data_store.to_string();
     }
 
-    // Helper function for getting all the keys from Value::Object.
-    // Returns a Vec<String> with all keys. If the key corresponds to a
-    // dictionary, a "/" is appended to the key name.
+    // Helper function for converting a scalar Value to String by following the IMDS specs.
+    // Strings are returned without quotes, while numbers and booleans are returned as they
+    // appear in the JSON document.
+    fn scalar_as_string(val: &Value) -> String {
+        match val.as_str() {
+            Some(value) => value.to_string(),
+            None => val.to_string(),
+        }
+    }
+
+    // Helper function for getting all the keys from Value::Object, or all the indices from
+    // Value::Array. Returns a Vec<String> with all keys. If the key corresponds to a
+    // dictionary or an array, a "/" is appended to the key name.
     // If the `dict` is Value::Null, Error::NotFound is thrown.
-    // If the `dict` is not a dictionary, a Vec with the value corresponding to
+    // If the `dict` is neither a dictionary nor an array, a Vec with the value corresponding to
     // the key is returned.
     fn get_keys(dict: &Value) -> Result<Vec<String>, Error> {
-        if dict.is_null() {
-            return Err(Error::NotFound);
-        }
-
-        let mut ret = Vec::new();
-        match dict.as_object() {
-            Some(map) => {
-                // When the object is a map, push all the keys in the Vec.
-                for key in map.keys() {
-                    let mut key = key.clone();
-                    if dict[&key].is_object() {
-                        key.push_str("/");
-                    }
-
-                    ret.push(key);
-                }
-                return Ok(ret);
-            }
-            None => {
-                // When the object is not a map, return the value.
-                match dict.as_str() {
-                    Some(val) => {
-                        ret.push(val.to_string());
-                        return Ok(ret);
-                    }
-                    None => return Err(Error::UnsupportedValueType),
-                };
+        let format_key = |key: String, val: &Value| {
+            if val.is_object() || val.is_array() {
+                format!("{}/", key)
+            } else {
+                key
             }
         };
+
+        match dict {
+            Value::Null => Err(Error::NotFound),
+            Value::Object(map) => Ok(map
+                .iter()
+                .map(|(key, val)| format_key(key.clone(), val))
+                .collect()),
+            Value::Array(vec) => Ok(vec
+                .iter()
+                .enumerate()
+                .map(|(index, val)| format_key(index.to_string(), val))
+                .collect()),
+            _ => Ok(vec![Mmds::scalar_as_string(dict)]),
+        }
     }
 
     // Helper function for converting a Value to String by following the IMDS specs.
-    // The only supported Value is String. Throws UnsupportedValueType when the type of the
-    // Value is not String.
+    // Dictionaries and arrays are represented by an empty string.
     fn get_value_as_string(val: &Value) -> Result<String, Error> {
-        if val.is_null() {
-            return Err(Error::NotFound);
+        match val {
+            Value::Null => Err(Error::NotFound),
+            Value::Object(_) | Value::Array(_) => Ok(String::new()),
+            _ => Ok(Mmds::scalar_as_string(val)),
         }
+    }
 
-        if val.is_object() {
-            return Ok(String::new());
+    // Returns the contents holding the value found at `path`, along with the JSON pointer of the
+    // value within them. The path is a sequence of keys and array indices separated by "/". A
+    // trailing "/" is ignored. Paths within the guest section shadow the host provided contents.
+    fn locate<'a>(&self, path: &'a str) -> (&Arc<Value>, &'a str) {
+        let (root, path) = match Mmds::guest_data_path(path) {
+            Some(guest_path) => (&self.guest_data, guest_path),
+            None => (&self.data_store, path),
+        };
+
+        // The pointer function splits the input by "/". With a trailing "/", pointer does not
+        // know how to get the object.
+        if path.ends_with('/') {
+            (root, &path[..(path.len() - 1)])
+        } else {
+            (root, path)
         }
+    }
 
-        match val.as_str() {
-            Some(value) => Ok(value.to_string()),
-            None => Err(Error::UnsupportedValueType),
+    // Returns the value found at `path`, or None if it's missing or null.
+    fn get_subtree(&self, path: &str) -> Option<&Value> {
+        let (root, pointer) = self.locate(path);
+        match root.pointer(pointer) {
+            Some(Value::Null) | None => None,
+            Some(val) => Some(val),
         }
     }
 
+    /// Returns a snapshot of the value found at `path`, which can be read after the MMDS lock
+    /// is released. A trailing "/" in the path is ignored.
+    ///
+    /// When the path is not found, or the value is null, a NotFound error is returned.
+    pub fn get_value_snapshot(&self, path: &str) -> Result<ValueSnapshot, Error> {
+        self.get_subtree(path).ok_or(Error::NotFound)?;
+        let (root, pointer) = self.locate(path);
+        Ok(ValueSnapshot {
+            root: root.clone(),
+            pointer: pointer.to_string(),
+        })
+    }
+
     /// This function replicates the behavior of the Instance Metadata Service
     /// https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/ec2-instance-metadata.html
     /// When the path ends with / there are two cases:
-    /// 1. For a (key, value) pair where the value is a dictionary, it will return all the keys
-    /// in the dictionary.
+    /// 1. For a (key, value) pair where the value is a dictionary or an array, it will return
+    /// all the keys in the dictionary, or all the indices in the array.
     /// 2. For a (key, value) pair where the value is a simple type (bool, string, number),
     /// it will return the value.
     ///
     /// When the path does not end with / there are also two cases to cover:
-    /// 1. The value corresponding to that path is a dictionary, the function returns
+    /// 1. The value corresponding to that path is a dictionary or an array, the function returns
     /// an empty string.
     /// 2. The value corresponding to that path is a simple type, the function returns the value.
     ///
-    /// When the path is not found, a NotFound error is returned.
+    /// When the path is not found, or the value is null, a NotFound error is returned.
     pub fn get_value(&self, path: String) -> Result<Vec<String>, Error> {
-        // The pointer function splits the input by "/". With a trailing "/", pointer does not
-        // know how to get the object.
-        let value = match path.ends_with('/') {
-            true => self.data_store.pointer(&path.as_str()[..(path.len() - 1)]),
-            false => self.data_store.pointer(path.as_str()),
-        };
-
-        match value {
+        match self.get_subtree(&path) {
             Some(val) => {
                 // If path ends with /, return all keys in Value::Object
                 if path.ends_with("/") {
@@ -146,6 +451,16 @@ impl Mmds {
             None => return Err(Error::NotFound),
         }
     }
+
+    /// Returns the JSON representation of the value found at `path`. A trailing "/" in the path
+    /// is ignored.
+    ///
+    /// When the path is not found, or the value is null, a NotFound error is returned.
+    pub fn get_value_json(&self, path: String) -> Result<String, Error> {
+        self.get_subtree(&path)
+            .map(Value::to_string)
+            .ok_or(Error::NotFound)
+    }
 }
 
 #[cfg(test)]
@@ -153,6 +468,8 @@ mod tests {
     use super::*;
     use serde_json;
 
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
     #[test]
     fn test_mmds() {
         let mut mmds = Mmds::default();
@@ -160,18 +477,227 @@ mod tests {
 
         let mut mmds_json = "{\"meta-data\":{\"iam\":\"dummy\"},\"user-data\":\"1522850095\"}";
 
-        mmds.put_data(serde_json::from_str(mmds_json).unwrap());
+        assert!(mmds.put_data(serde_json::from_str(mmds_json).unwrap()).is_ok());
         assert_eq!(mmds.is_initialized(), true);
 
         assert_eq!(mmds.get_data_str(), mmds_json);
 
         // update the user-data field add test that patch works as expected
         let patch_json = "{\"user-data\":\"10\"}";
-        mmds.patch_data(serde_json::from_str(patch_json).unwrap());
+        assert!(mmds.patch_data(serde_json::from_str(patch_json).unwrap()).is_ok());
         mmds_json = "{\"meta-data\":{\"iam\":\"dummy\"},\"user-data\":\"10\"}";
         assert_eq!(mmds.get_data_str(), mmds_json);
     }
 
+    #[test]
+    fn test_revision() {
+        let mut mmds = Mmds::default();
+        let updates = Arc::new(AtomicUsize::new(0));
+        let updates_clone = updates.clone();
+        mmds.add_update_listener(Arc::new(move || {
+            updates_clone.fetch_add(1, Ordering::SeqCst);
+        }));
+        assert_eq!(mmds.revision(), 0);
+
+        mmds.put_data(serde_json::from_str(r#"{"a": 1}"#).unwrap())
+            .unwrap();
+        assert_eq!(mmds.revision(), 1);
+        mmds.patch_data(serde_json::from_str(r#"{"b": 2}"#).unwrap())
+            .unwrap();
+        assert_eq!(mmds.revision(), 2);
+        assert_eq!(updates.load(Ordering::SeqCst), 2);
+
+        // Failed updates and guest writes leave the revision unchanged.
+        mmds.set_data_store_limit(1);
+        assert!(
+            mmds.patch_data(serde_json::from_str(r#"{"c": 3}"#).unwrap())
+                .is_err()
+        );
+        mmds.set_guest_data_limit(100);
+        assert!(mmds.put_guest_data("/guest/a", Value::from(1)).is_ok());
+        assert_eq!(mmds.revision(), 2);
+        assert_eq!(updates.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_guest_data() {
+        let mut mmds = Mmds::default();
+        mmds.put_data(serde_json::from_str(r#"{"guest": "host value"}"#).unwrap())
+            .unwrap();
+        assert_eq!(mmds.guest_data_limit(), 0);
+        assert_eq!(
+            mmds.put_guest_data("/guest/status", Value::from("ready")),
+            Err(GuestDataError::Disabled)
+        );
+
+        mmds.set_guest_data_limit(50);
+        assert!(Mmds::is_guest_data_path("/guest"));
+        assert!(Mmds::is_guest_data_path("/guest/a/"));
+        assert!(!Mmds::is_guest_data_path("/guests"));
+        assert_eq!(
+            mmds.put_guest_data("/status", Value::from("ready")),
+            Err(GuestDataError::InvalidPath)
+        );
+
+        // Missing dictionaries are created along the way.
+        assert!(
+            mmds.put_guest_data("/guest/health/disk", Value::from("ok"))
+                .is_ok()
+        );
+        assert!(
+            mmds.patch_guest_data("/guest/", serde_json::from_str(r#"{"status": 1}"#).unwrap())
+                .is_ok()
+        );
+        assert_eq!(
+            mmds.get_guest_data_str(),
+            r#"{"health":{"disk":"ok"},"status":1}"#
+        );
+
+        // The guest section shadows the host provided contents.
+        assert_eq!(
+            mmds.get_value("/guest/".to_string()).unwrap(),
+            vec!["health/", "status"]
+        );
+        assert_eq!(
+            mmds.get_value_json("/guest/health".to_string()).unwrap(),
+            r#"{"disk":"ok"}"#
+        );
+
+        // Failed updates leave the guest section unchanged.
+        assert_eq!(
+            mmds.put_guest_data("/guest/status/code", Value::from(1)),
+            Err(GuestDataError::InvalidPath)
+        );
+        assert_eq!(
+            mmds.put_guest_data("/guest", Value::from(1)),
+            Err(GuestDataError::InvalidPath)
+        );
+        assert_eq!(
+            mmds.put_guest_data("/guest/log", Value::from("x".repeat(20))),
+            Err(GuestDataError::LimitExceeded)
+        );
+        assert_eq!(
+            mmds.get_guest_data_str(),
+            r#"{"health":{"disk":"ok"},"status":1}"#
+        );
+
+        mmds.clear_guest_data();
+        assert_eq!(mmds.get_guest_data_str(), "{}");
+        assert_eq!(mmds.get_data_str(), r#"{"guest":"host value"}"#);
+    }
+
+    #[test]
+    fn test_data_store_limit() {
+        let mut mmds = Mmds::default();
+        assert_eq!(mmds.data_store_limit(), DEFAULT_DATA_STORE_LIMIT);
+
+        let mmds_json = "{\"user-data\":\"1522850095\"}";
+        mmds.set_data_store_limit(mmds_json.len() - 1);
+        assert_eq!(
+            mmds.put_data(serde_json::from_str(mmds_json).unwrap()),
+            Err(Error::DataStoreLimitExceeded)
+        );
+        assert_eq!(mmds.is_initialized(), false);
+        assert_eq!(mmds.get_data_str(), "{}");
+
+        mmds.set_data_store_limit(mmds_json.len());
+        assert!(mmds.put_data(serde_json::from_str(mmds_json).unwrap()).is_ok());
+        assert_eq!(mmds.get_data_str(), mmds_json);
+
+        // A patch which would grow the contents past the limit is not applied at all.
+        assert_eq!(
+            mmds.patch_data(serde_json::from_str("{\"user-data\":\"15228500950\"}").unwrap()),
+            Err(Error::DataStoreLimitExceeded)
+        );
+        assert_eq!(mmds.get_data_str(), mmds_json);
+
+        // A patch which shrinks the contents is applied.
+        assert!(
+            mmds.patch_data(serde_json::from_str("{\"user-data\":\"1\"}").unwrap())
+                .is_ok()
+        );
+        assert_eq!(mmds.get_data_str(), "{\"user-data\":\"1\"}");
+    }
+
+    #[test]
+    fn test_json_patch() {
+        let mut mmds = Mmds::default();
+        mmds.put_data(serde_json::from_str(r#"{"version": 1, "hosts": ["a", "b", "c"]}"#).unwrap())
+            .unwrap();
+        assert_eq!(mmds.revision(), 1);
+
+        assert!(
+            mmds.apply_json_patch(
+                serde_json::from_str(
+                    r#"[
+                        {"op": "test", "path": "/version", "value": 1},
+                        {"op": "replace", "path": "/version", "value": 2},
+                        {"op": "remove", "path": "/hosts/1"},
+                        {"op": "add", "path": "/hosts/-", "value": "d"},
+                        {"op": "copy", "from": "/hosts", "path": "/backup"},
+                        {"op": "move", "from": "/backup", "path": "/old"}
+                    ]"#
+                ).unwrap()
+            ).is_ok()
+        );
+        assert_eq!(
+            mmds.get_data_str(),
+            r#"{"hosts":["a","c","d"],"old":["a","c","d"],"version":2}"#
+        );
+        assert_eq!(mmds.revision(), 2);
+
+        // Failures leave the contents and the revision unchanged, even when earlier operations
+        // succeeded.
+        let data = mmds.get_data_str();
+        assert_eq!(
+            mmds.apply_json_patch(serde_json::from_str(r#"{"version": 3}"#).unwrap()),
+            Err(JsonPatchError::NotAnArray)
+        );
+        match mmds.apply_json_patch(
+            serde_json::from_str(
+                r#"[{"op": "remove", "path": "/old"}, {"op": "increment", "path": "/version"}]"#,
+            ).unwrap(),
+        ) {
+            Err(JsonPatchError::InvalidOperation(1, _)) => (),
+            _ => assert!(false),
+        }
+        assert_eq!(
+            mmds.apply_json_patch(
+                serde_json::from_str(
+                    r#"[{"op": "remove", "path": "/old"}, {"op": "remove", "path": "/hosts/5"}]"#
+                ).unwrap()
+            ),
+            Err(JsonPatchError::InvalidPointer(1))
+        );
+        assert_eq!(
+            mmds.apply_json_patch(
+                serde_json::from_str(
+                    r#"[
+                        {"op": "remove", "path": "/old"},
+                        {"op": "test", "path": "/version", "value": 1},
+                        {"op": "replace", "path": "/version", "value": 3}
+                    ]"#
+                ).unwrap()
+            ),
+            Err(JsonPatchError::TestFailed {
+                index: 1,
+                path: "/version".to_string(),
+                expected: Value::from(1),
+                actual: Value::from(2),
+            })
+        );
+        mmds.set_data_store_limit(data.len());
+        assert_eq!(
+            mmds.apply_json_patch(
+                serde_json::from_str(r#"[{"op": "add", "path": "/extra", "value": true}]"#)
+                    .unwrap()
+            ),
+            Err(JsonPatchError::DataStoreLimitExceeded)
+        );
+        assert_eq!(mmds.get_data_str(), data);
+        assert_eq!(mmds.revision(), 2);
+    }
+
     #[test]
     fn test_get_value() {
         let mut mmds = Mmds::default();
@@ -191,7 +717,7 @@ mod tests {
         }"#;
 
         let data_store: Value = serde_json::from_str(data).unwrap();
-        mmds.put_data(data_store);
+        mmds.put_data(data_store).unwrap();
 
         // Test invalid path.
         match mmds.get_value("/invalid_path".to_string()) {
@@ -245,7 +771,7 @@ mod tests {
         }"#;
 
         let data_store: Value = serde_json::from_str(data).unwrap();
-        mmds.put_data(data_store);
+        mmds.put_data(data_store).unwrap();
 
         // Test path does NOT end with /; Value is a String.
         match mmds.get_value("/phones/0".to_string()) {
@@ -255,28 +781,102 @@ mod tests {
     }
 
     #[test]
-    fn test_invalid_types() {
+    fn test_all_types() {
         let mut mmds = Mmds::default();
         let data = r#"{
             "name": {
                 "first": "John",
                 "second": "Doe"
             },
-            "age": 43
+            "age": 43,
+            "height": 1.85,
+            "active": true,
+            "nickname": null,
+            "tags": ["a", "b"],
+            "addresses": [
+                {"city": "Iasi"},
+                [1, 2]
+            ]
         }"#;
 
         let data_store: Value = serde_json::from_str(data).unwrap();
-        // TODO: This should fail; we should only accept String types
-        mmds.put_data(data_store);
-
-        match mmds.get_value("/age".to_string()) {
-            Ok(_) => assert!(false),
-            Err(e) => assert_eq!(e, Error::UnsupportedValueType),
-        };
+        mmds.put_data(data_store).unwrap();
+
+        assert_eq!(mmds.get_value("/age".to_string()).unwrap(), vec!["43"]);
+        assert_eq!(mmds.get_value("/age/".to_string()).unwrap(), vec!["43"]);
+        assert_eq!(mmds.get_value("/height".to_string()).unwrap(), vec!["1.85"]);
+        assert_eq!(mmds.get_value("/active".to_string()).unwrap(), vec!["true"]);
+        assert_eq!(
+            mmds.get_value("/nickname".to_string()).unwrap_err(),
+            Error::NotFound
+        );
+
+        // Arrays are listed by index, like dictionaries are listed by key.
+        assert_eq!(mmds.get_value("/tags".to_string()).unwrap(), vec![""]);
+        assert_eq!(
+            mmds.get_value("/tags/".to_string()).unwrap(),
+            vec!["0", "1"]
+        );
+        assert_eq!(mmds.get_value("/tags/1".to_string()).unwrap(), vec!["b"]);
+        assert_eq!(
+            mmds.get_value("/addresses/".to_string()).unwrap(),
+            vec!["0/", "1/"]
+        );
+        assert_eq!(
+            mmds.get_value("/addresses/0/".to_string()).unwrap(),
+            vec!["city"]
+        );
+        assert_eq!(
+            mmds.get_value("/addresses/0/city".to_string()).unwrap(),
+            vec!["Iasi"]
+        );
+        assert_eq!(
+            mmds.get_value("/addresses/1/1".to_string()).unwrap(),
+            vec!["2"]
+        );
+        assert_eq!(
+            mmds.get_value("/".to_string()).unwrap(),
+            vec![
+                "active",
+                "addresses/",
+                "age",
+                "height",
+                "name/",
+                "nickname",
+                "tags/"
+            ]
+        );
+    }
 
-        match mmds.get_value("/age/".to_string()) {
-            Ok(_) => assert!(false),
-            Err(e) => assert_eq!(e, Error::UnsupportedValueType),
-        };
+    #[test]
+    fn test_get_value_json() {
+        let mut mmds = Mmds::default();
+        assert_eq!(
+            mmds.get_value_json("/".to_string()).unwrap_err(),
+            Error::NotFound
+        );
+
+        let data = r#"{"age":43,"name":{"first":"John","second":"Doe"},"tags":["a","b"]}"#;
+        mmds.put_data(serde_json::from_str(data).unwrap()).unwrap();
+
+        assert_eq!(mmds.get_value_json("".to_string()).unwrap(), data);
+        assert_eq!(mmds.get_value_json("/".to_string()).unwrap(), data);
+        assert_eq!(
+            mmds.get_value_json("/name/".to_string()).unwrap(),
+            r#"{"first":"John","second":"Doe"}"#
+        );
+        assert_eq!(
+            mmds.get_value_json("/name/first".to_string()).unwrap(),
+            r#""John""#
+        );
+        assert_eq!(mmds.get_value_json("/age".to_string()).unwrap(), "43");
+        assert_eq!(
+            mmds.get_value_json("/tags".to_string()).unwrap(),
+            r#"["a","b"]"#
+        );
+        assert_eq!(
+            mmds.get_value_json("/invalid".to_string()).unwrap_err(),
+            Error::NotFound
+        );
     }
 }
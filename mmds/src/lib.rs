@@ -1,25 +1,52 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+extern crate hmac;
 extern crate json_patch;
 #[macro_use]
 extern crate lazy_static;
+extern crate libc;
 extern crate serde_json;
+extern crate sha2;
 
+extern crate logger;
 extern crate micro_http;
 
 pub mod data_store;
+pub mod reader;
+pub mod stores;
+pub mod token;
 
-use std::sync::{Arc, Mutex};
+use std::sync::Mutex;
+use std::time::Duration;
 
-use data_store::{Error as MmdsError, Mmds};
-use micro_http::{Body, Request, RequestError, Response, StatusCode, Version};
+use data_store::{GuestDataError, Mmds, ValueSnapshot, GUEST_DATA_PREFIX};
+use logger::{Metric, METRICS};
+use micro_http::{
+    Body, MediaType, Method, Request, RequestError, Response, StatusCode, Version,
+};
+use reader::{json_len, JsonReader, StrReader};
+use token::{TokenMode, TOKEN_HEADER, TOKEN_PATH, TOKEN_TTL_HEADER};
 
-lazy_static! {
-    // A static reference to a global Mmds instance. We currently use this for ease of access during
-    // prototyping. We'll consider something like passing Arc<Mutex<Mmds>> references to the
-    // appropriate threads in the future.
-    pub static ref MMDS: Arc<Mutex<Mmds>> = Arc::new(Mutex::new(Mmds::default()));
+/// The header used by conditional GET requests to set how long they wait for the MMDS contents
+/// to change, in seconds.
+pub const WAIT_HEADER: &str = "X-metadata-wait-seconds";
+/// How long conditional GET requests wait for the MMDS contents to change by default.
+pub const DEFAULT_WAIT_SECONDS: u64 = 60;
+/// The longest time a conditional GET request can wait for the MMDS contents to change.
+pub const MAX_WAIT_SECONDS: u64 = 300;
+/// Successful responses to GET requests with bodies larger than this many bytes are streamed,
+/// so the network stack can send them a piece at a time. The body is read from a snapshot of
+/// the MMDS contents as it is being sent, instead of being serialized up front.
+pub const STREAMED_BODY_THRESHOLD: usize = 16384;
+
+/// Describes a guest request which waits for the host to update the MMDS contents.
+#[derive(Debug, PartialEq)]
+pub struct LongPoll {
+    /// The revision of the MMDS contents already known by the guest.
+    pub revision: u64,
+    /// How long the request waits for the contents to change.
+    pub timeout: Duration,
 }
 
 fn build_response(http_version: Version, status_code: StatusCode, body: Body) -> Response {
@@ -28,78 +55,365 @@ fn build_response(http_version: Version, status_code: StatusCode, body: Body) ->
     response
 }
 
-pub fn parse_request(request_bytes: &[u8]) -> Response {
-    let request = Request::try_from(request_bytes);
-    match request {
+/// Parses the HTTP request in `request_bytes`, and builds the response using the contents of
+/// `mmds`.
+pub fn parse_request(mmds: &Mutex<Mmds>, request_bytes: &[u8]) -> Response {
+    match Request::try_from(request_bytes) {
         Ok(request) => {
-            let uri = request.uri().get_abs_path();
-            if uri.len() == 0 {
-                return build_response(
-                    request.http_version(),
-                    StatusCode::BadRequest,
-                    Body::new("Invalid URI.".to_string()),
-                );
-            }
-
-            // The lock can be held by one thread only, so it is safe to unwrap.
-            // If another thread poisoned the lock, we abort the execution.
-            let response = MMDS
-                .lock()
-                .expect("Failed to build MMDS response due to poisoned lock")
-                .get_value(uri.to_string());
-            match response {
-                Ok(response) => {
-                    let response_body = response.join("\n");
-                    build_response(
-                        request.http_version(),
-                        StatusCode::OK,
-                        Body::new(response_body),
-                    )
-                }
-                Err(e) => {
-                    match e {
-                        MmdsError::NotFound => {
-                            // NotFound
-                            let error_msg = format!("Resource not found: {}.", uri);
-                            return build_response(
-                                request.http_version(),
-                                StatusCode::NotFound,
-                                Body::new(error_msg),
-                            );
-                        }
-                        MmdsError::UnsupportedValueType => {
-                            // InternalServerError
-                            let error_msg =
-                                format!("The resource {} has an invalid format.", uri.to_string());
-                            return build_response(
-                                request.http_version(),
-                                StatusCode::InternalServerError,
-                                Body::new(error_msg),
-                            );
-                        }
-                    }
-                }
-            }
+            let mut response = respond_to_request(mmds, &request);
+            response.set_keep_alive(request.keep_alive());
+            response
         }
-        Err(e) => match e {
-            RequestError::InvalidHttpVersion(err_msg) => build_response(
-                Version::default(),
-                StatusCode::NotImplemented,
-                Body::new(err_msg.to_string()),
-            ),
-            RequestError::InvalidUri(err_msg) | RequestError::InvalidHttpMethod(err_msg) => {
-                build_response(
+        Err(e) => {
+            let mut response = match e {
+                RequestError::InvalidHttpVersion(err_msg) => build_response(
+                    Version::default(),
+                    StatusCode::NotImplemented,
+                    Body::new(err_msg.to_string()),
+                ),
+                RequestError::InvalidUri(err_msg)
+                | RequestError::InvalidHttpMethod(err_msg)
+                | RequestError::InvalidHeader(err_msg) => build_response(
                     Version::default(),
                     StatusCode::BadRequest,
                     Body::new(err_msg.to_string()),
-                )
+                ),
+                RequestError::InvalidRequest => build_response(
+                    Version::default(),
+                    StatusCode::BadRequest,
+                    Body::new("Invalid request.".to_string()),
+                ),
+                RequestError::HeadersTooLarge => build_response(
+                    Version::default(),
+                    StatusCode::BadRequest,
+                    Body::new("Request headers too large.".to_string()),
+                ),
+                RequestError::BodyTooLarge => build_response(
+                    Version::default(),
+                    StatusCode::PayloadTooLarge,
+                    Body::new("Request body too large.".to_string()),
+                ),
+            };
+            // We can't tell where the next request begins after an invalid one, so the
+            // connection is closed after sending the response.
+            response.set_keep_alive(false);
+            response
+        }
+    }
+}
+
+/// Checks whether the HTTP request in `request_bytes` has to wait until the host updates the
+/// contents of `mmds`, instead of being answered right away. This is the case for GET requests
+/// with an `If-None-Match` header which matches the current revision of the contents, unless
+/// they set a wait time of 0 via the `WAIT_HEADER` header.
+///
+/// Waiting requests are answered by `parse_request` as well, either after the contents change,
+/// or when the wait times out (in which case the response is a 304 Not Modified).
+pub fn long_poll(mmds: &Mutex<Mmds>, request_bytes: &[u8]) -> Option<LongPoll> {
+    let request = Request::try_from(request_bytes).ok()?;
+    if request.method() != Method::Get {
+        return None;
+    }
+    let if_none_match = request.headers().if_none_match()?;
+    let wait_seconds = wait_seconds(&request)?;
+
+    let mmds = mmds
+        .lock()
+        .expect("Failed to build MMDS response due to poisoned lock");
+    if wait_seconds == 0
+        || !etag_matches(if_none_match, mmds.revision())
+        || !has_valid_token(&request, &mmds)
+    {
+        return None;
+    }
+
+    Some(LongPoll {
+        revision: mmds.revision(),
+        timeout: Duration::from_secs(wait_seconds),
+    })
+}
+
+// The entity tag which identifies a revision of the MMDS contents.
+fn etag(revision: u64) -> String {
+    format!("\"{}\"", revision)
+}
+
+// Returns true if the list of entity tags from an If-None-Match header matches the given
+// revision. Weak tags are compared just like strong ones.
+fn etag_matches(if_none_match: &str, revision: u64) -> bool {
+    let etag = etag(revision);
+    if_none_match.split(',').map(str::trim).any(|tag| {
+        let tag = if tag.starts_with("W/") { &tag[2..] } else { tag };
+        tag == "*" || tag == etag
+    })
+}
+
+// Returns the wait time of a conditional GET request, in seconds, or None if the value of the
+// WAIT_HEADER header is invalid.
+fn wait_seconds(request: &Request) -> Option<u64> {
+    match request.headers().custom(WAIT_HEADER) {
+        Some(value) => value
+            .parse::<u64>()
+            .ok()
+            .filter(|seconds| *seconds <= MAX_WAIT_SECONDS),
+        None => Some(DEFAULT_WAIT_SECONDS),
+    }
+}
+
+// Returns true if the request may access the MMDS contents, as far as session tokens are
+// concerned.
+fn has_valid_token(request: &Request, mmds: &Mmds) -> bool {
+    let token_authority = mmds.token_authority();
+    let token_is_valid = match request.headers().custom(TOKEN_HEADER) {
+        Some(token) => token_authority.is_valid(token),
+        None => token_authority.mode() != TokenMode::Required,
+    };
+    token_authority.mode() == TokenMode::Disabled || token_is_valid
+}
+
+fn respond_to_request(mmds: &Mutex<Mmds>, request: &Request) -> Response {
+    let uri = request.uri().get_abs_path();
+    if uri.len() == 0 {
+        return build_response(
+            request.http_version(),
+            StatusCode::BadRequest,
+            Body::new("Invalid URI.".to_string()),
+        );
+    }
+
+    // The lock can be held by one thread only, so it is safe to unwrap.
+    // If another thread poisoned the lock, we abort the execution.
+    let mut mmds = mmds
+        .lock()
+        .expect("Failed to build MMDS response due to poisoned lock");
+
+    if uri == TOKEN_PATH && request.method() == Method::Put {
+        return respond_to_token_request(request, &mmds);
+    }
+
+    match request.method() {
+        Method::Get | Method::Put | Method::Patch => (),
+        _ => {
+            return build_response(
+                request.http_version(),
+                StatusCode::NotImplemented,
+                Body::new("Unsupported HTTP method.".to_string()),
+            )
+        }
+    }
+
+    if !has_valid_token(request, &mmds) {
+        METRICS.mmds.unauthorized_requests.inc();
+        return build_response(
+            request.http_version(),
+            StatusCode::Unauthorized,
+            Body::new("Missing, invalid, or expired session token.".to_string()),
+        );
+    }
+
+    if request.method() != Method::Get {
+        return respond_to_guest_write(request, uri, &mut mmds);
+    }
+
+    if wait_seconds(request).is_none() {
+        let error_msg = format!(
+            "Invalid {} header. The value must be between 0 and {}.",
+            WAIT_HEADER, MAX_WAIT_SECONDS
+        );
+        return build_response(
+            request.http_version(),
+            StatusCode::BadRequest,
+            Body::new(error_msg),
+        );
+    }
+
+    // Conditional requests for the current revision, which did not wait for it to change (or
+    // whose wait timed out), get an empty response.
+    let revision = mmds.revision();
+    if let Some(if_none_match) = request.headers().if_none_match() {
+        if etag_matches(if_none_match, revision) {
+            let mut response = Response::new(request.http_version(), StatusCode::NotModified);
+            response.set_etag(etag(revision));
+            return response;
+        }
+    }
+
+    let media_type = request
+        .headers()
+        .accept()
+        .and_then(|accept| {
+            MediaType::negotiate(accept, &[MediaType::PlainText, MediaType::ApplicationJson])
+        }).unwrap_or(MediaType::PlainText);
+    match mmds.get_value_snapshot(uri) {
+        Ok(snapshot) => {
+            let mut response = Response::new(request.http_version(), StatusCode::OK);
+            response.set_content_type(media_type);
+            set_value_body(&mut response, &mmds, uri, snapshot, media_type);
+            response.set_etag(etag(revision));
+            response
+        }
+        // Reads only fail when the requested resource is missing. The response still carries
+        // the revision, so the guest can wait for the resource to show up.
+        Err(_) => {
+            let error_msg = format!("Resource not found: {}.", uri);
+            let mut response = build_response(
+                request.http_version(),
+                StatusCode::NotFound,
+                Body::new(error_msg),
+            );
+            response.set_etag(etag(revision));
+            response
+        }
+    }
+}
+
+// Sets the body of `response` to the representation of the value captured by `snapshot`, which
+// was found at `uri`. Large bodies are streamed from the snapshot, and sent along with their
+// length.
+fn set_value_body(
+    response: &mut Response,
+    mmds: &Mmds,
+    uri: &str,
+    snapshot: ValueSnapshot,
+    media_type: MediaType,
+) {
+    match media_type {
+        MediaType::ApplicationJson => {
+            let len = json_len(snapshot.value());
+            if len > STREAMED_BODY_THRESHOLD {
+                response.set_sized_body_stream(Box::new(JsonReader::new(snapshot)), len);
+            } else {
+                response.set_body(Body::new(snapshot.value().to_string()));
+            }
+        }
+        MediaType::PlainText => {
+            // Only string values can be large, as opposed to key listings, and other scalars.
+            let len = snapshot.value().as_str().map_or(0, str::len);
+            if len > STREAMED_BODY_THRESHOLD {
+                // The unwrap() is safe because the value is a string.
+                let reader = StrReader::new(snapshot).unwrap();
+                response.set_sized_body_stream(Box::new(reader), len);
+            } else {
+                // The unwrap() is safe because the snapshot shows that the value exists.
+                let body = mmds.get_value(uri.to_string()).unwrap().join("\n");
+                response.set_body(Body::new(body));
             }
-            RequestError::InvalidRequest => build_response(
-                Version::default(),
+        }
+    }
+}
+
+// Guests can only write to the guest section of the MMDS, using PUT or PATCH requests with a
+// JSON body.
+fn respond_to_guest_write(request: &Request, uri: &str, mmds: &mut Mmds) -> Response {
+    if !Mmds::is_guest_data_path(uri) {
+        return build_response(
+            request.http_version(),
+            StatusCode::Forbidden,
+            Body::new(format!(
+                "Only the {} section of the MMDS is writable.",
+                GUEST_DATA_PREFIX
+            )),
+        );
+    }
+
+    let data = match request
+        .body()
+        .and_then(|body| serde_json::from_slice(body.raw()).ok())
+    {
+        Some(data) => data,
+        None => {
+            METRICS.mmds.guest_data_update_fails.inc();
+            return build_response(
+                request.http_version(),
+                StatusCode::BadRequest,
+                Body::new("The request body must be a JSON value.".to_string()),
+            );
+        }
+    };
+
+    let outcome = if request.method() == Method::Put {
+        mmds.put_guest_data(uri, data)
+    } else {
+        mmds.patch_guest_data(uri, data)
+    };
+
+    match outcome {
+        Ok(()) => {
+            METRICS.mmds.guest_data_updates.inc();
+            Response::new(request.http_version(), StatusCode::NoContent)
+        }
+        Err(e) => {
+            METRICS.mmds.guest_data_update_fails.inc();
+            let (status_code, error_msg) = match e {
+                GuestDataError::Disabled => (
+                    StatusCode::Forbidden,
+                    "Guest writes to the MMDS are disabled.".to_string(),
+                ),
+                GuestDataError::InvalidPath => (
+                    StatusCode::BadRequest,
+                    format!("Cannot write to {}.", uri),
+                ),
+                GuestDataError::LimitExceeded => (
+                    StatusCode::PayloadTooLarge,
+                    format!(
+                        "The {} section of the MMDS would exceed its size limit.",
+                        GUEST_DATA_PREFIX
+                    ),
+                ),
+            };
+            build_response(request.http_version(), status_code, Body::new(error_msg))
+        }
+    }
+}
+
+fn respond_to_token_request(request: &Request, mmds: &Mmds) -> Response {
+    let token_authority = mmds.token_authority();
+    if token_authority.mode() == TokenMode::Disabled {
+        return build_response(
+            request.http_version(),
+            StatusCode::NotFound,
+            Body::new("Session tokens are disabled.".to_string()),
+        );
+    }
+
+    // Requests which went through a proxy are refused, since they are likely to have been
+    // forged on behalf of some remote party.
+    if request.headers().custom("X-Forwarded-For").is_some() {
+        METRICS.mmds.token_requests_rejected.inc();
+        return build_response(
+            request.http_version(),
+            StatusCode::Forbidden,
+            Body::new("Forwarded session token requests are not allowed.".to_string()),
+        );
+    }
+
+    match request
+        .headers()
+        .custom(TOKEN_TTL_HEADER)
+        .and_then(token::parse_ttl)
+    {
+        Some(ttl) => {
+            METRICS.mmds.tokens_issued.inc();
+            build_response(
+                request.http_version(),
+                StatusCode::OK,
+                Body::new(token_authority.generate_token(ttl)),
+            )
+        }
+        None => {
+            METRICS.mmds.token_requests_rejected.inc();
+            let error_msg = format!(
+                "Missing or invalid {} header. The value must be between {} and {}.",
+                TOKEN_TTL_HEADER,
+                token::MIN_TOKEN_TTL_SECONDS,
+                token::MAX_TOKEN_TTL_SECONDS
+            );
+            build_response(
+                request.http_version(),
                 StatusCode::BadRequest,
-                Body::new("Invalid request.".to_string()),
-            ),
-        },
+                Body::new(error_msg),
+            )
+        }
     }
 }
 
@@ -108,6 +422,10 @@ mod tests {
     extern crate serde_json;
     use super::*;
 
+    use std::sync::Arc;
+
+    use token::TokenAuthority;
+
     #[test]
     fn test_parse_request() {
         let data = r#"{
@@ -124,40 +442,69 @@ mod tests {
                 "mobile": "+44 2345678"
             }
         }"#;
-        MMDS.lock()
+        let mmds = Arc::new(Mutex::new(Mmds::default()));
+        mmds.lock()
             .unwrap()
-            .put_data(serde_json::from_str(data).unwrap());
+            .put_data(serde_json::from_str(data).unwrap())
+            .unwrap();
 
         // Test invalid request.
         let request = b"HTTP/1.1";
         let dummy_response = Response::new(Version::Http11, StatusCode::BadRequest);
-        assert!(parse_request(request).status() == dummy_response.status());
+        assert!(parse_request(&mmds, request).status() == dummy_response.status());
 
         // Test unsupported HTTP version.
         let request = b"GET http://169.254.169.255/ HTTP/2.0\r\n";
         let mut expected_response = Response::new(Version::Http11, StatusCode::NotImplemented);
         expected_response.set_body(Body::new("Unsupported HTTP version.".to_string()));
-        let actual_response = parse_request(request);
+        let actual_response = parse_request(&mmds, request);
 
         assert!(expected_response.status() == actual_response.status());
         assert!(expected_response.body().unwrap() == actual_response.body().unwrap());
         assert!(expected_response.http_version() == actual_response.http_version());
 
         // Test invalid HTTP Method.
-        let request = b"PUT http://169.254.169.255/ HTTP/1.0\r\n";
+        let request = b"POST http://169.254.169.255/ HTTP/1.0\r\n";
         let mut expected_response = Response::new(Version::Http11, StatusCode::BadRequest);
         expected_response.set_body(Body::new("Unsupported HTTP method.".to_string()));
-        let actual_response = parse_request(request);
+        let actual_response = parse_request(&mmds, request);
 
         assert!(expected_response.status() == actual_response.status());
         assert!(expected_response.body().unwrap() == actual_response.body().unwrap());
         assert!(expected_response.http_version() == actual_response.http_version());
+        assert!(!actual_response.keep_alive());
+
+        // Test valid HTTP Method which is not handled by the MMDS.
+        let request = b"DELETE http://169.254.169.255/ HTTP/1.0\r\n";
+        let mut expected_response = Response::new(Version::Http10, StatusCode::NotImplemented);
+        expected_response.set_body(Body::new("Unsupported HTTP method.".to_string()));
+        let actual_response = parse_request(&mmds, request);
+
+        assert!(expected_response.status() == actual_response.status());
+        assert!(expected_response.body().unwrap() == actual_response.body().unwrap());
+        assert!(expected_response.http_version() == actual_response.http_version());
+
+        // Test invalid header.
+        let request = b"GET http://169.254.169.255/ HTTP/1.1\r\nContent-Length: x\r\n\r\n";
+        let mut expected_response = Response::new(Version::Http11, StatusCode::BadRequest);
+        expected_response.set_body(Body::new("Invalid Content-Length.".to_string()));
+        let actual_response = parse_request(&mmds, request);
+
+        assert!(expected_response.status() == actual_response.status());
+        assert!(expected_response.body().unwrap() == actual_response.body().unwrap());
+        assert!(!actual_response.keep_alive());
+
+        // Test body too large.
+        let request = b"PUT http://169.254.169.255/ HTTP/1.1\r\nContent-Length: 99999\r\n\r\n";
+        let actual_response = parse_request(&mmds, request);
+        assert!(actual_response.status() == StatusCode::PayloadTooLarge);
+        assert!(!actual_response.keep_alive());
 
         // Test invalid (empty absolute path) URI.
         let request = b"GET http:// HTTP/1.0\r\n";
         let mut expected_response = Response::new(Version::Http10, StatusCode::BadRequest);
         expected_response.set_body(Body::new("Invalid URI.".to_string()));
-        let actual_response = parse_request(request);
+        let actual_response = parse_request(&mmds, request);
 
         assert!(expected_response.status() == actual_response.status());
         assert!(expected_response.body().unwrap() == actual_response.body().unwrap());
@@ -167,7 +514,7 @@ mod tests {
         let request = b"GET http://169.254.169.254/invalid HTTP/1.0\r\n";
         let mut expected_response = Response::new(Version::Http10, StatusCode::NotFound);
         expected_response.set_body(Body::new("Resource not found: /invalid.".to_string()));
-        let actual_response = parse_request(request);
+        let actual_response = parse_request(&mmds, request);
 
         assert!(expected_response.status() == actual_response.status());
         assert!(expected_response.body().unwrap() == actual_response.body().unwrap());
@@ -178,7 +525,7 @@ mod tests {
         let mut expected_response = Response::new(Version::Http10, StatusCode::OK);
         let body = "age\nname/\nphones/".to_string();
         expected_response.set_body(Body::new(body));
-        let actual_response = parse_request(request);
+        let actual_response = parse_request(&mmds, request);
 
         assert!(expected_response.status() == actual_response.status());
         assert!(expected_response.body().unwrap() == actual_response.body().unwrap());
@@ -188,13 +535,20 @@ mod tests {
         let mut expected_response = Response::new(Version::Http11, StatusCode::OK);
         let body = "43".to_string();
         expected_response.set_body(Body::new(body));
-        let actual_response = parse_request(request);
+        let actual_response = parse_request(&mmds, request);
 
         assert!(expected_response.status() == actual_response.status());
         assert!(expected_response.body().unwrap() == actual_response.body().unwrap());
         assert!(expected_response.http_version() == actual_response.http_version());
+        assert!(actual_response.keep_alive());
 
-        // Test Internal Server Error.
+        // Test the Connection header.
+        let request = b"GET /age HTTP/1.1\r\nConnection: close\r\n\r\n";
+        assert!(!parse_request(&mmds, request).keep_alive());
+        let request = b"GET /age HTTP/1.0\r\nConnection: keep-alive\r\n\r\n";
+        assert!(parse_request(&mmds, request).keep_alive());
+
+        // Test non-string values.
         let data = r#"{
             "name": {
                 "first": "John",
@@ -202,17 +556,363 @@ mod tests {
             },
             "age": 43
         }"#;
-        MMDS.lock()
+        mmds.lock()
             .unwrap()
-            .put_data(serde_json::from_str(data).unwrap());
+            .put_data(serde_json::from_str(data).unwrap())
+            .unwrap();
 
         let request = b"GET http://169.254.169.254/age HTTP/1.0\r\n";
-        let mut expected_response = Response::new(Version::Http10, StatusCode::InternalServerError);
-        let body = format!("The resource /age has an invalid format.");
-        expected_response.set_body(Body::new(body));
-        let actual_response = parse_request(request);
+        let mut expected_response = Response::new(Version::Http10, StatusCode::OK);
+        expected_response.set_body(Body::new("43".to_string()));
+        let actual_response = parse_request(&mmds, request);
         assert!(expected_response.status() == actual_response.status());
         assert!(expected_response.body().unwrap() == actual_response.body().unwrap());
         assert!(expected_response.http_version() == actual_response.http_version());
+
+        // Test JSON responses.
+        let request = b"GET /name HTTP/1.1\r\nAccept: application/json\r\n\r\n";
+        let mut actual_response = parse_request(&mmds, request);
+        assert!(actual_response.status() == StatusCode::OK);
+        assert_eq!(
+            actual_response.body().unwrap().raw(),
+            br#"{"first":"John","second":"Doe"}"#.as_ref()
+        );
+        let mut response_buf = Vec::new();
+        actual_response.write_all(&mut response_buf).unwrap();
+        assert!(
+            String::from_utf8(response_buf)
+                .unwrap()
+                .contains("Content-Type: application/json\r\n")
+        );
+
+        let request = b"GET /age HTTP/1.1\r\nAccept: text/plain;q=0.5, application/*\r\n\r\n";
+        assert_eq!(parse_request(&mmds, request).body().unwrap().raw(), b"43");
+
+        // Text is preferred when both media types are acceptable.
+        let request = b"GET /name/ HTTP/1.1\r\nAccept: */*\r\n\r\n";
+        let mut actual_response = parse_request(&mmds, request);
+        assert_eq!(actual_response.body().unwrap().raw(), b"first\nsecond");
+        let mut response_buf = Vec::new();
+        actual_response.write_all(&mut response_buf).unwrap();
+        assert!(
+            String::from_utf8(response_buf)
+                .unwrap()
+                .contains("Content-Type: text/plain\r\n")
+        );
+
+        // Errors are always plain text.
+        let request = b"GET /invalid HTTP/1.1\r\nAccept: application/json\r\n\r\n";
+        let actual_response = parse_request(&mmds, request);
+        assert!(actual_response.status() == StatusCode::NotFound);
+        assert_eq!(
+            actual_response.body().unwrap().raw(),
+            b"Resource not found: /invalid."
+        );
+    }
+
+    #[test]
+    fn test_conditional_requests() {
+        let mmds = Arc::new(Mutex::new(Mmds::default()));
+        mmds.lock()
+            .unwrap()
+            .put_data(serde_json::from_str(r#"{"status": "booting"}"#).unwrap())
+            .unwrap();
+
+        // Every response to a GET request carries the current revision.
+        let mut response_buf = Vec::new();
+        let mut response = parse_request(&mmds, b"GET /status HTTP/1.1\r\n\r\n");
+        response.write_all(&mut response_buf).unwrap();
+        assert!(
+            String::from_utf8(response_buf)
+                .unwrap()
+                .contains("ETag: \"1\"\r\n")
+        );
+        let mut response_buf = Vec::new();
+        let mut response = parse_request(&mmds, b"GET /missing HTTP/1.1\r\n\r\n");
+        assert!(response.status() == StatusCode::NotFound);
+        response.write_all(&mut response_buf).unwrap();
+        assert!(
+            String::from_utf8(response_buf)
+                .unwrap()
+                .contains("ETag: \"1\"\r\n")
+        );
+
+        // Requests for the current revision wait for it to change, unless they ask otherwise.
+        let request = b"GET /status HTTP/1.1\r\nIf-None-Match: \"0\", W/\"1\"\r\n\r\n";
+        assert_eq!(
+            long_poll(&mmds, request),
+            Some(LongPoll {
+                revision: 1,
+                timeout: Duration::from_secs(DEFAULT_WAIT_SECONDS),
+            })
+        );
+        let request = b"GET /status HTTP/1.1\r\nIf-None-Match: *\r\n\
+                        X-metadata-wait-seconds: 5\r\n\r\n";
+        assert_eq!(
+            long_poll(&mmds, request),
+            Some(LongPoll {
+                revision: 1,
+                timeout: Duration::from_secs(5),
+            })
+        );
+        // Once the wait is over, the response is empty.
+        let response = parse_request(&mmds, request);
+        assert!(response.status() == StatusCode::NotModified);
+        assert!(response.body().is_none());
+        let request = b"GET /status HTTP/1.1\r\nIf-None-Match: \"1\"\r\n\
+                        X-metadata-wait-seconds: 0\r\n\r\n";
+        assert!(long_poll(&mmds, request).is_none());
+        assert!(parse_request(&mmds, request).status() == StatusCode::NotModified);
+
+        // Requests which are answered right away.
+        for request in &[
+            b"GET /status HTTP/1.1\r\n\r\n".as_ref(),
+            b"GET /status HTTP/1.1\r\nIf-None-Match: \"0\"\r\n\r\n".as_ref(),
+            b"PUT /guest/a HTTP/1.1\r\nIf-None-Match: \"1\"\r\n\r\n".as_ref(),
+            b"GET /status HTTP/1.1\r\nIf-None-Match: \"1\"\r\n\
+              X-metadata-wait-seconds: 301\r\n\r\n"
+                .as_ref(),
+            b"GET /status HTTP/1.1\r\nIf-None-Match".as_ref(),
+        ] {
+            assert!(long_poll(&mmds, request).is_none());
+        }
+        let request = b"GET /status HTTP/1.1\r\nIf-None-Match: \"1\"\r\n\
+                        X-metadata-wait-seconds: -1\r\n\r\n";
+        assert!(parse_request(&mmds, request).status() == StatusCode::BadRequest);
+
+        // Requests without a valid session token are rejected right away.
+        mmds.lock()
+            .unwrap()
+            .set_token_authority(TokenAuthority::new(TokenMode::Required, None));
+        let request = b"GET /status HTTP/1.1\r\nIf-None-Match: \"1\"\r\n\r\n";
+        assert!(long_poll(&mmds, request).is_none());
+        assert!(parse_request(&mmds, request).status() == StatusCode::Unauthorized);
+
+        // The revision changes with every update from the host.
+        mmds.lock()
+            .unwrap()
+            .set_token_authority(TokenAuthority::default());
+        mmds.lock()
+            .unwrap()
+            .patch_data(serde_json::from_str(r#"{"status": "ready"}"#).unwrap())
+            .unwrap();
+        assert!(long_poll(&mmds, request).is_none());
+        let response = parse_request(&mmds, request);
+        assert!(response.status() == StatusCode::OK);
+        assert_eq!(response.body().unwrap().raw(), b"ready");
+    }
+
+    fn guest_write(mmds: &Mutex<Mmds>, method: &str, path: &str, body: &str) -> Response {
+        let request = format!(
+            "{} {} HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            body.len(),
+            body
+        );
+        parse_request(mmds, request.as_bytes())
+    }
+
+    #[test]
+    fn test_guest_writes() {
+        let mmds = Arc::new(Mutex::new(Mmds::default()));
+        mmds.lock()
+            .unwrap()
+            .put_data(serde_json::from_str(r#"{"name": "John"}"#).unwrap())
+            .unwrap();
+
+        // Guest writes are disabled by default.
+        let response = guest_write(&mmds, "PUT", "/guest/status", r#""booting""#);
+        assert!(response.status() == StatusCode::Forbidden);
+
+        mmds.lock().unwrap().set_guest_data_limit(64);
+        let updates = METRICS.mmds.guest_data_updates.count();
+        let fails = METRICS.mmds.guest_data_update_fails.count();
+
+        // The host provided contents are read-only.
+        let response = guest_write(&mmds, "PUT", "/name", r#""Jane""#);
+        assert!(response.status() == StatusCode::Forbidden);
+        let response = guest_write(&mmds, "PUT", "/guestbook", r#""Jane""#);
+        assert!(response.status() == StatusCode::Forbidden);
+
+        let response = guest_write(&mmds, "PUT", "/guest/status", r#""booting""#);
+        assert!(response.status() == StatusCode::NoContent);
+        assert!(response.body().is_none());
+        let response = guest_write(&mmds, "PATCH", "/guest/", r#"{"health": {"disk": "ok"}}"#);
+        assert!(response.status() == StatusCode::NoContent);
+        let response = guest_write(&mmds, "PUT", "/guest/status", r#""ready""#);
+        assert!(response.status() == StatusCode::NoContent);
+        assert_eq!(METRICS.mmds.guest_data_updates.count(), updates + 3);
+
+        // The guest can read what it wrote.
+        let response = parse_request(&mmds, b"GET /guest/status HTTP/1.1\r\n\r\n");
+        assert_eq!(response.body().unwrap().raw(), b"ready");
+        let response = parse_request(&mmds, b"GET /guest/ HTTP/1.1\r\n\r\n");
+        assert_eq!(response.body().unwrap().raw(), b"health/\nstatus");
+
+        // Invalid writes.
+        let response = guest_write(&mmds, "PUT", "/guest/status", "not json");
+        assert!(response.status() == StatusCode::BadRequest);
+        let response = guest_write(&mmds, "PUT", "/guest/status/code", "1");
+        assert!(response.status() == StatusCode::BadRequest);
+        let response = guest_write(&mmds, "PUT", "/guest", "[]");
+        assert!(response.status() == StatusCode::BadRequest);
+        let response = guest_write(&mmds, "PUT", "/guest/log", &format!("\"{:064}\"", 0));
+        assert!(response.status() == StatusCode::PayloadTooLarge);
+        assert_eq!(METRICS.mmds.guest_data_update_fails.count(), fails + 4);
+
+        // The guest section is unchanged, and kept when the host replaces its contents.
+        mmds.lock()
+            .unwrap()
+            .put_data(serde_json::from_str(r#"{"name": "Jane"}"#).unwrap())
+            .unwrap();
+        assert_eq!(
+            mmds.lock().unwrap().get_guest_data_str(),
+            r#"{"health":{"disk":"ok"},"status":"ready"}"#
+        );
+    }
+
+    fn token_request(mmds: &Mutex<Mmds>, ttl: &str) -> Response {
+        let request = format!(
+            "PUT /latest/api/token HTTP/1.1\r\nX-metadata-token-ttl-seconds: {}\r\n\r\n",
+            ttl
+        );
+        parse_request(mmds, request.as_bytes())
+    }
+
+    fn get_with_token(mmds: &Mutex<Mmds>, token: &str) -> Response {
+        let request = format!(
+            "GET /name/first HTTP/1.1\r\nX-metadata-token: {}\r\n\r\n",
+            token
+        );
+        parse_request(mmds, request.as_bytes())
+    }
+
+    #[test]
+    fn test_session_tokens() {
+        let mmds = Arc::new(Mutex::new(Mmds::default()));
+        mmds.lock()
+            .unwrap()
+            .put_data(serde_json::from_str(r#"{"name": {"first": "John"}}"#).unwrap())
+            .unwrap();
+        let get_request = b"GET /name/first HTTP/1.1\r\n\r\n";
+
+        // Tokens are disabled by default, and the token header is ignored.
+        assert!(token_request(&mmds, "60").status() == StatusCode::NotFound);
+        assert!(get_with_token(&mmds, "invalid").status() == StatusCode::OK);
+
+        mmds.lock().unwrap().set_token_authority(TokenAuthority::new(
+            TokenMode::Optional,
+            Some(b"key".to_vec()),
+        ));
+
+        // Invalid token requests.
+        let rejected = METRICS.mmds.token_requests_rejected.count();
+        for ttl in &["0", "21601", "abc"] {
+            let response = token_request(&mmds, ttl);
+            assert!(response.status() == StatusCode::BadRequest);
+        }
+        let response = parse_request(&mmds, b"PUT /latest/api/token HTTP/1.1\r\n\r\n");
+        assert!(response.status() == StatusCode::BadRequest);
+        let response = parse_request(
+            &mmds,
+            b"PUT /latest/api/token HTTP/1.1\r\n\
+              X-metadata-token-ttl-seconds: 60\r\n\
+              X-Forwarded-For: 10.0.0.1\r\n\r\n",
+        );
+        assert!(response.status() == StatusCode::Forbidden);
+        assert_eq!(METRICS.mmds.token_requests_rejected.count(), rejected + 5);
+
+        // Get a valid token.
+        let issued = METRICS.mmds.tokens_issued.count();
+        let response = token_request(&mmds, "60");
+        assert!(response.status() == StatusCode::OK);
+        assert_eq!(METRICS.mmds.tokens_issued.count(), issued + 1);
+        let token = String::from_utf8(response.body().unwrap().raw().to_vec()).unwrap();
+
+        // In optional mode, requests without a token are served, but invalid tokens are not.
+        let unauthorized = METRICS.mmds.unauthorized_requests.count();
+        assert!(parse_request(&mmds, get_request).status() == StatusCode::OK);
+        assert!(get_with_token(&mmds, &token).status() == StatusCode::OK);
+        assert!(get_with_token(&mmds, "invalid").status() == StatusCode::Unauthorized);
+        assert_eq!(METRICS.mmds.unauthorized_requests.count(), unauthorized + 1);
+
+        // In required mode, every request has to present a valid token.
+        mmds.lock().unwrap().set_token_authority(TokenAuthority::new(
+            TokenMode::Required,
+            Some(b"key".to_vec()),
+        ));
+        let response = get_with_token(&mmds, &token);
+        assert!(response.status() == StatusCode::OK);
+        assert_eq!(response.body().unwrap().raw(), b"John");
+        let response = parse_request(&mmds, get_request);
+        assert!(response.status() == StatusCode::Unauthorized);
+        // The connection stays open after a rejected request.
+        assert!(response.keep_alive());
+
+        // Changing the key invalidates the previous tokens.
+        mmds.lock().unwrap().set_token_authority(TokenAuthority::new(
+            TokenMode::Required,
+            Some(b"other key".to_vec()),
+        ));
+        assert!(get_with_token(&mmds, &token).status() == StatusCode::Unauthorized);
+
+    }
+    #[test]
+    fn test_streamed_responses() {
+        let mmds = Arc::new(Mutex::new(Mmds::default()));
+        let user_data = "x".repeat(STREAMED_BODY_THRESHOLD + 1);
+        mmds.lock()
+            .unwrap()
+            .put_data(
+                serde_json::from_str(&format!(
+                    r#"{{"small": "data", "user-data": "{}"}}"#,
+                    user_data
+                )).unwrap(),
+            )
+            .unwrap();
+
+        // Small values are sent as a whole.
+        let response = parse_request(&mmds, b"GET /small HTTP/1.1\r\n\r\n");
+        assert!(!response.is_streaming());
+        assert_eq!(response.body().unwrap().raw(), b"data");
+
+        // Large ones are streamed, along with their length, so HTTP/1.0 connections can stay
+        // open as well.
+        let requests: [&[u8]; 2] = [
+            b"GET /user-data HTTP/1.1\r\n\r\n",
+            b"GET /user-data HTTP/1.0\r\nConnection: keep-alive\r\n\r\n",
+        ];
+        for request in requests.iter() {
+            let mut response = parse_request(&mmds, request);
+            assert!(response.status() == StatusCode::OK);
+            assert!(response.is_streaming());
+            assert!(response.keep_alive());
+            let mut response_buf = Vec::new();
+            response.write_all(&mut response_buf).unwrap();
+            let response_str = String::from_utf8(response_buf).unwrap();
+            assert!(response_str.contains(&format!("Content-Length: {}\r\n", user_data.len())));
+            assert!(!response_str.contains("Transfer-Encoding"));
+            assert!(response_str.contains("ETag: \"1\"\r\n"));
+            assert!(response_str.ends_with(&format!("\r\n\r\n{}", user_data)));
+        }
+
+        // The same goes for JSON responses. The body is read from a snapshot, which is not
+        // affected by later updates.
+        let mut response = parse_request(
+            &mmds,
+            b"GET / HTTP/1.1\r\nAccept: application/json\r\n\r\n",
+        );
+        assert!(response.is_streaming());
+        let expected_body = mmds.lock().unwrap().get_data_str();
+        mmds.lock()
+            .unwrap()
+            .put_data(serde_json::from_str(r#"{"small": "data"}"#).unwrap())
+            .unwrap();
+        let mut response_buf = Vec::new();
+        response.write_all(&mut response_buf).unwrap();
+        let response_str = String::from_utf8(response_buf).unwrap();
+        assert!(response_str.contains(&format!("Content-Length: {}\r\n", expected_body.len())));
+        assert!(response_str.ends_with(&format!("\r\n\r\n{}", expected_body)));
     }
 }
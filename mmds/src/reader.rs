@@ -0,0 +1,325 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Readers which produce the representation of a `ValueSnapshot` a few bytes at a time, so large
+//! MMDS responses can be streamed without holding a serialized copy of the whole value.
+
+use std::cmp::min;
+use std::io::{self, Read, Write};
+
+use serde_json::{self, Value};
+
+use data_store::ValueSnapshot;
+
+// The maximum number of bytes from a string value which are escaped in one go.
+const STRING_PIECE_LEN: usize = 4096;
+
+// Counts the bytes written to it, and drops them.
+struct ByteCounter(usize);
+
+impl Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Returns the length of the JSON representation of `value`, without serializing it in memory.
+pub fn json_len(value: &Value) -> usize {
+    let mut counter = ByteCounter(0);
+    // The unwrap() is safe because serializing a Value only fails when writing fails, and
+    // writes to the counter always succeed.
+    serde_json::to_writer(&mut counter, value).unwrap();
+    counter.0
+}
+
+// Returns the largest index of `s` which is at most `index`, and falls on a char boundary.
+fn floor_char_boundary(s: &str, mut index: usize) -> usize {
+    if index >= s.len() {
+        return s.len();
+    }
+    while !s.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+/// Reads the contents of a string value, as is.
+pub struct StrReader {
+    snapshot: ValueSnapshot,
+    // The number of bytes which were already read.
+    offset: usize,
+}
+
+impl StrReader {
+    /// Creates a reader for the string captured by `snapshot`, or returns None if the value is
+    /// not a string.
+    pub fn new(snapshot: ValueSnapshot) -> Option<Self> {
+        if !snapshot.value().is_string() {
+            return None;
+        }
+        Some(StrReader {
+            snapshot,
+            offset: 0,
+        })
+    }
+}
+
+impl Read for StrReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // The unwrap() is safe because new() checks that the value is a string.
+        let bytes = self.snapshot.value().as_str().unwrap().as_bytes();
+        let len = min(buf.len(), bytes.len() - self.offset);
+        buf[..len].copy_from_slice(&bytes[self.offset..self.offset + len]);
+        self.offset += len;
+        Ok(len)
+    }
+}
+
+// An object or array whose members are being serialized.
+enum Frame {
+    // The keys of the object, and the index of the next member.
+    Object { keys: Vec<String>, next: usize },
+    // The length of the array, and the index of the next element.
+    Array { len: usize, next: usize },
+}
+
+/// Reads the JSON representation of a value, which is produced as it is being read. Only the
+/// keys of the objects which are currently being serialized, and a small piece of the value, are
+/// held in memory at any given time. The output is the same as that of `serde_json::to_writer`.
+pub struct JsonReader {
+    snapshot: ValueSnapshot,
+    // The objects and arrays being serialized, from the outermost one to the innermost one.
+    stack: Vec<Frame>,
+    // Set while a string value is being escaped, to the number of its bytes escaped so far.
+    string_offset: Option<usize>,
+    // Output which was produced, but not read yet.
+    pending: Vec<u8>,
+    pending_offset: usize,
+    started: bool,
+    done: bool,
+}
+
+impl JsonReader {
+    /// Creates a reader for the value captured by `snapshot`.
+    pub fn new(snapshot: ValueSnapshot) -> Self {
+        JsonReader {
+            snapshot,
+            stack: Vec::new(),
+            string_offset: None,
+            pending: Vec::new(),
+            pending_offset: 0,
+            started: false,
+            done: false,
+        }
+    }
+
+    // Returns the value currently being serialized, which is the last member visited by each
+    // frame of the stack, starting from the root value.
+    fn current<'a>(root: &'a Value, stack: &[Frame]) -> &'a Value {
+        stack.iter().fold(root, |value, frame| match *frame {
+            Frame::Object { ref keys, next } => &value[&keys[next - 1]],
+            Frame::Array { next, .. } => &value[next - 1],
+        })
+    }
+
+    // Starts serializing `value`. Objects and arrays get a frame of their own, while strings
+    // are escaped one piece at a time.
+    fn begin_value(&mut self, value: &Value) {
+        match *value {
+            Value::Object(ref map) => {
+                self.pending.push(b'{');
+                self.stack.push(Frame::Object {
+                    keys: map.keys().cloned().collect(),
+                    next: 0,
+                });
+            }
+            Value::Array(ref vec) => {
+                self.pending.push(b'[');
+                self.stack.push(Frame::Array {
+                    len: vec.len(),
+                    next: 0,
+                });
+            }
+            Value::String(_) => {
+                self.pending.push(b'"');
+                self.string_offset = Some(0);
+            }
+            // The unwrap() is safe because writing to a Vec does not fail.
+            _ => serde_json::to_writer(&mut self.pending, value).unwrap(),
+        }
+    }
+
+    // Appends the next piece of output to self.pending.
+    fn produce(&mut self) {
+        // Cloning the snapshot only bumps a reference count, and it allows us to borrow the
+        // value while modifying self.
+        let snapshot = self.snapshot.clone();
+        let root = snapshot.value();
+
+        if let Some(offset) = self.string_offset {
+            // The unwrap() is safe because string_offset is only set for strings.
+            let s = JsonReader::current(root, &self.stack).as_str().unwrap();
+            let end = floor_char_boundary(s, offset + STRING_PIECE_LEN);
+            // The piece gets escaped as a standalone string, so we drop its quotes.
+            let escaped = serde_json::to_string(&s[offset..end]).unwrap();
+            self.pending
+                .extend_from_slice(&escaped.as_bytes()[1..escaped.len() - 1]);
+            if end == s.len() {
+                self.pending.push(b'"');
+                self.string_offset = None;
+                self.done = self.stack.is_empty();
+            } else {
+                self.string_offset = Some(end);
+            }
+            return;
+        }
+
+        if !self.started {
+            self.started = true;
+            self.begin_value(root);
+            self.done = self.stack.is_empty() && self.string_offset.is_none();
+            return;
+        }
+
+        let member = match self.stack.last_mut() {
+            Some(&mut Frame::Object {
+                ref keys,
+                ref mut next,
+            })
+                if *next < keys.len() =>
+            {
+                if *next > 0 {
+                    self.pending.push(b',');
+                }
+                // The unwrap() is safe because writing to a Vec does not fail.
+                serde_json::to_writer(&mut self.pending, &keys[*next]).unwrap();
+                self.pending.push(b':');
+                *next += 1;
+                true
+            }
+            Some(&mut Frame::Array { len, ref mut next }) if *next < len => {
+                if *next > 0 {
+                    self.pending.push(b',');
+                }
+                *next += 1;
+                true
+            }
+            _ => false,
+        };
+
+        if member {
+            self.begin_value(JsonReader::current(root, &self.stack));
+            return;
+        }
+
+        // All the members of the innermost object or array were serialized.
+        match self.stack.pop() {
+            Some(Frame::Object { .. }) => self.pending.push(b'}'),
+            Some(Frame::Array { .. }) => self.pending.push(b']'),
+            None => (),
+        }
+        self.done = self.stack.is_empty();
+    }
+}
+
+impl Read for JsonReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pending_offset == self.pending.len() {
+            if self.done {
+                return Ok(0);
+            }
+            self.pending.clear();
+            self.pending_offset = 0;
+            self.produce();
+        }
+
+        let len = min(buf.len(), self.pending.len() - self.pending_offset);
+        buf[..len].copy_from_slice(&self.pending[self.pending_offset..self.pending_offset + len]);
+        self.pending_offset += len;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use data_store::Mmds;
+
+    // Reads everything from `reader`, using a buffer of `buf_len` bytes.
+    fn read_all<R: Read>(mut reader: R, buf_len: usize) -> Vec<u8> {
+        let mut result = Vec::new();
+        let mut buf = vec![0u8; buf_len];
+        loop {
+            let len = reader.read(&mut buf).unwrap();
+            if len == 0 {
+                return result;
+            }
+            result.extend_from_slice(&buf[..len]);
+        }
+    }
+
+    #[test]
+    fn test_json_reader() {
+        let mut mmds = Mmds::default();
+        let mut data: Value = serde_json::from_str(
+            r#"{
+                "empty": {},
+                "list": [1, "two", null, true, [], {"three": 3.5}],
+                "nested": {"a": {"b": {"c": "d"}}},
+                "escaped \"key\"": "\t\u0001"
+            }"#,
+        ).unwrap();
+        // Escaping grows the string, and its pieces end in the middle of multi-byte chars.
+        data["long"] = Value::String("ăbc\"\n".repeat(STRING_PIECE_LEN));
+        mmds.put_data(data).unwrap();
+
+        for path in ["/", "/list", "/long", "/nested/a", "/list/0", "/empty"].iter() {
+            let expected = mmds.get_value_json(path.to_string()).unwrap();
+            let snapshot = mmds.get_value_snapshot(path).unwrap();
+            assert_eq!(json_len(snapshot.value()), expected.len());
+            for buf_len in [1, 7, 4096].iter() {
+                let output = read_all(JsonReader::new(snapshot.clone()), *buf_len);
+                assert_eq!(String::from_utf8(output).unwrap(), expected);
+            }
+        }
+
+        // The snapshot is not affected by later updates.
+        let snapshot = mmds.get_value_snapshot("/nested").unwrap();
+        mmds.put_data(Value::String("gone".to_string())).unwrap();
+        assert_eq!(
+            read_all(JsonReader::new(snapshot), 16),
+            br#"{"a":{"b":{"c":"d"}}}"#.to_vec()
+        );
+    }
+
+    #[test]
+    fn test_str_reader() {
+        let mut mmds = Mmds::default();
+        let long_string = "ăbc".repeat(10_000);
+        let mut data: Value = serde_json::from_str(r#"{"number": 1}"#).unwrap();
+        data["long"] = Value::String(long_string.clone());
+        mmds.put_data(data).unwrap();
+
+        assert!(StrReader::new(mmds.get_value_snapshot("/number").unwrap()).is_none());
+        assert!(StrReader::new(mmds.get_value_snapshot("/").unwrap()).is_none());
+
+        let reader = StrReader::new(mmds.get_value_snapshot("/long").unwrap()).unwrap();
+        assert_eq!(read_all(reader, 1000), long_string.as_bytes().to_vec());
+    }
+
+    #[test]
+    fn test_floor_char_boundary() {
+        let s = "aăb";
+        assert_eq!(floor_char_boundary(s, 0), 0);
+        assert_eq!(floor_char_boundary(s, 1), 1);
+        assert_eq!(floor_char_boundary(s, 2), 1);
+        assert_eq!(floor_char_boundary(s, 3), 3);
+        assert_eq!(floor_char_boundary(s, 100), 4);
+    }
+}
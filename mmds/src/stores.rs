@@ -0,0 +1,194 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use data_store::{Mmds, DEFAULT_DATA_STORE_LIMIT};
+use token::TokenAuthority;
+
+/// Holds the MMDS data stores of a microVM. Guests reach the default data store through every
+/// network interface which does not have a dedicated one.
+///
+/// The data stores are shared with the network stacks serving guest requests, so each of them
+/// is handed out behind an `Arc<Mutex<Mmds>>`.
+pub struct MmdsStores {
+    default_store: Arc<Mutex<Mmds>>,
+    // Dedicated data stores, indexed by the ID of the network interface they are served on.
+    interface_stores: HashMap<String, Arc<Mutex<Mmds>>>,
+    // Applied to every data store, including the ones created later on.
+    token_authority: TokenAuthority,
+    data_store_limit: usize,
+    guest_data_limit: usize,
+}
+
+impl Default for MmdsStores {
+    fn default() -> Self {
+        let token_authority = TokenAuthority::default();
+        let mut default_store = Mmds::default();
+        default_store.set_token_authority(token_authority.clone());
+        MmdsStores {
+            default_store: Arc::new(Mutex::new(default_store)),
+            interface_stores: HashMap::new(),
+            token_authority,
+            data_store_limit: DEFAULT_DATA_STORE_LIMIT,
+            guest_data_limit: 0,
+        }
+    }
+}
+
+impl MmdsStores {
+    /// Returns the data store shared by the network interfaces without a dedicated one.
+    pub fn default_store(&self) -> Arc<Mutex<Mmds>> {
+        self.default_store.clone()
+    }
+
+    /// Returns the data store dedicated to the network interface identified by `iface_id`, if
+    /// there is one.
+    pub fn interface_store(&self, iface_id: &str) -> Option<Arc<Mutex<Mmds>>> {
+        self.interface_stores.get(iface_id).cloned()
+    }
+
+    /// Returns the data store dedicated to the network interface identified by `iface_id`,
+    /// creating an empty one if it does not exist yet.
+    pub fn add_interface_store(&mut self, iface_id: &str) -> Arc<Mutex<Mmds>> {
+        let token_authority = &self.token_authority;
+        let data_store_limit = self.data_store_limit;
+        let guest_data_limit = self.guest_data_limit;
+        self.interface_stores
+            .entry(iface_id.to_string())
+            .or_insert_with(|| {
+                let mut mmds = Mmds::default();
+                mmds.set_token_authority(token_authority.clone());
+                mmds.set_data_store_limit(data_store_limit);
+                mmds.set_guest_data_limit(guest_data_limit);
+                Arc::new(Mutex::new(mmds))
+            }).clone()
+    }
+
+    /// Returns the data store served to the guest on the network interface identified by
+    /// `iface_id`: the dedicated one if it exists, or the default one otherwise.
+    pub fn store_for_interface(&self, iface_id: &str) -> Arc<Mutex<Mmds>> {
+        self.interface_store(iface_id)
+            .unwrap_or_else(|| self.default_store())
+    }
+
+    /// Sets the session token authority and the size limits of every data store, including the
+    /// ones created afterwards.
+    pub fn configure(
+        &mut self,
+        token_authority: TokenAuthority,
+        data_store_limit: usize,
+        guest_data_limit: usize,
+    ) {
+        self.token_authority = token_authority;
+        self.data_store_limit = data_store_limit;
+        self.guest_data_limit = guest_data_limit;
+
+        for store in Some(&self.default_store)
+            .into_iter()
+            .chain(self.interface_stores.values())
+        {
+            let mut mmds = store
+                .lock()
+                .expect("Failed to configure the MMDS due to poisoned lock");
+            mmds.set_token_authority(self.token_authority.clone());
+            mmds.set_data_store_limit(self.data_store_limit);
+            mmds.set_guest_data_limit(self.guest_data_limit);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate serde_json;
+
+    use super::*;
+
+    use token::TokenMode;
+
+    #[test]
+    fn test_interface_stores() {
+        let mut stores = MmdsStores::default();
+        stores
+            .default_store()
+            .lock()
+            .unwrap()
+            .put_data(serde_json::from_str(r#"{"tenant": "default"}"#).unwrap())
+            .unwrap();
+
+        // Interfaces without a dedicated data store share the default one.
+        assert!(stores.interface_store("eth0").is_none());
+        assert!(Arc::ptr_eq(
+            &stores.store_for_interface("eth0"),
+            &stores.default_store()
+        ));
+
+        let store = stores.add_interface_store("eth0");
+        store
+            .lock()
+            .unwrap()
+            .put_data(serde_json::from_str(r#"{"tenant": "eth0"}"#).unwrap())
+            .unwrap();
+        assert!(Arc::ptr_eq(&stores.add_interface_store("eth0"), &store));
+        assert!(Arc::ptr_eq(&stores.store_for_interface("eth0"), &store));
+        assert!(Arc::ptr_eq(
+            &stores.store_for_interface("eth1"),
+            &stores.default_store()
+        ));
+
+        assert_eq!(
+            stores
+                .store_for_interface("eth0")
+                .lock()
+                .unwrap()
+                .get_value("/tenant".to_string())
+                .unwrap(),
+            vec!["eth0"]
+        );
+        assert_eq!(
+            stores
+                .store_for_interface("eth1")
+                .lock()
+                .unwrap()
+                .get_value("/tenant".to_string())
+                .unwrap(),
+            vec!["default"]
+        );
+    }
+
+    #[test]
+    fn test_configure() {
+        let mut stores = MmdsStores::default();
+        let before = stores.add_interface_store("eth0");
+        stores.configure(
+            TokenAuthority::new(TokenMode::Required, Some(b"key".to_vec())),
+            1024,
+            256,
+        );
+        let after = stores.add_interface_store("eth1");
+
+        for store in &[stores.default_store(), before, after] {
+            let mmds = store.lock().unwrap();
+            assert_eq!(mmds.token_authority().mode(), TokenMode::Required);
+            assert_eq!(mmds.data_store_limit(), 1024);
+            assert_eq!(mmds.guest_data_limit(), 256);
+        }
+
+        // Every data store uses the same key, so the tokens are valid on any interface.
+        let token = stores
+            .default_store()
+            .lock()
+            .unwrap()
+            .token_authority()
+            .generate_token(60);
+        assert!(
+            stores
+                .store_for_interface("eth1")
+                .lock()
+                .unwrap()
+                .token_authority()
+                .is_valid(&token)
+        );
+    }
+}
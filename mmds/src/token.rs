@@ -0,0 +1,274 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Session tokens which guard the MMDS against requests a guest process can be tricked into
+//! making, such as those triggered by server side request forgery.
+//!
+//! A guest obtains a token with a `PUT` request on `TOKEN_PATH`, which specifies the token
+//! lifetime using the `TOKEN_TTL_HEADER` header. The token is then presented with subsequent
+//! requests using the `TOKEN_HEADER` header. Tokens are not stored: each one contains its
+//! expiry time, followed by an HMAC-SHA256 of the expiry time computed with a key only known
+//! to the MMDS.
+
+use std::io;
+use std::time::Instant;
+
+use hmac::{Hmac, KeyInit, Mac};
+use sha2::Sha256;
+
+/// The path used for requesting a session token.
+pub const TOKEN_PATH: &str = "/latest/api/token";
+/// The header carrying the session token in guest requests.
+pub const TOKEN_HEADER: &str = "X-metadata-token";
+/// The header carrying the requested lifetime of a new session token, in seconds.
+pub const TOKEN_TTL_HEADER: &str = "X-metadata-token-ttl-seconds";
+/// The minimum lifetime of a session token, in seconds.
+pub const MIN_TOKEN_TTL_SECONDS: u64 = 1;
+/// The maximum lifetime of a session token, in seconds.
+pub const MAX_TOKEN_TTL_SECONDS: u64 = 21600;
+
+const SHA256_DIGEST_LEN: usize = 32;
+const EXPIRY_LEN: usize = 8;
+const TOKEN_LEN: usize = 2 * (EXPIRY_LEN + SHA256_DIGEST_LEN);
+const KEY_LEN: usize = 32;
+
+type HmacSha256 = Hmac<Sha256>;
+
+lazy_static! {
+    // Token expiry times are expressed in seconds relative to this moment, so they are not
+    // affected by changes to the wall clock.
+    static ref CLOCK_START: Instant = Instant::now();
+}
+
+fn now_seconds() -> u64 {
+    CLOCK_START.elapsed().as_secs()
+}
+
+// Computes the HMAC-SHA256 of `message`.
+fn hmac_sha256(key: &[u8], message: &[u8]) -> HmacSha256 {
+    // The unwrap() is safe because HMAC accepts keys of any length.
+    let mut mac = HmacSha256::new_from_slice(key).unwrap();
+    mac.update(message);
+    mac
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+// Generates a key using the getrandom(2) syscall, which blocks until the kernel entropy pool is
+// initialized. Signing tokens with a predictable key would defeat their purpose, so failing to
+// get random bytes is fatal.
+fn random_key() -> Vec<u8> {
+    let mut key = vec![0u8; KEY_LEN];
+    let mut filled = 0;
+    while filled < KEY_LEN {
+        // Safe because the kernel writes at most KEY_LEN - filled bytes past the start of the
+        // unfilled part of key, and we check the return value.
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_getrandom,
+                key[filled..].as_mut_ptr(),
+                KEY_LEN - filled,
+                0,
+            )
+        };
+        if ret < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            panic!("Failed to generate a random MMDS token key: {}", e);
+        }
+        filled += ret as usize;
+    }
+    key
+}
+
+/// Describes whether guest requests have to present a session token.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TokenMode {
+    /// Session tokens can't be requested, and the token header is ignored.
+    Disabled,
+    /// Requests without a token are served, but a token which is present has to be valid.
+    Optional,
+    /// Every request has to present a valid token.
+    Required,
+}
+
+/// Generates and validates session tokens.
+#[derive(Clone)]
+pub struct TokenAuthority {
+    mode: TokenMode,
+    key: Vec<u8>,
+}
+
+impl Default for TokenAuthority {
+    fn default() -> Self {
+        TokenAuthority::new(TokenMode::Disabled, None)
+    }
+}
+
+impl TokenAuthority {
+    /// Creates a new `TokenAuthority`, which signs tokens with `key`. A random key is used when
+    /// `key` is None.
+    pub fn new(mode: TokenMode, key: Option<Vec<u8>>) -> Self {
+        TokenAuthority {
+            mode,
+            key: key.unwrap_or_else(random_key),
+        }
+    }
+
+    /// Returns the token enforcement mode.
+    pub fn mode(&self) -> TokenMode {
+        self.mode
+    }
+
+    /// Generates a token which is valid for `ttl_seconds` seconds.
+    pub fn generate_token(&self, ttl_seconds: u64) -> String {
+        self.generate_token_at(ttl_seconds, now_seconds())
+    }
+
+    /// Returns true if `token` was generated by this authority, and it has not expired yet.
+    pub fn is_valid(&self, token: &str) -> bool {
+        self.is_valid_at(token, now_seconds())
+    }
+
+    fn generate_token_at(&self, ttl_seconds: u64, now: u64) -> String {
+        let mut expiry = [0u8; EXPIRY_LEN];
+        let expiry_seconds = now.saturating_add(ttl_seconds);
+        for (i, byte) in expiry.iter_mut().enumerate() {
+            *byte = (expiry_seconds >> (56 - 8 * i)) as u8;
+        }
+
+        let mac = hmac_sha256(&self.key, &expiry).finalize().into_bytes();
+        format!("{}{}", to_hex(&expiry), to_hex(&mac))
+    }
+
+    fn is_valid_at(&self, token: &str, now: u64) -> bool {
+        if token.len() != TOKEN_LEN {
+            return false;
+        }
+        let bytes = match from_hex(token) {
+            Some(bytes) => bytes,
+            None => return false,
+        };
+        let (expiry, mac) = bytes.split_at(EXPIRY_LEN);
+
+        // The comparison takes the same time regardless of where the first mismatch is, so it
+        // does not reveal anything about the expected value.
+        if hmac_sha256(&self.key, expiry).verify_slice(mac).is_err() {
+            return false;
+        }
+
+        let expiry_seconds = expiry
+            .iter()
+            .fold(0u64, |acc, byte| acc << 8 | u64::from(*byte));
+        now < expiry_seconds
+    }
+}
+
+/// Parses the value of the `TOKEN_TTL_HEADER` header.
+pub fn parse_ttl(value: &str) -> Option<u64> {
+    match value.parse::<u64>() {
+        Ok(ttl) if ttl >= MIN_TOKEN_TTL_SECONDS && ttl <= MAX_TOKEN_TTL_SECONDS => Some(ttl),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex_mac(key: &[u8], message: &[u8]) -> String {
+        to_hex(&hmac_sha256(key, message).finalize().into_bytes())
+    }
+
+    #[test]
+    fn test_hmac_sha256() {
+        // Test cases 1, 2 and 6 from RFC 4231.
+        assert_eq!(
+            hex_mac(&[0x0b; 20], b"Hi There"),
+            "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7"
+        );
+        assert_eq!(
+            hex_mac(b"Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(
+            hex_mac(
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First"
+            ),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+    }
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(to_hex(&[0x00, 0xab, 0x10]), "00ab10");
+        assert_eq!(from_hex("00ab10").unwrap(), vec![0x00, 0xab, 0x10]);
+        assert_eq!(from_hex("00AB10").unwrap(), vec![0x00, 0xab, 0x10]);
+        assert!(from_hex("0").is_none());
+        assert!(from_hex("zz").is_none());
+        assert!(from_hex("éé").is_none());
+    }
+
+    #[test]
+    fn test_tokens() {
+        let authority = TokenAuthority::new(TokenMode::Required, Some(b"a secret key".to_vec()));
+        assert_eq!(authority.mode(), TokenMode::Required);
+
+        let token = authority.generate_token_at(60, 1000);
+        assert_eq!(token.len(), TOKEN_LEN);
+        assert!(authority.is_valid_at(&token, 1000));
+        assert!(authority.is_valid_at(&token, 1059));
+        // The token expires after its TTL.
+        assert!(!authority.is_valid_at(&token, 1060));
+
+        // Tokens generated with another key are rejected.
+        let other_authority = TokenAuthority::new(TokenMode::Required, Some(b"other".to_vec()));
+        assert!(!other_authority.is_valid_at(&token, 1000));
+
+        // Tampering with the expiry time invalidates the token.
+        let tampered = format!("{}{}", "00000000ffffffff", &token[2 * EXPIRY_LEN..]);
+        assert!(!authority.is_valid_at(&tampered, 1000));
+
+        // Malformed tokens.
+        assert!(!authority.is_valid_at("", 1000));
+        assert!(!authority.is_valid_at(&token[1..], 1000));
+        let not_hex = format!("{}x", &token[1..]);
+        assert!(!authority.is_valid_at(&not_hex, 1000));
+
+        // Tokens generated at the current time are valid.
+        let token = authority.generate_token(MIN_TOKEN_TTL_SECONDS);
+        assert!(authority.is_valid(&token));
+
+        // Random keys differ between authorities.
+        let first = TokenAuthority::default();
+        let second = TokenAuthority::default();
+        assert_eq!(first.mode(), TokenMode::Disabled);
+        assert!(first.key != second.key);
+        assert!(!second.is_valid(&first.generate_token(MAX_TOKEN_TTL_SECONDS)));
+    }
+
+    #[test]
+    fn test_parse_ttl() {
+        assert_eq!(parse_ttl("1"), Some(1));
+        assert_eq!(parse_ttl("21600"), Some(MAX_TOKEN_TTL_SECONDS));
+        assert_eq!(parse_ttl("0"), None);
+        assert_eq!(parse_ttl("21601"), None);
+        assert_eq!(parse_ttl("-1"), None);
+        assert_eq!(parse_ttl("abc"), None);
+    }
+}
@@ -0,0 +1,129 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt;
+use std::net::Ipv4Addr;
+
+use serde::de::{Deserialize, Deserializer, Error};
+use serde::ser::{Serialize, Serializer};
+
+/// A block of IPv4 addresses, written in CIDR notation (e.g. `10.0.0.0/8`). A plain address
+/// stands for a block which only contains that address.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ipv4Cidr {
+    // Only the first prefix_len bits can be set.
+    addr: Ipv4Addr,
+    prefix_len: u8,
+}
+
+impl Ipv4Cidr {
+    /// Returns the block of addresses which share their first `prefix_len` bits with `addr`, or
+    /// `None` if `prefix_len` is greater than 32.
+    pub fn new(addr: Ipv4Addr, prefix_len: u8) -> Option<Ipv4Cidr> {
+        if prefix_len > 32 {
+            return None;
+        }
+        let addr = Ipv4Addr::from(u32::from(addr) & Ipv4Cidr::mask(prefix_len));
+        Some(Ipv4Cidr { addr, prefix_len })
+    }
+
+    // The error contains the str that failed to be parsed, for nicer error message generation.
+    pub fn parse_str<S>(s: &S) -> Result<Ipv4Cidr, &str>
+    where
+        S: AsRef<str> + ?Sized,
+    {
+        let mut parts = s.as_ref().splitn(2, '/');
+        // The first part is always there, even if empty.
+        let addr = parts
+            .next()
+            .unwrap()
+            .parse::<Ipv4Addr>()
+            .map_err(|_| s.as_ref())?;
+        let prefix_len = match parts.next() {
+            Some(prefix_len) => prefix_len.parse::<u8>().map_err(|_| s.as_ref())?,
+            None => 32,
+        };
+        Ipv4Cidr::new(addr, prefix_len).ok_or_else(|| s.as_ref())
+    }
+
+    fn mask(prefix_len: u8) -> u32 {
+        // Shifting a u32 by 32 bits would overflow.
+        if prefix_len == 0 {
+            0
+        } else {
+            !0u32 << (32 - u32::from(prefix_len))
+        }
+    }
+
+    /// Checks whether `addr` is part of the block.
+    pub fn contains(&self, addr: Ipv4Addr) -> bool {
+        u32::from(addr) & Ipv4Cidr::mask(self.prefix_len) == u32::from(self.addr)
+    }
+}
+
+impl fmt::Display for Ipv4Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+impl Serialize for Ipv4Cidr {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.to_string().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Ipv4Cidr {
+    fn deserialize<D>(deserializer: D) -> Result<Ipv4Cidr, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        Ipv4Cidr::parse_str(&s)
+            .map_err(|_| D::Error::custom("The provided IPv4 address block is invalid."))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate serde_json;
+
+    use super::*;
+
+    #[test]
+    fn test_ipv4_cidr() {
+        for s in &["", "10.0.0", "10.0.0.0/", "10.0.0.0/33", "10.0.0.0/-1", "10.0.0.0/8/8"] {
+            assert!(Ipv4Cidr::parse_str(s).is_err());
+        }
+
+        // The host bits are ignored.
+        let cidr = Ipv4Cidr::parse_str("172.20.1.2/12").unwrap();
+        assert_eq!(cidr.to_string(), "172.16.0.0/12");
+        assert!(cidr.contains(Ipv4Addr::new(172, 16, 0, 0)));
+        assert!(cidr.contains(Ipv4Addr::new(172, 31, 255, 255)));
+        assert!(!cidr.contains(Ipv4Addr::new(172, 32, 0, 0)));
+        assert!(!cidr.contains(Ipv4Addr::new(172, 15, 255, 255)));
+
+        let cidr = Ipv4Cidr::parse_str("169.254.169.254").unwrap();
+        assert_eq!(cidr, Ipv4Cidr::new(Ipv4Addr::new(169, 254, 169, 254), 32).unwrap());
+        assert!(cidr.contains(Ipv4Addr::new(169, 254, 169, 254)));
+        assert!(!cidr.contains(Ipv4Addr::new(169, 254, 169, 253)));
+
+        let cidr = Ipv4Cidr::parse_str("1.2.3.4/0").unwrap();
+        assert_eq!(cidr.to_string(), "0.0.0.0/0");
+        assert!(cidr.contains(Ipv4Addr::new(255, 255, 255, 255)));
+
+        assert!(Ipv4Cidr::new(Ipv4Addr::new(10, 0, 0, 0), 33).is_none());
+    }
+
+    #[test]
+    fn test_ipv4_cidr_serialization() {
+        let cidr: Ipv4Cidr = serde_json::from_str("\"192.168.0.0/16\"").unwrap();
+        assert!(cidr.contains(Ipv4Addr::new(192, 168, 1, 1)));
+        assert_eq!(serde_json::to_string(&cidr).unwrap(), "\"192.168.0.0/16\"");
+        assert!(serde_json::from_str::<Ipv4Cidr>("\"192.168.0.0/40\"").is_err());
+    }
+}
@@ -16,6 +16,7 @@ extern crate serde;
 extern crate net_gen;
 extern crate sys_util;
 
+mod cidr;
 mod mac;
 mod tap;
 
@@ -24,6 +25,7 @@ use std::mem;
 use std::net;
 use std::os::unix::io::FromRawFd;
 
+pub use cidr::Ipv4Cidr;
 pub use mac::{MacAddr, MAC_ADDR_LEN};
 pub use tap::{Error as TapError, Tap};
 
@@ -55,6 +55,7 @@ extern crate serde_derive;
 extern crate logger;
 
 use serde::de::{self, Deserialize, Deserializer, MapAccess, Visitor};
+use serde::ser::{Serialize, SerializeStruct, Serializer};
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::time::Duration;
 use std::{fmt, io};
@@ -88,11 +89,12 @@ fn gcd(x: u64, y: u64) -> u64 {
 
 /// TokenBucket provides a lower level interface to rate limiting with a
 /// configurable capacity, refill-rate and initial burst.
-#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct TokenBucket {
     // Bucket defining traits.
     size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
     one_time_burst: Option<u64>,
     refill_time: u64,
 
@@ -397,6 +399,25 @@ impl RateLimiter {
         })
     }
 
+    /// Creates a new `RateLimiter` with the same token bucket parameters as this one (including
+    /// whatever is left of the one time bursts), and full budgets.
+    ///
+    /// # Errors
+    ///
+    /// If the timerfd creation fails, an error is returned.
+    pub fn try_clone_config(&self) -> io::Result<Self> {
+        let bandwidth = self.bandwidth.clone().unwrap_or_default();
+        let ops = self.ops.clone().unwrap_or_default();
+        RateLimiter::new(
+            bandwidth.size,
+            bandwidth.one_time_burst,
+            bandwidth.refill_time,
+            ops.size,
+            ops.one_time_burst,
+            ops.refill_time,
+        )
+    }
+
     /// Attempts to consume tokens and returns whether that is possible.
     ///
     /// If rate limiting is disabled on provided `token_type`, this function will always succeed.
@@ -495,6 +516,24 @@ impl AsRawFd for RateLimiter {
     }
 }
 
+// Only the token bucket parameters are serialized, in the same format that is deserialized.
+impl Serialize for RateLimiter {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let len = self.bandwidth.iter().count() + self.ops.iter().count();
+        let mut state = serializer.serialize_struct("RateLimiter", len)?;
+        if let Some(ref bandwidth) = self.bandwidth {
+            state.serialize_field("bandwidth", bandwidth)?;
+        }
+        if let Some(ref ops) = self.ops {
+            state.serialize_field("ops", ops)?;
+        }
+        state.end()
+    }
+}
+
 impl Default for RateLimiter {
     /// Default RateLimiter is a no-op limiter with infinite budget.
     fn default() -> Self {
@@ -797,4 +836,46 @@ mod tests {
         }"#;
         assert!(serde_json::from_str::<RateLimiter>(jstr).is_ok());
     }
+
+    #[test]
+    fn test_rate_limiter_serialization() {
+        let jstr = concat!(
+            r#"{"bandwidth":{"size":1000,"one_time_burst":2000,"refill_time":1000},"#,
+            r#""ops":{"size":10,"refill_time":1000}}"#
+        );
+        let x: RateLimiter = serde_json::from_str(jstr).unwrap();
+        assert_eq!(serde_json::to_string(&x).unwrap(), jstr);
+
+        let jstr = r#"{"ops":{"size":10,"refill_time":1000}}"#;
+        let x: RateLimiter = serde_json::from_str(jstr).unwrap();
+        assert_eq!(serde_json::to_string(&x).unwrap(), jstr);
+
+        assert_eq!(serde_json::to_string(&RateLimiter::default()).unwrap(), "{}");
+    }
+
+    #[test]
+    fn test_try_clone_config() {
+        let mut l = RateLimiter::new(1000, Some(500), 1000, 10, None, 1000).unwrap();
+        assert!(l.consume(1200, TokenType::Bytes));
+        assert!(l.consume(10, TokenType::Ops));
+        assert!(!l.consume(1, TokenType::Ops));
+
+        // The clone starts with full budgets, and only what is left of the one time burst.
+        let mut c = l.try_clone_config().unwrap();
+        assert_eq!(
+            serde_json::to_string(&c).unwrap(),
+            serde_json::to_string(&l).unwrap()
+        );
+        assert!(!c.is_blocked());
+        assert!(c.as_raw_fd() > 0);
+        assert_ne!(c.as_raw_fd(), l.as_raw_fd());
+        assert_eq!(c.get_token_bucket(TokenType::Bytes).unwrap().get_one_time_burst(), 0);
+        assert!(c.consume(1000, TokenType::Bytes));
+        assert!(c.consume(10, TokenType::Ops));
+
+        let c = RateLimiter::default().try_clone_config().unwrap();
+        assert!(c.get_token_bucket(TokenType::Bytes).is_none());
+        assert!(c.get_token_bucket(TokenType::Ops).is_none());
+        assert_eq!(c.as_raw_fd(), -1);
+    }
 }
@@ -4,6 +4,7 @@
 extern crate backtrace;
 #[macro_use(crate_version, crate_authors)]
 extern crate clap;
+extern crate futures;
 extern crate serde_json;
 
 extern crate api_server;
@@ -12,22 +13,36 @@ extern crate jailer;
 #[macro_use]
 extern crate logger;
 extern crate mmds;
+extern crate sys_util;
 extern crate vmm;
 
 use backtrace::Backtrace;
-use clap::{App, Arg};
+use clap::{App, Arg, ArgMatches};
+use futures::sync::oneshot;
+use futures::Future;
 
 use std::io::ErrorKind;
 use std::panic;
 use std::path::PathBuf;
-use std::sync::mpsc::channel;
-use std::sync::{Arc, RwLock};
-
-use api_server::{ApiServer, Error, UnixDomainSocket};
+use std::process;
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use api_server::access::AccessPolicy;
+use api_server::audit::{AuditLog, DEFAULT_AUDIT_LOG_MAX_SIZE};
+use api_server::{
+    put_mmds_data, ApiServer, Error, UnixDomainSocket, DEFAULT_VMM_ACTION_TIMEOUT_MS,
+    VMM_ACTION_QUEUE_SIZE,
+};
 use jailer::FirecrackerContext;
 use logger::{Metric, LOGGER, METRICS};
-use mmds::MMDS;
+use mmds::stores::MmdsStores;
+use sys_util::EventFd;
+use vmm::events::EventPublisher;
+use vmm::vmm_config::config_file::ConfigFile;
 use vmm::vmm_config::instance_info::{InstanceInfo, InstanceState};
+use vmm::{OutcomeSender, VmmAction, VmmData, VmmRequest};
 
 const DEFAULT_API_SOCK_PATH: &str = "/tmp/firecracker.socket";
 const DEFAULT_INSTANCE_ID: &str = "anonymous-instance";
@@ -68,11 +83,67 @@ fn main() {
                 .help("Path to unix domain socket used by the API")
                 .default_value(DEFAULT_API_SOCK_PATH)
                 .takes_value(true),
+        ).arg(
+            Arg::with_name("api_allowed_uids")
+                .long("api-allowed-uids")
+                .help("Comma-separated UIDs of the processes granted full access to the API")
+                .takes_value(true)
+                .use_delimiter(true)
+                .validator(validate_id),
+        ).arg(
+            Arg::with_name("api_allowed_gids")
+                .long("api-allowed-gids")
+                .help("Comma-separated GIDs of the processes granted full access to the API")
+                .takes_value(true)
+                .use_delimiter(true)
+                .validator(validate_id),
+        ).arg(
+            Arg::with_name("api_read_only_uids")
+                .long("api-read-only-uids")
+                .help("Comma-separated UIDs of the processes only allowed GET requests on the API")
+                .takes_value(true)
+                .use_delimiter(true)
+                .validator(validate_id),
+        ).arg(
+            Arg::with_name("api_read_only_gids")
+                .long("api-read-only-gids")
+                .help("Comma-separated GIDs of the processes only allowed GET requests on the API")
+                .takes_value(true)
+                .use_delimiter(true)
+                .validator(validate_id),
+        ).arg(
+            Arg::with_name("api_request_timeout")
+                .long("api-request-timeout")
+                .help("Time in milliseconds an API request waits for the VMM to handle it")
+                .takes_value(true)
+                .validator(validate_positive),
+        ).arg(
+            Arg::with_name("audit_log")
+                .long("audit-log")
+                .help("Path to a FIFO or file recording the API requests which change the microVM")
+                .takes_value(true),
+        ).arg(
+            Arg::with_name("audit_log_max_size")
+                .long("audit-log-max-size")
+                .help("Size in bytes beyond which the audit log file is rotated")
+                .takes_value(true)
+                .validator(validate_positive)
+                .requires("audit_log"),
         ).arg(
             Arg::with_name("context")
                 .long("context")
                 .help("Additional parameters sent to Firecracker.")
                 .takes_value(true),
+        ).arg(
+            Arg::with_name("config_file")
+                .long("config-file")
+                .help("Path to a JSON file describing the microVM, which is started right away")
+                .takes_value(true),
+        ).arg(
+            Arg::with_name("no_api")
+                .long("no-api")
+                .help("Don't expose the API socket (only valid with --config-file)")
+                .requires("config_file"),
         ).get_matches();
 
     let bind_path = cmd_arguments
@@ -80,6 +151,34 @@ fn main() {
         .map(|s| PathBuf::from(s))
         .expect("Missing argument: api_sock");
 
+    // When none of these is given, the API is open to anyone who can connect to the socket.
+    let access_policy = AccessPolicy {
+        allowed_uids: id_list(&cmd_arguments, "api_allowed_uids"),
+        allowed_gids: id_list(&cmd_arguments, "api_allowed_gids"),
+        read_only_uids: id_list(&cmd_arguments, "api_read_only_uids"),
+        read_only_gids: id_list(&cmd_arguments, "api_read_only_gids"),
+    };
+
+    let audit_log = cmd_arguments.value_of("audit_log").map(|path| {
+        let max_size = cmd_arguments
+            .value_of("audit_log_max_size")
+            // The unwrap() is safe because the value went through validate_positive().
+            .map(|s| s.parse::<u64>().unwrap())
+            .unwrap_or(DEFAULT_AUDIT_LOG_MAX_SIZE);
+        AuditLog::new(path, max_size).unwrap_or_else(|e| {
+            error!("Failed to open the audit log {}: {}", path, e);
+            process::exit(1);
+        })
+    });
+
+    let vmm_action_timeout = Duration::from_millis(
+        cmd_arguments
+            .value_of("api_request_timeout")
+            // The unwrap() is safe because the value went through validate_positive().
+            .map(|s| s.parse::<u64>().unwrap())
+            .unwrap_or(DEFAULT_VMM_ACTION_TIMEOUT_MS),
+    );
+
     let mut instance_id = String::from(DEFAULT_INSTANCE_ID);
     let mut seccomp_level = 0;
     let mut start_time_us = None;
@@ -99,11 +198,20 @@ fn main() {
     let shared_info = Arc::new(RwLock::new(InstanceInfo {
         state: InstanceState::Uninitialized,
         id: instance_id,
+        vmm_version: crate_version!().to_string(),
     }));
-    let mmds_info = MMDS.clone();
-    let (to_vmm, from_api) = channel();
-    let server =
-        ApiServer::new(mmds_info, shared_info.clone(), to_vmm).expect("Cannot create API server");
+    let mmds_stores = Arc::new(Mutex::new(MmdsStores::default()));
+    let events = Arc::new(EventPublisher::default());
+    let (to_vmm, from_api) = sync_channel(VMM_ACTION_QUEUE_SIZE);
+    let server = ApiServer::new(
+        mmds_stores.clone(),
+        shared_info.clone(),
+        events.clone(),
+        access_policy,
+        audit_log,
+        to_vmm.clone(),
+        vmm_action_timeout,
+    ).expect("Cannot create API server");
 
     let api_event_fd = server
         .get_event_fd_clone()
@@ -115,8 +223,35 @@ fn main() {
         None
     };
 
-    let _vmm_thread_handle =
-        vmm::start_vmm_thread(shared_info, api_event_fd, from_api, seccomp_level, kvm_fd);
+    let vmm_thread_handle = vmm::start_vmm_thread(
+        shared_info,
+        mmds_stores.clone(),
+        events,
+        api_event_fd,
+        from_api,
+        seccomp_level,
+        kvm_fd,
+    );
+
+    if let Some(path) = cmd_arguments.value_of("config_file") {
+        let vmm_event_fd = server
+            .get_event_fd_clone()
+            .expect("Cannot clone API eventFD.");
+        let vmm_action_sender = VmmActionSender {
+            sender: to_vmm,
+            event_fd: vmm_event_fd,
+        };
+        if let Err(e) = boot_from_config_file(path, &mmds_stores, &vmm_action_sender) {
+            error!("Failed to boot the microVM from {}: {}", path, e);
+            process::exit(1);
+        }
+    }
+
+    if cmd_arguments.is_present("no_api") {
+        // The VMM thread terminates the process when the microVM stops.
+        vmm_thread_handle.join().expect("VMM thread panicked.");
+        return;
+    }
 
     let uds_path_or_fd = if is_jailed {
         UnixDomainSocket::Fd(jailer::LISTENER_FD)
@@ -143,6 +278,98 @@ fn main() {
     }
 }
 
+// Checks a UID or GID passed on the command line, so that clap reports invalid ones.
+fn validate_id(id: String) -> Result<(), String> {
+    id.parse::<u32>()
+        .map(|_| ())
+        .map_err(|_| format!("{} is not a valid ID", id))
+}
+
+// Checks a number passed on the command line which must be greater than 0, so that clap reports
+// invalid ones.
+fn validate_positive(value: String) -> Result<(), String> {
+    match value.parse::<u64>() {
+        Ok(0) | Err(_) => Err(format!("{} is not a positive number", value)),
+        Ok(_) => Ok(()),
+    }
+}
+
+// Parses the UIDs or GIDs passed through the `name` argument.
+fn id_list(cmd_arguments: &ArgMatches, name: &str) -> Vec<u32> {
+    cmd_arguments
+        .values_of(name)
+        // The unwrap() is safe because the values went through validate_id().
+        .map(|values| values.map(|id| id.parse::<u32>().unwrap()).collect())
+        .unwrap_or_default()
+}
+
+// Hands actions over to the VMM thread, the same way the API server does.
+struct VmmActionSender {
+    sender: SyncSender<Box<VmmRequest>>,
+    event_fd: EventFd,
+}
+
+impl VmmActionSender {
+    // Sends the action built by `make_action` to the VMM, and waits for its outcome.
+    fn run<F>(&self, make_action: F) -> Result<VmmData, String>
+    where
+        F: FnOnce(OutcomeSender) -> VmmAction,
+    {
+        let (outcome_sender, outcome_receiver) = oneshot::channel();
+        self.sender
+            .send(Box::new(VmmRequest::from(make_action(outcome_sender))))
+            .map_err(|_| String::from("The VMM thread is gone."))?;
+        self.event_fd
+            .write(1)
+            .map_err(|e| format!("Cannot notify the VMM thread: {:?}", e))?;
+        outcome_receiver
+            .wait()
+            .map_err(|_| String::from("The VMM thread is gone."))?
+            .map_err(|e| e.to_string())
+    }
+}
+
+// Configures the microVM as described by the config file found at `path`, and starts it. Each
+// part of the configuration goes through the same VMM action as the matching API request.
+fn boot_from_config_file(
+    path: &str,
+    mmds_stores: &Mutex<MmdsStores>,
+    vmm: &VmmActionSender,
+) -> Result<(), String> {
+    let config = ConfigFile::from_path(path).map_err(|e| e.to_string())?;
+
+    // The logger goes first, so it gets to record the rest of the configuration.
+    if let Some(logger) = config.logger {
+        vmm.run(|sender| VmmAction::ConfigureLogger(logger, sender))?;
+    }
+    if let Some(machine_config) = config.machine_config {
+        vmm.run(|sender| VmmAction::SetVmConfiguration(machine_config, sender))?;
+    }
+    let boot_source = config.boot_source;
+    vmm.run(|sender| VmmAction::ConfigureBootSource(boot_source, sender))?;
+    for drive in config.drives {
+        vmm.run(|sender| VmmAction::InsertBlockDevice(drive, sender))?;
+    }
+    for netif in config.network_interfaces {
+        vmm.run(|sender| VmmAction::InsertNetworkDevice(netif, sender))?;
+    }
+    if let Some(mmds_config) = config.mmds_config {
+        vmm.run(|sender| VmmAction::SetMmdsConfiguration(mmds_config, sender))?;
+    }
+    // The contents go through the same path as a PUT request on /mmds.
+    if let Some(data) = config.mmds {
+        let store = mmds_stores
+            .lock()
+            .expect("Failed to acquire lock on MMDS stores")
+            .default_store();
+        let mut mmds = store.lock().expect("Failed to acquire lock on MMDS info");
+        put_mmds_data(&mut mmds, data)
+            .map_err(|e| format!("Cannot set the MMDS contents: {:?}", e))?;
+    }
+
+    vmm.run(VmmAction::StartMicroVm).map(|_| ())
+}
+
 #[cfg(test)]
 mod tests {
     extern crate tempfile;
@@ -153,10 +380,15 @@ mod tests {
     use std::fs::File;
     use std::io::BufRead;
     use std::io::BufReader;
+    use std::io::Write;
     use std::path::Path;
+    use std::sync::mpsc::Receiver;
     use std::time::Duration;
     use std::{fs, thread};
 
+    use vmm::vmm_config::machine_config::VmConfigError;
+    use vmm::{ErrorKind as VmmErrorKind, VmmActionError};
+
     /// Look through the log for lines that match expectations.
     /// expectations is a list of tuples of words we're looking for.
     /// A tuple matches a line if all the words in the tuple can be found on that line.
@@ -242,4 +474,192 @@ mod tests {
         // Clean up
         fs::remove_file(DEFAULT_API_SOCK_PATH).expect("failure in removing socket file");
     }
+
+    // Plays the part of the VMM thread, by answering the first `count` actions with `Ok` (or with
+    // an error, for the one at index `fail_at`), and returns their names.
+    fn answer_actions(
+        receiver: Receiver<Box<VmmRequest>>,
+        count: usize,
+        fail_at: Option<usize>,
+    ) -> thread::JoinHandle<Vec<String>> {
+        thread::spawn(move || {
+            let mut actions = Vec::new();
+            for index in 0..count {
+                let (name, sender) = match receiver.recv().unwrap().take().unwrap() {
+                    VmmAction::ConfigureBootSource(_, sender) => ("ConfigureBootSource", sender),
+                    VmmAction::ConfigureLogger(_, sender) => ("ConfigureLogger", sender),
+                    VmmAction::InsertBlockDevice(_, sender) => ("InsertBlockDevice", sender),
+                    VmmAction::InsertNetworkDevice(_, sender) => ("InsertNetworkDevice", sender),
+                    VmmAction::SetMmdsConfiguration(_, sender) => ("SetMmdsConfiguration", sender),
+                    VmmAction::SetVmConfiguration(_, sender) => ("SetVmConfiguration", sender),
+                    VmmAction::StartMicroVm(sender) => ("StartMicroVm", sender),
+                    _ => panic!("Unexpected action."),
+                };
+                actions.push(name.to_string());
+                let outcome = if fail_at == Some(index) {
+                    Err(VmmActionError::MachineConfig(
+                        VmmErrorKind::User,
+                        VmConfigError::InvalidVcpuCount,
+                    ))
+                } else {
+                    Ok(VmmData::Empty)
+                };
+                sender.send(outcome).unwrap();
+            }
+            // No other actions should follow.
+            assert!(receiver.recv().is_err());
+            actions
+        })
+    }
+
+    #[test]
+    fn test_id_list() {
+        let app = || {
+            App::new("firecracker").arg(
+                Arg::with_name("api_allowed_uids")
+                    .long("api-allowed-uids")
+                    .takes_value(true)
+                    .use_delimiter(true)
+                    .validator(validate_id),
+            )
+        };
+
+        let matches = app()
+            .get_matches_from_safe(vec!["firecracker", "--api-allowed-uids", "0,1000"])
+            .unwrap();
+        assert_eq!(id_list(&matches, "api_allowed_uids"), vec![0, 1000]);
+        let matches = app().get_matches_from_safe(vec!["firecracker"]).unwrap();
+        assert!(id_list(&matches, "api_allowed_uids").is_empty());
+
+        // Invalid IDs are reported as CLI errors, wherever they are in the list.
+        for ids in ["root", "1000,-1", "1000,4294967296"].iter() {
+            assert!(
+                app()
+                    .get_matches_from_safe(vec!["firecracker", "--api-allowed-uids", ids])
+                    .is_err()
+            );
+        }
+    }
+
+    #[test]
+    fn test_validate_positive() {
+        let app = || {
+            App::new("firecracker")
+                .arg(
+                    Arg::with_name("api_request_timeout")
+                        .long("api-request-timeout")
+                        .takes_value(true)
+                        .validator(validate_positive),
+                ).arg(
+                    Arg::with_name("audit_log_max_size")
+                        .long("audit-log-max-size")
+                        .takes_value(true)
+                        .validator(validate_positive),
+                )
+        };
+
+        let matches = app()
+            .get_matches_from_safe(vec![
+                "firecracker",
+                "--api-request-timeout",
+                "1500",
+                "--audit-log-max-size",
+                "4096",
+            ]).unwrap();
+        assert_eq!(matches.value_of("api_request_timeout"), Some("1500"));
+        assert_eq!(matches.value_of("audit_log_max_size"), Some("4096"));
+
+        // Invalid values are reported as CLI errors, instead of panicking later on.
+        for arg in ["--api-request-timeout", "--audit-log-max-size"].iter() {
+            for value in ["", "0", "-1", "1.5", "30s", "18446744073709551616"].iter() {
+                assert!(
+                    app()
+                        .get_matches_from_safe(vec!["firecracker", arg, value])
+                        .is_err()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_boot_from_config_file() {
+        let mut config_file = NamedTempFile::new().unwrap();
+        config_file
+            .write_all(
+                br#"{
+                    "boot-source": {"kernel_image_path": "/tmp/vmlinux"},
+                    "machine-config": {"vcpu_count": 2},
+                    "drives": [
+                        {
+                            "drive_id": "rootfs",
+                            "path_on_host": "/tmp/rootfs",
+                            "is_root_device": true,
+                            "is_read_only": false
+                        },
+                        {
+                            "drive_id": "scratch",
+                            "path_on_host": "/tmp/scratch",
+                            "is_root_device": false,
+                            "is_read_only": false
+                        }
+                    ],
+                    "logger": {"log_fifo": "/tmp/log", "metrics_fifo": "/tmp/metrics"},
+                    "mmds": {"foo": "bar"}
+                }"#,
+            ).unwrap();
+        let path = config_file.path().to_str().unwrap();
+        let mmds_stores = Mutex::new(MmdsStores::default());
+        let mmds_count = METRICS.put_api_requests.mmds_count.count();
+
+        let (sender, receiver) = sync_channel(VMM_ACTION_QUEUE_SIZE);
+        let vmm_thread = answer_actions(receiver, 6, None);
+        {
+            let vmm = VmmActionSender {
+                sender,
+                event_fd: EventFd::new().unwrap(),
+            };
+            boot_from_config_file(path, &mmds_stores, &vmm).unwrap();
+        }
+        assert_eq!(
+            vmm_thread.join().unwrap(),
+            vec![
+                "ConfigureLogger",
+                "SetVmConfiguration",
+                "ConfigureBootSource",
+                "InsertBlockDevice",
+                "InsertBlockDevice",
+                "StartMicroVm",
+            ]
+        );
+        let store = mmds_stores.lock().unwrap().default_store();
+        assert_eq!(
+            store.lock().unwrap().get_value("/foo".to_string()).unwrap(),
+            vec![String::from("bar")]
+        );
+        // The contents are set the same way as through a PUT request on /mmds.
+        assert_eq!(METRICS.put_api_requests.mmds_count.count(), mmds_count + 1);
+
+        // A failed action stops the boot sequence.
+        let (sender, receiver) = sync_channel(VMM_ACTION_QUEUE_SIZE);
+        let vmm_thread = answer_actions(receiver, 2, Some(1));
+        {
+            let vmm = VmmActionSender {
+                sender,
+                event_fd: EventFd::new().unwrap(),
+            };
+            assert!(boot_from_config_file(path, &mmds_stores, &vmm).is_err());
+        }
+        assert_eq!(
+            vmm_thread.join().unwrap(),
+            vec!["ConfigureLogger", "SetVmConfiguration"]
+        );
+
+        // So does a missing config file.
+        let (sender, _receiver) = sync_channel(VMM_ACTION_QUEUE_SIZE);
+        let vmm = VmmActionSender {
+            sender,
+            event_fd: EventFd::new().unwrap(),
+        };
+        assert!(boot_from_config_file("/this/path/does/not/exist", &mmds_stores, &vmm).is_err());
+    }
 }
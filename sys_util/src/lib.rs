@@ -14,6 +14,7 @@ pub mod ioctl;
 
 mod errno;
 mod eventfd;
+mod peer_cred;
 mod signal;
 mod struct_util;
 mod terminal;
@@ -21,6 +22,7 @@ mod terminal;
 pub use errno::{errno_result, Error, Result};
 pub use eventfd::*;
 pub use ioctl::*;
+pub use peer_cred::*;
 pub use signal::*;
 pub use struct_util::*;
 pub use terminal::*;
@@ -0,0 +1,72 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::mem;
+use std::os::unix::io::AsRawFd;
+
+use libc::{c_void, getsockopt, socklen_t, ucred, SOL_SOCKET, SO_PEERCRED};
+
+use {errno_result, Result};
+
+/// The credentials of the process on the other end of a Unix domain socket, as they were when the
+/// socket was connected.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PeerCredentials {
+    /// Process ID of the peer.
+    pub pid: i32,
+    /// Effective user ID of the peer.
+    pub uid: u32,
+    /// Effective group ID of the peer.
+    pub gid: u32,
+}
+
+/// Returns the credentials of the peer of the connected Unix domain socket `sock` (man 7 unix,
+/// SO_PEERCRED).
+pub fn get_peer_credentials<T: AsRawFd>(sock: &T) -> Result<PeerCredentials> {
+    let mut cred: ucred = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<ucred>() as socklen_t;
+    // This is safe because the kernel writes at most `len` bytes to `cred`, and we check the
+    // return value.
+    let ret = unsafe {
+        getsockopt(
+            sock.as_raw_fd(),
+            SOL_SOCKET,
+            SO_PEERCRED,
+            &mut cred as *mut ucred as *mut c_void,
+            &mut len,
+        )
+    };
+    if ret < 0 {
+        return errno_result();
+    }
+    Ok(PeerCredentials {
+        pid: cred.pid,
+        uid: cred.uid,
+        gid: cred.gid,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::File;
+    use std::os::unix::net::UnixStream;
+    use std::process;
+
+    use libc::{getegid, geteuid};
+
+    #[test]
+    fn test_get_peer_credentials() {
+        let (sock, _peer) = UnixStream::pair().unwrap();
+        let cred = get_peer_credentials(&sock).unwrap();
+        assert_eq!(cred.pid as u32, process::id());
+        // This is safe because the calls have no side effects.
+        assert_eq!(cred.uid, unsafe { geteuid() });
+        assert_eq!(cred.gid, unsafe { getegid() });
+
+        // Only sockets have peers.
+        let file = File::open("/dev/null").unwrap();
+        assert!(get_peer_credentials(&file).is_err());
+    }
+}
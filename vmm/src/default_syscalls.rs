@@ -50,6 +50,17 @@ pub const ALLOWED_SYSCALLS: &[i64] = &[
     libc::SYS_eventfd2,
     libc::SYS_epoll_create1,
     libc::SYS_getrandom,
+    libc::SYS_timerfd_settime,
+];
+
+/// List of syscalls which are only allowed when a guest network interface is backed by the
+/// user-mode network stack, which proxies guest flows over host sockets.
+pub const USER_NET_SYSCALLS: &[i64] = &[
+    libc::SYS_connect,
+    libc::SYS_getsockopt,
+    libc::SYS_recvfrom,
+    libc::SYS_sendto,
+    libc::SYS_shutdown,
 ];
 
 // See /usr/include/x86_64-linux-gnu/sys/epoll.h
@@ -121,11 +132,38 @@ const MAP_NORESERVE: u64 = 0x4000;
 
 // See /usr/include/x86_64-linux-gnu/bits/socket.h
 const PF_LOCAL: u64 = 1;
+const AF_INET: u64 = 2;
+const MSG_NOSIGNAL: u64 = 0x4000;
+const SHUT_WR: u64 = 1;
+
+// See /usr/include/x86_64-linux-gnu/bits/socket_type.h
+const SOCK_STREAM: u64 = 1;
+const SOCK_DGRAM: u64 = 2;
+const SOCK_NONBLOCK: u64 = 0x800;
+const SOCK_CLOEXEC: u64 = 0x80000;
+
+// See /usr/include/asm-generic/socket.h
+const SOL_SOCKET: u64 = 1;
+const SO_ERROR: u64 = 4;
+
+// See /usr/include/linux/in.h
+const SOCKADDR_IN_LEN: u64 = 16;
+
+/// Returns the syscalls allowed by the basic filter. The ones in `USER_NET_SYSCALLS` are only
+/// included if `user_net` is set.
+pub fn allowed_syscalls(user_net: bool) -> Vec<i64> {
+    let mut syscalls = ALLOWED_SYSCALLS.to_vec();
+    if user_net {
+        syscalls.extend_from_slice(USER_NET_SYSCALLS);
+    }
+    syscalls
+}
 
 /// The default context containing the white listed syscall rules required by `Firecracker` to
-/// function.
-pub fn default_context() -> Result<SeccompFilterContext, Error> {
-    Ok(SeccompFilterContext::new(
+/// function. The rules required by the user-mode network stack are only included if `user_net`
+/// is set, so that guests without such an interface cannot get the VMM to open host sockets.
+pub fn default_context(user_net: bool) -> Result<SeccompFilterContext, Error> {
+    let mut context = SeccompFilterContext::new(
         vec![
             (
                 libc::SYS_accept,
@@ -252,6 +290,17 @@ pub fn default_context() -> Result<SeccompFilterContext, Error> {
                     ],
                 ),
             ),
+            // Used without flags to generate the key which signs MMDS session tokens.
+            (
+                libc::SYS_getrandom,
+                (
+                    0,
+                    vec![SeccompRule::new(
+                        vec![SeccompCondition::new(2, SeccompCmpOp::Eq, 0)?],
+                        SeccompAction::Allow,
+                    )],
+                ),
+            ),
             (
                 libc::SYS_ioctl,
                 (
@@ -592,6 +641,7 @@ pub fn default_context() -> Result<SeccompFilterContext, Error> {
                 libc::SYS_stat,
                 (0, vec![SeccompRule::new(vec![], SeccompAction::Allow)]),
             ),
+            // Arms the timers of the net devices, e.g. for MMDS long-poll deadlines.
             (
                 libc::SYS_timerfd_settime,
                 (0, vec![SeccompRule::new(vec![], SeccompAction::Allow)]),
@@ -607,14 +657,300 @@ pub fn default_context() -> Result<SeccompFilterContext, Error> {
         ].into_iter()
         .collect(),
         SeccompAction::Trap,
-    )?)
+    )?;
+    if user_net {
+        add_user_net_rules(&mut context)?;
+    }
+    Ok(context)
+}
+
+// Adds the rules required by the user-mode network stack to `context`.
+fn add_user_net_rules(context: &mut SeccompFilterContext) -> Result<(), Error> {
+    // The user-mode network stack only connects IPv4 sockets.
+    context.add_rules(
+        libc::SYS_connect,
+        None,
+        vec![SeccompRule::new(
+            vec![SeccompCondition::new(2, SeccompCmpOp::Eq, SOCKADDR_IN_LEN)?],
+            SeccompAction::Allow,
+        )],
+    )?;
+    // Checks the outcome of non-blocking connects.
+    context.add_rules(
+        libc::SYS_getsockopt,
+        None,
+        vec![SeccompRule::new(
+            vec![
+                SeccompCondition::new(1, SeccompCmpOp::Eq, SOL_SOCKET)?,
+                SeccompCondition::new(2, SeccompCmpOp::Eq, SO_ERROR)?,
+            ],
+            SeccompAction::Allow,
+        )],
+    )?;
+    // recv() on connected sockets.
+    context.add_rules(
+        libc::SYS_recvfrom,
+        None,
+        vec![SeccompRule::new(
+            vec![
+                SeccompCondition::new(3, SeccompCmpOp::Eq, 0)?,
+                SeccompCondition::new(4, SeccompCmpOp::Eq, 0)?,
+            ],
+            SeccompAction::Allow,
+        )],
+    )?;
+    // send() on connected sockets.
+    context.add_rules(
+        libc::SYS_sendto,
+        None,
+        vec![SeccompRule::new(
+            vec![
+                SeccompCondition::new(3, SeccompCmpOp::Eq, MSG_NOSIGNAL)?,
+                SeccompCondition::new(4, SeccompCmpOp::Eq, 0)?,
+            ],
+            SeccompAction::Allow,
+        )],
+    )?;
+    context.add_rules(
+        libc::SYS_shutdown,
+        None,
+        vec![SeccompRule::new(
+            vec![SeccompCondition::new(1, SeccompCmpOp::Eq, SHUT_WR)?],
+            SeccompAction::Allow,
+        )],
+    )?;
+    // The TCP and UDP sockets proxying guest flows.
+    context.add_rules(
+        libc::SYS_socket,
+        None,
+        vec![
+            SeccompRule::new(
+                vec![
+                    SeccompCondition::new(0, SeccompCmpOp::Eq, AF_INET)?,
+                    SeccompCondition::new(
+                        1,
+                        SeccompCmpOp::Eq,
+                        SOCK_STREAM | SOCK_NONBLOCK | SOCK_CLOEXEC,
+                    )?,
+                    SeccompCondition::new(2, SeccompCmpOp::Eq, 0)?,
+                ],
+                SeccompAction::Allow,
+            ),
+            SeccompRule::new(
+                vec![
+                    SeccompCondition::new(0, SeccompCmpOp::Eq, AF_INET)?,
+                    SeccompCondition::new(
+                        1,
+                        SeccompCmpOp::Eq,
+                        SOCK_DGRAM | SOCK_NONBLOCK | SOCK_CLOEXEC,
+                    )?,
+                    SeccompCondition::new(2, SeccompCmpOp::Eq, 0)?,
+                ],
+                SeccompAction::Allow,
+            ),
+        ],
+    )
 }
 
 #[cfg(test)]
 mod tests {
+    extern crate dumbo;
     extern crate libc;
     extern crate seccomp;
 
+    use std::io::{ErrorKind, Read, Write};
+    use std::net::{Shutdown, SocketAddr, SocketAddrV4, TcpListener, UdpSocket};
+    use std::time::Duration;
+
+    use self::dumbo::user_net::{connect_tcp, connect_udp};
+    use mmds::token::{TokenAuthority, TokenMode};
+    use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
+
+    // Runs f in a forked child, under the given seccomp level, and returns the wait status of the
+    // child. The child exits with 0 if f returns true, and with 1 otherwise.
+    fn run_with_seccomp<F: FnOnce() -> bool>(level: seccomp::SeccompLevel, f: F) -> libc::c_int {
+        // Safe because the child only runs f and exits, without returning to the test harness.
+        let pid = unsafe { libc::fork() };
+        assert!(pid >= 0);
+        if pid == 0 {
+            let ok = seccomp::setup_seccomp(level).is_ok() && f();
+            // Safe because we are exiting the child process.
+            unsafe { libc::_exit(if ok { 0 } else { 1 }) };
+        }
+        let mut status = 0;
+        // Safe because status is a valid location, and we check the return value.
+        assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+        status
+    }
+
+    // The filters are installed per thread, and the advanced one does not allow exit_group.
+    fn advanced_context(user_net: bool) -> seccomp::SeccompFilterContext {
+        let mut context = super::default_context(user_net).unwrap();
+        context
+            .add_rules(
+                libc::SYS_exit_group,
+                None,
+                vec![seccomp::SeccompRule::new(
+                    vec![],
+                    seccomp::SeccompAction::Allow,
+                )],
+            ).unwrap();
+        context
+    }
+
+    // Does what the user-mode network stack does with its host sockets.
+    fn proxy_flows(tcp_addr: SocketAddrV4, udp_addr: SocketAddrV4) -> bool {
+        let mut stream = match connect_tcp(tcp_addr) {
+            Ok(stream) => stream,
+            Err(_) => return false,
+        };
+        // Wait for the non-blocking connect to complete.
+        let mut written = 0;
+        for _ in 0..10000 {
+            if let Ok(n) = stream.write(b"ping") {
+                written = n;
+                break;
+            }
+        }
+        if written != 4 || stream.take_error().is_err() || stream.shutdown(Shutdown::Write).is_err()
+        {
+            return false;
+        }
+        // Nothing was sent back on either socket, so the reads must fail with EAGAIN rather than
+        // be trapped.
+        let mut buf = [0u8; 4];
+        let tcp_would_block = match stream.read(&mut buf) {
+            Err(e) => e.kind() == ErrorKind::WouldBlock,
+            Ok(_) => false,
+        };
+
+        let sock = match connect_udp(udp_addr) {
+            Ok(sock) => sock,
+            Err(_) => return false,
+        };
+        let udp_would_block = match sock.recv(&mut buf) {
+            Err(e) => e.kind() == ErrorKind::WouldBlock,
+            Ok(_) => false,
+        };
+        tcp_would_block && sock.send(b"ping").ok() == Some(4) && udp_would_block
+    }
+
+    fn local_addr(addr: SocketAddr) -> SocketAddrV4 {
+        match addr {
+            SocketAddr::V4(addr) => addr,
+            SocketAddr::V6(_) => panic!("unexpected IPv6 address"),
+        }
+    }
+
+    #[test]
+    fn test_timerfd_seccomp() {
+        for advanced in [false, true].iter() {
+            let level = if *advanced {
+                seccomp::SeccompLevel::Advanced(advanced_context(false))
+            } else {
+                seccomp::SeccompLevel::Basic(super::ALLOWED_SYSCALLS)
+            };
+            // The net devices create their timers when activated, on the vCPU threads, and arm them
+            // from the VMM thread.
+            let mut timer = TimerFd::new_custom(ClockId::Monotonic, true, true).unwrap();
+            let status = run_with_seccomp(level, move || {
+                timer.set_state(
+                    TimerState::Oneshot(Duration::from_millis(100)),
+                    SetTimeFlags::Default,
+                );
+                timer.set_state(TimerState::Disarmed, SetTimeFlags::Default);
+                true
+            });
+            assert_eq!(status, 0);
+        }
+    }
+
+    #[test]
+    fn test_token_key_seccomp() {
+        for advanced in [false, true].iter() {
+            let level = if *advanced {
+                seccomp::SeccompLevel::Advanced(advanced_context(false))
+            } else {
+                seccomp::SeccompLevel::Basic(super::ALLOWED_SYSCALLS)
+            };
+            // Building an authority without a key generates a random one.
+            let status = run_with_seccomp(level, || {
+                TokenAuthority::new(TokenMode::Required, None).mode() == TokenMode::Required
+            });
+            assert_eq!(status, 0);
+        }
+    }
+
+    #[test]
+    fn test_user_net_seccomp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let tcp_addr = local_addr(listener.local_addr().unwrap());
+        let udp_addr = local_addr(udp.local_addr().unwrap());
+        let allowed_syscalls = super::allowed_syscalls(true);
+
+        for advanced in [false, true].iter() {
+            let level = if *advanced {
+                seccomp::SeccompLevel::Advanced(advanced_context(true))
+            } else {
+                seccomp::SeccompLevel::Basic(&allowed_syscalls)
+            };
+            let status = run_with_seccomp(level, || proxy_flows(tcp_addr, udp_addr));
+            // Exited normally, with status 0.
+            assert_eq!(status, 0);
+
+            // Check that both flows actually reached their peers.
+            let mut buf = [0u8; 4];
+            let (mut conn, _) = listener.accept().unwrap();
+            conn.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf, b"ping");
+            assert_eq!(udp.recv(&mut buf).unwrap(), 4);
+        }
+
+        // Only IPv4 sockets are allowed by the advanced filter.
+        let status = run_with_seccomp(
+            seccomp::SeccompLevel::Advanced(advanced_context(true)),
+            || {
+                // Safe because we check the return value.
+                let fd = unsafe { libc::socket(libc::AF_INET6, libc::SOCK_STREAM, 0) };
+                fd >= 0
+            },
+        );
+        // Killed by the signal in the low 7 bits of the wait status.
+        assert_eq!(status & 0x7f, libc::SIGSYS);
+    }
+
+    #[test]
+    fn test_user_net_seccomp_disabled() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let tcp_addr = local_addr(listener.local_addr().unwrap());
+
+        // Without a user-mode network interface, the advanced filter doesn't allow opening the
+        // sockets of the user-mode network stack.
+        let status = run_with_seccomp(
+            seccomp::SeccompLevel::Advanced(advanced_context(false)),
+            || {
+                // Safe because we check the return value.
+                let fd = unsafe {
+                    libc::socket(
+                        libc::AF_INET,
+                        libc::SOCK_STREAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                        0,
+                    )
+                };
+                fd >= 0
+            },
+        );
+        assert_eq!(status & 0x7f, libc::SIGSYS);
+
+        // The basic filter doesn't look at the socket arguments, but doesn't allow connecting it.
+        let status = run_with_seccomp(
+            seccomp::SeccompLevel::Basic(super::ALLOWED_SYSCALLS),
+            || connect_tcp(tcp_addr).is_ok(),
+        );
+        assert_eq!(status & 0x7f, libc::SIGSYS);
+    }
+
     #[test]
     #[cfg(target_env = "musl")]
     fn test_basic_seccomp() {
@@ -627,7 +963,7 @@ mod tests {
     #[cfg(target_env = "musl")]
     fn test_advanced_seccomp() {
         // Sets up context with additional rules required by the test.
-        let mut context = super::default_context().unwrap();
+        let mut context = super::default_context(false).unwrap();
         assert!(
             context
                 .add_rules(
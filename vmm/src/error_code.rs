@@ -0,0 +1,258 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use serde::{Serialize, Serializer};
+
+/// Identifies the errors reported over the API. Unlike the error messages, the codes are stable:
+/// once released, a code keeps its meaning, so API clients can rely on them to handle errors.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorCode {
+    // API requests.
+    /// The client is not allowed to make the request.
+    ApiAccessDenied,
+    /// The ID of the resource is empty.
+    ApiEmptyId,
+    /// The body of the request is not valid JSON, or doesn't match the expected schema.
+    ApiInvalidJson,
+    /// The HTTP method and path combination is not valid.
+    ApiInvalidPathMethod,
+    /// The contents of the request are not valid.
+    ApiInvalidRequest,
+    /// The response could not be serialized.
+    ApiSerializationFailure,
+    /// The VMM didn't handle the request before its deadline.
+    ApiVmmActionTimeout,
+    /// Too many requests are waiting for the VMM.
+    ApiVmmBusy,
+
+    // Boot source.
+    /// The kernel command line is not valid.
+    BootSourceInvalidKernelCommandLine,
+    /// The kernel file cannot be opened.
+    BootSourceInvalidKernelPath,
+    /// The boot source was not configured.
+    BootSourceNotConfigured,
+    /// The boot source cannot be changed after boot.
+    BootSourceUpdateNotAllowedPostBoot,
+
+    // Block devices.
+    /// The block device file cannot be opened.
+    DriveCannotOpen,
+    /// The block device ID is used more than once.
+    DriveDuplicateId,
+    /// No block device has the given ID.
+    DriveInvalidId,
+    /// The block device path is not valid.
+    DriveInvalidPath,
+    /// The operation is only allowed after boot.
+    DriveOperationNotAllowedPreBoot,
+    /// The block device path is used by another drive.
+    DrivePathAlreadyExists,
+    /// A root block device was already added.
+    DriveRootAlreadyAdded,
+    /// The block device could not be updated.
+    DriveUpdateFailed,
+    /// The block device cannot be changed after boot.
+    DriveUpdateNotAllowedPostBoot,
+
+    // Logger.
+    /// The logger could not be initialized.
+    LoggerInitializationFailure,
+    /// The logger was not configured.
+    LoggerNotConfigured,
+
+    // Machine configuration.
+    /// The memory size is not valid.
+    MachineConfigInvalidMemorySize,
+    /// The vCPU count is not valid.
+    MachineConfigInvalidVcpuCount,
+    /// The machine configuration cannot be changed after boot.
+    MachineConfigUpdateNotAllowedPostBoot,
+
+    // Whole microVM configuration.
+    /// Some fields of the microVM configuration are not valid.
+    MicrovmConfigInvalidFields,
+    /// The microVM configuration cannot be replaced after boot.
+    MicrovmConfigUpdateNotAllowedPostBoot,
+
+    // MMDS.
+    /// The MMDS connection retransmission timeout count is not valid.
+    MmdsConfigInvalidConnectionRtoCountMax,
+    /// The MMDS connection retransmission timeout period is not valid.
+    MmdsConfigInvalidConnectionRtoPeriod,
+    /// The MMDS data store limit is not valid.
+    MmdsConfigInvalidDataStoreLimit,
+    /// The MMDS IPv4 address is not valid.
+    MmdsConfigInvalidIpv4Addr,
+    /// The MMDS IPv6 address is not valid.
+    MmdsConfigInvalidIpv6Addr,
+    /// The MMDS connection limit is not valid.
+    MmdsConfigInvalidMaxConnections,
+    /// The MMDS TCP port is not valid.
+    MmdsConfigInvalidTcpPort,
+    /// The MMDS token key is not valid.
+    MmdsConfigInvalidTokenKey,
+    /// The MMDS connection limit is too large.
+    MmdsConfigMaxConnectionsTooLarge,
+    /// The MMDS configuration cannot be changed after boot.
+    MmdsConfigUpdateNotAllowedPostBoot,
+    /// The MMDS contents would exceed the data store limit.
+    MmdsDataStoreLimitExceeded,
+    /// The JSON Patch is not valid.
+    MmdsInvalidJsonPatch,
+    /// A JSON Patch operation refers to a location which does not exist.
+    MmdsJsonPatchInvalidPointer,
+    /// A JSON Patch test operation failed.
+    MmdsJsonPatchTestFailed,
+    /// The MMDS resource does not exist.
+    MmdsNotFound,
+    /// Dedicated MMDS data stores cannot be created after boot.
+    MmdsStoreCreationNotAllowedPostBoot,
+
+    // Network interfaces.
+    /// The network interface ID is used more than once.
+    NetworkInterfaceDuplicateId,
+    /// The guest MAC address is used by another network interface.
+    NetworkInterfaceGuestMacAddressInUse,
+    /// The host device is used by another network interface.
+    NetworkInterfaceHostDeviceNameInUse,
+    /// The network interface backend is not valid.
+    NetworkInterfaceInvalidBackend,
+    /// No network interface has the given ID.
+    NetworkInterfaceInvalidId,
+    /// The TAP device cannot be opened.
+    NetworkInterfaceOpenTap,
+    /// The network interface cannot be changed after boot.
+    NetworkInterfaceUpdateNotAllowedPostBoot,
+
+    // Starting the microVM.
+    /// The microVM is already running.
+    StartMicrovmAlreadyRunning,
+    /// The devices could not be set up.
+    StartMicrovmDeviceSetup,
+    /// The kernel command line could not be completed.
+    StartMicrovmInvalidKernelCommandLine,
+    /// The kernel could not be loaded.
+    StartMicrovmKernelLoader,
+    /// The guest memory could not be set up.
+    StartMicrovmMemorySetup,
+    /// The boot source was not configured.
+    StartMicrovmMissingKernelConfig,
+    /// The MMDS is configured for a network interface which does not exist.
+    StartMicrovmMmdsNetworkInterfaceNotFound,
+    /// A block device file cannot be opened.
+    StartMicrovmOpenBlockDevice,
+    /// The seccomp filters could not be installed.
+    StartMicrovmSeccompFilters,
+    /// The vCPUs could not be set up.
+    StartMicrovmVcpuSetup,
+    /// The VM could not be set up.
+    StartMicrovmVmSetup,
+
+    // Vsock devices.
+    /// The guest CID is used by another vsock device.
+    VsockGuestCidInUse,
+    /// The vsock device cannot be changed after boot.
+    VsockUpdateNotAllowedPostBoot,
+}
+
+impl ErrorCode {
+    /// Returns the code as it appears in API responses, i.e. `<resource>.<error>`.
+    pub fn as_str(&self) -> &'static str {
+        use self::ErrorCode::*;
+        match *self {
+            ApiAccessDenied => "api.access_denied",
+            ApiEmptyId => "api.empty_id",
+            ApiInvalidJson => "api.invalid_json",
+            ApiInvalidPathMethod => "api.invalid_path_method",
+            ApiInvalidRequest => "api.invalid_request",
+            ApiSerializationFailure => "api.serialization_failure",
+            ApiVmmActionTimeout => "api.vmm_action_timeout",
+            ApiVmmBusy => "api.vmm_busy",
+            BootSourceInvalidKernelCommandLine => "boot_source.invalid_kernel_command_line",
+            BootSourceInvalidKernelPath => "boot_source.invalid_kernel_path",
+            BootSourceNotConfigured => "boot_source.not_configured",
+            BootSourceUpdateNotAllowedPostBoot => "boot_source.update_not_allowed_post_boot",
+            DriveCannotOpen => "drive.cannot_open",
+            DriveDuplicateId => "drive.duplicate_id",
+            DriveInvalidId => "drive.invalid_id",
+            DriveInvalidPath => "drive.invalid_path",
+            DriveOperationNotAllowedPreBoot => "drive.operation_not_allowed_pre_boot",
+            DrivePathAlreadyExists => "drive.path_already_exists",
+            DriveRootAlreadyAdded => "drive.root_already_added",
+            DriveUpdateFailed => "drive.update_failed",
+            DriveUpdateNotAllowedPostBoot => "drive.update_not_allowed_post_boot",
+            LoggerInitializationFailure => "logger.initialization_failure",
+            LoggerNotConfigured => "logger.not_configured",
+            MachineConfigInvalidMemorySize => "machine_config.invalid_memory_size",
+            MachineConfigInvalidVcpuCount => "machine_config.invalid_vcpu_count",
+            MachineConfigUpdateNotAllowedPostBoot => "machine_config.update_not_allowed_post_boot",
+            MicrovmConfigInvalidFields => "microvm_config.invalid_fields",
+            MicrovmConfigUpdateNotAllowedPostBoot => {
+                "microvm_config.update_not_allowed_post_boot"
+            }
+            MmdsConfigInvalidConnectionRtoCountMax => {
+                "mmds_config.invalid_connection_rto_count_max"
+            }
+            MmdsConfigInvalidConnectionRtoPeriod => "mmds_config.invalid_connection_rto_period",
+            MmdsConfigInvalidDataStoreLimit => "mmds_config.invalid_data_store_limit",
+            MmdsConfigInvalidIpv4Addr => "mmds_config.invalid_ipv4_addr",
+            MmdsConfigInvalidIpv6Addr => "mmds_config.invalid_ipv6_addr",
+            MmdsConfigInvalidMaxConnections => "mmds_config.invalid_max_connections",
+            MmdsConfigInvalidTcpPort => "mmds_config.invalid_tcp_port",
+            MmdsConfigInvalidTokenKey => "mmds_config.invalid_token_key",
+            MmdsConfigMaxConnectionsTooLarge => "mmds_config.max_connections_too_large",
+            MmdsConfigUpdateNotAllowedPostBoot => "mmds_config.update_not_allowed_post_boot",
+            MmdsDataStoreLimitExceeded => "mmds.data_store_limit_exceeded",
+            MmdsInvalidJsonPatch => "mmds.invalid_json_patch",
+            MmdsJsonPatchInvalidPointer => "mmds.json_patch_invalid_pointer",
+            MmdsJsonPatchTestFailed => "mmds.json_patch_test_failed",
+            MmdsNotFound => "mmds.not_found",
+            MmdsStoreCreationNotAllowedPostBoot => "mmds.store_creation_not_allowed_post_boot",
+            NetworkInterfaceDuplicateId => "network_interface.duplicate_id",
+            NetworkInterfaceGuestMacAddressInUse => "network_interface.guest_mac_address_in_use",
+            NetworkInterfaceHostDeviceNameInUse => "network_interface.host_device_name_in_use",
+            NetworkInterfaceInvalidBackend => "network_interface.invalid_backend",
+            NetworkInterfaceInvalidId => "network_interface.invalid_id",
+            NetworkInterfaceOpenTap => "network_interface.open_tap",
+            NetworkInterfaceUpdateNotAllowedPostBoot => {
+                "network_interface.update_not_allowed_post_boot"
+            }
+            StartMicrovmAlreadyRunning => "start_microvm.already_running",
+            StartMicrovmDeviceSetup => "start_microvm.device_setup",
+            StartMicrovmInvalidKernelCommandLine => "start_microvm.invalid_kernel_command_line",
+            StartMicrovmKernelLoader => "start_microvm.kernel_loader",
+            StartMicrovmMemorySetup => "start_microvm.memory_setup",
+            StartMicrovmMissingKernelConfig => "start_microvm.missing_kernel_config",
+            StartMicrovmMmdsNetworkInterfaceNotFound => {
+                "start_microvm.mmds_network_interface_not_found"
+            }
+            StartMicrovmOpenBlockDevice => "start_microvm.open_block_device",
+            StartMicrovmSeccompFilters => "start_microvm.seccomp_filters",
+            StartMicrovmVcpuSetup => "start_microvm.vcpu_setup",
+            StartMicrovmVmSetup => "start_microvm.vm_setup",
+            VsockGuestCidInUse => "vsock.guest_cid_in_use",
+            VsockUpdateNotAllowedPostBoot => "vsock.update_not_allowed_post_boot",
+        }
+    }
+}
+
+impl Serialize for ErrorCode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialize() {
+        assert_eq!(
+            serde_json::to_string(&ErrorCode::DrivePathAlreadyExists).unwrap(),
+            r#""drive.path_already_exists""#
+        );
+        assert_eq!(ErrorCode::ApiAccessDenied.as_str(), "api.access_denied");
+    }
+}
@@ -0,0 +1,123 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::mem;
+use std::sync::Mutex;
+
+use vmm_config::instance_info::InstanceState;
+
+/// A notable occurrence in the lifetime of the microVM.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// The microVM transitioned to a new state.
+    StateChange {
+        /// The new state of the microVM.
+        state: InstanceState,
+    },
+    /// The guest signaled that it finished booting.
+    GuestBootComplete {
+        /// Time elapsed since the microVM was started, in microseconds.
+        boot_time_us: usize,
+    },
+    /// A vCPU stopped running because of an error.
+    VcpuFailure {
+        /// The index of the vCPU.
+        cpu_id: u8,
+        /// Description of the error.
+        reason: String,
+    },
+    /// A device failed to handle an event.
+    DeviceError {
+        /// Description of the error.
+        reason: String,
+    },
+}
+
+/// Receives the published events. The subscriber is dropped once it returns `false`, which
+/// signals that it is no longer interested in (or able to keep up with) the events.
+pub type EventSubscriber = Box<FnMut(&Event) -> bool + Send>;
+
+/// Dispatches the events of the microVM to the current subscribers. It is shared between the API
+/// thread, which adds subscribers, and the VMM and vCPU threads, which publish events.
+#[derive(Default)]
+pub struct EventPublisher {
+    subscribers: Mutex<Vec<EventSubscriber>>,
+}
+
+impl EventPublisher {
+    /// Adds a subscriber, which receives all the events published from now on.
+    pub fn subscribe(&self, subscriber: EventSubscriber) {
+        self.subscribers
+            .lock()
+            .expect("Failed to add event subscriber due to poisoned lock")
+            .push(subscriber);
+    }
+
+    /// Hands `event` over to all the subscribers.
+    pub fn publish(&self, event: Event) {
+        let mut subscribers = self
+            .subscribers
+            .lock()
+            .expect("Failed to publish event due to poisoned lock");
+        *subscribers = mem::replace(&mut *subscribers, Vec::new())
+            .into_iter()
+            .filter_map(|mut subscriber| {
+                if subscriber(&event) {
+                    Some(subscriber)
+                } else {
+                    None
+                }
+            }).collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn test_publish() {
+        let publisher = EventPublisher::default();
+        // Publishing without subscribers is a no-op.
+        publisher.publish(Event::StateChange {
+            state: InstanceState::Starting,
+        });
+
+        let (sender, receiver) = channel();
+        publisher.subscribe(Box::new(move |event: &Event| sender.send(event.clone()).is_ok()));
+        let (sender, dropped_receiver) = channel();
+        publisher.subscribe(Box::new(move |event: &Event| sender.send(event.clone()).is_ok()));
+        drop(dropped_receiver);
+
+        let event = Event::StateChange {
+            state: InstanceState::Running,
+        };
+        publisher.publish(event.clone());
+        assert_eq!(receiver.try_recv().unwrap(), event);
+        // The subscriber whose receiver is gone was dropped.
+        assert_eq!(publisher.subscribers.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_serialize() {
+        let event = Event::StateChange {
+            state: InstanceState::Running,
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"event":"state_change","state":"Running"}"#
+        );
+
+        let event = Event::VcpuFailure {
+            cpu_id: 1,
+            reason: String::from("KVM_EXIT_FAIL_ENTRY"),
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"event":"vcpu_failure","cpu_id":1,"reason":"KVM_EXIT_FAIL_ENTRY"}"#
+        );
+    }
+}
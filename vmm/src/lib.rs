@@ -16,17 +16,20 @@ extern crate libc;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+extern crate serde_json;
 extern crate time;
 extern crate timerfd;
 
 extern crate cpuid;
 extern crate devices;
+extern crate dumbo;
 extern crate fc_util;
 extern crate kernel;
 extern crate kvm;
 #[macro_use]
 extern crate logger;
 extern crate memory_model;
+extern crate mmds;
 extern crate net_util;
 extern crate rate_limiter;
 extern crate seccomp;
@@ -35,6 +38,10 @@ extern crate x86_64;
 
 mod default_syscalls;
 mod device_manager;
+/// Stable codes identifying the errors reported over the API.
+pub mod error_code;
+/// Lifecycle events of the microVM, which can be streamed over the API.
+pub mod events;
 /// Signal handling utilities for seccomp violations.
 mod sigsys_handler;
 mod vm_control;
@@ -52,23 +59,28 @@ use std::path::PathBuf;
 use std::result;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
 use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
-use std::sync::{Arc, Barrier, RwLock};
+use std::sync::{Arc, Barrier, Mutex, RwLock};
 use std::thread;
 use std::time::Duration;
 
 use libc::{c_void, siginfo_t};
+use serde::Serialize;
+use serde_json::Value;
 use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
 
 use device_manager::legacy::LegacyDeviceManager;
 use device_manager::mmio::MMIODeviceManager;
 use devices::virtio;
 use devices::{DeviceEventT, EpollHandler, EpollHandlerPayload};
+use error_code::ErrorCode;
+use events::{Event, EventPublisher};
 use fc_util::now_cputime_us;
 use kernel::cmdline as kernel_cmdline;
 use kernel::loader as kernel_loader;
 use kvm::*;
 use logger::{Level, Metric, LOGGER, METRICS};
 use memory_model::{GuestAddress, GuestMemory};
+use mmds::stores::MmdsStores;
 use seccomp::{
     setup_seccomp, SeccompLevel, SECCOMP_LEVEL_ADVANCED, SECCOMP_LEVEL_BASIC, SECCOMP_LEVEL_NONE,
 };
@@ -80,6 +92,8 @@ use vmm_config::drive::{BlockDeviceConfig, BlockDeviceConfigs, DriveError};
 use vmm_config::instance_info::{InstanceInfo, InstanceState, StartMicrovmError};
 use vmm_config::logger::{LoggerConfig, LoggerConfigError, LoggerLevel};
 use vmm_config::machine_config::{VmConfig, VmConfigError};
+use vmm_config::microvm::{FieldError, MicrovmConfig, MicrovmConfigError};
+use vmm_config::mmds::{MmdsConfig, MmdsConfigError};
 use vmm_config::net::{NetworkInterfaceConfig, NetworkInterfaceConfigs, NetworkInterfaceError};
 #[cfg(feature = "vsock")]
 use vmm_config::vsock::{VsockDeviceConfig, VsockDeviceConfigs, VsockError};
@@ -128,7 +142,7 @@ enum Error {
 }
 
 /// Types of errors associated with vmm actions.
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
 pub enum ErrorKind {
     /// User Errors describe bad configuration (user input).
     User,
@@ -153,6 +167,12 @@ pub enum VmmActionError {
     /// One of the actions `GetVmConfiguration` or `SetVmConfiguration` failed either because of bad
     /// input (`ErrorKind::User`) or an internal error (`ErrorKind::Internal`).
     MachineConfig(ErrorKind, VmConfigError),
+    /// The action `ConfigureMicrovm` failed either because of bad user input (`ErrorKind::User`)
+    /// or an internal error (`ErrorKind::Internal`).
+    MicrovmConfig(ErrorKind, MicrovmConfigError),
+    /// The action `SetMmdsConfiguration` failed either because of bad user input
+    /// (`ErrorKind::User`) or an internal error (`ErrorKind::Internal`).
+    MmdsConfig(ErrorKind, MmdsConfigError),
     /// The action `InsertNetworkDevice` failed either because of bad user input (`ErrorKind::User`)
     /// or an internal error (`ErrorKind::Internal`).
     NetworkConfig(ErrorKind, NetworkInterfaceError),
@@ -175,12 +195,32 @@ impl VmmActionError {
             DriveConfig(ref kind, _) => kind,
             Logger(ref kind, _) => kind,
             MachineConfig(ref kind, _) => kind,
+            MicrovmConfig(ref kind, _) => kind,
+            MmdsConfig(ref kind, _) => kind,
             NetworkConfig(ref kind, _) => kind,
             StartMicrovm(ref kind, _) => kind,
             #[cfg(feature = "vsock")]
             VsockConfig(ref kind, _) => kind,
         }
     }
+
+    /// Returns the code identifying the error in API responses.
+    pub fn get_code(&self) -> ErrorCode {
+        use self::VmmActionError::*;
+
+        match *self {
+            BootSource(_, ref err) => err.error_code(),
+            DriveConfig(_, ref err) => err.error_code(),
+            Logger(_, ref err) => err.error_code(),
+            MachineConfig(_, ref err) => err.error_code(),
+            MicrovmConfig(_, ref err) => err.error_code(),
+            MmdsConfig(_, ref err) => err.error_code(),
+            NetworkConfig(_, ref err) => err.error_code(),
+            StartMicrovm(_, ref err) => err.error_code(),
+            #[cfg(feature = "vsock")]
+            VsockConfig(_, ref err) => err.error_code(),
+        }
+    }
 }
 
 impl Display for VmmActionError {
@@ -192,6 +232,8 @@ impl Display for VmmActionError {
             DriveConfig(_, ref err) => write!(f, "{}", err.to_string()),
             Logger(_, ref err) => write!(f, "{}", err.to_string()),
             MachineConfig(_, ref err) => write!(f, "{}", err.to_string()),
+            MicrovmConfig(_, ref err) => write!(f, "{}", err.to_string()),
+            MmdsConfig(_, ref err) => write!(f, "{}", err.to_string()),
             NetworkConfig(_, ref err) => write!(f, "{}", err.to_string()),
             StartMicrovm(_, ref err) => write!(f, "{}", err.to_string()),
             #[cfg(feature = "vsock")]
@@ -210,8 +252,26 @@ pub enum VmmAction {
     /// Configure the logger using as input the `LoggerConfig`. This action can only be called
     /// before the microVM has booted. The response is sent using the `OutcomeSender`.
     ConfigureLogger(LoggerConfig, OutcomeSender),
+    /// Replace the whole pre-boot configuration of the microVM using `MicrovmConfig` as input.
+    /// Either all of it is applied, or none of it. This action can only be called before the
+    /// microVM has booted. The response is sent using the `OutcomeSender`.
+    ConfigureMicrovm(MicrovmConfig, OutcomeSender),
+    /// Get the configuration of the block device with the specified ID, or of all the block
+    /// devices when the ID is `None`. The response is sent using the `OutcomeSender`.
+    GetBlockDevices(Option<String>, OutcomeSender),
+    /// Get the boot source configuration. The response is sent using the `OutcomeSender`.
+    GetBootSource(OutcomeSender),
+    /// Get the logger configuration. The response is sent using the `OutcomeSender`.
+    GetLogger(OutcomeSender),
+    /// Get the configuration of the network interface with the specified ID, or of all the
+    /// network interfaces when the ID is `None`. The response is sent using the `OutcomeSender`.
+    GetNetworkInterfaces(Option<String>, OutcomeSender),
     /// Get the configuration of the microVM. The action response is sent using the `OutcomeSender`.
     GetVmConfiguration(OutcomeSender),
+    #[cfg(feature = "vsock")]
+    /// Get the configuration of all the vsock devices. The response is sent using the
+    /// `OutcomeSender`.
+    GetVsockDevices(OutcomeSender),
     /// Add a new block device or update one that already exists using the `BlockDeviceConfig` as
     /// input. This action can only be called before the microVM has booted. The response
     /// is sent using the `OutcomeSender`.
@@ -229,6 +289,10 @@ pub enum VmmAction {
     /// associated with this enum variant. This action can only be called after the microVM is
     /// started. The response is sent using the `OutcomeSender`.
     RescanBlockDevice(String, OutcomeSender),
+    /// Configure how the MMDS is exposed to the guest using `MmdsConfig` as input. This action
+    /// can only be called before the microVM has booted. The response is sent using the
+    /// `OutcomeSender`.
+    SetMmdsConfiguration(MmdsConfig, OutcomeSender),
     /// Set the microVM configuration (memory & vcpu) using `VmConfig` as input. This
     /// action can only be called before the microVM has booted. The action
     /// response is sent using the `OutcomeSender`.
@@ -250,6 +314,9 @@ pub enum VmmData {
     Empty,
     /// The microVM configuration represented by `VmConfig`.
     MachineConfiguration(VmConfig),
+    /// The configuration of a microVM resource, in the same JSON format it is supplied in.
+    /// Runtime-only information (such as opened TAP devices) is left out.
+    ResourceConfig(Value),
 }
 
 /// Data type used to communicate between the API and the VMM.
@@ -259,6 +326,55 @@ pub type OutcomeSender = oneshot::Sender<VmmRequestOutcome>;
 /// One shot channel used to receive a response.
 pub type OutcomeReceiver = oneshot::Receiver<VmmRequestOutcome>;
 
+/// A `VmmAction` on its way to the VMM. Until the VMM takes the action, the sender can withdraw
+/// the request through the matching `VmmRequestHandle`, and the VMM then drops the action without
+/// handling it.
+pub struct VmmRequest {
+    action: VmmAction,
+    // Set by whichever of the VMM and the sender gets to the request first.
+    claimed: Arc<AtomicBool>,
+}
+
+impl VmmRequest {
+    /// Wraps `action` into a request, and returns it along with the handle which can withdraw it.
+    pub fn new(action: VmmAction) -> (Self, VmmRequestHandle) {
+        let claimed = Arc::new(AtomicBool::new(false));
+        let handle = VmmRequestHandle {
+            claimed: claimed.clone(),
+        };
+        (VmmRequest { action, claimed }, handle)
+    }
+
+    /// Takes the action out of the request, unless the request was withdrawn. Once the action is
+    /// taken, the request can no longer be withdrawn.
+    pub fn take(self) -> Option<VmmAction> {
+        if self.claimed.swap(true, Ordering::SeqCst) {
+            None
+        } else {
+            Some(self.action)
+        }
+    }
+}
+
+impl From<VmmAction> for VmmRequest {
+    fn from(action: VmmAction) -> Self {
+        VmmRequest::new(action).0
+    }
+}
+
+/// Lets the sender of a `VmmRequest` withdraw it.
+pub struct VmmRequestHandle {
+    claimed: Arc<AtomicBool>,
+}
+
+impl VmmRequestHandle {
+    /// Withdraws the request, unless the VMM already took its action. Returns whether the request
+    /// was withdrawn.
+    pub fn withdraw(&self) -> bool {
+        !self.claimed.swap(true, Ordering::SeqCst)
+    }
+}
+
 type Result<T> = std::result::Result<T, Error>;
 
 /// Describes a KVM context that gets attached to the micro vm instance.
@@ -513,7 +629,15 @@ struct Vmm {
     kvm: KvmContext,
 
     vm_config: VmConfig,
+    mmds_config: MmdsConfig,
+    // The boot source and logger configurations, as last supplied by the user.
+    boot_source_config: Option<BootSourceConfig>,
+    logger_config: Option<LoggerConfig>,
+    // The MMDS data stores of this microVM, shared with the API server.
+    mmds_stores: Arc<Mutex<MmdsStores>>,
     shared_info: Arc<RwLock<InstanceInfo>>,
+    // Publishes the lifecycle events of this microVM to the API subscribers.
+    events: Arc<EventPublisher>,
 
     // guest VM core resources
     guest_memory: Option<GuestMemory>,
@@ -539,7 +663,7 @@ struct Vmm {
 
     // api resources
     api_event: EpollEvent<EventFd>,
-    from_api: Receiver<Box<VmmAction>>,
+    from_api: Receiver<Box<VmmRequest>>,
 
     write_metrics_event: EpollEvent<TimerFd>,
 
@@ -551,8 +675,10 @@ struct Vmm {
 impl Vmm {
     fn new(
         api_shared_info: Arc<RwLock<InstanceInfo>>,
+        mmds_stores: Arc<Mutex<MmdsStores>>,
+        events: Arc<EventPublisher>,
         api_event_fd: EventFd,
-        from_api: Receiver<Box<VmmAction>>,
+        from_api: Receiver<Box<VmmRequest>>,
         seccomp_level: u32,
         kvm_fd: Option<RawFd>,
     ) -> Result<Self> {
@@ -576,7 +702,12 @@ impl Vmm {
         Ok(Vmm {
             kvm,
             vm_config: VmConfig::default(),
+            mmds_config: MmdsConfig::default(),
+            boot_source_config: None,
+            logger_config: None,
+            mmds_stores,
             shared_info: api_shared_info,
+            events,
             guest_memory: None,
             kernel_config: None,
             kill_signaled: None,
@@ -606,12 +737,15 @@ impl Vmm {
         if let Some(device_idx) = self.drive_handler_id_map.get(drive_id) {
             match self.epoll_context.get_device_handler(*device_idx) {
                 Ok(handler) => {
-                    handler.handle_event(
-                        virtio::block::FS_UPDATE_EVENT,
-                        *device_idx as u32,
-                        EpollHandlerPayload::DrivePayload(disk_image),
-                    );
-                    Ok(())
+                    handler
+                        .handle_event(
+                            virtio::block::FS_UPDATE_EVENT,
+                            *device_idx as u32,
+                            EpollHandlerPayload::DrivePayload(disk_image),
+                        ).map_err(|e| {
+                            warn!("failed to update the disk of device {}: {:?}", device_idx, e);
+                            DriveError::BlockDeviceUpdateFailed
+                        })
                 }
                 Err(e) => {
                     warn!("invalid handler for device {}: {:?}", device_idx, e);
@@ -685,7 +819,7 @@ impl Vmm {
                     block_file,
                     drive_config.is_read_only,
                     epoll_config,
-                    drive_config.rate_limiter.take(),
+                    rate_limiter_for(&drive_config.rate_limiter)?,
                 ).map_err(StartMicrovmError::CreateBlockDevice)?,
             );
             device_manager
@@ -712,28 +846,48 @@ impl Vmm {
         for cfg in self.network_interface_configs.iter_mut() {
             let epoll_config = self.epoll_context.allocate_virtio_net_tokens();
 
-            let allow_mmds_requests = cfg.allow_mmds_requests();
-            let rx_rate_limiter = cfg.rx_rate_limiter.take();
-            let tx_rate_limiter = cfg.tx_rate_limiter.take();
-
-            if let Some(tap) = cfg.take_tap() {
-                let net_box = Box::new(
-                    devices::virtio::Net::new_with_tap(
-                        tap,
-                        cfg.guest_mac(),
-                        epoll_config,
-                        rx_rate_limiter,
-                        tx_rate_limiter,
-                        allow_mmds_requests,
-                    ).map_err(StartMicrovmError::CreateNetDevice)?,
-                );
-
-                device_manager
-                    .register_device(net_box, &mut kernel_config.cmdline, None)
-                    .map_err(StartMicrovmError::RegisterNetDevice)?;
+            let mmds_stores = &self.mmds_stores;
+            let mmds = self
+                .mmds_config
+                .network_stack_config(&cfg.iface_id, cfg.allow_mmds_requests())
+                .map(|config| {
+                    let store = mmds_stores
+                        .lock()
+                        .expect("Failed to access the MMDS data stores due to poisoned lock")
+                        .store_for_interface(&cfg.iface_id);
+                    (config, store)
+                });
+            let tx_filter = cfg.tx_filter();
+            let rx_rate_limiter = rate_limiter_for(&cfg.rx_rate_limiter)?;
+            let tx_rate_limiter = rate_limiter_for(&cfg.tx_rate_limiter)?;
+
+            let net = if let Some(user_net) = cfg.user_net() {
+                devices::virtio::Net::new_with_user_net(
+                    user_net.params(),
+                    cfg.guest_mac(),
+                    epoll_config,
+                    rx_rate_limiter,
+                    tx_rate_limiter,
+                    mmds,
+                    tx_filter,
+                ).map_err(StartMicrovmError::CreateNetDevice)?
+            } else if let Some(tap) = cfg.take_tap() {
+                devices::virtio::Net::new_with_tap(
+                    tap,
+                    cfg.guest_mac(),
+                    epoll_config,
+                    rx_rate_limiter,
+                    tx_rate_limiter,
+                    mmds,
+                    tx_filter,
+                ).map_err(StartMicrovmError::CreateNetDevice)?
             } else {
                 return Err(StartMicrovmError::NetDeviceNotConfigured)?;
-            }
+            };
+
+            device_manager
+                .register_device(Box::new(net), &mut kernel_config.cmdline, None)
+                .map_err(StartMicrovmError::RegisterNetDevice)?;
         }
         Ok(())
     }
@@ -785,6 +939,17 @@ impl Vmm {
         if self.kernel_config.is_none() {
             return Err(StartMicrovmError::MissingKernelConfig)?;
         }
+        if let Some(ref ids) = self.mmds_config.network_interfaces {
+            for id in ids {
+                if !self
+                    .network_interface_configs
+                    .iter()
+                    .any(|cfg| &cfg.iface_id == id)
+                {
+                    return Err(StartMicrovmError::MmdsNetworkInterfaceNotFound(id.clone()));
+                }
+            }
+        }
         Ok(())
     }
 
@@ -876,6 +1041,7 @@ impl Vmm {
             let mmio_bus = device_manager.bus.clone();
             let kill_signaled = kill_signaled.clone();
             let vcpu_thread_barrier = vcpu_thread_barrier.clone();
+            let events = self.events.clone();
             // If the lock is poisoned, it's OK to panic.
             let vcpu_exit_evt = self
                 .legacy_device_manager
@@ -936,6 +1102,9 @@ impl Vmm {
                                                 boot_time_cpu_us,
                                                 boot_time_cpu_us / 1000
                                             );
+                                            events.publish(Event::GuestBootComplete {
+                                                boot_time_us,
+                                            });
                                         }
                                         io_bus.write(addr as u64, data);
                                         METRICS.vcpu.exit_io_out.inc();
@@ -961,11 +1130,19 @@ impl Vmm {
                                     VcpuExit::FailEntry => {
                                         METRICS.vcpu.failures.inc();
                                         error!("Received KVM_EXIT_FAIL_ENTRY signal");
+                                        events.publish(Event::VcpuFailure {
+                                            cpu_id,
+                                            reason: String::from("KVM_EXIT_FAIL_ENTRY"),
+                                        });
                                         break;
                                     }
                                     VcpuExit::InternalError => {
                                         METRICS.vcpu.failures.inc();
                                         error!("Received KVM_EXIT_INTERNAL_ERROR signal");
+                                        events.publish(Event::VcpuFailure {
+                                            cpu_id,
+                                            reason: String::from("KVM_EXIT_INTERNAL_ERROR"),
+                                        });
                                         break;
                                     }
                                     r => {
@@ -973,6 +1150,10 @@ impl Vmm {
                                         // TODO: Are we sure we want to finish running a vcpu upon
                                         // receiving a vm exit that is not necessarily an error?
                                         error!("Unexpected exit reason on vcpu run: {:?}", r);
+                                        events.publish(Event::VcpuFailure {
+                                            cpu_id,
+                                            reason: format!("Unexpected exit reason: {:?}", r),
+                                        });
                                         break;
                                     }
                                 },
@@ -982,6 +1163,10 @@ impl Vmm {
                                     _ => {
                                         METRICS.vcpu.failures.inc();
                                         error!("Failure during vcpu run: {:?}", e);
+                                        events.publish(Event::VcpuFailure {
+                                            cpu_id,
+                                            reason: format!("Failure during vcpu run: {:?}", e),
+                                        });
                                         break;
                                     }
                                 },
@@ -1005,16 +1190,22 @@ impl Vmm {
         // Load seccomp filters before executing guest code.
         // Execution panics if filters cannot be loaded, use --seccomp-level=0 if skipping filters
         // altogether is the desired behaviour.
+        // The syscalls of the user-mode network stack are only allowed when it backs one of the
+        // guest network interfaces.
+        let user_net = self
+            .network_interface_configs
+            .iter()
+            .any(|cfg| cfg.user_net().is_some());
         match self.seccomp_level {
             SECCOMP_LEVEL_ADVANCED => {
                 setup_seccomp(SeccompLevel::Advanced(
-                    default_syscalls::default_context()
+                    default_syscalls::default_context(user_net)
                         .map_err(|e| StartMicrovmError::SeccompFilters(e))?,
                 )).map_err(|e| StartMicrovmError::SeccompFilters(e))?;
             }
             SECCOMP_LEVEL_BASIC => {
                 setup_seccomp(seccomp::SeccompLevel::Basic(
-                    default_syscalls::ALLOWED_SYSCALLS,
+                    &default_syscalls::allowed_syscalls(user_net),
                 )).map_err(|e| StartMicrovmError::SeccompFilters(e))?;
             }
             SECCOMP_LEVEL_NONE | _ => {}
@@ -1098,11 +1289,7 @@ impl Vmm {
 
         self.check_health()
             .map_err(|e| VmmActionError::StartMicrovm(ErrorKind::User, e))?;
-        // Use expect() to crash if the other thread poisoned this lock.
-        self.shared_info
-            .write()
-            .expect("Failed to start microVM because shared info couldn't be written due to poisoned lock")
-            .state = InstanceState::Starting;
+        self.update_instance_state(InstanceState::Starting);
 
         self.init_guest_memory()
             .map_err(|e| VmmActionError::StartMicrovm(ErrorKind::Internal, e))?;
@@ -1121,11 +1308,7 @@ impl Vmm {
         self.start_vcpus(entry_addr)
             .map_err(|e| VmmActionError::StartMicrovm(ErrorKind::Internal, e))?;
 
-        // Use expect() to crash if the other thread poisoned this lock.
-        self.shared_info
-            .write()
-            .expect("Failed to start microVM because shared info couldn't be written due to poisoned lock")
-            .state = InstanceState::Running;
+        self.update_instance_state(InstanceState::Running);
 
         // Arm the log write timer.
         // TODO: the timer does not stop on InstanceStop.
@@ -1145,9 +1328,20 @@ impl Vmm {
         Ok(VmmData::Empty)
     }
 
+    // Sets the state of the instance, and lets the event subscribers know about it.
+    fn update_instance_state(&self, state: InstanceState) {
+        // Use expect() to crash if the other thread poisoned this lock.
+        self.shared_info
+            .write()
+            .expect("Failed to update the instance state due to poisoned lock")
+            .state = state.clone();
+        self.events.publish(Event::StateChange { state });
+    }
+
     /// Waits for all vCPUs to exit and terminates the Firecracker process.
     fn stop(&mut self, exit_code: i32) {
         info!("Vmm is stopping.");
+        self.update_instance_state(InstanceState::Halting);
 
         if let Some(v) = self.kill_signaled.take() {
             v.store(true, Ordering::SeqCst);
@@ -1197,6 +1391,10 @@ impl Vmm {
             error!("Failed to log metrics while stopping: {}", e);
         }
 
+        // The event subscribers are served by the API thread, so this last event is only
+        // delivered if that thread gets to run before the process exits.
+        self.update_instance_state(InstanceState::Halted);
+
         // Exit from Firecracker using the provided exit code.
         std::process::exit(exit_code);
     }
@@ -1269,11 +1467,18 @@ impl Vmm {
                         EpollDispatch::DeviceHandler(device_idx, device_token) => {
                             METRICS.vmm.device_events.inc();
                             match self.epoll_context.get_device_handler(device_idx) {
-                                Ok(handler) => handler.handle_event(
-                                    device_token,
-                                    events[i].events().bits(),
-                                    EpollHandlerPayload::Empty,
-                                ),
+                                Ok(handler) => {
+                                    if let Err(e) = handler.handle_event(
+                                        device_token,
+                                        events[i].events().bits(),
+                                        EpollHandlerPayload::Empty,
+                                    ) {
+                                        error!("Device {} failed: {:?}", device_idx, e);
+                                        self.events.publish(Event::DeviceError {
+                                            reason: format!("{:?}", e),
+                                        });
+                                    }
+                                }
                                 Err(e) => {
                                     warn!("invalid handler for device {}: {:?}", device_idx, e)
                                 }
@@ -1314,27 +1519,97 @@ impl Vmm {
             ));
         }
 
-        let kernel_file = File::open(kernel_image_path).map_err(|_| {
-            VmmActionError::BootSource(ErrorKind::User, BootSourceConfigError::InvalidKernelPath)
-        })?;
+        let kernel_config = Vmm::kernel_config(&kernel_image_path, kernel_cmdline.as_ref())
+            .map_err(|e| VmmActionError::BootSource(ErrorKind::User, e))?;
+        self.configure_kernel(kernel_config);
+        self.boot_source_config = Some(BootSourceConfig {
+            kernel_image_path,
+            boot_args: kernel_cmdline,
+        });
+
+        Ok(VmmData::Empty)
+    }
+
+    // Opens the kernel image and builds the kernel command line described by a boot source.
+    fn kernel_config(
+        kernel_image_path: &str,
+        kernel_cmdline: Option<&String>,
+    ) -> std::result::Result<KernelConfig, BootSourceConfigError> {
+        let kernel_file =
+            File::open(kernel_image_path).map_err(|_| BootSourceConfigError::InvalidKernelPath)?;
         let mut cmdline = kernel_cmdline::Cmdline::new(x86_64::layout::CMDLINE_MAX_SIZE);
         cmdline
-            .insert_str(kernel_cmdline.unwrap_or(String::from(DEFAULT_KERNEL_CMDLINE)))
-            .map_err(|_| {
-                VmmActionError::BootSource(
-                    ErrorKind::User,
-                    BootSourceConfigError::InvalidKernelCommandLine,
-                )
-            })?;
+            .insert_str(kernel_cmdline.map_or(DEFAULT_KERNEL_CMDLINE, String::as_str))
+            .map_err(|_| BootSourceConfigError::InvalidKernelCommandLine)?;
 
-        let kernel_config = KernelConfig {
+        Ok(KernelConfig {
             kernel_file,
             cmdline,
             cmdline_addr: GuestAddress(x86_64::layout::CMDLINE_START),
-        };
-        self.configure_kernel(kernel_config);
+        })
+    }
 
-        Ok(VmmData::Empty)
+    fn get_boot_source(&self) -> std::result::Result<VmmData, VmmActionError> {
+        self.boot_source_config
+            .as_ref()
+            .map(resource_config)
+            .ok_or(VmmActionError::BootSource(
+                ErrorKind::User,
+                BootSourceConfigError::NotConfigured,
+            ))
+    }
+
+    fn get_block_devices(
+        &self,
+        drive_id: Option<String>,
+    ) -> std::result::Result<VmmData, VmmActionError> {
+        match drive_id {
+            Some(id) => self
+                .block_device_configs
+                .get(&id)
+                .map(resource_config)
+                .ok_or(VmmActionError::DriveConfig(
+                    ErrorKind::User,
+                    DriveError::InvalidBlockDeviceID,
+                )),
+            None => Ok(resource_config(&self.block_device_configs.config_list)),
+        }
+    }
+
+    fn get_network_interfaces(
+        &self,
+        iface_id: Option<String>,
+    ) -> std::result::Result<VmmData, VmmActionError> {
+        match iface_id {
+            Some(id) => self
+                .network_interface_configs
+                .get(&id)
+                .map(resource_config)
+                .ok_or(VmmActionError::NetworkConfig(
+                    ErrorKind::User,
+                    NetworkInterfaceError::InvalidIfaceId(id),
+                )),
+            None => Ok(resource_config(
+                &self.network_interface_configs.iter().collect::<Vec<_>>(),
+            )),
+        }
+    }
+
+    fn get_logger(&self) -> std::result::Result<VmmData, VmmActionError> {
+        self.logger_config
+            .as_ref()
+            .map(resource_config)
+            .ok_or(VmmActionError::Logger(
+                ErrorKind::User,
+                LoggerConfigError::NotConfigured,
+            ))
+    }
+
+    #[cfg(feature = "vsock")]
+    fn get_vsock_devices(&self) -> std::result::Result<VmmData, VmmActionError> {
+        Ok(resource_config(
+            &self.vsock_device_configs.iter().collect::<Vec<_>>(),
+        ))
     }
 
     fn set_vm_configuration(
@@ -1348,57 +1623,190 @@ impl Vmm {
             ));
         }
 
+        Vmm::update_vm_config(&mut self.vm_config, machine_config)
+            .map(|_| VmmData::Empty)
+            .map_err(|e| VmmActionError::MachineConfig(ErrorKind::User, e))
+    }
+
+    // Updates the fields of `vm_config` which have a new value in `machine_config`. Nothing is
+    // changed if the result wouldn't be valid.
+    fn update_vm_config(
+        vm_config: &mut VmConfig,
+        machine_config: VmConfig,
+    ) -> std::result::Result<(), VmConfigError> {
         if let Some(vcpu_count_value) = machine_config.vcpu_count {
             // Check that the vcpu_count value is >=1.
             if vcpu_count_value <= 0 {
-                return Err(VmmActionError::MachineConfig(
-                    ErrorKind::User,
-                    VmConfigError::InvalidVcpuCount,
-                ));
+                return Err(VmConfigError::InvalidVcpuCount);
             }
         }
 
         if let Some(mem_size_mib_value) = machine_config.mem_size_mib {
             // TODO: add other memory checks
             if mem_size_mib_value <= 0 {
-                return Err(VmmActionError::MachineConfig(
-                    ErrorKind::User,
-                    VmConfigError::InvalidMemorySize,
-                ));
+                return Err(VmConfigError::InvalidMemorySize);
             }
         }
 
         let ht_enabled = match machine_config.ht_enabled {
             Some(value) => value,
-            None => self.vm_config.ht_enabled.unwrap(),
+            None => vm_config.ht_enabled.unwrap(),
         };
 
         let vcpu_count_value = match machine_config.vcpu_count {
             Some(value) => value,
-            None => self.vm_config.vcpu_count.unwrap(),
+            None => vm_config.vcpu_count.unwrap(),
         };
 
         // If hyperthreading is enabled or is to be enabled in this call
         // only allow vcpu count to be 1 or even.
         if ht_enabled && vcpu_count_value > 1 && vcpu_count_value % 2 == 1 {
-            return Err(VmmActionError::MachineConfig(
-                ErrorKind::User,
-                VmConfigError::InvalidVcpuCount,
-            ));
+            return Err(VmConfigError::InvalidVcpuCount);
         }
 
         // Update all the fields that have a new value.
-        self.vm_config.vcpu_count = Some(vcpu_count_value);
-        self.vm_config.ht_enabled = Some(ht_enabled);
+        vm_config.vcpu_count = Some(vcpu_count_value);
+        vm_config.ht_enabled = Some(ht_enabled);
 
         if machine_config.mem_size_mib.is_some() {
-            self.vm_config.mem_size_mib = machine_config.mem_size_mib;
+            vm_config.mem_size_mib = machine_config.mem_size_mib;
         }
 
         if machine_config.cpu_template.is_some() {
-            self.vm_config.cpu_template = machine_config.cpu_template;
+            vm_config.cpu_template = machine_config.cpu_template;
         }
 
+        Ok(())
+    }
+
+    fn set_mmds_configuration(
+        &mut self,
+        mmds_config: MmdsConfig,
+    ) -> std::result::Result<VmmData, VmmActionError> {
+        if self.is_instance_initialized() {
+            return Err(VmmActionError::MmdsConfig(
+                ErrorKind::User,
+                MmdsConfigError::UpdateNotAllowedPostBoot,
+            ));
+        }
+        mmds_config
+            .validate()
+            .map_err(|e| VmmActionError::MmdsConfig(ErrorKind::User, e))?;
+        self.apply_mmds_configuration(mmds_config);
+        Ok(VmmData::Empty)
+    }
+
+    // Applies a MMDS configuration which is known to be valid.
+    fn apply_mmds_configuration(&mut self, mmds_config: MmdsConfig) {
+        self.mmds_stores
+            .lock()
+            .expect("Failed to configure the MMDS due to poisoned lock")
+            .configure(
+                mmds_config.token_authority(),
+                mmds_config.data_store_limit,
+                mmds_config.guest_data_limit,
+            );
+        self.mmds_config = mmds_config;
+    }
+
+    fn configure_microvm(
+        &mut self,
+        microvm_config: MicrovmConfig,
+    ) -> std::result::Result<VmmData, VmmActionError> {
+        if self.is_instance_initialized() {
+            return Err(VmmActionError::MicrovmConfig(
+                ErrorKind::User,
+                MicrovmConfigError::UpdateNotAllowedPostBoot,
+            ));
+        }
+
+        // Each part of the configuration is validated the same way as by the matching API
+        // resource, but it is built from scratch on the side. The current configuration is only
+        // replaced once every part is known to be valid.
+        let mut field_errors = Vec::new();
+
+        let kernel_config = match microvm_config.boot_source {
+            Some(ref boot_source) => match Vmm::kernel_config(
+                &boot_source.kernel_image_path,
+                boot_source.boot_args.as_ref(),
+            ) {
+                Ok(kernel_config) => Some(kernel_config),
+                Err(e) => {
+                    field_errors.push(FieldError::new(
+                        "boot-source",
+                        VmmActionError::BootSource(ErrorKind::User, e),
+                    ));
+                    None
+                }
+            },
+            None => None,
+        };
+
+        let mut vm_config = VmConfig::default();
+        if let Some(machine_config) = microvm_config.machine_config {
+            if let Err(e) = Vmm::update_vm_config(&mut vm_config, machine_config) {
+                field_errors.push(FieldError::new(
+                    "machine-config",
+                    VmmActionError::MachineConfig(ErrorKind::User, e),
+                ));
+            }
+        }
+
+        let mut block_device_configs = BlockDeviceConfigs::new();
+        for (index, drive) in microvm_config.drives.into_iter().enumerate() {
+            // Unlike a PUT request on an existing drive, a repeated ID is not an update.
+            let outcome = if block_device_configs.get(&drive.drive_id).is_some() {
+                Err(DriveError::DuplicateBlockDeviceID)
+            } else {
+                block_device_configs.insert(drive)
+            };
+            if let Err(e) = outcome {
+                field_errors.push(FieldError::new(
+                    format!("drives[{}]", index),
+                    VmmActionError::DriveConfig(ErrorKind::User, e),
+                ));
+            }
+        }
+
+        let mmds_config = microvm_config.mmds_config.unwrap_or_default();
+        if let Err(e) = mmds_config.validate() {
+            field_errors.push(FieldError::new(
+                "mmds-config",
+                VmmActionError::MmdsConfig(ErrorKind::User, e),
+            ));
+        }
+
+        let network_interface_configs = match self
+            .network_interface_configs
+            .stage(microvm_config.network_interfaces)
+        {
+            Ok(network_interface_configs) => network_interface_configs,
+            Err(errors) => {
+                for (index, e) in errors {
+                    field_errors.push(FieldError::new(
+                        format!("network-interfaces[{}]", index),
+                        VmmActionError::NetworkConfig(ErrorKind::User, e),
+                    ));
+                }
+                NetworkInterfaceConfigs::new()
+            }
+        };
+
+        if !field_errors.is_empty() {
+            self.network_interface_configs
+                .unstage(network_interface_configs);
+            return Err(VmmActionError::MicrovmConfig(
+                ErrorKind::User,
+                MicrovmConfigError::InvalidFields(field_errors),
+            ));
+        }
+
+        self.kernel_config = kernel_config;
+        self.boot_source_config = microvm_config.boot_source;
+        self.vm_config = vm_config;
+        self.block_device_configs = block_device_configs;
+        self.network_interface_configs = network_interface_configs;
+        self.apply_mmds_configuration(mmds_config);
         Ok(VmmData::Empty)
     }
 
@@ -1549,7 +1957,7 @@ impl Vmm {
     }
 
     fn init_logger(
-        &self,
+        &mut self,
         api_logger: LoggerConfig,
     ) -> std::result::Result<VmmData, VmmActionError> {
         if self.is_instance_initialized() {
@@ -1568,7 +1976,7 @@ impl Vmm {
         }
 
         match api_logger.level {
-            Some(val) => match val {
+            Some(ref val) => match *val {
                 LoggerLevel::Error => LOGGER.set_level(Level::Error),
                 LoggerLevel::Warning => LOGGER.set_level(Level::Warn),
                 LoggerLevel::Info => LOGGER.set_level(Level::Info),
@@ -1588,15 +1996,17 @@ impl Vmm {
         LOGGER
             .init(
                 &instance_id,
-                Some(api_logger.log_fifo),
-                Some(api_logger.metrics_fifo),
-            ).map(|_| VmmData::Empty)
-            .map_err(|e| {
+                Some(api_logger.log_fifo.clone()),
+                Some(api_logger.metrics_fifo.clone()),
+            ).map_err(|e| {
                 VmmActionError::Logger(
                     ErrorKind::User,
                     LoggerConfigError::InitializationFailure(e.to_string()),
                 )
-            })
+            })?;
+        self.logger_config = Some(api_logger);
+
+        Ok(VmmData::Empty)
     }
 
     fn send_response(outcome: VmmRequestOutcome, sender: OutcomeSender) {
@@ -1608,7 +2018,14 @@ impl Vmm {
 
     fn run_vmm_action(&mut self) -> Result<()> {
         let request = match self.from_api.try_recv() {
-            Ok(t) => *t,
+            Ok(t) => match t.take() {
+                Some(action) => action,
+                None => {
+                    // The sender stopped waiting for the outcome, so the action is dropped.
+                    info!("Dropped a VMM action which was withdrawn by the API server.");
+                    return Ok(());
+                }
+            },
             Err(TryRecvError::Empty) => {
                 return Err(Error::ApiChannel)?;
             }
@@ -1630,12 +2047,31 @@ impl Vmm {
             VmmAction::ConfigureLogger(logger_description, sender) => {
                 Vmm::send_response(self.init_logger(logger_description), sender);
             }
+            VmmAction::ConfigureMicrovm(microvm_config, sender) => {
+                Vmm::send_response(self.configure_microvm(microvm_config), sender);
+            }
+            VmmAction::GetBlockDevices(drive_id, sender) => {
+                Vmm::send_response(self.get_block_devices(drive_id), sender);
+            }
+            VmmAction::GetBootSource(sender) => {
+                Vmm::send_response(self.get_boot_source(), sender);
+            }
+            VmmAction::GetLogger(sender) => {
+                Vmm::send_response(self.get_logger(), sender);
+            }
+            VmmAction::GetNetworkInterfaces(iface_id, sender) => {
+                Vmm::send_response(self.get_network_interfaces(iface_id), sender);
+            }
             VmmAction::GetVmConfiguration(sender) => {
                 Vmm::send_response(
                     Ok(VmmData::MachineConfiguration(self.vm_config.clone())),
                     sender,
                 );
             }
+            #[cfg(feature = "vsock")]
+            VmmAction::GetVsockDevices(sender) => {
+                Vmm::send_response(self.get_vsock_devices(), sender);
+            }
             VmmAction::InsertBlockDevice(block_device_config, sender) => {
                 Vmm::send_response(self.insert_block_device(block_device_config), sender);
             }
@@ -1652,6 +2088,9 @@ impl Vmm {
             VmmAction::StartMicroVm(sender) => {
                 Vmm::send_response(self.start_microvm(), sender);
             }
+            VmmAction::SetMmdsConfiguration(mmds_config, sender) => {
+                Vmm::send_response(self.set_mmds_configuration(mmds_config), sender);
+            }
             VmmAction::SetVmConfiguration(machine_config_body, sender) => {
                 Vmm::send_response(self.set_vm_configuration(machine_config_body), sender);
             }
@@ -1685,6 +2124,14 @@ impl PartialEq for VmmAction {
                 &VmmAction::ConfigureLogger(ref log, _),
                 &VmmAction::ConfigureLogger(ref other_log, _),
             ) => log == other_log,
+            (
+                &VmmAction::ConfigureMicrovm(ref microvm_config, _),
+                &VmmAction::ConfigureMicrovm(ref other_microvm_config, _),
+            ) => microvm_config == other_microvm_config,
+            (
+                &VmmAction::SetMmdsConfiguration(ref mmds_config, _),
+                &VmmAction::SetMmdsConfiguration(ref other_mmds_config, _),
+            ) => mmds_config == other_mmds_config,
             (
                 &VmmAction::SetVmConfiguration(ref vm_config, _),
                 &VmmAction::SetVmConfiguration(ref other_vm_config, _),
@@ -1698,11 +2145,46 @@ impl PartialEq for VmmAction {
                 &VmmAction::RescanBlockDevice(ref other_req, _),
             ) => req == other_req,
             (&VmmAction::StartMicroVm(_), &VmmAction::StartMicroVm(_)) => true,
+            (
+                &VmmAction::GetBlockDevices(ref drive_id, _),
+                &VmmAction::GetBlockDevices(ref other_drive_id, _),
+            ) => drive_id == other_drive_id,
+            (&VmmAction::GetBootSource(_), &VmmAction::GetBootSource(_)) => true,
+            (&VmmAction::GetLogger(_), &VmmAction::GetLogger(_)) => true,
+            (
+                &VmmAction::GetNetworkInterfaces(ref iface_id, _),
+                &VmmAction::GetNetworkInterfaces(ref other_iface_id, _),
+            ) => iface_id == other_iface_id,
+            (&VmmAction::GetVmConfiguration(_), &VmmAction::GetVmConfiguration(_)) => true,
+            #[cfg(feature = "vsock")]
+            (&VmmAction::GetVsockDevices(_), &VmmAction::GetVsockDevices(_)) => true,
             _ => false,
         }
     }
 }
 
+// Serializes the configuration of a microVM resource, so it can be handed over to the API.
+fn resource_config<T: Serialize>(config: &T) -> VmmData {
+    // The configuration structs only have string keys, so serializing them can't fail.
+    VmmData::ResourceConfig(
+        serde_json::to_value(config).expect("Failed to serialize resource configuration"),
+    )
+}
+
+// Builds the rate limiter of a device, out of the one stored in its configuration. The latter is
+// kept around, so the configuration can still be retrieved after boot.
+fn rate_limiter_for(
+    config: &Option<rate_limiter::RateLimiter>,
+) -> std::result::Result<Option<rate_limiter::RateLimiter>, StartMicrovmError> {
+    match *config {
+        Some(ref rate_limiter) => rate_limiter
+            .try_clone_config()
+            .map(Some)
+            .map_err(StartMicrovmError::CreateRateLimiter),
+        None => Ok(None),
+    }
+}
+
 /// Starts a new vmm thread that can service API requests.
 ///
 /// # Arguments
@@ -1717,8 +2199,10 @@ impl PartialEq for VmmAction {
 ///              associated with `/dev/kvm`.
 pub fn start_vmm_thread(
     api_shared_info: Arc<RwLock<InstanceInfo>>,
+    mmds_stores: Arc<Mutex<MmdsStores>>,
+    events: Arc<EventPublisher>,
     api_event_fd: EventFd,
-    from_api: Receiver<Box<VmmAction>>,
+    from_api: Receiver<Box<VmmRequest>>,
     seccomp_level: u32,
     kvm_fd: Option<RawFd>,
 ) -> thread::JoinHandle<()> {
@@ -1728,6 +2212,8 @@ pub fn start_vmm_thread(
             // If this fails, consider it fatal. Use expect().
             let mut vmm = Vmm::new(
                 api_shared_info,
+                mmds_stores,
+                events,
                 api_event_fd,
                 from_api,
                 seccomp_level,
@@ -1753,9 +2239,11 @@ mod tests {
     use super::*;
 
     use std::fs::File;
+    use std::net::Ipv4Addr;
     use std::sync::atomic::AtomicUsize;
 
     use self::tempfile::NamedTempFile;
+    use futures::Future;
     use devices::virtio::ActivateResult;
     use net_util::MacAddr;
     use vmm_config::machine_config::CpuFeaturesTemplate;
@@ -1827,10 +2315,11 @@ mod tests {
             device_event: DeviceEventT,
             event_flags: u32,
             payload: EpollHandlerPayload,
-        ) {
+        ) -> devices::Result<()> {
             self.evt = Some(device_event);
             self.flags = Some(event_flags);
             self.payload = Some(payload);
+            Ok(())
         }
     }
 
@@ -1882,11 +2371,14 @@ mod tests {
         let shared_info = Arc::new(RwLock::new(InstanceInfo {
             state,
             id: "TEST_ID".to_string(),
+            vmm_version: "1.0".to_string(),
         }));
 
         let (_to_vmm, from_api) = channel();
         let vmm = Vmm::new(
             shared_info,
+            Arc::new(Mutex::new(MmdsStores::default())),
+            Arc::new(EventPublisher::default()),
             EventFd::new().expect("cannot create eventFD"),
             from_api,
             seccomp::SECCOMP_LEVEL_ADVANCED,
@@ -2003,11 +2495,13 @@ mod tests {
         // test create network interface
         let network_interface = NetworkInterfaceConfig {
             iface_id: String::from("netif"),
-            host_dev_name: String::from("hostname"),
+            host_dev_name: Some(String::from("hostname")),
             guest_mac: None,
             rx_rate_limiter: None,
             tx_rate_limiter: None,
             allow_mmds_requests: false,
+            tx_filter: None,
+            user_net: None,
             tap: None,
         };
         assert!(vmm.insert_net_device(network_interface).is_ok());
@@ -2016,11 +2510,13 @@ mod tests {
         // test update network interface
         let network_interface = NetworkInterfaceConfig {
             iface_id: String::from("netif"),
-            host_dev_name: String::from("hostname2"),
+            host_dev_name: Some(String::from("hostname2")),
             guest_mac: Some(mac.clone()),
             rx_rate_limiter: None,
             tx_rate_limiter: None,
             allow_mmds_requests: false,
+            tx_filter: None,
+            user_net: None,
             tap: None,
         };
         assert!(vmm.insert_net_device(network_interface).is_ok());
@@ -2028,11 +2524,13 @@ mod tests {
         // Test insert new net device with same mac fails.
         let network_interface = NetworkInterfaceConfig {
             iface_id: String::from("netif2"),
-            host_dev_name: String::from("hostname3"),
+            host_dev_name: Some(String::from("hostname3")),
             guest_mac: Some(mac),
             rx_rate_limiter: None,
             tx_rate_limiter: None,
             allow_mmds_requests: false,
+            tx_filter: None,
+            user_net: None,
             tap: None,
         };
         assert!(vmm.insert_net_device(network_interface).is_err());
@@ -2041,16 +2539,89 @@ mod tests {
         vmm.set_instance_state(InstanceState::Running);
         let network_interface = NetworkInterfaceConfig {
             iface_id: String::from("netif"),
-            host_dev_name: String::from("hostname2"),
+            host_dev_name: Some(String::from("hostname2")),
             guest_mac: None,
             rx_rate_limiter: None,
             tx_rate_limiter: None,
             allow_mmds_requests: false,
+            tx_filter: None,
+            user_net: None,
             tap: None,
         };
         assert!(vmm.insert_net_device(network_interface).is_err());
     }
 
+    #[test]
+    fn test_set_mmds_configuration() {
+        let mut vmm = create_vmm_object(InstanceState::Uninitialized);
+
+        let mmds_config = MmdsConfig {
+            ipv4_address: Ipv4Addr::new(169, 254, 170, 2),
+            tcp_port: 8080,
+            max_connections: 5,
+            network_interfaces: Some(vec![String::from("netif")]),
+            data_store_limit: 1024,
+            ..MmdsConfig::default()
+        };
+        assert!(vmm.set_mmds_configuration(mmds_config.clone()).is_ok());
+        assert_eq!(vmm.mmds_config, mmds_config);
+        assert_eq!(
+            vmm.mmds_stores
+                .lock()
+                .unwrap()
+                .default_store()
+                .lock()
+                .unwrap()
+                .data_store_limit(),
+            1024
+        );
+
+        // Invalid configurations are rejected, and the previous one is kept.
+        let invalid_config = MmdsConfig {
+            tcp_port: 0,
+            ..MmdsConfig::default()
+        };
+        match vmm.set_mmds_configuration(invalid_config) {
+            Err(VmmActionError::MmdsConfig(ErrorKind::User, MmdsConfigError::InvalidTcpPort)) => (),
+            _ => assert!(false),
+        }
+        assert_eq!(vmm.mmds_config, mmds_config);
+
+        // The microVM cannot start while the MMDS configuration refers to a missing interface.
+        vmm.configure_kernel(KernelConfig {
+            cmdline_addr: GuestAddress(0x1000),
+            cmdline: kernel_cmdline::Cmdline::new(10),
+            kernel_file: tempfile::tempfile().unwrap(),
+        });
+        match vmm.check_health() {
+            Err(StartMicrovmError::MmdsNetworkInterfaceNotFound(ref id)) => assert_eq!(id, "netif"),
+            _ => assert!(false),
+        }
+        let network_interface = NetworkInterfaceConfig {
+            iface_id: String::from("netif"),
+            host_dev_name: Some(String::from("hostname")),
+            guest_mac: None,
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+            allow_mmds_requests: false,
+            tx_filter: None,
+            user_net: None,
+            tap: None,
+        };
+        assert!(vmm.insert_net_device(network_interface).is_ok());
+        assert!(vmm.check_health().is_ok());
+
+        // Test that update post-boot fails.
+        vmm.set_instance_state(InstanceState::Running);
+        match vmm.set_mmds_configuration(MmdsConfig::default()) {
+            Err(VmmActionError::MmdsConfig(
+                ErrorKind::User,
+                MmdsConfigError::UpdateNotAllowedPostBoot,
+            )) => (),
+            _ => assert!(false),
+        }
+    }
+
     #[test]
     fn test_machine_configuration() {
         let mut vmm = create_vmm_object(InstanceState::Uninitialized);
@@ -2332,6 +2903,78 @@ mod tests {
         assert_eq!(vmm.is_instance_initialized(), true);
     }
 
+    #[test]
+    fn test_vmm_request() {
+        // The VMM takes the action first, so the request can't be withdrawn anymore.
+        let (outcome_sender, _) = oneshot::channel();
+        let (request, request_handle) = VmmRequest::new(VmmAction::StartMicroVm(outcome_sender));
+        assert!(request.take().is_some());
+        assert!(!request_handle.withdraw());
+
+        // The request is withdrawn first, so the VMM can't take the action anymore.
+        let (outcome_sender, _) = oneshot::channel();
+        let (request, request_handle) = VmmRequest::new(VmmAction::StartMicroVm(outcome_sender));
+        assert!(request_handle.withdraw());
+        assert!(!request_handle.withdraw());
+        assert!(request.take().is_none());
+    }
+
+    #[test]
+    fn test_run_vmm_action() {
+        let shared_info = Arc::new(RwLock::new(InstanceInfo {
+            state: InstanceState::Uninitialized,
+            id: "TEST_ID".to_string(),
+            vmm_version: "1.0".to_string(),
+        }));
+        let (to_vmm, from_api) = channel();
+        let mut vmm = Vmm::new(
+            shared_info,
+            Arc::new(Mutex::new(MmdsStores::default())),
+            Arc::new(EventPublisher::default()),
+            EventFd::new().expect("cannot create eventFD"),
+            from_api,
+            seccomp::SECCOMP_LEVEL_ADVANCED,
+            None,
+        ).expect("Cannot Create VMM");
+
+        let (outcome_sender, outcome_receiver) = oneshot::channel();
+        let request = VmmRequest::from(VmmAction::GetBootSource(outcome_sender));
+        to_vmm.send(Box::new(request)).unwrap();
+        assert!(vmm.run_vmm_action().is_ok());
+        assert!(outcome_receiver.wait().unwrap().is_err());
+
+        // Withdrawn requests are dropped without being handled.
+        let (outcome_sender, outcome_receiver) = oneshot::channel();
+        let (request, request_handle) = VmmRequest::new(VmmAction::GetBootSource(outcome_sender));
+        to_vmm.send(Box::new(request)).unwrap();
+        assert!(request_handle.withdraw());
+        assert!(vmm.run_vmm_action().is_ok());
+        assert!(outcome_receiver.wait().is_err());
+
+        assert!(vmm.run_vmm_action().is_err());
+    }
+
+    #[test]
+    fn test_update_instance_state() {
+        let vmm = create_vmm_object(InstanceState::Uninitialized);
+        let (sender, receiver) = channel();
+        vmm.events
+            .subscribe(Box::new(move |event: &Event| sender.send(event.clone()).is_ok()));
+
+        vmm.update_instance_state(InstanceState::Starting);
+        assert_eq!(
+            vmm.shared_info.read().unwrap().state,
+            InstanceState::Starting
+        );
+        assert_eq!(
+            receiver.try_recv().unwrap(),
+            Event::StateChange {
+                state: InstanceState::Starting
+            }
+        );
+        assert!(receiver.try_recv().is_err());
+    }
+
     #[test]
     fn test_attach_block_devices() {
         let mut vmm = create_vmm_object(InstanceState::Uninitialized);
@@ -2452,11 +3095,13 @@ mod tests {
         // test create network interface
         let network_interface = NetworkInterfaceConfig {
             iface_id: String::from("netif"),
-            host_dev_name: String::from("hostname3"),
+            host_dev_name: Some(String::from("hostname3")),
             guest_mac: None,
             rx_rate_limiter: None,
             tx_rate_limiter: None,
             allow_mmds_requests: false,
+            tx_filter: None,
+            user_net: None,
             tap: None,
         };
 
@@ -2512,6 +3157,258 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_configure_microvm() {
+        let mut vmm = create_vmm_object(InstanceState::Uninitialized);
+        let kernel_file = NamedTempFile::new().unwrap();
+        let root_file = NamedTempFile::new().unwrap();
+        let data_file = NamedTempFile::new().unwrap();
+
+        // The current configuration is replaced as a whole.
+        let data_drive = BlockDeviceConfig {
+            drive_id: String::from("data"),
+            path_on_host: data_file.path().to_path_buf(),
+            is_root_device: false,
+            partuuid: None,
+            is_read_only: true,
+            rate_limiter: None,
+        };
+        assert!(vmm.insert_block_device(data_drive).is_ok());
+        let microvm_config: MicrovmConfig = serde_json::from_str(&format!(
+            r#"{{
+                "boot-source": {{"kernel_image_path": "{}"}},
+                "machine-config": {{"vcpu_count": 2, "ht_enabled": true}},
+                "drives": [{{
+                    "drive_id": "rootfs",
+                    "path_on_host": "{}",
+                    "is_root_device": true,
+                    "is_read_only": false
+                }}],
+                "network-interfaces": [{{"iface_id": "eth0", "host_dev_name": "microvmtap0"}}]
+            }}"#,
+            kernel_file.path().to_str().unwrap(),
+            root_file.path().to_str().unwrap()
+        )).unwrap();
+        assert!(vmm.configure_microvm(microvm_config).is_ok());
+        assert!(vmm.kernel_config.is_some());
+        assert!(vmm.boot_source_config.is_some());
+        assert_eq!(vmm.vm_config.vcpu_count, Some(2));
+        assert_eq!(vmm.block_device_configs.config_list.len(), 1);
+        assert!(vmm.block_device_configs.get("rootfs").is_some());
+        assert!(vmm.network_interface_configs.get("eth0").is_some());
+
+        // Nothing is applied when any part is invalid, and every error is reported.
+        let microvm_config: MicrovmConfig = serde_json::from_str(&format!(
+            r#"{{
+                "boot-source": {{"kernel_image_path": "/invalid/path"}},
+                "machine-config": {{"vcpu_count": 3, "ht_enabled": true}},
+                "drives": [
+                    {{
+                        "drive_id": "rootfs",
+                        "path_on_host": "{}",
+                        "is_root_device": true,
+                        "is_read_only": false
+                    }},
+                    {{
+                        "drive_id": "data",
+                        "path_on_host": "{}",
+                        "is_root_device": true,
+                        "is_read_only": false
+                    }},
+                    {{
+                        "drive_id": "rootfs",
+                        "path_on_host": "{}",
+                        "is_root_device": false,
+                        "is_read_only": true
+                    }}
+                ],
+                "network-interfaces": [
+                    {{"iface_id": "eth1", "host_dev_name": "microvmtap0"}},
+                    {{"iface_id": "eth1", "host_dev_name": "microvmtap1"}}
+                ]
+            }}"#,
+            root_file.path().to_str().unwrap(),
+            data_file.path().to_str().unwrap(),
+            kernel_file.path().to_str().unwrap()
+        )).unwrap();
+        match vmm.configure_microvm(microvm_config) {
+            Err(VmmActionError::MicrovmConfig(
+                ErrorKind::User,
+                MicrovmConfigError::InvalidFields(field_errors),
+            )) => {
+                let fields: Vec<&str> = field_errors.iter().map(|e| e.field.as_str()).collect();
+                assert_eq!(
+                    fields,
+                    vec![
+                        "boot-source",
+                        "machine-config",
+                        "drives[1]",
+                        "drives[2]",
+                        "network-interfaces[1]",
+                    ]
+                );
+                assert_eq!(
+                    field_errors[2].error.get_code(),
+                    ErrorCode::DriveRootAlreadyAdded
+                );
+                // Repeated IDs are errors, rather than updates.
+                assert_eq!(field_errors[3].error.get_code(), ErrorCode::DriveDuplicateId);
+                assert_eq!(
+                    field_errors[4].error.get_code(),
+                    ErrorCode::NetworkInterfaceDuplicateId
+                );
+            }
+            _ => panic!("Expected an InvalidFields error."),
+        }
+        assert!(vmm.kernel_config.is_some());
+        assert_eq!(vmm.vm_config.vcpu_count, Some(2));
+        assert_eq!(vmm.block_device_configs.config_list.len(), 1);
+        assert!(vmm.network_interface_configs.get("eth1").is_none());
+        assert!(
+            vmm.network_interface_configs
+                .get("eth0")
+                .unwrap()
+                .tap
+                .is_some()
+        );
+
+        // The missing parts are reset to their defaults.
+        let microvm_config: MicrovmConfig = serde_json::from_str("{}").unwrap();
+        assert!(vmm.configure_microvm(microvm_config).is_ok());
+        assert!(vmm.kernel_config.is_none());
+        assert!(vmm.boot_source_config.is_none());
+        assert_eq!(vmm.vm_config, VmConfig::default());
+        assert!(vmm.block_device_configs.config_list.is_empty());
+        assert!(vmm.network_interface_configs.get("eth0").is_none());
+
+        vmm.set_instance_state(InstanceState::Running);
+        let microvm_config: MicrovmConfig = serde_json::from_str("{}").unwrap();
+        match vmm.configure_microvm(microvm_config) {
+            Err(VmmActionError::MicrovmConfig(
+                ErrorKind::User,
+                MicrovmConfigError::UpdateNotAllowedPostBoot,
+            )) => (),
+            _ => panic!("Expected an UpdateNotAllowedPostBoot error."),
+        }
+    }
+
+    #[test]
+    fn test_get_resource_configs() {
+        let mut vmm = create_vmm_object(InstanceState::Uninitialized);
+
+        // Nothing configured yet.
+        match vmm.get_boot_source() {
+            Err(VmmActionError::BootSource(
+                ErrorKind::User,
+                BootSourceConfigError::NotConfigured,
+            )) => (),
+            _ => assert!(false),
+        }
+        match vmm.get_block_devices(None) {
+            Ok(VmmData::ResourceConfig(config)) => assert_eq!(config.to_string(), "[]"),
+            _ => assert!(false),
+        }
+        match vmm.get_network_interfaces(None) {
+            Ok(VmmData::ResourceConfig(config)) => assert_eq!(config.to_string(), "[]"),
+            _ => assert!(false),
+        }
+
+        // Boot source.
+        let kernel_file = NamedTempFile::new().expect("Failed to create temporary kernel file.");
+        let kernel_path = String::from(kernel_file.path().to_str().unwrap());
+        assert!(
+            vmm.configure_boot_source(kernel_path.clone(), Some(String::from("reboot=k")))
+                .is_ok()
+        );
+        match vmm.get_boot_source() {
+            Ok(VmmData::ResourceConfig(config)) => assert_eq!(
+                config,
+                serde_json::from_str::<Value>(&format!(
+                    r#"{{"kernel_image_path": "{}", "boot_args": "reboot=k"}}"#,
+                    kernel_path
+                )).unwrap()
+            ),
+            _ => assert!(false),
+        }
+
+        // Block devices.
+        let f = NamedTempFile::new().unwrap();
+        let path_on_host = f.path().to_str().unwrap();
+        let root_block_device = BlockDeviceConfig {
+            drive_id: String::from("root"),
+            path_on_host: f.path().to_path_buf(),
+            is_root_device: true,
+            partuuid: None,
+            is_read_only: true,
+            rate_limiter: None,
+        };
+        assert!(vmm.insert_block_device(root_block_device).is_ok());
+        let root_json: Value = serde_json::from_str(&format!(
+            r#"{{
+                "drive_id": "root",
+                "path_on_host": "{}",
+                "is_root_device": true,
+                "is_read_only": true
+            }}"#,
+            path_on_host
+        )).unwrap();
+        match vmm.get_block_devices(None) {
+            Ok(VmmData::ResourceConfig(config)) => {
+                assert_eq!(config, Value::Array(vec![root_json.clone()]))
+            }
+            _ => assert!(false),
+        }
+        match vmm.get_block_devices(Some(String::from("root"))) {
+            Ok(VmmData::ResourceConfig(config)) => assert_eq!(config, root_json),
+            _ => assert!(false),
+        }
+        match vmm.get_block_devices(Some(String::from("foo"))) {
+            Err(VmmActionError::DriveConfig(ErrorKind::User, DriveError::InvalidBlockDeviceID)) => {
+                ()
+            }
+            _ => assert!(false),
+        }
+
+        // Network interfaces.
+        let network_interface = NetworkInterfaceConfig {
+            iface_id: String::from("netif"),
+            host_dev_name: Some(String::from("hostname")),
+            guest_mac: Some(MacAddr::parse_str("01:23:45:67:89:0A").unwrap()),
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+            allow_mmds_requests: false,
+            tx_filter: None,
+            user_net: None,
+            tap: None,
+        };
+        assert!(vmm.insert_net_device(network_interface).is_ok());
+        let netif_json: Value = serde_json::from_str(
+            r#"{
+                "iface_id": "netif",
+                "host_dev_name": "hostname",
+                "guest_mac": "01:23:45:67:89:0a",
+                "allow_mmds_requests": false
+            }"#,
+        ).unwrap();
+        match vmm.get_network_interfaces(None) {
+            Ok(VmmData::ResourceConfig(config)) => {
+                assert_eq!(config, Value::Array(vec![netif_json.clone()]))
+            }
+            _ => assert!(false),
+        }
+        match vmm.get_network_interfaces(Some(String::from("netif"))) {
+            Ok(VmmData::ResourceConfig(config)) => assert_eq!(config, netif_json),
+            _ => assert!(false),
+        }
+        match vmm.get_network_interfaces(Some(String::from("foo"))) {
+            Err(VmmActionError::NetworkConfig(
+                ErrorKind::User,
+                NetworkInterfaceError::InvalidIfaceId(ref id),
+            )) => assert_eq!(id, "foo"),
+            _ => assert!(false),
+        }
+    }
+
     #[test]
     fn test_rescan() {
         let mut vmm = create_vmm_object(InstanceState::Uninitialized);
@@ -2651,6 +3548,7 @@ mod tests {
             show_log_origin: None,
         };
         assert!(vmm.init_logger(desc).is_err());
+        assert!(vmm.get_logger().is_err());
 
         // Initializing logger with valid pipes is ok.
         let log_file = NamedTempFile::new().unwrap();
@@ -2662,6 +3560,12 @@ mod tests {
             show_level: Some(true),
             show_log_origin: Some(true),
         };
-        assert!(vmm.init_logger(desc).is_ok());
+        assert!(vmm.init_logger(desc.clone()).is_ok());
+        match vmm.get_logger() {
+            Ok(VmmData::ResourceConfig(config)) => {
+                assert_eq!(config, serde_json::to_value(&desc).unwrap())
+            }
+            _ => assert!(false),
+        }
     }
 }
@@ -3,9 +3,11 @@
 
 use std::fmt::{Display, Formatter, Result};
 
+use error_code::ErrorCode;
+
 /// Strongly typed data structure used to configure the boot source of the
 /// microvm.
-#[derive(Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct BootSourceConfig {
     /// Path of the kernel image.
@@ -23,6 +25,8 @@ pub enum BootSourceConfigError {
     InvalidKernelPath,
     /// The kernel command line is invalid.
     InvalidKernelCommandLine,
+    /// The boot source was not configured yet.
+    NotConfigured,
     /// The boot source cannot be update post boot.
     UpdateNotAllowedPostBoot,
 }
@@ -37,9 +41,23 @@ impl Display for BootSourceConfigError {
                  invalid permissions.",
             ),
             InvalidKernelCommandLine => write!(f, "The kernel command line is invalid!"),
+            NotConfigured => write!(f, "The boot source is not configured."),
             UpdateNotAllowedPostBoot => {
                 write!(f, "The update operation is not allowed after boot.")
             }
         }
     }
 }
+
+impl BootSourceConfigError {
+    /// Returns the code identifying the error in API responses.
+    pub fn error_code(&self) -> ErrorCode {
+        use self::BootSourceConfigError::*;
+        match *self {
+            InvalidKernelPath => ErrorCode::BootSourceInvalidKernelPath,
+            InvalidKernelCommandLine => ErrorCode::BootSourceInvalidKernelCommandLine,
+            NotConfigured => ErrorCode::BootSourceNotConfigured,
+            UpdateNotAllowedPostBoot => ErrorCode::BootSourceUpdateNotAllowedPostBoot,
+        }
+    }
+}
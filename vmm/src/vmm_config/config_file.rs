@@ -0,0 +1,174 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt::{Display, Formatter, Result};
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use serde_json::{self, Value};
+
+use vmm_config::boot_source::BootSourceConfig;
+use vmm_config::drive::BlockDeviceConfig;
+use vmm_config::logger::LoggerConfig;
+use vmm_config::machine_config::VmConfig;
+use vmm_config::mmds::MmdsConfig;
+use vmm_config::net::NetworkInterfaceConfig;
+
+/// Strongly typed structure describing the whole pre-boot configuration of a microVM. The field
+/// names match the API resources which accept the same configuration.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    /// The boot source, as accepted by `/boot-source`.
+    #[serde(rename = "boot-source")]
+    pub boot_source: BootSourceConfig,
+    /// The memory and vCPU configuration, as accepted by `/machine-config`.
+    #[serde(rename = "machine-config")]
+    pub machine_config: Option<VmConfig>,
+    /// The block devices, as accepted by `/drives/{drive_id}`.
+    #[serde(default)]
+    pub drives: Vec<BlockDeviceConfig>,
+    /// The network interfaces, as accepted by `/network-interfaces/{iface_id}`.
+    #[serde(default, rename = "network-interfaces")]
+    pub network_interfaces: Vec<NetworkInterfaceConfig>,
+    /// The logger configuration, as accepted by `/logger`.
+    pub logger: Option<LoggerConfig>,
+    /// How the MMDS is exposed to the guest, as accepted by `/mmds/config`.
+    #[serde(rename = "mmds-config")]
+    pub mmds_config: Option<MmdsConfig>,
+    /// The initial contents of the MMDS, as accepted by `/mmds`.
+    pub mmds: Option<Value>,
+}
+
+/// Errors associated with loading a `ConfigFile`.
+#[derive(Debug)]
+pub enum ConfigFileError {
+    /// The config file cannot be opened.
+    Open(io::Error),
+    /// The config file is not a valid JSON microVM configuration.
+    Parse(serde_json::Error),
+}
+
+impl Display for ConfigFileError {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        use self::ConfigFileError::*;
+        match *self {
+            Open(ref e) => write!(f, "Cannot open the config file: {}", e),
+            Parse(ref e) => write!(f, "Invalid config file: {}", e),
+        }
+    }
+}
+
+impl ConfigFile {
+    /// Reads the microVM configuration from the JSON document found at `path`.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> std::result::Result<Self, ConfigFileError> {
+        let file = File::open(path).map_err(ConfigFileError::Open)?;
+        serde_json::from_reader(file).map_err(ConfigFileError::Parse)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempfile;
+
+    use super::*;
+
+    use std::io::Write;
+
+    use self::tempfile::NamedTempFile;
+
+    fn config_file_with(contents: &str) -> NamedTempFile {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+        file
+    }
+
+    #[test]
+    fn test_from_path() {
+        let file = config_file_with(
+            r#"{
+                "boot-source": {
+                    "kernel_image_path": "/tmp/vmlinux.bin",
+                    "boot_args": "console=ttyS0"
+                },
+                "machine-config": {
+                    "vcpu_count": 2,
+                    "mem_size_mib": 256
+                },
+                "drives": [
+                    {
+                        "drive_id": "rootfs",
+                        "path_on_host": "/tmp/rootfs.ext4",
+                        "is_root_device": true,
+                        "is_read_only": false
+                    }
+                ],
+                "network-interfaces": [
+                    {
+                        "iface_id": "eth0",
+                        "host_dev_name": "tap0",
+                        "guest_mac": "06:00:00:00:00:01"
+                    }
+                ],
+                "mmds": {
+                    "latest": {"meta-data": {"ami-id": "ami-12345678"}}
+                }
+            }"#,
+        );
+
+        let config = ConfigFile::from_path(file.path()).unwrap();
+        assert_eq!(config.boot_source.kernel_image_path, "/tmp/vmlinux.bin");
+        assert_eq!(config.boot_source.boot_args, Some(String::from("console=ttyS0")));
+        let vm_config = config.machine_config.unwrap();
+        assert_eq!(vm_config.vcpu_count, Some(2));
+        assert_eq!(vm_config.mem_size_mib, Some(256));
+        assert_eq!(config.drives.len(), 1);
+        assert_eq!(config.drives[0].drive_id, "rootfs");
+        assert!(config.drives[0].is_root_device);
+        assert_eq!(config.network_interfaces.len(), 1);
+        assert_eq!(config.network_interfaces[0].iface_id, "eth0");
+        assert!(config.logger.is_none());
+        assert!(config.mmds_config.is_none());
+        assert_eq!(
+            config.mmds.unwrap()["latest"]["meta-data"]["ami-id"],
+            "ami-12345678"
+        );
+
+        // Only the boot source is mandatory.
+        let file = config_file_with(r#"{"boot-source": {"kernel_image_path": "/tmp/vmlinux"}}"#);
+        let config = ConfigFile::from_path(file.path()).unwrap();
+        assert!(config.machine_config.is_none());
+        assert!(config.drives.is_empty());
+        assert!(config.network_interfaces.is_empty());
+        assert!(config.mmds.is_none());
+    }
+
+    #[test]
+    fn test_from_path_errors() {
+        match ConfigFile::from_path("/this/path/does/not/exist") {
+            Err(ConfigFileError::Open(_)) => (),
+            _ => panic!("Expected an Open error."),
+        }
+
+        let file = config_file_with(r#"{"drives": []}"#);
+        match ConfigFile::from_path(file.path()) {
+            Err(ConfigFileError::Parse(_)) => (),
+            _ => panic!("Expected a Parse error."),
+        }
+
+        let file = config_file_with(
+            r#"{"boot-source": {"kernel_image_path": "/tmp/vmlinux"}, "actions": {}}"#,
+        );
+        match ConfigFile::from_path(file.path()) {
+            Err(ConfigFileError::Parse(_)) => (),
+            _ => panic!("Expected a Parse error."),
+        }
+
+        let file = config_file_with("{");
+        match ConfigFile::from_path(file.path()) {
+            Err(ConfigFileError::Parse(_)) => (),
+            _ => panic!("Expected a Parse error."),
+        }
+    }
+}
@@ -7,6 +7,7 @@ use std::fmt::{Display, Formatter};
 use std::path::PathBuf;
 use std::result;
 
+use error_code::ErrorCode;
 use rate_limiter::RateLimiter;
 
 type Result<T> = result::Result<T, DriveError>;
@@ -16,6 +17,8 @@ type Result<T> = result::Result<T, DriveError>;
 pub enum DriveError {
     /// Cannot open block device due to invalid permissions or path.
     CannotOpenBlockDevice,
+    /// The block device ID is used more than once in the same configuration.
+    DuplicateBlockDeviceID,
     /// The block device ID is invalid.
     InvalidBlockDeviceID,
     /// The block device path is invalid.
@@ -39,6 +42,7 @@ impl Display for DriveError {
             CannotOpenBlockDevice => {
                 write!(f, "Cannot open block device. Invalid permission/path.")
             }
+            DuplicateBlockDeviceID => write!(f, "The block device ID is used more than once!"),
             InvalidBlockDeviceID => write!(f, "Invalid block device ID!"),
             InvalidBlockDevicePath => write!(f, "Invalid block device path!"),
             BlockDevicePathAlreadyExists => write!(
@@ -55,8 +59,26 @@ impl Display for DriveError {
     }
 }
 
+impl DriveError {
+    /// Returns the code identifying the error in API responses.
+    pub fn error_code(&self) -> ErrorCode {
+        use self::DriveError::*;
+        match *self {
+            CannotOpenBlockDevice => ErrorCode::DriveCannotOpen,
+            DuplicateBlockDeviceID => ErrorCode::DriveDuplicateId,
+            InvalidBlockDeviceID => ErrorCode::DriveInvalidId,
+            InvalidBlockDevicePath => ErrorCode::DriveInvalidPath,
+            BlockDevicePathAlreadyExists => ErrorCode::DrivePathAlreadyExists,
+            BlockDeviceUpdateFailed => ErrorCode::DriveUpdateFailed,
+            OperationNotAllowedPreBoot => ErrorCode::DriveOperationNotAllowedPreBoot,
+            UpdateNotAllowedPostBoot => ErrorCode::DriveUpdateNotAllowedPostBoot,
+            RootBlockDeviceAlreadyAdded => ErrorCode::DriveRootAlreadyAdded,
+        }
+    }
+}
+
 /// Use this structure to set up the Block Device before booting the kernel.
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct BlockDeviceConfig {
     /// Unique identifier of the drive.
@@ -69,11 +91,13 @@ pub struct BlockDeviceConfig {
     pub is_root_device: bool,
     /// Part-UUID. Represents the unique id of the boot partition of this device. It is
     /// optional and it will be used only if the `is_root_device` field is true.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub partuuid: Option<String>,
     /// If set to true, the drive is opened in read-only mode. Otherwise, the
     /// drive is opened as read-write.
     pub is_read_only: bool,
     /// Rate Limiter for I/O operations.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub rate_limiter: Option<RateLimiter>,
 }
 
@@ -129,6 +153,11 @@ impl BlockDeviceConfigs {
         self.has_partuuid_root
     }
 
+    /// Returns the configuration of the device with the specified `drive_id`, if it exists.
+    pub fn get(&self, drive_id: &str) -> Option<&BlockDeviceConfig> {
+        self.config_list.iter().find(|cfg| cfg.drive_id == drive_id)
+    }
+
     /// Gets the index of the device with the specified `drive_id` if it exists in the list.
     pub fn get_index_of_drive_id(&self, drive_id: &String) -> Option<usize> {
         return self
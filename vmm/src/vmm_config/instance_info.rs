@@ -6,6 +6,7 @@ use std::fmt::{Display, Formatter, Result};
 
 use device_manager;
 use devices;
+use error_code::ErrorCode;
 use kernel::loader as kernel_loader;
 use memory_model::GuestMemoryError;
 use seccomp;
@@ -16,7 +17,9 @@ use x86_64;
 /// The microvm state. When Firecracker starts, the instance state is Uninitialized.
 /// Once start_microvm method is called, the state goes from Uninitialized to Starting.
 /// The state is changed to Running before ending the start_microvm method.
-/// Halting and Halted are currently unsupported.
+/// When the VMM stops, the state goes to Halting, and then to Halted right before the Firecracker
+/// process exits. Since the process does not wait for anyone to notice, the Halted state is not
+/// guaranteed to reach the API clients or the event subscribers.
 #[derive(Clone, Debug, PartialEq, Serialize)]
 pub enum InstanceState {
     /// Microvm is not initialized.
@@ -38,6 +41,8 @@ pub struct InstanceInfo {
     pub id: String,
     /// The state of the microVM.
     pub state: InstanceState,
+    /// The version of the Firecracker binary.
+    pub vmm_version: String,
 }
 
 /// Errors associated with starting the instance.
@@ -56,6 +61,8 @@ pub enum StartMicrovmError {
     /// Internal errors are due to resource exhaustion.
     /// Users errors are due to invalid permissions.
     CreateNetDevice(devices::virtio::Error),
+    /// Cannot create the rate limiter of a device.
+    CreateRateLimiter(std::io::Error),
     #[cfg(feature = "vsock")]
     /// Creating a vsock device can only fail if the /dev/vhost-vsock device cannot be open.
     CreateVsockDevice(devices::virtio::vhost::Error),
@@ -77,6 +84,8 @@ pub enum StartMicrovmError {
     MicroVMAlreadyRunning,
     /// Cannot start the VM because the kernel was not configured.
     MissingKernelConfig,
+    /// The MMDS configuration refers to a network interface which does not exist.
+    MmdsNetworkInterfaceNotFound(String),
     /// The net device configuration is missing the tap device.
     NetDeviceNotConfigured,
     /// Cannot open the block device backing file.
@@ -137,6 +146,7 @@ impl Display for StartMicrovmError {
 
                 write!(f, "Cannot create network device. {}", err_msg)
             }
+            CreateRateLimiter(ref err) => write!(f, "Cannot create rate limiter: {}", err),
             DeviceVmRequest(ref err) => {
                 let mut err_msg = format!("{:?}", err);
                 err_msg = err_msg.replace("\"", "");
@@ -171,6 +181,11 @@ impl Display for StartMicrovmError {
             }
             MicroVMAlreadyRunning => write!(f, "Microvm already running."),
             MissingKernelConfig => write!(f, "Cannot start microvm without kernel configuration."),
+            MmdsNetworkInterfaceNotFound(ref iface_id) => write!(
+                f,
+                "The MMDS configuration refers to the unknown network interface {}.",
+                iface_id
+            ),
             NetDeviceNotConfigured => {
                 write!(f, "The net device configuration is missing the tap device.")
             }
@@ -239,3 +254,37 @@ impl Display for StartMicrovmError {
         }
     }
 }
+
+impl StartMicrovmError {
+    /// Returns the code identifying the error in API responses. The internal errors are grouped
+    /// by the part of the microVM which couldn't be set up.
+    pub fn error_code(&self) -> ErrorCode {
+        use self::StartMicrovmError::*;
+        match *self {
+            ConfigureSystem(_) | GuestMemory(_) => ErrorCode::StartMicrovmMemorySetup,
+            ConfigureVm(_) | EventFd => ErrorCode::StartMicrovmVmSetup,
+            CreateBlockDevice(_)
+            | CreateNetDevice(_)
+            | CreateRateLimiter(_)
+            | DeviceManager
+            | DeviceVmRequest(_)
+            | LegacyIOBus(_)
+            | NetDeviceNotConfigured
+            | RegisterBlockDevice(_)
+            | RegisterEvent
+            | RegisterNetDevice(_) => ErrorCode::StartMicrovmDeviceSetup,
+            #[cfg(feature = "vsock")]
+            CreateVsockDevice(_) | RegisterVsockDevice(_) => ErrorCode::StartMicrovmDeviceSetup,
+            KernelCmdline(_) => ErrorCode::StartMicrovmInvalidKernelCommandLine,
+            Loader(_) => ErrorCode::StartMicrovmKernelLoader,
+            MicroVMAlreadyRunning => ErrorCode::StartMicrovmAlreadyRunning,
+            MissingKernelConfig => ErrorCode::StartMicrovmMissingKernelConfig,
+            MmdsNetworkInterfaceNotFound(_) => ErrorCode::StartMicrovmMmdsNetworkInterfaceNotFound,
+            OpenBlockDevice(_) => ErrorCode::StartMicrovmOpenBlockDevice,
+            SeccompFilters(_) => ErrorCode::StartMicrovmSeccompFilters,
+            Vcpu(_) | VcpuConfigure(_) | VcpusNotConfigured | VcpuSpawn(_) => {
+                ErrorCode::StartMicrovmVcpuSetup
+            }
+        }
+    }
+}
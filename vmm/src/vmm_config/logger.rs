@@ -3,6 +3,8 @@
 
 use std::fmt::{Display, Formatter, Result};
 
+use error_code::ErrorCode;
+
 /// Enum used for setting the log level.
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub enum LoggerLevel {
@@ -43,6 +45,8 @@ pub struct LoggerConfig {
 pub enum LoggerConfigError {
     /// Cannot initialize the logger due to bad user input.
     InitializationFailure(String),
+    /// The logger was not configured yet.
+    NotConfigured,
 }
 
 impl Display for LoggerConfigError {
@@ -50,6 +54,18 @@ impl Display for LoggerConfigError {
         use self::LoggerConfigError::*;
         match *self {
             InitializationFailure(ref err_msg) => write!(f, "{}", err_msg),
+            NotConfigured => write!(f, "The logger is not configured."),
+        }
+    }
+}
+
+impl LoggerConfigError {
+    /// Returns the code identifying the error in API responses.
+    pub fn error_code(&self) -> ErrorCode {
+        use self::LoggerConfigError::*;
+        match *self {
+            InitializationFailure(_) => ErrorCode::LoggerInitializationFailure,
+            NotConfigured => ErrorCode::LoggerNotConfigured,
         }
     }
 }
@@ -3,6 +3,8 @@
 
 use std::fmt::{Display, Formatter, Result};
 
+use error_code::ErrorCode;
+
 /// Errors associated with configuring the microVM.
 #[derive(Debug, PartialEq)]
 pub enum VmConfigError {
@@ -32,6 +34,18 @@ impl Display for VmConfigError {
     }
 }
 
+impl VmConfigError {
+    /// Returns the code identifying the error in API responses.
+    pub fn error_code(&self) -> ErrorCode {
+        use self::VmConfigError::*;
+        match *self {
+            InvalidVcpuCount => ErrorCode::MachineConfigInvalidVcpuCount,
+            InvalidMemorySize => ErrorCode::MachineConfigInvalidMemorySize,
+            UpdateNotAllowedPostBoot => ErrorCode::MachineConfigUpdateNotAllowedPostBoot,
+        }
+    }
+}
+
 /// Strongly typed structure that represents the configuration of the
 /// microvm.
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
@@ -81,6 +95,13 @@ impl Display for CpuFeaturesTemplate {
     }
 }
 
+impl CpuFeaturesTemplate {
+    /// Returns all the available templates.
+    pub fn all() -> Vec<CpuFeaturesTemplate> {
+        vec![CpuFeaturesTemplate::C3, CpuFeaturesTemplate::T2]
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
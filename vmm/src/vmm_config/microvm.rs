@@ -0,0 +1,166 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt::{Display, Formatter, Result};
+
+use error_code::ErrorCode;
+use vmm_config::boot_source::BootSourceConfig;
+use vmm_config::drive::BlockDeviceConfig;
+use vmm_config::machine_config::VmConfig;
+use vmm_config::mmds::MmdsConfig;
+use vmm_config::net::NetworkInterfaceConfig;
+use VmmActionError;
+
+/// Strongly typed structure describing the whole pre-boot configuration of a microVM, which
+/// replaces the current one at once: either every part of it is valid and gets applied, or the
+/// microVM is left untouched. The field names match the API resources which accept the same
+/// configuration, and the missing parts are reset to their defaults. The logger and the vsock
+/// devices are not part of it, and keep the configuration set through their own resources.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct MicrovmConfig {
+    /// The boot source, as accepted by `/boot-source`.
+    #[serde(rename = "boot-source")]
+    pub boot_source: Option<BootSourceConfig>,
+    /// The memory and vCPU configuration, as accepted by `/machine-config`.
+    #[serde(rename = "machine-config")]
+    pub machine_config: Option<VmConfig>,
+    /// The block devices, as accepted by `/drives/{drive_id}`.
+    #[serde(default)]
+    pub drives: Vec<BlockDeviceConfig>,
+    /// The network interfaces, as accepted by `/network-interfaces/{iface_id}`.
+    #[serde(default, rename = "network-interfaces")]
+    pub network_interfaces: Vec<NetworkInterfaceConfig>,
+    /// How the MMDS is exposed to the guest, as accepted by `/mmds/config`.
+    #[serde(rename = "mmds-config")]
+    pub mmds_config: Option<MmdsConfig>,
+}
+
+/// An error found in one of the fields of a `MicrovmConfig`.
+#[derive(Debug)]
+pub struct FieldError {
+    /// The field the error refers to, such as `machine-config` or `drives[1]`.
+    pub field: String,
+    /// The error, as reported by the API resource which accepts the same configuration.
+    pub error: VmmActionError,
+}
+
+impl FieldError {
+    /// Creates the error of the field `field`.
+    pub fn new<T: Into<String>>(field: T, error: VmmActionError) -> Self {
+        FieldError {
+            field: field.into(),
+            error,
+        }
+    }
+}
+
+/// Errors associated with replacing the configuration of the microVM.
+#[derive(Debug)]
+pub enum MicrovmConfigError {
+    /// Some fields of the configuration are not valid.
+    InvalidFields(Vec<FieldError>),
+    /// The configuration cannot be replaced after the microVM has booted.
+    UpdateNotAllowedPostBoot,
+}
+
+impl Display for MicrovmConfigError {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        use self::MicrovmConfigError::*;
+        match *self {
+            InvalidFields(ref field_errors) => {
+                write!(f, "The microVM configuration is not valid: ")?;
+                for (index, field_error) in field_errors.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, "; ")?;
+                    }
+                    write!(f, "{}: {}", field_error.field, field_error.error)?;
+                }
+                Ok(())
+            }
+            UpdateNotAllowedPostBoot => write!(
+                f,
+                "The microVM configuration cannot be replaced after the microVM has booted."
+            ),
+        }
+    }
+}
+
+impl MicrovmConfigError {
+    /// Returns the code identifying the error in API responses.
+    pub fn error_code(&self) -> ErrorCode {
+        use self::MicrovmConfigError::*;
+        match *self {
+            InvalidFields(_) => ErrorCode::MicrovmConfigInvalidFields,
+            UpdateNotAllowedPostBoot => ErrorCode::MicrovmConfigUpdateNotAllowedPostBoot,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json;
+
+    use vmm_config::drive::DriveError;
+    use vmm_config::machine_config::VmConfigError;
+    use ErrorKind;
+
+    #[test]
+    fn test_deserialize() {
+        let config: MicrovmConfig = serde_json::from_str(
+            r#"{
+                "boot-source": {"kernel_image_path": "/tmp/vmlinux.bin"},
+                "machine-config": {"vcpu_count": 2},
+                "drives": [
+                    {
+                        "drive_id": "rootfs",
+                        "path_on_host": "/tmp/rootfs.ext4",
+                        "is_root_device": true,
+                        "is_read_only": false
+                    }
+                ],
+                "network-interfaces": [{"iface_id": "eth0", "host_dev_name": "tap0"}]
+            }"#,
+        ).unwrap();
+        assert_eq!(
+            config.boot_source.unwrap().kernel_image_path,
+            "/tmp/vmlinux.bin"
+        );
+        assert_eq!(config.machine_config.unwrap().vcpu_count, Some(2));
+        assert_eq!(config.drives[0].drive_id, "rootfs");
+        assert_eq!(config.network_interfaces[0].iface_id, "eth0");
+        assert!(config.mmds_config.is_none());
+
+        // Every part is optional.
+        let config: MicrovmConfig = serde_json::from_str("{}").unwrap();
+        assert!(config.boot_source.is_none());
+        assert!(config.drives.is_empty());
+
+        assert!(serde_json::from_str::<MicrovmConfig>(r#"{"logger": {}}"#).is_err());
+    }
+
+    #[test]
+    fn test_display() {
+        let error = MicrovmConfigError::InvalidFields(vec![
+            FieldError::new(
+                "machine-config",
+                VmmActionError::MachineConfig(ErrorKind::User, VmConfigError::InvalidVcpuCount),
+            ),
+            FieldError::new(
+                "drives[1]",
+                VmmActionError::DriveConfig(ErrorKind::User, DriveError::InvalidBlockDevicePath),
+            ),
+        ]);
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "The microVM configuration is not valid: machine-config: {}; drives[1]: {}",
+                VmConfigError::InvalidVcpuCount,
+                DriveError::InvalidBlockDevicePath
+            )
+        );
+        assert_eq!(error.error_code(), ErrorCode::MicrovmConfigInvalidFields);
+    }
+}
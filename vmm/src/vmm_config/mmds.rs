@@ -0,0 +1,477 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt::{Debug, Display, Formatter, Result};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::num::{NonZeroU16, NonZeroU64, NonZeroUsize};
+
+use dumbo::ns::{
+    MmdsNetworkStackConfig, DEFAULT_CONNECTION_RTO_COUNT_MAX, DEFAULT_CONNECTION_RTO_PERIOD,
+    DEFAULT_IPV4_ADDR, DEFAULT_MAX_CONNECTIONS, DEFAULT_TCP_PORT, MAX_CONNECTIONS_LIMIT,
+};
+use error_code::ErrorCode;
+use mmds::data_store::DEFAULT_DATA_STORE_LIMIT;
+use mmds::token::{TokenAuthority, TokenMode};
+
+// The minimum length of a user provided key for signing session tokens.
+const MIN_TOKEN_KEY_LEN: usize = 16;
+
+/// Describes whether guest requests to the MMDS have to present a session token.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MmdsTokenMode {
+    /// Session tokens can't be requested.
+    Disabled,
+    /// Requests without a session token are accepted.
+    Optional,
+    /// Requests without a valid session token are rejected.
+    Required,
+}
+
+impl Default for MmdsTokenMode {
+    fn default() -> Self {
+        MmdsTokenMode::Disabled
+    }
+}
+
+/// This struct represents the strongly typed equivalent of the json body describing how the
+/// MMDS is exposed to the guest.
+#[derive(Clone, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MmdsConfig {
+    /// The IPv4 address the MMDS answers on.
+    #[serde(default = "default_ipv4_address")]
+    pub ipv4_address: Ipv4Addr,
+    /// The IPv6 address the MMDS answers on. The MMDS is not reachable over IPv6 when missing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ipv6_address: Option<Ipv6Addr>,
+    /// The TCP port the MMDS listens on.
+    #[serde(default = "default_tcp_port")]
+    pub tcp_port: u16,
+    /// The maximum number of concurrent guest connections to the MMDS, per network interface. At
+    /// most `MAX_CONNECTIONS_LIMIT`.
+    #[serde(default = "default_max_connections")]
+    pub max_connections: usize,
+    /// The TCP retransmission timeout of guest connections, expressed in CPU cycles.
+    #[serde(default = "default_connection_rto_period")]
+    pub connection_rto_period: u64,
+    /// How many times in a row the retransmission timer of a guest connection can fire before
+    /// the connection is reset.
+    #[serde(default = "default_connection_rto_count_max")]
+    pub connection_rto_count_max: u16,
+    /// When present, the MMDS is reachable from the guest only via the network interfaces with
+    /// these IDs, regardless of their `allow_mmds_requests` setting.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub network_interfaces: Option<Vec<String>>,
+    /// Whether guest requests have to present a session token.
+    #[serde(default)]
+    pub token_mode: MmdsTokenMode,
+    /// The key used for signing session tokens. A random key is used when missing.
+    #[serde(skip_serializing)]
+    pub token_key: Option<String>,
+    /// The maximum size, in bytes, of the serialized MMDS contents.
+    #[serde(default = "default_data_store_limit")]
+    pub data_store_limit: usize,
+    /// The maximum size, in bytes, of the serialized contents the guest can write under
+    /// `/guest`. Guest writes are disabled when 0.
+    #[serde(default)]
+    pub guest_data_limit: usize,
+}
+
+fn default_ipv4_address() -> Ipv4Addr {
+    Ipv4Addr::from(DEFAULT_IPV4_ADDR)
+}
+
+fn default_tcp_port() -> u16 {
+    DEFAULT_TCP_PORT
+}
+
+fn default_max_connections() -> usize {
+    DEFAULT_MAX_CONNECTIONS
+}
+
+fn default_connection_rto_period() -> u64 {
+    DEFAULT_CONNECTION_RTO_PERIOD
+}
+
+fn default_connection_rto_count_max() -> u16 {
+    DEFAULT_CONNECTION_RTO_COUNT_MAX
+}
+
+fn default_data_store_limit() -> usize {
+    DEFAULT_DATA_STORE_LIMIT
+}
+
+impl Default for MmdsConfig {
+    fn default() -> Self {
+        MmdsConfig {
+            ipv4_address: default_ipv4_address(),
+            ipv6_address: None,
+            tcp_port: default_tcp_port(),
+            max_connections: default_max_connections(),
+            connection_rto_period: default_connection_rto_period(),
+            connection_rto_count_max: default_connection_rto_count_max(),
+            network_interfaces: None,
+            token_mode: MmdsTokenMode::default(),
+            token_key: None,
+            data_store_limit: default_data_store_limit(),
+            guest_data_limit: 0,
+        }
+    }
+}
+
+// Written by hand, so that the token key doesn't end up in the log.
+impl Debug for MmdsConfig {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        f.debug_struct("MmdsConfig")
+            .field("ipv4_address", &self.ipv4_address)
+            .field("ipv6_address", &self.ipv6_address)
+            .field("tcp_port", &self.tcp_port)
+            .field("max_connections", &self.max_connections)
+            .field("connection_rto_period", &self.connection_rto_period)
+            .field("connection_rto_count_max", &self.connection_rto_count_max)
+            .field("network_interfaces", &self.network_interfaces)
+            .field("token_mode", &self.token_mode)
+            .field("token_key", &self.token_key.as_ref().map(|_| "<redacted>"))
+            .field("data_store_limit", &self.data_store_limit)
+            .field("guest_data_limit", &self.guest_data_limit)
+            .finish()
+    }
+}
+
+impl MmdsConfig {
+    /// Checks that the configuration can be used to build the MMDS network stack.
+    pub fn validate(&self) -> ::std::result::Result<(), MmdsConfigError> {
+        let addr = self.ipv4_address;
+        if addr.is_unspecified() || addr.is_broadcast() || addr.is_multicast() {
+            return Err(MmdsConfigError::InvalidIpv4Addr(addr));
+        }
+        if let Some(addr) = self.ipv6_address {
+            if addr.is_unspecified() || addr.is_loopback() || addr.is_multicast() {
+                return Err(MmdsConfigError::InvalidIpv6Addr(addr));
+            }
+        }
+        if self.tcp_port == 0 {
+            return Err(MmdsConfigError::InvalidTcpPort);
+        }
+        if self.max_connections == 0 {
+            return Err(MmdsConfigError::InvalidMaxConnections);
+        }
+        if self.max_connections > MAX_CONNECTIONS_LIMIT {
+            return Err(MmdsConfigError::MaxConnectionsTooLarge);
+        }
+        if self.connection_rto_period == 0 {
+            return Err(MmdsConfigError::InvalidConnectionRtoPeriod);
+        }
+        if self.connection_rto_count_max == 0 {
+            return Err(MmdsConfigError::InvalidConnectionRtoCountMax);
+        }
+        if let Some(ref key) = self.token_key {
+            if key.len() < MIN_TOKEN_KEY_LEN {
+                return Err(MmdsConfigError::InvalidTokenKey);
+            }
+        }
+        if self.data_store_limit == 0 {
+            return Err(MmdsConfigError::InvalidDataStoreLimit);
+        }
+        Ok(())
+    }
+
+    /// Returns the parameters used by a network interface to build its MMDS network stack, or
+    /// None if MMDS requests must not be detoured for the interface identified by `iface_id`.
+    /// The `allow_mmds_requests` value of the interface is only taken into account when no
+    /// explicit list of interfaces was configured.
+    pub fn network_stack_config(
+        &self,
+        iface_id: &str,
+        allow_mmds_requests: bool,
+    ) -> Option<MmdsNetworkStackConfig> {
+        let enabled = match self.network_interfaces {
+            Some(ref ids) => ids.iter().any(|id| id == iface_id),
+            None => allow_mmds_requests,
+        };
+
+        if !enabled {
+            return None;
+        }
+
+        // The unwraps are safe because validate() rejects values of 0, and the defaults are
+        // greater than 0.
+        Some(MmdsNetworkStackConfig {
+            ipv4_addr: self.ipv4_address,
+            ipv6_addr: self.ipv6_address,
+            tcp_port: self.tcp_port,
+            max_connections: NonZeroUsize::new(self.max_connections).unwrap(),
+            connection_rto_period: NonZeroU64::new(self.connection_rto_period).unwrap(),
+            connection_rto_count_max: NonZeroU16::new(self.connection_rto_count_max).unwrap(),
+        })
+    }
+
+    /// Returns the authority which generates and validates the MMDS session tokens.
+    pub fn token_authority(&self) -> TokenAuthority {
+        let mode = match self.token_mode {
+            MmdsTokenMode::Disabled => TokenMode::Disabled,
+            MmdsTokenMode::Optional => TokenMode::Optional,
+            MmdsTokenMode::Required => TokenMode::Required,
+        };
+        TokenAuthority::new(
+            mode,
+            self.token_key.as_ref().map(|key| key.as_bytes().to_vec()),
+        )
+    }
+}
+
+/// Errors associated with actions on the `MmdsConfig`.
+#[derive(Debug)]
+pub enum MmdsConfigError {
+    /// The maximum number of retransmission timeouts must be greater than 0.
+    InvalidConnectionRtoCountMax,
+    /// The retransmission timeout period must be greater than 0.
+    InvalidConnectionRtoPeriod,
+    /// The data store limit must be greater than 0.
+    InvalidDataStoreLimit,
+    /// The MMDS cannot use the specified IPv4 address.
+    InvalidIpv4Addr(Ipv4Addr),
+    /// The MMDS cannot use the specified IPv6 address.
+    InvalidIpv6Addr(Ipv6Addr),
+    /// The maximum number of connections must be greater than 0.
+    InvalidMaxConnections,
+    /// The TCP port must be greater than 0.
+    InvalidTcpPort,
+    /// The key used for signing session tokens is too short.
+    InvalidTokenKey,
+    /// The maximum number of connections is above the allowed limit.
+    MaxConnectionsTooLarge,
+    /// The update is not allowed after booting the microvm.
+    UpdateNotAllowedPostBoot,
+}
+
+impl Display for MmdsConfigError {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        use self::MmdsConfigError::*;
+        match *self {
+            InvalidConnectionRtoCountMax => write!(
+                f,
+                "The maximum number of MMDS connection retransmission timeouts must be greater \
+                 than 0."
+            ),
+            InvalidConnectionRtoPeriod => write!(
+                f,
+                "The MMDS connection retransmission timeout period must be greater than 0."
+            ),
+            InvalidDataStoreLimit => {
+                write!(f, "The MMDS data store limit must be greater than 0.")
+            }
+            InvalidIpv4Addr(ref addr) => {
+                write!(f, "The MMDS cannot use the IPv4 address {}.", addr)
+            }
+            InvalidIpv6Addr(ref addr) => {
+                write!(f, "The MMDS cannot use the IPv6 address {}.", addr)
+            }
+            InvalidMaxConnections => write!(
+                f,
+                "The maximum number of MMDS connections must be greater than 0."
+            ),
+            InvalidTcpPort => write!(f, "The MMDS TCP port must be greater than 0."),
+            InvalidTokenKey => write!(
+                f,
+                "The MMDS token key must contain at least {} characters.",
+                MIN_TOKEN_KEY_LEN
+            ),
+            MaxConnectionsTooLarge => write!(
+                f,
+                "The maximum number of MMDS connections cannot exceed {}.",
+                MAX_CONNECTIONS_LIMIT
+            ),
+            UpdateNotAllowedPostBoot => {
+                write!(f, "The update operation is not allowed after boot.")
+            }
+        }
+    }
+}
+
+impl MmdsConfigError {
+    /// Returns the code identifying the error in API responses.
+    pub fn error_code(&self) -> ErrorCode {
+        use self::MmdsConfigError::*;
+        match *self {
+            InvalidConnectionRtoCountMax => ErrorCode::MmdsConfigInvalidConnectionRtoCountMax,
+            InvalidConnectionRtoPeriod => ErrorCode::MmdsConfigInvalidConnectionRtoPeriod,
+            InvalidDataStoreLimit => ErrorCode::MmdsConfigInvalidDataStoreLimit,
+            InvalidIpv4Addr(_) => ErrorCode::MmdsConfigInvalidIpv4Addr,
+            InvalidIpv6Addr(_) => ErrorCode::MmdsConfigInvalidIpv6Addr,
+            InvalidMaxConnections => ErrorCode::MmdsConfigInvalidMaxConnections,
+            InvalidTcpPort => ErrorCode::MmdsConfigInvalidTcpPort,
+            InvalidTokenKey => ErrorCode::MmdsConfigInvalidTokenKey,
+            MaxConnectionsTooLarge => ErrorCode::MmdsConfigMaxConnectionsTooLarge,
+            UpdateNotAllowedPostBoot => ErrorCode::MmdsConfigUpdateNotAllowedPostBoot,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate serde_json;
+
+    use super::*;
+
+    #[test]
+    fn test_defaults() {
+        let cfg: MmdsConfig = serde_json::from_str("{}").unwrap();
+        assert_eq!(cfg, MmdsConfig::default());
+        assert!(cfg.validate().is_ok());
+        assert_eq!(
+            cfg.network_stack_config("eth0", true),
+            Some(MmdsNetworkStackConfig::default())
+        );
+        assert!(cfg.network_stack_config("eth0", false).is_none());
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut cfg = MmdsConfig::default();
+        cfg.ipv4_address = Ipv4Addr::new(0, 0, 0, 0);
+        assert!(cfg.validate().is_err());
+        cfg.ipv4_address = Ipv4Addr::new(255, 255, 255, 255);
+        assert!(cfg.validate().is_err());
+        cfg.ipv4_address = Ipv4Addr::new(224, 0, 0, 1);
+        assert!(cfg.validate().is_err());
+        cfg.ipv4_address = Ipv4Addr::new(169, 254, 170, 2);
+        assert!(cfg.validate().is_ok());
+
+        for addr in ["::", "::1", "ff02::1"].iter() {
+            cfg.ipv6_address = Some(addr.parse().unwrap());
+            match cfg.validate() {
+                Err(MmdsConfigError::InvalidIpv6Addr(_)) => (),
+                _ => assert!(false),
+            }
+        }
+        cfg.ipv6_address = Some("fe80::a9fe:a9fe".parse().unwrap());
+        assert!(cfg.validate().is_ok());
+
+        cfg.tcp_port = 0;
+        match cfg.validate() {
+            Err(MmdsConfigError::InvalidTcpPort) => (),
+            _ => assert!(false),
+        }
+        cfg.tcp_port = 8080;
+
+        cfg.max_connections = 0;
+        match cfg.validate() {
+            Err(MmdsConfigError::InvalidMaxConnections) => (),
+            _ => assert!(false),
+        }
+        cfg.max_connections = MAX_CONNECTIONS_LIMIT + 1;
+        match cfg.validate() {
+            Err(MmdsConfigError::MaxConnectionsTooLarge) => (),
+            _ => assert!(false),
+        }
+        assert_eq!(
+            cfg.validate().unwrap_err().to_string(),
+            "The maximum number of MMDS connections cannot exceed 1024."
+        );
+        cfg.max_connections = MAX_CONNECTIONS_LIMIT;
+        assert!(cfg.validate().is_ok());
+        cfg.max_connections = 1;
+
+        cfg.connection_rto_period = 0;
+        match cfg.validate() {
+            Err(MmdsConfigError::InvalidConnectionRtoPeriod) => (),
+            _ => assert!(false),
+        }
+        cfg.connection_rto_period = 1;
+
+        cfg.connection_rto_count_max = 0;
+        match cfg.validate() {
+            Err(MmdsConfigError::InvalidConnectionRtoCountMax) => (),
+            _ => assert!(false),
+        }
+        cfg.connection_rto_count_max = 1;
+
+        cfg.token_key = Some("too short".to_string());
+        match cfg.validate() {
+            Err(MmdsConfigError::InvalidTokenKey) => (),
+            _ => assert!(false),
+        }
+        cfg.token_key = Some("long enough to sign tokens".to_string());
+        assert!(cfg.validate().is_ok());
+
+        cfg.data_store_limit = 0;
+        match cfg.validate() {
+            Err(MmdsConfigError::InvalidDataStoreLimit) => (),
+            _ => assert!(false),
+        }
+        cfg.data_store_limit = 1024;
+        assert!(cfg.validate().is_ok());
+
+        cfg.guest_data_limit = 4096;
+        assert!(cfg.validate().is_ok());
+    }
+
+    #[test]
+    fn test_token_authority() {
+        let cfg: MmdsConfig = serde_json::from_str(
+            r#"{
+                "token_mode": "required",
+                "token_key": "long enough to sign tokens"
+            }"#,
+        ).unwrap();
+        assert!(cfg.validate().is_ok());
+        assert_eq!(cfg.token_mode, MmdsTokenMode::Required);
+
+        let authority = cfg.token_authority();
+        assert_eq!(authority.mode(), TokenMode::Required);
+        // Authorities built from the same key accept each other's tokens.
+        let token = cfg.token_authority().generate_token(60);
+        assert!(authority.is_valid(&token));
+
+        // The key is never serialized, nor printed.
+        assert!(!serde_json::to_string(&cfg).unwrap().contains("token_key"));
+        let debug = format!("{:?}", cfg);
+        assert!(!debug.contains("long enough to sign tokens"));
+        assert!(debug.contains("token_key: Some(\"<redacted>\")"));
+        assert!(format!("{:?}", MmdsConfig::default()).contains("token_key: None"));
+
+        assert_eq!(
+            MmdsConfig::default().token_authority().mode(),
+            TokenMode::Disabled
+        );
+        assert!(serde_json::from_str::<MmdsConfig>(r#"{"token_mode": "strict"}"#).is_err());
+    }
+
+    #[test]
+    fn test_network_interfaces() {
+        let cfg: MmdsConfig = serde_json::from_str(
+            r#"{
+                "ipv4_address": "169.254.170.2",
+                "tcp_port": 8080,
+                "max_connections": 5,
+                "connection_rto_period": 1000,
+                "connection_rto_count_max": 3,
+                "network_interfaces": ["eth1"]
+            }"#,
+        ).unwrap();
+        assert!(cfg.validate().is_ok());
+
+        // The explicit list of interfaces takes precedence over allow_mmds_requests.
+        assert!(cfg.network_stack_config("eth0", true).is_none());
+        let stack_cfg = cfg.network_stack_config("eth1", false).unwrap();
+        assert_eq!(stack_cfg.ipv4_addr, Ipv4Addr::new(169, 254, 170, 2));
+        assert_eq!(stack_cfg.tcp_port, 8080);
+        assert_eq!(stack_cfg.max_connections.get(), 5);
+        assert_eq!(stack_cfg.connection_rto_period.get(), 1000);
+        assert_eq!(stack_cfg.connection_rto_count_max.get(), 3);
+        // IPv6 is off unless an address is configured.
+        assert!(stack_cfg.ipv6_addr.is_none());
+
+        let cfg: MmdsConfig =
+            serde_json::from_str(r#"{"ipv6_address": "fe80::a9fe:a9fe"}"#).unwrap();
+        assert!(cfg.validate().is_ok());
+        assert_eq!(
+            cfg.network_stack_config("eth0", true).unwrap().ipv6_addr,
+            Some("fe80::a9fe:a9fe".parse().unwrap())
+        );
+
+        assert!(serde_json::from_str::<MmdsConfig>(r#"{"foo": 1}"#).is_err());
+    }
+}
@@ -3,6 +3,8 @@
 
 /// Wrapper for configuring the microVM boot source.
 pub mod boot_source;
+/// Wrapper for loading the whole microVM configuration from a file.
+pub mod config_file;
 /// Wrapper for configuring the block devices.
 pub mod drive;
 /// Wrapper over the microVM general information attached to the microVM.
@@ -11,6 +13,10 @@ pub mod instance_info;
 pub mod logger;
 /// Wrapper for configuring the memory and CPU of the microVM.
 pub mod machine_config;
+/// Wrapper for replacing the whole pre-boot configuration of the microVM at once.
+pub mod microvm;
+/// Wrapper for configuring how the microVM metadata service is exposed to the guest.
+pub mod mmds;
 /// Wrapper for configuring the network devices attached to the microVM.
 pub mod net;
 #[cfg(feature = "vsock")]
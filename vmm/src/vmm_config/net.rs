@@ -2,25 +2,115 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::fmt::{Display, Formatter, Result};
+use std::net::Ipv4Addr;
 use std::result;
 
-use net_util::{MacAddr, Tap, TapError};
+use devices::virtio::TxFilter;
+use dumbo::user_net::{
+    UserNetworkParams, DEFAULT_GATEWAY_ADDR, DEFAULT_GUEST_ADDR, DEFAULT_NETMASK,
+};
+use error_code::ErrorCode;
+use net_util::{Ipv4Cidr, MacAddr, Tap, TapError};
 use rate_limiter::RateLimiter;
 
+/// This struct represents the strongly typed equivalent of the json body describing the
+/// filtering applied to the frames transmitted by a guest network interface.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct TxFilterConfig {
+    /// When present, IPv4 packets and ARP frames sent by the guest must carry one of these
+    /// addresses as their source.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_source_ips: Option<Vec<Ipv4Addr>>,
+    /// Frames with any of these ethertypes are dropped.
+    #[serde(default)]
+    pub denied_ethertypes: Vec<u16>,
+    /// TCP segments heading to any of these destination ports are dropped.
+    #[serde(default)]
+    pub denied_tcp_ports: Vec<u16>,
+    /// UDP datagrams heading to any of these destination ports are dropped.
+    #[serde(default)]
+    pub denied_udp_ports: Vec<u16>,
+}
+
+/// This struct represents the strongly typed equivalent of the json body describing the
+/// user-mode network stack which backs a guest network interface instead of a TAP device.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserNetConfig {
+    /// Address of the emulated gateway. Guest connections to this address reach the host
+    /// loopback interface if `allow_host_loopback` is set, and are refused otherwise.
+    #[serde(default = "default_gateway_addr")]
+    pub gateway_addr: Ipv4Addr,
+    /// Address handed out to the guest via DHCP.
+    #[serde(default = "default_guest_addr")]
+    pub guest_addr: Ipv4Addr,
+    /// Netmask of the emulated subnet.
+    #[serde(default = "default_netmask")]
+    pub netmask: Ipv4Addr,
+    /// DNS server address advertised to the guest via DHCP.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dns_addr: Option<Ipv4Addr>,
+    /// Whether the guest can reach the services listening on the host loopback interface, via
+    /// the gateway address. Off by default.
+    #[serde(default)]
+    pub allow_host_loopback: bool,
+    /// Link-local or private address blocks which the guest can reach. These are off limits by
+    /// default, since they may belong to the host or its network. The DNS server is always
+    /// allowed.
+    #[serde(default)]
+    pub allowed_destinations: Vec<Ipv4Cidr>,
+    /// Address blocks which the guest cannot reach, even if allowed otherwise.
+    #[serde(default)]
+    pub denied_destinations: Vec<Ipv4Cidr>,
+}
+
+fn default_gateway_addr() -> Ipv4Addr {
+    Ipv4Addr::from(DEFAULT_GATEWAY_ADDR)
+}
+
+fn default_guest_addr() -> Ipv4Addr {
+    Ipv4Addr::from(DEFAULT_GUEST_ADDR)
+}
+
+fn default_netmask() -> Ipv4Addr {
+    Ipv4Addr::from(DEFAULT_NETMASK)
+}
+
+impl UserNetConfig {
+    /// Returns the parameters of the user-mode network stack described by this configuration.
+    pub fn params(&self) -> UserNetworkParams {
+        UserNetworkParams {
+            gateway_addr: self.gateway_addr,
+            guest_addr: self.guest_addr,
+            netmask: self.netmask,
+            dns_addr: self.dns_addr,
+            allow_host_loopback: self.allow_host_loopback,
+            allowed_destinations: self.allowed_destinations.clone(),
+            denied_destinations: self.denied_destinations.clone(),
+        }
+    }
+}
+
 /// This struct represents the strongly typed equivalent of the json body from net iface
 /// related requests.
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct NetworkInterfaceConfig {
     /// ID of the guest network interface.
     pub iface_id: String,
-    /// Host level path for the guest network interface.
-    pub host_dev_name: String,
+    /// Host level path for the guest network interface. Exactly one of `host_dev_name` and
+    /// `user_net` must be specified.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub host_dev_name: Option<String>,
     /// Guest MAC address.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub guest_mac: Option<MacAddr>,
     /// Rate Limiter for received packages.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub rx_rate_limiter: Option<RateLimiter>,
     /// Rate Limiter for transmitted packages.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub tx_rate_limiter: Option<RateLimiter>,
     #[serde(default = "default_allow_mmds_requests")]
     /// If this field is set, the device model will reply to HTTP GET
@@ -29,6 +119,15 @@ pub struct NetworkInterfaceConfig {
     /// same address are intercepted by the device model, and do not reach
     /// the associated TAP device.
     pub allow_mmds_requests: bool,
+    /// If this field is set, the frames sent by the guest via this interface are filtered
+    /// before reaching the TAP. Frames with a source MAC different from `guest_mac` (when the
+    /// latter is set) are also dropped.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tx_filter: Option<TxFilterConfig>,
+    /// If this field is set, the guest traffic is handled by a user-mode network stack which
+    /// performs NAT using regular host sockets, so no TAP device is required.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_net: Option<UserNetConfig>,
     /// Handle for a network tap interface created using `host_dev_name`.
     #[serde(skip)]
     pub tap: Option<Tap>,
@@ -58,15 +157,39 @@ impl NetworkInterfaceConfig {
     pub fn allow_mmds_requests(&self) -> bool {
         self.allow_mmds_requests
     }
+
+    /// Returns the user-mode network stack configuration, if one was specified.
+    pub fn user_net(&self) -> Option<&UserNetConfig> {
+        self.user_net.as_ref()
+    }
+
+    /// Builds the TX filter of the interface, if one was configured.
+    pub fn tx_filter(&self) -> Option<TxFilter> {
+        self.tx_filter.as_ref().map(|cfg| {
+            TxFilter::new(
+                self.guest_mac,
+                cfg.allowed_source_ips.clone(),
+                cfg.denied_ethertypes.clone(),
+                cfg.denied_tcp_ports.clone(),
+                cfg.denied_udp_ports.clone(),
+            )
+        })
+    }
 }
 
 /// Errors associated with `NetworkInterfaceConfig`.
 #[derive(Debug)]
 pub enum NetworkInterfaceError {
+    /// The network interface ID is used more than once in the same configuration.
+    DuplicateIfaceId(String),
     /// The MAC address is already in use.
     GuestMacAddressInUse(String),
     /// The host device name is already in use.
     HostDeviceNameInUse(String),
+    /// Exactly one of `host_dev_name` and `user_net` must be specified.
+    InvalidBackend,
+    /// No network interface with this ID was configured.
+    InvalidIfaceId(String),
     /// Cannot open/create tap device.
     OpenTap(TapError),
     /// The update is not allowed after booting the microvm.
@@ -77,6 +200,11 @@ impl Display for NetworkInterfaceError {
     fn fmt(&self, f: &mut Formatter) -> Result {
         use self::NetworkInterfaceError::*;
         match *self {
+            DuplicateIfaceId(ref iface_id) => write!(
+                f,
+                "The network interface ID {} is used more than once.",
+                iface_id
+            ),
             GuestMacAddressInUse(ref mac_addr) => write!(
                 f,
                 "{}",
@@ -87,6 +215,13 @@ impl Display for NetworkInterfaceError {
                 "{}",
                 format!("The host device name {} is already in use.", host_dev_name)
             ),
+            InvalidBackend => write!(
+                f,
+                "Exactly one of host_dev_name and user_net must be specified."
+            ),
+            InvalidIfaceId(ref iface_id) => {
+                write!(f, "Invalid network interface ID: {}", iface_id)
+            }
             OpenTap(ref e) => {
                 // We are propagating the Tap Error. This error can contain
                 // imbricated quotes which would result in an invalid json.
@@ -107,6 +242,22 @@ impl Display for NetworkInterfaceError {
     }
 }
 
+impl NetworkInterfaceError {
+    /// Returns the code identifying the error in API responses.
+    pub fn error_code(&self) -> ErrorCode {
+        use self::NetworkInterfaceError::*;
+        match *self {
+            DuplicateIfaceId(_) => ErrorCode::NetworkInterfaceDuplicateId,
+            GuestMacAddressInUse(_) => ErrorCode::NetworkInterfaceGuestMacAddressInUse,
+            HostDeviceNameInUse(_) => ErrorCode::NetworkInterfaceHostDeviceNameInUse,
+            InvalidBackend => ErrorCode::NetworkInterfaceInvalidBackend,
+            InvalidIfaceId(_) => ErrorCode::NetworkInterfaceInvalidId,
+            OpenTap(_) => ErrorCode::NetworkInterfaceOpenTap,
+            UpdateNotAllowedPostBoot => ErrorCode::NetworkInterfaceUpdateNotAllowedPostBoot,
+        }
+    }
+}
+
 /// A wrapper over the list of the `NetworkInterfaceConfig` that the microvm has configured.
 pub struct NetworkInterfaceConfigs {
     if_list: Vec<NetworkInterfaceConfig>,
@@ -120,6 +271,17 @@ impl NetworkInterfaceConfigs {
         }
     }
 
+    /// Returns an iterator over the network interfaces.
+    pub fn iter(&self) -> ::std::slice::Iter<NetworkInterfaceConfig> {
+        self.if_list.iter()
+    }
+
+    /// Returns the configuration of the network interface with the specified `iface_id`, if it
+    /// exists.
+    pub fn get(&self, iface_id: &str) -> Option<&NetworkInterfaceConfig> {
+        self.if_list.iter().find(|cfg| cfg.iface_id == iface_id)
+    }
+
     /// Returns a mutable iterator over the network interfaces.
     pub fn iter_mut(&mut self) -> ::std::slice::IterMut<NetworkInterfaceConfig> {
         self.if_list.iter_mut()
@@ -142,6 +304,65 @@ impl NetworkInterfaceConfigs {
         }
     }
 
+    /// Builds a list of network interfaces meant to replace this one, out of `netif_configs`.
+    /// Each interface is validated as by `insert`, except that an ID which shows up more than
+    /// once is an error, rather than an update. The TAP devices of this list are moved over
+    /// to the interfaces backed by the same host devices, instead of being opened again. On
+    /// failure, the TAP devices are handed back, and the errors are returned along with the
+    /// position of the offending interface in `netif_configs`.
+    pub fn stage(
+        &mut self,
+        netif_configs: Vec<NetworkInterfaceConfig>,
+    ) -> result::Result<NetworkInterfaceConfigs, Vec<(usize, NetworkInterfaceError)>> {
+        let mut staged = NetworkInterfaceConfigs::new();
+        let mut errors = Vec::new();
+        for (index, mut netif_config) in netif_configs.into_iter().enumerate() {
+            let validation = if staged.get(&netif_config.iface_id).is_some() {
+                Err(NetworkInterfaceError::DuplicateIfaceId(
+                    netif_config.iface_id.clone(),
+                ))
+            } else {
+                staged.validate_create(&netif_config)
+            };
+            if let Err(e) = validation {
+                errors.push((index, e));
+                continue;
+            }
+
+            if let Some(ref host_dev_name) = netif_config.host_dev_name {
+                netif_config.tap = self
+                    .iter_mut()
+                    .find(|netif| netif.host_dev_name.as_ref() == Some(host_dev_name))
+                    .and_then(NetworkInterfaceConfig::take_tap);
+            }
+            if let Err(e) = staged.insert(netif_config) {
+                errors.push((index, e));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(staged)
+        } else {
+            self.unstage(staged);
+            Err(errors)
+        }
+    }
+
+    /// Takes back the TAP devices moved to `staged` by `stage`, when the staged list is
+    /// discarded.
+    pub fn unstage(&mut self, mut staged: NetworkInterfaceConfigs) {
+        for staged_netif in staged.iter_mut() {
+            if let Some(tap) = staged_netif.take_tap() {
+                if let Some(netif) = self
+                    .iter_mut()
+                    .find(|netif| netif.host_dev_name == staged_netif.host_dev_name)
+                {
+                    netif.tap = Some(tap);
+                }
+            }
+        }
+    }
+
     fn get_index_of_mac(&self, mac: &MacAddr) -> Option<usize> {
         return self
             .if_list
@@ -153,7 +374,16 @@ impl NetworkInterfaceConfigs {
         return self
             .if_list
             .iter()
-            .position(|netif| &netif.host_dev_name == host_dev_name);
+            .position(|netif| netif.host_dev_name.as_ref() == Some(host_dev_name));
+    }
+
+    fn validate_backend(
+        new_config: &NetworkInterfaceConfig,
+    ) -> result::Result<(), NetworkInterfaceError> {
+        if new_config.host_dev_name.is_some() == new_config.user_net.is_some() {
+            return Err(NetworkInterfaceError::InvalidBackend);
+        }
+        Ok(())
     }
 
     fn validate_update(
@@ -164,6 +394,7 @@ impl NetworkInterfaceConfigs {
         // Check that the mac address is unique. In order to do so, we search for the
         // network interface that has the same mac address as the one specified in new_config.
         // If the same mac is used in another network interface config, return error.
+        Self::validate_backend(new_config)?;
         if new_config.guest_mac.is_some() {
             let mac_index = self.get_index_of_mac(&new_config.guest_mac.unwrap());
             if mac_index.is_some() && mac_index.unwrap() != index {
@@ -173,11 +404,13 @@ impl NetworkInterfaceConfigs {
             }
         }
         // Check that the host_dev_name is unique.
-        let dev_name_index = self.get_index_of_dev_name(&new_config.host_dev_name);
-        if dev_name_index.is_some() && dev_name_index.unwrap() != index {
-            return Err(NetworkInterfaceError::HostDeviceNameInUse(
-                new_config.host_dev_name.clone(),
-            ));
+        if let Some(ref host_dev_name) = new_config.host_dev_name {
+            let dev_name_index = self.get_index_of_dev_name(host_dev_name);
+            if dev_name_index.is_some() && dev_name_index.unwrap() != index {
+                return Err(NetworkInterfaceError::HostDeviceNameInUse(
+                    host_dev_name.clone(),
+                ));
+            }
         }
 
         Ok(())
@@ -190,18 +423,24 @@ impl NetworkInterfaceConfigs {
     ) -> result::Result<(), NetworkInterfaceError> {
         self.validate_update(index, &updated_netif_config)?;
 
-        // We are ignoring the tap field of the network interface we want to update. We are
-        // manually setting this field to a newly created tap (corresponding to the host_dev_name)
-        // or to the old tap device of the network interface we are trying to update.
-        updated_netif_config.tap =
-            if self.if_list[index].host_dev_name != updated_netif_config.host_dev_name {
-                Some(
-                    Tap::open_named(&updated_netif_config.host_dev_name.as_str())
-                        .map_err(NetworkInterfaceError::OpenTap)?,
-                )
-            } else {
-                self.if_list[index].tap.take()
-            };
+        // Unless it was already set (by `stage`), we are manually setting the tap field of the
+        // network interface we want to update to a newly created tap (corresponding to the
+        // host_dev_name) or to the old tap device of the network interface we are trying to
+        // update. Interfaces backed by the user-mode network stack have no tap.
+        updated_netif_config.tap = match updated_netif_config.host_dev_name {
+            Some(_) if updated_netif_config.tap.is_some() => updated_netif_config.tap.take(),
+            Some(ref host_dev_name) => {
+                if self.if_list[index].host_dev_name.as_ref() != Some(host_dev_name) {
+                    Some(
+                        Tap::open_named(host_dev_name.as_str())
+                            .map_err(NetworkInterfaceError::OpenTap)?,
+                    )
+                } else {
+                    self.if_list[index].tap.take()
+                }
+            }
+            None => None,
+        };
         self.if_list[index] = updated_netif_config;
 
         Ok(())
@@ -211,6 +450,8 @@ impl NetworkInterfaceConfigs {
         &self,
         new_config: &NetworkInterfaceConfig,
     ) -> result::Result<(), NetworkInterfaceError> {
+        Self::validate_backend(new_config)?;
+
         // Check that there is no other interface in the list that has the same mac.
         if new_config.guest_mac.is_some() && self
             .get_index_of_mac(&new_config.guest_mac.unwrap())
@@ -222,13 +463,12 @@ impl NetworkInterfaceConfigs {
         }
 
         // Check that there is no other interface in the list that has the same host_dev_name.
-        if self
-            .get_index_of_dev_name(&new_config.host_dev_name)
-            .is_some()
-        {
-            return Err(NetworkInterfaceError::HostDeviceNameInUse(
-                new_config.host_dev_name.clone(),
-            ));
+        if let Some(ref host_dev_name) = new_config.host_dev_name {
+            if self.get_index_of_dev_name(host_dev_name).is_some() {
+                return Err(NetworkInterfaceError::HostDeviceNameInUse(
+                    host_dev_name.clone(),
+                ));
+            }
         }
 
         Ok(())
@@ -236,15 +476,20 @@ impl NetworkInterfaceConfigs {
 
     fn create(
         &mut self,
-        netif_config: NetworkInterfaceConfig,
+        mut netif_config: NetworkInterfaceConfig,
     ) -> result::Result<(), NetworkInterfaceError> {
         self.validate_create(&netif_config)?;
-        let tap = Tap::open_named(netif_config.host_dev_name.as_str())
-            .map_err(NetworkInterfaceError::OpenTap)?;
+        let tap = match netif_config.host_dev_name {
+            Some(_) if netif_config.tap.is_some() => netif_config.tap.take(),
+            Some(ref host_dev_name) => Some(
+                Tap::open_named(host_dev_name.as_str()).map_err(NetworkInterfaceError::OpenTap)?,
+            ),
+            None => None,
+        };
         self.if_list.push(netif_config);
 
         let index = self.if_list.len() - 1;
-        self.if_list[index].tap = Some(tap);
+        self.if_list[index].tap = tap;
         Ok(())
     }
 }
@@ -259,11 +504,13 @@ mod tests {
     fn create_netif(id: &str, name: &str, mac: &str) -> NetworkInterfaceConfig {
         NetworkInterfaceConfig {
             iface_id: String::from(id),
-            host_dev_name: String::from(name),
+            host_dev_name: Some(String::from(name)),
             guest_mac: Some(MacAddr::parse_str(mac).unwrap()),
             rx_rate_limiter: Some(RateLimiter::default()),
             tx_rate_limiter: Some(RateLimiter::default()),
             allow_mmds_requests: false,
+            tx_filter: None,
+            user_net: None,
             tap: None,
         }
     }
@@ -279,6 +526,8 @@ mod tests {
                 rx_rate_limiter: None,
                 tx_rate_limiter: None,
                 allow_mmds_requests: self.allow_mmds_requests.clone(),
+                tx_filter: self.tx_filter.clone(),
+                user_net: self.user_net.clone(),
                 tap: None,
             }
         }
@@ -311,6 +560,56 @@ mod tests {
         assert_eq!(netif_configs.if_list.len(), 1);
     }
 
+    #[test]
+    fn test_stage() {
+        let mut netif_configs = NetworkInterfaceConfigs::new();
+        let netif_1 = create_netif("id_1", "dev10", "01:23:45:67:89:0a");
+        assert!(netif_configs.insert(netif_1).is_ok());
+
+        // The TAP device of dev10 is moved to the staged list, instead of being opened again.
+        let staged = netif_configs
+            .stage(vec![
+                create_netif("id_2", "dev10", "01:23:45:67:89:0b"),
+                create_netif("id_3", "dev11", "01:23:45:67:89:0c"),
+            ]).unwrap();
+        assert_eq!(staged.if_list.len(), 2);
+        assert!(staged.if_list.iter().all(|netif| netif.tap.is_some()));
+        assert!(netif_configs.if_list[0].tap.is_none());
+
+        // It goes back when the staged list is discarded.
+        netif_configs.unstage(staged);
+        assert!(netif_configs.if_list[0].tap.is_some());
+
+        // The errors come with the position of the offending interface, and the TAP devices are
+        // handed back.
+        let errors = match netif_configs.stage(vec![
+            create_netif("id_2", "dev10", "01:23:45:67:89:0b"),
+            create_netif("id_3", "dev10", "01:23:45:67:89:0c"),
+            create_netif("id_4", "dev12", "01:23:45:67:89:0b"),
+            create_netif("id_2", "dev13", "01:23:45:67:89:0d"),
+        ]) {
+            Err(errors) => errors,
+            Ok(_) => panic!("Expected errors."),
+        };
+        assert_eq!(errors.len(), 3);
+        match errors[0] {
+            (1, NetworkInterfaceError::HostDeviceNameInUse(_)) => (),
+            _ => panic!("Expected a HostDeviceNameInUse error."),
+        }
+        match errors[1] {
+            (2, NetworkInterfaceError::GuestMacAddressInUse(_)) => (),
+            _ => panic!("Expected a GuestMacAddressInUse error."),
+        }
+        // A repeated ID is not an update of the earlier interface.
+        match errors[2] {
+            (3, NetworkInterfaceError::DuplicateIfaceId(ref iface_id)) => {
+                assert_eq!(iface_id, "id_2")
+            }
+            _ => panic!("Expected a DuplicateIfaceId error."),
+        }
+        assert!(netif_configs.if_list[0].tap.is_some());
+    }
+
     #[test]
     fn test_insert_error_cases() {
         let mut netif_configs = NetworkInterfaceConfigs::new();
@@ -347,7 +646,7 @@ mod tests {
         let netif_2 = create_netif(id_2, host_dev_name_1, guest_mac_2);
         let expected_error = format!(
             "The host device name {} is already in use.",
-            netif_2.host_dev_name
+            host_dev_name_1
         );
         assert_eq!(
             netif_configs
@@ -381,7 +680,7 @@ mod tests {
         let netif_2 = create_netif(id_2, host_dev_name_1, guest_mac_2);
         let expected_error = format!(
             "The host device name {} is already in use.",
-            netif_2.host_dev_name
+            host_dev_name_1
         );
         assert_eq!(
             netif_configs
@@ -391,4 +690,49 @@ mod tests {
             expected_error
         );
     }
+
+    #[test]
+    fn test_user_net() {
+        let mut netif_configs = NetworkInterfaceConfigs::new();
+        let user_net = UserNetConfig {
+            gateway_addr: default_gateway_addr(),
+            guest_addr: default_guest_addr(),
+            netmask: default_netmask(),
+            dns_addr: None,
+            allow_host_loopback: false,
+            allowed_destinations: vec![],
+            denied_destinations: vec![],
+        };
+
+        // Error Case: both a tap and the user-mode stack.
+        let mut netif = create_netif("id_1", "dev5", "01:23:45:67:89:0c");
+        netif.user_net = Some(user_net.clone());
+        assert_eq!(
+            netif_configs.insert(netif.clone()).unwrap_err().to_string(),
+            "Exactly one of host_dev_name and user_net must be specified."
+        );
+
+        // Error Case: no backend at all.
+        netif.host_dev_name = None;
+        netif.user_net = None;
+        assert_eq!(
+            netif_configs.insert(netif.clone()).unwrap_err().to_string(),
+            "Exactly one of host_dev_name and user_net must be specified."
+        );
+
+        // No tap is opened for interfaces backed by the user-mode stack.
+        netif.user_net = Some(user_net.clone());
+        assert!(netif_configs.insert(netif.clone()).is_ok());
+        assert!(netif_configs.if_list[0].tap.is_none());
+        assert_eq!(
+            netif_configs.if_list[0].user_net().unwrap().params(),
+            UserNetworkParams::default()
+        );
+
+        // Update to a tap backed interface.
+        let netif = create_netif("id_1", "dev5", "01:23:45:67:89:0c");
+        assert!(netif_configs.insert(netif).is_ok());
+        assert!(netif_configs.if_list[0].tap.is_some());
+        assert!(netif_configs.if_list[0].user_net().is_none());
+    }
 }
@@ -4,6 +4,8 @@
 use std::fmt::{Display, Formatter, Result};
 use std::result;
 
+use error_code::ErrorCode;
+
 /// This struct represents the strongly typed equivalent of the json body
 /// from vsock related requests.
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
@@ -38,6 +40,17 @@ impl Display for VsockError {
     }
 }
 
+impl VsockError {
+    /// Returns the code identifying the error in API responses.
+    pub fn error_code(&self) -> ErrorCode {
+        use self::VsockError::*;
+        match *self {
+            GuestCIDAlreadyInUse(_) => ErrorCode::VsockGuestCidInUse,
+            UpdateNotAllowedPostBoot => ErrorCode::VsockUpdateNotAllowedPostBoot,
+        }
+    }
+}
+
 /// A list with all the vsock devices.
 pub struct VsockDeviceConfigs {
     configs: Vec<VsockDeviceConfig>,
@@ -80,7 +93,7 @@ impl VsockDeviceConfigs {
     }
 
     /// Returns an immutable iterator over the vsock available configurations.
-    pub fn iter(&mut self) -> ::std::slice::Iter<VsockDeviceConfig> {
+    pub fn iter(&self) -> ::std::slice::Iter<VsockDeviceConfig> {
         self.configs.iter()
     }
 }